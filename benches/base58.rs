@@ -0,0 +1,52 @@
+use std::hint::black_box;
+use std::time::Instant;
+
+use hex_literal::hex;
+use oxicoin::base58;
+
+const ITERATIONS: u32 = 100_000;
+
+fn bench<F>(name: &str, iterations: u32, mut f: F)
+where
+    F: FnMut(),
+{
+    let start = Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+
+    let elapsed = start.elapsed();
+    println!(
+        "{:<24} {:>10.1} ns/iter",
+        name,
+        elapsed.as_nanos() as f64 / iterations as f64
+    );
+}
+
+fn main() {
+    // P2PKH payload and a serialized extended public key
+    let address = hex!("00751e76e8199196d454941c45d1b3a323f1433bd6");
+    let xpub = base58::decode_checksum(
+        "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8",
+    )
+    .unwrap();
+
+    let encoded_address = base58::encode_checksum(address);
+    let encoded_xpub = base58::encode_checksum(&xpub);
+
+    bench("encode address", ITERATIONS, || {
+        black_box(base58::encode_checksum(black_box(address)));
+    });
+
+    bench("decode address", ITERATIONS, || {
+        black_box(base58::decode_checksum(black_box(&encoded_address)).unwrap());
+    });
+
+    bench("encode xpub", ITERATIONS, || {
+        black_box(base58::encode_checksum(black_box(&xpub)));
+    });
+
+    bench("decode xpub", ITERATIONS, || {
+        black_box(base58::decode_checksum(black_box(&encoded_xpub)).unwrap());
+    });
+}
@@ -0,0 +1,61 @@
+use std::hint::black_box;
+use std::time::Instant;
+
+use hex_literal::hex;
+use num_bigint::BigUint;
+use oxicoin::secp256k1::field::FieldElement;
+
+const ITERATIONS: u32 = 100_000;
+
+fn bench<F>(name: &str, iterations: u32, mut f: F)
+where
+    F: FnMut(),
+{
+    let start = Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+
+    let elapsed = start.elapsed();
+    println!(
+        "{:<24} {:>10.1} ns/iter",
+        name,
+        elapsed.as_nanos() as f64 / iterations as f64
+    );
+}
+
+fn main() {
+    let a = FieldElement::from_bytes_be(hex!(
+        "5a0bd8ffa8a2b0e9b6dc4ab4e7ab0a6c5e04bbd4a0e5c2cafb3d0aea8f3a28b1"
+    ));
+    let b = FieldElement::from_bytes_be(hex!(
+        "c0ffee00c0ffee00c0ffee00c0ffee00c0ffee00c0ffee00c0ffee00c0ffee00"
+    ));
+
+    bench("field add", ITERATIONS, || {
+        black_box(black_box(a) + black_box(b));
+    });
+
+    bench("field mul", ITERATIONS, || {
+        black_box(black_box(a) * black_box(b));
+    });
+
+    bench("field square", ITERATIONS, || {
+        black_box(black_box(a).square());
+    });
+
+    bench("field inverse", ITERATIONS / 100, || {
+        black_box(black_box(a).mul_inv());
+    });
+
+    // what every multiplication used to cost
+    let prime = BigUint::parse_bytes(
+        b"fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2f",
+        16,
+    )
+    .unwrap();
+    let (x, y) = (a.to_biguint(), b.to_biguint());
+    bench("biguint mul + mod", ITERATIONS, || {
+        black_box(black_box(&x) * black_box(&y) % &prime);
+    });
+}
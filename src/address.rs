@@ -0,0 +1,641 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use core::fmt::{self, Display, Formatter};
+use core::str::FromStr;
+
+use sha2::{Digest, Sha256};
+
+use crate::bech32;
+use crate::network::Network;
+use crate::secp256k1::crypto::PublicKey;
+use crate::utils::hash160;
+use crate::{base58, taproot, Error, Result};
+
+/// A Bitcoin address, segwit programs are encoded with bech32 (v0) or bech32m (v1).
+///
+/// Base58 addresses can't tell the test networks apart, these parse as testnet.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Address {
+    /// Pay to public key hash, legacy base58 address
+    P2pkh { hash: [u8; 20], network: Network },
+
+    /// Pay to script hash, legacy base58 address
+    P2sh { hash: [u8; 20], network: Network },
+
+    /// Pay to witness public key hash (segwit v0)
+    P2wpkh { program: [u8; 20], network: Network },
+
+    /// Pay to witness script hash (segwit v0)
+    P2wsh { program: [u8; 32], network: Network },
+
+    /// Pay to taproot, the program is the x-only output key (segwit v1)
+    P2tr {
+        output_key: [u8; 32],
+        network: Network,
+    },
+
+    /// Witness program of a version without defined semantics yet (v1 with other
+    /// lengths than 32 bytes, v2 to v16), spendable by anyone until a soft fork
+    WitnessUnknown {
+        version: u8,
+        program: Vec<u8>,
+        network: Network,
+    },
+}
+
+/// Kind of output an address or a script pays to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AddressType {
+    P2pkh,
+    P2sh,
+    /// Segwit v0 key hash
+    P2wpkh,
+    /// Segwit v0 script hash
+    P2wsh,
+    /// Segwit v1 output key
+    P2tr,
+    /// Segwit program of a future version
+    WitnessUnknown {
+        version: u8,
+    },
+    /// Any script without an address form
+    NonStandard,
+}
+
+/// What an address or a script pays to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressInfo {
+    pub address_type: AddressType,
+    /// Scripts aren't tied to a network, only addresses report it
+    pub network: Option<Network>,
+    pub witness_version: Option<u8>,
+    /// Hash or witness program, the whole script for non standard scripts
+    pub payload: Vec<u8>,
+}
+
+impl AddressInfo {
+    /// Classify an address of any network and type
+    pub fn from_address(address: &str) -> Result<Self> {
+        let address: Address = address.parse()?;
+        Ok(Self {
+            address_type: address.address_type(),
+            network: Some(address.network()),
+            witness_version: address.witness_version(),
+            payload: address.payload().to_vec(),
+        })
+    }
+
+    /// Classify a script pubkey, never fails since anything can be locked by a script
+    pub fn from_script(script_pubkey: &[u8]) -> Self {
+        match Address::from_script(script_pubkey, Network::Mainnet) {
+            Ok(address) => Self {
+                address_type: address.address_type(),
+                network: None,
+                witness_version: address.witness_version(),
+                payload: address.payload().to_vec(),
+            },
+            Err(_) => Self {
+                address_type: AddressType::NonStandard,
+                network: None,
+                witness_version: None,
+                payload: script_pubkey.to_vec(),
+            },
+        }
+    }
+}
+
+impl Address {
+    /// P2PKH address of the given key, serialized either compressed or not
+    pub fn p2pkh(public_key: &PublicKey, compressed: bool, network: Network) -> Result<Self> {
+        let hash = hash160(public_key.serialize(compressed)?);
+        let hash = hash.as_slice().try_into().unwrap(); // safe, 20 bytes
+        Ok(Self::P2pkh { hash, network })
+    }
+
+    /// P2SH address of the given redeem script
+    pub fn p2sh<B>(redeem_script: B, network: Network) -> Self
+    where
+        B: AsRef<[u8]>,
+    {
+        let hash = hash160(redeem_script);
+        let hash = hash.as_slice().try_into().unwrap(); // safe, 20 bytes
+        Self::P2sh { hash, network }
+    }
+
+    /// P2WPKH address of the given key, segwit only allows compressed keys
+    pub fn p2wpkh(public_key: &PublicKey, network: Network) -> Result<Self> {
+        let program = hash160(public_key.serialize_compressed()?);
+        let program = program.as_slice().try_into().unwrap(); // safe, 20 bytes
+        Ok(Self::P2wpkh { program, network })
+    }
+
+    /// P2WPKH address of a SEC serialized key, uncompressed keys aren't allowed
+    pub fn p2wpkh_from_sec<B>(sec: B, network: Network) -> Result<Self>
+    where
+        B: AsRef<[u8]>,
+    {
+        let sec = sec.as_ref();
+        if sec.len() == 65 {
+            return Err(Error::UncompressedSegwitKey);
+        }
+
+        Self::p2wpkh(&PublicKey::deserialize(sec)?, network)
+    }
+
+    /// P2WSH address of the given witness script (e.g. multisig or timelocks), the
+    /// program is its SHA256 rather than hash160
+    pub fn p2wsh<B>(witness_script: B, network: Network) -> Self
+    where
+        B: AsRef<[u8]>,
+    {
+        let program = Sha256::digest(witness_script.as_ref()).into();
+        Self::P2wsh { program, network }
+    }
+
+    /// P2TR address of the given x-only internal key, tweaked with the merkle root
+    /// of the script tree (BIP341), key path only outputs have no merkle root
+    pub fn p2tr(
+        internal_key: &[u8; 32],
+        merkle_root: Option<&[u8; 32]>,
+        network: Network,
+    ) -> Result<Self> {
+        let (output_key, _) = taproot::tweak_public_key(internal_key, merkle_root)?;
+        Ok(Self::P2tr {
+            output_key,
+            network,
+        })
+    }
+
+    pub fn network(&self) -> Network {
+        match self {
+            Self::P2pkh { network, .. }
+            | Self::P2sh { network, .. }
+            | Self::P2wpkh { network, .. }
+            | Self::P2wsh { network, .. }
+            | Self::P2tr { network, .. }
+            | Self::WitnessUnknown { network, .. } => *network,
+        }
+    }
+
+    /// The hash or witness program this address commits to
+    pub fn payload(&self) -> &[u8] {
+        match self {
+            Self::P2pkh { hash, .. } | Self::P2sh { hash, .. } => hash,
+            Self::P2wpkh { program, .. } => program,
+            Self::P2wsh { program, .. } => program,
+            Self::P2tr { output_key, .. } => output_key,
+            Self::WitnessUnknown { program, .. } => program,
+        }
+    }
+
+    pub fn address_type(&self) -> AddressType {
+        match self {
+            Self::P2pkh { .. } => AddressType::P2pkh,
+            Self::P2sh { .. } => AddressType::P2sh,
+            Self::P2wpkh { .. } => AddressType::P2wpkh,
+            Self::P2wsh { .. } => AddressType::P2wsh,
+            Self::P2tr { .. } => AddressType::P2tr,
+            Self::WitnessUnknown { version, .. } => {
+                AddressType::WitnessUnknown { version: *version }
+            }
+        }
+    }
+
+    /// Witness version of segwit addresses
+    pub fn witness_version(&self) -> Option<u8> {
+        match self {
+            Self::P2pkh { .. } | Self::P2sh { .. } => None,
+            Self::P2wpkh { .. } | Self::P2wsh { .. } => Some(0),
+            Self::P2tr { .. } => Some(1),
+            Self::WitnessUnknown { version, .. } => Some(*version),
+        }
+    }
+
+    /// The script locking the outputs sent to this address
+    pub fn script_pubkey(&self) -> Vec<u8> {
+        let payload = self.payload();
+        if let Self::WitnessUnknown { version, .. } = self {
+            // OP_n <2 to 40 bytes>
+            let prefix = [0x50 + version, payload.len() as u8];
+            return prefix.iter().chain(payload).copied().collect();
+        }
+
+        let prefix: &[u8] = match self {
+            // OP_DUP OP_HASH160 <20 bytes>
+            Self::P2pkh { .. } => &[0x76, 0xa9, 0x14],
+            // OP_HASH160 <20 bytes>
+            Self::P2sh { .. } => &[0xa9, 0x14],
+            // OP_0 <20 bytes>
+            Self::P2wpkh { .. } => &[0x00, 0x14],
+            // OP_0 <32 bytes>
+            Self::P2wsh { .. } => &[0x00, 0x20],
+            // OP_1 <32 bytes>
+            Self::P2tr { .. } => &[0x51, 0x20],
+            Self::WitnessUnknown { .. } => unreachable!(),
+        };
+        let suffix: &[u8] = match self {
+            // OP_EQUALVERIFY OP_CHECKSIG
+            Self::P2pkh { .. } => &[0x88, 0xac],
+            // OP_EQUAL
+            Self::P2sh { .. } => &[0x87],
+            _ => &[],
+        };
+
+        prefix
+            .iter()
+            .chain(payload)
+            .chain(suffix)
+            .copied()
+            .collect()
+    }
+
+    fn from_base58(string: &str) -> Result<Self> {
+        let data = base58::decode_checksum(string)?;
+        if data.len() != 21 {
+            return Err(Error::InvalidAddress("invalid base58 payload length"));
+        }
+
+        let hash = data[1..].try_into().unwrap(); // safe, 20 bytes
+        let network = Network::from_base58_prefix(data[0])
+            .ok_or(Error::InvalidAddress("unknown base58 prefix"))?;
+
+        if data[0] == network.p2pkh_prefix() {
+            Ok(Self::P2pkh { hash, network })
+        } else {
+            Ok(Self::P2sh { hash, network })
+        }
+    }
+
+    fn from_segwit(string: &str) -> Result<Self> {
+        let (hrp, version, program) = bech32::decode_segwit(string)?;
+        let network = Network::from_bech32_hrp(&hrp)
+            .ok_or(Error::InvalidAddress("unknown human readable part"))?;
+
+        match (version, program.len()) {
+            (0, 20) => Ok(Self::P2wpkh {
+                program: program.as_slice().try_into().unwrap(),
+                network,
+            }),
+            (0, 32) => Ok(Self::P2wsh {
+                program: program.as_slice().try_into().unwrap(),
+                network,
+            }),
+            (1, 32) => Ok(Self::P2tr {
+                output_key: program.as_slice().try_into().unwrap(),
+                network,
+            }),
+            (0, _) => Err(Error::InvalidAddress("unsupported witness program")),
+            _ => Ok(Self::WitnessUnknown {
+                version,
+                program,
+                network,
+            }),
+        }
+    }
+
+    /// Address of the given script pubkey, fails for scripts without an address form
+    pub fn from_script<B>(script_pubkey: B, network: Network) -> Result<Self>
+    where
+        B: AsRef<[u8]>,
+    {
+        let script = script_pubkey.as_ref();
+        let hash20 = |bytes: &[u8]| -> [u8; 20] { bytes.try_into().unwrap() };
+
+        match script {
+            [0x76, 0xa9, 0x14, hash @ .., 0x88, 0xac] if hash.len() == 20 => Ok(Self::P2pkh {
+                hash: hash20(hash),
+                network,
+            }),
+            [0xa9, 0x14, hash @ .., 0x87] if hash.len() == 20 => Ok(Self::P2sh {
+                hash: hash20(hash),
+                network,
+            }),
+            [opcode, len, program @ ..]
+                if (*opcode == 0x00 || (0x51..=0x60).contains(opcode))
+                    && *len as usize == program.len() =>
+            {
+                let version = if *opcode == 0x00 { 0 } else { opcode - 0x50 };
+                let hrp = network.bech32_hrp();
+                let encoded = bech32::encode_segwit(hrp, version, program)
+                    .map_err(|_| Error::InvalidAddress("invalid witness program"))?;
+                let address = Self::from_segwit(&encoded)?;
+
+                // test networks sharing `tb` would come back as testnet
+                Ok(address.with_network(network))
+            }
+            _ => Err(Error::InvalidAddress("non standard script")),
+        }
+    }
+
+    fn with_network(mut self, network: Network) -> Self {
+        match &mut self {
+            Self::P2pkh { network: n, .. }
+            | Self::P2sh { network: n, .. }
+            | Self::P2wpkh { network: n, .. }
+            | Self::P2wsh { network: n, .. }
+            | Self::P2tr { network: n, .. }
+            | Self::WitnessUnknown { network: n, .. } => *n = network,
+        }
+
+        self
+    }
+}
+
+impl Display for Address {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        let prefix = match self {
+            Self::P2pkh { network, .. } => Some(network.p2pkh_prefix()),
+            Self::P2sh { network, .. } => Some(network.p2sh_prefix()),
+            _ => None,
+        };
+
+        if let Some(prefix) = prefix {
+            let data: Vec<_> = core::iter::once(prefix)
+                .chain(self.payload().iter().copied())
+                .collect();
+            return write!(fmt, "{}", base58::encode_checksum(data));
+        }
+
+        let version = self.witness_version().unwrap(); // safe, segwit address
+        let hrp = self.network().bech32_hrp();
+        let encoded =
+            bech32::encode_segwit(hrp, version, self.payload()).map_err(|_| fmt::Error)?;
+        write!(fmt, "{}", encoded)
+    }
+}
+
+impl FromStr for Address {
+    type Err = Error;
+
+    fn from_str(string: &str) -> Result<Self> {
+        let lowercase = string.to_ascii_lowercase();
+        let is_segwit = Network::ALL.iter().any(|network| {
+            let hrp = network.bech32_hrp();
+            lowercase.starts_with(hrp) && lowercase[hrp.len()..].starts_with('1')
+        });
+
+        if is_segwit {
+            Self::from_segwit(string)
+        } else {
+            Self::from_base58(string)
+        }
+    }
+}
+
+impl From<Address> for String {
+    fn from(address: Address) -> Self {
+        address.to_string()
+    }
+}
+
+#[cfg(feature = "serde")]
+serde_impl!(Address, "a base58 or bech32 address");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secp256k1::crypto::PrivateKey;
+    use hex_literal::hex;
+
+    fn roundtrip(string: &str) -> Address {
+        let address: Address = string.parse().unwrap();
+        assert_eq!(address.to_string(), string);
+        address
+    }
+
+    #[test]
+    fn legacy_addresses() {
+        let public_key = PrivateKey::new(1usize).public_key().clone();
+        let address = Address::p2pkh(&public_key, true, Network::Mainnet).unwrap();
+        assert_eq!(address.to_string(), "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH");
+        assert_eq!(roundtrip("1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH"), address);
+        assert_eq!(
+            address.script_pubkey(),
+            hex!("76a914751e76e8199196d454941c45d1b3a323f1433bd688ac")
+        );
+
+        // 1-of-1 multisig with the key of secret 1
+        let redeem_script =
+            hex!("51210279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f8179851ae");
+        let address = Address::p2sh(&redeem_script[..], Network::Mainnet);
+        assert_eq!(roundtrip(&address.to_string()), address);
+        assert!(address.to_string().starts_with('3'));
+        assert_eq!(address.script_pubkey()[..2], [0xa9, 0x14]);
+        assert_eq!(address.script_pubkey()[22], 0x87);
+
+        let testnet = Address::p2sh(&redeem_script[..], Network::Signet);
+        assert!(testnet.to_string().starts_with('2'));
+        assert_eq!(roundtrip(&testnet.to_string()).network(), Network::Testnet);
+    }
+
+    #[test]
+    fn segwit_addresses() {
+        // BIP173 examples
+        let public_key = PrivateKey::new(1usize).public_key().clone();
+        let address = Address::p2wpkh(&public_key, Network::Mainnet).unwrap();
+        assert_eq!(
+            address.to_string(),
+            "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"
+        );
+        assert_eq!(
+            "BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4"
+                .parse::<Address>()
+                .unwrap(),
+            address
+        );
+        assert_eq!(
+            address.script_pubkey(),
+            hex!("0014751e76e8199196d454941c45d1b3a323f1433bd6")
+        );
+
+        let address = roundtrip("tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7");
+        assert_eq!(address.network(), Network::Testnet);
+        assert_eq!(
+            address.script_pubkey(),
+            hex!("00201863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262")
+        );
+
+        let regtest = Address::p2wpkh(&public_key, Network::Regtest).unwrap();
+        assert!(regtest.to_string().starts_with("bcrt1q"));
+        assert_eq!(roundtrip(&regtest.to_string()), regtest);
+
+        // BIP350 example, taproot output key
+        let address = roundtrip("bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0");
+        assert_eq!(address.witness_version(), Some(1));
+        assert_eq!(
+            address.script_pubkey(),
+            hex!("512079be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798")
+        );
+    }
+
+    #[test]
+    fn p2wsh_from_witness_script() {
+        // BIP173 example, <key of secret 1> OP_CHECKSIG
+        let witness_script =
+            hex!("210279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798ac");
+
+        let address = Address::p2wsh(&witness_script[..], Network::Testnet);
+        assert_eq!(
+            address.to_string(),
+            "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7"
+        );
+
+        let address = Address::p2wsh(&witness_script[..], Network::Mainnet);
+        assert_eq!(
+            address.to_string(),
+            "bc1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3qccfmv3"
+        );
+        assert_eq!(address.witness_version(), Some(0));
+    }
+
+    #[test]
+    fn p2tr_from_internal_key() {
+        // BIP341 wallet test vectors
+        let internal_key = hex!("d6889cb081036e0faefa3a35157ad71086b123b2b144b649798b494c300a961d");
+        let address = Address::p2tr(&internal_key, None, Network::Mainnet).unwrap();
+        assert_eq!(
+            address.to_string(),
+            "bc1p2wsldez5mud2yam29q22wgfh9439spgduvct83k3pm50fcxa5dps59h4z5"
+        );
+
+        let internal_key = hex!("187791b6f712a8ea41c8ecdd0ee77fab3e85263b37e1ec18a3651926b3a6cf27");
+        let merkle_root = hex!("5b75adecf53548f3ec6ad7d78383bf84cc57b55a3127c72b9a2481752dd88b21");
+        let address = Address::p2tr(&internal_key, Some(&merkle_root), Network::Mainnet).unwrap();
+        assert_eq!(
+            address.to_string(),
+            "bc1pz37fc4cn9ah8anwm4xqqhvxygjf9rjf2resrw8h8w4tmvcs0863sa2e586"
+        );
+        assert_eq!(roundtrip(&address.to_string()), address);
+    }
+
+    #[test]
+    fn p2wpkh_from_sec() {
+        let public_key = PrivateKey::new(1usize).public_key().clone();
+        let compressed = public_key.serialize(true).unwrap();
+        let uncompressed = public_key.serialize(false).unwrap();
+
+        let address = Address::p2wpkh_from_sec(compressed, Network::Mainnet).unwrap();
+        assert_eq!(
+            address,
+            public_key.p2wpkh_address(Network::Mainnet).unwrap()
+        );
+        assert!(matches!(
+            Address::p2wpkh_from_sec(uncompressed, Network::Mainnet),
+            Err(Error::UncompressedSegwitKey)
+        ));
+    }
+
+    #[test]
+    fn invalid_addresses() {
+        // bad checksum, mixed case, unknown prefix and v0 with bech32m
+        assert!("1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMJ"
+            .parse::<Address>()
+            .is_err());
+        assert!("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t5"
+            .parse::<Address>()
+            .is_err());
+        assert!("bc1QW508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"
+            .parse::<Address>()
+            .is_err());
+        assert!("xc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"
+            .parse::<Address>()
+            .is_err());
+        assert!("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kemeawh"
+            .parse::<Address>()
+            .is_err());
+    }
+
+    #[test]
+    fn classify_addresses() {
+        let info = AddressInfo::from_address("1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH").unwrap();
+        assert_eq!(info.address_type, AddressType::P2pkh);
+        assert_eq!(info.network, Some(Network::Mainnet));
+        assert_eq!(info.witness_version, None);
+        assert_eq!(
+            info.payload,
+            hex!("751e76e8199196d454941c45d1b3a323f1433bd6")
+        );
+
+        let info = AddressInfo::from_address("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx").unwrap();
+        assert_eq!(info.address_type, AddressType::P2wpkh);
+        assert_eq!(info.network, Some(Network::Testnet));
+        assert_eq!(info.witness_version, Some(0));
+
+        let info = AddressInfo::from_address(
+            "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0",
+        )
+        .unwrap();
+        assert_eq!(info.address_type, AddressType::P2tr);
+        assert_eq!(info.witness_version, Some(1));
+
+        // BIP350 valid vectors with future versions
+        let address =
+            roundtrip("bc1pw508d6qejxtdg4y5r3zarvary0c5xw7kw508d6qejxtdg4y5r3zarvary0c5xw7kt5nd6y");
+        assert_eq!(
+            address.address_type(),
+            AddressType::WitnessUnknown { version: 1 }
+        );
+        assert_eq!(address.payload().len(), 40);
+        assert_eq!(
+            address.script_pubkey(),
+            hex!("5128751e76e8199196d454941c45d1b3a323f1433bd6751e76e8199196d454941c45d1b3a323f1433bd6")
+        );
+
+        let info = AddressInfo::from_address("BC1SW50QGDZ25J").unwrap();
+        assert_eq!(
+            info.address_type,
+            AddressType::WitnessUnknown { version: 16 }
+        );
+        assert_eq!(info.payload, hex!("751e"));
+    }
+
+    #[test]
+    fn classify_scripts() {
+        let scripts = [
+            (
+                &hex!("76a914751e76e8199196d454941c45d1b3a323f1433bd688ac")[..],
+                AddressType::P2pkh,
+            ),
+            (
+                &hex!("a914751e76e8199196d454941c45d1b3a323f1433bd687")[..],
+                AddressType::P2sh,
+            ),
+            (
+                &hex!("0014751e76e8199196d454941c45d1b3a323f1433bd6")[..],
+                AddressType::P2wpkh,
+            ),
+            (
+                &hex!("00201863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262")[..],
+                AddressType::P2wsh,
+            ),
+            (
+                &hex!("512079be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798")[..],
+                AddressType::P2tr,
+            ),
+            (
+                &hex!("6002751e")[..],
+                AddressType::WitnessUnknown { version: 16 },
+            ),
+        ];
+
+        for (script, address_type) in &scripts {
+            let info = AddressInfo::from_script(script);
+            assert_eq!(info.address_type, *address_type);
+            assert_eq!(info.network, None);
+
+            let address = Address::from_script(script, Network::Signet).unwrap();
+            assert_eq!(address.network(), Network::Signet);
+            assert_eq!(address.script_pubkey(), script.to_vec());
+        }
+
+        // OP_RETURN and a v0 program of the wrong length
+        for script in &[&hex!("6a0568656c6c6f")[..], &hex!("0002751e")[..]] {
+            let info = AddressInfo::from_script(script);
+            assert_eq!(info.address_type, AddressType::NonStandard);
+            assert_eq!(info.witness_version, None);
+            assert_eq!(info.payload, script.to_vec());
+            assert!(Address::from_script(script, Network::Mainnet).is_err());
+        }
+    }
+}
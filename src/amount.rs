@@ -0,0 +1,315 @@
+//! Bitcoin amounts, kept as satoshis
+
+use alloc::string::String;
+use core::fmt::{self, Display, Formatter};
+use core::iter::Sum;
+use core::ops::{Add, AddAssign, Sub, SubAssign};
+use core::str::FromStr;
+#[cfg(feature = "std")]
+use std::io::{Read, Write};
+
+#[cfg(feature = "std")]
+use crate::consensus::{Decodable, Encodable};
+use crate::{Error, Result};
+
+/// Units an amount can be written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Denomination {
+    Btc,
+    MilliBtc,
+    Sat,
+}
+
+impl Denomination {
+    /// Decimal places relative to a satoshi
+    const fn precision(self) -> u32 {
+        match self {
+            Denomination::Btc => 8,
+            Denomination::MilliBtc => 5,
+            Denomination::Sat => 0,
+        }
+    }
+}
+
+impl Display for Denomination {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        fmt.write_str(match self {
+            Denomination::Btc => "BTC",
+            Denomination::MilliBtc => "mBTC",
+            Denomination::Sat => "sat",
+        })
+    }
+}
+
+/// Case insensitive, `sats` and `satoshi(s)` are accepted too
+impl FromStr for Denomination {
+    type Err = Error;
+
+    fn from_str(string: &str) -> Result<Self> {
+        match string.to_ascii_lowercase().as_str() {
+            "btc" => Ok(Denomination::Btc),
+            "mbtc" => Ok(Denomination::MilliBtc),
+            "sat" | "sats" | "satoshi" | "satoshis" => Ok(Denomination::Sat),
+            _ => Err(Error::InvalidAmount("unknown denomination")),
+        }
+    }
+}
+
+/// An amount of satoshis, the operators panic on overflow like the integer ones
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Amount(pub(crate) u64);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+    pub const ONE_SAT: Amount = Amount(1);
+    pub const ONE_BTC: Amount = Amount(100_000_000);
+    /// The most that will ever exist, 21 million BTC
+    pub const MAX_MONEY: Amount = Amount(21_000_000 * 100_000_000);
+
+    pub const fn from_sat(sats: u64) -> Self {
+        Amount(sats)
+    }
+
+    pub const fn to_sat(self) -> u64 {
+        self.0
+    }
+
+    /// Decimal amount in the given unit, with no more decimals than it allows
+    pub fn from_str_in(amount: &str, denomination: Denomination) -> Result<Self> {
+        let precision = denomination.precision();
+        let (whole, fraction) = match amount.find('.') {
+            Some(at) => (&amount[..at], &amount[at + 1..]),
+            None => (amount, ""),
+        };
+
+        let is_digits = |part: &str| part.bytes().all(|byte| byte.is_ascii_digit());
+        if (whole.is_empty() && fraction.is_empty()) || !is_digits(whole) || !is_digits(fraction) {
+            return Err(Error::InvalidAmount("not a decimal number"));
+        }
+
+        if fraction.len() > precision as usize {
+            return Err(Error::InvalidAmount("too many decimals"));
+        }
+
+        let whole: u64 = if whole.is_empty() {
+            0
+        } else {
+            whole
+                .parse()
+                .map_err(|_| Error::InvalidAmount("amount overflow"))?
+        };
+
+        let mut sats = 0;
+        for (i, digit) in fraction.bytes().enumerate() {
+            sats += u64::from(digit - b'0') * 10u64.pow(precision - 1 - i as u32);
+        }
+
+        whole
+            .checked_mul(10u64.pow(precision))
+            .and_then(|whole| whole.checked_add(sats))
+            .map(Amount)
+            .ok_or(Error::InvalidAmount("amount overflow"))
+    }
+
+    /// Decimal amount in the given unit, without trailing zeros or the unit
+    pub fn to_string_in(self, denomination: Denomination) -> String {
+        let scale = 10u64.pow(denomination.precision());
+        let (whole, fraction) = (self.0 / scale, self.0 % scale);
+        if fraction == 0 {
+            return alloc::format!("{}", whole);
+        }
+
+        let width = denomination.precision() as usize;
+        let fraction = alloc::format!("{:0width$}", fraction, width = width);
+        alloc::format!("{}.{}", whole, fraction.trim_end_matches('0'))
+    }
+
+    pub fn checked_add(self, rhs: Amount) -> Option<Amount> {
+        self.0.checked_add(rhs.0).map(Amount)
+    }
+
+    pub fn checked_sub(self, rhs: Amount) -> Option<Amount> {
+        self.0.checked_sub(rhs.0).map(Amount)
+    }
+
+    pub fn checked_mul(self, rhs: u64) -> Option<Amount> {
+        self.0.checked_mul(rhs).map(Amount)
+    }
+
+    pub fn checked_div(self, rhs: u64) -> Option<Amount> {
+        self.0.checked_div(rhs).map(Amount)
+    }
+}
+
+/// Written in BTC along with the unit, e.g. `1.5 BTC`
+impl Display for Amount {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            fmt,
+            "{} {}",
+            self.to_string_in(Denomination::Btc),
+            Denomination::Btc
+        )
+    }
+}
+
+/// A decimal amount followed by its unit, e.g. `1.5 BTC` or `546 sat`
+impl FromStr for Amount {
+    type Err = Error;
+
+    fn from_str(string: &str) -> Result<Self> {
+        let mut parts = string.split_whitespace();
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(amount), Some(denomination), None) => {
+                Amount::from_str_in(amount, denomination.parse()?)
+            }
+
+            _ => Err(Error::InvalidAmount("expected an amount and its unit")),
+        }
+    }
+}
+
+impl Add for Amount {
+    type Output = Amount;
+
+    fn add(self, rhs: Amount) -> Amount {
+        self.checked_add(rhs).expect("amount addition overflow")
+    }
+}
+
+impl AddAssign for Amount {
+    fn add_assign(&mut self, rhs: Amount) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub for Amount {
+    type Output = Amount;
+
+    fn sub(self, rhs: Amount) -> Amount {
+        self.checked_sub(rhs).expect("amount subtraction overflow")
+    }
+}
+
+impl SubAssign for Amount {
+    fn sub_assign(&mut self, rhs: Amount) {
+        *self = *self - rhs;
+    }
+}
+
+impl Sum for Amount {
+    fn sum<I: Iterator<Item = Amount>>(iter: I) -> Amount {
+        iter.fold(Amount::ZERO, Add::add)
+    }
+}
+
+impl<'a> Sum<&'a Amount> for Amount {
+    fn sum<I: Iterator<Item = &'a Amount>>(iter: I) -> Amount {
+        iter.copied().sum()
+    }
+}
+
+/// 8 bytes little endian satoshis
+#[cfg(feature = "std")]
+impl Encodable for Amount {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        self.0.consensus_encode(writer)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Decodable for Amount {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        Ok(Amount(u64::consensus_decode(reader)?))
+    }
+}
+
+#[cfg(feature = "serde")]
+serde_impl!(Amount, "an amount followed by its unit");
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+
+    use super::*;
+
+    #[test]
+    fn parse_and_format() {
+        let amount = Amount::from_str_in("1.5", Denomination::Btc).unwrap();
+        assert_eq!(amount.to_sat(), 150_000_000);
+        assert_eq!(amount.to_string_in(Denomination::MilliBtc), "1500");
+        assert_eq!(amount.to_string_in(Denomination::Sat), "150000000");
+        assert_eq!(amount.to_string(), "1.5 BTC");
+
+        assert_eq!(
+            Amount::from_str_in("0.00001", Denomination::MilliBtc).unwrap(),
+            Amount::ONE_SAT
+        );
+        assert_eq!(
+            Amount::from_str_in(".5", Denomination::Btc)
+                .unwrap()
+                .to_sat(),
+            50_000_000
+        );
+        assert_eq!(
+            Amount::from_sat(1).to_string_in(Denomination::Btc),
+            "0.00000001"
+        );
+        assert_eq!(
+            Amount::from_sat(120).to_string_in(Denomination::MilliBtc),
+            "0.0012"
+        );
+
+        assert_eq!("21000000 BTC".parse::<Amount>().unwrap(), Amount::MAX_MONEY);
+        assert_eq!("546 sat".parse::<Amount>().unwrap(), Amount::from_sat(546));
+        assert_eq!(
+            "2.5 mbtc".parse::<Amount>().unwrap(),
+            Amount::from_sat(250_000)
+        );
+        assert_eq!(
+            Amount::ZERO.to_string().parse::<Amount>().unwrap(),
+            Amount::ZERO
+        );
+
+        let invalid = [
+            "1.5",
+            "1.5 BTC x",
+            "1,5 BTC",
+            "0.000000001 BTC",
+            "0.5 sat",
+            ". BTC",
+            "-1 BTC",
+            "1 bits",
+            "184467440737.09551616 BTC",
+        ];
+        for amount in invalid.iter() {
+            assert!(amount.parse::<Amount>().is_err(), "{}", amount);
+        }
+    }
+
+    #[test]
+    fn arithmetic() {
+        let amounts = [Amount::from_sat(1_000), Amount::from_sat(2_500)];
+        let total: Amount = amounts.iter().sum();
+        assert_eq!(total, Amount::from_sat(3_500));
+        assert_eq!(total - amounts[0], amounts[1]);
+
+        assert_eq!(Amount::ZERO.checked_sub(Amount::ONE_SAT), None);
+        assert_eq!(
+            Amount::from_sat(u64::MAX).checked_add(Amount::ONE_SAT),
+            None
+        );
+        assert_eq!(
+            Amount::ONE_BTC.checked_mul(3),
+            Some(Amount::from_sat(300_000_000))
+        );
+        assert_eq!(Amount::ONE_BTC.checked_div(0), None);
+        assert!(Amount::ONE_SAT < Amount::ONE_BTC);
+    }
+
+    #[test]
+    #[should_panic(expected = "amount subtraction overflow")]
+    fn subtraction_overflow() {
+        let _ = Amount::ZERO - Amount::ONE_SAT;
+    }
+}
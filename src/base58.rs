@@ -1,34 +1,77 @@
-use lazy_static::lazy_static;
-use num_bigint::BigUint;
-use num_integer::Integer;
-use num_traits::{ToPrimitive, Zero};
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
 
 use crate::utils::hash256;
+use crate::{Error, Result};
 
 const BASE58_ALPHABET: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
 
+/// Value of each ASCII character in the alphabet, 0xff when not present
+const BASE58_DIGITS: [u8; 128] = {
+    let mut digits = [0xff; 128];
+    let mut i = 0;
+    while i < BASE58_ALPHABET.len() {
+        digits[BASE58_ALPHABET[i] as usize] = i as u8;
+        i += 1;
+    }
+    digits
+};
+
+/// Five base58 digits fit in a single `u32` limb
+const BASE58_LIMB: u64 = 58 * 58 * 58 * 58 * 58;
+
 pub fn encode<B>(bytes: B) -> String
 where
     B: AsRef<[u8]>,
 {
-    lazy_static! {
-        static ref BASE: BigUint = BigUint::from(58usize);
-    }
-
     let bytes = bytes.as_ref();
     let zeroes_count = bytes.iter().take_while(|b| **b == 0).count();
-    let prefix = String::from_utf8(vec![b'1'; zeroes_count]).unwrap();
-    let mut number = BigUint::from_bytes_be(bytes);
-
-    let mut result = String::new();
-    while !number.is_zero() {
-        let (q, r) = number.div_mod_floor(&*BASE);
-        number = q;
-        result.push(BASE58_ALPHABET[r.to_usize().unwrap()] as char);
+
+    // little endian limbs of five base58 digits, three input bytes are multiplied
+    // in at once (log(256)/log(58) ~ 1.37 digits per byte)
+    let mut limbs: Vec<u32> = Vec::with_capacity(bytes.len() * 138 / 500 + 1);
+    for chunk in bytes[zeroes_count..].chunks(3) {
+        let shift = 8 * chunk.len() as u32;
+        let mut carry = chunk
+            .iter()
+            .fold(0u64, |acc, byte| (acc << 8) | u64::from(*byte));
+
+        for limb in limbs.iter_mut() {
+            carry += u64::from(*limb) << shift;
+            *limb = (carry % BASE58_LIMB) as u32;
+            carry /= BASE58_LIMB;
+        }
+
+        while carry > 0 {
+            limbs.push((carry % BASE58_LIMB) as u32);
+            carry /= BASE58_LIMB;
+        }
+    }
+
+    let mut digits: Vec<u8> = Vec::with_capacity(limbs.len() * 5);
+    for limb in limbs {
+        let mut limb = limb;
+        for _ in 0..5 {
+            digits.push((limb % 58) as u8);
+            limb /= 58;
+        }
+    }
+
+    while digits.last() == Some(&0) {
+        digits.pop();
     }
 
-    result.push_str(&prefix);
-    result.chars().rev().collect()
+    let mut result = String::with_capacity(zeroes_count + digits.len());
+    result.extend(core::iter::repeat_n('1', zeroes_count));
+    result.extend(
+        digits
+            .iter()
+            .rev()
+            .map(|digit| BASE58_ALPHABET[*digit as usize] as char),
+    );
+
+    result
 }
 
 pub fn encode_checksum<B>(bytes: B) -> String
@@ -45,7 +88,78 @@ where
     encode(&data)
 }
 
-#[cfg(test)]
+/// Decode the given base58 string, leading ones become zero bytes
+pub fn decode<S>(string: S) -> Result<Vec<u8>>
+where
+    S: AsRef<str>,
+{
+    let string = string.as_ref();
+    let ones_count = string.bytes().take_while(|c| *c == b'1').count();
+
+    let digits = string
+        .chars()
+        .map(|c| {
+            BASE58_DIGITS
+                .get(c as usize)
+                .copied()
+                .filter(|digit| *digit != 0xff)
+                .ok_or(Error::InvalidBase58Character(c))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // little endian `u32` limbs, five digits are multiplied in at once
+    // (log(58)/log(2^32) ~ 0.18 limbs per digit)
+    let mut limbs: Vec<u32> = Vec::with_capacity(digits.len() * 19 / 100 + 1);
+    for chunk in digits.chunks(5) {
+        let factor = 58u64.pow(chunk.len() as u32);
+        let mut carry = chunk
+            .iter()
+            .fold(0u64, |acc, digit| acc * 58 + u64::from(*digit));
+
+        for limb in limbs.iter_mut() {
+            carry += u64::from(*limb) * factor;
+            *limb = carry as u32;
+            carry >>= 32;
+        }
+
+        while carry > 0 {
+            limbs.push(carry as u32);
+            carry >>= 32;
+        }
+    }
+
+    let bytes: Vec<_> = limbs
+        .iter()
+        .rev()
+        .flat_map(|limb| limb.to_be_bytes())
+        .collect();
+    let leading_zeroes = bytes.iter().take_while(|b| **b == 0).count();
+
+    let mut result = vec![0u8; ones_count];
+    result.extend_from_slice(&bytes[leading_zeroes..]);
+    Ok(result)
+}
+
+/// Decode the given base58 string and verify its four bytes checksum, only
+/// the payload (without the checksum) is returned
+pub fn decode_checksum<S>(string: S) -> Result<Vec<u8>>
+where
+    S: AsRef<str>,
+{
+    let mut data = decode(string)?;
+    if data.len() < 4 {
+        return Err(Error::InvalidBase58Checksum);
+    }
+
+    let checksum = data.split_off(data.len() - 4);
+    if hash256(&data)[..4] != checksum[..] {
+        return Err(Error::InvalidBase58Checksum);
+    }
+
+    Ok(data)
+}
+
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use super::*;
     use hex_literal::hex;
@@ -64,4 +178,58 @@ mod tests {
         let expected = "EQJsjkd6JaGwxrjEhfeqPenqHwrBmPQZjJGNSCHBkcF7";
         assert_eq!(encode(input), expected.to_string());
     }
+
+    #[test]
+    fn decode_base58() {
+        let input = "9MA8fRQrT4u8Zj8ZRd6MAiiyaxb2Y1CMpvVkHQu5hVM6";
+        let expected = hex!("7c076ff316692a3d7eb3c3bb0f8b1488cf72e1afcd929e29307032997a838a3d");
+        assert_eq!(decode(input).unwrap(), expected);
+
+        let input = encode_checksum(hex!("00e0a8ce4fd3b2c0c1e0c1c5e3a2c1d8"));
+        let expected = hex!("00e0a8ce4fd3b2c0c1e0c1c5e3a2c1d8");
+        assert_eq!(decode_checksum(&input).unwrap(), expected);
+
+        assert!(decode("0OIl").is_err());
+        assert!(decode_checksum("9MA8fRQrT4u8Zj8ZRd6MAiiyaxb2Y1CMpvVkHQu5hVM6").is_err());
+    }
+
+    #[test]
+    fn leading_zeroes() {
+        assert_eq!(encode([]), "");
+        assert_eq!(encode([0, 0]), "11");
+        assert_eq!(encode([0, 0, 58]), "1121");
+        assert_eq!(decode("").unwrap(), []);
+        assert_eq!(decode("11").unwrap(), [0, 0]);
+        assert_eq!(decode("1121").unwrap(), [0, 0, 58]);
+
+        let bytes: Vec<_> = (0..=255u8).rev().collect();
+        assert_eq!(decode(encode(&bytes)).unwrap(), bytes);
+        assert!(decode("1é").is_err());
+    }
+
+    #[test]
+    fn decode_addresses_and_wifs() {
+        let address = "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH";
+        let expected = hex!("00751e76e8199196d454941c45d1b3a323f1433bd6");
+        assert_eq!(decode_checksum(address).unwrap(), expected);
+
+        let wif = "5HueCGU8rMjxEXxiPuD5BDku4MkFqeZyd4dZ1jvhTVqvbTLvyTJ";
+        let expected = hex!("800c28fca386c7a227600b2fe50b7cae11ec86d3bf1fbe471be89827e19d72aa1d");
+        assert_eq!(decode_checksum(wif).unwrap(), expected);
+
+        // a single changed character breaks the checksum
+        let tampered = "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMJ";
+        assert!(matches!(
+            decode_checksum(tampered),
+            Err(Error::InvalidBase58Checksum)
+        ));
+        assert!(matches!(
+            decode_checksum("1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAM0"),
+            Err(Error::InvalidBase58Character('0'))
+        ));
+        assert!(matches!(
+            decode_checksum("1z"),
+            Err(Error::InvalidBase58Checksum)
+        ));
+    }
 }
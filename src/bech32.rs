@@ -0,0 +1,377 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::{Error, Result};
+
+// Bech32 strings (BIP173): a human readable part, the `1` separator and 5 bits
+// values encoded with the charset below, the last six being the checksum.
+
+const CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// Maximum length of a whole bech32 string
+pub const MAX_LENGTH: usize = 90;
+
+/// Checksum flavours, bech32 for witness v0 and bech32m (BIP350) from v1 onwards
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    Bech32,
+    Bech32m,
+}
+
+impl Variant {
+    /// Checksum required for the given witness version
+    pub fn for_witness_version(version: u8) -> Self {
+        if version == 0 {
+            Self::Bech32
+        } else {
+            Self::Bech32m
+        }
+    }
+
+    fn constant(self) -> u32 {
+        match self {
+            Self::Bech32 => 1,
+            Self::Bech32m => 0x2bc8_30a3,
+        }
+    }
+}
+
+fn polymod<I>(values: I) -> u32
+where
+    I: IntoIterator<Item = u8>,
+{
+    const GENERATOR: [u32; 5] = [
+        0x3b6a_57b2,
+        0x2650_8e6d,
+        0x1ea1_19fa,
+        0x3d42_33dd,
+        0x2a14_62b3,
+    ];
+
+    let mut checksum = 1u32;
+    for value in values {
+        let top = checksum >> 25;
+        checksum = (checksum & 0x1ff_ffff) << 5 ^ u32::from(value);
+
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+
+    checksum
+}
+
+fn hrp_expand(hrp: &str) -> impl Iterator<Item = u8> + '_ {
+    hrp.bytes()
+        .map(|b| b >> 5)
+        .chain(core::iter::once(0))
+        .chain(hrp.bytes().map(|b| b & 0x1f))
+}
+
+/// Human readable parts are 1 to 83 ASCII characters in the range [33, 126]
+fn check_hrp(hrp: &str) -> Result<()> {
+    if hrp.is_empty() || hrp.len() > MAX_LENGTH - 7 {
+        return Err(Error::InvalidBech32("invalid human readable part length"));
+    }
+
+    if hrp.bytes().any(|b| !(33..=126).contains(&b)) {
+        return Err(Error::InvalidBech32(
+            "invalid human readable part character",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Six 5 bits values checksumming the human readable part and data
+pub fn create_checksum(hrp: &str, data: &[u8], variant: Variant) -> [u8; 6] {
+    let values = hrp_expand(hrp)
+        .chain(data.iter().copied())
+        .chain([0u8; 6].iter().copied());
+    let polymod = polymod(values) ^ variant.constant();
+
+    let mut checksum = [0u8; 6];
+    for (i, value) in checksum.iter_mut().enumerate() {
+        *value = ((polymod >> (5 * (5 - i))) & 0x1f) as u8;
+    }
+
+    checksum
+}
+
+/// Variant of the checksum ending `data`, if it's valid for any
+pub fn verify_checksum(hrp: &str, data: &[u8]) -> Option<Variant> {
+    match polymod(hrp_expand(hrp).chain(data.iter().copied())) {
+        c if c == Variant::Bech32.constant() => Some(Variant::Bech32),
+        c if c == Variant::Bech32m.constant() => Some(Variant::Bech32m),
+        _ => None,
+    }
+}
+
+/// Encode the given 5 bits values under the human readable part, the result is
+/// lowercase
+pub fn encode(hrp: &str, data: &[u8], variant: Variant) -> Result<String> {
+    check_hrp(hrp)?;
+    if hrp.len() + 1 + data.len() + 6 > MAX_LENGTH {
+        return Err(Error::InvalidBech32("too long"));
+    }
+
+    if data.iter().any(|value| *value > 0x1f) {
+        return Err(Error::InvalidBech32("value out of range"));
+    }
+
+    let hrp = hrp.to_ascii_lowercase();
+    let checksum = create_checksum(&hrp, data, variant);
+
+    let mut result = String::with_capacity(hrp.len() + 1 + data.len() + 6);
+    result.push_str(&hrp);
+    result.push('1');
+    data.iter()
+        .chain(&checksum)
+        .for_each(|value| result.push(CHARSET[*value as usize] as char));
+
+    Ok(result)
+}
+
+/// Decode the given string into its (lowercase) human readable part and 5 bits
+/// values (without the checksum)
+pub fn decode(string: &str) -> Result<(String, Vec<u8>, Variant)> {
+    if string.len() > MAX_LENGTH {
+        return Err(Error::InvalidBech32("too long"));
+    }
+
+    let has_lower = string.bytes().any(|b| b.is_ascii_lowercase());
+    let has_upper = string.bytes().any(|b| b.is_ascii_uppercase());
+    if has_lower && has_upper {
+        return Err(Error::InvalidBech32("mixed case"));
+    }
+
+    let string = string.to_ascii_lowercase();
+    let separator = string
+        .rfind('1')
+        .ok_or(Error::InvalidBech32("missing separator"))?;
+
+    let (hrp, data) = (&string[..separator], &string[separator + 1..]);
+    check_hrp(hrp)?;
+    if data.len() < 6 {
+        return Err(Error::InvalidBech32("too short"));
+    }
+
+    let data = data
+        .bytes()
+        .map(|c| {
+            CHARSET
+                .iter()
+                .position(|a| *a == c)
+                .map(|value| value as u8)
+                .ok_or(Error::InvalidBech32("invalid character"))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let variant = verify_checksum(hrp, &data).ok_or(Error::InvalidBech32("invalid checksum"))?;
+
+    Ok((hrp.into(), data[..data.len() - 6].to_vec(), variant))
+}
+
+/// Regroup the bits of `data` from `from` bits values into `to` bits values, e.g.
+/// bytes into 5 bits values (with padding) and back (without)
+pub fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Result<Vec<u8>> {
+    let max = (1u32 << to) - 1;
+    let mut acc = 0u32;
+    let mut bits = 0u32;
+    let mut result = Vec::with_capacity(data.len() * from as usize / to as usize + 1);
+
+    for value in data {
+        let value = u32::from(*value);
+        if value >> from != 0 {
+            return Err(Error::InvalidBech32("value out of range"));
+        }
+
+        acc = (acc << from) | value;
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            result.push(((acc >> bits) & max) as u8);
+        }
+    }
+
+    if pad {
+        if bits > 0 {
+            result.push(((acc << (to - bits)) & max) as u8);
+        }
+    } else if bits >= from || (acc << (to - bits)) & max != 0 {
+        return Err(Error::InvalidBech32("invalid padding"));
+    }
+
+    Ok(result)
+}
+
+/// Encode a segwit address, picking bech32 or bech32m from the witness version
+pub fn encode_segwit(hrp: &str, version: u8, program: &[u8]) -> Result<String> {
+    check_witness_program(version, program)?;
+
+    let data: Vec<_> = core::iter::once(version)
+        .chain(convert_bits(program, 8, 5, true)?)
+        .collect();
+    encode(hrp, &data, Variant::for_witness_version(version))
+}
+
+/// Decode a segwit address into its human readable part, witness version and
+/// program, the checksum must be the one required by the version (BIP350)
+pub fn decode_segwit(string: &str) -> Result<(String, u8, Vec<u8>)> {
+    let (hrp, data, variant) = decode(string)?;
+    let (&version, data) = data
+        .split_first()
+        .ok_or(Error::InvalidBech32("missing witness version"))?;
+
+    if variant != Variant::for_witness_version(version) {
+        return Err(Error::InvalidBech32("wrong checksum for witness version"));
+    }
+
+    let program = convert_bits(data, 5, 8, false)?;
+    check_witness_program(version, &program)?;
+
+    Ok((hrp, version, program))
+}
+
+fn check_witness_program(version: u8, program: &[u8]) -> Result<()> {
+    if version > 16 {
+        return Err(Error::InvalidBech32("invalid witness version"));
+    }
+
+    match (version, program.len()) {
+        (0, 20) | (0, 32) => Ok(()),
+        (0, _) => Err(Error::InvalidBech32("invalid witness v0 program length")),
+        (_, 2..=40) => Ok(()),
+        _ => Err(Error::InvalidBech32("invalid witness program length")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_checksums() {
+        // BIP173 test vectors
+        let valid = [
+            "A12UEL5L",
+            "a12uel5l",
+            "an83characterlonghumanreadablepartthatcontainsthenumber1andtheexcludedcharactersbio1tt5tgs",
+            "abcdef1qpzry9x8gf2tvdw0s3jn54khce6mua7lmqqqxw",
+            "11qqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqc8247j",
+            "split1checkupstagehandshakeupstreamerranterredcaperred2y9e3w",
+            "?1ezyfcl",
+        ];
+
+        for string in valid.iter() {
+            let (hrp, data, variant) = decode(string).unwrap();
+            assert_eq!(variant, Variant::Bech32);
+            assert_eq!(
+                encode(&hrp, &data, variant).unwrap(),
+                string.to_ascii_lowercase()
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_checksums() {
+        // BIP173 test vectors
+        let invalid = [
+            "\x201nwldj5",
+            "an84characterslonghumanreadablepartthatcontainsthenumber1andtheexcludedcharactersbio1569pvx",
+            "pzry9x0s0muk",
+            "1pzry9x0s0muk",
+            "x1b4n0q5v",
+            "li1dgmt3",
+            "A1G7SGD8",
+            "10a06t8",
+            "1qzzfhee",
+            "a12UEL5L",
+        ];
+
+        for string in invalid.iter() {
+            assert!(decode(string).is_err(), "{}", string);
+        }
+
+        let (hrp, mut data, variant) = decode("a12uel5l").unwrap();
+        assert!(encode("", &data, variant).is_err());
+        data.push(0x20);
+        assert!(encode(&hrp, &data, variant).is_err());
+    }
+
+    #[test]
+    fn bits_conversion() {
+        let bytes = [0x00, 0x14, 0x75, 0x1e, 0x76, 0xe8, 0x19, 0x91];
+        let values = convert_bits(&bytes, 8, 5, true).unwrap();
+        assert!(values.iter().all(|value| *value <= 0x1f));
+        assert_eq!(convert_bits(&values, 5, 8, false).unwrap(), bytes);
+
+        // non zero padding and values out of range
+        assert!(convert_bits(&[0x1f], 5, 8, false).is_err());
+        assert!(convert_bits(&[0x20], 5, 8, true).is_err());
+    }
+
+    #[test]
+    fn segwit_addresses() {
+        // BIP350 test vectors
+        let valid = [
+            ("BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4", 0, 20),
+            (
+                "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7",
+                0,
+                32,
+            ),
+            (
+                "bc1pw508d6qejxtdg4y5r3zarvary0c5xw7kw508d6qejxtdg4y5r3zarvary0c5xw7kt5nd6y",
+                1,
+                40,
+            ),
+            ("BC1SW50QGDZ25J", 16, 2),
+            ("bc1zw508d6qejxtdg4y5r3zarvaryvaxxpcs", 2, 16),
+            (
+                "tb1pqqqqp399et2xygdj5xreqhjjvcmzhxw4aywxecjdzew6hylgvsesf3hn0c",
+                1,
+                32,
+            ),
+            (
+                "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0",
+                1,
+                32,
+            ),
+        ];
+
+        for (string, version, length) in valid.iter() {
+            let (hrp, decoded_version, program) = decode_segwit(string).unwrap();
+            assert_eq!(decoded_version, *version);
+            assert_eq!(program.len(), *length);
+
+            let encoded = encode_segwit(&hrp, *version, &program).unwrap();
+            assert_eq!(encoded, string.to_ascii_lowercase());
+        }
+
+        let invalid = [
+            // v1 with bech32, v0 with bech32m
+            "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqh2y7hd",
+            "tb1z0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqglt7rf",
+            "BC1S0XLXVLHEMJA6C4DQV22UAPCTQUPFHLXM9H8Z3K2E72Q4K9HCZ7VQ54WELL",
+            "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kemeawh",
+            "tb1q0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vq24jc47",
+            // invalid versions, lengths and padding
+            "bc130xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vq7zws8p",
+            "bc1pw5dgrnzv",
+            "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7v8n0nx0muaewav253zgeav",
+            "BC1QR508D6QEJXTDG4Y5R3ZARVARYV98GJ9P",
+            "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7v07qwwzcrf",
+            "bc1gmk9yu",
+        ];
+
+        for string in invalid.iter() {
+            assert!(decode_segwit(string).is_err(), "{}", string);
+        }
+
+        assert!(encode_segwit("bc", 0, &[0u8; 21]).is_err());
+        assert!(encode_segwit("bc", 17, &[0u8; 32]).is_err());
+    }
+}
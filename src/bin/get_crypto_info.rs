@@ -1,6 +1,7 @@
 use std::env;
 
 use anyhow::{anyhow, Result};
+use oxicoin::network::Network;
 use oxicoin::secp256k1::crypto::PrivateKey;
 use oxicoin::utils::hash256;
 
@@ -16,10 +17,22 @@ fn main() -> Result<()> {
     let private_key = PrivateKey::from_bytes_be(secret_digest);
     let public_key = private_key.public_key();
 
-    println!("Main address: {:?}", public_key.create_address(true, false));
-    println!("Test address: {:?}", public_key.create_address(true, true));
-    println!("Main WIF: {:?}", private_key.create_wif(true, false));
-    println!("Test WIF: {:?}", private_key.create_wif(true, true));
+    println!(
+        "Main address: {}",
+        public_key.create_address(true, Network::Mainnet)?
+    );
+    println!(
+        "Test address: {}",
+        public_key.create_address(true, Network::Testnet)?
+    );
+    println!(
+        "Main WIF: {:?}",
+        private_key.create_wif(true, Network::Mainnet)
+    );
+    println!(
+        "Test WIF: {:?}",
+        private_key.create_wif(true, Network::Testnet)
+    );
 
     Ok(())
 }
@@ -0,0 +1,348 @@
+//! `bitcoin:` payment URIs (BIP21)
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Display, Formatter};
+use core::str::FromStr;
+
+use crate::address::Address;
+use crate::amount::{Amount, Denomination};
+use crate::{Error, Result};
+
+const SCHEME: &str = "bitcoin:";
+
+/// A payment request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bip21Uri {
+    pub(crate) address: Address,
+    pub(crate) amount: Option<Amount>,
+    pub(crate) label: Option<String>,
+    pub(crate) message: Option<String>,
+    /// BOLT11 invoice of unified QR codes, kept as is
+    pub(crate) lightning: Option<String>,
+    /// Any other parameter, in the order they were given
+    pub(crate) extras: Vec<(String, String)>,
+}
+
+impl Bip21Uri {
+    pub fn new(address: Address) -> Self {
+        Self {
+            address,
+            amount: None,
+            label: None,
+            message: None,
+            lightning: None,
+            extras: Vec::new(),
+        }
+    }
+
+    pub fn with_amount(mut self, amount: Amount) -> Self {
+        self.amount = Some(amount);
+        self
+    }
+
+    pub fn with_label<S: Into<String>>(mut self, label: S) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    pub fn with_message<S: Into<String>>(mut self, message: S) -> Self {
+        self.message = Some(message.into());
+        self
+    }
+
+    pub fn with_lightning<S: Into<String>>(mut self, invoice: S) -> Self {
+        self.lightning = Some(invoice.into());
+        self
+    }
+
+    /// Add a parameter this crate doesn't know about, `req-` ones included
+    pub fn with_param<K, V>(mut self, key: K, value: V) -> Self
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.extras.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn address(&self) -> &Address {
+        &self.address
+    }
+
+    pub fn amount(&self) -> Option<Amount> {
+        self.amount
+    }
+
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    pub fn lightning(&self) -> Option<&str> {
+        self.lightning.as_deref()
+    }
+
+    pub fn extras(&self) -> &[(String, String)] {
+        &self.extras
+    }
+}
+
+impl From<Address> for Bip21Uri {
+    fn from(address: Address) -> Self {
+        Self::new(address)
+    }
+}
+
+impl From<Bip21Uri> for Address {
+    fn from(uri: Bip21Uri) -> Self {
+        uri.address
+    }
+}
+
+impl Display for Bip21Uri {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{}{}", SCHEME, self.address)?;
+
+        let mut separator = '?';
+        let mut param = |fmt: &mut Formatter<'_>, key: &str, value: &str| {
+            write!(
+                fmt,
+                "{}{}={}",
+                separator,
+                percent_encode(key),
+                percent_encode(value)
+            )?;
+            separator = '&';
+            Ok(())
+        };
+
+        if let Some(amount) = self.amount {
+            param(fmt, "amount", &amount.to_string_in(Denomination::Btc))?;
+        }
+
+        if let Some(label) = &self.label {
+            param(fmt, "label", label)?;
+        }
+
+        if let Some(message) = &self.message {
+            param(fmt, "message", message)?;
+        }
+
+        if let Some(lightning) = &self.lightning {
+            param(fmt, "lightning", lightning)?;
+        }
+
+        for (key, value) in &self.extras {
+            param(fmt, key, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The scheme is case insensitive, unknown `req-` parameters are rejected
+impl FromStr for Bip21Uri {
+    type Err = Error;
+
+    fn from_str(uri: &str) -> Result<Self> {
+        let has_scheme = uri
+            .get(..SCHEME.len())
+            .is_some_and(|scheme| scheme.eq_ignore_ascii_case(SCHEME));
+
+        if !has_scheme {
+            return Err(Error::InvalidBip21("missing bitcoin scheme"));
+        }
+
+        let rest = &uri[SCHEME.len()..];
+        let (address, query) = match rest.find('?') {
+            Some(at) => (&rest[..at], Some(&rest[at + 1..])),
+            None => (rest, None),
+        };
+
+        let mut result = Self::new(address.parse()?);
+        let params = query.into_iter().flat_map(|query| query.split('&'));
+
+        for param in params.filter(|param| !param.is_empty()) {
+            let (key, value) = match param.find('=') {
+                Some(at) => (&param[..at], &param[at + 1..]),
+                None => (param, ""),
+            };
+
+            let key = percent_decode(key)?;
+            let value = percent_decode(value)?;
+            let slot = match key.as_str() {
+                "amount" => {
+                    let amount = Amount::from_str_in(&value, Denomination::Btc)
+                        .map_err(|_| Error::InvalidBip21("invalid amount"))?;
+                    if result.amount.replace(amount).is_some() {
+                        return Err(Error::InvalidBip21("duplicated parameter"));
+                    }
+
+                    continue;
+                }
+
+                "label" => &mut result.label,
+                "message" => &mut result.message,
+                "lightning" => &mut result.lightning,
+                key if key.starts_with("req-") => {
+                    return Err(Error::InvalidBip21("unknown required parameter"));
+                }
+
+                _ => {
+                    result.extras.push((key, value));
+                    continue;
+                }
+            };
+
+            if slot.replace(value).is_some() {
+                return Err(Error::InvalidBip21("duplicated parameter"));
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+/// Everything but the unreserved characters (RFC 3986) is escaped
+fn percent_encode(string: &str) -> String {
+    let mut result = String::with_capacity(string.len());
+    for byte in string.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                result.push(byte as char)
+            }
+
+            _ => result.push_str(&alloc::format!("%{:02X}", byte)),
+        }
+    }
+
+    result
+}
+
+fn percent_decode(string: &str) -> Result<String> {
+    let invalid = || Error::InvalidBip21("invalid percent encoding");
+    let bytes = string.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = string.get(i + 1..i + 3).ok_or_else(invalid)?;
+            result.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+            i += 3;
+        } else {
+            result.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(result).map_err(|_| invalid())
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    // the address of the BIP21 examples has an invalid checksum
+    const ADDRESS: &str = "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH";
+
+    #[test]
+    fn bip21_examples() {
+        let uri: Bip21Uri = "bitcoin:1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH?label=Luke-Jr"
+            .parse()
+            .unwrap();
+        assert_eq!(uri.address().to_string(), ADDRESS);
+        assert_eq!(uri.label(), Some("Luke-Jr"));
+        assert_eq!(uri.amount(), None);
+
+        let uri: Bip21Uri = "bitcoin:1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH?amount=20.3&label=Luke-Jr"
+            .parse()
+            .unwrap();
+        assert_eq!(uri.amount(), Some(Amount::from_sat(2_030_000_000)));
+
+        let uri: Bip21Uri = "bitcoin:1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH?amount=50&label=Luke-Jr&message=Donation%20for%20project%20xyz"
+            .parse()
+            .unwrap();
+        assert_eq!(uri.amount(), Some(Amount::from_sat(5_000_000_000)));
+        assert_eq!(uri.message(), Some("Donation for project xyz"));
+
+        let uri: Bip21Uri = "bitcoin:1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH?somethingyoudontunderstand=50&somethingelseyoudontget=999"
+            .parse()
+            .unwrap();
+        assert_eq!(uri.extras().len(), 2);
+        assert_eq!(
+            uri.extras()[1],
+            ("somethingelseyoudontget".into(), "999".into())
+        );
+
+        assert!(
+            "bitcoin:1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH?req-somethingyoudontunderstand=50"
+                .parse::<Bip21Uri>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn unified_qr_codes() {
+        let uri: Bip21Uri = "BITCOIN:BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4?amount=0.00001&lightning=LNBC10U1P3PJ257PP5"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            uri.address().to_string(),
+            "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"
+        );
+        assert_eq!(uri.amount(), Some(Amount::from_sat(1000)));
+        assert_eq!(uri.lightning(), Some("LNBC10U1P3PJ257PP5"));
+    }
+
+    #[test]
+    fn display_roundtrip() {
+        let address: Address = ADDRESS.parse().unwrap();
+        let uri = Bip21Uri::from(address.clone())
+            .with_amount(Amount::from_sat(150_000_001))
+            .with_label("Luke-Jr")
+            .with_message("Pay me & thanks 100%")
+            .with_param("req-x", "y");
+
+        let encoded = uri.to_string();
+        assert_eq!(
+            encoded,
+            "bitcoin:1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH?amount=1.50000001&label=Luke-Jr\
+             &message=Pay%20me%20%26%20thanks%20100%25&req-x=y"
+        );
+
+        // required extras are kept when building, but can't be parsed back
+        assert!(encoded.parse::<Bip21Uri>().is_err());
+
+        let uri = Bip21Uri::from(address.clone()).with_amount(Amount::ONE_BTC);
+        assert_eq!(
+            uri.to_string(),
+            "bitcoin:1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH?amount=1"
+        );
+        assert_eq!(uri.to_string().parse::<Bip21Uri>().unwrap(), uri);
+        assert_eq!(Address::from(uri), address);
+    }
+
+    #[test]
+    fn invalid_uris() {
+        let invalid = [
+            "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH",
+            "litecoin:1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH",
+            "bitcoin:1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMh",
+            "bitcoin:1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH?amount=1,5",
+            "bitcoin:1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH?amount=0.000000001",
+            "bitcoin:1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH?amount=.",
+            "bitcoin:1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH?amount=1&amount=2",
+            "bitcoin:1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH?label=a&label=b",
+            "bitcoin:1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH?label=%zz",
+        ];
+
+        for uri in invalid.iter() {
+            assert!(uri.parse::<Bip21Uri>().is_err(), "{}", uri);
+        }
+    }
+}
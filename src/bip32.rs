@@ -0,0 +1,643 @@
+use std::fmt::{self, Display, Formatter};
+use std::ops::Range;
+use std::str::FromStr;
+
+use hex_literal::hex;
+use num_bigint::BigUint;
+use num_traits::Zero;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use subtle::{Choice, ConstantTimeEq};
+
+use crate::address::Address;
+use crate::network::Network;
+use crate::secp256k1::crypto::{PrivateKey, PublicKey};
+use crate::secp256k1::scalar::Scalar;
+use crate::secp256k1::{mul_g, N};
+use crate::utils::{hash160, hmac_sha512};
+use crate::{base58, Error, Result};
+
+/// First index of the hardened children
+pub const HARDENED: u32 = 0x8000_0000;
+
+/// Version bytes used when serializing extended keys
+pub mod version {
+    use super::hex;
+
+    pub const XPRV: [u8; 4] = hex!("0488ade4");
+    pub const XPUB: [u8; 4] = hex!("0488b21e");
+    pub const TPRV: [u8; 4] = hex!("04358394");
+    pub const TPUB: [u8; 4] = hex!("043587cf");
+    pub const ZPRV: [u8; 4] = hex!("04b2430c");
+    pub const ZPUB: [u8; 4] = hex!("04b24746");
+    pub const VPRV: [u8; 4] = hex!("045f18bc");
+    pub const VPUB: [u8; 4] = hex!("045f1cf6");
+}
+
+/// Format a single child index, hardened ones are suffixed with `'`
+pub(crate) fn fmt_index(index: u32, fmt: &mut Formatter) -> fmt::Result {
+    if index >= HARDENED {
+        write!(fmt, "{}'", index - HARDENED)
+    } else {
+        write!(fmt, "{}", index)
+    }
+}
+
+/// Parse a single child index, both `'` and `h` are accepted as hardened markers
+pub(crate) fn parse_index(index: &str) -> Result<u32> {
+    let (number, hardened) = match index.strip_suffix(|c| c == '\'' || c == 'h' || c == 'H') {
+        Some(number) => (number, true),
+        None => (index, false),
+    };
+
+    let number: u32 = number
+        .parse()
+        .map_err(|_| Error::InvalidDerivationPath("invalid child index"))?;
+
+    if number >= HARDENED {
+        return Err(Error::InvalidDerivationPath("child index out of range"));
+    }
+
+    Ok(if hardened { number + HARDENED } else { number })
+}
+
+/// A list of child indexes, e.g. `m/84'/0'/0'`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DerivationPath(pub(crate) Vec<u32>);
+
+impl DerivationPath {
+    pub fn new(indexes: Vec<u32>) -> Self {
+        Self(indexes)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Create a new path with `index` appended
+    pub fn child(&self, index: u32) -> Self {
+        let mut indexes = self.0.clone();
+        indexes.push(index);
+        Self(indexes)
+    }
+
+    /// Create a new path with all the indexes of `other` appended
+    pub fn extend(&self, other: &DerivationPath) -> Self {
+        Self(self.0.iter().chain(&other.0).copied().collect())
+    }
+}
+
+impl AsRef<[u32]> for DerivationPath {
+    fn as_ref(&self) -> &[u32] {
+        &self.0
+    }
+}
+
+impl From<Vec<u32>> for DerivationPath {
+    fn from(indexes: Vec<u32>) -> Self {
+        Self(indexes)
+    }
+}
+
+impl FromStr for DerivationPath {
+    type Err = Error;
+
+    fn from_str(path: &str) -> Result<Self> {
+        let path = path.strip_prefix('m').unwrap_or(path);
+        let path = path.strip_prefix('/').unwrap_or(path);
+        if path.is_empty() {
+            return Ok(Self::default());
+        }
+
+        path.split('/')
+            .map(parse_index)
+            .collect::<Result<_>>()
+            .map(Self)
+    }
+}
+
+impl Display for DerivationPath {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        write!(fmt, "m")?;
+        for index in &self.0 {
+            write!(fmt, "/")?;
+            fmt_index(*index, fmt)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Fields shared by the 78 bytes serialization of private and public extended keys
+fn deserialize_header(bytes: &[u8]) -> Result<(u8, [u8; 4], u32, [u8; 32])> {
+    if bytes.len() != 78 {
+        return Err(Error::InvalidExtendedKey("expecting 78 bytes"));
+    }
+
+    let depth = bytes[4];
+
+    let mut parent_fingerprint = [0u8; 4];
+    parent_fingerprint.copy_from_slice(&bytes[5..9]);
+
+    let mut child_number = [0u8; 4];
+    child_number.copy_from_slice(&bytes[9..13]);
+    let child_number = u32::from_be_bytes(child_number);
+
+    let mut chain_code = [0u8; 32];
+    chain_code.copy_from_slice(&bytes[13..45]);
+
+    if depth == 0 && (parent_fingerprint != [0u8; 4] || child_number != 0) {
+        return Err(Error::InvalidExtendedKey("invalid master key"));
+    }
+
+    Ok((depth, parent_fingerprint, child_number, chain_code))
+}
+
+/// Read the version bytes of a serialized extended key
+pub(crate) fn version_of(bytes: &[u8]) -> Result<[u8; 4]> {
+    if bytes.len() != 78 {
+        return Err(Error::InvalidExtendedKey("expecting 78 bytes"));
+    }
+
+    let mut version = [0u8; 4];
+    version.copy_from_slice(&bytes[..4]);
+    Ok(version)
+}
+
+#[derive(Debug, Clone)]
+pub struct ExtendedPrivateKey {
+    pub(crate) depth: u8,
+    pub(crate) parent_fingerprint: [u8; 4],
+    pub(crate) child_number: u32,
+    pub(crate) chain_code: [u8; 32],
+    pub(crate) private_key: PrivateKey,
+}
+
+impl ExtendedPrivateKey {
+    /// Create the master key from the given seed (BIP32)
+    pub fn new_master<B>(seed: B) -> Result<Self>
+    where
+        B: AsRef<[u8]>,
+    {
+        let seed = seed.as_ref();
+        if seed.len() < 16 || seed.len() > 64 {
+            return Err(Error::InvalidSeedLength(seed.len()));
+        }
+
+        let digest = hmac_sha512(b"Bitcoin seed", seed);
+        let secret = BigUint::from_bytes_be(&digest[..32]);
+        if secret.is_zero() || secret >= *N {
+            return Err(Error::InvalidExtendedKey("master key out of range"));
+        }
+
+        let mut chain_code = [0u8; 32];
+        chain_code.copy_from_slice(&digest[32..]);
+
+        Ok(Self {
+            depth: 0,
+            parent_fingerprint: [0u8; 4],
+            child_number: 0,
+            chain_code,
+            private_key: PrivateKey::new(secret),
+        })
+    }
+
+    /// Derive the child at `index`, indexes from [`HARDENED`] onwards give hardened children
+    pub fn derive_child(&self, index: u32) -> Result<Self> {
+        let mut data = if index >= HARDENED {
+            let secret = self.private_key.secret.to_bytes_be();
+            std::iter::once(0x00)
+                .chain(secret.iter().copied())
+                .collect()
+        } else {
+            self.private_key.public_key().serialize(true)?
+        };
+
+        data.extend_from_slice(&index.to_be_bytes());
+
+        let digest = hmac_sha512(self.chain_code, data);
+        let tweak = BigUint::from_bytes_be(&digest[..32]);
+        if tweak >= *N {
+            return Err(Error::InvalidChildKey(index));
+        }
+
+        let secret = Scalar::new(tweak) + &self.private_key.secret;
+        if secret.is_zero() {
+            return Err(Error::InvalidChildKey(index));
+        }
+
+        let mut chain_code = [0u8; 32];
+        chain_code.copy_from_slice(&digest[32..]);
+
+        Ok(Self {
+            depth: self
+                .depth
+                .checked_add(1)
+                .ok_or(Error::InvalidChildKey(index))?,
+            parent_fingerprint: self.fingerprint()?,
+            child_number: index,
+            chain_code,
+            private_key: PrivateKey::new(secret),
+        })
+    }
+
+    /// Derive successive children following the given indexes
+    pub fn derive_path<P>(&self, path: P) -> Result<Self>
+    where
+        P: AsRef<[u32]>,
+    {
+        path.as_ref()
+            .iter()
+            .try_fold(self.clone(), |key, index| key.derive_child(*index))
+    }
+
+    /// Get the extended public key (a.k.a. neutered key) of this key
+    pub fn extended_public_key(&self) -> ExtendedPublicKey {
+        ExtendedPublicKey {
+            depth: self.depth,
+            parent_fingerprint: self.parent_fingerprint,
+            child_number: self.child_number,
+            chain_code: self.chain_code,
+            public_key: self.private_key.public_key().clone(),
+        }
+    }
+
+    pub fn private_key(&self) -> &PrivateKey {
+        &self.private_key
+    }
+
+    pub fn chain_code(&self) -> &[u8; 32] {
+        &self.chain_code
+    }
+
+    pub fn depth(&self) -> u8 {
+        self.depth
+    }
+
+    pub fn child_number(&self) -> u32 {
+        self.child_number
+    }
+
+    /// First four bytes of the hash160 of the compressed public key
+    pub fn fingerprint(&self) -> Result<[u8; 4]> {
+        let digest = hash160(self.private_key.public_key().serialize_compressed()?);
+        let mut fingerprint = [0u8; 4];
+        fingerprint.copy_from_slice(&digest[..4]);
+        Ok(fingerprint)
+    }
+
+    /// Serialize this key into its 78 bytes representation
+    pub fn serialize(&self, version: [u8; 4]) -> Result<Vec<u8>> {
+        let secret = self.private_key.secret.to_bytes_be();
+
+        let result = version
+            .iter()
+            .copied()
+            .chain(std::iter::once(self.depth))
+            .chain(self.parent_fingerprint.iter().copied())
+            .chain(self.child_number.to_be_bytes().iter().copied())
+            .chain(self.chain_code.iter().copied())
+            .chain(std::iter::once(0x00))
+            .chain(secret.iter().copied())
+            .collect();
+
+        Ok(result)
+    }
+
+    /// Deserialize the 78 bytes representation, version bytes aren't checked
+    pub fn deserialize<B>(bytes: B) -> Result<Self>
+    where
+        B: AsRef<[u8]>,
+    {
+        let bytes = bytes.as_ref();
+        let (depth, parent_fingerprint, child_number, chain_code) = deserialize_header(bytes)?;
+
+        if bytes[45] != 0x00 {
+            return Err(Error::InvalidExtendedKey("expecting private key data"));
+        }
+
+        let secret = BigUint::from_bytes_be(&bytes[46..]);
+        if secret.is_zero() || secret >= *N {
+            return Err(Error::InvalidExtendedKey("private key out of range"));
+        }
+
+        Ok(Self {
+            depth,
+            parent_fingerprint,
+            child_number,
+            chain_code,
+            private_key: PrivateKey::new(secret),
+        })
+    }
+
+    /// Create the base58 (with checksum) string, e.g. `xprv...`
+    pub fn create_xprv(&self, version: [u8; 4]) -> Result<String> {
+        Ok(base58::encode_checksum(self.serialize(version)?))
+    }
+
+    /// Parse a base58 (with checksum) string, e.g. `xprv...`
+    pub fn from_xprv<S>(xprv: S) -> Result<Self>
+    where
+        S: AsRef<str>,
+    {
+        Self::deserialize(base58::decode_checksum(xprv)?)
+    }
+}
+
+impl ConstantTimeEq for ExtendedPrivateKey {
+    fn ct_eq(&self, other: &Self) -> Choice {
+        self.depth.ct_eq(&other.depth)
+            & self.parent_fingerprint.ct_eq(&other.parent_fingerprint)
+            & self.child_number.ct_eq(&other.child_number)
+            & self.chain_code.ct_eq(&other.chain_code)
+            & self.private_key.ct_eq(&other.private_key)
+    }
+}
+
+impl PartialEq for ExtendedPrivateKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.ct_eq(other).into()
+    }
+}
+
+impl Eq for ExtendedPrivateKey {}
+
+/// Base58 `xprv` encoding, the network isn't part of the key so mainnet is used
+impl Display for ExtendedPrivateKey {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        let xprv = self.create_xprv(version::XPRV).map_err(|_| fmt::Error)?;
+        write!(fmt, "{}", xprv)
+    }
+}
+
+/// Base58 string of any network, the version bytes aren't checked
+impl FromStr for ExtendedPrivateKey {
+    type Err = Error;
+
+    fn from_str(string: &str) -> Result<Self> {
+        Self::from_xprv(string)
+    }
+}
+
+#[cfg(feature = "serde")]
+serde_impl!(
+    ExtendedPrivateKey,
+    "a base58 extended private key",
+    |key: &ExtendedPrivateKey| key.serialize(version::XPRV),
+    ExtendedPrivateKey::deserialize::<&[u8]>
+);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtendedPublicKey {
+    pub(crate) depth: u8,
+    pub(crate) parent_fingerprint: [u8; 4],
+    pub(crate) child_number: u32,
+    pub(crate) chain_code: [u8; 32],
+    pub(crate) public_key: PublicKey,
+}
+
+impl ExtendedPublicKey {
+    /// Derive the (non hardened) child at `index`
+    pub fn derive_child(&self, index: u32) -> Result<Self> {
+        if index >= HARDENED {
+            return Err(Error::HardenedPublicDerivation(index));
+        }
+
+        let mut data = self.public_key.serialize(true)?;
+        data.extend_from_slice(&index.to_be_bytes());
+
+        let digest = hmac_sha512(self.chain_code, data);
+        let tweak = BigUint::from_bytes_be(&digest[..32]);
+        if tweak >= *N {
+            return Err(Error::InvalidChildKey(index));
+        }
+
+        let ec_point = mul_g(&Scalar::new(tweak)) + &self.public_key.ec_point;
+        if ec_point.is_point_at_inf() {
+            return Err(Error::InvalidChildKey(index));
+        }
+
+        let mut chain_code = [0u8; 32];
+        chain_code.copy_from_slice(&digest[32..]);
+
+        Ok(Self {
+            depth: self
+                .depth
+                .checked_add(1)
+                .ok_or(Error::InvalidChildKey(index))?,
+            parent_fingerprint: self.fingerprint()?,
+            child_number: index,
+            chain_code,
+            public_key: PublicKey { ec_point },
+        })
+    }
+
+    /// Derive successive children following the given indexes
+    pub fn derive_path<P>(&self, path: P) -> Result<Self>
+    where
+        P: AsRef<[u32]>,
+    {
+        path.as_ref()
+            .iter()
+            .try_fold(self.clone(), |key, index| key.derive_child(*index))
+    }
+
+    /// Derive every child in the given range (spread over all cores with the
+    /// `parallel` feature), typically a batch of receiving keys
+    pub fn derive_range(&self, range: Range<u32>) -> Result<Vec<Self>> {
+        #[cfg(feature = "parallel")]
+        let children = range.into_par_iter().map(|index| self.derive_child(index));
+
+        #[cfg(not(feature = "parallel"))]
+        let children = range.map(|index| self.derive_child(index));
+
+        children.collect()
+    }
+
+    /// Legacy addresses (P2PKH, compressed keys) of the children in the given range
+    pub fn derive_addresses(&self, range: Range<u32>, network: Network) -> Result<Vec<Address>> {
+        #[cfg(feature = "parallel")]
+        let addresses = range.into_par_iter().map(|index| {
+            let child = self.derive_child(index)?;
+            child.public_key.create_address(true, network)
+        });
+
+        #[cfg(not(feature = "parallel"))]
+        let addresses = range.map(|index| {
+            let child = self.derive_child(index)?;
+            child.public_key.create_address(true, network)
+        });
+
+        addresses.collect()
+    }
+
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    pub fn chain_code(&self) -> &[u8; 32] {
+        &self.chain_code
+    }
+
+    pub fn depth(&self) -> u8 {
+        self.depth
+    }
+
+    pub fn child_number(&self) -> u32 {
+        self.child_number
+    }
+
+    /// First four bytes of the hash160 of the compressed public key
+    pub fn fingerprint(&self) -> Result<[u8; 4]> {
+        let digest = hash160(self.public_key.serialize_compressed()?);
+        let mut fingerprint = [0u8; 4];
+        fingerprint.copy_from_slice(&digest[..4]);
+        Ok(fingerprint)
+    }
+
+    /// Serialize this key into its 78 bytes representation
+    pub fn serialize(&self, version: [u8; 4]) -> Result<Vec<u8>> {
+        let result = version
+            .iter()
+            .copied()
+            .chain(std::iter::once(self.depth))
+            .chain(self.parent_fingerprint.iter().copied())
+            .chain(self.child_number.to_be_bytes().iter().copied())
+            .chain(self.chain_code.iter().copied())
+            .chain(self.public_key.serialize_compressed()?.iter().copied())
+            .collect();
+
+        Ok(result)
+    }
+
+    /// Deserialize the 78 bytes representation, version bytes aren't checked
+    pub fn deserialize<B>(bytes: B) -> Result<Self>
+    where
+        B: AsRef<[u8]>,
+    {
+        let bytes = bytes.as_ref();
+        let (depth, parent_fingerprint, child_number, chain_code) = deserialize_header(bytes)?;
+
+        if bytes[45] != 0x02 && bytes[45] != 0x03 {
+            return Err(Error::InvalidExtendedKey("expecting compressed public key"));
+        }
+
+        Ok(Self {
+            depth,
+            parent_fingerprint,
+            child_number,
+            chain_code,
+            public_key: PublicKey::deserialize(&bytes[45..])?,
+        })
+    }
+
+    /// Create the base58 (with checksum) string, e.g. `xpub...`
+    pub fn create_xpub(&self, version: [u8; 4]) -> Result<String> {
+        Ok(base58::encode_checksum(self.serialize(version)?))
+    }
+
+    /// Parse a base58 (with checksum) string, e.g. `xpub...`
+    pub fn from_xpub<S>(xpub: S) -> Result<Self>
+    where
+        S: AsRef<str>,
+    {
+        Self::deserialize(base58::decode_checksum(xpub)?)
+    }
+}
+
+/// Base58 `xpub` encoding, the network isn't part of the key so mainnet is used
+impl Display for ExtendedPublicKey {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        let xpub = self.create_xpub(version::XPUB).map_err(|_| fmt::Error)?;
+        write!(fmt, "{}", xpub)
+    }
+}
+
+/// Base58 string of any network, the version bytes aren't checked
+impl FromStr for ExtendedPublicKey {
+    type Err = Error;
+
+    fn from_str(string: &str) -> Result<Self> {
+        Self::from_xpub(string)
+    }
+}
+
+#[cfg(feature = "serde")]
+serde_impl!(
+    ExtendedPublicKey,
+    "a base58 extended public key",
+    |key: &ExtendedPublicKey| key.serialize(version::XPUB),
+    ExtendedPublicKey::deserialize::<&[u8]>
+);
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+
+    #[test]
+    fn bip32_test_vector_1() {
+        let master =
+            ExtendedPrivateKey::new_master(hex!("000102030405060708090a0b0c0d0e0f")).unwrap();
+        assert_eq!(
+            master.create_xprv(version::XPRV).unwrap(),
+            "xprv9s21ZrQH143K3QTDL4LXw2F7HEK3wJUD2nW2nRk4stbPy6cq3jPPqjiChkVvvNKmPGJxWUtg6LnF5kejMRNNU3TGtRBeJgk33yuGBxrMPHi"
+        );
+
+        let child = master.derive_path([HARDENED, 1]).unwrap();
+        assert_eq!(child.depth(), 2);
+        assert_eq!(
+            child.create_xprv(version::XPRV).unwrap(),
+            "xprv9wTYmMFdV23N2TdNG573QoEsfRrWKQgWeibmLntzniatZvR9BmLnvSxqu53Kw1UmYPxLgboyZQaXwTCg8MSY3H2EU4pWcQDnRnrVA1xe8fs"
+        );
+
+        let parsed = ExtendedPrivateKey::from_xprv(child.create_xprv(version::XPRV).unwrap());
+        assert_eq!(parsed.unwrap(), child);
+    }
+
+    #[test]
+    fn public_derivation() {
+        let xpub = "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8";
+        let master = ExtendedPublicKey::from_xpub(xpub).unwrap();
+        assert_eq!(master.create_xpub(version::XPUB).unwrap(), xpub);
+
+        let private = ExtendedPrivateKey::new_master(hex!("000102030405060708090a0b0c0d0e0f"))
+            .unwrap()
+            .derive_path([0, 7, 1])
+            .unwrap();
+
+        let public = master.derive_path([0, 7, 1]).unwrap();
+        assert_eq!(private.extended_public_key(), public);
+        assert!(master.derive_child(HARDENED).is_err());
+
+        let children = master.derive_range(0..4).unwrap();
+        assert_eq!(children.len(), 4);
+        assert_eq!(children[3], master.derive_child(3).unwrap());
+
+        let addresses = master.derive_addresses(0..4, Network::Mainnet).unwrap();
+        let expected = children[2]
+            .public_key()
+            .create_address(true, Network::Mainnet)
+            .unwrap();
+        assert_eq!(addresses[2], expected);
+        assert!(master
+            .derive_addresses(HARDENED - 1..HARDENED + 1, Network::Mainnet)
+            .is_err());
+    }
+
+    #[test]
+    fn derivation_path() {
+        let path: DerivationPath = "m/84'/0h/0'/1/23".parse().unwrap();
+        assert_eq!(path.as_ref(), &[84 + HARDENED, HARDENED, HARDENED, 1, 23]);
+        assert_eq!(path.to_string(), "m/84'/0'/0'/1/23");
+
+        assert!("m".parse::<DerivationPath>().unwrap().is_empty());
+        assert!("m/2147483648".parse::<DerivationPath>().is_err());
+        assert!("m/1/x".parse::<DerivationPath>().is_err());
+    }
+}
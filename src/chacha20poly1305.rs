@@ -0,0 +1,289 @@
+//! ChaCha20, Poly1305 and the AEAD made of both (RFC 8439), what the v2 P2P
+//! transport (BIP324) encrypts with
+
+use alloc::vec::Vec;
+
+use subtle::ConstantTimeEq;
+
+use crate::{Error, Result};
+
+pub const KEY_SIZE: usize = 32;
+pub const NONCE_SIZE: usize = 12;
+pub const TAG_SIZE: usize = 16;
+/// Bytes of keystream each block gives
+pub const BLOCK_SIZE: usize = 64;
+
+/// "expand 32-byte k"
+const CONSTANTS: [u32; 4] = [0x61707865, 0x3320646e, 0x79622d32, 0x6b206574];
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+fn words<const N: usize>(bytes: &[u8]) -> [u32; N] {
+    let mut words = [0; N];
+    for (word, bytes) in words.iter_mut().zip(bytes.chunks(4)) {
+        *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+    words
+}
+
+/// The keystream block `counter` of `key` and `nonce`
+pub fn chacha20_block(
+    key: &[u8; KEY_SIZE],
+    nonce: &[u8; NONCE_SIZE],
+    counter: u32,
+) -> [u8; BLOCK_SIZE] {
+    let key: [u32; 8] = words(key);
+    let nonce: [u32; 3] = words(nonce);
+
+    let mut initial = [0u32; 16];
+    initial[..4].copy_from_slice(&CONSTANTS);
+    initial[4..12].copy_from_slice(&key);
+    initial[12] = counter;
+    initial[13..].copy_from_slice(&nonce);
+
+    let mut state = initial;
+    for _ in 0..10 {
+        quarter_round(&mut state, 0, 4, 8, 12);
+        quarter_round(&mut state, 1, 5, 9, 13);
+        quarter_round(&mut state, 2, 6, 10, 14);
+        quarter_round(&mut state, 3, 7, 11, 15);
+        quarter_round(&mut state, 0, 5, 10, 15);
+        quarter_round(&mut state, 1, 6, 11, 12);
+        quarter_round(&mut state, 2, 7, 8, 13);
+        quarter_round(&mut state, 3, 4, 9, 14);
+    }
+
+    let mut block = [0; BLOCK_SIZE];
+    for ((bytes, word), initial) in block.chunks_mut(4).zip(state.iter()).zip(initial.iter()) {
+        bytes.copy_from_slice(&word.wrapping_add(*initial).to_le_bytes());
+    }
+    block
+}
+
+/// Encrypts or decrypts `data` in place, with the keystream starting at block
+/// `counter`
+pub fn chacha20(key: &[u8; KEY_SIZE], nonce: &[u8; NONCE_SIZE], counter: u32, data: &mut [u8]) {
+    for (i, chunk) in data.chunks_mut(BLOCK_SIZE).enumerate() {
+        let block = chacha20_block(key, nonce, counter.wrapping_add(i as u32));
+        chunk
+            .iter_mut()
+            .zip(block.iter())
+            .for_each(|(byte, key)| *byte ^= key);
+    }
+}
+
+/// The one-time authenticator of `message`, `key` must never be used twice
+pub fn poly1305(key: &[u8; KEY_SIZE], message: &[u8]) -> [u8; TAG_SIZE] {
+    // 130 bits numbers in limbs of 44, 44 and 42 bits
+    const MASK_44: u64 = (1 << 44) - 1;
+    const MASK_42: u64 = (1 << 42) - 1;
+
+    let halves = |bytes: &[u8]| -> (u64, u64) {
+        let mut low = [0; 8];
+        let mut high = [0; 8];
+        low.copy_from_slice(&bytes[..8]);
+        high.copy_from_slice(&bytes[8..16]);
+        (u64::from_le_bytes(low), u64::from_le_bytes(high))
+    };
+
+    // r clamped as the RFC says
+    let (t0, t1) = halves(&key[..16]);
+    let r0 = t0 & 0xffc0fffffff;
+    let r1 = ((t0 >> 44) | (t1 << 20)) & 0xfffffc0ffff;
+    let r2 = (t1 >> 24) & 0x00ffffffc0f;
+    let (s1, s2) = (r1 * 20, r2 * 20);
+
+    let (mut h0, mut h1, mut h2) = (0u64, 0u64, 0u64);
+    for chunk in message.chunks(16) {
+        // full blocks get their 2^128 bit here, the last one with its 0x01
+        let mut block = [0; 16];
+        block[..chunk.len()].copy_from_slice(chunk);
+        let high_bit = match chunk.len() {
+            16 => 1 << 40,
+            len => {
+                block[len] = 1;
+                0
+            }
+        };
+
+        let (t0, t1) = halves(&block);
+        h0 += t0 & MASK_44;
+        h1 += ((t0 >> 44) | (t1 << 20)) & MASK_44;
+        h2 += ((t1 >> 24) & MASK_42) | high_bit;
+
+        // h * r mod 2^130 - 5
+        let mul = |a: u64, b: u64| a as u128 * b as u128;
+        let d0 = mul(h0, r0) + mul(h1, s2) + mul(h2, s1);
+        let mut d1 = mul(h0, r1) + mul(h1, r0) + mul(h2, s2);
+        let mut d2 = mul(h0, r2) + mul(h1, r1) + mul(h2, r0);
+
+        d1 += d0 >> 44;
+        h0 = d0 as u64 & MASK_44;
+        d2 += d1 >> 44;
+        h1 = d1 as u64 & MASK_44;
+        let carry = (d2 >> 42) as u64;
+        h2 = d2 as u64 & MASK_42;
+        h0 += carry * 5;
+        h1 += h0 >> 44;
+        h0 &= MASK_44;
+    }
+
+    // fully carried and reduced
+    let mut carry;
+    for _ in 0..2 {
+        carry = h1 >> 44;
+        h1 &= MASK_44;
+        h2 += carry;
+        carry = h2 >> 42;
+        h2 &= MASK_42;
+        h0 += carry * 5;
+        carry = h0 >> 44;
+        h0 &= MASK_44;
+        h1 += carry;
+    }
+
+    // h - p, kept if it doesn't underflow
+    let mut g0 = h0 + 5;
+    carry = g0 >> 44;
+    g0 &= MASK_44;
+    let mut g1 = h1 + carry;
+    carry = g1 >> 44;
+    g1 &= MASK_44;
+    let g2 = (h2 + carry).wrapping_sub(1 << 42);
+    let mask = (g2 >> 63).wrapping_sub(1);
+    h0 = (h0 & !mask) | (g0 & mask);
+    h1 = (h1 & !mask) | (g1 & mask);
+    h2 = (h2 & !mask) | (g2 & mask);
+
+    // plus s, mod 2^128
+    let (t0, t1) = halves(&key[16..]);
+    h0 += t0 & MASK_44;
+    carry = h0 >> 44;
+    h0 &= MASK_44;
+    h1 += (((t0 >> 44) | (t1 << 20)) & MASK_44) + carry;
+    carry = h1 >> 44;
+    h1 &= MASK_44;
+    h2 += ((t1 >> 24) & MASK_42) + carry;
+
+    let mut tag = [0; TAG_SIZE];
+    tag[..8].copy_from_slice(&(h0 | (h1 << 44)).to_le_bytes());
+    tag[8..].copy_from_slice(&((h1 >> 20) | (h2 << 24)).to_le_bytes());
+    tag
+}
+
+/// The tag of `aad` and `ciphertext`, its Poly1305 key taken from the first
+/// keystream block
+fn tag(key: &[u8; KEY_SIZE], nonce: &[u8; NONCE_SIZE], aad: &[u8], ciphertext: &[u8]) -> [u8; 16] {
+    let mut poly_key = [0; KEY_SIZE];
+    poly_key.copy_from_slice(&chacha20_block(key, nonce, 0)[..KEY_SIZE]);
+
+    let padding = |len: usize| &[0u8; 16][..(16 - len % 16) % 16];
+    let mut data = Vec::with_capacity(aad.len() + ciphertext.len() + 48);
+    data.extend_from_slice(aad);
+    data.extend_from_slice(padding(aad.len()));
+    data.extend_from_slice(ciphertext);
+    data.extend_from_slice(padding(ciphertext.len()));
+    data.extend_from_slice(&(aad.len() as u64).to_le_bytes());
+    data.extend_from_slice(&(ciphertext.len() as u64).to_le_bytes());
+    poly1305(&poly_key, &data)
+}
+
+/// `plaintext` encrypted and followed by the tag authenticating it along with
+/// `aad`
+pub fn encrypt(
+    key: &[u8; KEY_SIZE],
+    nonce: &[u8; NONCE_SIZE],
+    aad: &[u8],
+    plaintext: &[u8],
+) -> Vec<u8> {
+    let mut ciphertext = Vec::with_capacity(plaintext.len() + TAG_SIZE);
+    ciphertext.extend_from_slice(plaintext);
+    chacha20(key, nonce, 1, &mut ciphertext);
+
+    let tag = tag(key, nonce, aad, &ciphertext);
+    ciphertext.extend_from_slice(&tag);
+    ciphertext
+}
+
+/// The plaintext of what [`encrypt`] gave, failing if it or `aad` don't match
+/// the tag
+pub fn decrypt(
+    key: &[u8; KEY_SIZE],
+    nonce: &[u8; NONCE_SIZE],
+    aad: &[u8],
+    ciphertext: &[u8],
+) -> Result<Vec<u8>> {
+    if ciphertext.len() < TAG_SIZE {
+        return Err(Error::AuthenticationFailed);
+    }
+
+    let (ciphertext, expected) = ciphertext.split_at(ciphertext.len() - TAG_SIZE);
+    if !bool::from(tag(key, nonce, aad, ciphertext).ct_eq(expected)) {
+        return Err(Error::AuthenticationFailed);
+    }
+
+    let mut plaintext = ciphertext.to_vec();
+    chacha20(key, nonce, 1, &mut plaintext);
+    Ok(plaintext)
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+
+    #[test]
+    fn block() {
+        // RFC 8439 2.3.2
+        let key = hex!("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f");
+        let nonce = hex!("000000090000004a00000000");
+        assert_eq!(
+            chacha20_block(&key, &nonce, 1)[..],
+            hex!("10f1e7e4d13b5915500fdd1fa32071c4c7d1f4c733c068030422aa9ac3d46c4ed2826446079faa0914c2d705d98b02a2b5129cd1de164eb9cbd083e8a2503c4e")[..]
+        );
+    }
+
+    #[test]
+    fn mac() {
+        // RFC 8439 2.5.2
+        let key = hex!("85d6be7857556d337f4452fe42d506a80103808afb0db2fd4abff6af4149f51b");
+        assert_eq!(
+            poly1305(&key, b"Cryptographic Forum Research Group"),
+            hex!("a8061dc1305136c6c22b8baf0c0127a9")
+        );
+        // the key alone when there's nothing to authenticate
+        assert_eq!(poly1305(&[1; 32], b""), [1; 16]);
+    }
+
+    #[test]
+    fn aead() {
+        // RFC 8439 2.8.2
+        let key = hex!("808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f");
+        let nonce = hex!("070000004041424344454647");
+        let aad = hex!("50515253c0c1c2c3c4c5c6c7");
+        let plaintext = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+
+        let ciphertext = encrypt(&key, &nonce, &aad, plaintext);
+        assert_eq!(
+            ciphertext[..],
+            hex!("d31a8d34648e60db7b86afbc53ef7ec2a4aded51296e08fea9e2b5a736ee62d63dbea45e8ca9671282fafb69da92728b1a71de0a9e060b2905d6a5b67ecd3b3692ddbd7f2d778b8c9803aee328091b58fab324e4fad675945585808b4831d7bc3ff4def08e4b7a9de576d26586cec64b61161ae10b594f09e26a7e902ecbd0600691")[..]
+        );
+        assert_eq!(decrypt(&key, &nonce, &aad, &ciphertext).unwrap(), plaintext);
+
+        let mut tampered = ciphertext.clone();
+        tampered[0] ^= 1;
+        assert!(decrypt(&key, &nonce, &aad, &tampered).is_err());
+        assert!(decrypt(&key, &nonce, b"", &ciphertext).is_err());
+        assert!(decrypt(&key, &nonce, &aad, &ciphertext[..TAG_SIZE - 1]).is_err());
+    }
+}
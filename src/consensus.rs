@@ -0,0 +1,305 @@
+use std::io::{Cursor, Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::secp256k1::crypto::PublicKey;
+use crate::secp256k1::signature::Signature;
+use crate::varint::{self, VarInt};
+use crate::{Error, Result};
+
+/// Upper bound of any length prefixed vector, same as Bitcoin Core's `MAX_SIZE`
+pub const MAX_VEC_SIZE: u64 = 0x0200_0000;
+
+/// Types with a consensus (wire) encoding
+pub trait Encodable {
+    /// Write the consensus encoding, returning the number of bytes written
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize>;
+}
+
+/// Types that can be read back from their consensus (wire) encoding
+pub trait Decodable: Sized {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self>;
+}
+
+/// Consensus encoding of the given value
+pub fn serialize<T: Encodable + ?Sized>(value: &T) -> Result<Vec<u8>> {
+    let mut result = Vec::new();
+    value.consensus_encode(&mut result)?;
+    Ok(result)
+}
+
+/// Decode a value that must span the whole given bytes
+pub fn deserialize<T: Decodable>(bytes: &[u8]) -> Result<T> {
+    let mut cursor = Cursor::new(bytes);
+    let value = T::consensus_decode(&mut cursor)?;
+
+    let trailing = bytes.len() - cursor.position() as usize;
+    if trailing != 0 {
+        return Err(Error::TrailingBytes(trailing));
+    }
+
+    Ok(value)
+}
+
+/// Write a CompactSize, always the shortest encoding
+pub fn write_compact_size<W: Write + ?Sized>(writer: &mut W, value: u64) -> Result<usize> {
+    let encoded = varint::encode(value);
+    writer.write_all(&encoded)?;
+    Ok(encoded.len())
+}
+
+/// Read a CompactSize, non canonical encodings are rejected
+pub fn read_compact_size<R: Read + ?Sized>(reader: &mut R) -> Result<u64> {
+    let mut bytes = [0u8; 9];
+    reader.read_exact(&mut bytes[..1])?;
+
+    let width = match bytes[0] {
+        0xfd => 2,
+        0xfe => 4,
+        0xff => 8,
+        _ => 0,
+    };
+
+    reader.read_exact(&mut bytes[1..=width])?;
+    varint::decode(&bytes[..=width])
+}
+
+/// Read a length prefix for a vector, the length is bounded by [`MAX_VEC_SIZE`]
+fn read_len<R: Read + ?Sized>(reader: &mut R) -> Result<usize> {
+    let len = read_compact_size(reader)?;
+    if len > MAX_VEC_SIZE {
+        return Err(Error::OversizedVector(len));
+    }
+
+    Ok(len as usize)
+}
+
+macro_rules! int_encodable {
+    ($($type:ty => $write:ident, $read:ident;)*) => {
+        $(
+            impl Encodable for $type {
+                fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+                    writer.$write::<LittleEndian>(*self)?;
+                    Ok(std::mem::size_of::<$type>())
+                }
+            }
+
+            impl Decodable for $type {
+                fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+                    Ok(reader.$read::<LittleEndian>()?)
+                }
+            }
+        )*
+    };
+}
+
+int_encodable! {
+    u16 => write_u16, read_u16;
+    u32 => write_u32, read_u32;
+    u64 => write_u64, read_u64;
+    i32 => write_i32, read_i32;
+    i64 => write_i64, read_i64;
+}
+
+impl Encodable for u8 {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        writer.write_u8(*self)?;
+        Ok(1)
+    }
+}
+
+impl Decodable for u8 {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        Ok(reader.read_u8()?)
+    }
+}
+
+impl Encodable for bool {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        u8::from(*self).consensus_encode(writer)
+    }
+}
+
+impl Decodable for bool {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        Ok(u8::consensus_decode(reader)? != 0)
+    }
+}
+
+impl<const N: usize> Encodable for [u8; N] {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        writer.write_all(self)?;
+        Ok(N)
+    }
+}
+
+impl<const N: usize> Decodable for [u8; N] {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        let mut result = [0u8; N];
+        reader.read_exact(&mut result)?;
+        Ok(result)
+    }
+}
+
+/// Length prefixed sequence, byte vectors are written as is after the prefix
+impl<T: Encodable> Encodable for [T] {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        let mut len = write_compact_size(writer, self.len() as u64)?;
+        for item in self {
+            len += item.consensus_encode(writer)?;
+        }
+
+        Ok(len)
+    }
+}
+
+impl<T: Encodable> Encodable for Vec<T> {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        self.as_slice().consensus_encode(writer)
+    }
+}
+
+impl<T: Decodable> Decodable for Vec<T> {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        let len = read_len(reader)?;
+
+        // don't trust the prefix for the allocation, the data may be truncated
+        let mut result = Vec::with_capacity(len.min(1024));
+        for _ in 0..len {
+            result.push(T::consensus_decode(reader)?);
+        }
+
+        Ok(result)
+    }
+}
+
+impl Encodable for VarInt {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        let encoded = self.serialize();
+        writer.write_all(&encoded)?;
+        Ok(encoded.len())
+    }
+}
+
+impl Decodable for VarInt {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        let value = read_compact_size(reader)?;
+        VarInt::deserialize(varint::encode(value).as_slice())
+    }
+}
+
+/// Compressed SEC format
+impl Encodable for PublicKey {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        self.serialize_compressed()?.consensus_encode(writer)
+    }
+}
+
+/// Either compressed or uncompressed SEC format, told apart by the prefix
+impl Decodable for PublicKey {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        let mut bytes = [0u8; 65];
+        reader.read_exact(&mut bytes[..1])?;
+
+        let len = if bytes[0] == 0x04 { 65 } else { 33 };
+        reader.read_exact(&mut bytes[1..len])?;
+        PublicKey::deserialize(&bytes[..len])
+    }
+}
+
+/// DER format, which is already self delimiting
+impl Encodable for Signature {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        let der = self.serialize()?;
+        writer.write_all(&der)?;
+        Ok(der.len())
+    }
+}
+
+impl Decodable for Signature {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        let mut header = [0u8; 2];
+        reader.read_exact(&mut header)?;
+
+        let mut der = vec![0u8; 2 + header[1] as usize];
+        der[..2].copy_from_slice(&header);
+        reader.read_exact(&mut der[2..])?;
+        Signature::deserialize(der.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use hex_literal::hex;
+
+    use super::*;
+
+    #[test]
+    fn integers_are_little_endian() -> Result<()> {
+        assert_eq!(serialize(&0x0102_0304u32)?, vec![0x04, 0x03, 0x02, 0x01]);
+        assert_eq!(serialize(&-2i32)?, vec![0xfe, 0xff, 0xff, 0xff]);
+        assert_eq!(deserialize::<u64>(&hex!("0100000000000000"))?, 1);
+        assert!(deserialize::<u32>(&hex!("010000")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn vectors_are_length_prefixed() -> Result<()> {
+        let bytes = vec![0xabu8; 300];
+        let encoded = serialize(&bytes)?;
+        assert_eq!(encoded[..3], hex!("fd2c01"));
+        assert_eq!(encoded.len(), 303);
+        assert_eq!(deserialize::<Vec<u8>>(&encoded)?, bytes);
+
+        let words = vec![1u16, 2, 3];
+        assert_eq!(serialize(&words)?, hex!("03010002000300"));
+        assert_eq!(deserialize::<Vec<u16>>(&hex!("03010002000300"))?, words);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_bad_lengths() {
+        // non canonical prefix
+        assert!(deserialize::<Vec<u8>>(&hex!("fd0100ab")).is_err());
+        // prefix larger than the data
+        assert!(deserialize::<Vec<u8>>(&hex!("05abab")).is_err());
+        // prefix larger than the maximum
+        assert!(deserialize::<Vec<u8>>(&hex!("feffffff7f")).is_err());
+        // trailing data
+        assert!(deserialize::<Vec<u8>>(&hex!("01abab")).is_err());
+    }
+
+    #[test]
+    fn compact_size_roundtrip() -> Result<()> {
+        for &value in &[0u64, 0xfc, 0xfd, 0xffff, 0x1_0000, 0xffff_ffff, u64::MAX] {
+            let mut encoded = Vec::new();
+            let len = write_compact_size(&mut encoded, value)?;
+            assert_eq!(len, varint::encoded_len(value));
+            assert_eq!(read_compact_size(&mut encoded.as_slice())?, value);
+            assert_eq!(deserialize::<VarInt>(&encoded)?.as_u64(), value);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn keys_and_signatures() -> Result<()> {
+        let sec = hex!("0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798");
+        let key: PublicKey = deserialize(&sec)?;
+        assert_eq!(serialize(&key)?, sec);
+
+        let der = hex!("3045022100ed81ff192e75a3fd2304004dcadb746fa5e24c5031ccfcf21320b0277457c98f02207a986d955c6e0cb35d446a89d3f56100f4d7f67801c31967743a9c8e10615bed");
+        let signature: Signature = deserialize(&der)?;
+        assert_eq!(serialize(&signature)?, der.to_vec());
+
+        // a key followed by a signature, read back from the same stream
+        let mut stream = sec.to_vec();
+        stream.extend_from_slice(&der);
+        let mut reader = stream.as_slice();
+        assert_eq!(PublicKey::consensus_decode(&mut reader)?, key);
+        assert_eq!(Signature::consensus_decode(&mut reader)?, signature);
+        assert!(reader.is_empty());
+        Ok(())
+    }
+}
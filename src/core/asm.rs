@@ -0,0 +1,147 @@
+//! Human readable scripts, in the format of Bitcoin Core's `decodescript`
+
+use crate::{Error, Result};
+
+use super::interpreter::decode_num;
+use super::opcode::Opcode;
+use super::script::{push_int, push_slice, Instruction, Script};
+
+impl Script {
+    /// Pushes of up to 4 bytes as numbers, longer ones in hex and opcodes by name,
+    /// small numbers as themselves. A push past the end shows as `[error]`
+    pub fn to_asm(&self) -> String {
+        let mut asm = Vec::new();
+        for instruction in self.instructions() {
+            let token = match instruction {
+                Ok(Instruction::PushBytes(data)) => match decode_num(data, false, 4) {
+                    Ok(n) => n.to_string(),
+                    Err(_) => hex::encode(data),
+                },
+                Ok(Instruction::Op(opcode)) => match (opcode, opcode.small_int()) {
+                    (_, Some(n)) => n.to_string(),
+                    (Opcode::OP_UNKNOWN(_), _) => "OP_UNKNOWN".to_string(),
+                    _ => opcode.to_string(),
+                },
+                Err(_) => "[error]".to_string(),
+            };
+
+            asm.push(token);
+        }
+
+        asm.join(" ")
+    }
+
+    /// Parse the output of [`Script::to_asm`]. Numbers are pushed minimally, hex
+    /// strings pushed as data unless prefixed by `0x` which inserts them as they
+    /// are, and opcodes go with or without their `OP_` prefix
+    pub fn from_asm(asm: &str) -> Result<Self> {
+        let mut script = Vec::new();
+        for token in asm.split_whitespace() {
+            match parse_number(token) {
+                Some(n) => push_int(&mut script, n),
+                None if token.starts_with("0x") => {
+                    let bytes = hex::decode(&token[2..])
+                        .map_err(|_| Error::InvalidScript("invalid raw hex"))?;
+                    script.extend(bytes);
+                }
+                None if token.starts_with("OP_") || !is_hex(token) => {
+                    script.push(u8::from(token.parse::<Opcode>()?));
+                }
+                None => {
+                    let data =
+                        hex::decode(token).map_err(|_| Error::InvalidScript("invalid hex push"))?;
+                    push_slice(&mut script, &data);
+                }
+            }
+        }
+
+        Ok(Script::from(script))
+    }
+}
+
+/// Decimal numbers that fit a 4 byte script number, what `to_asm` shows pushes
+/// of up to 4 bytes as
+fn parse_number(token: &str) -> Option<i64> {
+    let digits = token.strip_prefix('-').unwrap_or(token);
+    if digits.is_empty() || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+
+    token
+        .parse::<i64>()
+        .ok()
+        .filter(|n| n.abs() <= i64::from(i32::MAX))
+}
+
+fn is_hex(token: &str) -> bool {
+    token.len().is_multiple_of(2) && token.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use crate::core::script::ScriptBuilder;
+
+    use super::*;
+
+    #[test]
+    fn to_asm() {
+        let p2pkh =
+            Script::from(hex!("76a91489abcdefabbaabbaabbaabbaabbaabbaabbaabba88ac").to_vec());
+        assert_eq!(
+            p2pkh.to_asm(),
+            "OP_DUP OP_HASH160 89abcdefabbaabbaabbaabbaabbaabbaabbaabba OP_EQUALVERIFY OP_CHECKSIG"
+        );
+
+        // small pushes as numbers, sign bit included
+        let script =
+            Script::from(hex!("00 4f 51 60 0111 0181 02ff7f 04ffffff7f 0500000000ff").to_vec());
+        assert_eq!(
+            script.to_asm(),
+            "0 -1 1 16 17 -1 32767 2147483647 00000000ff"
+        );
+
+        let script = Script::from(hex!("b1 b2 ba 50 bb ff").to_vec());
+        assert_eq!(
+            script.to_asm(),
+            "OP_CHECKLOCKTIMEVERIFY OP_CHECKSEQUENCEVERIFY OP_CHECKSIGADD OP_RESERVED OP_UNKNOWN OP_INVALIDOPCODE"
+        );
+
+        assert_eq!(Script::from(hex!("51 02ff").to_vec()).to_asm(), "1 [error]");
+        assert_eq!(Script::new().to_asm(), "");
+    }
+
+    #[test]
+    fn from_asm() {
+        let asm =
+            "OP_DUP OP_HASH160 89abcdefabbaabbaabbaabbaabbaabbaabbaabba OP_EQUALVERIFY OP_CHECKSIG";
+        let script = Script::from_asm(asm).unwrap();
+        assert_eq!(
+            script.as_bytes(),
+            hex!("76a91489abcdefabbaabbaabbaabbaabbaabbaabbaabba88ac")
+        );
+        assert_eq!(script.to_asm(), asm);
+
+        let script = Script::from_asm("0 -1 16 17 1000 DUP TRUE NOP2 0x4c01ff").unwrap();
+        assert_eq!(
+            script.as_bytes(),
+            hex!("00 4f 60 0111 02e803 76 51 b1 4c01ff")
+        );
+
+        // what to_asm shows comes back the same for minimal pushes
+        let script = ScriptBuilder::new()
+            .push_int(2)
+            .push_slice(&[0x02; 33])
+            .push_int(100_000_000)
+            .push_slice(&[0x7f; 5])
+            .push_opcode(Opcode::OP_CHECKMULTISIG)
+            .into_script();
+        assert_eq!(Script::from_asm(&script.to_asm()).unwrap(), script);
+
+        assert!(Script::from_asm("OP_NOTANOPCODE").is_err());
+        assert!(Script::from_asm("abc").is_err());
+        assert!(Script::from_asm("0xzz").is_err());
+        assert!(Script::from_asm("").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,656 @@
+//! Blocks and their 80 bytes headers, what chains of proof of work are made of
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+use std::io::{Read, Write};
+
+use bytes::Buf;
+
+use crate::consensus::{self, Decodable, Encodable};
+use crate::varint;
+use crate::{Error, Result};
+
+use super::coinbase::{witness_commitment, MAX_SCRIPT_SIG_SIZE, MIN_SCRIPT_SIG_SIZE};
+use super::input::OutPoint;
+use super::merkle::{merkle_root_mutated, MerkleProof};
+use super::output::TxOut;
+use super::tx::Transaction;
+use super::txid::{BlockHash, Txid};
+
+/// Consensus limit of the block weight, also bounding its serialized size
+pub const MAX_BLOCK_WEIGHT: usize = 4_000_000;
+/// Consensus limit of the signature checks of a block, weighted like in
+/// [`Transaction::sigop_cost`]
+pub const MAX_BLOCK_SIGOPS_COST: usize = 80_000;
+
+/// Why a block breaks the consensus limits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlockValidationError {
+    NoTransactions,
+    /// Above [`MAX_BLOCK_WEIGHT`]
+    Weight,
+    /// Above [`MAX_BLOCK_SIGOPS_COST`]
+    SigopsCost,
+    FirstNotCoinbase,
+    ExtraCoinbase {
+        index: usize,
+    },
+    /// Script sig shorter than 2 bytes or longer than 100
+    CoinbaseScriptSize,
+    /// The coinbase doesn't start with the height of the block (BIP34)
+    CoinbaseHeight {
+        expected: u32,
+    },
+    DuplicateTx {
+        index: usize,
+    },
+}
+
+impl Display for BlockValidationError {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockValidationError::NoTransactions => fmt.write_str("no transactions"),
+            BlockValidationError::Weight => fmt.write_str("too heavy"),
+            BlockValidationError::SigopsCost => fmt.write_str("too many signature checks"),
+            BlockValidationError::FirstNotCoinbase => {
+                fmt.write_str("first transaction isn't a coinbase")
+            }
+            BlockValidationError::ExtraCoinbase { index } => {
+                write!(fmt, "transaction {} is a second coinbase", index)
+            }
+            BlockValidationError::CoinbaseScriptSize => {
+                fmt.write_str("coinbase script sig size out of range")
+            }
+            BlockValidationError::CoinbaseHeight { expected } => {
+                write!(fmt, "coinbase doesn't commit to height {}", expected)
+            }
+            BlockValidationError::DuplicateTx { index } => {
+                write!(fmt, "transaction {} is a duplicate", index)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BlockHeader {
+    pub(crate) version: i32,
+    pub(crate) prev_blockhash: BlockHash,
+    /// Merkle root of the txids, in internal byte order
+    pub(crate) merkle_root: [u8; 32],
+    pub(crate) time: u32,
+    /// Target in compact form
+    pub(crate) bits: u32,
+    pub(crate) nonce: u32,
+}
+
+impl BlockHeader {
+    /// Serialized size of every header
+    pub const SIZE: usize = 80;
+
+    pub fn new(
+        version: i32,
+        prev_blockhash: BlockHash,
+        merkle_root: [u8; 32],
+        time: u32,
+        bits: u32,
+        nonce: u32,
+    ) -> Self {
+        Self {
+            version,
+            prev_blockhash,
+            merkle_root,
+            time,
+            bits,
+            nonce,
+        }
+    }
+
+    pub fn version(&self) -> i32 {
+        self.version
+    }
+
+    pub fn prev_blockhash(&self) -> &BlockHash {
+        &self.prev_blockhash
+    }
+
+    pub fn merkle_root(&self) -> &[u8; 32] {
+        &self.merkle_root
+    }
+
+    /// Seconds since the Unix epoch, as set by the miner
+    pub fn time(&self) -> u32 {
+        self.time
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    pub fn nonce(&self) -> u32 {
+        self.nonce
+    }
+
+    /// Double SHA256 of the serialized header
+    pub fn block_hash(&self) -> BlockHash {
+        BlockHash::hash(&self.serialize())
+    }
+
+    pub fn serialize(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        self.consensus_encode(&mut &mut bytes[..])
+            .expect("80 bytes fit a header");
+        bytes
+    }
+
+    pub fn deserialize(buf: impl Buf) -> Result<Self> {
+        Self::consensus_decode(&mut buf.reader())
+    }
+
+    /// Parse exactly 80 bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        consensus::deserialize(bytes)
+    }
+}
+
+impl Encodable for BlockHeader {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        Ok(self.version.consensus_encode(writer)?
+            + self.prev_blockhash.consensus_encode(writer)?
+            + self.merkle_root.consensus_encode(writer)?
+            + self.time.consensus_encode(writer)?
+            + self.bits.consensus_encode(writer)?
+            + self.nonce.consensus_encode(writer)?)
+    }
+}
+
+impl Decodable for BlockHeader {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        Ok(Self {
+            version: i32::consensus_decode(reader)?,
+            prev_blockhash: BlockHash::consensus_decode(reader)?,
+            merkle_root: <[u8; 32]>::consensus_decode(reader)?,
+            time: u32::consensus_decode(reader)?,
+            bits: u32::consensus_decode(reader)?,
+            nonce: u32::consensus_decode(reader)?,
+        })
+    }
+}
+
+/// A header and the transactions it commits to, the coinbase first
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub(crate) header: BlockHeader,
+    pub(crate) txdata: Vec<Transaction>,
+}
+
+impl Block {
+    pub fn new(header: BlockHeader, txdata: Vec<Transaction>) -> Self {
+        Self { header, txdata }
+    }
+
+    pub fn header(&self) -> &BlockHeader {
+        &self.header
+    }
+
+    pub fn transactions(&self) -> &[Transaction] {
+        &self.txdata
+    }
+
+    pub fn coinbase(&self) -> Option<&Transaction> {
+        self.txdata.first()
+    }
+
+    pub fn block_hash(&self) -> BlockHash {
+        self.header.block_hash()
+    }
+
+    /// Serialized size with the witness data
+    pub fn total_size(&self) -> Result<usize> {
+        self.consensus_encode(&mut std::io::sink())
+    }
+
+    /// BIP141 weight, the header and transaction count weigh as non witness data
+    pub fn weight(&self) -> Result<usize> {
+        let base = BlockHeader::SIZE + varint::encoded_len(self.txdata.len() as u64);
+        self.txdata
+            .iter()
+            .try_fold(base * 4, |weight, tx| Ok(weight + tx.weight()?))
+    }
+
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        consensus::serialize(self)
+    }
+
+    fn txids(&self) -> Result<Vec<[u8; 32]>> {
+        self.txdata
+            .iter()
+            .map(|tx| tx.txid().map(Txid::to_bytes))
+            .collect()
+    }
+
+    /// Read a block from `buf`, see [`Block::consensus_decode`] to read it from
+    /// a stream instead
+    pub fn deserialize(buf: impl Buf) -> Result<Self> {
+        Self::consensus_decode(&mut buf.reader())
+    }
+
+    /// Merkle root of the txids and whether it's mutated, see
+    /// [`merkle_root_mutated`]
+    pub fn compute_merkle_root(&self) -> Result<([u8; 32], bool)> {
+        Ok(merkle_root_mutated(&self.txids()?))
+    }
+
+    /// Proof that the transaction `txid` is part of the block
+    pub fn merkle_proof(&self, txid: &Txid) -> Result<Option<MerkleProof>> {
+        let txids = self
+            .txids()?
+            .into_iter()
+            .map(Txid::from_bytes)
+            .collect::<Vec<_>>();
+        Ok(MerkleProof::new(&txids, txid))
+    }
+
+    /// Whether the header commits to exactly these transactions
+    pub fn check_merkle_root(&self) -> Result<()> {
+        match self.compute_merkle_root()? {
+            (_, true) => Err(Error::InvalidBlock("mutated merkle tree")),
+            (root, false) if root != self.header.merkle_root => {
+                Err(Error::InvalidBlock("merkle root mismatch"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// BIP141 rules: witness data needs a commitment in the coinbase to the wtxids,
+    /// whose witness is the 32 bytes reserved value
+    pub fn check_witness_commitment(&self) -> Result<()> {
+        let coinbase = self
+            .coinbase()
+            .filter(|tx| tx.is_coinbase())
+            .ok_or(Error::InvalidBlock("missing coinbase"))?;
+
+        let commitment = match coinbase.witness_commitment() {
+            Some(commitment) => commitment,
+            None if self.txdata.iter().any(Transaction::has_witness) => {
+                return Err(Error::InvalidBlock("unexpected witness data"));
+            }
+            None => return Ok(()),
+        };
+
+        let reserved_value = match &coinbase.inputs[0].witness[..] {
+            [value] if value.len() == 32 => {
+                let mut reserved_value = [0; 32];
+                reserved_value.copy_from_slice(value);
+                reserved_value
+            }
+            _ => return Err(Error::InvalidBlock("bad witness reserved value")),
+        };
+
+        let wtxids = self.txdata[1..]
+            .iter()
+            .map(Transaction::wtxid)
+            .collect::<Result<Vec<_>>>()?;
+
+        if witness_commitment(&wtxids, &reserved_value) != commitment {
+            return Err(Error::InvalidBlock("witness commitment mismatch"));
+        }
+
+        Ok(())
+    }
+
+    /// Consensus limits checked without the spent outputs, like Bitcoin Core's
+    /// `CheckBlock`: transactions, weight, the coinbase, duplicates and legacy
+    /// signature checks. The BIP34 height is checked when given
+    pub fn check_limits(&self, height: Option<u32>) -> Result<()> {
+        let reject = |error| Err(Error::BlockValidation(error));
+
+        let coinbase = match self.txdata.first() {
+            Some(coinbase) if coinbase.is_coinbase() => coinbase,
+            Some(_) => return reject(BlockValidationError::FirstNotCoinbase),
+            None => return reject(BlockValidationError::NoTransactions),
+        };
+
+        if self.weight()? > MAX_BLOCK_WEIGHT {
+            return reject(BlockValidationError::Weight);
+        }
+
+        let script_sig_size = coinbase.inputs[0].script_sig.len();
+        if !(MIN_SCRIPT_SIG_SIZE..=MAX_SCRIPT_SIG_SIZE).contains(&script_sig_size) {
+            return reject(BlockValidationError::CoinbaseScriptSize);
+        }
+
+        if let Some(height) = height {
+            if coinbase.coinbase_height() != Some(height) {
+                return reject(BlockValidationError::CoinbaseHeight { expected: height });
+            }
+        }
+
+        let mut txids = HashSet::with_capacity(self.txdata.len());
+        let mut legacy_sigops = 0;
+        for (index, tx) in self.txdata.iter().enumerate() {
+            if index > 0 && tx.is_coinbase() {
+                return reject(BlockValidationError::ExtraCoinbase { index });
+            }
+
+            if !txids.insert(tx.txid()?) {
+                return reject(BlockValidationError::DuplicateTx { index });
+            }
+
+            // without the spent outputs only the legacy ones are counted
+            legacy_sigops += tx.sigop_cost(&[]);
+        }
+
+        if legacy_sigops > MAX_BLOCK_SIGOPS_COST {
+            return reject(BlockValidationError::SigopsCost);
+        }
+
+        Ok(())
+    }
+
+    /// Weighted signature checks of every transaction, P2SH and witness ones
+    /// included. `spent` gives the outputs spent from earlier blocks
+    pub fn sigop_cost(&self, mut spent: impl FnMut(&OutPoint) -> Option<TxOut>) -> Result<usize> {
+        let mut created = HashMap::new();
+        let mut cost = 0;
+        for tx in &self.txdata {
+            let prevouts = match tx.is_coinbase() {
+                true => Vec::new(),
+                false => tx
+                    .inputs
+                    .iter()
+                    .map(|input| {
+                        created
+                            .get(&input.previous_output)
+                            .cloned()
+                            .or_else(|| spent(&input.previous_output))
+                            .ok_or(Error::InvalidBlock("missing spent output"))
+                    })
+                    .collect::<Result<Vec<_>>>()?,
+            };
+            cost += tx.sigop_cost(&prevouts);
+
+            let txid = tx.txid()?;
+            for (vout, output) in tx.outputs.iter().enumerate() {
+                created.insert(OutPoint::new(txid, vout as u32), output.clone());
+            }
+        }
+
+        Ok(cost)
+    }
+
+    /// Whether [`Block::sigop_cost`] is within [`MAX_BLOCK_SIGOPS_COST`]
+    pub fn check_sigop_cost(&self, spent: impl FnMut(&OutPoint) -> Option<TxOut>) -> Result<()> {
+        match self.sigop_cost(spent)? > MAX_BLOCK_SIGOPS_COST {
+            true => Err(Error::BlockValidation(BlockValidationError::SigopsCost)),
+            false => Ok(()),
+        }
+    }
+}
+
+impl Encodable for Block {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        Ok(self.header.consensus_encode(writer)? + self.txdata.consensus_encode(writer)?)
+    }
+}
+
+/// Transactions are decoded one at a time off the reader, which can't give more
+/// than [`MAX_BLOCK_WEIGHT`] bytes
+impl Decodable for Block {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        let mut reader = reader.take(MAX_BLOCK_WEIGHT as u64);
+        Ok(Self {
+            header: BlockHeader::consensus_decode(&mut reader)?,
+            txdata: Vec::consensus_decode(&mut reader)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use hex_literal::hex;
+
+    use crate::amount::Amount;
+    use crate::core::input::{OutPoint, TxIn};
+    use crate::core::output::TxOut;
+    use crate::core::script::Script;
+    use crate::core::witness::Witness;
+
+    use super::*;
+
+    const GENESIS: [u8; 80] = hex!("0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c");
+
+    #[test]
+    fn genesis_header() -> Result<()> {
+        let header = BlockHeader::from_bytes(&GENESIS)?;
+        assert_eq!(header.version(), 1);
+        assert_eq!(header.prev_blockhash(), &BlockHash::default());
+        assert_eq!(header.merkle_root()[0], 0x3b);
+        assert_eq!(header.time(), 1231006505);
+        assert_eq!(header.bits(), 0x1d00ffff);
+        assert_eq!(header.nonce(), 2083236893);
+        assert_eq!(
+            header.block_hash().to_string(),
+            "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f"
+        );
+
+        assert_eq!(header.serialize(), GENESIS);
+        assert_eq!(consensus::serialize(&header)?, GENESIS.to_vec());
+        assert_eq!(BlockHeader::deserialize(&GENESIS[..])?, header);
+
+        assert!(BlockHeader::from_bytes(&GENESIS[..79]).is_err());
+        assert!(matches!(
+            BlockHeader::from_bytes(&[&GENESIS[..], &[0]].concat()),
+            Err(Error::TrailingBytes(1))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn genesis_block() -> Result<()> {
+        let raw = [&GENESIS[..], &hex!("0101000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000")[..]].concat();
+
+        let block = Block::deserialize(&raw[..])?;
+        assert_eq!(block.transactions().len(), 1);
+        assert!(block.coinbase().unwrap().is_coinbase());
+        assert_eq!(
+            block.coinbase().unwrap().txid()?.to_bytes(),
+            *block.header().merkle_root()
+        );
+        assert_eq!(block.serialize()?, raw);
+        assert_eq!(block.total_size()?, raw.len());
+        assert_eq!(block.weight()?, raw.len() * 4);
+
+        block.check_merkle_root()?;
+        block.check_witness_commitment()?;
+
+        let mut other = block.clone();
+        other.header.merkle_root = [0; 32];
+        assert!(other.check_merkle_root().is_err());
+        Ok(())
+    }
+
+    fn spending_tx(vout: u32, witness: bool) -> Transaction {
+        let mut input = TxIn::new(OutPoint::new(Txid::from_bytes([0x11; 32]), vout));
+        if witness {
+            input.witness = Witness::p2wpkh(&[0x30; 72], &[0x02; 33]);
+        }
+        let output = TxOut::new(Amount::from_sat(90_000), Script::from(vec![0x51]));
+        Transaction::new(2, vec![input], vec![output], 0)
+    }
+
+    fn block(txdata: Vec<Transaction>) -> Result<Block> {
+        let mut block = Block::new(
+            BlockHeader::new(0x2000_0000, BlockHash::default(), [0; 32], 0, 0x207fffff, 0),
+            txdata,
+        );
+        block.header.merkle_root = block.compute_merkle_root()?.0;
+        Ok(block)
+    }
+
+    #[test]
+    fn merkle_mutation() -> Result<()> {
+        let coinbase = Transaction::new_coinbase(1, &[], vec![], None)?;
+        let txdata = vec![coinbase, spending_tx(0, false), spending_tx(1, false)];
+        let valid = block(txdata.clone())?;
+        valid.check_merkle_root()?;
+
+        // the last transaction repeated hashes to the same root
+        let mut repeated = txdata;
+        repeated.push(repeated[2].clone());
+        let (root, mutated) = block(repeated.clone())?.compute_merkle_root()?;
+        assert!(mutated);
+        assert_eq!(root, *valid.header().merkle_root());
+        assert!(matches!(
+            block(repeated)?.check_merkle_root(),
+            Err(Error::InvalidBlock("mutated merkle tree"))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn witness_commitments() -> Result<()> {
+        let spend = spending_tx(0, true);
+        let commitment = witness_commitment(&[spend.wtxid()?], &[0; 32]);
+        let coinbase = Transaction::new_coinbase(1, &[], vec![], Some(&commitment))?;
+
+        let valid = block(vec![coinbase.clone(), spend.clone()])?;
+        valid.check_witness_commitment()?;
+        let decoded = Block::deserialize(&valid.serialize()?[..])?;
+        assert_eq!(decoded, valid);
+        assert!(decoded.weight()? < decoded.total_size()? * 4);
+
+        // other witness data
+        let mut tampered = valid.clone();
+        tampered.txdata[1].inputs[0].witness[0][0] = 0x31;
+        assert!(matches!(
+            tampered.check_witness_commitment(),
+            Err(Error::InvalidBlock("witness commitment mismatch"))
+        ));
+
+        let mut reserved = valid;
+        reserved.txdata[0].inputs[0].witness = Witness::new();
+        assert!(reserved.check_witness_commitment().is_err());
+
+        let plain = Transaction::new_coinbase(1, &[], vec![], None)?;
+        assert!(matches!(
+            block(vec![plain.clone(), spend])?.check_witness_commitment(),
+            Err(Error::InvalidBlock("unexpected witness data"))
+        ));
+        block(vec![plain, spending_tx(0, false)])?.check_witness_commitment()?;
+        assert!(block(vec![spending_tx(0, false)])?
+            .check_witness_commitment()
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn consensus_limits() -> Result<()> {
+        let reject = |block: Block, height| match block.check_limits(height) {
+            Err(Error::BlockValidation(error)) => Some(error),
+            _ => None,
+        };
+        let coinbase = |height| Transaction::new_coinbase(height, &[], vec![], None);
+
+        let valid = block(vec![coinbase(5)?, spending_tx(0, false)])?;
+        valid.check_limits(Some(5))?;
+        valid.check_limits(None)?;
+        assert_eq!(
+            reject(valid, Some(6)),
+            Some(BlockValidationError::CoinbaseHeight { expected: 6 })
+        );
+
+        // 5 as a one byte push instead of OP_5
+        let mut non_minimal = coinbase(5)?;
+        non_minimal.inputs[0].script_sig = Script::from(vec![0x01, 0x05]);
+        assert_eq!(
+            reject(block(vec![non_minimal, spending_tx(0, false)])?, Some(5)),
+            Some(BlockValidationError::CoinbaseHeight { expected: 5 })
+        );
+
+        assert_eq!(
+            reject(block(vec![])?, None),
+            Some(BlockValidationError::NoTransactions)
+        );
+        assert_eq!(
+            reject(block(vec![spending_tx(0, false)])?, None),
+            Some(BlockValidationError::FirstNotCoinbase)
+        );
+        assert_eq!(
+            reject(block(vec![coinbase(5)?, coinbase(6)?])?, None),
+            Some(BlockValidationError::ExtraCoinbase { index: 1 })
+        );
+        assert_eq!(
+            reject(
+                block(vec![
+                    coinbase(5)?,
+                    spending_tx(0, false),
+                    spending_tx(0, false)
+                ])?,
+                None
+            ),
+            Some(BlockValidationError::DuplicateTx { index: 2 })
+        );
+
+        let mut long = coinbase(5)?;
+        long.inputs[0].script_sig = Script::from(vec![0x55; 101]);
+        assert_eq!(
+            reject(block(vec![long])?, None),
+            Some(BlockValidationError::CoinbaseScriptSize)
+        );
+
+        let mut heavy = spending_tx(0, false);
+        heavy.outputs[0].script_pubkey = Script::from(vec![0x51; 1_000_000]);
+        assert_eq!(
+            reject(block(vec![coinbase(5)?, heavy])?, None),
+            Some(BlockValidationError::Weight)
+        );
+
+        // 20001 legacy OP_CHECKSIGs weigh 4 each
+        let mut checksigs = spending_tx(0, false);
+        checksigs.outputs[0].script_pubkey = Script::from(vec![0xac; 20_001]);
+        assert_eq!(
+            reject(block(vec![coinbase(5)?, checksigs])?, None),
+            Some(BlockValidationError::SigopsCost)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn block_sigop_cost() -> Result<()> {
+        // a 2-of-3 P2SH spend, then a spend of its output in the same block
+        let mut redeem_script = vec![0x52];
+        for key in 0..3 {
+            redeem_script.push(33);
+            redeem_script.extend(&[key; 33]);
+        }
+        redeem_script.extend(&[0x53, 0xae]);
+        let redeem_script = Script::from(redeem_script);
+
+        let external = OutPoint::new(Txid::from_bytes([0x22; 32]), 0);
+        let mut input = TxIn::new(external);
+        input.script_sig = Script::p2sh_script_sig(&[vec![]], &redeem_script);
+        let output = TxOut::new(Amount::from_sat(90_000), Script::from(vec![0x51]));
+        let p2sh_spend = Transaction::new(2, vec![input], vec![output], 0);
+
+        let input = TxIn::new(OutPoint::new(p2sh_spend.txid()?, 0));
+        let output = TxOut::new(Amount::from_sat(80_000), Script::from(vec![0xac]));
+        let chained = Transaction::new(2, vec![input], vec![output], 0);
+
+        let coinbase = Transaction::new_coinbase(1, &[], vec![], None)?;
+        let block = block(vec![coinbase, p2sh_spend, chained])?;
+        let spent = |outpoint: &OutPoint| match *outpoint == external {
+            true => Some(TxOut::new(
+                Amount::from_sat(100_000),
+                Script::p2sh(&[0; 20]),
+            )),
+            false => None,
+        };
+
+        // 3 keys counted accurately plus a legacy OP_CHECKSIG, both weighing 4
+        assert_eq!(block.sigop_cost(spent)?, 4 * 3 + 4);
+        block.check_sigop_cost(spent)?;
+        assert!(block.sigop_cost(|_| None).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,292 @@
+//! Bloom filters of the elements a light client cares about (BIP37), peers only
+//! relay the transactions matching them
+
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+
+use crate::consensus::{self, Decodable, Encodable};
+use crate::{Error, Result};
+
+/// Largest filter in bytes peers accept
+pub const MAX_BLOOM_FILTER_SIZE: usize = 36_000;
+/// Most hash functions peers accept
+pub const MAX_HASH_FUNCS: u32 = 50;
+
+/// Multiplier of the hash function index in the Murmur3 seed
+const SEED_MULTIPLIER: u32 = 0xfba4_c795;
+
+/// What the peer adds to the filter when an output matches, so spends of it
+/// match too
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BloomFlags {
+    /// Nothing
+    None,
+    /// The outpoint of every matching output
+    All,
+    /// The outpoint of matching P2PK and bare multisig outputs
+    PubkeyOnly,
+}
+
+impl TryFrom<u8> for BloomFlags {
+    type Error = Error;
+
+    fn try_from(byte: u8) -> Result<Self> {
+        match byte {
+            0 => Ok(BloomFlags::None),
+            1 => Ok(BloomFlags::All),
+            2 => Ok(BloomFlags::PubkeyOnly),
+            _ => Err(Error::InvalidBloomFilter("unknown flags")),
+        }
+    }
+}
+
+impl From<BloomFlags> for u8 {
+    fn from(flags: BloomFlags) -> Self {
+        match flags {
+            BloomFlags::None => 0,
+            BloomFlags::All => 1,
+            BloomFlags::PubkeyOnly => 2,
+        }
+    }
+}
+
+/// Probabilistic set, false positives happen at the rate it was sized for but
+/// false negatives never do
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilter {
+    pub(crate) data: Vec<u8>,
+    pub(crate) hash_funcs: u32,
+    /// Randomizes the seeds so filters of the same elements differ
+    pub(crate) tweak: u32,
+    pub(crate) flags: BloomFlags,
+}
+
+impl BloomFilter {
+    /// Filter sized for `elements` with `fp_rate` false positives, within the
+    /// limits peers accept
+    pub fn new(elements: u32, fp_rate: f64, tweak: u32, flags: BloomFlags) -> Self {
+        let ln2 = std::f64::consts::LN_2;
+        let elements = f64::from(elements.max(1));
+
+        let bytes = (-1.0 / (ln2 * ln2) * elements * fp_rate.ln() / 8.0) as usize;
+        let bytes = bytes.clamp(1, MAX_BLOOM_FILTER_SIZE);
+        let hash_funcs = (bytes as f64 * 8.0 / elements * ln2) as u32;
+
+        Self {
+            data: vec![0; bytes],
+            hash_funcs: hash_funcs.clamp(1, MAX_HASH_FUNCS),
+            tweak,
+            flags,
+        }
+    }
+
+    pub fn hash_funcs(&self) -> u32 {
+        self.hash_funcs
+    }
+
+    pub fn tweak(&self) -> u32 {
+        self.tweak
+    }
+
+    pub fn flags(&self) -> BloomFlags {
+        self.flags
+    }
+
+    /// Size of the bit field in bytes
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Whether nothing was inserted yet, no bit is set
+    pub fn is_empty(&self) -> bool {
+        self.data.iter().all(|byte| *byte == 0)
+    }
+
+    pub fn insert(&mut self, element: &[u8]) {
+        if self.data.is_empty() {
+            return;
+        }
+
+        for n in 0..self.hash_funcs {
+            let bit = self.bit_index(n, element);
+            self.data[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+
+    /// Everything matches an empty filter, as in Bitcoin Core
+    pub fn contains(&self, element: &[u8]) -> bool {
+        if self.data.is_empty() {
+            return true;
+        }
+
+        (0..self.hash_funcs).all(|n| {
+            let bit = self.bit_index(n, element);
+            self.data[bit / 8] & (1 << (bit % 8)) != 0
+        })
+    }
+
+    /// Whether peers would accept it in a `filterload`
+    pub fn is_within_size_constraints(&self) -> bool {
+        self.data.len() <= MAX_BLOOM_FILTER_SIZE && self.hash_funcs <= MAX_HASH_FUNCS
+    }
+
+    /// Payload of the `filterload` message
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        consensus::serialize(self)
+    }
+
+    fn bit_index(&self, n: u32, element: &[u8]) -> usize {
+        let seed = n.wrapping_mul(SEED_MULTIPLIER).wrapping_add(self.tweak);
+        murmur3(seed, element) as usize % (self.data.len() * 8)
+    }
+}
+
+/// The bit field, number of hash functions, tweak and flags
+impl Encodable for BloomFilter {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        Ok(self.data.consensus_encode(writer)?
+            + self.hash_funcs.consensus_encode(writer)?
+            + self.tweak.consensus_encode(writer)?
+            + u8::from(self.flags).consensus_encode(writer)?)
+    }
+}
+
+impl Decodable for BloomFilter {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        let filter = Self {
+            data: Vec::consensus_decode(reader)?,
+            hash_funcs: u32::consensus_decode(reader)?,
+            tweak: u32::consensus_decode(reader)?,
+            flags: BloomFlags::try_from(u8::consensus_decode(reader)?)?,
+        };
+
+        if !filter.is_within_size_constraints() {
+            return Err(Error::InvalidBloomFilter("too large"));
+        }
+
+        Ok(filter)
+    }
+}
+
+/// 32 bits Murmur3 hash of `data`
+pub fn murmur3(seed: u32, data: &[u8]) -> u32 {
+    const C1: u32 = 0xcc9e_2d51;
+    const C2: u32 = 0x1b87_3593;
+
+    let mix = |mut k: u32| {
+        k = k.wrapping_mul(C1);
+        k = k.rotate_left(15);
+        k.wrapping_mul(C2)
+    };
+
+    let mut h = seed;
+    let blocks = data.chunks_exact(4);
+    let tail = blocks.remainder();
+    for block in blocks {
+        let k = u32::from_le_bytes([block[0], block[1], block[2], block[3]]);
+        h ^= mix(k);
+        h = h.rotate_left(13);
+        h = h.wrapping_mul(5).wrapping_add(0xe654_6b64);
+    }
+
+    if !tail.is_empty() {
+        let k = tail
+            .iter()
+            .rev()
+            .fold(0u32, |k, byte| k << 8 | u32::from(*byte));
+        h ^= mix(k);
+    }
+
+    h ^= data.len() as u32;
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2_ae35);
+    h ^ (h >> 16)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use hex_literal::hex;
+
+    use super::*;
+
+    #[test]
+    fn murmur3_vectors() {
+        // Bitcoin Core's hash tests
+        let vectors: [(u32, u32, &[u8]); 14] = [
+            (0x0000_0000, 0x0000_0000, &[]),
+            (0x6a39_6f08, 0xfba4_c795, &[]),
+            (0x81f1_6f39, 0xffff_ffff, &[]),
+            (0x514e_28b7, 0x0000_0000, &hex!("00")),
+            (0xea3f_0b17, 0xfba4_c795, &hex!("00")),
+            (0xfd6c_f10d, 0x0000_0000, &hex!("ff")),
+            (0x16c6_b7ab, 0x0000_0000, &hex!("0011")),
+            (0x8eb5_1c3d, 0x0000_0000, &hex!("001122")),
+            (0xb447_1bf8, 0x0000_0000, &hex!("00112233")),
+            (0xe230_1fa8, 0x0000_0000, &hex!("0011223344")),
+            (0xfc2e_4a15, 0x0000_0000, &hex!("001122334455")),
+            (0xb074_502c, 0x0000_0000, &hex!("00112233445566")),
+            (0x8034_d2a0, 0x0000_0000, &hex!("0011223344556677")),
+            (0xb469_8def, 0x0000_0000, &hex!("001122334455667788")),
+        ];
+
+        for (expected, seed, data) in vectors.iter() {
+            assert_eq!(murmur3(*seed, data), *expected, "{:x?}", data);
+        }
+    }
+
+    #[test]
+    fn insert_and_serialize() -> Result<()> {
+        // Bitcoin Core's bloom tests
+        for (tweak, serialized) in [
+            (0, hex!("03614e9b050000000000000001")),
+            (2147483649, hex!("03ce4299050000000100008001")),
+        ]
+        .iter()
+        {
+            let mut filter = BloomFilter::new(3, 0.01, *tweak, BloomFlags::All);
+            assert!(filter.is_empty());
+
+            let first = hex!("99108ad8ed9bb6274d3980bab5a85c048f0950c8");
+            filter.insert(&first);
+            assert!(filter.contains(&first));
+            assert!(!filter.contains(&hex!("19108ad8ed9bb6274d3980bab5a85c048f0950c8")));
+
+            filter.insert(&hex!("b5a2c786d9ef4658287ced5914b37a1b4aa32eee"));
+            filter.insert(&hex!("b9300670b4c5366e95b2699e8b18bc75e5f729c5"));
+            assert!(filter.contains(&hex!("b9300670b4c5366e95b2699e8b18bc75e5f729c5")));
+
+            assert_eq!(filter.serialize()?, serialized.to_vec());
+            assert_eq!(consensus::deserialize::<BloomFilter>(serialized)?, filter);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn size_constraints() -> Result<()> {
+        let filter = BloomFilter::new(1_000_000, 0.0001, 0, BloomFlags::None);
+        assert_eq!(filter.len(), MAX_BLOOM_FILTER_SIZE);
+        assert!(filter.is_within_size_constraints());
+
+        let filter = BloomFilter::new(1, 1e-20, 0, BloomFlags::PubkeyOnly);
+        assert_eq!(filter.hash_funcs(), MAX_HASH_FUNCS);
+        assert_eq!(filter.flags(), BloomFlags::PubkeyOnly);
+
+        // unknown flags and oversized filters
+        assert!(consensus::deserialize::<BloomFilter>(&hex!("01ff050000000000000003")).is_err());
+        let mut large = vec![0xfd, 0x41, 0x8d];
+        large.extend(vec![0; 36_001]);
+        large.extend(&[1, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert!(consensus::deserialize::<BloomFilter>(&large).is_err());
+
+        // an empty filter is accepted and matches everything
+        let mut empty: BloomFilter = consensus::deserialize(&hex!("00050000000000000000"))?;
+        assert_eq!(empty.len(), 0);
+        empty.insert(b"element");
+        assert!(empty.contains(b"element"));
+        assert!(empty.contains(b"anything else"));
+        Ok(())
+    }
+}
@@ -0,0 +1,781 @@
+//! Header chain of an SPV client: every valid header seen, the tips of the
+//! chains they form and the one with the most work, the active chain
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use num_bigint::BigUint;
+
+use crate::network::Network;
+use crate::{Error, Result};
+
+use super::block::BlockHeader;
+use super::pow::{self, PowParams};
+use super::tx::Transaction;
+use super::txid::BlockHash;
+
+/// Blocks whose times the median time past is taken over
+pub const MEDIAN_TIME_SPAN: usize = 11;
+
+/// A header with its place in the tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainEntry {
+    pub(crate) header: BlockHeader,
+    pub(crate) hash: BlockHash,
+    pub(crate) height: u32,
+    /// Work of the chain up to and including this header
+    pub(crate) chainwork: BigUint,
+}
+
+impl ChainEntry {
+    pub fn header(&self) -> &BlockHeader {
+        &self.header
+    }
+
+    pub fn hash(&self) -> &BlockHash {
+        &self.hash
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn chainwork(&self) -> &BigUint {
+        &self.chainwork
+    }
+}
+
+/// What accepting a header did to the active chain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChainEvent {
+    /// The header was already known
+    Known,
+    /// It extends a chain with no more work than the active one
+    Fork,
+    /// It extends the active chain
+    Extended,
+    /// A chain with more work replaced the active one, `disconnected` from the
+    /// old tip down and `connected` from the fork up to the new tip
+    Reorg {
+        disconnected: Vec<BlockHeader>,
+        connected: Vec<BlockHeader>,
+    },
+}
+
+/// Headers connected to a genesis header, the active chain is the one with
+/// the most work, the first seen on ties
+#[derive(Debug, Clone)]
+pub struct HeaderChain {
+    pub(crate) params: PowParams,
+    pub(crate) entries: HashMap<BlockHash, ChainEntry>,
+    /// Headers nothing builds on yet
+    pub(crate) tips: HashSet<BlockHash>,
+    /// Hashes of the active chain by height
+    pub(crate) active: Vec<BlockHash>,
+    /// Hashes the chain must have at these heights
+    pub(crate) checkpoints: BTreeMap<u32, BlockHash>,
+    /// Block whose ancestors are trusted to have valid scripts
+    pub(crate) assume_valid: Option<BlockHash>,
+}
+
+impl HeaderChain {
+    pub fn new(genesis: BlockHeader, params: PowParams) -> Self {
+        let hash = genesis.block_hash();
+        let entry = ChainEntry {
+            header: genesis,
+            hash,
+            height: 0,
+            chainwork: genesis.work(),
+        };
+
+        Self {
+            params,
+            entries: vec![(hash, entry)].into_iter().collect(),
+            tips: vec![hash].into_iter().collect(),
+            active: vec![hash],
+            checkpoints: BTreeMap::new(),
+            assume_valid: None,
+        }
+    }
+
+    /// Chain of the genesis block of `network` with its hardcoded checkpoints
+    pub fn for_network(network: Network) -> Self {
+        let params = network.params();
+        Self::new(*params.genesis_block().header(), params.pow)
+            .with_checkpoints(params.checkpoints())
+    }
+
+    /// Headers at these heights must have these hashes, and no fork can start
+    /// below the highest one known
+    pub fn with_checkpoints(
+        mut self,
+        checkpoints: impl IntoIterator<Item = (u32, BlockHash)>,
+    ) -> Self {
+        self.checkpoints.extend(checkpoints);
+        self
+    }
+
+    /// Scripts of the ancestors of `hash` are trusted once it's in the active
+    /// chain, see [`HeaderChain::is_assumed_valid`]
+    pub fn with_assume_valid(mut self, hash: BlockHash) -> Self {
+        self.assume_valid = Some(hash);
+        self
+    }
+
+    pub fn params(&self) -> &PowParams {
+        &self.params
+    }
+
+    /// Height of the active tip
+    pub fn height(&self) -> u32 {
+        (self.active.len() - 1) as u32
+    }
+
+    /// Entry of the active tip
+    pub fn tip(&self) -> &ChainEntry {
+        &self.entries[&self.active[self.active.len() - 1]] // safe, never empty
+    }
+
+    /// Tips of every chain, the active one included
+    pub fn tips(&self) -> impl Iterator<Item = &ChainEntry> {
+        self.tips.iter().map(move |hash| &self.entries[hash])
+    }
+
+    /// Any known header
+    pub fn get(&self, hash: &BlockHash) -> Option<&ChainEntry> {
+        self.entries.get(hash)
+    }
+
+    /// Header of the active chain at `height`
+    pub fn at_height(&self, height: u32) -> Option<&ChainEntry> {
+        self.active
+            .get(height as usize)
+            .map(|hash| &self.entries[hash])
+    }
+
+    pub fn contains(&self, hash: &BlockHash) -> bool {
+        self.entries.contains_key(hash)
+    }
+
+    /// Whether `hash` is in the active chain
+    pub fn is_active(&self, hash: &BlockHash) -> bool {
+        self.entries
+            .get(hash)
+            .is_some_and(|entry| self.active.get(entry.height as usize) == Some(hash))
+    }
+
+    /// Ancestor at `height` of the header `hash`, itself included
+    pub fn ancestor(&self, hash: &BlockHash, height: u32) -> Option<&ChainEntry> {
+        let mut entry = self.entries.get(hash)?;
+        if height > entry.height {
+            return None;
+        }
+
+        // side chains are walked back until they meet the active one
+        while entry.height != height && self.active.get(entry.height as usize) != Some(&entry.hash)
+        {
+            entry = &self.entries[entry.header.prev_blockhash()];
+        }
+
+        match entry.height == height {
+            true => Some(entry),
+            false => self.at_height(height),
+        }
+    }
+
+    /// Hashes of the active chain to start `getheaders` from: the last ten,
+    /// then doubling the step back to the genesis
+    pub fn locator(&self) -> Vec<BlockHash> {
+        let mut locator = Vec::new();
+        let mut height = self.height();
+        let mut step = 1;
+        loop {
+            locator.push(self.active[height as usize]);
+            if height == 0 {
+                break;
+            }
+
+            if locator.len() >= 10 {
+                step *= 2;
+            }
+            height = height.saturating_sub(step);
+        }
+
+        locator
+    }
+
+    /// Headers of the active chain after the first hash of `locator` in it, the
+    /// genesis if none is, up to `stop_hash` or `max` of them. How `getheaders`
+    /// is answered
+    pub fn headers_after(
+        &self,
+        locator: &[BlockHash],
+        stop_hash: &BlockHash,
+        max: usize,
+    ) -> Vec<BlockHeader> {
+        let fork = locator
+            .iter()
+            .find(|hash| self.is_active(hash))
+            .map_or(0, |hash| self.entries[hash].height);
+
+        let mut headers = Vec::new();
+        for hash in self.active.iter().skip(fork as usize + 1).take(max) {
+            headers.push(self.entries[hash].header);
+            if hash == stop_hash {
+                break;
+            }
+        }
+
+        headers
+    }
+
+    /// Highest checkpoint whose header is known
+    pub fn last_checkpoint(&self) -> Option<&ChainEntry> {
+        self.checkpoints
+            .values()
+            .rev()
+            .find_map(|hash| self.entries.get(hash))
+    }
+
+    /// Whether the scripts of the block `hash` can go unchecked, it's in the
+    /// active chain below a checkpoint or the assume valid block
+    pub fn is_assumed_valid(&self, hash: &BlockHash) -> bool {
+        let height = match self.entries.get(hash) {
+            Some(entry) if self.is_active(hash) => entry.height,
+            _ => return false,
+        };
+
+        self.assume_valid
+            .iter()
+            .chain(self.checkpoints.values())
+            .filter_map(|trusted| self.entries.get(trusted))
+            .any(|trusted| trusted.height >= height && self.is_active(&trusted.hash))
+    }
+
+    /// Median of the times of the block of the active chain at `height` and the
+    /// ten before it (BIP113), what time locktimes are compared against
+    pub fn median_time_past(&self, height: u32) -> Option<u32> {
+        self.at_height(height)
+            .map(|entry| self.median_time_past_of(entry))
+    }
+
+    /// Whether `tx` can go in the next block of the active chain
+    pub fn is_final_tx(&self, tx: &Transaction) -> bool {
+        let tip = self.tip();
+        tx.is_final(tip.height + 1, self.median_time_past_of(tip))
+    }
+
+    /// Whether the relative locktimes of `tx` (BIP68) let it go in the next
+    /// block of the active chain, `coin_heights` being where the outputs its
+    /// inputs spend confirmed
+    pub fn sequence_locks_satisfied(&self, tx: &Transaction, coin_heights: &[u32]) -> bool {
+        if tx.version() < 2 {
+            return true;
+        }
+
+        let tip = self.tip();
+        let height = tip.height + 1;
+        let mtp = self.median_time_past_of(tip);
+        tx.inputs()
+            .iter()
+            .zip(coin_heights)
+            .all(|(input, coin_height)| {
+                let locktime = match input.sequence().to_relative_lock_time() {
+                    Some(locktime) => locktime,
+                    None => return true,
+                };
+
+                // time counts from the median time past of the block before the coin's
+                let coin_time = self
+                    .median_time_past(coin_height.saturating_sub(1))
+                    .unwrap_or(u32::MAX);
+                locktime.is_satisfied_by(
+                    height.saturating_sub(*coin_height),
+                    mtp.saturating_sub(coin_time),
+                )
+            })
+    }
+
+    fn median_time_past_of<'a>(&'a self, mut entry: &'a ChainEntry) -> u32 {
+        let mut times = Vec::with_capacity(MEDIAN_TIME_SPAN);
+        loop {
+            times.push(entry.header.time());
+            match self.entries.get(entry.header.prev_blockhash()) {
+                Some(prev) if times.len() < MEDIAN_TIME_SPAN => entry = prev,
+                _ => break,
+            }
+        }
+
+        times.sort_unstable();
+        times[times.len() / 2]
+    }
+
+    /// Validates `header` against the one it builds on, its time, proof of work
+    /// and difficulty, and adds it to the tree
+    pub fn accept(&mut self, header: BlockHeader) -> Result<ChainEvent> {
+        let hash = header.block_hash();
+        if self.entries.contains_key(&hash) {
+            return Ok(ChainEvent::Known);
+        }
+
+        let prev = self
+            .entries
+            .get(header.prev_blockhash())
+            .ok_or(Error::InvalidBlock("unknown previous header"))?;
+
+        let height = prev.height + 1;
+        match self.checkpoints.get(&height) {
+            Some(checkpoint) if *checkpoint != hash => {
+                return Err(Error::InvalidBlock("checkpoint mismatch"));
+            }
+            _ => {}
+        }
+
+        if self
+            .last_checkpoint()
+            .is_some_and(|checkpoint| height < checkpoint.height)
+        {
+            return Err(Error::InvalidBlock("fork before the last checkpoint"));
+        }
+
+        if header.time() <= self.median_time_past_of(prev) {
+            return Err(Error::InvalidBlock("time too old"));
+        }
+
+        header.validate_pow()?;
+        let prev_hash = prev.hash;
+        let bits = pow::next_work_required(
+            &self.params,
+            &prev.header,
+            prev.height,
+            header.time(),
+            |height| self.ancestor(&prev_hash, height).map(|entry| entry.header),
+        )?;
+        if header.bits() != bits {
+            return Err(Error::InvalidBlock("unexpected difficulty bits"));
+        }
+
+        let entry = ChainEntry {
+            header,
+            hash,
+            height,
+            chainwork: &prev.chainwork + header.work(),
+        };
+        let more_work = entry.chainwork > self.tip().chainwork;
+
+        self.tips.remove(&prev_hash);
+        self.tips.insert(hash);
+        self.entries.insert(hash, entry);
+
+        if !more_work {
+            return Ok(ChainEvent::Fork);
+        }
+
+        if prev_hash == self.tip().hash {
+            self.active.push(hash);
+            return Ok(ChainEvent::Extended);
+        }
+
+        Ok(self.reorganize(hash))
+    }
+
+    /// Makes the chain ending at `tip` the active one
+    fn reorganize(&mut self, tip: BlockHash) -> ChainEvent {
+        let mut connected = Vec::new();
+        let mut entry = &self.entries[&tip];
+        while self.active.get(entry.height as usize) != Some(&entry.hash) {
+            connected.push(entry.hash);
+            entry = &self.entries[entry.header.prev_blockhash()];
+        }
+
+        let fork = entry.height as usize;
+        let entries = &self.entries;
+        let disconnected = self
+            .active
+            .drain(fork + 1..)
+            .rev()
+            .map(|hash| entries[&hash].header)
+            .collect();
+
+        connected.reverse();
+        self.active.extend(&connected);
+
+        ChainEvent::Reorg {
+            disconnected,
+            connected: connected
+                .iter()
+                .map(|hash| self.entries[hash].header)
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use hex_literal::hex;
+
+    use crate::core::input::{OutPoint, TxIn};
+    use crate::core::locktime::Sequence;
+    use crate::core::txid::Txid;
+    use crate::network::Network;
+
+    use super::*;
+
+    /// Regtest genesis
+    fn genesis() -> Result<BlockHeader> {
+        Ok(BlockHeader::from_bytes(&hex!("0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4adae5494dffff7f2002000000"))?)
+    }
+
+    /// Header on top of `prev` at regtest difficulty, `salt` tells siblings apart
+    fn mine(prev: &BlockHeader, salt: u8) -> BlockHeader {
+        mine_at(prev, salt, prev.time() + 600)
+    }
+
+    fn mine_at(prev: &BlockHeader, salt: u8, time: u32) -> BlockHeader {
+        let mut header = BlockHeader::new(4, prev.block_hash(), [salt; 32], time, 0x207f_ffff, 0);
+        while header.validate_pow().is_err() {
+            header.nonce += 1;
+        }
+        header
+    }
+
+    fn mine_chain(prev: &BlockHeader, len: usize, salt: u8) -> Vec<BlockHeader> {
+        let mut headers: Vec<BlockHeader> = Vec::new();
+        for _ in 0..len {
+            let header = mine(headers.last().unwrap_or(prev), salt);
+            headers.push(header);
+        }
+        headers
+    }
+
+    #[test]
+    fn extend_and_validate() -> Result<()> {
+        let genesis = genesis()?;
+        assert_eq!(
+            genesis.block_hash().to_string(),
+            "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206"
+        );
+
+        let mut chain = HeaderChain::new(genesis, PowParams::new(Network::Regtest));
+        let headers = mine_chain(&genesis, 5, 0);
+        for header in &headers {
+            assert_eq!(chain.accept(*header)?, ChainEvent::Extended);
+        }
+        assert_eq!(chain.accept(headers[2])?, ChainEvent::Known);
+
+        assert_eq!(chain.height(), 5);
+        assert_eq!(chain.tip().header(), &headers[4]);
+        assert_eq!(chain.tips().count(), 1);
+        assert_eq!(
+            chain.at_height(0).map(|entry| *entry.hash()),
+            Some(genesis.block_hash())
+        );
+        assert_eq!(chain.tip().chainwork(), &(genesis.work() * 6u32));
+
+        // not connected, bad proof of work and bad bits
+        let orphan = mine(&mine(&headers[4], 0), 0);
+        assert!(chain.accept(orphan).is_err());
+
+        let mut header = mine(&headers[4], 0);
+        header.nonce += 1;
+        while header.validate_pow().is_ok() {
+            header.nonce += 1;
+        }
+        assert!(chain.accept(header).is_err());
+
+        let mut header = mine(&headers[4], 0);
+        header.bits = 0x207f_fffe;
+        while header.validate_pow().is_err() {
+            header.nonce += 1;
+        }
+        assert!(chain.accept(header).is_err());
+        assert_eq!(chain.height(), 5);
+        Ok(())
+    }
+
+    #[test]
+    fn forks_and_reorgs() -> Result<()> {
+        let genesis = genesis()?;
+        let mut chain = HeaderChain::new(genesis, PowParams::new(Network::Regtest));
+
+        let main = mine_chain(&genesis, 4, 0);
+        for header in &main {
+            chain.accept(*header)?;
+        }
+
+        // a fork at height 2 catching up doesn't replace the first seen chain
+        let fork = mine_chain(&main[1], 3, 1);
+        assert_eq!(chain.accept(fork[0])?, ChainEvent::Fork);
+        assert_eq!(chain.accept(fork[1])?, ChainEvent::Fork);
+        assert_eq!(chain.tips().count(), 2);
+        assert!(!chain.is_active(&fork[1].block_hash()));
+        assert_eq!(
+            chain
+                .ancestor(&fork[1].block_hash(), 1)
+                .map(|entry| *entry.header()),
+            Some(main[0])
+        );
+        assert_eq!(
+            chain
+                .ancestor(&fork[1].block_hash(), 3)
+                .map(|entry| *entry.header()),
+            Some(fork[0])
+        );
+        assert!(chain.ancestor(&fork[1].block_hash(), 5).is_none());
+
+        assert_eq!(
+            chain.accept(fork[2])?,
+            ChainEvent::Reorg {
+                disconnected: vec![main[3], main[2]],
+                connected: fork.clone(),
+            }
+        );
+        assert_eq!(chain.height(), 5);
+        assert_eq!(chain.tip().header(), &fork[2]);
+        assert!(chain.is_active(&main[1].block_hash()));
+        assert!(!chain.is_active(&main[2].block_hash()));
+
+        // and back
+        let header = mine(&main[3], 0);
+        assert_eq!(chain.accept(header)?, ChainEvent::Fork);
+        assert_eq!(
+            chain.accept(mine(&header, 0))?,
+            ChainEvent::Reorg {
+                disconnected: fork.iter().rev().cloned().collect(),
+                connected: vec![main[2], main[3], header, mine(&header, 0)],
+            }
+        );
+        assert_eq!(chain.tips().count(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn taller_side_chains() -> Result<()> {
+        let genesis = genesis()?;
+        let mut chain = HeaderChain::new(genesis, PowParams::new(Network::Regtest));
+        let main = mine_chain(&genesis, 2, 0);
+        for header in &main {
+            chain.accept(*header)?;
+        }
+
+        // longer but with less work, as a run of min difficulty testnet blocks
+        let side = mine_chain(&genesis, 4, 1);
+        for (height, header) in (1..).zip(&side) {
+            let hash = header.block_hash();
+            chain.tips.remove(header.prev_blockhash());
+            chain.tips.insert(hash);
+            chain.entries.insert(
+                hash,
+                ChainEntry {
+                    header: *header,
+                    hash,
+                    height,
+                    chainwork: genesis.work() + height,
+                },
+            );
+        }
+        let tip = side[3].block_hash();
+
+        assert!(!chain.is_active(&tip));
+        assert!(!chain.is_assumed_valid(&tip));
+        assert_eq!(
+            chain.ancestor(&tip, 1).map(|entry| *entry.header()),
+            Some(side[0])
+        );
+        assert_eq!(
+            chain.ancestor(&tip, 0).map(|entry| *entry.hash()),
+            Some(genesis.block_hash())
+        );
+        assert_eq!(chain.headers_after(&[tip], &tip, 10), main);
+        assert_eq!(chain.tip().header(), &main[1]);
+        Ok(())
+    }
+
+    #[test]
+    fn locator() -> Result<()> {
+        let genesis = genesis()?;
+        let mut chain = HeaderChain::new(genesis, PowParams::new(Network::Regtest));
+        assert_eq!(chain.locator(), vec![genesis.block_hash()]);
+
+        let main = mine_chain(&genesis, 30, 0);
+        for header in &main {
+            chain.accept(*header)?;
+        }
+
+        let heights: Vec<_> = chain
+            .locator()
+            .iter()
+            .map(|hash| chain.get(hash).map(ChainEntry::height))
+            .collect::<Option<_>>()
+            .unwrap_or_default();
+        let expected = [30, 29, 28, 27, 26, 25, 24, 23, 22, 21, 19, 15, 7, 0];
+        assert_eq!(heights, expected);
+
+        // answered from the last block both chains share
+        let mut peer = HeaderChain::new(genesis, PowParams::new(Network::Regtest));
+        for header in &main[..12] {
+            peer.accept(*header)?;
+        }
+        let stop = BlockHash::default();
+        assert_eq!(
+            chain.headers_after(&peer.locator(), &stop, 2000),
+            &main[12..]
+        );
+        assert_eq!(
+            chain.headers_after(&peer.locator(), &stop, 5),
+            &main[12..17]
+        );
+        let stop = main[13].block_hash();
+        assert_eq!(
+            chain.headers_after(&peer.locator(), &stop, 2000),
+            &main[12..14]
+        );
+        assert_eq!(chain.headers_after(&[], &stop, 1), &main[..1]);
+        // the locator skips past the tip of the peer, to the height 7 it has
+        let stop = BlockHash::default();
+        assert_eq!(
+            peer.headers_after(&chain.locator(), &stop, 2000),
+            &main[7..12]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn median_time_past() -> Result<()> {
+        let genesis = genesis()?;
+        let mut chain = HeaderChain::new(genesis, PowParams::new(Network::Regtest));
+        let start = genesis.time();
+        assert_eq!(chain.median_time_past(0), Some(start));
+
+        // times out of order, still above the median time past of each parent
+        let offsets = [1, 3, 2, 5, 4, 7, 6, 9, 8, 11, 10, 12];
+        let mut prev = genesis;
+        for offset in offsets.iter() {
+            prev = mine_at(&prev, 0, start + offset * 600);
+            chain.accept(prev)?;
+        }
+
+        // the later of the middle two with an even count
+        assert_eq!(chain.median_time_past(1), Some(start + 600));
+        assert_eq!(chain.median_time_past(2), Some(start + 600));
+        assert_eq!(chain.median_time_past(3), Some(start + 2 * 600));
+        // heights 2 to 12, the first 11 drop out
+        assert_eq!(chain.median_time_past(12), Some(start + 7 * 600));
+        assert_eq!(chain.median_time_past(13), None);
+
+        // not after the median time past of its parent
+        let mtp = chain.median_time_past(12).unwrap_or_default();
+        assert!(chain.accept(mine_at(&prev, 0, mtp)).is_err());
+        assert!(chain.accept(mine_at(&prev, 1, mtp + 1)).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn locktimes() -> Result<()> {
+        let genesis = genesis()?;
+        let mut chain = HeaderChain::new(genesis, PowParams::new(Network::Regtest));
+        for header in mine_chain(&genesis, 20, 0) {
+            chain.accept(header)?;
+        }
+        let mtp = chain.median_time_past(20).unwrap_or_default();
+        assert_eq!(mtp, genesis.time() + 15 * 600);
+
+        let tx = |sequence: Sequence, locktime: u32| {
+            let mut input = TxIn::new(OutPoint::new(Txid::from_bytes([1; 32]), 0));
+            input.set_sequence(sequence);
+            Transaction::new(2, vec![input], vec![], locktime)
+        };
+
+        // heights and times, against the next block and the tip's median time past
+        let locked = Sequence::ENABLE_LOCKTIME_NO_RBF;
+        assert!(chain.is_final_tx(&tx(locked, 20)));
+        assert!(!chain.is_final_tx(&tx(locked, 21)));
+        assert!(chain.is_final_tx(&tx(Sequence::MAX, 21)));
+        assert!(chain.is_final_tx(&tx(locked, mtp - 1)));
+        assert!(!chain.is_final_tx(&tx(locked, mtp)));
+        assert!(!chain.is_final_tx(&tx(locked, genesis.time() + 20 * 600)));
+
+        // coins confirmed at height 11, 10 blocks and 6000 seconds of median time past ago
+        let coin_height = [11];
+        let blocks = |blocks| tx(Sequence::from_height(blocks), 0);
+        assert!(chain.sequence_locks_satisfied(&blocks(10), &coin_height));
+        assert!(!chain.sequence_locks_satisfied(&blocks(11), &coin_height));
+
+        let intervals = |intervals| tx(Sequence::from_512_second_intervals(intervals), 0);
+        assert!(chain.sequence_locks_satisfied(&intervals(11), &coin_height));
+        assert!(!chain.sequence_locks_satisfied(&intervals(12), &coin_height));
+
+        // version 1 transactions have none
+        let mut v1 = blocks(11);
+        v1.version = 1;
+        assert!(chain.sequence_locks_satisfied(&v1, &coin_height));
+        Ok(())
+    }
+
+    #[test]
+    fn checkpoints() -> Result<()> {
+        let genesis = genesis()?;
+        let main = mine_chain(&genesis, 5, 0);
+        let mut chain = HeaderChain::new(genesis, PowParams::new(Network::Regtest))
+            .with_checkpoints(vec![(3, main[2].block_hash())]);
+        assert!(chain.last_checkpoint().is_none());
+
+        chain.accept(main[0])?;
+        chain.accept(main[1])?;
+        assert!(matches!(
+            chain.accept(mine(&main[1], 1)),
+            Err(Error::InvalidBlock("checkpoint mismatch"))
+        ));
+        for header in &main[2..] {
+            chain.accept(*header)?;
+        }
+        assert_eq!(chain.last_checkpoint().map(ChainEntry::height), Some(3));
+
+        // forks can only start at the last checkpoint or later
+        assert!(matches!(
+            chain.accept(mine(&main[0], 1)),
+            Err(Error::InvalidBlock("fork before the last checkpoint"))
+        ));
+        assert_eq!(chain.accept(mine(&main[2], 1))?, ChainEvent::Fork);
+
+        let mainnet = HeaderChain::for_network(Network::Mainnet);
+        assert_eq!(mainnet.height(), 0);
+        assert_eq!(mainnet.checkpoints.len(), 13);
+        Ok(())
+    }
+
+    #[test]
+    fn assume_valid() -> Result<()> {
+        let genesis = genesis()?;
+        let main = mine_chain(&genesis, 6, 0);
+        let mut chain = HeaderChain::new(genesis, PowParams::new(Network::Regtest))
+            .with_checkpoints(vec![(2, main[1].block_hash())])
+            .with_assume_valid(main[3].block_hash());
+        for header in &main[..3] {
+            chain.accept(*header)?;
+        }
+
+        // the assume valid block isn't known yet, only the checkpoint counts
+        assert!(chain.is_assumed_valid(&genesis.block_hash()));
+        assert!(chain.is_assumed_valid(&main[1].block_hash()));
+        assert!(!chain.is_assumed_valid(&main[2].block_hash()));
+
+        for header in &main[3..] {
+            chain.accept(*header)?;
+        }
+        assert!(chain.is_assumed_valid(&main[2].block_hash()));
+        assert!(chain.is_assumed_valid(&main[3].block_hash()));
+        assert!(!chain.is_assumed_valid(&main[4].block_hash()));
+
+        // a chain with more work leaving it out trusts nothing past the checkpoint
+        let fork = mine_chain(&main[2], 4, 1);
+        for header in &fork {
+            chain.accept(*header)?;
+        }
+        assert!(!chain.is_active(&main[3].block_hash()));
+        assert!(!chain.is_assumed_valid(&main[2].block_hash()));
+        assert!(!chain.is_assumed_valid(&fork[0].block_hash()));
+        assert!(chain.is_assumed_valid(&main[1].block_hash()));
+        Ok(())
+    }
+}
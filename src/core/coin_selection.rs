@@ -0,0 +1,579 @@
+//! Coin selection over a caller provided set of UTXOs, every strategy works with
+//! effective values, what an output is worth once the fee to spend it is paid
+
+use std::cmp::Reverse;
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use crate::amount::Amount;
+use crate::{Error, Result};
+
+use super::fee::{FeeRate, InputType};
+use super::input::OutPoint;
+use super::output::TxOut;
+
+/// Tries before Branch and Bound gives up on finding a changeless solution
+const BNB_TOTAL_TRIES: usize = 100_000;
+/// Random subsets tried by the knapsack solver
+const KNAPSACK_ITERATIONS: usize = 1_000;
+
+/// Weight of a P2WPKH output, the default change
+const P2WPKH_OUTPUT_WEIGHT: usize = 31 * 4;
+/// Dust limit of P2WPKH outputs at the default 3 sat/vB dust relay fee
+const P2WPKH_DUST_LIMIT: Amount = Amount::from_sat(294);
+
+/// An output the wallet can spend along with how it's going to be spent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Utxo {
+    pub(crate) outpoint: OutPoint,
+    pub(crate) output: TxOut,
+    pub(crate) input_type: InputType,
+}
+
+impl Utxo {
+    pub fn new(outpoint: OutPoint, output: TxOut, input_type: InputType) -> Self {
+        Self {
+            outpoint,
+            output,
+            input_type,
+        }
+    }
+
+    pub fn outpoint(&self) -> &OutPoint {
+        &self.outpoint
+    }
+
+    pub fn output(&self) -> &TxOut {
+        &self.output
+    }
+
+    pub fn input_type(&self) -> InputType {
+        self.input_type
+    }
+
+    /// Its amount minus the fee paid by its input, negative when not worth spending
+    fn effective_value(&self, fee_rate: FeeRate) -> i64 {
+        let fee = fee_rate.fee_for_weight(self.input_type.input_weight());
+        self.output.amount.to_sat() as i64 - fee.to_sat() as i64
+    }
+}
+
+/// What the selection pays for besides the target amount
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SelectionParams {
+    pub(crate) fee_rate: FeeRate,
+    pub(crate) base_weight: usize,
+    pub(crate) change_weight: usize,
+    pub(crate) change_spend_weight: usize,
+    pub(crate) dust_limit: Amount,
+}
+
+impl SelectionParams {
+    /// `base_weight` is the weight of the transaction without any input, with its
+    /// recipient outputs but no change. The change defaults to P2WPKH
+    pub fn new(fee_rate: FeeRate, base_weight: usize) -> Self {
+        Self {
+            fee_rate,
+            base_weight,
+            change_weight: P2WPKH_OUTPUT_WEIGHT,
+            change_spend_weight: InputType::P2wpkh.input_weight(),
+            dust_limit: P2WPKH_DUST_LIMIT,
+        }
+    }
+
+    /// Weight of the change output and of the input spending it later on
+    pub fn with_change_weights(mut self, output_weight: usize, spend_weight: usize) -> Self {
+        self.change_weight = output_weight;
+        self.change_spend_weight = spend_weight;
+        self
+    }
+
+    /// Smallest change worth creating, anything below goes to the fee
+    pub fn with_dust_limit(mut self, dust_limit: Amount) -> Self {
+        self.dust_limit = dust_limit;
+        self
+    }
+
+    fn base_fee(&self) -> i64 {
+        self.fee_rate.fee_for_weight(self.base_weight).to_sat() as i64
+    }
+
+    fn change_fee(&self) -> i64 {
+        self.fee_rate.fee_for_weight(self.change_weight).to_sat() as i64
+    }
+
+    /// Creating a change output now and spending it later
+    fn cost_of_change(&self) -> i64 {
+        let weight = self.change_weight + self.change_spend_weight;
+        self.fee_rate.fee_for_weight(weight).to_sat() as i64
+    }
+}
+
+/// The chosen UTXOs, the change to send back if any and the resulting fee
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selection {
+    pub(crate) utxos: Vec<Utxo>,
+    pub(crate) change: Option<Amount>,
+    pub(crate) fee: Amount,
+}
+
+impl Selection {
+    pub fn utxos(&self) -> &[Utxo] {
+        &self.utxos
+    }
+
+    pub fn change(&self) -> Option<Amount> {
+        self.change
+    }
+
+    pub fn fee(&self) -> Amount {
+        self.fee
+    }
+
+    /// Sum of the selected amounts
+    pub fn total(&self) -> Result<Amount> {
+        total_amount(&self.utxos)
+    }
+
+    /// Turns the picked UTXOs into a selection, adding change only when what's
+    /// left once it pays for itself isn't dust
+    fn new(utxos: Vec<Utxo>, target: Amount, params: &SelectionParams) -> Result<Self> {
+        let effective: i64 = utxos
+            .iter()
+            .map(|utxo| utxo.effective_value(params.fee_rate))
+            .sum();
+        let excess = effective - target.to_sat() as i64 - params.base_fee();
+        let change = excess - params.change_fee();
+
+        let change = match change >= params.dust_limit.to_sat() as i64 {
+            true => Some(Amount::from_sat(change as u64)),
+            false => None,
+        };
+
+        let total = total_amount(&utxos)?;
+        let fee = total - target - change.unwrap_or(Amount::ZERO);
+        Ok(Self { utxos, change, fee })
+    }
+}
+
+fn total_amount(utxos: &[Utxo]) -> Result<Amount> {
+    utxos.iter().try_fold(Amount::ZERO, |total, utxo| {
+        total
+            .checked_add(utxo.output.amount)
+            .ok_or(Error::InvalidAmount("amount overflow"))
+    })
+}
+
+/// The UTXOs worth spending at the fee rate with their effective values, highest
+/// first, and whether they're enough to pay `target` plus the base fee
+fn spendable(
+    utxos: &[Utxo],
+    target: Amount,
+    params: &SelectionParams,
+) -> Result<Vec<(usize, i64)>> {
+    // keeps the sums of effective values below from overflowing
+    if total_amount(utxos)? > Amount::MAX_MONEY || target > Amount::MAX_MONEY {
+        return Err(Error::InvalidAmount("amount out of range"));
+    }
+
+    let mut pool: Vec<_> = utxos
+        .iter()
+        .map(|utxo| utxo.effective_value(params.fee_rate))
+        .enumerate()
+        .filter(|(_, value)| *value > 0)
+        .collect();
+    pool.sort_by_key(|(_, value)| Reverse(*value));
+
+    let available: i64 = pool.iter().map(|(_, value)| value).sum();
+    if available < target.to_sat() as i64 + params.base_fee() {
+        return Err(Error::CoinSelection("insufficient funds"));
+    }
+
+    Ok(pool)
+}
+
+fn pick(utxos: &[Utxo], indexes: impl Iterator<Item = usize>) -> Vec<Utxo> {
+    indexes.map(|index| utxos[index].clone()).collect()
+}
+
+/// Depth first search for a changeless selection, one whose excess over the target
+/// is below the cost of change, keeping the one wasting the least
+pub fn branch_and_bound(
+    utxos: &[Utxo],
+    target: Amount,
+    params: &SelectionParams,
+) -> Result<Selection> {
+    let pool = spendable(utxos, target, params)?;
+    let target_value = target.to_sat() as i64 + params.base_fee();
+    let upper_bound = target_value + params.cost_of_change();
+
+    let mut available: i64 = pool.iter().map(|(_, value)| value).sum();
+    let mut value = 0;
+    let mut selection: Vec<bool> = Vec::with_capacity(pool.len());
+    let mut best: Option<(i64, Vec<bool>)> = None;
+
+    for _ in 0..BNB_TOTAL_TRIES {
+        let mut backtrack = false;
+        if value + available < target_value || value > upper_bound {
+            backtrack = true;
+        } else if value >= target_value {
+            let waste = value - target_value;
+            if best.as_ref().is_none_or(|(best, _)| waste <= *best) {
+                best = Some((waste, selection.clone()));
+            }
+
+            backtrack = true;
+        }
+
+        if backtrack {
+            // walk back to the last included UTXO and try omitting it instead
+            while selection.last() == Some(&false) {
+                selection.pop();
+                available += pool[selection.len()].1;
+            }
+
+            match selection.last_mut() {
+                Some(included) => *included = false,
+                None => break,
+            }
+
+            value -= pool[selection.len() - 1].1;
+            continue;
+        }
+
+        let current = pool[selection.len()].1;
+        available -= current;
+
+        // including an UTXO of the same value as the omitted previous one yields
+        // the same subsets already explored
+        let equivalent = selection.last() == Some(&false) && pool[selection.len() - 1].1 == current;
+        if equivalent {
+            selection.push(false);
+        } else {
+            selection.push(true);
+            value += current;
+        }
+    }
+
+    let (_, best) = best.ok_or(Error::CoinSelection("no changeless solution"))?;
+    let indexes = pool
+        .iter()
+        .zip(best)
+        .filter(|(_, included)| *included)
+        .map(|((index, _), _)| *index);
+
+    Selection::new(pick(utxos, indexes), target, params)
+}
+
+/// Randomized search for the subset closest to the target, or to the target plus
+/// enough for a change output that isn't dust, falling back to the smallest UTXO
+/// covering it alone
+pub fn knapsack(utxos: &[Utxo], target: Amount, params: &SelectionParams) -> Result<Selection> {
+    let mut pool = spendable(utxos, target, params)?;
+    let target_value = target.to_sat() as i64 + params.base_fee();
+    let change_target = target_value + params.change_fee() + params.dust_limit.to_sat() as i64;
+
+    let mut rng = rand::thread_rng();
+    pool.shuffle(&mut rng);
+
+    let mut lowest_larger: Option<(usize, i64)> = None;
+    let mut applicable = Vec::new();
+    for &(index, value) in &pool {
+        if value == target_value {
+            return Selection::new(pick(utxos, Some(index).into_iter()), target, params);
+        }
+
+        if value < change_target {
+            applicable.push((index, value));
+        } else if lowest_larger.is_none_or(|(_, lowest)| value < lowest) {
+            lowest_larger = Some((index, value));
+        }
+    }
+
+    let total_lower: i64 = applicable.iter().map(|(_, value)| value).sum();
+    if total_lower == target_value {
+        let indexes = applicable.iter().map(|(index, _)| *index);
+        return Selection::new(pick(utxos, indexes), target, params);
+    }
+
+    if total_lower < target_value {
+        // there are funds so some larger one must cover it
+        let (index, _) = lowest_larger.ok_or(Error::CoinSelection("insufficient funds"))?;
+        return Selection::new(pick(utxos, Some(index).into_iter()), target, params);
+    }
+
+    applicable.sort_by_key(|(_, value)| Reverse(*value));
+    let values: Vec<_> = applicable.iter().map(|(_, value)| *value).collect();
+
+    let (mut best_value, mut best) = approximate_best_subset(&mut rng, &values, target_value);
+    if best_value != target_value && total_lower >= change_target {
+        let (value, subset) = approximate_best_subset(&mut rng, &values, change_target);
+        best_value = value;
+        best = subset;
+    }
+
+    if let Some((index, lowest)) = lowest_larger {
+        if (best_value != target_value && best_value < change_target) || lowest <= best_value {
+            return Selection::new(pick(utxos, Some(index).into_iter()), target, params);
+        }
+    }
+
+    let indexes = applicable
+        .iter()
+        .zip(best)
+        .filter(|(_, included)| *included)
+        .map(|((index, _), _)| *index);
+
+    Selection::new(pick(utxos, indexes), target, params)
+}
+
+/// The smallest sum reaching `target` found over random subsets of `values`,
+/// which must reach it when all included
+fn approximate_best_subset<R: Rng>(rng: &mut R, values: &[i64], target: i64) -> (i64, Vec<bool>) {
+    let mut best = vec![true; values.len()];
+    let mut best_value: i64 = values.iter().sum();
+
+    for _ in 0..KNAPSACK_ITERATIONS {
+        if best_value == target {
+            break;
+        }
+
+        let mut included = vec![false; values.len()];
+        let mut total = 0;
+        let mut reached = false;
+
+        // a random pass first, then one adding whatever was left out
+        for pass in 0..2 {
+            if reached {
+                break;
+            }
+
+            for (i, value) in values.iter().enumerate() {
+                let include = match pass {
+                    0 => rng.gen(),
+                    _ => !included[i],
+                };
+
+                if !include {
+                    continue;
+                }
+
+                total += value;
+                included[i] = true;
+                if total >= target {
+                    reached = true;
+                    if total < best_value {
+                        best_value = total;
+                        best = included.clone();
+                    }
+
+                    total -= value;
+                    included[i] = false;
+                }
+            }
+        }
+    }
+
+    (best_value, best)
+}
+
+/// Spend the UTXOs with the highest effective values until the target is paid
+pub fn largest_first(
+    utxos: &[Utxo],
+    target: Amount,
+    params: &SelectionParams,
+) -> Result<Selection> {
+    let pool = spendable(utxos, target, params)?;
+    let target_value = target.to_sat() as i64 + params.base_fee();
+
+    let mut value = 0;
+    let indexes = pool
+        .iter()
+        .take_while(|(_, current)| {
+            let missing = value < target_value;
+            value += current;
+            missing
+        })
+        .map(|(index, _)| *index);
+
+    Selection::new(pick(utxos, indexes), target, params)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::core::script::Script;
+    use crate::core::txid::Txid;
+
+    use super::*;
+
+    fn utxo(vout: u32, sats: u64) -> Utxo {
+        let output = TxOut::new(Amount::from_sat(sats), Script::from(vec![0x51; 22]));
+        Utxo::new(
+            OutPoint::new(Txid::from_bytes([0x11; 32]), vout),
+            output,
+            InputType::P2wpkh,
+        )
+    }
+
+    /// An amount of `sats` once its 68 vbytes input is paid at 1 sat/vB
+    fn effective(vout: u32, sats: u64) -> Utxo {
+        utxo(vout, sats + 69)
+    }
+
+    fn vouts(selection: &Selection) -> Vec<u32> {
+        let mut vouts: Vec<_> = selection
+            .utxos
+            .iter()
+            .map(|utxo| utxo.outpoint.vout())
+            .collect();
+        vouts.sort_unstable();
+        vouts
+    }
+
+    fn check(selection: &Selection, target: Amount) {
+        let change = selection.change.unwrap_or(Amount::ZERO);
+        assert_eq!(selection.total().unwrap(), target + change + selection.fee);
+    }
+
+    #[test]
+    fn branch_and_bound_exact_match() -> Result<()> {
+        let utxos = [
+            effective(0, 1_000),
+            effective(1, 2_000),
+            effective(2, 3_000),
+            effective(3, 4_000),
+        ];
+        let params = SelectionParams::new(FeeRate::MIN_RELAY, 0);
+        assert_eq!(InputType::P2wpkh.input_weight().div_ceil(4), 69);
+
+        let selection = branch_and_bound(&utxos, Amount::from_sat(5_000), &params)?;
+        check(&selection, Amount::from_sat(5_000));
+        assert_eq!(selection.change, None);
+        assert_eq!(selection.fee, Amount::from_sat(2 * 69));
+        assert!(vouts(&selection) == [0, 3] || vouts(&selection) == [1, 2]);
+
+        // a few sats over the target are left as fee instead of creating change
+        let selection = branch_and_bound(&utxos, Amount::from_sat(4_990), &params)?;
+        assert_eq!(selection.change, None);
+        assert_eq!(selection.fee, Amount::from_sat(2 * 69 + 10));
+
+        // the base weight is paid too
+        let params = SelectionParams::new(FeeRate::MIN_RELAY, 400);
+        let selection = branch_and_bound(&utxos, Amount::from_sat(4_900), &params)?;
+        assert_eq!(selection.change, None);
+        assert_eq!(selection.fee, Amount::from_sat(2 * 69 + 100));
+
+        let params = SelectionParams::new(FeeRate::MIN_RELAY, 0);
+        assert!(branch_and_bound(&utxos, Amount::from_sat(10_001), &params).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn branch_and_bound_without_solution() {
+        let utxos = [effective(0, 10_000), effective(1, 20_000)];
+        let params = SelectionParams::new(FeeRate::MIN_RELAY, 0);
+
+        // every subset overshoots by more than the cost of change
+        let result = branch_and_bound(&utxos, Amount::from_sat(5_000), &params);
+        assert!(matches!(
+            result,
+            Err(Error::CoinSelection("no changeless solution"))
+        ));
+    }
+
+    #[test]
+    fn knapsack_selection() -> Result<()> {
+        let params = SelectionParams::new(FeeRate::MIN_RELAY, 0);
+
+        // an exact match alone is always taken
+        let utxos = [
+            effective(0, 7_000),
+            effective(1, 5_000),
+            effective(2, 9_000),
+        ];
+        let selection = knapsack(&utxos, Amount::from_sat(5_000), &params)?;
+        assert_eq!(vouts(&selection), [1]);
+        assert_eq!(selection.change, None);
+
+        // the smallest covering the target with change beats combining small ones
+        let utxos = [
+            effective(0, 1_000),
+            effective(1, 2_000),
+            effective(2, 50_000),
+            effective(3, 80_000),
+        ];
+        let target = Amount::from_sat(20_000);
+        let selection = knapsack(&utxos, target, &params)?;
+        check(&selection, target);
+        assert_eq!(vouts(&selection), [2]);
+        let change = selection.change.unwrap();
+        assert_eq!(change, Amount::from_sat(30_000 - 31));
+
+        // small ones adding up to the target are enough
+        let target = Amount::from_sat(3_000);
+        let selection = knapsack(&utxos, target, &params)?;
+        assert_eq!(vouts(&selection), [0, 1]);
+        assert_eq!(selection.change, None);
+
+        assert!(knapsack(&utxos, Amount::from_sat(140_000), &params).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn largest_first_selection() -> Result<()> {
+        let utxos = [
+            effective(0, 1_000),
+            effective(1, 40_000),
+            effective(2, 30_000),
+            // not worth spending at this rate
+            utxo(3, 60),
+        ];
+        let params = SelectionParams::new(FeeRate::MIN_RELAY, 200);
+
+        let target = Amount::from_sat(50_000);
+        let selection = largest_first(&utxos, target, &params)?;
+        check(&selection, target);
+        assert_eq!(vouts(&selection), [1, 2]);
+        assert_eq!(selection.change, Some(Amount::from_sat(20_000 - 50 - 31)));
+        assert_eq!(selection.fee, Amount::from_sat(2 * 69 + 50 + 31));
+
+        // dust change goes to the fee
+        let target = Amount::from_sat(69_800);
+        let selection = largest_first(&utxos, target, &params)?;
+        assert_eq!(selection.change, None);
+        assert_eq!(selection.fee, Amount::from_sat(2 * 69 + 200));
+
+        let params = params.with_dust_limit(Amount::from_sat(100));
+        let selection = largest_first(&utxos, target, &params)?;
+        assert_eq!(selection.change, Some(Amount::from_sat(200 - 50 - 31)));
+
+        assert!(largest_first(&utxos, Amount::from_sat(71_000), &params).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn amounts_out_of_range() {
+        let params = SelectionParams::new(FeeRate::MIN_RELAY, 200);
+        let target = Amount::from_sat(1_000);
+        let overflowing = [utxo(0, u64::MAX / 2 + 1), utxo(1, u64::MAX / 2 + 1)];
+        let too_much = [utxo(0, Amount::MAX_MONEY.to_sat() + 1)];
+
+        for utxos in [&overflowing[..], &too_much[..]].iter() {
+            assert!(matches!(
+                branch_and_bound(utxos, target, &params),
+                Err(Error::InvalidAmount(_))
+            ));
+            assert!(matches!(
+                knapsack(utxos, target, &params),
+                Err(Error::InvalidAmount(_))
+            ));
+            assert!(matches!(
+                largest_first(utxos, target, &params),
+                Err(Error::InvalidAmount(_))
+            ));
+        }
+    }
+}
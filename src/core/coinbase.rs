@@ -0,0 +1,302 @@
+//! Coinbase transactions, the first of every block, claiming the subsidy and fees
+
+use std::convert::TryFrom;
+
+use crate::amount::Amount;
+use crate::utils::hash256;
+use crate::{Error, Result};
+
+use super::input::{OutPoint, TxIn};
+use super::merkle::merkle_root;
+use super::output::TxOut;
+use super::script::{push_int, push_slice, Script};
+use super::tx::Transaction;
+use super::txid::Wtxid;
+use super::witness::Witness;
+
+/// Blocks between each halving of the subsidy
+const HALVING_INTERVAL: u32 = 210_000;
+/// Script sig length limits of a coinbase
+pub(crate) const MIN_SCRIPT_SIG_SIZE: usize = 2;
+pub(crate) const MAX_SCRIPT_SIG_SIZE: usize = 100;
+/// `OP_RETURN OP_PUSHBYTES_36` followed by the BIP141 commitment header
+const WITNESS_COMMITMENT_HEADER: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+
+/// Subsidy of the blocks before the first halving
+const INITIAL_SUBSIDY: u64 = 50 * Amount::ONE_BTC.to_sat();
+
+/// New coins created by the block at `height` on mainnet, halving every 210000
+/// blocks
+pub fn block_subsidy(height: u32) -> Amount {
+    subsidy_at_height(height, HALVING_INTERVAL)
+}
+
+/// New coins created by the block at `height` when the subsidy halves every
+/// `halving_interval` blocks
+pub fn subsidy_at_height(height: u32, halving_interval: u32) -> Amount {
+    match height / halving_interval {
+        halvings if halvings >= 64 => Amount::ZERO,
+        halvings => Amount::from_sat(INITIAL_SUBSIDY >> halvings),
+    }
+}
+
+/// Coins created by the blocks up to and including the one at `height`, the
+/// unspendable genesis coinbase included
+pub fn supply_at_height(height: u32, halving_interval: u32) -> Amount {
+    let blocks = u64::from(height) + 1;
+    let interval = u64::from(halving_interval);
+
+    let mut supply = 0;
+    for halvings in 0..64 {
+        let start = halvings * interval;
+        if start >= blocks {
+            break;
+        }
+
+        supply += (blocks - start).min(interval) * (INITIAL_SUBSIDY >> halvings);
+    }
+
+    Amount::from_sat(supply)
+}
+
+/// BIP141 commitment to the `wtxids` of every transaction but the coinbase and
+/// the `reserved_value` in the coinbase witness
+pub fn witness_commitment(wtxids: &[Wtxid], reserved_value: &[u8; 32]) -> [u8; 32] {
+    // the coinbase wtxid counts as zeros
+    let mut leaves = vec![[0; 32]];
+    leaves.extend(wtxids.iter().map(|wtxid| wtxid.to_bytes()));
+
+    let mut data = merkle_root(&leaves).to_vec();
+    data.extend_from_slice(reserved_value);
+    to_array(&hash256(data))
+}
+
+/// The zero value output carrying a witness commitment
+pub fn witness_commitment_output(commitment: &[u8; 32]) -> TxOut {
+    let mut script = WITNESS_COMMITMENT_HEADER.to_vec();
+    script.extend_from_slice(commitment);
+    TxOut::new(Amount::ZERO, Script::from(script))
+}
+
+impl Transaction {
+    /// Coinbase of the block at `height`, its script sig is the BIP34 height
+    /// followed by `extra_nonce`. With a `witness_commitment` the reserved value
+    /// (zeros) becomes the witness and the commitment the last output
+    pub fn new_coinbase(
+        height: u32,
+        extra_nonce: &[u8],
+        mut outputs: Vec<TxOut>,
+        witness_commitment: Option<&[u8; 32]>,
+    ) -> Result<Self> {
+        let mut script_sig = Vec::new();
+        push_int(&mut script_sig, i64::from(height));
+        if !extra_nonce.is_empty() {
+            push_slice(&mut script_sig, extra_nonce);
+        }
+
+        // heights up to 16 are a single opcode, too short on their own
+        if script_sig.len() < MIN_SCRIPT_SIG_SIZE {
+            script_sig.push(0x00);
+        }
+
+        if script_sig.len() > MAX_SCRIPT_SIG_SIZE {
+            return Err(Error::InvalidTransaction("coinbase script sig too long"));
+        }
+
+        let mut input = TxIn::new(OutPoint::null());
+        input.script_sig = Script::from(script_sig);
+
+        if let Some(commitment) = witness_commitment {
+            input.witness = Witness::from(vec![vec![0; 32]]);
+            outputs.push(witness_commitment_output(commitment));
+        }
+
+        Ok(Transaction::new(2, vec![input], outputs, 0))
+    }
+
+    /// A single input spending the null outpoint
+    pub fn is_coinbase(&self) -> bool {
+        self.inputs.len() == 1 && self.inputs[0].previous_output.is_null()
+    }
+
+    /// Height pushed first in the script sig of a coinbase (BIP34), only if
+    /// pushed minimally as Bitcoin Core requires
+    pub fn coinbase_height(&self) -> Option<u32> {
+        if !self.is_coinbase() {
+            return None;
+        }
+
+        let script_sig = self.inputs[0].script_sig.bytes.as_slice();
+        let height = match script_sig {
+            [0x00, ..] => Some(0),
+            [opcode @ 0x51..=0x60, ..] => Some(u32::from(opcode - 0x50)),
+            [len @ 1..=4, rest @ ..] => {
+                let bytes = rest.get(..*len as usize)?;
+                // negative numbers aren't heights
+                if bytes.last()? & 0x80 != 0 {
+                    return None;
+                }
+
+                let height = bytes
+                    .iter()
+                    .rev()
+                    .fold(0u64, |height, byte| height << 8 | u64::from(*byte));
+                u32::try_from(height).ok()
+            }
+            _ => None,
+        }?;
+
+        let mut expected = Vec::new();
+        push_int(&mut expected, i64::from(height));
+        match script_sig.starts_with(&expected) {
+            true => Some(height),
+            false => None,
+        }
+    }
+
+    /// The commitment in the last output carrying one, if any
+    pub fn witness_commitment(&self) -> Option<[u8; 32]> {
+        let index = self.witness_commitment_index()?;
+        let script = &self.outputs[index].script_pubkey.bytes;
+        Some(to_array(&script[WITNESS_COMMITMENT_HEADER.len()..][..32]))
+    }
+
+    /// Index of the last output carrying a witness commitment, what comes after
+    /// the commitment in its script is free for other uses
+    pub fn witness_commitment_index(&self) -> Option<usize> {
+        self.outputs.iter().rposition(|output| {
+            let script = &output.script_pubkey.bytes;
+            script.len() >= WITNESS_COMMITMENT_HEADER.len() + 32
+                && script.starts_with(&WITNESS_COMMITMENT_HEADER)
+        })
+    }
+}
+
+fn to_array(bytes: &[u8]) -> [u8; 32] {
+    let mut array = [0; 32];
+    array.copy_from_slice(bytes);
+    array
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use hex_literal::hex;
+
+    use super::*;
+
+    #[test]
+    fn subsidy_schedule() {
+        assert_eq!(block_subsidy(0), Amount::from_sat(5_000_000_000));
+        assert_eq!(block_subsidy(209_999), Amount::from_sat(5_000_000_000));
+        assert_eq!(block_subsidy(210_000), Amount::from_sat(2_500_000_000));
+        assert_eq!(block_subsidy(840_000), Amount::from_sat(312_500_000));
+        assert_eq!(block_subsidy(HALVING_INTERVAL * 33), Amount::ZERO);
+        assert_eq!(block_subsidy(u32::MAX), Amount::ZERO);
+
+        // regtest halves every 150 blocks
+        assert_eq!(subsidy_at_height(149, 150), Amount::from_sat(5_000_000_000));
+        assert_eq!(subsidy_at_height(150, 150), Amount::from_sat(2_500_000_000));
+    }
+
+    #[test]
+    fn supply_schedule() {
+        assert_eq!(
+            supply_at_height(0, HALVING_INTERVAL),
+            Amount::from_sat(5_000_000_000)
+        );
+        assert_eq!(
+            supply_at_height(209_999, HALVING_INTERVAL),
+            Amount::from_sat(210_000 * 5_000_000_000)
+        );
+        assert_eq!(
+            supply_at_height(210_000, HALVING_INTERVAL),
+            Amount::from_sat(210_000 * 5_000_000_000 + 2_500_000_000)
+        );
+
+        // the sum of every subsidy, a bit under 21 million
+        let max = supply_at_height(u32::MAX, HALVING_INTERVAL);
+        assert_eq!(max, Amount::from_sat(2_099_999_997_690_000));
+        assert_eq!(
+            supply_at_height(HALVING_INTERVAL * 33, HALVING_INTERVAL),
+            max
+        );
+        let summed = (0..=1_000)
+            .map(|height| subsidy_at_height(height, 150).to_sat())
+            .sum();
+        assert_eq!(supply_at_height(1_000, 150), Amount::from_sat(summed));
+    }
+
+    #[test]
+    fn bip34_heights() -> Result<()> {
+        let output = TxOut::new(block_subsidy(227_836), Script::from(vec![0x51]));
+        let coinbase = Transaction::new_coinbase(227_836, b"extra", vec![output], None)?;
+        assert!(coinbase.is_coinbase());
+        assert!(coinbase.inputs[0].witness.is_empty());
+
+        // the first block enforcing BIP34 pushes 0x0379fc
+        let script_sig = &coinbase.inputs[0].script_sig.bytes;
+        assert_eq!(script_sig[..4], hex!("03fc7903"));
+        assert_eq!(&script_sig[5..], b"extra");
+        assert_eq!(coinbase.coinbase_height(), Some(227_836));
+
+        for height in [0, 1, 16, 17, 127, 128, 255, 256, 32_768, 8_388_608].iter() {
+            let coinbase = Transaction::new_coinbase(*height, &[], vec![], None)?;
+            let script_sig = &coinbase.inputs[0].script_sig.bytes;
+            assert!(script_sig.len() >= MIN_SCRIPT_SIG_SIZE);
+            assert_eq!(coinbase.coinbase_height(), Some(*height), "{}", height);
+        }
+
+        let coinbase = Transaction::new_coinbase(128, &[], vec![], None)?;
+        assert_eq!(coinbase.inputs[0].script_sig.bytes, hex!("028000"));
+
+        // pushes Bitcoin Core doesn't take as the height
+        for script_sig in [
+            &hex!("0105")[..],
+            &hex!("0110")[..],
+            &hex!("03800000")[..],
+            &hex!("04fc790300")[..],
+            &hex!("4c03fc7903")[..],
+        ]
+        .iter()
+        {
+            let mut coinbase = coinbase.clone();
+            coinbase.inputs[0].script_sig = Script::from(script_sig.to_vec());
+            assert_eq!(coinbase.coinbase_height(), None, "{:x?}", script_sig);
+        }
+
+        assert!(Transaction::new_coinbase(1, &[0; 99], vec![], None).is_err());
+        let spending = Transaction::new(
+            2,
+            vec![TxIn::new(OutPoint::new(Default::default(), 0))],
+            vec![],
+            0,
+        );
+        assert_eq!(spending.coinbase_height(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn witness_commitments() -> Result<()> {
+        // only the coinbase, the root is its zero wtxid
+        let commitment = witness_commitment(&[], &[0; 32]);
+        assert_eq!(commitment[..], hash256([0; 64])[..]);
+
+        let wtxids = [Wtxid::from_bytes([0x11; 32]), Wtxid::from_bytes([0x22; 32])];
+        let commitment = witness_commitment(&wtxids, &[0; 32]);
+        let left = hash256([[0; 32], [0x11; 32]].concat());
+        let right = hash256([[0x22; 32], [0x22; 32]].concat());
+        let root = hash256([left, right].concat());
+        assert_eq!(commitment[..], hash256([&root[..], &[0; 32]].concat())[..]);
+
+        let coinbase = Transaction::new_coinbase(800_000, &[], vec![], Some(&commitment))?;
+        assert_eq!(coinbase.inputs[0].witness[..], [vec![0; 32]]);
+        assert_eq!(coinbase.outputs.len(), 1);
+        assert_eq!(
+            coinbase.outputs[0].script_pubkey.bytes[..6],
+            WITNESS_COMMITMENT_HEADER
+        );
+        assert_eq!(coinbase.witness_commitment(), Some(commitment));
+        Ok(())
+    }
+}
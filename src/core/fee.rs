@@ -0,0 +1,280 @@
+use std::fmt::{self, Display, Formatter};
+
+use crate::amount::Amount;
+use crate::varint;
+use crate::{Error, Result};
+
+use super::script::Script;
+use super::tx::Transaction;
+use super::witness::Witness;
+
+/// DER signature of the highest size plus the sighash byte
+const MAX_ECDSA_SIGNATURE_SIZE: usize = 73;
+const COMPRESSED_KEY_SIZE: usize = 33;
+/// Schnorr signature with `SIGHASH_DEFAULT`
+const SCHNORR_SIGNATURE_SIZE: usize = 64;
+
+/// Fee rate in satoshis per virtual byte, kept per thousand vbytes so fractional
+/// rates like 1.5 sat/vB are representable
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FeeRate(u64);
+
+impl FeeRate {
+    pub const ZERO: FeeRate = FeeRate(0);
+    /// Default minimum relay fee rate of most nodes
+    pub const MIN_RELAY: FeeRate = FeeRate(1_000);
+    /// Default rate outputs are worth spending at, below it they're dust
+    pub const DUST_RELAY: FeeRate = FeeRate(3_000);
+
+    pub const fn from_sat_per_vb(sats: u64) -> Self {
+        FeeRate(sats * 1_000)
+    }
+
+    pub const fn from_sat_per_kvb(sats: u64) -> Self {
+        FeeRate(sats)
+    }
+
+    /// The rate paid by `fee` over `vsize` vbytes, rounding down
+    pub fn from_fee(fee: Amount, vsize: usize) -> Result<Self> {
+        if vsize == 0 {
+            return Err(Error::InvalidTransaction("zero vsize"));
+        }
+
+        fee.to_sat()
+            .checked_mul(1_000)
+            .map(|sats| FeeRate(sats / vsize as u64))
+            .ok_or(Error::InvalidAmount("amount overflow"))
+    }
+
+    pub const fn to_sat_per_kvb(self) -> u64 {
+        self.0
+    }
+
+    /// The fee for `vsize` vbytes at this rate, rounding up
+    pub fn fee_for_vsize(self, vsize: usize) -> Amount {
+        let sats = (self.0 as u128 * vsize as u128).div_ceil(1_000);
+        Amount::from_sat(sats as u64)
+    }
+
+    /// The fee for `weight` weight units at this rate, rounding up
+    pub fn fee_for_weight(self, weight: usize) -> Amount {
+        let sats = (self.0 as u128 * weight as u128).div_ceil(4_000);
+        Amount::from_sat(sats as u64)
+    }
+
+    pub fn fee_for(self, tx: &Transaction) -> Result<Amount> {
+        Ok(self.fee_for_vsize(tx.vsize()?))
+    }
+
+    /// What's missing from `paid` for `tx` to reach this rate, zero if it's already
+    /// paying enough
+    pub fn fee_to_reach(self, tx: &Transaction, paid: Amount) -> Result<Amount> {
+        Ok(self.fee_for(tx)?.checked_sub(paid).unwrap_or(Amount::ZERO))
+    }
+}
+
+/// Written in sat/vB with up to 3 decimals
+impl Display for FeeRate {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        let (whole, fraction) = (self.0 / 1_000, self.0 % 1_000);
+        if fraction == 0 {
+            return write!(fmt, "{} sat/vB", whole);
+        }
+
+        let fraction = format!("{:03}", fraction);
+        write!(fmt, "{}.{} sat/vB", whole, fraction.trim_end_matches('0'))
+    }
+}
+
+/// How the inputs of an unsigned transaction are going to be spent, to estimate
+/// the size of the signed transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InputType {
+    /// Compressed public key
+    P2pkh,
+    P2shP2wpkh,
+    P2wpkh,
+    /// Key path with `SIGHASH_DEFAULT`
+    P2trKeySpend,
+}
+
+impl InputType {
+    /// Weight of a signed input of this type, the segwit marker and flag aside
+    pub fn input_weight(self) -> usize {
+        let (script_sig, witness) = self.dummy_satisfaction();
+        let script_len = script_sig.bytes.len();
+        let base = 36 + varint::encoded_len(script_len as u64) + script_len + 4;
+
+        let witness = match witness.is_empty() {
+            true => 0,
+            false => witness
+                .iter()
+                .fold(varint::encoded_len(witness.len() as u64), |len, item| {
+                    len + varint::encoded_len(item.len() as u64) + item.len()
+                }),
+        };
+
+        base * 4 + witness
+    }
+
+    /// Script sig and witness of the same size as the final ones, in the worst case
+    fn dummy_satisfaction(self) -> (Script, Vec<Vec<u8>>) {
+        let signature = vec![0; MAX_ECDSA_SIGNATURE_SIZE];
+        let key = vec![0; COMPRESSED_KEY_SIZE];
+
+        match self {
+            InputType::P2pkh => {
+                let mut script_sig = vec![signature.len() as u8];
+                script_sig.extend(&signature);
+                script_sig.push(key.len() as u8);
+                script_sig.extend(&key);
+                (Script::from(script_sig), vec![])
+            }
+
+            InputType::P2shP2wpkh => {
+                // push of the `OP_0 <20 bytes>` redeem script
+                let mut script_sig = vec![22, 0x00, 20];
+                script_sig.extend(&[0; 20]);
+                (Script::from(script_sig), vec![signature, key])
+            }
+
+            InputType::P2wpkh => (Script::new(), vec![signature, key]),
+            InputType::P2trKeySpend => (Script::new(), vec![vec![0; SCHNORR_SIGNATURE_SIZE]]),
+        }
+    }
+}
+
+impl Transaction {
+    /// Weight once every input is signed as given by `input_types`, one per input
+    pub fn estimated_weight(&self, input_types: &[InputType]) -> Result<usize> {
+        if input_types.len() != self.inputs.len() {
+            return Err(Error::InvalidTransaction("one input type per input needed"));
+        }
+
+        let mut signed = self.clone();
+        for (input, input_type) in signed.inputs.iter_mut().zip(input_types) {
+            let (script_sig, witness) = input_type.dummy_satisfaction();
+            input.script_sig = script_sig;
+            input.witness = Witness::from(witness);
+        }
+
+        signed.weight()
+    }
+
+    /// Virtual size once every input is signed as given by `input_types`
+    pub fn estimated_vsize(&self, input_types: &[InputType]) -> Result<usize> {
+        Ok(self.estimated_weight(input_types)?.div_ceil(4))
+    }
+
+    /// The rate paid by `fee`, only meaningful once the transaction is signed
+    pub fn fee_rate(&self, fee: Amount) -> Result<FeeRate> {
+        FeeRate::from_fee(fee, self.vsize()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use hex_literal::hex;
+
+    use crate::consensus;
+    use crate::core::input::{OutPoint, TxIn};
+    use crate::core::output::TxOut;
+    use crate::core::txid::Txid;
+    use crate::secp256k1::crypto::PrivateKey;
+    use crate::utils::hash160;
+
+    use super::*;
+
+    #[test]
+    fn fee_rates() -> Result<()> {
+        let rate = FeeRate::from_sat_per_vb(2);
+        assert_eq!(rate.fee_for_vsize(141), Amount::from_sat(282));
+        assert_eq!(rate.to_string(), "2 sat/vB");
+
+        let rate = FeeRate::from_sat_per_kvb(1_500);
+        assert_eq!(rate.fee_for_vsize(141), Amount::from_sat(212));
+        assert_eq!(rate.to_string(), "1.5 sat/vB");
+        assert_eq!(FeeRate::from_sat_per_kvb(1_010).to_string(), "1.01 sat/vB");
+
+        assert_eq!(
+            FeeRate::from_fee(Amount::from_sat(282), 141)?,
+            FeeRate::from_sat_per_vb(2)
+        );
+        assert_eq!(
+            FeeRate::from_fee(Amount::from_sat(100), 3)?.to_sat_per_kvb(),
+            33_333
+        );
+        assert!(FeeRate::from_fee(Amount::ONE_SAT, 0).is_err());
+        assert!(FeeRate::MIN_RELAY > FeeRate::ZERO);
+
+        // BIP143 P2WPKH example, 261 vbytes
+        let raw = hex!("01000000000102fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f00000000494830450221008b9d1dc26ba6a9cb62127b02742fa9d754cd3bebf337f7a55d114c8e5cdd30be022040529b194ba3f9281a99f2b1c0a19c0489bc22ede944ccf4ecbab4cc618ef3ed01eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac000247304402203609e17b84f6a7d30c80bfa610b5b4542f32a8a0d5447a12fb1366d7f01cc44a0220573a954c4518331561406f90300e8f3358f51928d43c212a8caed02de67eebee0121025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee635711000000");
+        let tx: Transaction = consensus::deserialize(&raw)?;
+
+        let rate = FeeRate::from_sat_per_vb(10);
+        assert_eq!(rate.fee_for(&tx)?, Amount::from_sat(2_610));
+        assert_eq!(
+            rate.fee_to_reach(&tx, Amount::from_sat(2_000))?,
+            Amount::from_sat(610)
+        );
+        assert_eq!(
+            rate.fee_to_reach(&tx, Amount::from_sat(3_000))?,
+            Amount::ZERO
+        );
+        assert_eq!(tx.fee_rate(Amount::from_sat(2_610))?, rate);
+        assert_eq!(rate.fee_for_weight(1042), Amount::from_sat(2_605));
+        Ok(())
+    }
+
+    #[test]
+    fn estimated_sizes() -> Result<()> {
+        let key = PrivateKey::new(8675309u32);
+        let sec = key.public_key().serialize_compressed()?;
+
+        let inputs = vec![
+            TxIn::new(OutPoint::new(Txid::from_bytes([0x11; 32]), 0)),
+            TxIn::new(OutPoint::new(Txid::from_bytes([0x22; 32]), 1)),
+        ];
+        let output = TxOut::new(Amount::from_sat(90_000), Script::from(vec![0x51]));
+        let mut tx = Transaction::new(2, inputs, vec![output], 0);
+
+        // an estimate is never below the real size
+        let types = [InputType::P2pkh, InputType::P2wpkh];
+        let estimate = tx.estimated_weight(&types)?;
+
+        let mut p2pkh = vec![0x76, 0xa9, 0x14];
+        p2pkh.extend(hash160(sec));
+        p2pkh.extend(&[0x88, 0xac]);
+        let mut p2wpkh = vec![0x00, 0x14];
+        p2wpkh.extend(hash160(sec));
+
+        let prev_output = TxOut::new(Amount::from_sat(50_000), Script::from(p2wpkh));
+        tx.sign_p2pkh_input(0, &key, &Script::from(p2pkh))?;
+        tx.sign_p2wpkh_input(1, &key, &prev_output)?;
+        let weight = tx.weight()?;
+        // at most two bytes less per signature, which are four times heavier outside
+        // of the witness
+        assert!(estimate >= weight && estimate - weight <= 2 * 4 + 2);
+
+        // one input and one 34 bytes script output, 94 bytes without the witness
+        let output = TxOut::new(Amount::from_sat(1_000), Script::from(vec![0x51; 34]));
+        let tx = Transaction::new(
+            2,
+            vec![TxIn::new(OutPoint::new(Txid::from_bytes([0; 32]), 0))],
+            vec![output],
+            0,
+        );
+        assert_eq!(tx.estimated_vsize(&[InputType::P2trKeySpend])?, 111);
+        assert_eq!(tx.estimated_vsize(&[InputType::P2wpkh])?, 122);
+        assert_eq!(tx.estimated_vsize(&[InputType::P2shP2wpkh])?, 145);
+        assert_eq!(tx.estimated_vsize(&[InputType::P2pkh])?, 94 + 108);
+        assert!(tx.estimated_weight(&[]).is_err());
+
+        // the input alone, 57.5 vbytes for key path spends
+        assert_eq!(InputType::P2trKeySpend.input_weight(), 230);
+        assert_eq!(InputType::P2wpkh.input_weight(), 41 * 4 + 109);
+        assert_eq!(InputType::P2pkh.input_weight(), 149 * 4);
+        Ok(())
+    }
+}
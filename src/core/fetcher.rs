@@ -1,14 +1,12 @@
-use std::io::Cursor;
-
-use byteorder::{LittleEndian, ReadBytesExt};
-use bytes::{Buf, BytesMut};
+use bytes::BytesMut;
 use dashmap::DashMap;
 use hyper::body::HttpBody;
 use hyper::client::connect::HttpConnector;
 use hyper::{Client, Uri};
 use lazy_static::lazy_static;
 
-use crate::core::tx::Tx;
+use crate::core::tx::Transaction;
+use crate::network::Network;
 use crate::utils::default;
 use crate::{Error, Result};
 
@@ -18,7 +16,7 @@ lazy_static! {
 
 #[derive(Debug)]
 pub struct TxFetcher {
-    cache: DashMap<String, Tx>,
+    cache: DashMap<String, Transaction>,
     client: Client<HttpConnector>,
 }
 
@@ -30,17 +28,17 @@ impl TxFetcher {
         }
     }
 
-    const fn get_url(testnet: bool) -> &'static str {
-        if testnet {
-            "http://testnet.programmingbitcoin.com"
-        } else {
-            "http://mainnet.programmingbitcoin.com"
+    /// The test networks are all served by the testnet explorer
+    const fn get_url(network: Network) -> &'static str {
+        match network {
+            Network::Mainnet => "http://mainnet.programmingbitcoin.com",
+            _ => "http://testnet.programmingbitcoin.com",
         }
     }
 
-    pub async fn fetch(&self, tx_id: &str, testnet: bool, fresh: bool) -> Result<Tx> {
+    pub async fn fetch(&self, tx_id: &str, network: Network, fresh: bool) -> Result<Transaction> {
         if fresh || !self.cache.contains_key(tx_id) {
-            let url = format!("{}/tx/{}.hex", Self::get_url(testnet), hex::encode(tx_id));
+            let url = format!("{}/tx/{}.hex", Self::get_url(network), hex::encode(tx_id));
             let uri: Uri = url.parse().unwrap();
 
             let mut response = self.client.get(uri).await?;
@@ -50,16 +48,7 @@ impl TxFetcher {
                 bytes.extend_from_slice(&chunk?);
             }
 
-            let tx = if bytes[4] == 0x0 {
-                let chain = bytes[..4].chain(&bytes[6..]);
-                let mut tx = Tx::deserialize(chain, testnet)?;
-                let mut last_four = Cursor::new(&bytes[(bytes.len() - 4)..]);
-                tx.locktime = last_four.read_u64::<LittleEndian>()?;
-
-                tx
-            } else {
-                Tx::deserialize(bytes, testnet)?
-            };
+            let tx = Transaction::deserialize(bytes)?;
 
             if tx.id()? != tx_id {
                 return Err(Error::FetchedInvalidTransaction);
@@ -68,7 +57,6 @@ impl TxFetcher {
             self.cache.insert(tx_id.to_string(), tx);
         }
 
-        self.cache.get_mut(tx_id).unwrap().testnet = testnet;
         return Ok(self.cache.get(tx_id).unwrap().value().clone());
     }
 }
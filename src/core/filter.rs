@@ -0,0 +1,471 @@
+//! Compact block filters (BIP158), Golomb-Rice coded sets of the scripts a
+//! block touches that clients download and match against their own (BIP157)
+
+use std::collections::BTreeSet;
+use std::io::{Read, Write};
+
+use crate::consensus::{self, write_compact_size, Decodable, Encodable};
+use crate::utils::hash256;
+use crate::varint;
+use crate::{Error, Result};
+
+use super::block::Block;
+use super::input::OutPoint;
+use super::script::Script;
+use super::txid::BlockHash;
+
+/// Bits of the remainder of each delta in basic filters
+const BASIC_FILTER_P: u8 = 19;
+/// Inverse of the false positive rate of basic filters
+const BASIC_FILTER_M: u64 = 784_931;
+/// `OP_RETURN` outputs can't be spent, they're left out
+const OP_RETURN: u8 = 0x6a;
+
+/// A basic block filter, the element count followed by the coded set
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct BlockFilter {
+    pub(crate) content: Vec<u8>,
+}
+
+impl BlockFilter {
+    /// Filter of the output scripts created and spent by `block`, `spent_script`
+    /// gives the script of the outputs its inputs spend
+    pub fn new_basic(
+        block: &Block,
+        mut spent_script: impl FnMut(&OutPoint) -> Option<Script>,
+    ) -> Result<Self> {
+        let mut elements = BTreeSet::new();
+        for tx in block.transactions() {
+            for output in tx.outputs() {
+                let script = output.script_pubkey().as_bytes();
+                if !script.is_empty() && script[0] != OP_RETURN {
+                    elements.insert(script.to_vec());
+                }
+            }
+
+            if tx.is_coinbase() {
+                continue;
+            }
+
+            for input in tx.inputs() {
+                let script = spent_script(input.previous_output())
+                    .ok_or(Error::InvalidBlock("missing spent output"))?;
+                if !script.is_empty() {
+                    elements.insert(script.bytes);
+                }
+            }
+        }
+
+        let key = siphash_key(&block.block_hash());
+        Ok(Self {
+            content: encode_set(&elements, &key),
+        })
+    }
+
+    /// From the content of a `cfilter` message
+    pub fn from_content(content: Vec<u8>) -> Self {
+        Self { content }
+    }
+
+    pub fn content(&self) -> &[u8] {
+        &self.content
+    }
+
+    /// Double SHA256 of the content
+    pub fn filter_hash(&self) -> [u8; 32] {
+        to_array(&hash256(&self.content))
+    }
+
+    /// Header chaining this filter to the one of the previous block, zeros
+    /// before the genesis block
+    pub fn filter_header(&self, prev_header: &[u8; 32]) -> [u8; 32] {
+        to_array(&hash256(
+            [&self.filter_hash()[..], &prev_header[..]].concat(),
+        ))
+    }
+
+    /// Whether any of `scripts` is in the filter of the block `block_hash`, false
+    /// positives happen once every 784931 scripts
+    pub fn match_any(&self, block_hash: &BlockHash, scripts: &[&[u8]]) -> Result<bool> {
+        let mut reader = BitReader::new(&self.content);
+        let n = consensus::read_compact_size(&mut reader.bytes)?;
+        if n == 0 || scripts.is_empty() {
+            return Ok(false);
+        }
+
+        // every element takes at least the remainder bits and a zero
+        let bits = reader.bytes.len() as u64 * 8;
+        if n > u64::from(u32::MAX) || n > bits / (u64::from(BASIC_FILTER_P) + 1) {
+            return Err(Error::InvalidBlock("block filter element count too large"));
+        }
+
+        let key = siphash_key(block_hash);
+        let range = n
+            .checked_mul(BASIC_FILTER_M)
+            .ok_or(Error::InvalidBlock("block filter element count too large"))?;
+        let mut queries: Vec<_> = scripts
+            .iter()
+            .map(|script| hash_to_range(&key, script, range))
+            .collect();
+        queries.sort_unstable();
+
+        // both sorted, walk them together
+        let mut value = 0;
+        let mut queries = queries.into_iter().peekable();
+        for _ in 0..n {
+            value = reader
+                .read_golomb(BASIC_FILTER_P)?
+                .checked_add(value)
+                .ok_or(Error::InvalidBlock("block filter value overflow"))?;
+            while let Some(query) = queries.peek() {
+                match query.cmp(&value) {
+                    std::cmp::Ordering::Less => {
+                        queries.next();
+                    }
+                    std::cmp::Ordering::Equal => return Ok(true),
+                    std::cmp::Ordering::Greater => break,
+                }
+            }
+
+            if queries.peek().is_none() {
+                break;
+            }
+        }
+
+        Ok(false)
+    }
+}
+
+/// Length prefixed content, the filter of a `cfilter` message
+impl Encodable for BlockFilter {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        self.content.consensus_encode(writer)
+    }
+}
+
+impl Decodable for BlockFilter {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        Ok(Self {
+            content: Vec::consensus_decode(reader)?,
+        })
+    }
+}
+
+/// The first 16 bytes of the block hash, as two little endian numbers
+fn siphash_key(block_hash: &BlockHash) -> (u64, u64) {
+    let bytes = block_hash.to_bytes();
+    let mut k0 = [0; 8];
+    let mut k1 = [0; 8];
+    k0.copy_from_slice(&bytes[..8]);
+    k1.copy_from_slice(&bytes[8..16]);
+    (u64::from_le_bytes(k0), u64::from_le_bytes(k1))
+}
+
+/// SipHash of `element` mapped uniformly to `[0, range)`
+fn hash_to_range(key: &(u64, u64), element: &[u8], range: u64) -> u64 {
+    ((u128::from(siphash24(key.0, key.1, element)) * u128::from(range)) >> 64) as u64
+}
+
+/// Element count and the sorted hashes, each delta Golomb-Rice coded
+fn encode_set(elements: &BTreeSet<Vec<u8>>, key: &(u64, u64)) -> Vec<u8> {
+    let n = elements.len() as u64;
+    let mut content = Vec::with_capacity(varint::encoded_len(n));
+    write_compact_size(&mut content, n).expect("writes to a vector don't fail");
+
+    let range = n * BASIC_FILTER_M;
+    let mut hashes: Vec<_> = elements
+        .iter()
+        .map(|element| hash_to_range(key, element, range))
+        .collect();
+    hashes.sort_unstable();
+
+    let mut writer = BitWriter::new(content);
+    let mut last = 0;
+    for hash in hashes {
+        writer.write_golomb(hash - last, BASIC_FILTER_P);
+        last = hash;
+    }
+
+    writer.finish()
+}
+
+/// Bits most significant first, the last byte padded with zeros
+struct BitWriter {
+    bytes: Vec<u8>,
+    used: u8,
+}
+
+impl BitWriter {
+    fn new(bytes: Vec<u8>) -> Self {
+        // a full byte has no room left
+        Self { bytes, used: 8 }
+    }
+
+    fn write_bits(&mut self, value: u64, count: u8) {
+        for i in (0..count).rev() {
+            if self.used == 8 {
+                self.bytes.push(0);
+                self.used = 0;
+            }
+
+            let bit = ((value >> i) & 1) as u8;
+            *self.bytes.last_mut().unwrap() |= bit << (7 - self.used); // safe, pushed above
+            self.used += 1;
+        }
+    }
+
+    /// The quotient in unary, ones ended by a zero, then `p` bits of remainder
+    fn write_golomb(&mut self, value: u64, p: u8) {
+        for _ in 0..value >> p {
+            self.write_bits(1, 1);
+        }
+
+        self.write_bits(0, 1);
+        self.write_bits(value, p);
+    }
+
+    fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    /// Bits of the first byte already read
+    used: u8,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, used: 0 }
+    }
+
+    fn read_bits(&mut self, count: u8) -> Result<u64> {
+        let mut value = 0;
+        for _ in 0..count {
+            let byte = *self
+                .bytes
+                .first()
+                .ok_or(Error::InvalidBlock("truncated block filter"))?;
+            value = value << 1 | u64::from((byte >> (7 - self.used)) & 1);
+
+            self.used += 1;
+            if self.used == 8 {
+                self.bytes = &self.bytes[1..];
+                self.used = 0;
+            }
+        }
+
+        Ok(value)
+    }
+
+    fn read_golomb(&mut self, p: u8) -> Result<u64> {
+        let mut quotient: u64 = 0;
+        while self.read_bits(1)? == 1 {
+            quotient += 1;
+        }
+
+        // the shifted out bits would be lost
+        if quotient.leading_zeros() < u32::from(p) {
+            return Err(Error::InvalidBlock("block filter value overflow"));
+        }
+
+        Ok(quotient << p | self.read_bits(p)?)
+    }
+}
+
+/// SipHash-2-4 of `data` with the key `(k0, k1)`
+pub(crate) fn siphash24(k0: u64, k1: u64, data: &[u8]) -> u64 {
+    let mut v = [
+        k0 ^ 0x736f_6d65_7073_6575,
+        k1 ^ 0x646f_7261_6e64_6f6d,
+        k0 ^ 0x6c79_6765_6e65_7261,
+        k1 ^ 0x7465_6462_7974_6573,
+    ];
+
+    let round = |v: &mut [u64; 4]| {
+        v[0] = v[0].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(13) ^ v[0];
+        v[0] = v[0].rotate_left(32);
+        v[2] = v[2].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(16) ^ v[2];
+        v[0] = v[0].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(21) ^ v[0];
+        v[2] = v[2].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(17) ^ v[2];
+        v[2] = v[2].rotate_left(32);
+    };
+
+    let compress = |v: &mut [u64; 4], m: u64| {
+        v[3] ^= m;
+        round(v);
+        round(v);
+        v[0] ^= m;
+    };
+
+    let blocks = data.chunks_exact(8);
+    let tail = blocks.remainder();
+    for block in blocks {
+        let mut word = [0; 8];
+        word.copy_from_slice(block);
+        compress(&mut v, u64::from_le_bytes(word));
+    }
+
+    // the tail with the length in the top byte
+    let last = tail
+        .iter()
+        .rev()
+        .fold(0u64, |word, byte| word << 8 | u64::from(*byte));
+    compress(&mut v, last | (data.len() as u64) << 56);
+
+    v[2] ^= 0xff;
+    for _ in 0..4 {
+        round(&mut v);
+    }
+
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+fn to_array(bytes: &[u8]) -> [u8; 32] {
+    let mut array = [0; 32];
+    array.copy_from_slice(bytes);
+    array
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use hex_literal::hex;
+
+    use crate::amount::Amount;
+    use crate::core::block::BlockHeader;
+    use crate::core::input::TxIn;
+    use crate::core::output::TxOut;
+    use crate::core::tx::Transaction;
+    use crate::core::txid::Txid;
+
+    use super::*;
+
+    #[test]
+    fn siphash_vectors() {
+        // reference implementation, key 00..0f
+        let (k0, k1) = (0x0706_0504_0302_0100, 0x0f0e_0d0c_0b0a_0908);
+        assert_eq!(siphash24(k0, k1, &[]), 0x726f_db47_dd0e_0e31);
+        let data: Vec<u8> = (0..15).collect();
+        assert_eq!(siphash24(k0, k1, &data), 0xa129_ca61_49be_45e5);
+    }
+
+    #[test]
+    fn golomb_coding() -> Result<()> {
+        let mut writer = BitWriter::new(Vec::new());
+        for value in [0, 1, 1 << 19, 3 << 19 | 5, 12345678].iter() {
+            writer.write_golomb(*value, BASIC_FILTER_P);
+        }
+        let bytes = writer.finish();
+
+        let mut reader = BitReader::new(&bytes);
+        for value in [0, 1, 1 << 19, 3 << 19 | 5, 12345678].iter() {
+            assert_eq!(reader.read_golomb(BASIC_FILTER_P)?, *value);
+        }
+        assert!(BitReader::new(&bytes[..3]).read_bits(25).is_err());
+        Ok(())
+    }
+
+    /// Testnet genesis, the first BIP158 test vector
+    fn testnet_genesis() -> Result<Block> {
+        let header = BlockHeader::from_bytes(&hex!("0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4adae5494dffff001d1aa4ae18"))?;
+        let coinbase = consensus::deserialize(&hex!("01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000"))?;
+        Ok(Block::new(header, vec![coinbase]))
+    }
+
+    #[test]
+    fn basic_filters() -> Result<()> {
+        let genesis = testnet_genesis()?;
+        assert_eq!(
+            genesis.block_hash().to_string(),
+            "000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943"
+        );
+
+        let filter = BlockFilter::new_basic(&genesis, |_| None)?;
+        assert_eq!(filter.content(), hex!("019dfca8"));
+
+        let mut header = filter.filter_header(&[0; 32]);
+        header.reverse();
+        assert_eq!(
+            header,
+            hex!("21584579b7eb08997773e5aeff3a7f932700042d0ed2a6129012b7d7ae81b750")
+        );
+
+        let bytes = consensus::serialize(&filter)?;
+        assert_eq!(bytes, hex!("04019dfca8"));
+        assert_eq!(consensus::deserialize::<BlockFilter>(&bytes)?, filter);
+
+        let script = genesis.transactions()[0].outputs()[0].script_pubkey();
+        let hash = genesis.block_hash();
+        assert!(filter.match_any(&hash, &[&[0x51], script.as_bytes()])?);
+        assert!(!filter.match_any(&hash, &[&[0x51]])?);
+        assert!(!filter.match_any(&hash, &[])?);
+        Ok(())
+    }
+
+    #[test]
+    fn spent_scripts() -> Result<()> {
+        let created: Vec<_> = (0..50u8).map(|i| Script::p2wpkh(&[i; 20])).collect();
+        let spent = Script::p2pkh(&[0xff; 20]);
+
+        let coinbase = Transaction::new_coinbase(1, &[], vec![], None)?;
+        let outputs = created
+            .iter()
+            .map(|script| TxOut::new(Amount::from_sat(1_000), script.clone()))
+            .chain(vec![
+                TxOut::op_return(b"left out")?,
+                TxOut::new(Amount::ZERO, Script::new()),
+            ])
+            .collect();
+        let input = TxIn::new(OutPoint::new(Txid::from_bytes([0x11; 32]), 0));
+        let tx = Transaction::new(2, vec![input], outputs, 0);
+
+        let header = BlockHeader::new(1, Default::default(), [0; 32], 0, 0, 0);
+        let block = Block::new(header, vec![coinbase, tx]);
+        let hash = block.block_hash();
+
+        assert!(BlockFilter::new_basic(&block, |_| None).is_err());
+        let filter = BlockFilter::new_basic(&block, |_| Some(spent.clone()))?;
+        assert_eq!(filter.content()[0], 51);
+
+        for script in created.iter().chain(Some(&spent)) {
+            assert!(filter.match_any(&hash, &[script.as_bytes()])?);
+        }
+        assert!(!filter.match_any(&hash, &[Script::op_return(b"left out").as_bytes()])?);
+
+        // the key is the block hash
+        let other = Default::default();
+        assert!(!filter.match_any(&other, &[created[0].as_bytes(), created[1].as_bytes()])?);
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_filters() {
+        let hash = Default::default();
+        let script: &[u8] = &[0x51];
+
+        // element counts that can't fit in the content
+        for content in [
+            &hex!("fffffffffffeffffffffff")[..],
+            &hex!("ffffffffffffffffffffffffffffffff")[..],
+            &hex!("fe00000001ffffffff")[..],
+            &hex!("02ff")[..],
+        ]
+        .iter()
+        {
+            let filter = BlockFilter::from_content(content.to_vec());
+            assert!(filter.match_any(&hash, &[script]).is_err());
+        }
+
+        // a unary quotient running off the end
+        let mut content = vec![0x05];
+        content.extend(vec![0xff; 4096]);
+        let filter = BlockFilter::from_content(content);
+        assert!(filter.match_any(&hash, &[script]).is_err());
+    }
+}
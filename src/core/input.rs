@@ -1,91 +1,148 @@
-use std::io::Read;
+use std::io::{Read, Write};
 
-use byteorder::{LittleEndian, ReadBytesExt};
-use bytes::{Buf, Bytes};
+use bytes::Buf;
 use derivative::Derivative;
 
-use crate::core::tx::Tx;
+use crate::amount::Amount;
+use crate::consensus::{self, Decodable, Encodable};
+use crate::core::tx::Transaction;
+use crate::network::Network;
 use crate::Result;
 
 use super::fetcher::TX_FETCHER;
+use super::locktime::Sequence;
+use super::output::TxOut;
 use super::script::Script;
+use super::txid::Txid;
+use super::witness::Witness;
+
+/// Reference to the output of a previous transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OutPoint {
+    pub(crate) txid: Txid,
+    pub(crate) vout: u32,
+}
+
+impl OutPoint {
+    pub fn new(txid: Txid, vout: u32) -> Self {
+        Self { txid, vout }
+    }
+
+    /// Previous output of coinbase inputs, which don't spend anything
+    pub fn null() -> Self {
+        Self::new(Txid::default(), u32::MAX)
+    }
 
-#[derive(Derivative, Clone)]
+    pub fn is_null(&self) -> bool {
+        *self == Self::null()
+    }
+
+    pub fn txid(&self) -> &Txid {
+        &self.txid
+    }
+
+    pub fn vout(&self) -> u32 {
+        self.vout
+    }
+}
+
+impl Encodable for OutPoint {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        Ok(self.txid.consensus_encode(writer)? + self.vout.consensus_encode(writer)?)
+    }
+}
+
+impl Decodable for OutPoint {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        Ok(Self {
+            txid: Txid::consensus_decode(reader)?,
+            vout: u32::consensus_decode(reader)?,
+        })
+    }
+}
+
+#[derive(Derivative, Clone, PartialEq, Eq)]
 #[derivative(Debug)]
-pub struct Input {
-    #[derivative(Debug(format_with = "crate::format::bytes::fmt"))]
-    pub(crate) prev_tx: Bytes, // size: 32 bytes
-    pub(crate) prev_idx: u32,
+pub struct TxIn {
+    pub(crate) previous_output: OutPoint,
     #[derivative(Debug = "ignore")]
     pub(crate) script_sig: Script, // size: variable
     #[derivative(Debug = "ignore")]
     pub(crate) sequence: u32,
+    /// Witness stack (BIP141), serialized by the transaction rather than the input
+    #[derivative(Debug = "ignore")]
+    pub(crate) witness: Witness,
 }
 
-impl Input {
-    const DEFAULT_SEQUENCE: u32 = 0xffffffff;
+impl TxIn {
+    pub const DEFAULT_SEQUENCE: u32 = 0xffffffff;
 
-    pub fn new<B>(prev_tx: B, prev_idx: u32) -> Result<Self>
-    where
-        B: AsRef<[u8]>,
-    {
-        let prev_tx = Bytes::copy_from_slice(prev_tx.as_ref());
-        let script_sig = Script::new();
-        let sequence = Self::DEFAULT_SEQUENCE;
+    pub fn new(previous_output: OutPoint) -> Self {
+        Self {
+            previous_output,
+            script_sig: Script::new(),
+            sequence: Self::DEFAULT_SEQUENCE,
+            witness: Witness::new(),
+        }
+    }
 
-        Ok(Self {
-            prev_tx,
-            prev_idx,
-            script_sig,
-            sequence,
-        })
+    pub fn previous_output(&self) -> &OutPoint {
+        &self.previous_output
     }
 
-    pub async fn fetch_tx(&self, testnet: bool) -> Result<Tx> {
-        let tx_id = hex::encode(&self.prev_tx);
-        TX_FETCHER.fetch(&tx_id, testnet, false).await
+    pub fn script_sig(&self) -> &Script {
+        &self.script_sig
     }
 
-    pub fn value(&self, tx: &Tx) -> u64 {
-        tx.outputs[self.prev_idx as usize].amount
+    pub fn sequence(&self) -> Sequence {
+        Sequence::from_consensus(self.sequence)
     }
 
-    pub fn script_pubkey<'a>(&self, tx: &'a Tx) -> &'a Script {
-        &tx.outputs[self.prev_idx as usize].script_pubkey
+    pub fn witness(&self) -> &Witness {
+        &self.witness
     }
 
-    pub fn serialize(&self) -> Result<Vec<u8>> {
-        let prev_tx_bytes = self.prev_tx.iter().copied().rev();
-        let prev_idx_bytes = self.prev_idx.to_le_bytes();
-        let script_sig_bytes = self.script_sig.serialize()?.into_iter();
-        let sequence_bytes = self.sequence.to_le_bytes();
+    pub async fn fetch_tx(&self, network: Network) -> Result<Transaction> {
+        let tx_id = self.previous_output.txid.to_string();
+        TX_FETCHER.fetch(&tx_id, network, false).await
+    }
 
-        let result = prev_tx_bytes
-            .chain(prev_idx_bytes.iter().copied())
-            .chain(script_sig_bytes)
-            .chain(sequence_bytes.iter().copied())
-            .collect();
+    pub fn value(&self, tx: &Transaction) -> Amount {
+        self.spent_output(tx).amount
+    }
 
-        Ok(result)
+    pub fn script_pubkey<'a>(&self, tx: &'a Transaction) -> &'a Script {
+        &self.spent_output(tx).script_pubkey
     }
 
-    pub fn deserialize(buf: impl Buf) -> Result<Self> {
-        let mut reader = buf.reader();
+    fn spent_output<'a>(&self, tx: &'a Transaction) -> &'a TxOut {
+        &tx.outputs[self.previous_output.vout as usize]
+    }
 
-        let mut prev_tx_bytes = [0u8; 32];
-        reader.read_exact(&mut prev_tx_bytes)?;
-        prev_tx_bytes.reverse();
-        let prev_tx = Bytes::copy_from_slice(&prev_tx_bytes[..]);
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        consensus::serialize(self)
+    }
 
-        let prev_idx = reader.read_u32::<LittleEndian>()?;
-        let script_sig = Script::deserialize(reader.get_mut())?;
-        let sequence = reader.read_u32::<LittleEndian>()?;
+    pub fn deserialize(buf: impl Buf) -> Result<Self> {
+        Self::consensus_decode(&mut buf.reader())
+    }
+}
+
+impl Encodable for TxIn {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        Ok(self.previous_output.consensus_encode(writer)?
+            + self.script_sig.consensus_encode(writer)?
+            + self.sequence.consensus_encode(writer)?)
+    }
+}
 
+impl Decodable for TxIn {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
         Ok(Self {
-            prev_tx,
-            prev_idx,
-            script_sig,
-            sequence,
+            previous_output: OutPoint::consensus_decode(reader)?,
+            script_sig: Script::consensus_decode(reader)?,
+            sequence: u32::consensus_decode(reader)?,
+            witness: Witness::new(),
         })
     }
 }
@@ -0,0 +1,2158 @@
+//! Script execution for legacy, P2SH, segwit v0 and tapscript spends, following
+//! the rules of Bitcoin Core's interpreter
+
+use std::convert::TryFrom;
+use std::fmt::{self, Display, Formatter};
+use std::ops::{BitOr, BitOrAssign};
+
+use ripemd160::Ripemd160;
+use sha2::{Digest, Sha256};
+
+use crate::secp256k1::crypto::PublicKey;
+use crate::secp256k1::schnorr;
+use crate::secp256k1::signature::Signature;
+use crate::taproot::{tapbranch_hash, tapleaf_hash, tweak_public_key, TAPROOT_LEAF_TAPSCRIPT};
+use crate::utils::{hash160, hash256, sha1};
+
+use super::locktime::{LockTime, Sequence};
+use super::opcode::Opcode;
+use super::output::TxOut;
+use super::script::{push_slice, script_num, Instruction, Instructions, Script, MAX_SCRIPT_SIZE};
+use super::sighash::{SighashCache, SIGHASH_ANYONECANPAY, SIGHASH_DEFAULT};
+use super::tx::Transaction;
+use super::witness::Witness;
+
+/// Largest element that can be pushed to the stack
+pub const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+/// Non push opcodes allowed in a legacy or segwit v0 script
+pub const MAX_OPS_PER_SCRIPT: usize = 201;
+pub const MAX_PUBKEYS_PER_MULTISIG: usize = 20;
+/// Limit of the stack and alt stack together
+pub const MAX_STACK_SIZE: usize = 1000;
+
+pub(crate) const TAPROOT_LEAF_MASK: u8 = 0xfe;
+const TAPROOT_CONTROL_BASE_SIZE: usize = 33;
+const TAPROOT_CONTROL_NODE_SIZE: usize = 32;
+const TAPROOT_CONTROL_MAX_NODE_COUNT: usize = 128;
+/// Tapscript budget spent by each signature check and given on top of the witness size
+const VALIDATION_WEIGHT_PER_SIGOP: i64 = 50;
+const VALIDATION_WEIGHT_OFFSET: i64 = 50;
+/// `s` must be at most half the curve order with `LOW_S`
+const HALF_ORDER: [u8; 32] = [
+    0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0x5d, 0x57, 0x6e, 0x73, 0x57, 0xa4, 0x50, 0x1d, 0xdf, 0xe9, 0x2f, 0x46, 0x68, 0x1b, 0x20, 0xa0,
+];
+
+/// Why an input fails to spend its output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ScriptError {
+    /// Finished with an empty or false top element
+    EvalFalse,
+    OpReturn,
+    ScriptSize,
+    PushSize,
+    OpCount,
+    StackSize,
+    SigCount,
+    PubkeyCount,
+    Verify,
+    EqualVerify,
+    CheckMultisigVerify,
+    CheckSigVerify,
+    NumEqualVerify,
+    BadOpcode,
+    DisabledOpcode,
+    InvalidStackOperation,
+    InvalidAltstackOperation,
+    UnbalancedConditional,
+    /// Number operand longer than allowed
+    NumOverflow,
+    NegativeLockTime,
+    UnsatisfiedLockTime,
+    /// Unknown sighash type, or one not allowed here
+    InvalidSighashType,
+    /// Not strict DER (BIP66)
+    SigDer,
+    SigHighS,
+    SigNullDummy,
+    PubkeyType,
+    SigPushOnly,
+    MinimalData,
+    CleanStack,
+    MinimalIf,
+    NullFail,
+    DiscourageUpgradableNops,
+    DiscourageUpgradableWitnessProgram,
+    DiscourageUpgradableTaprootVersion,
+    DiscourageOpSuccess,
+    DiscourageUpgradablePubkeyType,
+    WitnessProgramWrongLength,
+    WitnessProgramWitnessEmpty,
+    WitnessProgramMismatch,
+    WitnessMalleated,
+    WitnessMalleatedP2sh,
+    WitnessUnexpected,
+    WitnessPubkeyType,
+    /// Failing BIP340 signature
+    InvalidSignature,
+    SchnorrSigSize,
+    TaprootWrongControlSize,
+    TapscriptValidationWeight,
+    TapscriptCheckMultisig,
+    TapscriptMinimalIf,
+    TapscriptEmptyPubkey,
+    OpCodeSeparator,
+    SigFindAndDelete,
+}
+
+impl Display for ScriptError {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        fmt.write_str(match self {
+            ScriptError::EvalFalse => "script evaluated to false",
+            ScriptError::OpReturn => "OP_RETURN executed",
+            ScriptError::ScriptSize => "script too long",
+            ScriptError::PushSize => "pushed element too long",
+            ScriptError::OpCount => "too many opcodes",
+            ScriptError::StackSize => "stack too large",
+            ScriptError::SigCount => "invalid signature count",
+            ScriptError::PubkeyCount => "invalid public key count",
+            ScriptError::Verify => "OP_VERIFY failed",
+            ScriptError::EqualVerify => "OP_EQUALVERIFY failed",
+            ScriptError::CheckMultisigVerify => "OP_CHECKMULTISIGVERIFY failed",
+            ScriptError::CheckSigVerify => "OP_CHECKSIGVERIFY failed",
+            ScriptError::NumEqualVerify => "OP_NUMEQUALVERIFY failed",
+            ScriptError::BadOpcode => "bad opcode",
+            ScriptError::DisabledOpcode => "disabled opcode",
+            ScriptError::InvalidStackOperation => "not enough elements in the stack",
+            ScriptError::InvalidAltstackOperation => "not enough elements in the alt stack",
+            ScriptError::UnbalancedConditional => "unbalanced conditional",
+            ScriptError::NumOverflow => "script number overflow",
+            ScriptError::NegativeLockTime => "negative locktime",
+            ScriptError::UnsatisfiedLockTime => "locktime requirement not satisfied",
+            ScriptError::InvalidSighashType => "invalid sighash type",
+            ScriptError::SigDer => "non canonical DER signature",
+            ScriptError::SigHighS => "signature s value above half the order",
+            ScriptError::SigNullDummy => "non empty OP_CHECKMULTISIG dummy",
+            ScriptError::PubkeyType => "invalid public key encoding",
+            ScriptError::SigPushOnly => "script sig isn't push only",
+            ScriptError::MinimalData => "non minimal push or number",
+            ScriptError::CleanStack => "stack not clean after evaluation",
+            ScriptError::MinimalIf => "OP_IF argument must be minimal",
+            ScriptError::NullFail => "failing signature must be empty",
+            ScriptError::DiscourageUpgradableNops => "upgradable NOP used",
+            ScriptError::DiscourageUpgradableWitnessProgram => "upgradable witness program used",
+            ScriptError::DiscourageUpgradableTaprootVersion => "upgradable leaf version used",
+            ScriptError::DiscourageOpSuccess => "OP_SUCCESSx used",
+            ScriptError::DiscourageUpgradablePubkeyType => "upgradable public key type used",
+            ScriptError::WitnessProgramWrongLength => "witness program has the wrong length",
+            ScriptError::WitnessProgramWitnessEmpty => "witness program with an empty witness",
+            ScriptError::WitnessProgramMismatch => "witness program mismatch",
+            ScriptError::WitnessMalleated => "witness spend with a script sig",
+            ScriptError::WitnessMalleatedP2sh => "nested witness spend with extra script sig data",
+            ScriptError::WitnessUnexpected => "witness given for a non witness spend",
+            ScriptError::WitnessPubkeyType => "witness public keys must be compressed",
+            ScriptError::InvalidSignature => "invalid signature",
+            ScriptError::SchnorrSigSize => "invalid schnorr signature size",
+            ScriptError::TaprootWrongControlSize => "invalid taproot control block size",
+            ScriptError::TapscriptValidationWeight => {
+                "too many signature checks for the witness size"
+            }
+            ScriptError::TapscriptCheckMultisig => "OP_CHECKMULTISIG in tapscript",
+            ScriptError::TapscriptMinimalIf => "tapscript OP_IF argument must be minimal",
+            ScriptError::TapscriptEmptyPubkey => "empty public key in tapscript",
+            ScriptError::OpCodeSeparator => "OP_CODESEPARATOR in a non witness script",
+            ScriptError::SigFindAndDelete => "signature found in the script code",
+        })
+    }
+}
+
+pub type ScriptResult<T> = std::result::Result<T, ScriptError>;
+
+/// Rules to enforce on top of the base ones, softforks and policy alike
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct VerifyFlags(u32);
+
+impl VerifyFlags {
+    pub const NONE: VerifyFlags = VerifyFlags(0);
+    /// BIP16
+    pub const P2SH: VerifyFlags = VerifyFlags(1 << 0);
+    /// Defined sighash types and well formed public keys
+    pub const STRICTENC: VerifyFlags = VerifyFlags(1 << 1);
+    /// BIP66
+    pub const DERSIG: VerifyFlags = VerifyFlags(1 << 2);
+    pub const LOW_S: VerifyFlags = VerifyFlags(1 << 3);
+    /// BIP147
+    pub const NULLDUMMY: VerifyFlags = VerifyFlags(1 << 4);
+    pub const SIGPUSHONLY: VerifyFlags = VerifyFlags(1 << 5);
+    pub const MINIMALDATA: VerifyFlags = VerifyFlags(1 << 6);
+    pub const DISCOURAGE_UPGRADABLE_NOPS: VerifyFlags = VerifyFlags(1 << 7);
+    pub const CLEANSTACK: VerifyFlags = VerifyFlags(1 << 8);
+    /// BIP65
+    pub const CHECKLOCKTIMEVERIFY: VerifyFlags = VerifyFlags(1 << 9);
+    /// BIP112
+    pub const CHECKSEQUENCEVERIFY: VerifyFlags = VerifyFlags(1 << 10);
+    /// BIP141
+    pub const WITNESS: VerifyFlags = VerifyFlags(1 << 11);
+    pub const DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM: VerifyFlags = VerifyFlags(1 << 12);
+    pub const MINIMALIF: VerifyFlags = VerifyFlags(1 << 13);
+    pub const NULLFAIL: VerifyFlags = VerifyFlags(1 << 14);
+    pub const WITNESS_PUBKEYTYPE: VerifyFlags = VerifyFlags(1 << 15);
+    pub const CONST_SCRIPTCODE: VerifyFlags = VerifyFlags(1 << 16);
+    /// BIP341 and BIP342
+    pub const TAPROOT: VerifyFlags = VerifyFlags(1 << 17);
+    pub const DISCOURAGE_UPGRADABLE_TAPROOT_VERSION: VerifyFlags = VerifyFlags(1 << 18);
+    pub const DISCOURAGE_OP_SUCCESS: VerifyFlags = VerifyFlags(1 << 19);
+    pub const DISCOURAGE_UPGRADABLE_PUBKEYTYPE: VerifyFlags = VerifyFlags(1 << 20);
+
+    /// Every softfork active today
+    pub const CONSENSUS: VerifyFlags = VerifyFlags(
+        Self::P2SH.0
+            | Self::DERSIG.0
+            | Self::NULLDUMMY.0
+            | Self::CHECKLOCKTIMEVERIFY.0
+            | Self::CHECKSEQUENCEVERIFY.0
+            | Self::WITNESS.0
+            | Self::TAPROOT.0,
+    );
+
+    /// What nodes relay, the consensus rules and every policy one
+    pub const STANDARD: VerifyFlags = VerifyFlags(
+        Self::CONSENSUS.0
+            | Self::STRICTENC.0
+            | Self::LOW_S.0
+            | Self::MINIMALDATA.0
+            | Self::DISCOURAGE_UPGRADABLE_NOPS.0
+            | Self::CLEANSTACK.0
+            | Self::DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM.0
+            | Self::MINIMALIF.0
+            | Self::NULLFAIL.0
+            | Self::WITNESS_PUBKEYTYPE.0
+            | Self::CONST_SCRIPTCODE.0
+            | Self::DISCOURAGE_UPGRADABLE_TAPROOT_VERSION.0
+            | Self::DISCOURAGE_OP_SUCCESS.0
+            | Self::DISCOURAGE_UPGRADABLE_PUBKEYTYPE.0,
+    );
+
+    pub const fn from_bits(bits: u32) -> Self {
+        VerifyFlags(bits)
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub const fn contains(self, other: VerifyFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for VerifyFlags {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        VerifyFlags(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for VerifyFlags {
+    fn bitor_assign(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+}
+
+/// Rules a script runs under, also what its signatures commit to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SigVersion {
+    /// Script sigs, output scripts and P2SH redeem scripts
+    Base,
+    WitnessV0,
+    /// Taproot key path spends
+    Taproot,
+    Tapscript,
+}
+
+/// Taproot spend details signature hashes commit to, and the signature checks
+/// budget of tapscript
+#[derive(Debug, Clone, Default)]
+pub struct ExecutionData {
+    annex: Option<Vec<u8>>,
+    leaf_hash: Option<[u8; 32]>,
+    codesep_pos: Option<u32>,
+    validation_weight_left: i64,
+}
+
+impl ExecutionData {
+    pub fn annex(&self) -> Option<&[u8]> {
+        self.annex.as_deref()
+    }
+
+    /// Hash of the executing tapscript leaf
+    pub fn leaf_hash(&self) -> Option<&[u8; 32]> {
+        self.leaf_hash.as_ref()
+    }
+
+    /// Opcode position of the last executed `OP_CODESEPARATOR`
+    pub fn codesep_pos(&self) -> Option<u32> {
+        self.codesep_pos
+    }
+}
+
+/// What the interpreter needs from the spending transaction
+pub trait SignatureChecker {
+    /// ECDSA `signature`, sighash type byte included, by `public_key` over the
+    /// legacy or segwit v0 signature hash with `script_code`
+    fn check_ecdsa(
+        &mut self,
+        signature: &[u8],
+        public_key: &[u8],
+        script_code: &Script,
+        sig_version: SigVersion,
+    ) -> bool;
+
+    /// BIP340 `signature`, with an optional sighash type byte, by `public_key`
+    /// over the taproot signature hash
+    fn check_schnorr(
+        &mut self,
+        signature: &[u8],
+        public_key: &[u8; 32],
+        sig_version: SigVersion,
+        exec_data: &ExecutionData,
+    ) -> ScriptResult<()>;
+
+    /// Whether the transaction locktime satisfies `OP_CHECKLOCKTIMEVERIFY`
+    fn check_locktime(&self, locktime: i64) -> bool;
+
+    /// Whether the input sequence satisfies `OP_CHECKSEQUENCEVERIFY`
+    fn check_sequence(&self, sequence: i64) -> bool;
+}
+
+/// Checker without a transaction, every signature and timelock fails
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NullChecker;
+
+impl SignatureChecker for NullChecker {
+    fn check_ecdsa(&mut self, _: &[u8], _: &[u8], _: &Script, _: SigVersion) -> bool {
+        false
+    }
+
+    fn check_schnorr(
+        &mut self,
+        _: &[u8],
+        _: &[u8; 32],
+        _: SigVersion,
+        _: &ExecutionData,
+    ) -> ScriptResult<()> {
+        Err(ScriptError::InvalidSignature)
+    }
+
+    fn check_locktime(&self, _: i64) -> bool {
+        false
+    }
+
+    fn check_sequence(&self, _: i64) -> bool {
+        false
+    }
+}
+
+/// Checks signatures of an input against the signature hashes of its transaction,
+/// `prevouts` being the outputs spent by every input in order
+#[derive(Debug, Clone)]
+pub struct TransactionChecker<'a> {
+    cache: SighashCache<'a>,
+    prevouts: &'a [TxOut],
+    index: usize,
+}
+
+impl<'a> TransactionChecker<'a> {
+    pub fn new(tx: &'a Transaction, prevouts: &'a [TxOut], index: usize) -> Self {
+        Self {
+            cache: SighashCache::new(tx),
+            prevouts,
+            index,
+        }
+    }
+
+    /// Check another input of the same transaction, reusing the sighash midstates
+    pub fn set_input(&mut self, index: usize) {
+        self.index = index;
+    }
+}
+
+impl SignatureChecker for TransactionChecker<'_> {
+    fn check_ecdsa(
+        &mut self,
+        signature: &[u8],
+        public_key: &[u8],
+        script_code: &Script,
+        sig_version: SigVersion,
+    ) -> bool {
+        let (sighash_type, der) = match signature.split_last() {
+            Some((sighash_type, der)) => (u32::from(*sighash_type), der),
+            None => return false,
+        };
+
+        let digest = match sig_version {
+            SigVersion::Base => {
+                self.cache
+                    .transaction()
+                    .legacy_sighash(self.index, script_code, sighash_type)
+            }
+            SigVersion::WitnessV0 => match self.prevouts.get(self.index) {
+                Some(prevout) => self.cache.segwit_v0_sighash(
+                    self.index,
+                    script_code,
+                    prevout.amount,
+                    sighash_type,
+                ),
+                None => return false,
+            },
+            _ => return false,
+        };
+
+        let (digest, public_key, signature) = match (
+            digest,
+            PublicKey::deserialize(public_key),
+            Signature::deserialize(der),
+        ) {
+            (Ok(digest), Ok(public_key), Ok(signature)) => (digest, public_key, signature),
+            _ => return false,
+        };
+
+        signature.is_valid(digest, &public_key).unwrap_or(false)
+    }
+
+    fn check_schnorr(
+        &mut self,
+        signature: &[u8],
+        public_key: &[u8; 32],
+        sig_version: SigVersion,
+        exec_data: &ExecutionData,
+    ) -> ScriptResult<()> {
+        let sighash_type = match signature.len() {
+            64 => SIGHASH_DEFAULT,
+            65 if u32::from(signature[64]) != SIGHASH_DEFAULT => u32::from(signature[64]),
+            65 => return Err(ScriptError::InvalidSighashType),
+            _ => return Err(ScriptError::SchnorrSigSize),
+        };
+
+        let annex = exec_data.annex();
+        let digest = match (sig_version, exec_data.leaf_hash()) {
+            (SigVersion::Taproot, _) => {
+                self.cache
+                    .taproot_key_spend_sighash(self.index, self.prevouts, annex, sighash_type)
+            }
+            (SigVersion::Tapscript, Some(leaf_hash)) => self.cache.taproot_script_spend_sighash(
+                self.index,
+                self.prevouts,
+                leaf_hash,
+                exec_data.codesep_pos(),
+                annex,
+                sighash_type,
+            ),
+            _ => return Err(ScriptError::InvalidSignature),
+        }
+        .map_err(|_| ScriptError::InvalidSighashType)?;
+
+        let mut schnorr_signature = [0; 64];
+        schnorr_signature.copy_from_slice(&signature[..64]);
+        match schnorr::verify(public_key, &digest, &schnorr_signature) {
+            true => Ok(()),
+            false => Err(ScriptError::InvalidSignature),
+        }
+    }
+
+    fn check_locktime(&self, locktime: i64) -> bool {
+        let tx = self.cache.transaction();
+        let required = match u32::try_from(locktime) {
+            Ok(locktime) => LockTime::from_consensus(locktime),
+            Err(_) => return false,
+        };
+
+        // a final input would disable the locktime being checked
+        required.is_implied_by(tx.locktime())
+            && tx
+                .inputs
+                .get(self.index)
+                .is_some_and(|input| !input.sequence().is_final())
+    }
+
+    fn check_sequence(&self, sequence: i64) -> bool {
+        let tx = self.cache.transaction();
+        let required = match u32::try_from(sequence) {
+            Ok(sequence) => Sequence::from_consensus(sequence).to_relative_lock_time(),
+            Err(_) => return false,
+        };
+
+        let input = match tx.inputs.get(self.index) {
+            Some(input) if tx.version >= 2 => input.sequence().to_relative_lock_time(),
+            _ => return false,
+        };
+
+        match (required, input) {
+            (Some(required), Some(input)) => required.is_implied_by(input),
+            _ => false,
+        }
+    }
+}
+
+/// Run `script` on `stack`, used on its own mostly to test scripts
+pub fn eval_script(
+    stack: &mut Vec<Vec<u8>>,
+    script: &Script,
+    flags: VerifyFlags,
+    checker: &mut dyn SignatureChecker,
+    sig_version: SigVersion,
+) -> ScriptResult<()> {
+    let mut exec_data = ExecutionData::default();
+    eval(
+        stack,
+        &script.bytes,
+        flags,
+        checker,
+        sig_version,
+        &mut exec_data,
+    )
+}
+
+/// Whether `script_sig` and `witness` satisfy `script_pubkey` under `flags`
+pub fn verify_script(
+    script_sig: &Script,
+    script_pubkey: &Script,
+    witness: &Witness,
+    flags: VerifyFlags,
+    checker: &mut dyn SignatureChecker,
+) -> ScriptResult<()> {
+    if flags.contains(VerifyFlags::SIGPUSHONLY) && !script_sig.is_push_only() {
+        return Err(ScriptError::SigPushOnly);
+    }
+
+    let mut stack = Vec::new();
+    eval_script(&mut stack, script_sig, flags, checker, SigVersion::Base)?;
+    let p2sh_stack = match flags.contains(VerifyFlags::P2SH) {
+        true => stack.clone(),
+        false => Vec::new(),
+    };
+
+    eval_script(&mut stack, script_pubkey, flags, checker, SigVersion::Base)?;
+    if !stack.last().is_some_and(|top| cast_to_bool(top)) {
+        return Err(ScriptError::EvalFalse);
+    }
+
+    let mut had_witness = false;
+    if flags.contains(VerifyFlags::WITNESS) {
+        if let Some((version, program)) = script_pubkey.witness_program() {
+            had_witness = true;
+            if !script_sig.is_empty() {
+                return Err(ScriptError::WitnessMalleated);
+            }
+
+            verify_witness_program(witness, version, program, flags, checker, false)?;
+            // the witness already has a clean stack, keep a single element
+            stack.truncate(1);
+        }
+    }
+
+    if flags.contains(VerifyFlags::P2SH) && script_pubkey.p2sh_hash().is_some() {
+        if !script_sig.is_push_only() {
+            return Err(ScriptError::SigPushOnly);
+        }
+
+        stack = p2sh_stack;
+        let redeem_script = Script::from(stack.pop().ok_or(ScriptError::EvalFalse)?);
+        eval_script(&mut stack, &redeem_script, flags, checker, SigVersion::Base)?;
+        if !stack.last().is_some_and(|top| cast_to_bool(top)) {
+            return Err(ScriptError::EvalFalse);
+        }
+
+        if flags.contains(VerifyFlags::WITNESS) {
+            if let Some((version, program)) = redeem_script.witness_program() {
+                had_witness = true;
+
+                // nothing but the push of the redeem script
+                let mut expected = Vec::new();
+                push_slice(&mut expected, &redeem_script.bytes);
+                if script_sig.bytes != expected {
+                    return Err(ScriptError::WitnessMalleatedP2sh);
+                }
+
+                verify_witness_program(witness, version, program, flags, checker, true)?;
+                stack.truncate(1);
+            }
+        }
+    }
+
+    if flags.contains(VerifyFlags::CLEANSTACK) && stack.len() != 1 {
+        return Err(ScriptError::CleanStack);
+    }
+
+    if flags.contains(VerifyFlags::WITNESS) && !had_witness && !witness.is_empty() {
+        return Err(ScriptError::WitnessUnexpected);
+    }
+
+    Ok(())
+}
+
+fn verify_witness_program(
+    witness: &Witness,
+    version: u8,
+    program: &[u8],
+    flags: VerifyFlags,
+    checker: &mut dyn SignatureChecker,
+    is_p2sh: bool,
+) -> ScriptResult<()> {
+    match (version, program.len()) {
+        (0, 32) => {
+            let (script, stack) = witness
+                .split_last()
+                .ok_or(ScriptError::WitnessProgramWitnessEmpty)?;
+            if Sha256::digest(script)[..] != *program {
+                return Err(ScriptError::WitnessProgramMismatch);
+            }
+
+            let mut exec_data = ExecutionData::default();
+            let stack = stack.to_vec();
+            execute_witness_script(
+                stack,
+                script,
+                flags,
+                checker,
+                SigVersion::WitnessV0,
+                &mut exec_data,
+            )
+        }
+        (0, 20) => {
+            if witness.len() != 2 {
+                return Err(ScriptError::WitnessProgramMismatch);
+            }
+
+            // BIP143 script code, the P2PKH script of the same hash
+            let mut script = vec![0x76, 0xa9, 0x14];
+            script.extend_from_slice(program);
+            script.extend_from_slice(&[0x88, 0xac]);
+
+            let mut exec_data = ExecutionData::default();
+            let stack = witness.to_vec();
+            execute_witness_script(
+                stack,
+                &script,
+                flags,
+                checker,
+                SigVersion::WitnessV0,
+                &mut exec_data,
+            )
+        }
+        (0, _) => Err(ScriptError::WitnessProgramWrongLength),
+        (1, 32) if !is_p2sh => {
+            if !flags.contains(VerifyFlags::TAPROOT) {
+                return Ok(());
+            }
+
+            if witness.is_empty() {
+                return Err(ScriptError::WitnessProgramWitnessEmpty);
+            }
+
+            let mut exec_data = ExecutionData {
+                annex: witness.taproot_annex().map(<[u8]>::to_vec),
+                ..Default::default()
+            };
+            let stack = witness.without_annex();
+
+            let mut output_key = [0; 32];
+            output_key.copy_from_slice(program);
+
+            // key path, a single signature
+            if let [signature] = stack {
+                return checker.check_schnorr(
+                    signature,
+                    &output_key,
+                    SigVersion::Taproot,
+                    &exec_data,
+                );
+            }
+
+            let (control, stack) = stack.split_last().unwrap(); // safe, 2 elements at least
+            let (script, stack) = stack.split_last().unwrap();
+            if control.len() < TAPROOT_CONTROL_BASE_SIZE
+                || control.len()
+                    > TAPROOT_CONTROL_BASE_SIZE
+                        + TAPROOT_CONTROL_NODE_SIZE * TAPROOT_CONTROL_MAX_NODE_COUNT
+                || !(control.len() - TAPROOT_CONTROL_BASE_SIZE)
+                    .is_multiple_of(TAPROOT_CONTROL_NODE_SIZE)
+            {
+                return Err(ScriptError::TaprootWrongControlSize);
+            }
+
+            let leaf_version = control[0] & TAPROOT_LEAF_MASK;
+            let leaf_hash = tapleaf_hash(leaf_version, script);
+            if !verify_taproot_commitment(control, &output_key, &leaf_hash) {
+                return Err(ScriptError::WitnessProgramMismatch);
+            }
+
+            exec_data.leaf_hash = Some(leaf_hash);
+            if leaf_version != TAPROOT_LEAF_TAPSCRIPT {
+                return match flags.contains(VerifyFlags::DISCOURAGE_UPGRADABLE_TAPROOT_VERSION) {
+                    true => Err(ScriptError::DiscourageUpgradableTaprootVersion),
+                    false => Ok(()),
+                };
+            }
+
+            exec_data.validation_weight_left = witness.size() as i64 + VALIDATION_WEIGHT_OFFSET;
+            execute_witness_script(
+                stack.to_vec(),
+                script,
+                flags,
+                checker,
+                SigVersion::Tapscript,
+                &mut exec_data,
+            )
+        }
+        _ => match flags.contains(VerifyFlags::DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM) {
+            true => Err(ScriptError::DiscourageUpgradableWitnessProgram),
+            false => Ok(()),
+        },
+    }
+}
+
+fn execute_witness_script(
+    mut stack: Vec<Vec<u8>>,
+    script: &[u8],
+    flags: VerifyFlags,
+    checker: &mut dyn SignatureChecker,
+    sig_version: SigVersion,
+    exec_data: &mut ExecutionData,
+) -> ScriptResult<()> {
+    if sig_version == SigVersion::Tapscript {
+        // any OP_SUCCESSx makes the script succeed, even unparsable ones after it
+        for instruction in Instructions::new(script) {
+            match instruction {
+                Ok(Instruction::Op(opcode)) if opcode.is_success() => {
+                    return match flags.contains(VerifyFlags::DISCOURAGE_OP_SUCCESS) {
+                        true => Err(ScriptError::DiscourageOpSuccess),
+                        false => Ok(()),
+                    };
+                }
+                Ok(_) => {}
+                Err(_) => return Err(ScriptError::BadOpcode),
+            }
+        }
+
+        if stack.len() > MAX_STACK_SIZE {
+            return Err(ScriptError::StackSize);
+        }
+    }
+
+    if stack
+        .iter()
+        .any(|element| element.len() > MAX_SCRIPT_ELEMENT_SIZE)
+    {
+        return Err(ScriptError::PushSize);
+    }
+
+    eval(&mut stack, script, flags, checker, sig_version, exec_data)?;
+
+    // witness scripts must leave exactly one true element
+    match stack.as_slice() {
+        [top] if cast_to_bool(top) => Ok(()),
+        [_] => Err(ScriptError::EvalFalse),
+        _ => Err(ScriptError::CleanStack),
+    }
+}
+
+fn eval(
+    stack: &mut Vec<Vec<u8>>,
+    script: &[u8],
+    flags: VerifyFlags,
+    checker: &mut dyn SignatureChecker,
+    sig_version: SigVersion,
+    exec_data: &mut ExecutionData,
+) -> ScriptResult<()> {
+    use Opcode::*;
+
+    let tapscript = sig_version == SigVersion::Tapscript;
+    if !tapscript && script.len() > MAX_SCRIPT_SIZE {
+        return Err(ScriptError::ScriptSize);
+    }
+
+    let require_minimal = flags.contains(VerifyFlags::MINIMALDATA);
+    let mut alt_stack: Vec<Vec<u8>> = Vec::new();
+    let mut exec_stack: Vec<bool> = Vec::new();
+    let mut op_count = 0;
+    let mut code_start = 0;
+    let mut instructions = Instructions::new(script);
+
+    for opcode_pos in 0.. {
+        let start = script.len() - instructions.as_bytes().len();
+        let instruction = match instructions.next() {
+            Some(Ok(instruction)) => instruction,
+            Some(Err(_)) => return Err(ScriptError::BadOpcode),
+            None => break,
+        };
+
+        let opcode = Opcode::from(script[start]);
+        let executing = exec_stack.iter().all(|branch| *branch);
+
+        if !tapscript && opcode.to_u8() > OP_16.to_u8() {
+            op_count += 1;
+            if op_count > MAX_OPS_PER_SCRIPT {
+                return Err(ScriptError::OpCount);
+            }
+        }
+
+        // fail even in unexecuted branches
+        if opcode.is_disabled() {
+            return Err(ScriptError::DisabledOpcode);
+        }
+
+        if opcode == OP_CODESEPARATOR
+            && sig_version == SigVersion::Base
+            && flags.contains(VerifyFlags::CONST_SCRIPTCODE)
+        {
+            return Err(ScriptError::OpCodeSeparator);
+        }
+
+        if let Instruction::PushBytes(data) = instruction {
+            if data.len() > MAX_SCRIPT_ELEMENT_SIZE {
+                return Err(ScriptError::PushSize);
+            }
+
+            if executing {
+                if require_minimal && !is_minimal_push(opcode, data) {
+                    return Err(ScriptError::MinimalData);
+                }
+                stack.push(data.to_vec());
+            }
+        } else if let Some(n) = opcode.small_int().filter(|_| executing) {
+            stack.push(script_num(n));
+        } else if executing
+            || matches!(
+                opcode,
+                OP_IF | OP_NOTIF | OP_VERIF | OP_VERNOTIF | OP_ELSE | OP_ENDIF
+            )
+        {
+            match opcode {
+                OP_NOP => {}
+
+                OP_CHECKLOCKTIMEVERIFY if flags.contains(VerifyFlags::CHECKLOCKTIMEVERIFY) => {
+                    // 5 bytes, locktimes go up to 2^32 - 1
+                    let locktime = decode_num(top(stack, 1)?, require_minimal, 5)?;
+                    if locktime < 0 {
+                        return Err(ScriptError::NegativeLockTime);
+                    }
+
+                    if !checker.check_locktime(locktime) {
+                        return Err(ScriptError::UnsatisfiedLockTime);
+                    }
+                }
+
+                OP_CHECKSEQUENCEVERIFY if flags.contains(VerifyFlags::CHECKSEQUENCEVERIFY) => {
+                    let sequence = decode_num(top(stack, 1)?, require_minimal, 5)?;
+                    if sequence < 0 {
+                        return Err(ScriptError::NegativeLockTime);
+                    }
+
+                    // with the disable flag it stays a NOP
+                    if sequence & (1 << 31) == 0 && !checker.check_sequence(sequence) {
+                        return Err(ScriptError::UnsatisfiedLockTime);
+                    }
+                }
+
+                OP_NOP1
+                | OP_CHECKLOCKTIMEVERIFY
+                | OP_CHECKSEQUENCEVERIFY
+                | OP_NOP4
+                | OP_NOP5
+                | OP_NOP6
+                | OP_NOP7
+                | OP_NOP8
+                | OP_NOP9
+                | OP_NOP10 => {
+                    if flags.contains(VerifyFlags::DISCOURAGE_UPGRADABLE_NOPS) {
+                        return Err(ScriptError::DiscourageUpgradableNops);
+                    }
+                }
+
+                OP_IF | OP_NOTIF => {
+                    let mut value = false;
+                    if executing {
+                        let condition = stack.pop().ok_or(ScriptError::UnbalancedConditional)?;
+                        let minimal = condition.is_empty() || condition == [1];
+                        if tapscript && !minimal {
+                            return Err(ScriptError::TapscriptMinimalIf);
+                        }
+
+                        if sig_version == SigVersion::WitnessV0
+                            && flags.contains(VerifyFlags::MINIMALIF)
+                            && !minimal
+                        {
+                            return Err(ScriptError::MinimalIf);
+                        }
+
+                        value = cast_to_bool(&condition) == (opcode == OP_IF);
+                    }
+
+                    exec_stack.push(value);
+                }
+
+                OP_ELSE => {
+                    let branch = exec_stack
+                        .last_mut()
+                        .ok_or(ScriptError::UnbalancedConditional)?;
+                    *branch = !*branch;
+                }
+
+                OP_ENDIF => {
+                    exec_stack.pop().ok_or(ScriptError::UnbalancedConditional)?;
+                }
+
+                OP_VERIFY => {
+                    if !cast_to_bool(top(stack, 1)?) {
+                        return Err(ScriptError::Verify);
+                    }
+                    stack.pop();
+                }
+
+                OP_RETURN => return Err(ScriptError::OpReturn),
+
+                OP_TOALTSTACK => alt_stack.push(pop(stack)?),
+
+                OP_FROMALTSTACK => {
+                    let element = alt_stack
+                        .pop()
+                        .ok_or(ScriptError::InvalidAltstackOperation)?;
+                    stack.push(element);
+                }
+
+                OP_2DROP => {
+                    require(stack, 2)?;
+                    stack.truncate(stack.len() - 2);
+                }
+
+                OP_2DUP | OP_3DUP | OP_2OVER => {
+                    let (depth, count) = match opcode {
+                        OP_2DUP => (2, 2),
+                        OP_3DUP => (3, 3),
+                        _ => (4, 2),
+                    };
+
+                    require(stack, depth)?;
+                    let start = stack.len() - depth;
+                    let elements = stack[start..start + count].to_vec();
+                    stack.extend(elements);
+                }
+
+                OP_2ROT => {
+                    require(stack, 6)?;
+                    let start = stack.len() - 6;
+                    let elements: Vec<_> = stack.drain(start..start + 2).collect();
+                    stack.extend(elements);
+                }
+
+                OP_2SWAP => {
+                    require(stack, 4)?;
+                    let len = stack.len();
+                    stack.swap(len - 4, len - 2);
+                    stack.swap(len - 3, len - 1);
+                }
+
+                OP_IFDUP => {
+                    let element = top(stack, 1)?;
+                    if cast_to_bool(element) {
+                        let element = element.clone();
+                        stack.push(element);
+                    }
+                }
+
+                OP_DEPTH => stack.push(script_num(stack.len() as i64)),
+
+                OP_DROP => {
+                    pop(stack)?;
+                }
+
+                OP_DUP | OP_OVER => {
+                    let depth = if opcode == OP_DUP { 1 } else { 2 };
+                    let element = top(stack, depth)?.clone();
+                    stack.push(element);
+                }
+
+                OP_NIP => {
+                    require(stack, 2)?;
+                    stack.remove(stack.len() - 2);
+                }
+
+                OP_PICK | OP_ROLL => {
+                    require(stack, 2)?;
+                    let n = pop_num(stack, require_minimal)?;
+                    if n < 0 || n as usize >= stack.len() {
+                        return Err(ScriptError::InvalidStackOperation);
+                    }
+
+                    let index = stack.len() - 1 - n as usize;
+                    let element = match opcode {
+                        OP_ROLL => stack.remove(index),
+                        _ => stack[index].clone(),
+                    };
+                    stack.push(element);
+                }
+
+                OP_ROT => {
+                    require(stack, 3)?;
+                    let element = stack.remove(stack.len() - 3);
+                    stack.push(element);
+                }
+
+                OP_SWAP => {
+                    require(stack, 2)?;
+                    let len = stack.len();
+                    stack.swap(len - 2, len - 1);
+                }
+
+                OP_TUCK => {
+                    require(stack, 2)?;
+                    let element = stack[stack.len() - 1].clone();
+                    stack.insert(stack.len() - 2, element);
+                }
+
+                OP_SIZE => {
+                    let size = top(stack, 1)?.len();
+                    stack.push(script_num(size as i64));
+                }
+
+                OP_EQUAL | OP_EQUALVERIFY => {
+                    require(stack, 2)?;
+                    let equal = pop(stack)? == pop(stack)?;
+                    match opcode {
+                        OP_EQUALVERIFY if !equal => return Err(ScriptError::EqualVerify),
+                        OP_EQUALVERIFY => {}
+                        _ => push_bool(stack, equal),
+                    }
+                }
+
+                OP_1ADD | OP_1SUB | OP_NEGATE | OP_ABS | OP_NOT | OP_0NOTEQUAL => {
+                    let n = pop_num(stack, require_minimal)?;
+                    let result = match opcode {
+                        OP_1ADD => n + 1,
+                        OP_1SUB => n - 1,
+                        OP_NEGATE => -n,
+                        OP_ABS => n.abs(),
+                        OP_NOT => (n == 0) as i64,
+                        _ => (n != 0) as i64,
+                    };
+                    stack.push(script_num(result));
+                }
+
+                OP_ADD
+                | OP_SUB
+                | OP_BOOLAND
+                | OP_BOOLOR
+                | OP_NUMEQUAL
+                | OP_NUMEQUALVERIFY
+                | OP_NUMNOTEQUAL
+                | OP_LESSTHAN
+                | OP_GREATERTHAN
+                | OP_LESSTHANOREQUAL
+                | OP_GREATERTHANOREQUAL
+                | OP_MIN
+                | OP_MAX => {
+                    require(stack, 2)?;
+                    let b = pop_num(stack, require_minimal)?;
+                    let a = pop_num(stack, require_minimal)?;
+                    let result = match opcode {
+                        OP_ADD => a + b,
+                        OP_SUB => a - b,
+                        OP_BOOLAND => (a != 0 && b != 0) as i64,
+                        OP_BOOLOR => (a != 0 || b != 0) as i64,
+                        OP_NUMEQUAL | OP_NUMEQUALVERIFY => (a == b) as i64,
+                        OP_NUMNOTEQUAL => (a != b) as i64,
+                        OP_LESSTHAN => (a < b) as i64,
+                        OP_GREATERTHAN => (a > b) as i64,
+                        OP_LESSTHANOREQUAL => (a <= b) as i64,
+                        OP_GREATERTHANOREQUAL => (a >= b) as i64,
+                        OP_MIN => a.min(b),
+                        _ => a.max(b),
+                    };
+
+                    match opcode {
+                        OP_NUMEQUALVERIFY if result == 0 => {
+                            return Err(ScriptError::NumEqualVerify)
+                        }
+                        OP_NUMEQUALVERIFY => {}
+                        _ => stack.push(script_num(result)),
+                    }
+                }
+
+                OP_WITHIN => {
+                    require(stack, 3)?;
+                    let max = pop_num(stack, require_minimal)?;
+                    let min = pop_num(stack, require_minimal)?;
+                    let n = pop_num(stack, require_minimal)?;
+                    push_bool(stack, min <= n && n < max);
+                }
+
+                OP_RIPEMD160 | OP_SHA1 | OP_SHA256 | OP_HASH160 | OP_HASH256 => {
+                    let data = pop(stack)?;
+                    let hash = match opcode {
+                        OP_RIPEMD160 => Ripemd160::digest(&data).to_vec(),
+                        OP_SHA1 => sha1(&data).to_vec(),
+                        OP_SHA256 => Sha256::digest(&data).to_vec(),
+                        OP_HASH160 => hash160(&data),
+                        _ => hash256(&data),
+                    };
+                    stack.push(hash);
+                }
+
+                OP_CODESEPARATOR => {
+                    code_start = script.len() - instructions.as_bytes().len();
+                    exec_data.codesep_pos = Some(opcode_pos);
+                }
+
+                OP_CHECKSIG | OP_CHECKSIGVERIFY => {
+                    require(stack, 2)?;
+                    let public_key = pop(stack)?;
+                    let signature = pop(stack)?;
+                    let script_code = &script[code_start..];
+                    let success = match tapscript {
+                        true => {
+                            check_tapscript_sig(&signature, &public_key, flags, checker, exec_data)?
+                        }
+                        false => check_ecdsa_sig(
+                            &signature,
+                            &public_key,
+                            script_code,
+                            flags,
+                            checker,
+                            sig_version,
+                        )?,
+                    };
+
+                    match opcode {
+                        OP_CHECKSIGVERIFY if !success => return Err(ScriptError::CheckSigVerify),
+                        OP_CHECKSIGVERIFY => {}
+                        _ => push_bool(stack, success),
+                    }
+                }
+
+                OP_CHECKSIGADD if tapscript => {
+                    require(stack, 3)?;
+                    let public_key = pop(stack)?;
+                    let n = pop_num(stack, require_minimal)?;
+                    let signature = pop(stack)?;
+                    let success =
+                        check_tapscript_sig(&signature, &public_key, flags, checker, exec_data)?;
+                    stack.push(script_num(n + success as i64));
+                }
+
+                OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY => {
+                    if tapscript {
+                        return Err(ScriptError::TapscriptCheckMultisig);
+                    }
+
+                    let script_code = &script[code_start..];
+                    let success = check_multisig(
+                        stack,
+                        script_code,
+                        flags,
+                        checker,
+                        sig_version,
+                        &mut op_count,
+                    )?;
+                    match opcode {
+                        OP_CHECKMULTISIGVERIFY if !success => {
+                            return Err(ScriptError::CheckMultisigVerify)
+                        }
+                        OP_CHECKMULTISIGVERIFY => {}
+                        _ => push_bool(stack, success),
+                    }
+                }
+
+                _ => return Err(ScriptError::BadOpcode),
+            }
+        }
+
+        if stack.len() + alt_stack.len() > MAX_STACK_SIZE {
+            return Err(ScriptError::StackSize);
+        }
+    }
+
+    if !exec_stack.is_empty() {
+        return Err(ScriptError::UnbalancedConditional);
+    }
+
+    Ok(())
+}
+
+/// `OP_CHECKSIG` before tapscript, `false` for an empty signature
+fn check_ecdsa_sig(
+    signature: &[u8],
+    public_key: &[u8],
+    script_code: &[u8],
+    flags: VerifyFlags,
+    checker: &mut dyn SignatureChecker,
+    sig_version: SigVersion,
+) -> ScriptResult<bool> {
+    let mut script_code = script_code.to_vec();
+    if sig_version == SigVersion::Base {
+        let (code, found) = find_and_delete(&script_code, signature);
+        if found && flags.contains(VerifyFlags::CONST_SCRIPTCODE) {
+            return Err(ScriptError::SigFindAndDelete);
+        }
+        script_code = code;
+    }
+
+    check_signature_encoding(signature, flags)?;
+    check_pubkey_encoding(public_key, flags, sig_version)?;
+
+    let script_code = Script::from(script_code);
+    let success = !signature.is_empty()
+        && checker.check_ecdsa(signature, public_key, &script_code, sig_version);
+    if !success && flags.contains(VerifyFlags::NULLFAIL) && !signature.is_empty() {
+        return Err(ScriptError::NullFail);
+    }
+
+    Ok(success)
+}
+
+/// `OP_CHECKSIG` and `OP_CHECKSIGADD` of tapscript, unknown key types succeed
+fn check_tapscript_sig(
+    signature: &[u8],
+    public_key: &[u8],
+    flags: VerifyFlags,
+    checker: &mut dyn SignatureChecker,
+    exec_data: &mut ExecutionData,
+) -> ScriptResult<bool> {
+    let success = !signature.is_empty();
+    if success {
+        exec_data.validation_weight_left -= VALIDATION_WEIGHT_PER_SIGOP;
+        if exec_data.validation_weight_left < 0 {
+            return Err(ScriptError::TapscriptValidationWeight);
+        }
+    }
+
+    match public_key.len() {
+        0 => return Err(ScriptError::TapscriptEmptyPubkey),
+        32 if success => {
+            let mut key = [0; 32];
+            key.copy_from_slice(public_key);
+            checker.check_schnorr(signature, &key, SigVersion::Tapscript, exec_data)?;
+        }
+        32 => {}
+        _ if flags.contains(VerifyFlags::DISCOURAGE_UPGRADABLE_PUBKEYTYPE) => {
+            return Err(ScriptError::DiscourageUpgradablePubkeyType)
+        }
+        _ => {}
+    }
+
+    Ok(success)
+}
+
+/// `OP_CHECKMULTISIG`, signatures must follow the order of their keys
+fn check_multisig(
+    stack: &mut Vec<Vec<u8>>,
+    script_code: &[u8],
+    flags: VerifyFlags,
+    checker: &mut dyn SignatureChecker,
+    sig_version: SigVersion,
+    op_count: &mut usize,
+) -> ScriptResult<bool> {
+    let require_minimal = flags.contains(VerifyFlags::MINIMALDATA);
+
+    let mut depth = 1;
+    let keys_count = decode_num(top(stack, depth)?, require_minimal, 4)?;
+    if keys_count < 0 || keys_count as usize > MAX_PUBKEYS_PER_MULTISIG {
+        return Err(ScriptError::PubkeyCount);
+    }
+
+    let keys_count = keys_count as usize;
+    *op_count += keys_count;
+    if *op_count > MAX_OPS_PER_SCRIPT {
+        return Err(ScriptError::OpCount);
+    }
+
+    let keys_depth = depth + 1;
+    depth += keys_count + 1;
+    let sigs_count = decode_num(top(stack, depth)?, require_minimal, 4)?;
+    if sigs_count < 0 || sigs_count as usize > keys_count {
+        return Err(ScriptError::SigCount);
+    }
+
+    let sigs_count = sigs_count as usize;
+    let sigs_depth = depth + 1;
+    depth += sigs_count + 1;
+    // the extra element consumed by the off by one bug
+    require(stack, depth)?;
+
+    let signatures: Vec<_> = (0..sigs_count)
+        .map(|i| stack[stack.len() - sigs_depth - i].clone())
+        .collect();
+    let keys: Vec<_> = (0..keys_count)
+        .map(|i| stack[stack.len() - keys_depth - i].clone())
+        .collect();
+
+    let mut script_code = script_code.to_vec();
+    if sig_version == SigVersion::Base {
+        for signature in &signatures {
+            let (code, found) = find_and_delete(&script_code, signature);
+            if found && flags.contains(VerifyFlags::CONST_SCRIPTCODE) {
+                return Err(ScriptError::SigFindAndDelete);
+            }
+            script_code = code;
+        }
+    }
+
+    let script_code = Script::from(script_code);
+    let (mut sig_index, mut key_index) = (0, 0);
+    let mut success = true;
+    while success && sig_index < sigs_count {
+        let (signature, public_key) = (&signatures[sig_index], &keys[key_index]);
+        check_signature_encoding(signature, flags)?;
+        check_pubkey_encoding(public_key, flags, sig_version)?;
+
+        if !signature.is_empty()
+            && checker.check_ecdsa(signature, public_key, &script_code, sig_version)
+        {
+            sig_index += 1;
+        }
+
+        key_index += 1;
+        // more signatures left than keys
+        if sigs_count - sig_index > keys_count - key_index {
+            success = false;
+        }
+    }
+
+    if !success
+        && flags.contains(VerifyFlags::NULLFAIL)
+        && signatures.iter().any(|sig| !sig.is_empty())
+    {
+        return Err(ScriptError::NullFail);
+    }
+
+    stack.truncate(stack.len() - (depth - 1));
+    let dummy = pop(stack)?;
+    if flags.contains(VerifyFlags::NULLDUMMY) && !dummy.is_empty() {
+        return Err(ScriptError::SigNullDummy);
+    }
+
+    Ok(success)
+}
+
+/// Encoding rules of `DERSIG`, `LOW_S` and `STRICTENC`, empty signatures pass
+fn check_signature_encoding(signature: &[u8], flags: VerifyFlags) -> ScriptResult<()> {
+    if signature.is_empty() {
+        return Ok(());
+    }
+
+    let strict = VerifyFlags::DERSIG | VerifyFlags::LOW_S | VerifyFlags::STRICTENC;
+    if flags.bits() & strict.bits() != 0 && !is_strict_der(signature) {
+        return Err(ScriptError::SigDer);
+    }
+
+    if flags.contains(VerifyFlags::LOW_S) && !is_low_s(signature) {
+        return Err(ScriptError::SigHighS);
+    }
+
+    let sighash_type = u32::from(signature[signature.len() - 1]) & !SIGHASH_ANYONECANPAY;
+    if flags.contains(VerifyFlags::STRICTENC) && !(1..=3).contains(&sighash_type) {
+        return Err(ScriptError::InvalidSighashType);
+    }
+
+    Ok(())
+}
+
+fn check_pubkey_encoding(
+    public_key: &[u8],
+    flags: VerifyFlags,
+    sig_version: SigVersion,
+) -> ScriptResult<()> {
+    let compressed = matches!(public_key, [0x02 | 0x03, rest @ ..] if rest.len() == 32);
+    let uncompressed = matches!(public_key, [0x04, rest @ ..] if rest.len() == 64);
+
+    if flags.contains(VerifyFlags::STRICTENC) && !compressed && !uncompressed {
+        return Err(ScriptError::PubkeyType);
+    }
+
+    if flags.contains(VerifyFlags::WITNESS_PUBKEYTYPE)
+        && sig_version == SigVersion::WitnessV0
+        && !compressed
+    {
+        return Err(ScriptError::WitnessPubkeyType);
+    }
+
+    Ok(())
+}
+
+/// BIP66 strict DER, followed by the sighash type byte
+fn is_strict_der(signature: &[u8]) -> bool {
+    let len = signature.len();
+    if !(9..=73).contains(&len) || signature[0] != 0x30 || signature[1] as usize != len - 3 {
+        return false;
+    }
+
+    let r_len = signature[3] as usize;
+    if 5 + r_len >= len {
+        return false;
+    }
+
+    let s_len = signature[5 + r_len] as usize;
+    if r_len + s_len + 7 != len {
+        return false;
+    }
+
+    // positive integers without unnecessary leading zeros
+    let valid_integer = |start: usize, int_len: usize| {
+        signature[start - 2] == 0x02
+            && int_len != 0
+            && signature[start] & 0x80 == 0
+            && !(int_len > 1 && signature[start] == 0x00 && signature[start + 1] & 0x80 == 0)
+    };
+
+    valid_integer(4, r_len) && valid_integer(r_len + 6, s_len)
+}
+
+/// Whether `s` of a strict DER signature is at most half the curve order
+fn is_low_s(signature: &[u8]) -> bool {
+    let r_len = signature[3] as usize;
+    let s_len = signature[5 + r_len] as usize;
+    let s = &signature[6 + r_len..6 + r_len + s_len];
+    let s = &s[s.iter().take_while(|byte| **byte == 0).count()..];
+
+    s.len() < 32 || (s.len() == 32 && s <= &HALF_ORDER[..])
+}
+
+/// Whether `data` was pushed with the smallest opcode possible
+fn is_minimal_push(opcode: Opcode, data: &[u8]) -> bool {
+    let opcode = opcode.to_u8();
+    match data {
+        [] => opcode == 0x00,
+        [n @ 1..=16] => opcode == 0x50 + n,
+        [0x81] => opcode == 0x4f,
+        _ if data.len() <= 0x4b => opcode as usize == data.len(),
+        _ if data.len() <= 0xff => opcode == 0x4c,
+        _ if data.len() <= 0xffff => opcode == 0x4d,
+        _ => true,
+    }
+}
+
+/// Script number of at most `max_size` bytes, little endian sign magnitude
+pub(crate) fn decode_num(
+    bytes: &[u8],
+    require_minimal: bool,
+    max_size: usize,
+) -> ScriptResult<i64> {
+    if bytes.len() > max_size {
+        return Err(ScriptError::NumOverflow);
+    }
+
+    let (last, rest) = match bytes.split_last() {
+        Some((last, rest)) => (*last, rest),
+        None => return Ok(0),
+    };
+
+    // a zero last byte is only allowed to keep the sign bit of the one before clear
+    if require_minimal && last & 0x7f == 0 && rest.last().is_none_or(|byte| byte & 0x80 == 0) {
+        return Err(ScriptError::MinimalData);
+    }
+
+    let magnitude = rest
+        .iter()
+        .rev()
+        .fold(i64::from(last & 0x7f), |n, byte| n << 8 | i64::from(*byte));
+    match last & 0x80 != 0 {
+        true => Ok(-magnitude),
+        false => Ok(magnitude),
+    }
+}
+
+/// False for empty elements, zeros and negative zero
+fn cast_to_bool(element: &[u8]) -> bool {
+    match element.split_last() {
+        Some((last, rest)) => rest.iter().any(|byte| *byte != 0) || (*last != 0 && *last != 0x80),
+        None => false,
+    }
+}
+
+/// `script` without any push of `signature`, and whether one was found
+fn find_and_delete(script: &[u8], signature: &[u8]) -> (Vec<u8>, bool) {
+    if signature.is_empty() {
+        return (script.to_vec(), false);
+    }
+
+    let mut pattern = Vec::new();
+    push_slice(&mut pattern, signature);
+
+    let (mut result, mut found) = (Vec::with_capacity(script.len()), false);
+    let mut rest = script;
+    loop {
+        // only matched at opcode boundaries
+        while rest.starts_with(&pattern) {
+            rest = &rest[pattern.len()..];
+            found = true;
+        }
+
+        let mut instructions = Instructions::new(rest);
+        match instructions.next() {
+            Some(Ok(_)) => {
+                let len = rest.len() - instructions.as_bytes().len();
+                result.extend_from_slice(&rest[..len]);
+                rest = &rest[len..];
+            }
+            _ => {
+                result.extend_from_slice(rest);
+                return (result, found);
+            }
+        }
+    }
+}
+
+/// Whether the control block proves the leaf is committed to by `output_key`
+fn verify_taproot_commitment(control: &[u8], output_key: &[u8; 32], leaf_hash: &[u8; 32]) -> bool {
+    let mut internal_key = [0; 32];
+    internal_key.copy_from_slice(&control[1..TAPROOT_CONTROL_BASE_SIZE]);
+
+    let root = control[TAPROOT_CONTROL_BASE_SIZE..]
+        .chunks(TAPROOT_CONTROL_NODE_SIZE)
+        .fold(*leaf_hash, |node, sibling| {
+            let mut sibling_hash = [0; 32];
+            sibling_hash.copy_from_slice(sibling);
+            tapbranch_hash(&node, &sibling_hash)
+        });
+
+    match tweak_public_key(&internal_key, Some(&root)) {
+        Ok((key, odd)) => key == *output_key && odd == (control[0] & 1 == 1),
+        Err(_) => false,
+    }
+}
+
+fn require(stack: &[Vec<u8>], depth: usize) -> ScriptResult<()> {
+    match stack.len() >= depth {
+        true => Ok(()),
+        false => Err(ScriptError::InvalidStackOperation),
+    }
+}
+
+/// Element at `depth` from the top, 1 being the top
+fn top(stack: &[Vec<u8>], depth: usize) -> ScriptResult<&Vec<u8>> {
+    require(stack, depth)?;
+    Ok(&stack[stack.len() - depth])
+}
+
+fn pop(stack: &mut Vec<Vec<u8>>) -> ScriptResult<Vec<u8>> {
+    stack.pop().ok_or(ScriptError::InvalidStackOperation)
+}
+
+/// 4 bytes number operand
+fn pop_num(stack: &mut Vec<Vec<u8>>, require_minimal: bool) -> ScriptResult<i64> {
+    decode_num(&pop(stack)?, require_minimal, 4)
+}
+
+fn push_bool(stack: &mut Vec<Vec<u8>>, value: bool) {
+    stack.push(if value { vec![1] } else { vec![] });
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use hex_literal::hex;
+
+    use crate::amount::Amount;
+    use crate::core::input::{OutPoint, TxIn};
+    use crate::core::multisig::{Multisig, MultisigKind};
+    use crate::core::script::ScriptBuilder;
+    use crate::core::txid::Txid;
+    use crate::secp256k1::crypto::PrivateKey;
+    use crate::Error;
+
+    use super::Opcode::*;
+    use super::*;
+
+    fn run(script: &Script, flags: VerifyFlags) -> ScriptResult<Vec<Vec<u8>>> {
+        let mut stack = Vec::new();
+        eval_script(
+            &mut stack,
+            script,
+            flags,
+            &mut NullChecker,
+            SigVersion::Base,
+        )?;
+        Ok(stack)
+    }
+
+    fn spending_tx(version: u32, locktime: u32, sequence: u32) -> Transaction {
+        let mut input = TxIn::new(OutPoint::new(Txid::from_bytes([0x11; 32]), 0));
+        input.set_sequence(Sequence::from_consensus(sequence));
+        let output = TxOut::new(Amount::from_sat(90_000), Script::from(vec![0x51]));
+        Transaction::new(version, vec![input], vec![output], locktime)
+    }
+
+    #[test]
+    fn evaluation() {
+        let flags = VerifyFlags::NONE;
+        let eval = |builder: ScriptBuilder| run(&builder.into_script(), flags);
+
+        // 2 3 OP_ADD 5 OP_EQUAL
+        let script = ScriptBuilder::new()
+            .push_int(2)
+            .push_int(3)
+            .push_opcode(OP_ADD)
+            .push_int(5)
+            .push_opcode(OP_EQUAL);
+        assert_eq!(eval(script), Ok(vec![vec![1]]));
+
+        let script = ScriptBuilder::new()
+            .push_int(-5)
+            .push_opcode(OP_ABS)
+            .push_int(1000)
+            .push_opcode(OP_SUB)
+            .push_int(1)
+            .push_int(0)
+            .push_int(10)
+            .push_opcode(OP_WITHIN);
+        assert_eq!(eval(script), Ok(vec![script_num(-995), vec![1]]));
+
+        // OP_IF takes the else branch
+        let script = ScriptBuilder::new()
+            .push_int(0)
+            .push_opcode(OP_IF)
+            .push_int(7)
+            .push_opcode(OP_ELSE)
+            .push_int(8)
+            .push_opcode(OP_ENDIF);
+        assert_eq!(eval(script), Ok(vec![vec![8]]));
+
+        let script = ScriptBuilder::new()
+            .push_int(1)
+            .push_int(2)
+            .push_int(3)
+            .push_int(2)
+            .push_opcode(OP_ROLL)
+            .push_opcode(OP_2DUP)
+            .push_opcode(OP_DEPTH);
+        assert_eq!(
+            eval(script),
+            Ok(vec![vec![2], vec![3], vec![1], vec![3], vec![1], vec![5]])
+        );
+
+        let script = ScriptBuilder::new()
+            .push_slice(b"abc")
+            .push_opcode(OP_SHA1)
+            .push_slice(b"")
+            .push_opcode(OP_SHA1);
+        assert_eq!(
+            eval(script),
+            Ok(vec![
+                hex!("a9993e364706816aba3e25717850c26c9cd0d89d").to_vec(),
+                hex!("da39a3ee5e6b4b0d3255bfef95601890afd80709").to_vec(),
+            ])
+        );
+    }
+
+    #[test]
+    fn evaluation_errors() {
+        let eval = |builder: ScriptBuilder, flags| run(&builder.into_script(), flags);
+        let none = VerifyFlags::NONE;
+
+        let script = ScriptBuilder::new().push_int(1).push_opcode(OP_RETURN);
+        assert_eq!(eval(script, none), Err(ScriptError::OpReturn));
+
+        // disabled opcodes fail even in unexecuted branches, others don't
+        let script = ScriptBuilder::new()
+            .push_int(0)
+            .push_opcode(OP_IF)
+            .push_opcode(OP_CAT)
+            .push_opcode(OP_ENDIF);
+        assert_eq!(eval(script, none), Err(ScriptError::DisabledOpcode));
+        let script = ScriptBuilder::new()
+            .push_int(0)
+            .push_opcode(OP_IF)
+            .push_opcode(OP_RETURN)
+            .push_opcode(OP_UNKNOWN(0xbb))
+            .push_opcode(OP_ENDIF);
+        assert_eq!(eval(script, none), Ok(vec![]));
+
+        let script = ScriptBuilder::new().push_int(1).push_opcode(OP_IF);
+        assert_eq!(eval(script, none), Err(ScriptError::UnbalancedConditional));
+        let script = ScriptBuilder::new().push_opcode(OP_ENDIF);
+        assert_eq!(eval(script, none), Err(ScriptError::UnbalancedConditional));
+        let script = ScriptBuilder::new().push_opcode(OP_DROP);
+        assert_eq!(eval(script, none), Err(ScriptError::InvalidStackOperation));
+        let script = ScriptBuilder::new().push_opcode(OP_FROMALTSTACK);
+        assert_eq!(
+            eval(script, none),
+            Err(ScriptError::InvalidAltstackOperation)
+        );
+        let script = ScriptBuilder::new().push_opcode(OP_CHECKSIGADD);
+        assert_eq!(eval(script, none), Err(ScriptError::BadOpcode));
+
+        // 5 byte numbers overflow arithmetic
+        let script = ScriptBuilder::new()
+            .push_slice(&[0, 0, 0, 0, 1])
+            .push_opcode(OP_1ADD);
+        assert_eq!(eval(script, none), Err(ScriptError::NumOverflow));
+
+        // non minimal pushes and numbers
+        let script = Script::from(vec![0x01, 0x05]);
+        assert_eq!(run(&script, none), Ok(vec![vec![5]]));
+        assert_eq!(
+            run(&script, VerifyFlags::MINIMALDATA),
+            Err(ScriptError::MinimalData)
+        );
+        let script = ScriptBuilder::new()
+            .push_slice(&[0x05, 0x00])
+            .push_opcode(OP_1ADD);
+        assert_eq!(
+            eval(script, VerifyFlags::MINIMALDATA),
+            Err(ScriptError::MinimalData)
+        );
+
+        let script = ScriptBuilder::new().push_opcode(OP_NOP4);
+        assert_eq!(eval(script.clone(), none), Ok(vec![]));
+        assert_eq!(
+            eval(script, VerifyFlags::DISCOURAGE_UPGRADABLE_NOPS),
+            Err(ScriptError::DiscourageUpgradableNops)
+        );
+
+        let script = Script::from(vec![0x4c]);
+        assert_eq!(run(&script, none), Err(ScriptError::BadOpcode));
+
+        let mut script = ScriptBuilder::new();
+        for _ in 0..=MAX_OPS_PER_SCRIPT {
+            script = script.push_opcode(OP_NOP);
+        }
+        assert_eq!(eval(script, none), Err(ScriptError::OpCount));
+
+        let mut script = ScriptBuilder::new();
+        for _ in 0..=MAX_STACK_SIZE {
+            script = script.push_int(1);
+        }
+        assert_eq!(eval(script, none), Err(ScriptError::StackSize));
+    }
+
+    #[test]
+    fn numbers_and_booleans() {
+        assert_eq!(decode_num(&[], true, 4), Ok(0));
+        assert_eq!(decode_num(&[0x81], true, 4), Ok(-1));
+        assert_eq!(decode_num(&[0x80, 0x00], true, 4), Ok(128));
+        assert_eq!(
+            decode_num(&[0xff, 0xff, 0xff, 0xff], true, 4),
+            Ok(-0x7fff_ffff)
+        );
+        assert_eq!(decode_num(&[0x00], true, 4), Err(ScriptError::MinimalData));
+        assert_eq!(decode_num(&[0x00], false, 4), Ok(0));
+        assert_eq!(decode_num(&[0x80], false, 4), Ok(0));
+        assert_eq!(
+            decode_num(&[1, 2, 3, 4, 5], true, 4),
+            Err(ScriptError::NumOverflow)
+        );
+
+        for n in [0, 1, -1, 127, 128, -128, 255, 0x7fff_ffff, -0x7fff_ffff].iter() {
+            assert_eq!(decode_num(&script_num(*n), true, 4), Ok(*n));
+        }
+
+        assert!(!cast_to_bool(&[]));
+        assert!(!cast_to_bool(&[0x00, 0x00]));
+        assert!(!cast_to_bool(&[0x00, 0x80]));
+        assert!(cast_to_bool(&[0x80, 0x00]));
+        assert!(cast_to_bool(&[0x01]));
+    }
+
+    #[test]
+    fn signature_encodings() {
+        let der = hex!("3044022011111111111111111111111111111111111111111111111111111111111111110220222222222222222222222222222222222222222222222222222222222222222201");
+        assert!(is_strict_der(&der));
+        assert!(is_low_s(&der));
+        assert_eq!(
+            check_signature_encoding(&der, VerifyFlags::STANDARD),
+            Ok(())
+        );
+
+        // s above half the order
+        let mut high_s = der;
+        high_s[38] = 0x7f;
+        high_s[39] = 0xff;
+        high_s[40..70].copy_from_slice(&[0xff; 30]);
+        assert!(!is_low_s(&high_s));
+        assert_eq!(
+            check_signature_encoding(&high_s, VerifyFlags::STANDARD),
+            Err(ScriptError::SigHighS)
+        );
+
+        let mut undefined = der;
+        undefined[70] = 0x04;
+        assert_eq!(
+            check_signature_encoding(&undefined, VerifyFlags::STANDARD),
+            Err(ScriptError::InvalidSighashType)
+        );
+        assert_eq!(
+            check_signature_encoding(&undefined, VerifyFlags::CONSENSUS),
+            Ok(())
+        );
+
+        // r with an unnecessary leading zero
+        let mut padded = der;
+        padded[4] = 0x00;
+        padded[5] = 0x00;
+        assert!(!is_strict_der(&padded));
+        assert_eq!(
+            check_signature_encoding(&padded, VerifyFlags::CONSENSUS),
+            Err(ScriptError::SigDer)
+        );
+        assert_eq!(check_signature_encoding(&padded, VerifyFlags::NONE), Ok(()));
+
+        assert_eq!(
+            check_pubkey_encoding(&[0x05; 33], VerifyFlags::STRICTENC, SigVersion::Base),
+            Err(ScriptError::PubkeyType)
+        );
+        assert_eq!(
+            check_pubkey_encoding(&[0x04; 65], VerifyFlags::STANDARD, SigVersion::WitnessV0),
+            Err(ScriptError::WitnessPubkeyType)
+        );
+    }
+
+    #[test]
+    fn oversized_der_lengths() -> Result<()> {
+        let public_key = PrivateKey::new(2001u32)
+            .public_key()
+            .serialize_compressed()?;
+        let script_pubkey = ScriptBuilder::new()
+            .push_slice(&public_key)
+            .push_opcode(OP_CHECKSIG)
+            .into_script();
+        let prevouts = vec![TxOut::new(Amount::from_sat(100_000), script_pubkey)];
+
+        // without the encoding checks the signature reaches the DER parser
+        for signature in [
+            &hex!("30ff02010102010101")[..],
+            &hex!("30fe02010102010101")[..],
+        ]
+        .iter()
+        {
+            let mut tx = spending_tx(2, 0, 0xffffffff);
+            tx.inputs[0].script_sig = ScriptBuilder::new().push_slice(signature).into_script();
+            assert!(matches!(
+                tx.verify_with_flags(&prevouts, VerifyFlags::NONE),
+                Err(Error::InvalidInput {
+                    error: ScriptError::EvalFalse,
+                    ..
+                })
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn find_and_delete_pushes() {
+        // the push is removed at opcode boundaries only
+        let script = hex!("0302ff03 ac 0302ff03 0302ff03 4c0302ff03");
+        let (deleted, found) = find_and_delete(&script, &hex!("02ff03"));
+        assert!(found);
+        assert_eq!(deleted, hex!("ac 4c0302ff03"));
+
+        let script = hex!("0403020100");
+        let (deleted, found) = find_and_delete(&script, &hex!("020100"));
+        assert!(!found);
+        assert_eq!(deleted, script);
+    }
+
+    #[test]
+    fn timelocks() {
+        let cltv = ScriptBuilder::new()
+            .push_int(500)
+            .push_opcode(OP_CHECKLOCKTIMEVERIFY)
+            .push_opcode(OP_DROP)
+            .push_int(1)
+            .into_script();
+        let csv = ScriptBuilder::new()
+            .push_int(10)
+            .push_opcode(OP_CHECKSEQUENCEVERIFY)
+            .push_opcode(OP_DROP)
+            .push_int(1)
+            .into_script();
+        let flags = VerifyFlags::CONSENSUS;
+
+        let check = |tx: &Transaction, script: &Script| {
+            let prevouts = [TxOut::new(Amount::from_sat(100_000), script.clone())];
+            let mut checker = TransactionChecker::new(tx, &prevouts, 0);
+            verify_script(&Script::new(), script, &Witness::new(), flags, &mut checker)
+        };
+
+        assert_eq!(check(&spending_tx(2, 500, 0xfffffffe), &cltv), Ok(()));
+        assert_eq!(
+            check(&spending_tx(2, 499, 0xfffffffe), &cltv),
+            Err(ScriptError::UnsatisfiedLockTime)
+        );
+        // a final input disables the locktime, a timestamp isn't a height
+        assert_eq!(
+            check(&spending_tx(2, 500, 0xffffffff), &cltv),
+            Err(ScriptError::UnsatisfiedLockTime)
+        );
+        assert_eq!(
+            check(&spending_tx(2, 500_000_000, 0xfffffffe), &cltv),
+            Err(ScriptError::UnsatisfiedLockTime)
+        );
+
+        assert_eq!(check(&spending_tx(2, 0, 10), &csv), Ok(()));
+        assert_eq!(
+            check(&spending_tx(2, 0, 9), &csv),
+            Err(ScriptError::UnsatisfiedLockTime)
+        );
+        assert_eq!(
+            check(&spending_tx(1, 0, 10), &csv),
+            Err(ScriptError::UnsatisfiedLockTime)
+        );
+
+        // before the softforks they are NOPs
+        let tx = spending_tx(1, 0, 0xffffffff);
+        let prevouts = [TxOut::new(Amount::from_sat(100_000), cltv.clone())];
+        let mut checker = TransactionChecker::new(&tx, &prevouts, 0);
+        assert_eq!(
+            verify_script(
+                &Script::new(),
+                &cltv,
+                &Witness::new(),
+                VerifyFlags::P2SH,
+                &mut checker
+            ),
+            Ok(())
+        );
+
+        let negative = ScriptBuilder::new()
+            .push_int(-1)
+            .push_opcode(OP_CHECKLOCKTIMEVERIFY)
+            .into_script();
+        assert_eq!(
+            check(&spending_tx(2, 500, 0), &negative),
+            Err(ScriptError::NegativeLockTime)
+        );
+    }
+
+    #[test]
+    fn multisig_spends() -> Result<()> {
+        let keys: Vec<_> = (1..=3u32).map(|i| PrivateKey::new(2000 + i)).collect();
+        let public_keys: Vec<_> = keys.iter().map(|key| key.public_key().clone()).collect();
+        let amount = Amount::from_sat(100_000);
+
+        for kind in [
+            MultisigKind::P2sh,
+            MultisigKind::P2wsh,
+            MultisigKind::P2shP2wsh,
+        ]
+        .iter()
+        {
+            let multisig = Multisig::new(2, &public_keys, *kind)?;
+            let mut tx = spending_tx(2, 0, 0xffffffff);
+            let signatures = vec![
+                (
+                    public_keys[0].clone(),
+                    multisig.sign(&tx, 0, amount, &keys[0])?,
+                ),
+                (
+                    public_keys[2].clone(),
+                    multisig.sign(&tx, 0, amount, &keys[2])?,
+                ),
+            ];
+            multisig.finalize(&mut tx, 0, amount, &signatures)?;
+
+            let prevouts = vec![TxOut::new(amount, multisig.script_pubkey())];
+            tx.verify_with_flags(&prevouts, VerifyFlags::STANDARD)?;
+
+            // a non empty dummy
+            let mut malleated = tx.clone();
+            match kind {
+                MultisigKind::P2sh => malleated.inputs[0].script_sig.bytes[0] = 0x51,
+                _ => malleated.inputs[0].witness[0] = vec![0x01],
+            }
+            assert!(matches!(
+                malleated.verify(&prevouts),
+                Err(Error::InvalidInput {
+                    error: ScriptError::SigNullDummy,
+                    ..
+                })
+            ));
+
+            // the other amount breaks segwit signatures only
+            let prevouts = vec![TxOut::new(
+                Amount::from_sat(100_001),
+                multisig.script_pubkey(),
+            )];
+            match kind {
+                MultisigKind::P2sh => tx.verify(&prevouts)?,
+                _ => assert!(tx.verify(&prevouts).is_err()),
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn tapscript_spends() -> Result<()> {
+        let internal = PrivateKey::new(31337u32);
+        let internal_key = schnorr::x_only_public_key(&internal);
+        let keys = [PrivateKey::new(41u32), PrivateKey::new(42u32)];
+        let x_only: Vec<_> = keys.iter().map(schnorr::x_only_public_key).collect();
+
+        // 2-of-2 with OP_CHECKSIGADD
+        let script = ScriptBuilder::new()
+            .push_slice(&x_only[0])
+            .push_opcode(OP_CHECKSIG)
+            .push_slice(&x_only[1])
+            .push_opcode(OP_CHECKSIGADD)
+            .push_int(2)
+            .push_opcode(OP_NUMEQUAL)
+            .into_script();
+
+        // another leaf, the tree has two
+        let sibling = tapleaf_hash(TAPROOT_LEAF_TAPSCRIPT, &[0x6a]);
+        let leaf_hash = tapleaf_hash(TAPROOT_LEAF_TAPSCRIPT, &script.bytes);
+        let root = tapbranch_hash(&leaf_hash, &sibling);
+        let (output_key, odd) = tweak_public_key(&internal_key, Some(&root))?;
+        let mut control = vec![TAPROOT_LEAF_TAPSCRIPT | odd as u8];
+        control.extend_from_slice(&internal_key);
+        control.extend_from_slice(&sibling);
+
+        let script_pubkey = ScriptBuilder::new()
+            .push_int(1)
+            .push_slice(&output_key)
+            .into_script();
+        let prevouts = vec![TxOut::new(Amount::from_sat(100_000), script_pubkey)];
+        let mut tx = spending_tx(2, 0, 0xffffffff);
+
+        let digest = SighashCache::new(&tx).taproot_script_spend_sighash(
+            0,
+            &prevouts,
+            &leaf_hash,
+            None,
+            None,
+            SIGHASH_DEFAULT,
+        )?;
+        let signatures: Vec<_> = keys
+            .iter()
+            .map(|key| schnorr::sign(key, &digest, &[0; 32]).map(|sig| sig.to_vec()))
+            .collect::<crate::Result<_>>()?;
+
+        // signatures in reverse order of their keys
+        tx.inputs[0].witness = Witness::p2tr_script_spend(
+            vec![signatures[1].clone(), signatures[0].clone()],
+            &script,
+            &control,
+        );
+        tx.verify_with_flags(&prevouts, VerifyFlags::STANDARD)?;
+
+        // an empty signature counts zero
+        let mut missing = tx.clone();
+        missing.inputs[0].witness[0] = vec![];
+        assert!(matches!(
+            missing.verify(&prevouts),
+            Err(Error::InvalidInput {
+                error: ScriptError::EvalFalse,
+                ..
+            })
+        ));
+
+        // invalid signatures fail right away
+        let mut tampered = tx.clone();
+        tampered.inputs[0].witness[1][5] ^= 1;
+        assert!(matches!(
+            tampered.verify(&prevouts),
+            Err(Error::InvalidInput {
+                error: ScriptError::InvalidSignature,
+                ..
+            })
+        ));
+
+        let mut wrong_path = tx.clone();
+        wrong_path.inputs[0].witness[3][40] ^= 1;
+        assert!(matches!(
+            wrong_path.verify(&prevouts),
+            Err(Error::InvalidInput {
+                error: ScriptError::WitnessProgramMismatch,
+                ..
+            })
+        ));
+
+        let mut short_control = tx.clone();
+        short_control.inputs[0].witness[3].pop();
+        assert!(matches!(
+            short_control.verify(&prevouts),
+            Err(Error::InvalidInput {
+                error: ScriptError::TaprootWrongControlSize,
+                ..
+            })
+        ));
+
+        // OP_SUCCESSx leaves succeed, unless discouraged
+        let success = vec![0xbb];
+        let success_hash = tapleaf_hash(TAPROOT_LEAF_TAPSCRIPT, &success);
+        let (output_key, odd) = tweak_public_key(&internal_key, Some(&success_hash))?;
+        let script_pubkey = ScriptBuilder::new()
+            .push_int(1)
+            .push_slice(&output_key)
+            .into_script();
+        let prevouts = vec![TxOut::new(Amount::from_sat(100_000), script_pubkey)];
+        let mut control = vec![TAPROOT_LEAF_TAPSCRIPT | odd as u8];
+        control.extend_from_slice(&internal_key);
+        tx.inputs[0].witness = Witness::p2tr_script_spend(vec![], &Script::from(success), &control);
+        tx.verify(&prevouts)?;
+        assert!(matches!(
+            tx.verify_with_flags(&prevouts, VerifyFlags::STANDARD),
+            Err(Error::InvalidInput {
+                error: ScriptError::DiscourageOpSuccess,
+                ..
+            })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn witness_rules() -> Result<()> {
+        let key = PrivateKey::new(8675309u32);
+        let sec = key.public_key().serialize_compressed()?;
+        let p2wpkh = ScriptBuilder::new()
+            .push_int(0)
+            .push_slice(&hash160(sec))
+            .into_script();
+        let prevouts = vec![TxOut::new(Amount::from_sat(100_000), p2wpkh.clone())];
+
+        let mut tx = spending_tx(2, 0, 0xffffffff);
+        tx.sign_p2wpkh_input(0, &key, &prevouts[0])?;
+        tx.verify_with_flags(&prevouts, VerifyFlags::STANDARD)?;
+
+        let mut malleated = tx.clone();
+        malleated.inputs[0].script_sig = ScriptBuilder::new().push_int(1).into_script();
+        assert!(matches!(
+            malleated.verify(&prevouts),
+            Err(Error::InvalidInput {
+                error: ScriptError::WitnessMalleated,
+                ..
+            })
+        ));
+
+        // witness data on a legacy output
+        let legacy = vec![TxOut::new(
+            Amount::from_sat(100_000),
+            Script::from(vec![0x51]),
+        )];
+        assert!(matches!(
+            tx.verify(&legacy),
+            Err(Error::InvalidInput {
+                error: ScriptError::WitnessUnexpected,
+                ..
+            })
+        ));
+
+        // unknown witness versions are anyone can spend, but not standard
+        let v2 = ScriptBuilder::new()
+            .push_int(2)
+            .push_slice(&[0x01; 32])
+            .into_script();
+        let future = vec![TxOut::new(Amount::from_sat(100_000), v2)];
+        tx.verify(&future)?;
+        assert!(matches!(
+            tx.verify_with_flags(&future, VerifyFlags::STANDARD),
+            Err(Error::InvalidInput {
+                error: ScriptError::DiscourageUpgradableWitnessProgram,
+                ..
+            })
+        ));
+        Ok(())
+    }
+}
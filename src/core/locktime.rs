@@ -0,0 +1,342 @@
+//! Absolute (nLockTime, BIP65) and relative (nSequence, BIP68/112) locktimes
+
+use std::fmt::{self, Display, Formatter};
+
+use crate::{Error, Result};
+
+use super::input::TxIn;
+use super::tx::Transaction;
+
+/// Locktimes below this value are block heights, the rest are timestamps
+pub const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+
+/// Absolute locktime, the transaction can't be mined before the given height or
+/// median time past
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LockTime {
+    Blocks(u32),
+    /// Unix timestamp
+    Seconds(u32),
+}
+
+impl LockTime {
+    /// No locktime at all
+    pub const ZERO: LockTime = LockTime::Blocks(0);
+
+    pub const fn from_consensus(locktime: u32) -> Self {
+        match locktime < LOCKTIME_THRESHOLD {
+            true => LockTime::Blocks(locktime),
+            false => LockTime::Seconds(locktime),
+        }
+    }
+
+    pub fn from_height(height: u32) -> Result<Self> {
+        match height < LOCKTIME_THRESHOLD {
+            true => Ok(LockTime::Blocks(height)),
+            false => Err(Error::InvalidLockTime("height above the threshold")),
+        }
+    }
+
+    pub fn from_time(time: u32) -> Result<Self> {
+        match time >= LOCKTIME_THRESHOLD {
+            true => Ok(LockTime::Seconds(time)),
+            false => Err(Error::InvalidLockTime("time below the threshold")),
+        }
+    }
+
+    pub const fn to_consensus_u32(self) -> u32 {
+        match self {
+            LockTime::Blocks(locktime) | LockTime::Seconds(locktime) => locktime,
+        }
+    }
+
+    pub const fn is_block_height(self) -> bool {
+        matches!(self, LockTime::Blocks(_))
+    }
+
+    pub const fn is_block_time(self) -> bool {
+        matches!(self, LockTime::Seconds(_))
+    }
+
+    /// Whether a transaction with this locktime can go in the block at `height`,
+    /// `mtp` being the median time past of the block before it (BIP113)
+    pub fn is_satisfied_by(self, height: u32, mtp: u32) -> bool {
+        match self {
+            LockTime::Blocks(locktime) => locktime < height,
+            LockTime::Seconds(locktime) => locktime < mtp,
+        }
+    }
+
+    /// Whether `self` required by `OP_CHECKLOCKTIMEVERIFY` is met by the
+    /// transaction locktime `other`, both must be of the same kind
+    pub fn is_implied_by(self, other: LockTime) -> bool {
+        match (self, other) {
+            (LockTime::Blocks(required), LockTime::Blocks(locktime))
+            | (LockTime::Seconds(required), LockTime::Seconds(locktime)) => required <= locktime,
+            _ => false,
+        }
+    }
+}
+
+impl Default for LockTime {
+    fn default() -> Self {
+        LockTime::ZERO
+    }
+}
+
+impl From<u32> for LockTime {
+    fn from(locktime: u32) -> Self {
+        LockTime::from_consensus(locktime)
+    }
+}
+
+impl Display for LockTime {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            LockTime::Blocks(height) => write!(fmt, "block {}", height),
+            LockTime::Seconds(time) => write!(fmt, "time {}", time),
+        }
+    }
+}
+
+/// Relative locktime of an input (BIP68), counted from the confirmation of the
+/// output it spends
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RelativeLockTime {
+    Blocks(u16),
+    /// Intervals of 512 seconds
+    Intervals(u16),
+}
+
+impl RelativeLockTime {
+    /// Enough intervals to last at least `seconds`
+    pub fn from_seconds_ceil(seconds: u32) -> Result<Self> {
+        let intervals = seconds.div_ceil(Sequence::SECONDS_GRANULARITY);
+        match intervals <= u16::MAX as u32 {
+            true => Ok(RelativeLockTime::Intervals(intervals as u16)),
+            false => Err(Error::InvalidLockTime("too many seconds")),
+        }
+    }
+
+    /// Whether it passed once `blocks` and `seconds` of median time past elapsed
+    /// since the spent output confirmed
+    pub fn is_satisfied_by(self, blocks: u32, seconds: u32) -> bool {
+        match self {
+            RelativeLockTime::Blocks(required) => u32::from(required) <= blocks,
+            RelativeLockTime::Intervals(required) => {
+                u32::from(required) * Sequence::SECONDS_GRANULARITY <= seconds
+            }
+        }
+    }
+
+    /// Whether `self` required by `OP_CHECKSEQUENCEVERIFY` is met by the input
+    /// relative locktime `other`, both must be of the same kind
+    pub fn is_implied_by(self, other: RelativeLockTime) -> bool {
+        match (self, other) {
+            (RelativeLockTime::Blocks(required), RelativeLockTime::Blocks(locktime))
+            | (RelativeLockTime::Intervals(required), RelativeLockTime::Intervals(locktime)) => {
+                required <= locktime
+            }
+            _ => false,
+        }
+    }
+
+    pub fn to_sequence(self) -> Sequence {
+        match self {
+            RelativeLockTime::Blocks(blocks) => Sequence(u32::from(blocks)),
+            RelativeLockTime::Intervals(intervals) => {
+                Sequence(Sequence::TYPE_FLAG | u32::from(intervals))
+            }
+        }
+    }
+}
+
+/// Sequence number of an input, also carries its relative locktime and whether
+/// it signals replaceability
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Sequence(pub(crate) u32);
+
+impl Sequence {
+    /// Final, disables the transaction locktime unless another input enables it
+    pub const MAX: Sequence = Sequence(0xffffffff);
+    /// Enables the locktime without signaling replaceability
+    pub const ENABLE_LOCKTIME_NO_RBF: Sequence = Sequence(0xfffffffe);
+    /// Highest sequence signaling replaceability (BIP125)
+    pub const ENABLE_RBF: Sequence = Sequence(0xfffffffd);
+
+    /// Set when the sequence isn't a relative locktime
+    const DISABLE_FLAG: u32 = 1 << 31;
+    /// Set when the relative locktime is in 512 seconds intervals
+    const TYPE_FLAG: u32 = 1 << 22;
+    const LOCKTIME_MASK: u32 = 0xffff;
+    const SECONDS_GRANULARITY: u32 = 512;
+
+    pub const fn from_consensus(sequence: u32) -> Self {
+        Sequence(sequence)
+    }
+
+    pub const fn from_height(blocks: u16) -> Self {
+        Sequence(blocks as u32)
+    }
+
+    pub const fn from_512_second_intervals(intervals: u16) -> Self {
+        Sequence(Self::TYPE_FLAG | intervals as u32)
+    }
+
+    pub const fn to_consensus_u32(self) -> u32 {
+        self.0
+    }
+
+    pub const fn is_final(self) -> bool {
+        self.0 == Self::MAX.0
+    }
+
+    pub const fn is_rbf(self) -> bool {
+        self.0 <= Self::ENABLE_RBF.0
+    }
+
+    /// Whether the transaction locktime applies, which any non final input enables
+    pub const fn enables_absolute_locktime(self) -> bool {
+        !self.is_final()
+    }
+
+    /// The relative locktime it encodes, only enforced from transaction version 2
+    pub fn to_relative_lock_time(self) -> Option<RelativeLockTime> {
+        if self.0 & Self::DISABLE_FLAG != 0 {
+            return None;
+        }
+
+        let value = (self.0 & Self::LOCKTIME_MASK) as u16;
+        match self.0 & Self::TYPE_FLAG != 0 {
+            true => Some(RelativeLockTime::Intervals(value)),
+            false => Some(RelativeLockTime::Blocks(value)),
+        }
+    }
+}
+
+impl Default for Sequence {
+    fn default() -> Self {
+        Sequence::MAX
+    }
+}
+
+impl From<RelativeLockTime> for Sequence {
+    fn from(locktime: RelativeLockTime) -> Self {
+        locktime.to_sequence()
+    }
+}
+
+impl TxIn {
+    pub fn set_sequence(&mut self, sequence: Sequence) {
+        self.sequence = sequence.0;
+    }
+}
+
+impl Transaction {
+    pub fn set_locktime(&mut self, locktime: LockTime) {
+        self.locktime = locktime.to_consensus_u32();
+    }
+
+    /// Whether it can go in the block at `height` whose previous block has the
+    /// median time past `mtp`, only the absolute locktime is checked
+    pub fn is_final(&self, height: u32, mtp: u32) -> bool {
+        self.locktime == 0
+            || self.locktime().is_satisfied_by(height, mtp)
+            || self.inputs.iter().all(|input| input.sequence().is_final())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::input::OutPoint;
+    use crate::core::txid::Txid;
+
+    use super::*;
+
+    #[test]
+    fn absolute_locktimes() {
+        assert_eq!(LockTime::from(800_000), LockTime::Blocks(800_000));
+        assert_eq!(
+            LockTime::from(1_700_000_000),
+            LockTime::Seconds(1_700_000_000)
+        );
+        assert_eq!(
+            LockTime::from(LOCKTIME_THRESHOLD).to_string(),
+            "time 500000000"
+        );
+        assert!(LockTime::from_height(LOCKTIME_THRESHOLD).is_err());
+        assert!(LockTime::from_time(LOCKTIME_THRESHOLD - 1).is_err());
+
+        let locktime = LockTime::from_height(800_000).unwrap();
+        assert!(!locktime.is_satisfied_by(800_000, 1_700_000_000));
+        assert!(locktime.is_satisfied_by(800_001, 0));
+
+        let locktime = LockTime::from_time(1_700_000_000).unwrap();
+        assert!(!locktime.is_satisfied_by(900_000, 1_700_000_000));
+        assert!(locktime.is_satisfied_by(0, 1_700_000_001));
+
+        // CLTV compares against a transaction locktime of the same kind
+        assert!(LockTime::Blocks(100).is_implied_by(LockTime::Blocks(100)));
+        assert!(!LockTime::Blocks(101).is_implied_by(LockTime::Blocks(100)));
+        assert!(!LockTime::Blocks(100).is_implied_by(LockTime::Seconds(LOCKTIME_THRESHOLD)));
+    }
+
+    #[test]
+    fn relative_locktimes() {
+        // BIP68, flags and masked values
+        let sequence = Sequence::from_height(144);
+        assert_eq!(sequence.to_consensus_u32(), 144);
+        assert_eq!(
+            sequence.to_relative_lock_time(),
+            Some(RelativeLockTime::Blocks(144))
+        );
+
+        let sequence = Sequence::from_512_second_intervals(3);
+        assert_eq!(sequence.to_consensus_u32(), 0x0040_0003);
+        assert_eq!(
+            RelativeLockTime::from_seconds_ceil(1_025).unwrap(),
+            RelativeLockTime::Intervals(3)
+        );
+        assert!(RelativeLockTime::from_seconds_ceil(512 * 65_536).is_err());
+
+        // unused bits are ignored, the disable flag turns it off
+        let sequence = Sequence::from_consensus(0x003f_0010);
+        assert_eq!(
+            sequence.to_relative_lock_time(),
+            Some(RelativeLockTime::Blocks(16))
+        );
+        assert_eq!(Sequence::MAX.to_relative_lock_time(), None);
+        assert_eq!(Sequence::ENABLE_RBF.to_relative_lock_time(), None);
+
+        let locktime = RelativeLockTime::Intervals(2);
+        assert!(!locktime.is_satisfied_by(1_000, 1_023));
+        assert!(locktime.is_satisfied_by(0, 1_024));
+        assert!(RelativeLockTime::Blocks(10).is_satisfied_by(10, 0));
+        assert!(!RelativeLockTime::Blocks(10).is_implied_by(locktime));
+        assert_eq!(Sequence::from(locktime), locktime.to_sequence());
+
+        assert!(Sequence::from_height(1).is_rbf());
+        assert!(!Sequence::ENABLE_LOCKTIME_NO_RBF.is_rbf());
+        assert!(Sequence::ENABLE_LOCKTIME_NO_RBF.enables_absolute_locktime());
+    }
+
+    #[test]
+    fn final_transactions() {
+        let mut tx = Transaction::new(
+            2,
+            vec![TxIn::new(OutPoint::new(Txid::from_bytes([0x11; 32]), 0))],
+            vec![],
+            0,
+        );
+        assert!(tx.is_final(0, 0));
+
+        // final sequences disable the locktime
+        tx.set_locktime(LockTime::from_height(800_000).unwrap());
+        assert!(tx.is_final(700_000, 0));
+
+        tx.inputs[0].set_sequence(Sequence::ENABLE_LOCKTIME_NO_RBF);
+        assert_eq!(tx.locktime(), LockTime::Blocks(800_000));
+        assert!(!tx.is_final(700_000, 0));
+        assert!(tx.is_final(800_001, 0));
+    }
+}
@@ -0,0 +1,553 @@
+//! Merkle trees of transaction hashes, the last node of odd levels is paired
+//! with itself, and proofs of inclusion in them, whole or partial (BIP37)
+
+use std::io::{Read, Write};
+
+use crate::consensus::{Decodable, Encodable};
+use crate::utils::hash256;
+use crate::{Error, Result};
+
+use super::block::{Block, BlockHeader, MAX_BLOCK_WEIGHT};
+use super::txid::Txid;
+
+/// Weight of the smallest transaction, bounding the ones a block can have
+const MIN_TRANSACTION_WEIGHT: usize = 4 * 60;
+
+/// Merkle root of `hashes`, zeros without any
+pub fn merkle_root(hashes: &[[u8; 32]]) -> [u8; 32] {
+    merkle_root_mutated(hashes).0
+}
+
+/// Merkle root of `hashes` and whether two identical sibling nodes let another
+/// list hash to the same (CVE-2012-2459), like `[a, b, c]` and `[a, b, c, c]`
+pub fn merkle_root_mutated(hashes: &[[u8; 32]]) -> ([u8; 32], bool) {
+    if hashes.is_empty() {
+        return ([0; 32], false);
+    }
+
+    let mut level = hashes.to_vec();
+    let mut mutated = false;
+    while level.len() > 1 {
+        mutated |= level.chunks_exact(2).any(|pair| pair[0] == pair[1]);
+        level = next_level(&level);
+    }
+
+    (level[0], mutated)
+}
+
+/// Path from a transaction to the merkle root of its block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleProof {
+    pub(crate) txid: Txid,
+    /// Position in the block, the bits tell the side of each step
+    pub(crate) index: u32,
+    /// Siblings from the leaves up
+    pub(crate) path: Vec<[u8; 32]>,
+}
+
+impl MerkleProof {
+    /// Proof for `txid` among the `txids` of a block, in block order
+    pub fn new(txids: &[Txid], txid: &Txid) -> Option<Self> {
+        let index = txids.iter().position(|other| other == txid)?;
+
+        let mut level: Vec<_> = txids.iter().map(|txid| txid.to_bytes()).collect();
+        let mut position = index;
+        let mut path = Vec::new();
+        while level.len() > 1 {
+            let sibling = level.get(position ^ 1).unwrap_or(&level[position]);
+            path.push(*sibling);
+            level = next_level(&level);
+            position /= 2;
+        }
+
+        Some(Self {
+            txid: *txid,
+            index: index as u32,
+            path,
+        })
+    }
+
+    /// From a proof given by someone else, like an Electrum server
+    pub fn from_parts(txid: Txid, index: u32, path: Vec<[u8; 32]>) -> Self {
+        Self { txid, index, path }
+    }
+
+    pub fn txid(&self) -> &Txid {
+        &self.txid
+    }
+
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn path(&self) -> &[[u8; 32]] {
+        &self.path
+    }
+
+    /// Root the proof hashes up to
+    pub fn compute_root(&self) -> [u8; 32] {
+        self.path.iter().enumerate().fold(
+            self.txid.to_bytes(),
+            |node, (depth, sibling)| match (self.index >> depth) & 1 {
+                0 => hash_pair(&node, sibling),
+                _ => hash_pair(sibling, &node),
+            },
+        )
+    }
+
+    /// Whether the transaction is committed to by `header`
+    pub fn verify(&self, header: &BlockHeader) -> bool {
+        // an index pointing past the tree can't come from a real proof
+        self.path.len() < 32
+            && self.index >> self.path.len() == 0
+            && self.compute_root() == *header.merkle_root()
+    }
+}
+
+/// The part of a block's merkle tree proving which of its transactions match a
+/// filter (BIP37): the flag bits of a depth first traversal and the hashes of
+/// the subtrees without matches and the matching leaves
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartialMerkleTree {
+    pub(crate) total: u32,
+    pub(crate) hashes: Vec<[u8; 32]>,
+    /// Whether each visited node is, or is the parent of, a match
+    pub(crate) flags: Vec<bool>,
+}
+
+impl PartialMerkleTree {
+    /// Tree of the `txids` of a block, `matches` tells which ones to prove
+    pub fn new(txids: &[Txid], matches: &[bool]) -> Result<Self> {
+        if txids.is_empty() {
+            return Err(Error::InvalidBlock("empty partial merkle tree"));
+        }
+
+        let leaves: Vec<_> = txids.iter().map(|txid| txid.to_bytes()).collect();
+        let mut tree = Self {
+            total: leaves.len() as u32,
+            hashes: Vec::new(),
+            flags: Vec::new(),
+        };
+
+        let height = tree.height();
+        tree.build(height, 0, &leaves, matches);
+        Ok(tree)
+    }
+
+    /// Transactions in the block
+    pub fn total(&self) -> u32 {
+        self.total
+    }
+
+    pub fn hashes(&self) -> &[[u8; 32]] {
+        &self.hashes
+    }
+
+    /// Merkle root the tree hashes up to, the matched txids go to `matches` with
+    /// their position in the block. Fails on trees not built by
+    /// [`PartialMerkleTree::new`]
+    pub fn extract_matches(&self, matches: &mut Vec<(Txid, u32)>) -> Result<[u8; 32]> {
+        if self.total == 0 {
+            return Err(Error::InvalidBlock("empty partial merkle tree"));
+        }
+
+        if self.total as usize > MAX_BLOCK_WEIGHT / MIN_TRANSACTION_WEIGHT {
+            return Err(Error::InvalidBlock("too many transactions"));
+        }
+
+        // every hash needs at least a flag bit
+        if self.hashes.len() > self.total as usize || self.flags.len() < self.hashes.len() {
+            return Err(Error::InvalidBlock("too many hashes"));
+        }
+
+        let mut traversal = Traversal::default();
+        let root = self.extract(self.height(), 0, &mut traversal)?;
+
+        // only the padding of the last flags byte can be left
+        if traversal.bits_used.div_ceil(8) != self.flags.len().div_ceil(8)
+            || traversal.hashes_used != self.hashes.len()
+        {
+            return Err(Error::InvalidBlock("unused partial merkle tree data"));
+        }
+
+        matches.append(&mut traversal.matches);
+        Ok(root)
+    }
+
+    /// Levels above the leaves
+    fn height(&self) -> u32 {
+        let mut height = 0;
+        while self.width(height) > 1 {
+            height += 1;
+        }
+
+        height
+    }
+
+    /// Nodes at `height`, the leaves at 0
+    fn width(&self, height: u32) -> usize {
+        (self.total as usize + (1 << height) - 1) >> height
+    }
+
+    fn hash(&self, height: u32, position: usize, leaves: &[[u8; 32]]) -> [u8; 32] {
+        if height == 0 {
+            return leaves[position];
+        }
+
+        let left = self.hash(height - 1, position * 2, leaves);
+        let right = match position * 2 + 1 < self.width(height - 1) {
+            true => self.hash(height - 1, position * 2 + 1, leaves),
+            false => left,
+        };
+
+        hash_pair(&left, &right)
+    }
+
+    fn build(&mut self, height: u32, position: usize, leaves: &[[u8; 32]], matches: &[bool]) {
+        let start = position << height;
+        let end = ((position + 1) << height).min(leaves.len());
+        let parent_of_match = (start..end).any(|i| matches.get(i).copied().unwrap_or(false));
+        self.flags.push(parent_of_match);
+
+        if height == 0 || !parent_of_match {
+            let hash = self.hash(height, position, leaves);
+            self.hashes.push(hash);
+            return;
+        }
+
+        self.build(height - 1, position * 2, leaves, matches);
+        if position * 2 + 1 < self.width(height - 1) {
+            self.build(height - 1, position * 2 + 1, leaves, matches);
+        }
+    }
+
+    fn extract(&self, height: u32, position: usize, traversal: &mut Traversal) -> Result<[u8; 32]> {
+        let parent_of_match = *self
+            .flags
+            .get(traversal.bits_used)
+            .ok_or(Error::InvalidBlock("partial merkle tree out of flags"))?;
+        traversal.bits_used += 1;
+
+        if height == 0 || !parent_of_match {
+            let hash = *self
+                .hashes
+                .get(traversal.hashes_used)
+                .ok_or(Error::InvalidBlock("partial merkle tree out of hashes"))?;
+            traversal.hashes_used += 1;
+
+            if height == 0 && parent_of_match {
+                traversal
+                    .matches
+                    .push((Txid::from_bytes(hash), position as u32));
+            }
+
+            return Ok(hash);
+        }
+
+        let left = self.extract(height - 1, position * 2, traversal)?;
+        let right = match position * 2 + 1 < self.width(height - 1) {
+            true => self.extract(height - 1, position * 2 + 1, traversal)?,
+            false => left,
+        };
+
+        // identical siblings would let another tree hash to the same root
+        if position * 2 + 1 < self.width(height - 1) && left == right {
+            return Err(Error::InvalidBlock("duplicate partial merkle tree nodes"));
+        }
+
+        Ok(hash_pair(&left, &right))
+    }
+}
+
+/// Progress of [`PartialMerkleTree::extract`]
+#[derive(Default)]
+struct Traversal {
+    bits_used: usize,
+    hashes_used: usize,
+    matches: Vec<(Txid, u32)>,
+}
+
+/// Transaction count, hashes and the flag bits packed in bytes, least
+/// significant bit first
+impl Encodable for PartialMerkleTree {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        let mut flags = vec![0u8; self.flags.len().div_ceil(8)];
+        for (i, flag) in self.flags.iter().enumerate() {
+            flags[i / 8] |= (*flag as u8) << (i % 8);
+        }
+
+        Ok(self.total.consensus_encode(writer)?
+            + self.hashes.consensus_encode(writer)?
+            + flags.consensus_encode(writer)?)
+    }
+}
+
+impl Decodable for PartialMerkleTree {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        let total = u32::consensus_decode(reader)?;
+        let hashes = Vec::consensus_decode(reader)?;
+        let bytes: Vec<u8> = Vec::consensus_decode(reader)?;
+        let flags = (0..bytes.len() * 8)
+            .map(|i| bytes[i / 8] & (1 << (i % 8)) != 0)
+            .collect();
+
+        Ok(Self {
+            total,
+            hashes,
+            flags,
+        })
+    }
+}
+
+/// Payload of the `merkleblock` message, a header and the proof of the
+/// transactions matching the filter of the peer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleBlock {
+    pub(crate) header: BlockHeader,
+    pub(crate) txn: PartialMerkleTree,
+}
+
+impl MerkleBlock {
+    /// Proof of the transactions of `block` for which `matches` is true
+    pub fn from_block(block: &Block, matches: impl Fn(&Txid) -> bool) -> Result<Self> {
+        let txids = block
+            .transactions()
+            .iter()
+            .map(|tx| tx.txid())
+            .collect::<Result<Vec<_>>>()?;
+        let flags: Vec<_> = txids.iter().map(matches).collect();
+
+        Ok(Self {
+            header: *block.header(),
+            txn: PartialMerkleTree::new(&txids, &flags)?,
+        })
+    }
+
+    pub fn header(&self) -> &BlockHeader {
+        &self.header
+    }
+
+    pub fn txn(&self) -> &PartialMerkleTree {
+        &self.txn
+    }
+
+    /// Matched txids with their position, once the tree is checked against the
+    /// merkle root of the header
+    pub fn extract_matches(&self) -> Result<Vec<(Txid, u32)>> {
+        let mut matches = Vec::new();
+        let root = self.txn.extract_matches(&mut matches)?;
+        if root != self.header.merkle_root {
+            return Err(Error::InvalidBlock("merkle root mismatch"));
+        }
+
+        Ok(matches)
+    }
+}
+
+impl Encodable for MerkleBlock {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        Ok(self.header.consensus_encode(writer)? + self.txn.consensus_encode(writer)?)
+    }
+}
+
+impl Decodable for MerkleBlock {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        Ok(Self {
+            header: BlockHeader::consensus_decode(reader)?,
+            txn: PartialMerkleTree::consensus_decode(reader)?,
+        })
+    }
+}
+
+fn next_level(level: &[[u8; 32]]) -> Vec<[u8; 32]> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => hash_pair(left, right),
+            [last] => hash_pair(last, last),
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut node = [0; 32];
+    node.copy_from_slice(&hash256([&left[..], &right[..]].concat()));
+    node
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::consensus;
+    use crate::core::tx::Transaction;
+    use crate::core::txid::BlockHash;
+
+    use super::*;
+
+    /// Transactions of block 100000
+    fn txids() -> Result<Vec<Txid>> {
+        Ok(vec![
+            "8c14f0db3df150123e6f3dbbf30f8b955a8249b62ac1d1ff16284aefa3d06d87".parse()?,
+            "fff2525b8931402dd09222c50775608f75787bd2b87e56995a7bdd30f79702c4".parse()?,
+            "6359f0868171b1d194cbee1af2f16ea598ae8fad666d9b012c8ed2b79a236ec4".parse()?,
+            "e9a66845e05d5abc0ad04ec80f774a7e585c6e8db975962d069a522137b80c1d".parse()?,
+        ])
+    }
+
+    fn root_of(txids: &[Txid]) -> [u8; 32] {
+        let hashes: Vec<_> = txids.iter().map(|txid| txid.to_bytes()).collect();
+        merkle_root(&hashes)
+    }
+
+    #[test]
+    fn roots() -> Result<()> {
+        let txids = txids()?;
+        let root: Txid =
+            "f3e94742aca4b5ef85488dc37c06c3282295ffec960994b2c0d5ac2a25a95766".parse()?;
+        assert_eq!(root_of(&txids), root.to_bytes());
+
+        // a lone transaction is the root
+        assert_eq!(root_of(&txids[..1]), txids[0].to_bytes());
+        assert_eq!(merkle_root(&[]), [0; 32]);
+
+        // the last one of three is paired with itself
+        let mut repeated: Vec<_> = txids[..3].iter().map(|txid| txid.to_bytes()).collect();
+        let (odd, mutated) = merkle_root_mutated(&repeated);
+        assert!(!mutated);
+        repeated.push(repeated[2]);
+        assert_eq!(merkle_root_mutated(&repeated), (odd, true));
+        Ok(())
+    }
+
+    #[test]
+    fn proofs() -> Result<()> {
+        for count in 1..=7 {
+            let txids: Vec<_> = (0..count).map(|i| Txid::from_bytes([i; 32])).collect();
+            let header = BlockHeader::new(1, BlockHash::default(), root_of(&txids), 0, 0, 0);
+
+            for (i, txid) in txids.iter().enumerate() {
+                let proof = MerkleProof::new(&txids, txid).unwrap();
+                assert_eq!(proof.index(), i as u32);
+                assert_eq!(proof.txid(), txid);
+                assert!(proof.verify(&header), "{} of {}", i, count);
+
+                // the same path on the other side of a distinct sibling
+                if i ^ 1 < txids.len() {
+                    let moved = MerkleProof::from_parts(*txid, i as u32 ^ 1, proof.path.clone());
+                    assert!(!moved.verify(&header));
+                }
+            }
+
+            assert_eq!(
+                MerkleProof::new(&txids, &Txid::from_bytes([0xff; 32])),
+                None
+            );
+        }
+
+        let txids = txids()?;
+        let proof = MerkleProof::new(&txids, &txids[2]).unwrap();
+        assert_eq!(proof.path().len(), 2);
+        assert_eq!(proof.path()[0], txids[3].to_bytes());
+
+        // indexes past the tree
+        let header = BlockHeader::new(1, BlockHash::default(), root_of(&txids), 0, 0, 0);
+        let past = MerkleProof::from_parts(txids[2], 6, proof.path.clone());
+        assert_eq!(past.compute_root(), proof.compute_root());
+        assert!(!past.verify(&header));
+        Ok(())
+    }
+
+    #[test]
+    fn partial_trees() -> Result<()> {
+        for count in [1u32, 2, 3, 4, 7, 16, 17, 56, 100].iter() {
+            let txids: Vec<_> = (0..*count)
+                .map(|i| Txid::from_bytes(hash_pair(&[0; 32], &[i as u8; 32])))
+                .collect();
+            let root = root_of(&txids);
+
+            // none, every third, all
+            for step in [0, 3, 1].iter() {
+                let matches: Vec<_> = (0..*count).map(|i| *step != 0 && i % step == 0).collect();
+                let tree = PartialMerkleTree::new(&txids, &matches)?;
+                assert_eq!(tree.total(), *count);
+
+                let bytes = consensus::serialize(&tree)?;
+                let decoded: PartialMerkleTree = consensus::deserialize(&bytes)?;
+                let mut matched = Vec::new();
+                assert_eq!(decoded.extract_matches(&mut matched)?, root);
+
+                let expected: Vec<_> = (0..*count)
+                    .filter(|i| matches[*i as usize])
+                    .map(|i| (txids[i as usize], i))
+                    .collect();
+                assert_eq!(matched, expected);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_partial_trees() -> Result<()> {
+        let txids: Vec<_> = (0..5u8).map(|i| Txid::from_bytes([i; 32])).collect();
+        let tree = PartialMerkleTree::new(&txids, &[false, true, false, false, true])?;
+
+        let mut extra_hash = tree.clone();
+        extra_hash.hashes.push([0; 32]);
+        assert!(extra_hash.extract_matches(&mut Vec::new()).is_err());
+
+        let mut missing_flags = tree.clone();
+        missing_flags.flags.truncate(3);
+        assert!(missing_flags.extract_matches(&mut Vec::new()).is_err());
+
+        let mut extra_flags = tree.clone();
+        extra_flags.flags.extend(vec![false; 8]);
+        assert!(extra_flags.extract_matches(&mut Vec::new()).is_err());
+
+        let mut empty = tree;
+        empty.total = 0;
+        assert!(empty.extract_matches(&mut Vec::new()).is_err());
+        assert!(PartialMerkleTree::new(&[], &[]).is_err());
+
+        // the last leaf repeated makes a tree with the same root
+        let mut repeated = txids[..3].to_vec();
+        repeated.push(repeated[2]);
+        let tree = PartialMerkleTree::new(&repeated, &[false, false, true, true])?;
+        assert!(matches!(
+            tree.extract_matches(&mut Vec::new()),
+            Err(Error::InvalidBlock("duplicate partial merkle tree nodes"))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn merkle_blocks() -> Result<()> {
+        let txdata: Vec<_> = (0..4)
+            .map(|height| Transaction::new_coinbase(height, &[], vec![], None))
+            .collect::<crate::Result<_>>()?;
+        let txids: Vec<_> = txdata
+            .iter()
+            .map(|tx| tx.txid())
+            .collect::<crate::Result<_>>()?;
+        let header = BlockHeader::new(1, BlockHash::default(), root_of(&txids), 0, 0, 0);
+        let block = Block::new(header, txdata);
+
+        let merkle_block = MerkleBlock::from_block(&block, |txid| *txid == txids[2])?;
+        let bytes = consensus::serialize(&merkle_block)?;
+        assert_eq!(&bytes[..80], &header.serialize()[..]);
+        // the flags come back padded to whole bytes
+        let decoded: MerkleBlock = consensus::deserialize(&bytes)?;
+        assert_eq!(consensus::serialize(&decoded)?, bytes);
+        assert_eq!(decoded.extract_matches()?, vec![(txids[2], 2)]);
+
+        let mut other = decoded;
+        other.header.merkle_root = [0; 32];
+        assert!(other.extract_matches().is_err());
+
+        // not even a coinbase to prove
+        let empty = Block::new(header, vec![]);
+        assert!(MerkleBlock::from_block(&empty, |_| true).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,516 @@
+//! Block templates: candidate transactions picked by ancestor fee rate, like
+//! Bitcoin Core's `BlockAssembler`, under a coinbase paying the subsidy and fees
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+use crate::amount::Amount;
+use crate::network::Network;
+use crate::varint;
+use crate::{Error, Result};
+
+use super::block::{Block, BlockHeader, MAX_BLOCK_SIGOPS_COST, MAX_BLOCK_WEIGHT};
+use super::coinbase::{block_subsidy, witness_commitment};
+use super::output::TxOut;
+use super::script::Script;
+use super::tx::Transaction;
+use super::txid::{BlockHash, Txid};
+
+/// Room left for the coinbase, as Bitcoin Core does
+const COINBASE_RESERVED_WEIGHT: usize = 4_000;
+const COINBASE_RESERVED_SIGOPS: usize = 400;
+/// Version signaling no soft fork (BIP9)
+const TEMPLATE_VERSION: i32 = 0x2000_0000;
+
+/// A transaction the template can include, any of its inputs spending another
+/// candidate makes that one an ancestor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    pub(crate) tx: Transaction,
+    pub(crate) fee: Amount,
+    pub(crate) weight: usize,
+    pub(crate) sigop_cost: usize,
+}
+
+impl Candidate {
+    /// Only the legacy signature checks are counted, see
+    /// [`Candidate::with_sigop_cost`]
+    pub fn new(tx: Transaction, fee: Amount) -> Result<Self> {
+        Ok(Self {
+            weight: tx.weight()?,
+            sigop_cost: tx.sigop_cost(&[]),
+            tx,
+            fee,
+        })
+    }
+
+    /// The full cost, from [`Transaction::sigop_cost`] with the spent outputs
+    pub fn with_sigop_cost(mut self, sigop_cost: usize) -> Self {
+        self.sigop_cost = sigop_cost;
+        self
+    }
+
+    pub fn tx(&self) -> &Transaction {
+        &self.tx
+    }
+
+    pub fn fee(&self) -> Amount {
+        self.fee
+    }
+}
+
+/// The block at `height` on top of `prev_blockhash` waiting for a nonce
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockTemplate {
+    pub(crate) block: Block,
+    pub(crate) height: u32,
+    pub(crate) fees: Amount,
+    pub(crate) sigop_cost: usize,
+}
+
+impl BlockTemplate {
+    pub fn block(&self) -> &Block {
+        &self.block
+    }
+
+    pub fn header(&self) -> &BlockHeader {
+        self.block.header()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Fees of every transaction, claimed by the coinbase
+    pub fn fees(&self) -> Amount {
+        self.fees
+    }
+
+    /// Weighted signature checks of the selected transactions
+    pub fn sigop_cost(&self) -> usize {
+        self.sigop_cost
+    }
+
+    pub fn into_block(self) -> Block {
+        self.block
+    }
+}
+
+/// What the template builds on and pays to, the subsidy follows the mainnet
+/// schedule unless set
+#[derive(Debug, Clone)]
+pub struct BlockTemplateBuilder {
+    pub(crate) prev_blockhash: BlockHash,
+    pub(crate) height: u32,
+    pub(crate) time: u32,
+    pub(crate) bits: u32,
+    pub(crate) version: i32,
+    pub(crate) script_pubkey: Script,
+    pub(crate) subsidy: Amount,
+    pub(crate) extra_nonce: Vec<u8>,
+    pub(crate) max_weight: usize,
+    pub(crate) max_sigops_cost: usize,
+}
+
+impl BlockTemplateBuilder {
+    pub fn new(
+        prev_blockhash: BlockHash,
+        height: u32,
+        time: u32,
+        bits: u32,
+        script_pubkey: Script,
+    ) -> Self {
+        Self {
+            prev_blockhash,
+            height,
+            time,
+            bits,
+            version: TEMPLATE_VERSION,
+            script_pubkey,
+            subsidy: block_subsidy(height),
+            extra_nonce: Vec::new(),
+            max_weight: MAX_BLOCK_WEIGHT,
+            max_sigops_cost: MAX_BLOCK_SIGOPS_COST,
+        }
+    }
+
+    pub fn with_version(mut self, version: i32) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// For networks with another halving interval, see
+    /// [`Params::subsidy_at_height`](crate::params::Params::subsidy_at_height)
+    pub fn with_subsidy(mut self, subsidy: Amount) -> Self {
+        self.subsidy = subsidy;
+        self
+    }
+
+    /// Pushed after the height in the coinbase script sig
+    pub fn with_extra_nonce(mut self, extra_nonce: &[u8]) -> Self {
+        self.extra_nonce = extra_nonce.to_vec();
+        self
+    }
+
+    /// Lower limits than the consensus ones, the coinbase still gets its room
+    pub fn with_limits(mut self, max_weight: usize, max_sigops_cost: usize) -> Self {
+        self.max_weight = max_weight.min(MAX_BLOCK_WEIGHT);
+        self.max_sigops_cost = max_sigops_cost.min(MAX_BLOCK_SIGOPS_COST);
+        self
+    }
+
+    /// Picks the candidates with their ancestors by the fee rate of each
+    /// package until nothing else fits, and builds the coinbase with a witness
+    /// commitment on top of them
+    pub fn build(&self, candidates: &[Candidate]) -> Result<BlockTemplate> {
+        let selected = self.select(candidates)?;
+        let fees = selected.iter().map(|index| candidates[*index].fee).sum();
+        let sigop_cost = selected
+            .iter()
+            .map(|index| candidates[*index].sigop_cost)
+            .sum();
+        let txs: Vec<_> = selected
+            .into_iter()
+            .map(|index| candidates[index].tx.clone())
+            .collect();
+
+        let wtxids = txs
+            .iter()
+            .map(Transaction::wtxid)
+            .collect::<Result<Vec<_>>>()?;
+        let commitment = witness_commitment(&wtxids, &[0; 32]);
+        let output = TxOut::new(self.subsidy + fees, self.script_pubkey.clone());
+        let coinbase = Transaction::new_coinbase(
+            self.height,
+            &self.extra_nonce,
+            vec![output],
+            Some(&commitment),
+        )?;
+
+        let mut txdata = vec![coinbase];
+        txdata.extend(txs);
+        let header = BlockHeader::new(
+            self.version,
+            self.prev_blockhash,
+            [0; 32],
+            self.time,
+            self.bits,
+            0,
+        );
+        let mut block = Block::new(header, txdata);
+        block.header.merkle_root = block.compute_merkle_root()?.0;
+
+        Ok(BlockTemplate {
+            block,
+            height: self.height,
+            fees,
+            sigop_cost,
+        })
+    }
+
+    /// Indexes of the selected candidates, parents before children
+    fn select(&self, candidates: &[Candidate]) -> Result<Vec<usize>> {
+        let ancestors = ancestors(candidates)?;
+
+        // the header and the transaction count as non witness data
+        let mut weight = COINBASE_RESERVED_WEIGHT
+            + (BlockHeader::SIZE + varint::encoded_len(candidates.len() as u64 + 1)) * 4;
+        let mut sigop_cost = COINBASE_RESERVED_SIGOPS;
+        let mut included = HashSet::new();
+        let mut failed = HashSet::new();
+        let mut selected = Vec::new();
+
+        loop {
+            // the package of each candidate left is it and its ancestors left
+            let best = (0..candidates.len())
+                .filter(|index| !included.contains(index) && !failed.contains(index))
+                .map(|index| {
+                    let package: Vec<_> = ancestors[index]
+                        .iter()
+                        .copied()
+                        .chain(Some(index))
+                        .filter(|member| !included.contains(member))
+                        .collect();
+                    let fee: Amount = package.iter().map(|member| candidates[*member].fee).sum();
+                    let weight: usize = package
+                        .iter()
+                        .map(|member| candidates[*member].weight)
+                        .sum();
+                    (index, package, fee, weight)
+                })
+                .max_by(
+                    |(first, _, first_fee, first_weight),
+                     (second, _, second_fee, second_weight)| {
+                        compare_fee_rates(
+                            (*first_fee, *first_weight),
+                            (*second_fee, *second_weight),
+                        )
+                        // the first candidate on ties
+                        .then(second.cmp(first))
+                    },
+                );
+
+            let (index, mut package, _, package_weight) = match best {
+                Some(best) => best,
+                None => break,
+            };
+
+            let package_sigops: usize = package
+                .iter()
+                .map(|member| candidates[*member].sigop_cost)
+                .sum();
+            if weight + package_weight > self.max_weight
+                || sigop_cost + package_sigops > self.max_sigops_cost
+            {
+                failed.insert(index);
+                continue;
+            }
+
+            // ancestors have fewer ancestors than their descendants
+            package.sort_by_key(|member| (ancestors[*member].len(), *member));
+            weight += package_weight;
+            sigop_cost += package_sigops;
+            included.extend(package.iter().copied());
+            selected.extend(package);
+        }
+
+        Ok(selected)
+    }
+}
+
+/// Mines a regtest block at `height` on top of `prev_header` with all of `txs`,
+/// a second after it. Their fees are left unclaimed, as they aren't known
+pub fn mine_block(
+    prev_header: &BlockHeader,
+    height: u32,
+    txs: &[Transaction],
+    script_pubkey: Script,
+) -> Result<Block> {
+    let params = Network::Regtest.params();
+    let candidates = txs
+        .iter()
+        .map(|tx| Candidate::new(tx.clone(), Amount::ZERO))
+        .collect::<Result<Vec<_>>>()?;
+    let template = BlockTemplateBuilder::new(
+        prev_header.block_hash(),
+        height,
+        prev_header.time() + 1,
+        params.pow.pow_limit,
+        script_pubkey,
+    )
+    .with_subsidy(params.subsidy_at_height(height))
+    .build(&candidates)?;
+
+    let mut block = template.into_block();
+    if block.transactions().len() != txs.len() + 1 {
+        return Err(Error::InvalidBlock("transactions don't fit in a block"));
+    }
+
+    // about every other nonce works at the regtest target
+    while block.header.validate_pow().is_err() {
+        block.header.nonce = block
+            .header
+            .nonce
+            .checked_add(1)
+            .ok_or(Error::InvalidBlock("no nonce meets the target"))?;
+    }
+    Ok(block)
+}
+
+/// Indexes of the candidates each one spends from, directly or not
+fn ancestors(candidates: &[Candidate]) -> Result<Vec<HashSet<usize>>> {
+    let indexes = candidates
+        .iter()
+        .enumerate()
+        .map(|(index, candidate)| Ok((candidate.tx.txid()?, index)))
+        .collect::<Result<HashMap<Txid, usize>>>()?;
+
+    let parents: Vec<HashSet<usize>> = candidates
+        .iter()
+        .map(|candidate| {
+            candidate
+                .tx
+                .inputs()
+                .iter()
+                .filter_map(|input| indexes.get(input.previous_output().txid()).copied())
+                .collect()
+        })
+        .collect();
+
+    Ok((0..candidates.len())
+        .map(|index| {
+            let mut ancestors = HashSet::new();
+            let mut pending: Vec<_> = parents[index].iter().copied().collect();
+            while let Some(ancestor) = pending.pop() {
+                if ancestor != index && ancestors.insert(ancestor) {
+                    pending.extend(parents[ancestor].iter().copied());
+                }
+            }
+            ancestors
+        })
+        .collect())
+}
+
+/// Compares `fee / weight` rates without rounding
+fn compare_fee_rates(first: (Amount, usize), second: (Amount, usize)) -> Ordering {
+    let first_rate = u128::from(first.0.to_sat()) * second.1 as u128;
+    let second_rate = u128::from(second.0.to_sat()) * first.1 as u128;
+    first_rate.cmp(&second_rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::core::chain::{ChainEvent, HeaderChain};
+    use crate::core::input::{OutPoint, TxIn};
+    use crate::core::witness::Witness;
+
+    use super::*;
+
+    fn spend(txid: Txid, witness: bool) -> Transaction {
+        let mut input = TxIn::new(OutPoint::new(txid, 0));
+        if witness {
+            input.witness = Witness::p2wpkh(&[0x30; 72], &[0x02; 33]);
+        }
+        let output = TxOut::new(Amount::from_sat(10_000), Script::p2wpkh(&[0x33; 20]));
+        Transaction::new(2, vec![input], vec![output], 0)
+    }
+
+    fn builder() -> BlockTemplateBuilder {
+        BlockTemplateBuilder::new(
+            BlockHash::default(),
+            300_000,
+            1_700_000_000,
+            0x207f_ffff,
+            Script::p2wpkh(&[0x44; 20]),
+        )
+    }
+
+    #[test]
+    fn coinbase_and_commitment() -> Result<()> {
+        let first = spend(Txid::from_bytes([1; 32]), true);
+        let second = spend(Txid::from_bytes([2; 32]), false);
+        let candidates = vec![
+            Candidate::new(first.clone(), Amount::from_sat(1_000))?,
+            Candidate::new(second.clone(), Amount::from_sat(2_000))?,
+        ];
+
+        let template = builder().with_extra_nonce(b"nonce").build(&candidates)?;
+        assert_eq!(template.height(), 300_000);
+        assert_eq!(template.fees(), Amount::from_sat(3_000));
+        assert_eq!(template.sigop_cost(), 0);
+
+        // the higher fee rate first
+        let block = template.block();
+        assert_eq!(block.transactions()[1..], [second, first]);
+        let coinbase = &block.transactions()[0];
+        assert_eq!(coinbase.coinbase_height(), Some(300_000));
+        assert_eq!(
+            coinbase.outputs()[0].amount,
+            block_subsidy(300_000) + Amount::from_sat(3_000)
+        );
+        assert_eq!(template.header().prev_blockhash(), &BlockHash::default());
+        assert_eq!(template.header().nonce(), 0);
+
+        block.check_merkle_root()?;
+        block.check_witness_commitment()?;
+        block.check_limits(Some(300_000))?;
+
+        let empty = builder().with_subsidy(Amount::ZERO).build(&[])?;
+        assert_eq!(empty.block().transactions().len(), 1);
+        assert_eq!(
+            empty.block().transactions()[0].outputs()[0].amount,
+            Amount::ZERO
+        );
+        empty.block().check_witness_commitment()?;
+        Ok(())
+    }
+
+    #[test]
+    fn mine_regtest_blocks() -> Result<()> {
+        let params = Network::Regtest.params();
+        let mut chain = HeaderChain::for_network(Network::Regtest);
+        let script_pubkey = Script::p2wpkh(&[0x44; 20]);
+
+        let mut prev = *params.genesis_block().header();
+        let mut coinbases = Vec::new();
+        for height in 1..=3 {
+            let block = mine_block(&prev, height, &[], script_pubkey.clone())?;
+            block.check_limits(Some(height))?;
+            assert!(matches!(
+                chain.accept(*block.header())?,
+                ChainEvent::Extended
+            ));
+            prev = *block.header();
+            coinbases.push(block.transactions()[0].clone());
+        }
+        assert_eq!(chain.height(), 3);
+        assert_eq!(
+            coinbases[0].outputs()[0].amount,
+            params.subsidy_at_height(1)
+        );
+
+        // spending the first coinbase
+        let tx = spend(coinbases[0].txid()?, true);
+        let block = mine_block(&prev, 4, std::slice::from_ref(&tx), script_pubkey)?;
+        assert_eq!(block.transactions()[1], tx);
+        block.check_merkle_root()?;
+        block.check_witness_commitment()?;
+        block.check_limits(Some(4))?;
+        assert!(matches!(
+            chain.accept(*block.header())?,
+            ChainEvent::Extended
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn ancestor_fee_rates() -> Result<()> {
+        // a low fee parent bumped by its child, and an unrelated middle one
+        let parent = spend(Txid::from_bytes([1; 32]), false);
+        let child = spend(parent.txid()?, false);
+        let middle = spend(Txid::from_bytes([2; 32]), false);
+        let candidates = vec![
+            Candidate::new(child.clone(), Amount::from_sat(10_000))?,
+            Candidate::new(middle.clone(), Amount::from_sat(3_000))?,
+            Candidate::new(parent.clone(), Amount::from_sat(100))?,
+        ];
+
+        let all = builder().build(&candidates)?;
+        assert_eq!(
+            all.block().transactions()[1..],
+            [parent.clone(), child.clone(), middle.clone()]
+        );
+
+        // room for two, the package is worth more than the middle one
+        let weight = parent.weight()?;
+        let base = COINBASE_RESERVED_WEIGHT + (BlockHeader::SIZE + 1) * 4;
+        let two = builder()
+            .with_limits(base + weight * 5 / 2, MAX_BLOCK_SIGOPS_COST)
+            .build(&candidates)?;
+        assert_eq!(two.block().transactions()[1..], [parent.clone(), child]);
+        assert_eq!(two.fees(), Amount::from_sat(10_100));
+
+        // room for one, the package doesn't fit and the next best does
+        let one = builder()
+            .with_limits(base + weight * 3 / 2, MAX_BLOCK_SIGOPS_COST)
+            .build(&candidates)?;
+        assert_eq!(one.block().transactions()[1..], [middle]);
+
+        // too many signature checks
+        let candidates = vec![
+            Candidate::new(parent.clone(), Amount::from_sat(5_000))?.with_sigop_cost(1_000),
+            Candidate::new(
+                spend(Txid::from_bytes([3; 32]), false),
+                Amount::from_sat(100),
+            )?,
+        ];
+        let limited = builder()
+            .with_limits(MAX_BLOCK_WEIGHT, COINBASE_RESERVED_SIGOPS + 999)
+            .build(&candidates)?;
+        assert_eq!(limited.block().transactions().len(), 2);
+        assert_ne!(limited.block().transactions()[1], parent);
+        Ok(())
+    }
+}
@@ -1,5 +1,30 @@
+pub mod asm;
+pub mod block;
+pub mod bloom;
+pub mod chain;
+pub mod coin_selection;
+pub mod coinbase;
+pub mod fee;
 pub mod fetcher;
+pub mod filter;
 pub mod input;
+pub mod interpreter;
+pub mod locktime;
+pub mod merkle;
+pub mod mining;
+pub mod multisig;
+pub mod opcode;
 pub mod output;
+pub mod policy;
+pub mod pow;
+pub mod rbf;
 pub mod script;
+pub mod sighash;
+pub mod sign;
+pub mod signet;
+pub mod tapmultisig;
+pub mod timelock;
 pub mod tx;
+pub mod txid;
+pub mod verify;
+pub mod witness;
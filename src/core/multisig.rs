@@ -0,0 +1,482 @@
+//! m-of-n `OP_CHECKMULTISIG` spends, keys sorted as in BIP67 so every signer
+//! builds the same script
+
+use sha2::{Digest, Sha256};
+
+use crate::amount::Amount;
+use crate::secp256k1::crypto::{PrivateKey, PublicKey};
+use crate::secp256k1::signature::Signature;
+use crate::utils::hash160;
+use crate::{Error, Result};
+
+use super::opcode::Opcode;
+use super::script::{push_slice, Instruction, Script};
+use super::sighash::SIGHASH_ALL;
+use super::tx::Transaction;
+use super::witness::Witness;
+
+const OP_0: u8 = 0x00;
+const OP_1: u8 = 0x51;
+const OP_EQUAL: u8 = 0x87;
+const OP_HASH160: u8 = 0xa9;
+const OP_CHECKMULTISIG: u8 = 0xae;
+
+/// Keys allowed by `OP_CHECKMULTISIG`
+const MAX_KEYS: usize = 20;
+/// Keys fitting the 520 bytes limit of a P2SH redeem script
+const MAX_P2SH_KEYS: usize = 15;
+
+/// How the multisig script is committed to by the output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MultisigKind {
+    P2sh,
+    P2wsh,
+    /// P2WSH nested in P2SH
+    P2shP2wsh,
+}
+
+/// The script shared by the signers of an m-of-n multisig
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Multisig {
+    pub(crate) threshold: usize,
+    /// Compressed SEC encodings, sorted
+    pub(crate) keys: Vec<[u8; 33]>,
+    pub(crate) kind: MultisigKind,
+}
+
+impl Multisig {
+    /// `threshold` of the given keys must sign, their order doesn't matter
+    pub fn new(threshold: usize, keys: &[PublicKey], kind: MultisigKind) -> Result<Self> {
+        let max_keys = match kind {
+            MultisigKind::P2sh => MAX_P2SH_KEYS,
+            _ => MAX_KEYS,
+        };
+
+        if keys.len() > max_keys {
+            return Err(Error::InvalidMultisig("too many keys"));
+        }
+
+        if threshold == 0 || threshold > keys.len() {
+            return Err(Error::InvalidMultisig("threshold out of range"));
+        }
+
+        let mut keys = keys
+            .iter()
+            .map(PublicKey::serialize_compressed)
+            .collect::<Result<Vec<_>>>()?;
+        keys.sort_unstable();
+
+        Ok(Self {
+            threshold,
+            keys,
+            kind,
+        })
+    }
+
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    /// The compressed keys in script order
+    pub fn keys(&self) -> &[[u8; 33]] {
+        &self.keys
+    }
+
+    pub fn kind(&self) -> MultisigKind {
+        self.kind
+    }
+
+    /// `OP_m <keys> OP_n OP_CHECKMULTISIG`, the redeem script of P2SH and the
+    /// witness script otherwise
+    pub fn script(&self) -> Script {
+        multisig_script(self.threshold, &self.keys)
+    }
+
+    /// The output paying to this multisig
+    pub fn script_pubkey(&self) -> Script {
+        match self.kind {
+            MultisigKind::P2sh => p2sh(&self.script().bytes),
+            MultisigKind::P2wsh => Script::from(self.witness_program()),
+            MultisigKind::P2shP2wsh => p2sh(&self.witness_program()),
+        }
+    }
+
+    /// `SIGHASH_ALL` digest each signer signs for the input at `index`, `amount` is
+    /// the value it spends and only matters for segwit
+    pub fn sighash(&self, tx: &Transaction, index: usize, amount: Amount) -> Result<[u8; 32]> {
+        match self.kind {
+            MultisigKind::P2sh => tx.legacy_sighash(index, &self.script(), SIGHASH_ALL),
+            _ => tx.segwit_v0_sighash(index, &self.script(), amount, SIGHASH_ALL),
+        }
+    }
+
+    /// Partial signature of one signer, DER followed by the sighash byte
+    pub fn sign(
+        &self,
+        tx: &Transaction,
+        index: usize,
+        amount: Amount,
+        key: &PrivateKey,
+    ) -> Result<Vec<u8>> {
+        let sec = key.public_key().serialize_compressed()?;
+        if !self.keys.contains(&sec) {
+            return Err(Error::InvalidMultisig("key isn't part of the multisig"));
+        }
+
+        let digest = self.sighash(tx, index, amount)?;
+        let mut signature = key.create_signature(digest)?.serialize()?;
+        signature.push(SIGHASH_ALL as u8);
+        Ok(signature)
+    }
+
+    /// Set the script sig or witness of the input at `index` from the collected
+    /// partial signatures, checked against the sighash and put in key order.
+    /// Signatures beyond the threshold are left out
+    pub fn finalize(
+        &self,
+        tx: &mut Transaction,
+        index: usize,
+        amount: Amount,
+        signatures: &[(PublicKey, Vec<u8>)],
+    ) -> Result<()> {
+        let digest = self.sighash(tx, index, amount)?;
+
+        let mut ordered = vec![None; self.keys.len()];
+        for (public_key, signature) in signatures.iter() {
+            let sec = public_key.serialize_compressed()?;
+            let position = self
+                .keys
+                .iter()
+                .position(|key| *key == sec)
+                .ok_or(Error::InvalidMultisig("key isn't part of the multisig"))?;
+
+            let (sighash_type, der) = signature
+                .split_last()
+                .ok_or(Error::InvalidSignature("empty signature"))?;
+
+            if u32::from(*sighash_type) != SIGHASH_ALL {
+                return Err(Error::InvalidMultisig("unsupported sighash type"));
+            }
+
+            if !Signature::deserialize(der)?.is_valid(digest, public_key)? {
+                return Err(Error::InvalidSignature("doesn't sign the input"));
+            }
+
+            ordered[position] = Some(signature.clone());
+        }
+
+        let signatures: Vec<_> = ordered.into_iter().flatten().take(self.threshold).collect();
+        if signatures.len() < self.threshold {
+            return Err(Error::InvalidMultisig("not enough signatures"));
+        }
+
+        let script = self.script().bytes;
+        let input = tx
+            .inputs
+            .get_mut(index)
+            .ok_or(Error::InvalidTransaction("input index out of range"))?;
+
+        // the extra item popped by OP_CHECKMULTISIG goes first
+        match self.kind {
+            MultisigKind::P2sh => {
+                let mut script_sig = vec![OP_0];
+                for signature in signatures.iter() {
+                    push_slice(&mut script_sig, signature);
+                }
+
+                push_slice(&mut script_sig, &script);
+                input.script_sig = Script::from(script_sig);
+            }
+
+            MultisigKind::P2wsh | MultisigKind::P2shP2wsh => {
+                if self.kind == MultisigKind::P2shP2wsh {
+                    let mut script_sig = Vec::new();
+                    push_slice(&mut script_sig, &self.witness_program());
+                    input.script_sig = Script::from(script_sig);
+                }
+
+                let mut witness = vec![vec![]];
+                witness.extend(signatures);
+                witness.push(script);
+                input.witness = Witness::from(witness);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// `OP_0 <sha256 of the script>`
+    fn witness_program(&self) -> Vec<u8> {
+        let mut program = vec![OP_0];
+        push_slice(&mut program, &Sha256::digest(&self.script().bytes));
+        program
+    }
+}
+
+impl Script {
+    /// `OP_m <keys> OP_n OP_CHECKMULTISIG` with the compressed keys, in the given
+    /// order or `sorted` as in BIP67
+    pub fn multisig(threshold: usize, keys: &[PublicKey], sorted: bool) -> Result<Self> {
+        if keys.len() > MAX_KEYS {
+            return Err(Error::InvalidMultisig("too many keys"));
+        }
+
+        if threshold == 0 || threshold > keys.len() {
+            return Err(Error::InvalidMultisig("threshold out of range"));
+        }
+
+        let mut keys = keys
+            .iter()
+            .map(PublicKey::serialize_compressed)
+            .collect::<Result<Vec<_>>>()?;
+        if sorted {
+            keys.sort_unstable();
+        }
+
+        Ok(multisig_script(threshold, &keys))
+    }
+
+    /// Threshold and keys of a `OP_m <keys> OP_n OP_CHECKMULTISIG` script
+    pub fn multisig_keys(&self) -> Option<(usize, Vec<PublicKey>)> {
+        let instructions = self.instructions().collect::<Result<Vec<_>>>().ok()?;
+        let (threshold, rest) = instructions.split_first()?;
+        let (checkmultisig, rest) = rest.split_last()?;
+        let (total, keys) = rest.split_last()?;
+
+        if *checkmultisig != Instruction::Op(Opcode::OP_CHECKMULTISIG) {
+            return None;
+        }
+
+        let threshold = read_number(threshold)?;
+        let total = read_number(total)?;
+        if total != keys.len() || threshold > total || total > MAX_KEYS {
+            return None;
+        }
+
+        let keys = keys
+            .iter()
+            .map(|key| match key {
+                Instruction::PushBytes(bytes) if matches!(bytes.len(), 33 | 65) => {
+                    PublicKey::deserialize(bytes).ok()
+                }
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        Some((threshold, keys))
+    }
+}
+
+fn multisig_script(threshold: usize, keys: &[[u8; 33]]) -> Script {
+    let mut script = Vec::new();
+    push_number(&mut script, threshold);
+    for key in keys.iter() {
+        push_slice(&mut script, key);
+    }
+
+    push_number(&mut script, keys.len());
+    script.push(OP_CHECKMULTISIG);
+    Script::from(script)
+}
+
+fn p2sh(script: &[u8]) -> Script {
+    let mut script_pubkey = vec![OP_HASH160];
+    push_slice(&mut script_pubkey, &hash160(script));
+    script_pubkey.push(OP_EQUAL);
+    Script::from(script_pubkey)
+}
+
+/// Small numbers use their own opcode, the rest a one byte push
+fn push_number(script: &mut Vec<u8>, number: usize) {
+    match number {
+        1..=16 => script.push(OP_1 - 1 + number as u8),
+        _ => script.extend_from_slice(&[1, number as u8]),
+    }
+}
+
+/// Inverse of `push_number`, only positive numbers
+fn read_number(instruction: &Instruction) -> Option<usize> {
+    match instruction {
+        Instruction::Op(opcode) => opcode.small_int().filter(|n| *n > 0).map(|n| n as usize),
+        Instruction::PushBytes([n @ 17..=0x7f]) => Some(*n as usize),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use hex_literal::hex;
+
+    use crate::core::input::{OutPoint, TxIn};
+    use crate::core::output::TxOut;
+    use crate::core::txid::Txid;
+
+    use super::*;
+
+    #[test]
+    fn bip67_sorted_script() -> Result<()> {
+        // first BIP67 test vector, 39bgKC7RFbpoCRbtD5KEdkYKtNyhpsNa3Z
+        let keys = [
+            PublicKey::deserialize(
+                &hex!("02ff12471208c14bd580709cb2358d98975247d8765f92bc25eab3b2763ed605f8")[..],
+            )?,
+            PublicKey::deserialize(
+                &hex!("02fe6f0a5a297eb38c391581c4413e084773ea23954d93f7753db7dc0adc188b2f")[..],
+            )?,
+        ];
+
+        let multisig = Multisig::new(2, &keys, MultisigKind::P2sh)?;
+        assert_eq!(
+            multisig.script().bytes,
+            hex!("522102fe6f0a5a297eb38c391581c4413e084773ea23954d93f7753db7dc0adc188b2f2102ff12471208c14bd580709cb2358d98975247d8765f92bc25eab3b2763ed605f852ae")
+        );
+        assert_eq!(
+            multisig.script_pubkey().bytes,
+            hex!("a91456be8ea93912f37685542a2a864a5600f88a675487")
+        );
+
+        // the order keys are given in doesn't matter
+        let reversed = [keys[1].clone(), keys[0].clone()];
+        assert_eq!(Multisig::new(2, &reversed, MultisigKind::P2sh)?, multisig);
+
+        assert!(Multisig::new(0, &keys, MultisigKind::P2sh).is_err());
+        assert!(Multisig::new(3, &keys, MultisigKind::P2wsh).is_err());
+        let many = vec![keys[0].clone(); 16];
+        assert!(Multisig::new(2, &many, MultisigKind::P2sh).is_err());
+        assert!(Multisig::new(2, &many, MultisigKind::P2wsh).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn multisig_scripts() -> Result<()> {
+        let keys: Vec<_> = (1..=3u32)
+            .map(|i| PrivateKey::new(2000 + i).public_key().clone())
+            .collect();
+
+        let sorted = Script::multisig(2, &keys, true)?;
+        assert_eq!(
+            sorted,
+            Multisig::new(2, &keys, MultisigKind::P2wsh)?.script()
+        );
+        let (threshold, parsed) = sorted.multisig_keys().unwrap();
+        assert_eq!(threshold, 2);
+        let sec = parsed
+            .iter()
+            .map(PublicKey::serialize_compressed)
+            .collect::<crate::Result<Vec<_>>>()?;
+        assert!(sec.windows(2).all(|pair| pair[0] <= pair[1]));
+
+        // unsorted keeps the given order
+        let unsorted = Script::multisig(1, &keys, false)?;
+        let (threshold, parsed) = unsorted.multisig_keys().unwrap();
+        assert_eq!(threshold, 1);
+        assert_eq!(parsed, keys);
+
+        // past 16 keys the counts are pushed
+        let many = vec![keys[0].clone(); 20];
+        let script = Script::multisig(17, &many, true)?;
+        assert_eq!(script.as_bytes()[..2], [0x01, 17]);
+        assert_eq!(script.multisig_keys().unwrap().0, 17);
+
+        assert!(Script::multisig(0, &keys, true).is_err());
+        assert!(Script::multisig(4, &keys, true).is_err());
+        assert!(Script::multisig(1, &[], true).is_err());
+        assert!(Script::multisig(1, &vec![keys[0].clone(); 21], true).is_err());
+
+        // threshold above the key count, or not a multisig at all
+        let mut bytes = sorted.bytes.clone();
+        bytes[0] = 0x54;
+        assert!(Script::from(bytes).multisig_keys().is_none());
+        assert!(Script::p2wpkh(&[0; 20]).multisig_keys().is_none());
+        assert!(Script::from(vec![0x51, 0x51, 0xae])
+            .multisig_keys()
+            .is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn sign_and_finalize() -> Result<()> {
+        let keys: Vec<_> = (1..=3u32).map(|i| PrivateKey::new(1000 + i)).collect();
+        let public_keys: Vec<_> = keys.iter().map(|key| key.public_key().clone()).collect();
+        let amount = Amount::from_sat(100_000);
+
+        for kind in [
+            MultisigKind::P2sh,
+            MultisigKind::P2wsh,
+            MultisigKind::P2shP2wsh,
+        ]
+        .iter()
+        {
+            let multisig = Multisig::new(2, &public_keys, *kind)?;
+            let input = TxIn::new(OutPoint::new(Txid::from_bytes([0x11; 32]), 0));
+            let output = TxOut::new(Amount::from_sat(90_000), Script::from(vec![0x51]));
+            let mut tx = Transaction::new(2, vec![input], vec![output], 0);
+
+            // every signer on its own, collected in any order
+            let signatures = vec![
+                (
+                    public_keys[2].clone(),
+                    multisig.sign(&tx, 0, amount, &keys[2])?,
+                ),
+                (
+                    public_keys[0].clone(),
+                    multisig.sign(&tx, 0, amount, &keys[0])?,
+                ),
+            ];
+
+            let outsider = PrivateKey::new(7u32);
+            assert!(multisig.sign(&tx, 0, amount, &outsider).is_err());
+            assert!(multisig
+                .finalize(&mut tx, 0, amount, &signatures[..1])
+                .is_err());
+
+            // a signature of another signer's key
+            let forged = vec![(public_keys[1].clone(), signatures[0].1.clone())];
+            assert!(multisig.finalize(&mut tx, 0, amount, &forged).is_err());
+
+            multisig.finalize(&mut tx, 0, amount, &signatures)?;
+            let position = |signature: &[u8]| {
+                let (key, _) = signatures.iter().find(|(_, sig)| sig == signature)?;
+                let sec = key.serialize_compressed().ok()?;
+                multisig.keys.iter().position(|key| *key == sec)
+            };
+
+            let script = multisig.script().bytes;
+            let input = &tx.inputs[0];
+            let placed = match kind {
+                MultisigKind::P2sh => {
+                    assert!(input.witness.is_empty());
+                    let script_sig = &input.script_sig.bytes;
+                    assert_eq!(script_sig[0], OP_0);
+                    assert!(script_sig.ends_with(&script));
+
+                    let first = &script_sig[2..2 + script_sig[1] as usize];
+                    let rest = &script_sig[2 + first.len()..];
+                    let second = &rest[1..1 + rest[0] as usize];
+                    vec![first.to_vec(), second.to_vec()]
+                }
+
+                _ => {
+                    assert_eq!(input.witness.len(), 4);
+                    assert!(input.witness[0].is_empty());
+                    assert_eq!(input.witness[3], script);
+
+                    let expected = match kind {
+                        MultisigKind::P2wsh => vec![],
+                        _ => {
+                            let program = multisig.witness_program();
+                            [&[program.len() as u8][..], &program].concat()
+                        }
+                    };
+                    assert_eq!(input.script_sig.bytes, expected);
+                    input.witness[1..3].to_vec()
+                }
+            };
+
+            assert!(position(&placed[0]) < position(&placed[1]));
+        }
+
+        Ok(())
+    }
+}
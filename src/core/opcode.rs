@@ -0,0 +1,315 @@
+//! Script opcodes, named like Bitcoin Core does
+
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use crate::{Error, Result};
+
+macro_rules! opcodes {
+    ($($name:ident = $byte:literal),* $(,)?) => {
+        /// Every opcode byte, direct pushes and unassigned bytes keep their value
+        #[allow(non_camel_case_types)]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum Opcode {
+            $($name,)*
+            /// Pushes the next 1 to 75 bytes
+            OP_PUSHBYTES(u8),
+            /// Bytes without an opcode, `OP_SUCCESSx` in tapscript
+            OP_UNKNOWN(u8),
+        }
+
+        impl From<u8> for Opcode {
+            fn from(byte: u8) -> Self {
+                match byte {
+                    $($byte => Opcode::$name,)*
+                    0x01..=0x4b => Opcode::OP_PUSHBYTES(byte),
+                    _ => Opcode::OP_UNKNOWN(byte),
+                }
+            }
+        }
+
+        impl From<Opcode> for u8 {
+            fn from(opcode: Opcode) -> Self {
+                match opcode {
+                    $(Opcode::$name => $byte,)*
+                    Opcode::OP_PUSHBYTES(byte) | Opcode::OP_UNKNOWN(byte) => byte,
+                }
+            }
+        }
+
+        /// Canonical names, `OP_PUSHBYTES_n` for direct pushes
+        impl Display for Opcode {
+            fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+                match self {
+                    $(Opcode::$name => fmt.write_str(stringify!($name)),)*
+                    Opcode::OP_PUSHBYTES(len) => write!(fmt, "OP_PUSHBYTES_{}", len),
+                    Opcode::OP_UNKNOWN(byte) => write!(fmt, "OP_UNKNOWN_{:#04x}", byte),
+                }
+            }
+        }
+
+        /// Named opcodes with or without the `OP_` prefix, plus the aliases Bitcoin
+        /// Core accepts
+        impl FromStr for Opcode {
+            type Err = Error;
+
+            fn from_str(name: &str) -> Result<Self> {
+                match name.strip_prefix("OP_").unwrap_or(name) {
+                    "FALSE" => Ok(Opcode::OP_0),
+                    "TRUE" => Ok(Opcode::OP_1),
+                    "NOP2" => Ok(Opcode::OP_CHECKLOCKTIMEVERIFY),
+                    "NOP3" => Ok(Opcode::OP_CHECKSEQUENCEVERIFY),
+                    $(name if name == &stringify!($name)[3..] => Ok(Opcode::$name),)*
+                    _ => Err(Error::InvalidScript("unknown opcode")),
+                }
+            }
+        }
+    };
+}
+
+opcodes!(
+    OP_0 = 0x00,
+    OP_PUSHDATA1 = 0x4c,
+    OP_PUSHDATA2 = 0x4d,
+    OP_PUSHDATA4 = 0x4e,
+    OP_1NEGATE = 0x4f,
+    OP_RESERVED = 0x50,
+    OP_1 = 0x51,
+    OP_2 = 0x52,
+    OP_3 = 0x53,
+    OP_4 = 0x54,
+    OP_5 = 0x55,
+    OP_6 = 0x56,
+    OP_7 = 0x57,
+    OP_8 = 0x58,
+    OP_9 = 0x59,
+    OP_10 = 0x5a,
+    OP_11 = 0x5b,
+    OP_12 = 0x5c,
+    OP_13 = 0x5d,
+    OP_14 = 0x5e,
+    OP_15 = 0x5f,
+    OP_16 = 0x60,
+    OP_NOP = 0x61,
+    OP_VER = 0x62,
+    OP_IF = 0x63,
+    OP_NOTIF = 0x64,
+    OP_VERIF = 0x65,
+    OP_VERNOTIF = 0x66,
+    OP_ELSE = 0x67,
+    OP_ENDIF = 0x68,
+    OP_VERIFY = 0x69,
+    OP_RETURN = 0x6a,
+    OP_TOALTSTACK = 0x6b,
+    OP_FROMALTSTACK = 0x6c,
+    OP_2DROP = 0x6d,
+    OP_2DUP = 0x6e,
+    OP_3DUP = 0x6f,
+    OP_2OVER = 0x70,
+    OP_2ROT = 0x71,
+    OP_2SWAP = 0x72,
+    OP_IFDUP = 0x73,
+    OP_DEPTH = 0x74,
+    OP_DROP = 0x75,
+    OP_DUP = 0x76,
+    OP_NIP = 0x77,
+    OP_OVER = 0x78,
+    OP_PICK = 0x79,
+    OP_ROLL = 0x7a,
+    OP_ROT = 0x7b,
+    OP_SWAP = 0x7c,
+    OP_TUCK = 0x7d,
+    OP_CAT = 0x7e,
+    OP_SUBSTR = 0x7f,
+    OP_LEFT = 0x80,
+    OP_RIGHT = 0x81,
+    OP_SIZE = 0x82,
+    OP_INVERT = 0x83,
+    OP_AND = 0x84,
+    OP_OR = 0x85,
+    OP_XOR = 0x86,
+    OP_EQUAL = 0x87,
+    OP_EQUALVERIFY = 0x88,
+    OP_RESERVED1 = 0x89,
+    OP_RESERVED2 = 0x8a,
+    OP_1ADD = 0x8b,
+    OP_1SUB = 0x8c,
+    OP_2MUL = 0x8d,
+    OP_2DIV = 0x8e,
+    OP_NEGATE = 0x8f,
+    OP_ABS = 0x90,
+    OP_NOT = 0x91,
+    OP_0NOTEQUAL = 0x92,
+    OP_ADD = 0x93,
+    OP_SUB = 0x94,
+    OP_MUL = 0x95,
+    OP_DIV = 0x96,
+    OP_MOD = 0x97,
+    OP_LSHIFT = 0x98,
+    OP_RSHIFT = 0x99,
+    OP_BOOLAND = 0x9a,
+    OP_BOOLOR = 0x9b,
+    OP_NUMEQUAL = 0x9c,
+    OP_NUMEQUALVERIFY = 0x9d,
+    OP_NUMNOTEQUAL = 0x9e,
+    OP_LESSTHAN = 0x9f,
+    OP_GREATERTHAN = 0xa0,
+    OP_LESSTHANOREQUAL = 0xa1,
+    OP_GREATERTHANOREQUAL = 0xa2,
+    OP_MIN = 0xa3,
+    OP_MAX = 0xa4,
+    OP_WITHIN = 0xa5,
+    OP_RIPEMD160 = 0xa6,
+    OP_SHA1 = 0xa7,
+    OP_SHA256 = 0xa8,
+    OP_HASH160 = 0xa9,
+    OP_HASH256 = 0xaa,
+    OP_CODESEPARATOR = 0xab,
+    OP_CHECKSIG = 0xac,
+    OP_CHECKSIGVERIFY = 0xad,
+    OP_CHECKMULTISIG = 0xae,
+    OP_CHECKMULTISIGVERIFY = 0xaf,
+    OP_NOP1 = 0xb0,
+    OP_CHECKLOCKTIMEVERIFY = 0xb1,
+    OP_CHECKSEQUENCEVERIFY = 0xb2,
+    OP_NOP4 = 0xb3,
+    OP_NOP5 = 0xb4,
+    OP_NOP6 = 0xb5,
+    OP_NOP7 = 0xb6,
+    OP_NOP8 = 0xb7,
+    OP_NOP9 = 0xb8,
+    OP_NOP10 = 0xb9,
+    OP_CHECKSIGADD = 0xba,
+    OP_INVALIDOPCODE = 0xff,
+);
+
+impl Opcode {
+    pub fn to_u8(self) -> u8 {
+        self.into()
+    }
+
+    /// Opcodes up to `OP_16`, all of them push something (`OP_RESERVED` counts,
+    /// like in Bitcoin Core)
+    pub fn is_push(self) -> bool {
+        self.to_u8() <= 0x60
+    }
+
+    /// The number pushed by `OP_1NEGATE`, `OP_0` and `OP_1` to `OP_16`
+    pub fn small_int(self) -> Option<i64> {
+        match self.to_u8() {
+            0x00 => Some(0),
+            0x4f => Some(-1),
+            byte @ 0x51..=0x60 => Some(i64::from(byte - 0x50)),
+            _ => None,
+        }
+    }
+
+    /// `OP_1ADD` up to `OP_WITHIN`, disabled ones included
+    pub fn is_arithmetic(self) -> bool {
+        (0x8b..=0xa5).contains(&self.to_u8())
+    }
+
+    /// Hashing and signature checking opcodes
+    pub fn is_crypto(self) -> bool {
+        matches!(self.to_u8(), 0xa6..=0xaf | 0xba)
+    }
+
+    /// Fail the script even when not executed, since 2010
+    pub fn is_disabled(self) -> bool {
+        matches!(
+            self,
+            Opcode::OP_CAT
+                | Opcode::OP_SUBSTR
+                | Opcode::OP_LEFT
+                | Opcode::OP_RIGHT
+                | Opcode::OP_INVERT
+                | Opcode::OP_AND
+                | Opcode::OP_OR
+                | Opcode::OP_XOR
+                | Opcode::OP_2MUL
+                | Opcode::OP_2DIV
+                | Opcode::OP_MUL
+                | Opcode::OP_DIV
+                | Opcode::OP_MOD
+                | Opcode::OP_LSHIFT
+                | Opcode::OP_RSHIFT
+        )
+    }
+
+    /// `OP_SUCCESSx` of BIP342, make a tapscript succeed as soon as it's parsed
+    pub fn is_success(self) -> bool {
+        matches!(
+            self.to_u8(),
+            0x50 | 0x62 | 0x7e..=0x81 | 0x83..=0x86 | 0x89..=0x8a | 0x8d..=0x8e | 0x95..=0x99 | 0xbb..=0xfe
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn byte_roundtrip() {
+        for byte in 0..=255u8 {
+            let opcode = Opcode::from(byte);
+            assert_eq!(opcode.to_u8(), byte);
+        }
+
+        assert_eq!(Opcode::from(0xac), Opcode::OP_CHECKSIG);
+        assert_eq!(Opcode::from(0x14), Opcode::OP_PUSHBYTES(20));
+        assert_eq!(Opcode::from(0xbb), Opcode::OP_UNKNOWN(0xbb));
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(Opcode::OP_CHECKSIGADD.to_string(), "OP_CHECKSIGADD");
+        assert_eq!(Opcode::OP_2DUP.to_string(), "OP_2DUP");
+        assert_eq!(Opcode::from(0x14).to_string(), "OP_PUSHBYTES_20");
+        assert_eq!(Opcode::from(0xfe).to_string(), "OP_UNKNOWN_0xfe");
+
+        assert_eq!(
+            "OP_CHECKSIG".parse::<Opcode>().unwrap(),
+            Opcode::OP_CHECKSIG
+        );
+        assert_eq!("2DUP".parse::<Opcode>().unwrap(), Opcode::OP_2DUP);
+        assert_eq!("OP_TRUE".parse::<Opcode>().unwrap(), Opcode::OP_1);
+        assert_eq!(
+            "NOP2".parse::<Opcode>().unwrap(),
+            Opcode::OP_CHECKLOCKTIMEVERIFY
+        );
+        assert!("OP_PUSHBYTES_20".parse::<Opcode>().is_err());
+        assert!("OP_CHECKSIGG".parse::<Opcode>().is_err());
+    }
+
+    #[test]
+    fn classification() {
+        assert!(Opcode::OP_16.is_push());
+        assert!(Opcode::OP_PUSHBYTES(33).is_push());
+        assert!(!Opcode::OP_NOP.is_push());
+        assert_eq!(Opcode::OP_1NEGATE.small_int(), Some(-1));
+        assert_eq!(Opcode::OP_16.small_int(), Some(16));
+        assert_eq!(Opcode::OP_PUSHBYTES(1).small_int(), None);
+
+        assert!(Opcode::OP_WITHIN.is_arithmetic());
+        assert!(!Opcode::OP_RIPEMD160.is_arithmetic());
+        assert!(Opcode::OP_CHECKSIGADD.is_crypto());
+        assert!(!Opcode::OP_EQUAL.is_crypto());
+
+        assert!(Opcode::OP_CAT.is_disabled());
+        assert!(!Opcode::OP_SIZE.is_disabled());
+
+        // BIP342: 80, 98, 126-129, 131-134, 137-138, 141-142, 149-153 and 187-254
+        let success: Vec<u8> = (0..=255u8)
+            .filter(|byte| Opcode::from(*byte).is_success())
+            .collect();
+        let mut expected = vec![80, 98];
+        expected.extend(126..=129);
+        expected.extend(131..=134);
+        expected.extend(137..=138);
+        expected.extend(141..=142);
+        expected.extend(149..=153);
+        expected.extend(187..=254);
+        assert_eq!(success, expected);
+    }
+}
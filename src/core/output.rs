@@ -1,38 +1,142 @@
-use byteorder::{LittleEndian, ReadBytesExt};
+use std::io::{Read, Write};
+
 use bytes::Buf;
 
-use crate::Result;
+use crate::amount::Amount;
+use crate::consensus::{self, Decodable, Encodable};
+use crate::varint;
+use crate::{Error, Result};
 
+use super::fee::FeeRate;
 use super::script::Script;
 
-#[derive(Debug, Clone)]
-pub struct Output {
-    pub(crate) amount: u64,
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxOut {
+    pub(crate) amount: Amount,
     pub(crate) script_pubkey: Script,
 }
 
-impl Output {
-    pub fn serialize(&self) -> Result<Vec<u8>> {
-        let result = self
-            .amount
-            .to_le_bytes()
-            .iter()
-            .copied()
-            .chain(self.script_pubkey.serialize()?)
-            .collect();
+impl TxOut {
+    pub fn new(amount: Amount, script_pubkey: Script) -> Self {
+        Self {
+            amount,
+            script_pubkey,
+        }
+    }
+
+    /// Zero value `OP_RETURN` output carrying `data`, at most the 80 bytes relayed by
+    /// default
+    pub fn op_return(data: &[u8]) -> Result<Self> {
+        let script_pubkey = Script::op_return(data);
+        if !script_pubkey.is_standard_op_return() {
+            return Err(Error::InvalidScript("data carrier above the standard size"));
+        }
+
+        Ok(Self::new(Amount::ZERO, script_pubkey))
+    }
+
+    pub fn amount(&self) -> Amount {
+        self.amount
+    }
+
+    pub fn script_pubkey(&self) -> &Script {
+        &self.script_pubkey
+    }
+
+    /// Smallest amount worth spending at `fee_rate`, the fee of the output plus
+    /// the input spending it. Unspendable outputs have none
+    pub fn dust_threshold(&self, fee_rate: FeeRate) -> Amount {
+        if self.script_pubkey.is_unspendable() {
+            return Amount::ZERO;
+        }
+
+        // outpoint, script sig length and sequence plus a signature and key, a
+        // quarter of it for witness programs
+        let script_len = self.script_pubkey.bytes.len();
+        let output_size = 8 + varint::encoded_len(script_len as u64) + script_len;
+        let input_size = match self.script_pubkey.is_witness_program() {
+            true => 32 + 4 + 1 + 107 / 4 + 4,
+            false => 32 + 4 + 1 + 107 + 4,
+        };
+
+        fee_rate.fee_for_vsize(output_size + input_size)
+    }
+
+    /// Whether relaying nodes at `fee_rate`, usually [`FeeRate::DUST_RELAY`],
+    /// reject it as dust
+    pub fn is_dust(&self, fee_rate: FeeRate) -> bool {
+        self.amount < self.dust_threshold(fee_rate)
+    }
 
-        Ok(result)
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        consensus::serialize(self)
     }
 
     pub fn deserialize(buf: impl Buf) -> Result<Self> {
-        let mut reader = buf.reader();
+        Self::consensus_decode(&mut buf.reader())
+    }
+}
 
-        let amount = reader.read_u64::<LittleEndian>()?;
-        let script_pubkey = Script::deserialize(reader.get_mut())?;
+impl Encodable for TxOut {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        Ok(self.amount.consensus_encode(writer)? + self.script_pubkey.consensus_encode(writer)?)
+    }
+}
 
+impl Decodable for TxOut {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
         Ok(Self {
-            amount,
-            script_pubkey,
+            amount: Amount::consensus_decode(reader)?,
+            script_pubkey: Script::consensus_decode(reader)?,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::core::script::MAX_OP_RETURN_RELAY;
+
+    use super::*;
+
+    #[test]
+    fn op_return_outputs() {
+        let output = TxOut::op_return(&[0x42; 80]).unwrap();
+        assert_eq!(output.amount(), Amount::ZERO);
+        assert_eq!(output.script_pubkey().len(), MAX_OP_RETURN_RELAY);
+        assert_eq!(output.dust_threshold(FeeRate::DUST_RELAY), Amount::ZERO);
+        assert!(matches!(
+            TxOut::op_return(&[0x42; 81]),
+            Err(Error::InvalidScript(_))
+        ));
+    }
+
+    #[test]
+    fn dust_thresholds() {
+        let output = |script: Vec<u8>| TxOut::new(Amount::from_sat(546), Script::from(script));
+        let p2pkh = output([&[0x76, 0xa9, 0x14][..], &[0; 20], &[0x88, 0xac]].concat());
+        let p2wpkh = output([&[0x00, 0x14][..], &[0; 20]].concat());
+        let p2tr = output([&[0x51, 0x20][..], &[0; 32]].concat());
+
+        // the usual limits at the default dust relay fee
+        let rate = FeeRate::DUST_RELAY;
+        assert_eq!(p2pkh.dust_threshold(rate), Amount::from_sat(546));
+        assert_eq!(p2wpkh.dust_threshold(rate), Amount::from_sat(294));
+        assert_eq!(p2tr.dust_threshold(rate), Amount::from_sat(330));
+        assert!(!p2pkh.is_dust(rate));
+        assert!(p2pkh.is_dust(FeeRate::from_sat_per_vb(4)));
+
+        let mut small = p2wpkh.clone();
+        small.amount = Amount::from_sat(293);
+        assert!(small.is_dust(rate));
+
+        let op_return = TxOut::new(Amount::ZERO, Script::from(vec![0x6a, 0x01, 0xff]));
+        assert!(!op_return.is_dust(rate));
+
+        // version 17 isn't a witness version
+        let unknown = output([&[0x61, 0x02][..], &[0; 2]].concat());
+        assert_eq!(
+            unknown.dust_threshold(rate),
+            Amount::from_sat((8 + 1 + 4 + 148) * 3)
+        );
+    }
+}
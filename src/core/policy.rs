@@ -0,0 +1,522 @@
+//! Standardness rules relaying nodes apply on top of consensus, following Bitcoin
+//! Core's `IsStandardTx`, `AreInputsStandard` and `IsWitnessStandard`
+
+use std::fmt::{self, Display, Formatter};
+
+use crate::taproot::TAPROOT_LEAF_TAPSCRIPT;
+use crate::{Error, Result};
+
+use super::fee::FeeRate;
+use super::input::TxIn;
+use super::interpreter::{MAX_PUBKEYS_PER_MULTISIG, TAPROOT_LEAF_MASK};
+use super::opcode::Opcode;
+use super::output::TxOut;
+use super::script::{script_num, Instruction, Script, ScriptType, MAX_OP_RETURN_RELAY};
+use super::tx::Transaction;
+
+/// Heaviest transaction relayed
+pub const MAX_STANDARD_TX_WEIGHT: usize = 400_000;
+/// Smallest serialization without witness, 64 bytes could pass as a merkle node
+pub const MIN_STANDARD_TX_NONWITNESS_SIZE: usize = 65;
+/// Enough for a 15-of-15 P2SH multisig with compressed keys
+pub const MAX_STANDARD_SCRIPTSIG_SIZE: usize = 1650;
+/// Signature checks allowed in a P2SH redeem script
+pub const MAX_P2SH_SIGOPS: usize = 15;
+/// Fifth of the block limit
+pub const MAX_STANDARD_TX_SIGOPS_COST: usize = 16_000;
+pub const MAX_STANDARD_P2WSH_SCRIPT_SIZE: usize = 3600;
+pub const MAX_STANDARD_P2WSH_STACK_ITEMS: usize = 100;
+pub const MAX_STANDARD_P2WSH_STACK_ITEM_SIZE: usize = 80;
+pub const MAX_STANDARD_TAPSCRIPT_STACK_ITEM_SIZE: usize = 80;
+/// Keys of a standard bare multisig
+const MAX_STANDARD_MULTISIG_KEYS: usize = 3;
+/// Legacy sigops weigh as much as their serialization
+const WITNESS_SCALE_FACTOR: usize = 4;
+const TX_MAX_STANDARD_VERSION: u32 = 3;
+
+/// Why a transaction isn't relayed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PolicyError {
+    Version,
+    /// Above [`MAX_STANDARD_TX_WEIGHT`]
+    TxSize,
+    /// Below [`MIN_STANDARD_TX_NONWITNESS_SIZE`]
+    TxSizeSmall,
+    ScriptSigSize {
+        index: usize,
+    },
+    ScriptSigNotPushOnly {
+        index: usize,
+    },
+    /// Output of an unknown template or a bare multisig of more than 3 keys
+    ScriptPubkey {
+        index: usize,
+    },
+    Dust {
+        index: usize,
+    },
+    /// Data carrier above [`MAX_OP_RETURN_RELAY`]
+    OpReturnSize {
+        index: usize,
+    },
+    MultiOpReturn,
+    /// Spends an output of an unknown template or an unknown witness version,
+    /// or a P2SH redeem script with too many signature checks
+    NonStandardInput {
+        index: usize,
+    },
+    NonStandardWitness {
+        index: usize,
+    },
+    /// Above [`MAX_STANDARD_TX_SIGOPS_COST`]
+    SigopsCost,
+}
+
+impl Display for PolicyError {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicyError::Version => fmt.write_str("unknown version"),
+            PolicyError::TxSize => fmt.write_str("too heavy"),
+            PolicyError::TxSizeSmall => fmt.write_str("too small without witness"),
+            PolicyError::ScriptSigSize { index } => {
+                write!(fmt, "script sig of input {} too long", index)
+            }
+            PolicyError::ScriptSigNotPushOnly { index } => {
+                write!(fmt, "script sig of input {} isn't push only", index)
+            }
+            PolicyError::ScriptPubkey { index } => {
+                write!(fmt, "output {} has a non standard script", index)
+            }
+            PolicyError::Dust { index } => write!(fmt, "output {} is dust", index),
+            PolicyError::OpReturnSize { index } => {
+                write!(fmt, "output {} carries too much data", index)
+            }
+            PolicyError::MultiOpReturn => fmt.write_str("more than one OP_RETURN output"),
+            PolicyError::NonStandardInput { index } => {
+                write!(fmt, "input {} spends a non standard output", index)
+            }
+            PolicyError::NonStandardWitness { index } => {
+                write!(fmt, "input {} has a non standard witness", index)
+            }
+            PolicyError::SigopsCost => fmt.write_str("too many signature checks"),
+        }
+    }
+}
+
+impl Script {
+    /// Signature checks in the script, `accurate` counts the keys of multisigs
+    /// preceded by their count instead of the maximum
+    pub fn sigop_count(&self, accurate: bool) -> usize {
+        let mut count = 0;
+        let mut last = None;
+        for instruction in self.instructions() {
+            let opcode = match instruction {
+                Ok(Instruction::Op(opcode)) => opcode,
+                Ok(Instruction::PushBytes(_)) => {
+                    last = None;
+                    continue;
+                }
+                Err(_) => break,
+            };
+
+            count += match opcode {
+                Opcode::OP_CHECKSIG | Opcode::OP_CHECKSIGVERIFY => 1,
+                Opcode::OP_CHECKMULTISIG | Opcode::OP_CHECKMULTISIGVERIFY => {
+                    match last.and_then(Opcode::small_int) {
+                        Some(keys @ 1..=16) if accurate => keys as usize,
+                        _ => MAX_PUBKEYS_PER_MULTISIG,
+                    }
+                }
+                _ => 0,
+            };
+            last = Some(opcode);
+        }
+
+        count
+    }
+
+    /// Last push of a push only script sig, the redeem script of P2SH spends
+    fn last_push(&self) -> Option<Vec<u8>> {
+        let mut last = None;
+        for instruction in self.instructions() {
+            last = match instruction.ok()? {
+                Instruction::PushBytes(data) => Some(data.to_vec()),
+                Instruction::Op(opcode) => Some(script_num(opcode.small_int()?)),
+            };
+        }
+
+        last
+    }
+}
+
+impl Transaction {
+    /// Rules checked without the spent outputs: version, size, script sigs and
+    /// outputs, dust at [`FeeRate::DUST_RELAY`] included
+    pub fn check_standard(&self) -> Result<()> {
+        let reject = |error| Err(Error::NonStandard(error));
+
+        if self.version == 0 || self.version > TX_MAX_STANDARD_VERSION {
+            return reject(PolicyError::Version);
+        }
+
+        if self.weight()? > MAX_STANDARD_TX_WEIGHT {
+            return reject(PolicyError::TxSize);
+        }
+
+        if self.base_size()? < MIN_STANDARD_TX_NONWITNESS_SIZE {
+            return reject(PolicyError::TxSizeSmall);
+        }
+
+        for (index, input) in self.inputs.iter().enumerate() {
+            if input.script_sig.len() > MAX_STANDARD_SCRIPTSIG_SIZE {
+                return reject(PolicyError::ScriptSigSize { index });
+            }
+
+            if !input.script_sig.is_push_only() {
+                return reject(PolicyError::ScriptSigNotPushOnly { index });
+            }
+        }
+
+        let mut op_returns = 0;
+        for (index, output) in self.outputs.iter().enumerate() {
+            match output.script_pubkey.classify() {
+                ScriptType::NonStandard => return reject(PolicyError::ScriptPubkey { index }),
+                ScriptType::Multisig => {
+                    let (_, keys) = output.script_pubkey.multisig_keys().unwrap(); // safe, classified
+                    if keys.len() > MAX_STANDARD_MULTISIG_KEYS {
+                        return reject(PolicyError::ScriptPubkey { index });
+                    }
+                }
+                ScriptType::OpReturn => {
+                    if output.script_pubkey.len() > MAX_OP_RETURN_RELAY {
+                        return reject(PolicyError::OpReturnSize { index });
+                    }
+
+                    op_returns += 1;
+                }
+                _ => {}
+            }
+
+            if output.is_dust(FeeRate::DUST_RELAY) {
+                return reject(PolicyError::Dust { index });
+            }
+        }
+
+        if op_returns > 1 {
+            return reject(PolicyError::MultiOpReturn);
+        }
+
+        Ok(())
+    }
+
+    /// Rules on what the inputs spend, `prevouts` in input order: known templates
+    /// and witness versions, P2SH signature checks, witness sizes and the total
+    /// signature check cost
+    pub fn check_inputs_standard(&self, prevouts: &[TxOut]) -> Result<()> {
+        let reject = |error| Err(Error::NonStandard(error));
+
+        if prevouts.len() != self.inputs.len() {
+            return Err(Error::InvalidTransaction("one prevout per input needed"));
+        }
+
+        for (index, (input, prevout)) in self.inputs.iter().zip(prevouts).enumerate() {
+            let script_pubkey = &prevout.script_pubkey;
+            match script_pubkey.classify() {
+                ScriptType::NonStandard | ScriptType::WitnessUnknown { .. } => {
+                    return reject(PolicyError::NonStandardInput { index });
+                }
+                ScriptType::P2sh => {
+                    let redeem_script = match input.script_sig.last_push() {
+                        Some(redeem_script) => Script::from(redeem_script),
+                        None => return reject(PolicyError::NonStandardInput { index }),
+                    };
+
+                    if redeem_script.sigop_count(true) > MAX_P2SH_SIGOPS {
+                        return reject(PolicyError::NonStandardInput { index });
+                    }
+                }
+                _ => {}
+            }
+
+            if !input.witness.is_empty() && !is_witness_standard(input, script_pubkey) {
+                return reject(PolicyError::NonStandardWitness { index });
+            }
+        }
+
+        if self.sigop_cost(prevouts) > MAX_STANDARD_TX_SIGOPS_COST {
+            return reject(PolicyError::SigopsCost);
+        }
+
+        Ok(())
+    }
+
+    /// Weighted signature checks, legacy ones count 4 and witness ones 1
+    pub fn sigop_cost(&self, prevouts: &[TxOut]) -> usize {
+        let legacy = self
+            .inputs
+            .iter()
+            .map(|input| input.script_sig.sigop_count(false))
+            .chain(
+                self.outputs
+                    .iter()
+                    .map(|output| output.script_pubkey.sigop_count(false)),
+            )
+            .sum::<usize>();
+
+        if self.is_coinbase() {
+            return legacy * WITNESS_SCALE_FACTOR;
+        }
+
+        let mut p2sh = 0;
+        let mut witness = 0;
+        for (input, prevout) in self.inputs.iter().zip(prevouts) {
+            let mut program = prevout.script_pubkey.clone();
+            if program.p2sh_hash().is_some() {
+                let redeem_script = match input.script_sig.last_push() {
+                    Some(redeem_script) => Script::from(redeem_script),
+                    None => continue,
+                };
+
+                p2sh += redeem_script.sigop_count(true);
+                program = redeem_script;
+            }
+
+            witness += match program.witness_program() {
+                Some((0, program)) if program.len() == 20 => 1,
+                Some((0, program)) if program.len() == 32 => input
+                    .witness
+                    .last()
+                    .map(|script| Script::from(script.clone()).sigop_count(true))
+                    .unwrap_or(0),
+                _ => 0,
+            };
+        }
+
+        (legacy + p2sh) * WITNESS_SCALE_FACTOR + witness
+    }
+}
+
+/// Witness size limits of P2WSH and tapscript spends, no annex
+fn is_witness_standard(input: &TxIn, script_pubkey: &Script) -> bool {
+    let mut program = script_pubkey.clone();
+    if script_pubkey.p2sh_hash().is_some() {
+        match input.script_sig.last_push() {
+            Some(redeem_script) => program = Script::from(redeem_script),
+            None => return false,
+        }
+    }
+
+    let witness = &input.witness;
+    match program.witness_program() {
+        Some((0, program)) if program.len() == 32 => {
+            let (script, stack) = witness.split_last().unwrap(); // safe, not empty
+            script.len() <= MAX_STANDARD_P2WSH_SCRIPT_SIZE
+                && stack.len() <= MAX_STANDARD_P2WSH_STACK_ITEMS
+                && stack
+                    .iter()
+                    .all(|item| item.len() <= MAX_STANDARD_P2WSH_STACK_ITEM_SIZE)
+        }
+        Some((1, program)) if program.len() == 32 && script_pubkey.p2sh_hash().is_none() => {
+            if witness.taproot_annex().is_some() {
+                return false;
+            }
+
+            match witness.taproot_control_block() {
+                Some(control_block)
+                    if control_block[0] & TAPROOT_LEAF_MASK == TAPROOT_LEAF_TAPSCRIPT =>
+                {
+                    let stack = &witness[..witness.len() - 2];
+                    stack
+                        .iter()
+                        .all(|item| item.len() <= MAX_STANDARD_TAPSCRIPT_STACK_ITEM_SIZE)
+                }
+                _ => true,
+            }
+        }
+        // witness data where there's no witness program fails consensus
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sha2::{Digest, Sha256};
+
+    use crate::amount::Amount;
+    use crate::core::input::OutPoint;
+    use crate::core::script::ScriptBuilder;
+    use crate::core::txid::Txid;
+    use crate::core::witness::Witness;
+    use crate::secp256k1::crypto::PrivateKey;
+
+    use super::*;
+
+    fn tx(outputs: Vec<TxOut>) -> Transaction {
+        let mut input = TxIn::new(OutPoint::new(Txid::from_bytes([0x11; 32]), 0));
+        input.witness = Witness::p2wpkh(&[0x30; 72], &[0x02; 33]);
+        Transaction::new(2, vec![input], outputs, 0)
+    }
+
+    fn rejection(result: Result<()>) -> Option<PolicyError> {
+        match result {
+            Err(Error::NonStandard(error)) => Some(error),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn standard_transactions() {
+        let payment = TxOut::new(Amount::from_sat(10_000), Script::p2wpkh(&[0x01; 20]));
+        let standard = tx(vec![payment.clone()]);
+        assert!(standard.check_standard().is_ok());
+
+        let mut version = standard.clone();
+        version.version = 4;
+        assert_eq!(
+            rejection(version.check_standard()),
+            Some(PolicyError::Version)
+        );
+
+        let dust = TxOut::new(Amount::from_sat(100), Script::p2wpkh(&[0x01; 20]));
+        assert_eq!(
+            rejection(tx(vec![payment.clone(), dust]).check_standard()),
+            Some(PolicyError::Dust { index: 1 })
+        );
+
+        let anyone = TxOut::new(Amount::from_sat(10_000), Script::from(vec![0x51]));
+        assert_eq!(
+            rejection(tx(vec![payment.clone(), anyone]).check_standard()),
+            Some(PolicyError::ScriptPubkey { index: 1 })
+        );
+
+        // bare multisig up to 3 keys
+        let keys: Vec<_> = (1..=4u32)
+            .map(|i| PrivateKey::new(i).public_key().clone())
+            .collect();
+        let bare = |n| {
+            let script = Script::multisig(1, &keys[..n], true).unwrap();
+            TxOut::new(Amount::from_sat(10_000), script)
+        };
+        assert!(tx(vec![bare(3)]).check_standard().is_ok());
+        assert_eq!(
+            rejection(tx(vec![bare(4)]).check_standard()),
+            Some(PolicyError::ScriptPubkey { index: 0 })
+        );
+
+        let data = TxOut::op_return(b"hello").unwrap();
+        assert!(tx(vec![payment.clone(), data.clone()])
+            .check_standard()
+            .is_ok());
+        assert_eq!(
+            rejection(tx(vec![data.clone(), data]).check_standard()),
+            Some(PolicyError::MultiOpReturn)
+        );
+        let large = TxOut::new(Amount::ZERO, Script::op_return(&[0; 81]));
+        assert_eq!(
+            rejection(tx(vec![payment.clone(), large]).check_standard()),
+            Some(PolicyError::OpReturnSize { index: 1 })
+        );
+
+        // 61 bytes without the witness
+        let small = tx(vec![TxOut::new(Amount::ZERO, Script::from(vec![0x6a]))]);
+        assert_eq!(
+            rejection(small.check_standard()),
+            Some(PolicyError::TxSizeSmall)
+        );
+
+        let mut not_push = standard;
+        not_push.inputs[0].script_sig = Script::from(vec![0x76]);
+        assert_eq!(
+            rejection(not_push.check_standard()),
+            Some(PolicyError::ScriptSigNotPushOnly { index: 0 })
+        );
+    }
+
+    #[test]
+    fn standard_inputs() {
+        let payment = TxOut::new(Amount::from_sat(10_000), Script::p2wpkh(&[0x01; 20]));
+        let prevout = |script| vec![TxOut::new(Amount::from_sat(20_000), script)];
+
+        let spend = tx(vec![payment.clone()]);
+        assert!(spend
+            .check_inputs_standard(&prevout(Script::p2wpkh(&[0x02; 20])))
+            .is_ok());
+        assert!(spend.check_inputs_standard(&[]).is_err());
+
+        let future = Script::from([&[0x52, 0x20][..], &[0x03; 32]].concat());
+        assert_eq!(
+            rejection(spend.check_inputs_standard(&prevout(future))),
+            Some(PolicyError::NonStandardInput { index: 0 })
+        );
+
+        // a redeem script with more than 15 signature checks
+        let redeem_script = (0..16)
+            .fold(ScriptBuilder::new(), |builder, _| {
+                builder.push_opcode(Opcode::OP_CHECKSIG)
+            })
+            .into_script();
+        let mut hash = [0; 20];
+        hash.copy_from_slice(&crate::utils::hash160(redeem_script.as_bytes()));
+        let mut p2sh = tx(vec![payment.clone()]);
+        p2sh.inputs[0].witness = Witness::new();
+        p2sh.inputs[0].script_sig = Script::p2sh_script_sig(&[], &redeem_script);
+        assert_eq!(
+            rejection(p2sh.check_inputs_standard(&prevout(Script::p2sh(&hash)))),
+            Some(PolicyError::NonStandardInput { index: 0 })
+        );
+
+        // P2WSH stack items of at most 80 bytes
+        let witness_script = Script::from(vec![0x75, 0x51]);
+        let mut program = [0; 32];
+        program.copy_from_slice(&Sha256::digest(witness_script.as_bytes()));
+        let mut p2wsh = tx(vec![payment.clone()]);
+        p2wsh.inputs[0].witness = Witness::p2wsh(&[vec![0; 80]], &witness_script);
+        assert!(p2wsh
+            .check_inputs_standard(&prevout(Script::p2wsh(&program)))
+            .is_ok());
+        p2wsh.inputs[0].witness = Witness::p2wsh(&[vec![0; 81]], &witness_script);
+        assert_eq!(
+            rejection(p2wsh.check_inputs_standard(&prevout(Script::p2wsh(&program)))),
+            Some(PolicyError::NonStandardWitness { index: 0 })
+        );
+
+        // no annex
+        let mut p2tr = tx(vec![payment]);
+        p2tr.inputs[0].witness = Witness::from(vec![vec![0x01; 64], vec![0x50]]);
+        assert_eq!(
+            rejection(p2tr.check_inputs_standard(&prevout(Script::p2tr(&[0x04; 32])))),
+            Some(PolicyError::NonStandardWitness { index: 0 })
+        );
+    }
+
+    #[test]
+    fn sigop_counts() {
+        assert_eq!(Script::p2pkh(&[0; 20]).sigop_count(false), 1);
+
+        let keys: Vec<_> = (1..=3u32)
+            .map(|i| PrivateKey::new(i).public_key().clone())
+            .collect();
+        let multisig = Script::multisig(2, &keys, true).unwrap();
+        assert_eq!(multisig.sigop_count(true), 3);
+        assert_eq!(multisig.sigop_count(false), MAX_PUBKEYS_PER_MULTISIG);
+
+        // legacy ones weigh 4 times as much
+        let payment = TxOut::new(Amount::from_sat(10_000), Script::p2pkh(&[0x01; 20]));
+        let spend = tx(vec![payment.clone()]);
+        let prevouts = vec![TxOut::new(
+            Amount::from_sat(20_000),
+            Script::p2wpkh(&[0x02; 20]),
+        )];
+        assert_eq!(spend.sigop_cost(&prevouts), 4 + 1);
+
+        let mut hash = [0; 20];
+        hash.copy_from_slice(&crate::utils::hash160(multisig.as_bytes()));
+        let mut p2sh = tx(vec![payment]);
+        p2sh.inputs[0].witness = Witness::new();
+        p2sh.inputs[0].script_sig =
+            Script::p2sh_script_sig(&[vec![], vec![0x30; 72], vec![0x30; 72]], &multisig);
+        let prevouts = vec![TxOut::new(Amount::from_sat(20_000), Script::p2sh(&hash))];
+        assert_eq!(p2sh.sigop_cost(&prevouts), 4 + 3 * 4);
+        assert!(p2sh.check_inputs_standard(&prevouts).is_ok());
+    }
+}
@@ -0,0 +1,387 @@
+//! Proof of work: targets in their compact `bits` form and the work they take
+
+use num_bigint::BigUint;
+use num_traits::{One, ToPrimitive, Zero};
+
+use crate::network::Network;
+use crate::{Error, Result};
+
+use super::block::BlockHeader;
+
+/// Difficulty rules of a network
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PowParams {
+    /// Easiest target, in compact form
+    pub pow_limit: u32,
+    /// Seconds each retarget period should take
+    pub target_timespan: u32,
+    /// Seconds between blocks
+    pub target_spacing: u32,
+    /// Testnet's rule, blocks found more than twice the spacing after the
+    /// previous one can be at the easiest target
+    pub allow_min_difficulty_blocks: bool,
+    /// Whether every block keeps the same target, like regtest
+    pub no_retargeting: bool,
+    /// Testnet4's fix of the timewarp attack (BIP94), retargets start from the
+    /// target of the first block of the period, never a minimum difficulty one
+    pub enforce_bip94: bool,
+}
+
+impl PowParams {
+    pub fn new(network: Network) -> Self {
+        let mainnet = Self {
+            pow_limit: 0x1d00_ffff,
+            target_timespan: 14 * 24 * 60 * 60,
+            target_spacing: 10 * 60,
+            allow_min_difficulty_blocks: false,
+            no_retargeting: false,
+            enforce_bip94: false,
+        };
+
+        match network {
+            Network::Mainnet => mainnet,
+            Network::Testnet => Self {
+                allow_min_difficulty_blocks: true,
+                ..mainnet
+            },
+            Network::Testnet4 => Self {
+                allow_min_difficulty_blocks: true,
+                enforce_bip94: true,
+                ..mainnet
+            },
+            Network::Signet => Self {
+                pow_limit: 0x1e03_77ae,
+                ..mainnet
+            },
+            Network::Regtest => Self {
+                pow_limit: 0x207f_ffff,
+                allow_min_difficulty_blocks: true,
+                no_retargeting: true,
+                ..mainnet
+            },
+        }
+    }
+
+    /// Blocks between retargets, 2016
+    pub fn adjustment_interval(&self) -> u32 {
+        self.target_timespan / self.target_spacing
+    }
+}
+
+/// Target of the period after the one that started at `first_time` and whose
+/// last block is at `last_time` with `last_bits`. Like Bitcoin Core the span is
+/// measured over the 2015 intervals of the period, not 2016, and clamped to a
+/// quarter and four times the expected one
+pub fn retarget(params: &PowParams, last_bits: u32, first_time: u32, last_time: u32) -> u32 {
+    if params.no_retargeting {
+        return last_bits;
+    }
+
+    let expected = i64::from(params.target_timespan);
+    let timespan = (i64::from(last_time) - i64::from(first_time)).clamp(expected / 4, expected * 4);
+
+    // invalid targets never make it into a chain, they're left as they are
+    let target = match compact_to_target(last_bits) {
+        Ok(target) => target,
+        Err(_) => return last_bits,
+    };
+
+    let pow_limit = compact_to_target(params.pow_limit).unwrap_or_default();
+    let target = (target * timespan as u64 / expected as u64).min(pow_limit);
+    target_to_compact(&target)
+}
+
+/// The `bits` the block after `prev`, at `prev_height`, must have when its time
+/// is `time`. `ancestor` gives the header of this chain at a height, the first
+/// of the period at retargets and the ones going back to the last regular
+/// target under testnet's minimum difficulty rule
+pub fn next_work_required(
+    params: &PowParams,
+    prev: &BlockHeader,
+    prev_height: u32,
+    time: u32,
+    ancestor: impl Fn(u32) -> Option<BlockHeader>,
+) -> Result<u32> {
+    let interval = params.adjustment_interval();
+    let height = prev_height + 1;
+    let missing = || Error::InvalidBlock("missing ancestor header");
+
+    if !height.is_multiple_of(interval) {
+        if !params.allow_min_difficulty_blocks {
+            return Ok(prev.bits);
+        }
+
+        if time > prev.time.saturating_add(params.target_spacing * 2) {
+            return Ok(params.pow_limit);
+        }
+
+        // the last block that wasn't mined at the easiest target
+        let mut last = *prev;
+        let mut last_height = prev_height;
+        while !last_height.is_multiple_of(interval) && last.bits == params.pow_limit {
+            last_height -= 1;
+            last = ancestor(last_height).ok_or_else(missing)?;
+        }
+
+        return Ok(last.bits);
+    }
+
+    let first = ancestor(height - interval).ok_or_else(missing)?;
+    let last_bits = match params.enforce_bip94 {
+        true => first.bits,
+        false => prev.bits,
+    };
+
+    Ok(retarget(params, last_bits, first.time, prev.time))
+}
+
+/// Target encoded in `bits`, a floating point number with a 8 bits exponent in
+/// bytes and a 24 bits mantissa whose top bit is the sign, like Bitcoin Core's
+/// `SetCompact`. Negative and overflowing targets fail
+pub fn compact_to_target(bits: u32) -> Result<BigUint> {
+    let size = bits >> 24;
+    let mut word = bits & 0x007f_ffff;
+    if size <= 3 {
+        word >>= 8 * (3 - size);
+    }
+
+    if word != 0 && bits & 0x0080_0000 != 0 {
+        return Err(Error::InvalidBlock("negative target"));
+    }
+
+    if word != 0 && (size > 34 || (word > 0xff && size > 33) || (word > 0xffff && size > 32)) {
+        return Err(Error::InvalidBlock("target overflow"));
+    }
+
+    Ok(match size {
+        0..=3 => BigUint::from(word),
+        _ => BigUint::from(word) << (8 * (size - 3)) as usize,
+    })
+}
+
+/// The most precise `bits` for `target`, the mantissa loses what doesn't fit
+pub fn target_to_compact(target: &BigUint) -> u32 {
+    let mut size = target.bits().div_ceil(8) as u32;
+    let mut compact = match size {
+        0..=3 => target.to_u32().unwrap_or_default() << (8 * (3 - size)),
+        _ => (target >> (8 * (size - 3)) as usize)
+            .to_u32()
+            .unwrap_or_default(),
+    };
+
+    // the mantissa can't have its sign bit set
+    if compact & 0x0080_0000 != 0 {
+        compact >>= 8;
+        size += 1;
+    }
+
+    compact | size << 24
+}
+
+/// Expected number of hashes to find a block at `target`, `2^256 / (target + 1)`
+pub fn target_to_work(target: &BigUint) -> BigUint {
+    (BigUint::one() << 256) / (target + 1u32)
+}
+
+impl BlockHeader {
+    /// Target the hash of the header must be at most
+    pub fn target(&self) -> Result<BigUint> {
+        compact_to_target(self.bits)
+    }
+
+    /// Whether the hash, as a little endian number, meets the target in `bits`
+    pub fn validate_pow(&self) -> Result<()> {
+        let target = self.target()?;
+        if target.is_zero() {
+            return Err(Error::InvalidBlock("zero target"));
+        }
+
+        if BigUint::from_bytes_le(self.block_hash().as_ref()) > target {
+            return Err(Error::InvalidBlock("hash above target"));
+        }
+
+        Ok(())
+    }
+
+    /// Work proven by the header, what chains are compared by once summed up.
+    /// Invalid targets prove none
+    pub fn work(&self) -> BigUint {
+        match self.target() {
+            Ok(target) => target_to_work(&target),
+            Err(_) => BigUint::zero(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use hex_literal::hex;
+
+    use crate::core::txid::BlockHash;
+
+    use super::*;
+
+    fn target(hex: &str) -> BigUint {
+        BigUint::parse_bytes(hex.as_bytes(), 16).unwrap()
+    }
+
+    #[test]
+    fn compact_targets() -> Result<()> {
+        // Bitcoin Core's arith_uint256 tests
+        let vectors = [
+            (0x0000_0000, "0", 0),
+            (0x0012_3456, "0", 0),
+            (0x0100_3456, "0", 0),
+            (0x0200_0056, "0", 0),
+            (0x0300_0000, "0", 0),
+            (0x0400_0000, "0", 0),
+            (0x0092_3456, "0", 0),
+            (0x0180_3456, "0", 0),
+            (0x0280_0056, "0", 0),
+            (0x0380_0000, "0", 0),
+            (0x0480_0000, "0", 0),
+            (0x0112_3456, "12", 0x0112_0000),
+            (0x0200_8000, "80", 0x0200_8000),
+            (0x0212_3456, "1234", 0x0212_3400),
+            (0x0312_3456, "123456", 0x0312_3456),
+            (0x0412_3456, "12345600", 0x0412_3456),
+            (0x0500_9234, "92340000", 0x0500_9234),
+            (
+                0x2012_3456,
+                "1234560000000000000000000000000000000000000000000000000000000000",
+                0x2012_3456,
+            ),
+        ];
+
+        for (bits, expected, compact) in vectors.iter() {
+            let decoded = compact_to_target(*bits)?;
+            assert_eq!(decoded, target(expected), "{:08x}", bits);
+            assert_eq!(target_to_compact(&decoded), *compact, "{:08x}", bits);
+        }
+
+        for bits in [0x0492_3456, 0x0181_0000].iter() {
+            assert!(matches!(
+                compact_to_target(*bits),
+                Err(Error::InvalidBlock("negative target"))
+            ));
+        }
+
+        for bits in [0xff12_3456, 0x2301_0000, 0x2200_0100, 0x2101_0000].iter() {
+            assert!(matches!(
+                compact_to_target(*bits),
+                Err(Error::InvalidBlock("target overflow"))
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn header_pow() -> Result<()> {
+        let genesis = BlockHeader::from_bytes(&hex!("0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c"))?;
+        assert_eq!(
+            genesis.target()?,
+            target("ffff0000000000000000000000000000000000000000000000000000")
+        );
+        genesis.validate_pow()?;
+        assert_eq!(genesis.work(), BigUint::from(0x1_0001_0001u64));
+
+        let mut other = genesis;
+        other.nonce += 1;
+        assert!(matches!(
+            other.validate_pow(),
+            Err(Error::InvalidBlock("hash above target"))
+        ));
+
+        // regtest difficulty, about every other hash works
+        other.bits = 0x207f_ffff;
+        assert_eq!(other.work(), BigUint::from(2u32));
+
+        other.bits = 0;
+        assert!(other.validate_pow().is_err());
+        other.bits = 0x0492_3456;
+        assert!(other.validate_pow().is_err());
+        assert!(other.work().is_zero());
+        Ok(())
+    }
+
+    #[test]
+    fn retargets() {
+        // Bitcoin Core's pow tests
+        let params = PowParams::new(Network::Mainnet);
+        assert_eq!(params.adjustment_interval(), 2016);
+        assert_eq!(
+            retarget(&params, 0x1d00_ffff, 1261130161, 1262152739),
+            0x1d00_d86a
+        );
+        // slower than expected, but at the easiest target already
+        assert_eq!(
+            retarget(&params, 0x1d00_ffff, 1231006505, 1233061996),
+            0x1d00_ffff
+        );
+        // clamped to four times and a quarter the difficulty
+        assert_eq!(
+            retarget(&params, 0x1c05_a3f4, 1279008237, 1279297671),
+            0x1c01_68fd
+        );
+        assert_eq!(
+            retarget(&params, 0x1c38_7f6f, 1263163443, 1269211443),
+            0x1d00_e1fd
+        );
+
+        let regtest = PowParams::new(Network::Regtest);
+        assert_eq!(retarget(&regtest, 0x207f_ffff, 0, 1), 0x207f_ffff);
+    }
+
+    fn header(time: u32, bits: u32) -> BlockHeader {
+        BlockHeader::new(1, BlockHash::default(), [0; 32], time, bits, 0)
+    }
+
+    #[test]
+    fn next_targets() -> Result<()> {
+        let params = PowParams::new(Network::Testnet);
+        let regular = 0x1c00_ffff;
+
+        // heights 2016 to 2020, the last two at the minimum difficulty
+        let chain: Vec<_> = (0..5)
+            .map(|i| match i {
+                0..=2 => header(600 * i, regular),
+                _ => header(600 * i, params.pow_limit),
+            })
+            .collect();
+        let ancestor = |height: u32| chain.get(height as usize - 2016).copied();
+        let prev = chain[4];
+
+        // a block 20 minutes late can be easy, otherwise it goes back to the last
+        // regular target
+        assert_eq!(
+            next_work_required(&params, &prev, 2020, prev.time + 1201, ancestor)?,
+            params.pow_limit
+        );
+        assert_eq!(
+            next_work_required(&params, &prev, 2020, prev.time + 1200, ancestor)?,
+            regular
+        );
+
+        let mainnet = PowParams::new(Network::Mainnet);
+        assert_eq!(
+            next_work_required(&mainnet, &prev, 2020, prev.time + 1201, ancestor)?,
+            params.pow_limit
+        );
+        assert_eq!(
+            next_work_required(&mainnet, &chain[1], 2017, 0, ancestor)?,
+            regular
+        );
+
+        // a retarget measures from the first block of the period
+        let first = header(1261130161, 0x1d00_ffff);
+        let last = header(1262152739, 0x1d00_ffff);
+        let bits = next_work_required(&mainnet, &last, 4031, 0, |height| match height {
+            2016 => Some(first),
+            _ => None,
+        })?;
+        assert_eq!(bits, 0x1d00_d86a);
+        assert!(next_work_required(&mainnet, &last, 4031, 0, |_| None).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,246 @@
+//! Replace-by-fee (BIP125), signaling and building replacements paying more
+
+use crate::amount::Amount;
+use crate::{Error, Result};
+
+use super::fee::{FeeRate, InputType};
+use super::locktime::Sequence;
+use super::tx::Transaction;
+
+/// Rate the fee of a replacement must grow by over its own size
+const INCREMENTAL_RELAY_FEE: FeeRate = FeeRate::MIN_RELAY;
+
+impl Transaction {
+    /// Whether any input opts in to replacement, explicit signaling as in BIP125
+    pub fn is_rbf_signaling(&self) -> bool {
+        self.inputs.iter().any(|input| input.sequence().is_rbf())
+    }
+
+    /// Make every input signal replaceability, sequences already doing so are kept
+    pub fn signal_rbf(&mut self) {
+        for input in self.inputs.iter_mut() {
+            if !input.sequence().is_rbf() {
+                input.set_sequence(Sequence::ENABLE_RBF);
+            }
+        }
+    }
+
+    /// The unsigned replacement spending the same inputs, the output at
+    /// `change_index` pays for the new fee. It reaches `fee_rate` once signed as
+    /// given by `input_types`, and pays at least the original fee plus the
+    /// incremental relay fee for its own size
+    pub fn bump_fee(
+        &self,
+        original_fee: Amount,
+        input_types: &[InputType],
+        fee_rate: FeeRate,
+        change_index: usize,
+    ) -> Result<Transaction> {
+        if !self.is_rbf_signaling() {
+            return Err(Error::InvalidTransaction("not signaling replaceability"));
+        }
+
+        let mut replacement = self.clone();
+        for input in replacement.inputs.iter_mut() {
+            input.script_sig.bytes.clear();
+            input.witness.clear();
+        }
+
+        let vsize = replacement.estimated_vsize(input_types)?;
+        let fee = original_fee
+            .checked_add(INCREMENTAL_RELAY_FEE.fee_for_vsize(vsize))
+            .ok_or(Error::InvalidAmount("amount overflow"))?
+            .max(fee_rate.fee_for_vsize(vsize));
+
+        let change = replacement
+            .outputs
+            .get_mut(change_index)
+            .ok_or(Error::InvalidTransaction("change index out of range"))?;
+
+        change.amount = change
+            .amount
+            .checked_sub(fee - original_fee)
+            .ok_or(Error::InvalidTransaction("change can't pay the new fee"))?;
+
+        if change.is_dust(FeeRate::DUST_RELAY) {
+            return Err(Error::InvalidTransaction("change can't pay the new fee"));
+        }
+
+        Ok(replacement)
+    }
+
+    /// Check the BIP125 rules of `self` paying `fee` replacing `original` paying
+    /// `original_fee`, both signed. Conflicting transactions other than the
+    /// original and the unconfirmed inputs rule are up to the caller
+    pub fn check_replacement(
+        &self,
+        original: &Transaction,
+        original_fee: Amount,
+        fee: Amount,
+    ) -> Result<()> {
+        if !original.is_rbf_signaling() {
+            return Err(Error::InvalidTransaction("not signaling replaceability"));
+        }
+
+        let spends_all = original.inputs.iter().all(|original| {
+            self.inputs
+                .iter()
+                .any(|input| input.previous_output == original.previous_output)
+        });
+
+        if !spends_all {
+            return Err(Error::InvalidTransaction("replacement misses inputs"));
+        }
+
+        if fee < original_fee {
+            return Err(Error::InvalidTransaction("replacement pays a lower fee"));
+        }
+
+        let vsize = self.vsize()?;
+        if fee - original_fee < INCREMENTAL_RELAY_FEE.fee_for_vsize(vsize) {
+            return Err(Error::InvalidTransaction(
+                "replacement doesn't pay its relay",
+            ));
+        }
+
+        if self.fee_rate(fee)? <= original.fee_rate(original_fee)? {
+            return Err(Error::InvalidTransaction(
+                "replacement pays a lower fee rate",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::core::input::{OutPoint, TxIn};
+    use crate::core::output::TxOut;
+    use crate::core::script::Script;
+    use crate::core::txid::Txid;
+    use crate::secp256k1::crypto::PrivateKey;
+    use crate::utils::hash160;
+
+    use super::*;
+
+    fn p2wpkh_output(key: &PrivateKey, sats: u64) -> Result<TxOut> {
+        let mut script = vec![0x00, 0x14];
+        script.extend(hash160(key.public_key().serialize_compressed()?));
+        Ok(TxOut::new(Amount::from_sat(sats), Script::from(script)))
+    }
+
+    /// One P2WPKH input of 100_000 sats paying 60_000 plus change
+    fn original(key: &PrivateKey) -> Result<(Transaction, TxOut)> {
+        let prev_output = p2wpkh_output(key, 100_000)?;
+        let mut input = TxIn::new(OutPoint::new(Txid::from_bytes([0x11; 32]), 0));
+        input.set_sequence(Sequence::ENABLE_RBF);
+
+        let outputs = vec![
+            TxOut::new(Amount::from_sat(60_000), Script::from(vec![0x51; 34])),
+            p2wpkh_output(key, 39_000)?,
+        ];
+
+        let mut tx = Transaction::new(2, vec![input], outputs, 0);
+        tx.sign_p2wpkh_input(0, key, &prev_output)?;
+        Ok((tx, prev_output))
+    }
+
+    #[test]
+    fn rbf_signaling() {
+        let mut tx = Transaction::new(
+            2,
+            vec![TxIn::new(OutPoint::new(Txid::from_bytes([0x11; 32]), 0))],
+            vec![],
+            0,
+        );
+        assert!(!tx.is_rbf_signaling());
+
+        tx.inputs[0].set_sequence(Sequence::ENABLE_LOCKTIME_NO_RBF);
+        assert!(!tx.is_rbf_signaling());
+
+        tx.signal_rbf();
+        assert!(tx.is_rbf_signaling());
+        assert_eq!(tx.inputs[0].sequence(), Sequence::ENABLE_RBF);
+
+        // relative locktimes signal too and are kept as they are
+        tx.inputs[0].set_sequence(Sequence::from_height(144));
+        tx.signal_rbf();
+        assert_eq!(tx.inputs[0].sequence(), Sequence::from_height(144));
+    }
+
+    #[test]
+    fn bump_fee() -> Result<()> {
+        let key = PrivateKey::new(8675309u32);
+        let (tx, prev_output) = original(&key)?;
+        let original_fee = Amount::from_sat(1_000);
+
+        let rate = FeeRate::from_sat_per_vb(20);
+        let mut replacement = tx.bump_fee(original_fee, &[InputType::P2wpkh], rate, 1)?;
+        assert!(replacement.inputs[0].witness.is_empty());
+        assert_eq!(replacement.outputs[0], tx.outputs[0]);
+
+        let vsize = replacement.estimated_vsize(&[InputType::P2wpkh])?;
+        let fee = Amount::from_sat(39_000) - replacement.outputs[1].amount + original_fee;
+        assert_eq!(fee, rate.fee_for_vsize(vsize));
+
+        replacement.sign_p2wpkh_input(0, &key, &prev_output)?;
+        replacement.check_replacement(&tx, original_fee, fee)?;
+        assert!(replacement.fee_rate(fee)? >= rate);
+
+        // a rate below the original still pays the incremental relay fee
+        let replacement = tx.bump_fee(original_fee, &[InputType::P2wpkh], FeeRate::ZERO, 1)?;
+        assert_eq!(
+            replacement.outputs[1].amount,
+            Amount::from_sat(39_000) - FeeRate::MIN_RELAY.fee_for_vsize(vsize)
+        );
+
+        // change too small, or not there
+        let rate = FeeRate::from_sat_per_vb(300);
+        assert!(tx
+            .bump_fee(original_fee, &[InputType::P2wpkh], rate, 1)
+            .is_err());
+        assert!(tx
+            .bump_fee(original_fee, &[InputType::P2wpkh], rate, 2)
+            .is_err());
+
+        let mut final_tx = tx.clone();
+        final_tx.inputs[0].set_sequence(Sequence::MAX);
+        let rate = FeeRate::from_sat_per_vb(2);
+        assert!(final_tx
+            .bump_fee(original_fee, &[InputType::P2wpkh], rate, 1)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn replacement_rules() -> Result<()> {
+        let key = PrivateKey::new(8675309u32);
+        let (tx, _) = original(&key)?;
+        let original_fee = Amount::from_sat(1_000);
+        let vsize = tx.vsize()? as u64;
+
+        // the same transaction pays the same fee and rate
+        assert!(tx
+            .check_replacement(&tx, original_fee, original_fee)
+            .is_err());
+
+        // more fee but not enough to relay the replacement
+        let fee = original_fee + Amount::from_sat(vsize - 1);
+        assert!(tx.check_replacement(&tx, original_fee, fee).is_err());
+        let fee = original_fee + Amount::from_sat(vsize);
+        tx.check_replacement(&tx, original_fee, fee)?;
+
+        // missing the original input
+        let mut other = tx.clone();
+        other.inputs[0].previous_output = OutPoint::new(Txid::from_bytes([0x22; 32]), 0);
+        assert!(other.check_replacement(&tx, original_fee, fee).is_err());
+
+        let mut final_tx = tx.clone();
+        final_tx.inputs[0].set_sequence(Sequence::MAX);
+        assert!(tx.check_replacement(&final_tx, original_fee, fee).is_err());
+        Ok(())
+    }
+}
@@ -1,20 +1,734 @@
+use std::io::{Read, Write};
+
 use bytes::Buf;
 
-use crate::Result;
+use crate::address::Address;
+use crate::consensus::{self, Decodable, Encodable};
+use crate::network::Network;
+use crate::{Error, Result};
 
-#[derive(Debug, Clone)]
-pub struct Script {}
+use super::opcode::Opcode;
+
+/// Consensus limit of a script to be spendable
+pub const MAX_SCRIPT_SIZE: usize = 10_000;
+/// Largest `OP_RETURN` script relayed by default, 80 bytes of data plus the
+/// `OP_RETURN` and push opcodes
+pub const MAX_OP_RETURN_RELAY: usize = 83;
+
+/// Raw script bytes, parsed lazily into instructions
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Script {
+    pub(crate) bytes: Vec<u8>,
+}
 
 impl Script {
     pub fn new() -> Self {
-        Self {}
+        Self::default()
+    }
+
+    /// `OP_DUP OP_HASH160 <hash> OP_EQUALVERIFY OP_CHECKSIG`
+    pub fn p2pkh(hash: &[u8; 20]) -> Self {
+        Self::from([&[0x76, 0xa9, 0x14][..], hash, &[0x88, 0xac]].concat())
+    }
+
+    /// `OP_HASH160 <hash> OP_EQUAL`
+    pub fn p2sh(hash: &[u8; 20]) -> Self {
+        Self::from([&[0xa9, 0x14][..], hash, &[0x87]].concat())
+    }
+
+    /// `OP_0 <hash>`
+    pub fn p2wpkh(hash: &[u8; 20]) -> Self {
+        Self::from([&[0x00, 0x14][..], hash].concat())
+    }
+
+    /// `OP_0 <hash>`, the hash being the SHA256 of the witness script
+    pub fn p2wsh(hash: &[u8; 32]) -> Self {
+        Self::from([&[0x00, 0x20][..], hash].concat())
+    }
+
+    /// `OP_1 <output key>`, with the x-only tweaked key
+    pub fn p2tr(output_key: &[u8; 32]) -> Self {
+        Self::from([&[0x51, 0x20][..], output_key].concat())
+    }
+
+    /// `OP_RETURN <data>`, an unspendable output carrying `data`
+    pub fn op_return(data: &[u8]) -> Self {
+        ScriptBuilder::new()
+            .push_opcode(Opcode::OP_RETURN)
+            .push_slice(data)
+            .into_script()
+    }
+
+    /// `OP_RETURN <push> <push> ...`, with each push in its own element
+    pub fn op_return_pushes(pushes: &[&[u8]]) -> Self {
+        pushes
+            .iter()
+            .fold(
+                ScriptBuilder::new().push_opcode(Opcode::OP_RETURN),
+                |builder, data| builder.push_slice(data),
+            )
+            .into_script()
+    }
+
+    /// Data pushed after the `OP_RETURN` of a null data script, small numbers as
+    /// the bytes they push
+    pub fn op_return_data(&self) -> Option<Vec<Vec<u8>>> {
+        let pushes = self.null_data()?;
+        Instructions::new(pushes)
+            .map(|instruction| match instruction {
+                Ok(Instruction::PushBytes(data)) => Some(data.to_vec()),
+                Ok(Instruction::Op(opcode)) => opcode.small_int().map(script_num),
+                Err(_) => None,
+            })
+            .collect()
+    }
+
+    /// Null data script within the size relayed by default
+    pub fn is_standard_op_return(&self) -> bool {
+        self.null_data().is_some() && self.bytes.len() <= MAX_OP_RETURN_RELAY
+    }
+
+    /// Script sig of a P2SH spend, pushing the `stack` elements and the redeem script
+    pub fn p2sh_script_sig(stack: &[Vec<u8>], redeem_script: &Script) -> Self {
+        stack
+            .iter()
+            .chain(Some(&redeem_script.bytes))
+            .fold(ScriptBuilder::new(), |builder, element| {
+                builder.push_slice(element)
+            })
+            .into_script()
+    }
+
+    /// Address paying to this script, fails for scripts without an address form
+    pub fn to_address(&self, network: Network) -> Result<Address> {
+        Address::from_script(&self.bytes, network)
     }
 
     pub fn serialize(&self) -> Result<Vec<u8>> {
-        todo!()
+        consensus::serialize(self)
     }
 
     pub fn deserialize(buf: impl Buf) -> Result<Self> {
-        todo!()
+        Self::consensus_decode(&mut buf.reader())
+    }
+
+    /// Length in bytes, without the length prefix of the serialization
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Opcodes and pushed data, failing on pushes past the end of the script
+    pub fn instructions(&self) -> Instructions<'_> {
+        Instructions::new(&self.bytes)
+    }
+
+    /// Only data pushes and small numbers, like script sigs have to be
+    pub fn is_push_only(&self) -> bool {
+        is_push_only(&self.bytes)
+    }
+
+    /// Standard template this script follows
+    pub fn classify(&self) -> ScriptType {
+        if self.p2pk_key().is_some() {
+            ScriptType::P2pk
+        } else if self.p2pkh_hash().is_some() {
+            ScriptType::P2pkh
+        } else if self.p2sh_hash().is_some() {
+            ScriptType::P2sh
+        } else if let Some((version, program)) = self.witness_program() {
+            match (version, program.len()) {
+                (0, 20) => ScriptType::P2wpkh,
+                (0, 32) => ScriptType::P2wsh,
+                (0, _) => ScriptType::NonStandard,
+                (1, 32) => ScriptType::P2tr,
+                _ => ScriptType::WitnessUnknown { version },
+            }
+        } else if self.null_data().is_some() {
+            ScriptType::OpReturn
+        } else if self.multisig_keys().is_some() {
+            ScriptType::Multisig
+        } else {
+            ScriptType::NonStandard
+        }
+    }
+
+    /// What the template commits to: the key of P2PK, the hash of P2PKH and P2SH,
+    /// the witness program of segwit outputs and the pushes after `OP_RETURN`
+    pub fn payload(&self) -> Option<&[u8]> {
+        match self.classify() {
+            ScriptType::P2pk => self.p2pk_key(),
+            ScriptType::P2pkh => self.p2pkh_hash(),
+            ScriptType::P2sh => self.p2sh_hash(),
+            ScriptType::P2wpkh
+            | ScriptType::P2wsh
+            | ScriptType::P2tr
+            | ScriptType::WitnessUnknown { .. } => {
+                self.witness_program().map(|(_, program)| program)
+            }
+            ScriptType::OpReturn => self.null_data(),
+            ScriptType::Multisig | ScriptType::NonStandard => None,
+        }
+    }
+
+    /// SEC key of a `<33 or 65 bytes> OP_CHECKSIG` script
+    pub(crate) fn p2pk_key(&self) -> Option<&[u8]> {
+        match self.bytes.as_slice() {
+            [0x21, key @ .., 0xac] if key.len() == 33 && matches!(key[0], 0x02 | 0x03) => Some(key),
+            [0x41, key @ .., 0xac] if key.len() == 65 && key[0] == 0x04 => Some(key),
+            _ => None,
+        }
+    }
+
+    /// Pushes following the `OP_RETURN` of a null data script
+    pub(crate) fn null_data(&self) -> Option<&[u8]> {
+        match self.bytes.split_first() {
+            Some((0x6a, rest)) if is_push_only(rest) => Some(rest),
+            _ => None,
+        }
+    }
+
+    /// Hash of a `OP_DUP OP_HASH160 <20 bytes> OP_EQUALVERIFY OP_CHECKSIG` script
+    pub(crate) fn p2pkh_hash(&self) -> Option<&[u8]> {
+        match self.bytes.as_slice() {
+            [0x76, 0xa9, 0x14, hash @ .., 0x88, 0xac] if hash.len() == 20 => Some(hash),
+            _ => None,
+        }
+    }
+
+    /// `OP_RETURN` outputs or scripts above the consensus size limit, which can
+    /// never be spent
+    pub(crate) fn is_unspendable(&self) -> bool {
+        self.bytes.first() == Some(&0x6a) || self.bytes.len() > MAX_SCRIPT_SIZE
+    }
+
+    /// A version byte (`OP_0` to `OP_16`) followed by a push of 2 to 40 bytes
+    pub(crate) fn is_witness_program(&self) -> bool {
+        self.witness_program().is_some()
+    }
+
+    /// Version and program of a witness program
+    pub(crate) fn witness_program(&self) -> Option<(u8, &[u8])> {
+        let (version, len, program) = match self.bytes.as_slice() {
+            [version, len, program @ ..] => (*version, *len as usize, program),
+            _ => return None,
+        };
+
+        let version = match version {
+            0x00 => 0,
+            0x51..=0x60 => version - 0x50,
+            _ => return None,
+        };
+
+        match (2..=40).contains(&program.len()) && len == program.len() {
+            true => Some((version, program)),
+            false => None,
+        }
+    }
+
+    /// Hash of a `OP_HASH160 <20 bytes> OP_EQUAL` script
+    pub(crate) fn p2sh_hash(&self) -> Option<&[u8]> {
+        match self.bytes.as_slice() {
+            [0xa9, 0x14, hash @ .., 0x87] if hash.len() == 20 => Some(hash),
+            _ => None,
+        }
+    }
+
+    /// Hash of a `OP_0 <20 bytes>` script
+    pub(crate) fn p2wpkh_hash(&self) -> Option<&[u8]> {
+        match self.bytes.as_slice() {
+            [0x00, 0x14, hash @ ..] if hash.len() == 20 => Some(hash),
+            _ => None,
+        }
+    }
+
+    /// X-only output key of a `OP_1 <32 bytes>` script
+    pub(crate) fn p2tr_output_key(&self) -> Option<&[u8]> {
+        match self.bytes.as_slice() {
+            [0x51, 0x20, key @ ..] if key.len() == 32 => Some(key),
+            _ => None,
+        }
+    }
+}
+
+/// Output templates, as told apart by [`Script::classify`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ScriptType {
+    /// `<key> OP_CHECKSIG`
+    P2pk,
+    P2pkh,
+    P2sh,
+    /// Segwit v0 key hash
+    P2wpkh,
+    /// Segwit v0 script hash
+    P2wsh,
+    /// Segwit v1 output key
+    P2tr,
+    /// Segwit program of a future version
+    WitnessUnknown {
+        version: u8,
+    },
+    /// Bare `OP_CHECKMULTISIG`
+    Multisig,
+    /// `OP_RETURN` followed by pushes only
+    OpReturn,
+    NonStandard,
+}
+
+/// A single step of a script
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction<'a> {
+    /// Data pushed by `OP_0`, direct pushes or `OP_PUSHDATA1/2/4`
+    PushBytes(&'a [u8]),
+    /// Any other opcode, including `OP_1NEGATE` and `OP_1` to `OP_16`
+    Op(Opcode),
+}
+
+/// Iterator over the instructions of a script, stops after the first error
+#[derive(Debug, Clone)]
+pub struct Instructions<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Instructions<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    /// The bytes not yet parsed
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.bytes.len() {
+            self.bytes = &[];
+            return Err(Error::InvalidScript("push past the end of the script"));
+        }
+
+        let (data, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(data)
+    }
+
+    fn take_len(&mut self, size: usize) -> Result<usize> {
+        let bytes = self.take(size)?;
+        Ok(bytes
+            .iter()
+            .rev()
+            .fold(0, |len, byte| len << 8 | *byte as usize))
+    }
+}
+
+impl<'a> Iterator for Instructions<'a> {
+    type Item = Result<Instruction<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (&opcode, rest) = self.bytes.split_first()?;
+        self.bytes = rest;
+
+        let len = match opcode {
+            0x00..=0x4b => Ok(opcode as usize),
+            0x4c => self.take_len(1),
+            0x4d => self.take_len(2),
+            0x4e => self.take_len(4),
+            _ => return Some(Ok(Instruction::Op(Opcode::from(opcode)))),
+        };
+
+        Some(
+            len.and_then(|len| self.take(len))
+                .map(Instruction::PushBytes),
+        )
+    }
+}
+
+/// Composes scripts from opcodes and data, always pushing minimally
+#[derive(Debug, Clone, Default)]
+pub struct ScriptBuilder {
+    bytes: Vec<u8>,
+}
+
+impl ScriptBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push_opcode(mut self, opcode: Opcode) -> Self {
+        self.bytes.push(opcode.into());
+        self
+    }
+
+    /// Pushes of a single byte from 1 to 16 (or 0x81) become `OP_1` to `OP_16`
+    /// (or `OP_1NEGATE`), like the minimal push rule requires
+    pub fn push_slice(mut self, data: &[u8]) -> Self {
+        match data {
+            [] => self.bytes.push(0x00),
+            [n @ 1..=16] => self.bytes.push(0x50 + n),
+            [0x81] => self.bytes.push(0x4f),
+            _ => push_slice(&mut self.bytes, data),
+        }
+
+        self
+    }
+
+    /// Small numbers as opcodes, others as minimal script numbers
+    pub fn push_int(mut self, n: i64) -> Self {
+        push_int(&mut self.bytes, n);
+        self
+    }
+
+    pub fn into_script(self) -> Script {
+        Script::from(self.bytes)
+    }
+}
+
+/// Minimal push of `n`, `OP_0`, `OP_1NEGATE` and `OP_1` to `OP_16` for small
+/// numbers and little endian sign magnitude bytes otherwise
+pub(crate) fn push_int(script: &mut Vec<u8>, n: i64) {
+    match n {
+        0 => script.push(0x00),
+        -1 => script.push(0x4f),
+        1..=16 => script.push(0x50 + n as u8),
+        _ => push_slice(script, &script_num(n)),
+    }
+}
+
+/// Encoding of numbers on the script stack
+pub(crate) fn script_num(n: i64) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut abs = n.unsigned_abs();
+    while abs > 0 {
+        bytes.push(abs as u8);
+        abs >>= 8;
+    }
+
+    // the most significant bit is the sign
+    match bytes.last_mut() {
+        Some(last) if *last & 0x80 != 0 => bytes.push(if n < 0 { 0x80 } else { 0x00 }),
+        Some(last) if n < 0 => *last |= 0x80,
+        _ => {}
+    }
+
+    bytes
+}
+
+/// Minimal push of `data`, direct below 76 bytes and `OP_PUSHDATA1/2/4` above
+pub(crate) fn push_slice(script: &mut Vec<u8>, data: &[u8]) {
+    match data.len() {
+        0..=0x4b => script.push(data.len() as u8),
+        0x4c..=0xff => script.extend_from_slice(&[0x4c, data.len() as u8]),
+        0x100..=0xffff => {
+            script.push(0x4d);
+            script.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        }
+        _ => {
+            script.push(0x4e);
+            script.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        }
+    }
+
+    script.extend_from_slice(data);
+}
+
+fn is_push_only(bytes: &[u8]) -> bool {
+    Instructions::new(bytes).all(|instruction| match instruction {
+        Ok(Instruction::PushBytes(_)) => true,
+        Ok(Instruction::Op(opcode)) => opcode.is_push(),
+        Err(_) => false,
+    })
+}
+
+impl From<Vec<u8>> for Script {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self { bytes }
+    }
+}
+
+impl From<&Address> for Script {
+    fn from(address: &Address) -> Self {
+        Self::from(address.script_pubkey())
+    }
+}
+
+impl AsRef<[u8]> for Script {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+/// Length prefixed raw bytes
+impl Encodable for Script {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        self.bytes.consensus_encode(writer)
+    }
+}
+
+impl Decodable for Script {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        let bytes = Vec::consensus_decode(reader)?;
+        Ok(Self { bytes })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+
+    #[test]
+    fn instructions() {
+        // P2PKH
+        let script =
+            Script::from(hex!("76a91489abcdefabbaabbaabbaabbaabbaabbaabbaabba88ac").to_vec());
+        let instructions: Vec<_> = script.instructions().collect::<Result<_>>().unwrap();
+        assert_eq!(
+            instructions,
+            vec![
+                Instruction::Op(Opcode::OP_DUP),
+                Instruction::Op(Opcode::OP_HASH160),
+                Instruction::PushBytes(&hex!("89abcdefabbaabbaabbaabbaabbaabbaabbaabba")),
+                Instruction::Op(Opcode::OP_EQUALVERIFY),
+                Instruction::Op(Opcode::OP_CHECKSIG),
+            ]
+        );
+        assert!(!script.is_push_only());
+
+        let script = Script::from(hex!("00 4c0201ff 4d0100aa 4e01000000bb").to_vec());
+        let instructions: Vec<_> = script.instructions().collect::<Result<_>>().unwrap();
+        assert_eq!(
+            instructions,
+            vec![
+                Instruction::PushBytes(&[]),
+                Instruction::PushBytes(&[0x01, 0xff]),
+                Instruction::PushBytes(&[0xaa]),
+                Instruction::PushBytes(&[0xbb]),
+            ]
+        );
+        assert!(script.is_push_only());
+
+        for truncated in [
+            &hex!("02ff")[..],
+            &hex!("4c")[..],
+            &hex!("4d01")[..],
+            &hex!("4e02000000ff")[..],
+        ]
+        .iter()
+        {
+            let script = Script::from(truncated.to_vec());
+            let mut instructions = script.instructions();
+            assert!(matches!(
+                instructions.next(),
+                Some(Err(Error::InvalidScript(_)))
+            ));
+            assert!(instructions.next().is_none());
+            assert!(!script.is_push_only());
+        }
+    }
+
+    #[test]
+    fn builder() {
+        let script = ScriptBuilder::new()
+            .push_int(2)
+            .push_slice(&[0x02; 33])
+            .push_int(1)
+            .push_opcode(Opcode::OP_CHECKMULTISIG)
+            .into_script();
+        assert_eq!(script.len(), 37);
+        assert_eq!(script.as_bytes()[..2], [0x52, 0x21]);
+        assert_eq!(script.as_bytes()[35..], [0x51, 0xae]);
+
+        let script = ScriptBuilder::new()
+            .push_slice(&[])
+            .push_slice(&[0x10])
+            .push_slice(&[0x81])
+            .push_slice(&[0x11])
+            .into_script();
+        assert_eq!(script.as_bytes(), hex!("00 60 4f 0111"));
+
+        let data = vec![0xab; 300];
+        let script = ScriptBuilder::new().push_slice(&data).into_script();
+        assert_eq!(script.as_bytes()[..3], hex!("4d2c01"));
+        assert_eq!(
+            script.instructions().next().unwrap().unwrap(),
+            Instruction::PushBytes(&data)
+        );
+
+        // the serialization prefixes the length
+        assert_eq!(script.serialize().unwrap()[..3], hex!("fd2f01"));
+    }
+
+    #[test]
+    fn templates() -> Result<()> {
+        let hash = hex!("751e76e8199196d454941c45d1b3a323f1433bd6");
+        let p2wpkh = Script::p2wpkh(&hash);
+        assert_eq!(
+            p2wpkh.to_address(Network::Mainnet)?.to_string(),
+            "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"
+        );
+        assert_eq!(p2wpkh.p2wpkh_hash(), Some(&hash[..]));
+
+        let p2pkh = Script::p2pkh(&hash);
+        assert_eq!(p2pkh.p2pkh_hash(), Some(&hash[..]));
+        let p2sh = Script::p2sh(&hash);
+        assert_eq!(p2sh.p2sh_hash(), Some(&hash[..]));
+        let p2wsh = Script::p2wsh(&[0x11; 32]);
+        assert_eq!(p2wsh.witness_program(), Some((0, &[0x11; 32][..])));
+        let p2tr = Script::p2tr(&[0x22; 32]);
+        assert_eq!(p2tr.p2tr_output_key(), Some(&[0x22; 32][..]));
+
+        // every template survives a trip through its address
+        for script in [p2pkh, p2sh, p2wpkh, p2wsh, p2tr].iter() {
+            for network in [Network::Mainnet, Network::Regtest].iter() {
+                let address = script.to_address(*network)?;
+                assert_eq!(address.network(), *network);
+                assert_eq!(Script::from(&address), *script);
+            }
+        }
+
+        let op_return = Script::op_return(b"hello");
+        assert_eq!(op_return.as_bytes(), hex!("6a 0568656c6c6f"));
+        assert!(op_return.is_unspendable());
+        assert!(op_return.to_address(Network::Mainnet).is_err());
+        assert_eq!(Script::op_return(&[]).as_bytes(), hex!("6a00"));
+        Ok(())
+    }
+
+    #[test]
+    fn classification() {
+        let key = hex!("0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798");
+        let p2pk = ScriptBuilder::new()
+            .push_slice(&key)
+            .push_opcode(Opcode::OP_CHECKSIG)
+            .into_script();
+        assert_eq!(p2pk.classify(), ScriptType::P2pk);
+        assert_eq!(p2pk.payload(), Some(&key[..]));
+
+        let cases = [
+            (
+                Script::p2pkh(&[0x01; 20]),
+                ScriptType::P2pkh,
+                &[0x01; 20][..],
+            ),
+            (Script::p2sh(&[0x02; 20]), ScriptType::P2sh, &[0x02; 20][..]),
+            (
+                Script::p2wpkh(&[0x03; 20]),
+                ScriptType::P2wpkh,
+                &[0x03; 20][..],
+            ),
+            (
+                Script::p2wsh(&[0x04; 32]),
+                ScriptType::P2wsh,
+                &[0x04; 32][..],
+            ),
+            (Script::p2tr(&[0x05; 32]), ScriptType::P2tr, &[0x05; 32][..]),
+            (
+                Script::from(hex!("5202abcd").to_vec()),
+                ScriptType::WitnessUnknown { version: 2 },
+                &hex!("abcd")[..],
+            ),
+            (
+                Script::from(hex!("6a 03010203 51").to_vec()),
+                ScriptType::OpReturn,
+                &hex!("03010203 51")[..],
+            ),
+        ];
+
+        for (script, kind, payload) in cases.iter() {
+            assert_eq!(script.classify(), *kind);
+            assert_eq!(script.payload(), Some(*payload));
+        }
+
+        let multisig = ScriptBuilder::new()
+            .push_int(1)
+            .push_slice(&key)
+            .push_int(1)
+            .push_opcode(Opcode::OP_CHECKMULTISIG)
+            .into_script();
+        assert_eq!(multisig.classify(), ScriptType::Multisig);
+        assert_eq!(multisig.payload(), None);
+
+        for nonstandard in [
+            &hex!("")[..],
+            &hex!("51")[..],
+            // v0 programs of other lengths
+            &hex!("0002abcd")[..],
+            // OP_RETURN followed by something else than pushes
+            &hex!("6a ac")[..],
+            // not a valid key prefix
+            &hex!("21 05 79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798 ac")[..],
+        ]
+        .iter()
+        {
+            let script = Script::from(nonstandard.to_vec());
+            assert_eq!(script.classify(), ScriptType::NonStandard);
+            assert_eq!(script.payload(), None);
+        }
+    }
+
+    #[test]
+    fn op_returns() {
+        let commitment = [0xab; 32];
+        let script = Script::op_return_pushes(&[b"ord", &[], &[0x01], &commitment]);
+        assert_eq!(script.as_bytes()[..7], hex!("6a 036f7264 00 51"));
+        assert_eq!(
+            script.op_return_data(),
+            Some(vec![
+                b"ord".to_vec(),
+                vec![],
+                vec![0x01],
+                commitment.to_vec()
+            ])
+        );
+        assert!(script.is_standard_op_return());
+
+        assert_eq!(Script::op_return(&[]).op_return_data(), Some(vec![vec![]]));
+        assert_eq!(Script::from(vec![0x6a]).op_return_data(), Some(vec![]));
+        assert_eq!(
+            Script::from(vec![0x6a, 0x4f]).op_return_data(),
+            Some(vec![vec![0x81]])
+        );
+        assert_eq!(
+            Script::from(hex!("6a 51 ac").to_vec()).op_return_data(),
+            None
+        );
+        assert_eq!(Script::p2wpkh(&[0; 20]).op_return_data(), None);
+
+        // 80 bytes of data is the most relayed by default
+        assert!(Script::op_return(&[0; 80]).is_standard_op_return());
+        assert!(!Script::op_return(&[0; 81]).is_standard_op_return());
+        assert!(Script::op_return_pushes(&[&[0; 40], &[0; 40]]).is_standard_op_return());
+        assert!(!Script::op_return_pushes(&[&[0; 41], &[0; 40]]).is_standard_op_return());
+    }
+
+    #[test]
+    fn script_nums() {
+        let cases: &[(i64, &[u8])] = &[
+            (0, &hex!("00")),
+            (-1, &hex!("4f")),
+            (16, &hex!("60")),
+            (17, &hex!("0111")),
+            (-2, &hex!("0182")),
+            (127, &hex!("017f")),
+            (128, &hex!("028000")),
+            (-128, &hex!("028080")),
+            (255, &hex!("02ff00")),
+            (-255, &hex!("02ff80")),
+            (32_767, &hex!("02ff7f")),
+            (227_836, &hex!("03fc7903")),
+            (i64::MIN + 1, &hex!("08ffffffffffffffff")),
+        ];
+
+        for (n, expected) in cases {
+            let script = ScriptBuilder::new().push_int(*n).into_script();
+            assert_eq!(script.as_bytes(), *expected, "{}", n);
+        }
     }
 }
@@ -0,0 +1,701 @@
+use sha2::{Digest, Sha256};
+
+use crate::amount::Amount;
+use crate::consensus::{self, Encodable};
+use crate::utils::{hash256, tagged_hash};
+use crate::varint;
+use crate::{Error, Result};
+
+use super::opcode::Opcode;
+use super::output::TxOut;
+use super::script::{Instruction, Script};
+use super::tx::Transaction;
+
+/// Taproot only, signs the same as `SIGHASH_ALL` without the extra byte
+pub const SIGHASH_DEFAULT: u32 = 0x00;
+pub const SIGHASH_ALL: u32 = 0x01;
+pub const SIGHASH_NONE: u32 = 0x02;
+pub const SIGHASH_SINGLE: u32 = 0x03;
+pub const SIGHASH_ANYONECANPAY: u32 = 0x80;
+
+/// Digest of `SIGHASH_SINGLE` without a matching output, signing it signs nothing
+const SIGHASH_SINGLE_BUG: [u8; 32] = {
+    let mut one = [0; 32];
+    one[0] = 1;
+    one
+};
+
+impl Transaction {
+    /// Pre-segwit signature hash of the input at `input_index`, the low 5 bits of the
+    /// sighash type select ALL, NONE or SINGLE (anything else is ALL)
+    pub fn legacy_sighash(
+        &self,
+        input_index: usize,
+        script_code: &Script,
+        sighash_type: u32,
+    ) -> Result<[u8; 32]> {
+        if input_index >= self.inputs.len() {
+            return Err(Error::InvalidTransaction("input index out of range"));
+        }
+
+        let base_type = sighash_type & 0x1f;
+        let anyone_can_pay = sighash_type & SIGHASH_ANYONECANPAY != 0;
+        if base_type == SIGHASH_SINGLE && input_index >= self.outputs.len() {
+            return Ok(SIGHASH_SINGLE_BUG);
+        }
+
+        let mut preimage = Vec::new();
+        self.version.consensus_encode(&mut preimage)?;
+
+        let signed_inputs: Vec<_> = match anyone_can_pay {
+            true => vec![input_index],
+            false => (0..self.inputs.len()).collect(),
+        };
+
+        preimage.extend(varint::encode(signed_inputs.len() as u64));
+        for index in signed_inputs {
+            let input = &self.inputs[index];
+            input.previous_output.consensus_encode(&mut preimage)?;
+
+            if index == input_index {
+                remove_codeseparators(script_code).consensus_encode(&mut preimage)?;
+                input.sequence.consensus_encode(&mut preimage)?;
+            } else {
+                Script::new().consensus_encode(&mut preimage)?;
+                let sequence = match base_type {
+                    SIGHASH_NONE | SIGHASH_SINGLE => 0,
+                    _ => input.sequence,
+                };
+                sequence.consensus_encode(&mut preimage)?;
+            }
+        }
+
+        match base_type {
+            SIGHASH_NONE => preimage.extend(varint::encode(0)),
+            SIGHASH_SINGLE => {
+                preimage.extend(varint::encode(input_index as u64 + 1));
+
+                // the outputs before are blanked out, -1 amount and empty script
+                for _ in 0..input_index {
+                    u64::MAX.consensus_encode(&mut preimage)?;
+                    Script::new().consensus_encode(&mut preimage)?;
+                }
+                self.outputs[input_index].consensus_encode(&mut preimage)?;
+            }
+            _ => {
+                self.outputs.consensus_encode(&mut preimage)?;
+            }
+        }
+
+        self.locktime.consensus_encode(&mut preimage)?;
+        sighash_type.consensus_encode(&mut preimage)?;
+        Ok(double_sha256(&preimage))
+    }
+}
+
+impl Transaction {
+    /// Segwit v0 signature hash (BIP143) of the input at `input_index` spending
+    /// `value`, use a [`SighashCache`] when signing more than one input
+    pub fn segwit_v0_sighash(
+        &self,
+        input_index: usize,
+        script_code: &Script,
+        value: Amount,
+        sighash_type: u32,
+    ) -> Result<[u8; 32]> {
+        SighashCache::new(self).segwit_v0_sighash(input_index, script_code, value, sighash_type)
+    }
+}
+
+/// Single SHA256 over all the inputs or outputs, the same for every signed input
+#[derive(Debug, Clone)]
+struct Midstates {
+    prevouts: [u8; 32],
+    sequences: [u8; 32],
+    outputs: [u8; 32],
+}
+
+/// BIP143 hashes the common midstates once more
+#[derive(Debug, Clone)]
+struct SegwitV0Midstates {
+    prevouts: [u8; 32],
+    sequences: [u8; 32],
+    outputs: [u8; 32],
+}
+
+/// BIP341 commits to every spent output too
+#[derive(Debug, Clone)]
+struct TaprootMidstates {
+    amounts: [u8; 32],
+    script_pubkeys: [u8; 32],
+}
+
+/// Signature hashes of a transaction computing the shared parts only once, the
+/// spent outputs given for taproot must be the same on every call
+#[derive(Debug, Clone)]
+pub struct SighashCache<'a> {
+    tx: &'a Transaction,
+    common: Option<Midstates>,
+    segwit_v0: Option<SegwitV0Midstates>,
+    taproot: Option<TaprootMidstates>,
+}
+
+impl<'a> SighashCache<'a> {
+    pub fn new(tx: &'a Transaction) -> Self {
+        Self {
+            tx,
+            common: None,
+            segwit_v0: None,
+            taproot: None,
+        }
+    }
+
+    pub fn transaction(&self) -> &'a Transaction {
+        self.tx
+    }
+
+    /// Segwit v0 signature hash (BIP143), the script code is serialized as is
+    pub fn segwit_v0_sighash(
+        &mut self,
+        input_index: usize,
+        script_code: &Script,
+        value: Amount,
+        sighash_type: u32,
+    ) -> Result<[u8; 32]> {
+        let tx = self.tx;
+        let input = tx
+            .inputs
+            .get(input_index)
+            .ok_or(Error::InvalidTransaction("input index out of range"))?;
+
+        let base_type = sighash_type & 0x1f;
+        let anyone_can_pay = sighash_type & SIGHASH_ANYONECANPAY != 0;
+        let midstates = self.segwit_v0_midstates()?;
+
+        let mut preimage = Vec::new();
+        tx.version.consensus_encode(&mut preimage)?;
+        match anyone_can_pay {
+            true => [0; 32].consensus_encode(&mut preimage)?,
+            false => midstates.prevouts.consensus_encode(&mut preimage)?,
+        };
+
+        match anyone_can_pay || base_type == SIGHASH_NONE || base_type == SIGHASH_SINGLE {
+            true => [0; 32].consensus_encode(&mut preimage)?,
+            false => midstates.sequences.consensus_encode(&mut preimage)?,
+        };
+
+        input.previous_output.consensus_encode(&mut preimage)?;
+        script_code.consensus_encode(&mut preimage)?;
+        value.consensus_encode(&mut preimage)?;
+        input.sequence.consensus_encode(&mut preimage)?;
+
+        let outputs = match base_type {
+            SIGHASH_NONE => [0; 32],
+            SIGHASH_SINGLE => match tx.outputs.get(input_index) {
+                Some(output) => double_sha256(&output.serialize()?),
+                None => [0; 32],
+            },
+            _ => midstates.outputs,
+        };
+
+        outputs.consensus_encode(&mut preimage)?;
+        tx.locktime.consensus_encode(&mut preimage)?;
+        sighash_type.consensus_encode(&mut preimage)?;
+        Ok(double_sha256(&preimage))
+    }
+
+    /// Taproot key path signature hash (BIP341), `prevouts` are the outputs spent
+    /// by every input and `annex` must start with `0x50`
+    pub fn taproot_key_spend_sighash(
+        &mut self,
+        input_index: usize,
+        prevouts: &[TxOut],
+        annex: Option<&[u8]>,
+        sighash_type: u32,
+    ) -> Result<[u8; 32]> {
+        self.taproot_sighash(input_index, prevouts, annex, None, sighash_type)
+    }
+
+    /// Taproot script path signature hash (BIP342) of the leaf hashing to `leaf_hash`,
+    /// `codesep_pos` is the opcode position of the last executed `OP_CODESEPARATOR`
+    pub fn taproot_script_spend_sighash(
+        &mut self,
+        input_index: usize,
+        prevouts: &[TxOut],
+        leaf_hash: &[u8; 32],
+        codesep_pos: Option<u32>,
+        annex: Option<&[u8]>,
+        sighash_type: u32,
+    ) -> Result<[u8; 32]> {
+        let leaf = (leaf_hash, codesep_pos.unwrap_or(u32::MAX));
+        self.taproot_sighash(input_index, prevouts, annex, Some(leaf), sighash_type)
+    }
+
+    fn taproot_sighash(
+        &mut self,
+        input_index: usize,
+        prevouts: &[TxOut],
+        annex: Option<&[u8]>,
+        leaf: Option<(&[u8; 32], u32)>,
+        sighash_type: u32,
+    ) -> Result<[u8; 32]> {
+        let tx = self.tx;
+        let input = tx
+            .inputs
+            .get(input_index)
+            .ok_or(Error::InvalidTransaction("input index out of range"))?;
+
+        if prevouts.len() != tx.inputs.len() {
+            return Err(Error::InvalidTransaction(
+                "one spent output per input needed",
+            ));
+        }
+
+        if !matches!(sighash_type, 0x00..=0x03 | 0x81..=0x83) {
+            return Err(Error::InvalidTransaction("invalid taproot sighash type"));
+        }
+
+        if annex.is_some_and(|annex| annex.first() != Some(&0x50)) {
+            return Err(Error::InvalidTransaction("annex must start with 0x50"));
+        }
+
+        let base_type = sighash_type & 0x03;
+        let anyone_can_pay = sighash_type & SIGHASH_ANYONECANPAY != 0;
+        if base_type == SIGHASH_SINGLE && input_index >= tx.outputs.len() {
+            return Err(Error::InvalidTransaction("no output for SIGHASH_SINGLE"));
+        }
+
+        // epoch, always zero for now
+        let mut message = vec![0x00, sighash_type as u8];
+        tx.version.consensus_encode(&mut message)?;
+        tx.locktime.consensus_encode(&mut message)?;
+
+        if !anyone_can_pay {
+            let common = self.midstates()?.clone();
+            let taproot = self.taproot_midstates(prevouts)?;
+            message.extend_from_slice(&common.prevouts);
+            message.extend_from_slice(&taproot.amounts);
+            message.extend_from_slice(&taproot.script_pubkeys);
+            message.extend_from_slice(&common.sequences);
+        }
+
+        if base_type != SIGHASH_NONE && base_type != SIGHASH_SINGLE {
+            message.extend_from_slice(&self.midstates()?.outputs);
+        }
+
+        let spend_type = (leaf.is_some() as u8) << 1 | annex.is_some() as u8;
+        message.push(spend_type);
+
+        if anyone_can_pay {
+            let prevout = &prevouts[input_index];
+            input.previous_output.consensus_encode(&mut message)?;
+            prevout.amount.consensus_encode(&mut message)?;
+            prevout.script_pubkey.consensus_encode(&mut message)?;
+            input.sequence.consensus_encode(&mut message)?;
+        } else {
+            (input_index as u32).consensus_encode(&mut message)?;
+        }
+
+        if let Some(annex) = annex {
+            let mut encoded = Vec::with_capacity(annex.len() + 9);
+            consensus::write_compact_size(&mut encoded, annex.len() as u64)?;
+            encoded.extend_from_slice(annex);
+            message.extend(Sha256::digest(&encoded));
+        }
+
+        if base_type == SIGHASH_SINGLE {
+            let output = tx.outputs[input_index].serialize()?;
+            message.extend(Sha256::digest(&output));
+        }
+
+        if let Some((leaf_hash, codesep_pos)) = leaf {
+            message.extend_from_slice(leaf_hash);
+            // key version, only 0x00 is defined
+            message.push(0x00);
+            codesep_pos.consensus_encode(&mut message)?;
+        }
+
+        Ok(tagged_hash("TapSighash", message))
+    }
+
+    fn midstates(&mut self) -> Result<&Midstates> {
+        if self.common.is_none() {
+            let (mut prevouts, mut sequences) = (Vec::new(), Vec::new());
+            for input in &self.tx.inputs {
+                input.previous_output.consensus_encode(&mut prevouts)?;
+                input.sequence.consensus_encode(&mut sequences)?;
+            }
+
+            let mut outputs = Vec::new();
+            for output in &self.tx.outputs {
+                output.consensus_encode(&mut outputs)?;
+            }
+
+            self.common = Some(Midstates {
+                prevouts: Sha256::digest(&prevouts).into(),
+                sequences: Sha256::digest(&sequences).into(),
+                outputs: Sha256::digest(&outputs).into(),
+            });
+        }
+
+        Ok(self.common.as_ref().unwrap())
+    }
+
+    fn segwit_v0_midstates(&mut self) -> Result<&SegwitV0Midstates> {
+        if self.segwit_v0.is_none() {
+            let common = self.midstates()?;
+            self.segwit_v0 = Some(SegwitV0Midstates {
+                prevouts: Sha256::digest(&common.prevouts).into(),
+                sequences: Sha256::digest(&common.sequences).into(),
+                outputs: Sha256::digest(&common.outputs).into(),
+            });
+        }
+
+        Ok(self.segwit_v0.as_ref().unwrap())
+    }
+
+    fn taproot_midstates(&mut self, prevouts: &[TxOut]) -> Result<&TaprootMidstates> {
+        if self.taproot.is_none() {
+            let (mut amounts, mut script_pubkeys) = (Vec::new(), Vec::new());
+            for prevout in prevouts {
+                prevout.amount.consensus_encode(&mut amounts)?;
+                prevout
+                    .script_pubkey
+                    .consensus_encode(&mut script_pubkeys)?;
+            }
+
+            self.taproot = Some(TaprootMidstates {
+                amounts: Sha256::digest(&amounts).into(),
+                script_pubkeys: Sha256::digest(&script_pubkeys).into(),
+            });
+        }
+
+        Ok(self.taproot.as_ref().unwrap())
+    }
+}
+
+fn double_sha256(data: &[u8]) -> [u8; 32] {
+    let mut digest = [0; 32];
+    digest.copy_from_slice(&hash256(data));
+    digest
+}
+
+/// The script code with its `OP_CODESEPARATOR`s removed, pushed data is kept as is
+fn remove_codeseparators(script: &Script) -> Script {
+    let mut result = Vec::with_capacity(script.bytes.len());
+    let mut instructions = script.instructions();
+
+    loop {
+        let rest = instructions.as_bytes();
+        let instruction = match instructions.next() {
+            Some(instruction) => instruction,
+            None => break,
+        };
+
+        match instruction {
+            Ok(Instruction::Op(Opcode::OP_CODESEPARATOR)) => {}
+            Ok(_) => {
+                let len = rest.len() - instructions.as_bytes().len();
+                result.extend_from_slice(&rest[..len]);
+            }
+            // truncated pushes are kept up to the end of the script
+            Err(_) => result.extend_from_slice(rest),
+        }
+    }
+
+    Script::from(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use hex_literal::hex;
+
+    use crate::consensus;
+    use crate::secp256k1::crypto::PublicKey;
+    use crate::secp256k1::signature::Signature;
+
+    use super::*;
+
+    fn p2pkh_code() -> Script {
+        Script::from(hex!("76a9141d0f172a0ecb48aee1be1f2687d2963ae33f71a188ac").to_vec())
+    }
+
+    #[test]
+    fn legacy_sighash_verifies() -> Result<()> {
+        // mainnet 452c629d..., the digest must verify the signature in its script sig
+        let raw = hex!("0100000001813f79011acb80925dfe69b3def355fe914bd1d96a3f5f71bf8303c6a989c7d1000000006b483045022100ed81ff192e75a3fd2304004dcadb746fa5e24c5031ccfcf21320b0277457c98f02207a986d955c6e0cb35d446a89d3f56100f4d7f67801c31967743a9c8e10615bed01210349fc4e631e3624a545de3f89f5d8684c7b8138bd94bdd531d2e213bf016b278afeffffff02a135ef01000000001976a914bc3b654dca7e56b04dca18f2566cdaf02e8d9ada88ac99c39800000000001976a9141c4bc762dd5423e332166702cb75f40df79fea1288ac19430600");
+        let tx: Transaction = consensus::deserialize(&raw)?;
+
+        let script_sig = &tx.inputs[0].script_sig.bytes;
+        let signature = &script_sig[1..1 + script_sig[0] as usize];
+        let sec = &script_sig[2 + script_sig[0] as usize..];
+
+        let script_code =
+            Script::from(hex!("76a914a802fc56c704ce87c42d7c92eb75e7896bdc41ae88ac").to_vec());
+        let digest = tx.legacy_sighash(0, &script_code, SIGHASH_ALL)?;
+        assert_eq!(
+            digest,
+            hex!("27e0c5994dec7824e56dec6b2fcb342eb7cdb0d0957c2fce9882f715e85d81a6")
+        );
+
+        let signature = Signature::deserialize(&signature[..signature.len() - 1])?;
+        assert!(signature.is_valid(digest, &PublicKey::deserialize(sec)?)?);
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_sighash_types() -> Result<()> {
+        // unsigned transaction of the BIP143 P2WPKH example
+        let raw = hex!("0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f0000000000eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac11000000");
+        let mut tx: Transaction = consensus::deserialize(&raw)?;
+
+        let cases = [
+            (
+                SIGHASH_ALL,
+                hex!("c46030820cbc48402a47cc5b5d3d41648f4e3a711f56b804d601d09dc112a6a4"),
+            ),
+            (
+                SIGHASH_NONE,
+                hex!("ffbbcf554debe55f76a79db7d205edc891f194184a93a660366bb8f7facb89e2"),
+            ),
+            (
+                SIGHASH_SINGLE,
+                hex!("33cd468bd6b82f04bcef180b748c521d6fdee3b11711a2f27b2e465915afaec2"),
+            ),
+            (
+                SIGHASH_ALL | SIGHASH_ANYONECANPAY,
+                hex!("8cfeea8cfe3a35332ec31f53900716682d964e0c16372b1f7689ed93f3a40756"),
+            ),
+            (
+                SIGHASH_NONE | SIGHASH_ANYONECANPAY,
+                hex!("bd8ca4cb1ab60a8db8451bd58bc068a9abd5ea20a08029b38934c9d50c1d6721"),
+            ),
+            (
+                SIGHASH_SINGLE | SIGHASH_ANYONECANPAY,
+                hex!("865c7791b88917498a4c402176c302f146c53a6c2f50ecda08548f515237dca6"),
+            ),
+        ];
+
+        for (sighash_type, expected) in cases.iter() {
+            assert_eq!(
+                &tx.legacy_sighash(1, &p2pkh_code(), *sighash_type)?,
+                expected
+            );
+        }
+
+        // code separators aren't part of the signed script
+        let mut with_separator = vec![u8::from(Opcode::OP_CODESEPARATOR)];
+        with_separator.extend(&p2pkh_code().bytes);
+        assert_eq!(
+            tx.legacy_sighash(1, &Script::from(with_separator), SIGHASH_ALL)?,
+            cases[0].1
+        );
+
+        // SIGHASH_SINGLE without a matching output
+        tx.outputs.truncate(1);
+        assert_eq!(
+            tx.legacy_sighash(1, &p2pkh_code(), SIGHASH_SINGLE)?,
+            SIGHASH_SINGLE_BUG
+        );
+        assert!(tx.legacy_sighash(2, &p2pkh_code(), SIGHASH_ALL).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn segwit_v0_sighash_verifies() -> Result<()> {
+        // BIP143 native P2WPKH example, signature of the second input
+        let raw = hex!("01000000000102fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f00000000494830450221008b9d1dc26ba6a9cb62127b02742fa9d754cd3bebf337f7a55d114c8e5cdd30be022040529b194ba3f9281a99f2b1c0a19c0489bc22ede944ccf4ecbab4cc618ef3ed01eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac000247304402203609e17b84f6a7d30c80bfa610b5b4542f32a8a0d5447a12fb1366d7f01cc44a0220573a954c4518331561406f90300e8f3358f51928d43c212a8caed02de67eebee0121025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee635711000000");
+        let tx: Transaction = consensus::deserialize(&raw)?;
+        let value = Amount::from_sat(600_000_000);
+
+        let mut cache = SighashCache::new(&tx);
+        let digest = cache.segwit_v0_sighash(1, &p2pkh_code(), value, SIGHASH_ALL)?;
+        assert_eq!(
+            digest,
+            hex!("c37af31116d1b27caf68aae9e3ac82f1477929014d5b917657d0eb49478cb670")
+        );
+
+        let midstates = cache.segwit_v0.clone().unwrap();
+        assert_eq!(
+            midstates.prevouts,
+            hex!("96b827c8483d4e9b96712b6713a7b68d6e8003a781feba36c31143470b4efd37")
+        );
+        assert_eq!(
+            midstates.sequences,
+            hex!("52b0a642eea2fb7ae638c36f6252b6750293dbe574a806984b8e4d8548339a3b")
+        );
+        assert_eq!(
+            midstates.outputs,
+            hex!("863ef3e1a92afbfdb97f31ad0fc7683ee943e9abcf2501590ff8f6551f47e5e5")
+        );
+
+        let witness = &tx.inputs[1].witness;
+        let signature = Signature::deserialize(&witness[0][..witness[0].len() - 1])?;
+        assert!(signature.is_valid(digest, &PublicKey::deserialize(&witness[1][..])?)?);
+        Ok(())
+    }
+
+    #[test]
+    fn segwit_v0_sighash_types() -> Result<()> {
+        let raw = hex!("0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f0000000000eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac11000000");
+        let mut tx: Transaction = consensus::deserialize(&raw)?;
+        let value = Amount::from_sat(600_000_000);
+
+        let cases = [
+            (
+                SIGHASH_NONE,
+                hex!("6ff11a9b87fb510a3a31af006bd3811b632f8a39d88a2bfda49cee203dcc356e"),
+            ),
+            (
+                SIGHASH_SINGLE,
+                hex!("f4fe57286dd2ca8ac0e3dfccd54c352fcdcacbed80f194e264b75d7a7c74e4ce"),
+            ),
+            (
+                SIGHASH_ALL | SIGHASH_ANYONECANPAY,
+                hex!("fc5b6bbc855883bcfdaefb77071740ccde4929f15e6a13286584e779b2529d91"),
+            ),
+            (
+                SIGHASH_NONE | SIGHASH_ANYONECANPAY,
+                hex!("4abb5ef58a968f8e1ab88a9fb72f2ce74b3022e65d334ac7b8aeda747515dc15"),
+            ),
+            (
+                SIGHASH_SINGLE | SIGHASH_ANYONECANPAY,
+                hex!("79ff9ff708f79ce8f7a4f90d62028533a99d7340b7fb3d819dfd9a599a78e39c"),
+            ),
+        ];
+
+        let mut cache = SighashCache::new(&tx);
+        for (sighash_type, expected) in cases.iter() {
+            let digest = cache.segwit_v0_sighash(1, &p2pkh_code(), value, *sighash_type)?;
+            assert_eq!(&digest, expected);
+        }
+
+        // no output at the same index, no bug here, the outputs hash is just zeros
+        tx.outputs.truncate(1);
+        assert_eq!(
+            tx.segwit_v0_sighash(1, &p2pkh_code(), value, SIGHASH_SINGLE)?,
+            hex!("471a6e7963aa0c328ee12392fb1a345148edf326b4223660e1a770fdc2826435")
+        );
+        assert!(tx
+            .segwit_v0_sighash(2, &p2pkh_code(), value, SIGHASH_ALL)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn taproot_sighash_types() -> Result<()> {
+        let raw = hex!("0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f0000000000eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac11000000");
+        let mut tx: Transaction = consensus::deserialize(&raw)?;
+        let prevouts = [
+            TxOut::new(
+                Amount::ONE_BTC,
+                Script::from(
+                    hex!("512053a1f6e454df1aa2776a2814a721372d6258050de330b3c6d10ee8f4e0dda343")
+                        .to_vec(),
+                ),
+            ),
+            TxOut::new(
+                Amount::from_sat(200_000_000),
+                Script::from(
+                    hex!("5120147c9c57132f6e7ecddba9800bb0c4449251c92a1e60371ee77557b6620f3ea3")
+                        .to_vec(),
+                ),
+            ),
+        ];
+
+        let cases = [
+            (
+                SIGHASH_DEFAULT,
+                hex!("090233f35896c16b422bec8680541492d2ab2e380471441559be21b9c4244d27"),
+            ),
+            (
+                SIGHASH_ALL,
+                hex!("7b9a352f72d366dd674b36b9a68d1649d8b61e473f647643416e571585890c09"),
+            ),
+            (
+                SIGHASH_NONE,
+                hex!("9e47a999cd8d073ae5242e26b6afee440d2b5d0980dd3c0facaa1c13cb26af2e"),
+            ),
+            (
+                SIGHASH_SINGLE,
+                hex!("51672fb57e3d78139b2629daacdf5c4e89bde7a16586843a554683a943eefc1d"),
+            ),
+            (
+                SIGHASH_ALL | SIGHASH_ANYONECANPAY,
+                hex!("b8936f144fcfea41b7aa953f3d30bf518309931e5bab6cfccb505e69ec9cc3cc"),
+            ),
+            (
+                SIGHASH_NONE | SIGHASH_ANYONECANPAY,
+                hex!("b74fe91cf737a3be52cd509a9c1bacfd8c55614774be7d3a2c7a9a690e382b14"),
+            ),
+            (
+                SIGHASH_SINGLE | SIGHASH_ANYONECANPAY,
+                hex!("5293e28378ba179f8402a0554a8dbef820bc07c216be143c3843ac42440dc9e1"),
+            ),
+        ];
+
+        let mut cache = SighashCache::new(&tx);
+        for (sighash_type, expected) in cases.iter() {
+            let digest = cache.taproot_key_spend_sighash(1, &prevouts, None, *sighash_type)?;
+            assert_eq!(&digest, expected);
+        }
+
+        let annex = hex!("50aa");
+        assert_eq!(
+            cache.taproot_key_spend_sighash(1, &prevouts, Some(&annex), SIGHASH_DEFAULT)?,
+            hex!("a07243aba010b64ccd1069ec1b834c15b7d2472c5cd80ea55dc44ae168e04420")
+        );
+
+        let leaf_hash = [0x11; 32];
+        assert_eq!(
+            cache.taproot_script_spend_sighash(
+                0,
+                &prevouts,
+                &leaf_hash,
+                None,
+                None,
+                SIGHASH_DEFAULT
+            )?,
+            hex!("12820cc407281dbb5e7d09971e52cc258f70b103e38e4f23a900669d07a9ae3f")
+        );
+        assert_eq!(
+            cache.taproot_script_spend_sighash(
+                0,
+                &prevouts,
+                &leaf_hash,
+                Some(3),
+                Some(&[0x50]),
+                SIGHASH_SINGLE | SIGHASH_ANYONECANPAY
+            )?,
+            hex!("9d7656b47dedaaf9559a404d730b71360e8b7bf9aee6067ec5598afacde8db8f")
+        );
+
+        assert!(cache
+            .taproot_key_spend_sighash(1, &prevouts, None, 0x04)
+            .is_err());
+        assert!(cache
+            .taproot_key_spend_sighash(1, &prevouts, None, 0x80)
+            .is_err());
+        assert!(cache
+            .taproot_key_spend_sighash(1, &prevouts[..1], None, SIGHASH_ALL)
+            .is_err());
+        assert!(cache
+            .taproot_key_spend_sighash(1, &prevouts, Some(&[0x51]), SIGHASH_ALL)
+            .is_err());
+
+        // unlike the legacy algorithms there's no digest to sign without an output
+        tx.outputs.truncate(1);
+        let mut cache = SighashCache::new(&tx);
+        assert!(cache
+            .taproot_key_spend_sighash(1, &prevouts, None, SIGHASH_SINGLE)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn codeseparators_inside_pushes() {
+        let script = Script::from(hex!("ab02abab4c01abab4d").to_vec());
+        assert_eq!(remove_codeseparators(&script).bytes, hex!("02abab4c01ab4d"));
+    }
+}
@@ -0,0 +1,286 @@
+use crate::secp256k1::crypto::PrivateKey;
+use crate::secp256k1::schnorr;
+use crate::taproot::tweak_private_key;
+use crate::utils::hash160;
+use crate::{Error, Result};
+
+use super::output::TxOut;
+use super::script::{push_slice, Script};
+use super::sighash::{SighashCache, SIGHASH_ALL, SIGHASH_DEFAULT};
+use super::tx::Transaction;
+use super::witness::Witness;
+
+impl Transaction {
+    /// Sign the P2PKH input at `index` with `SIGHASH_ALL` and set its script sig,
+    /// `prev_script` is the spent script pubkey and decides whether the public key
+    /// goes compressed or not
+    pub fn sign_p2pkh_input(
+        &mut self,
+        index: usize,
+        key: &PrivateKey,
+        prev_script: &Script,
+    ) -> Result<()> {
+        let hash = prev_script
+            .p2pkh_hash()
+            .ok_or(Error::InvalidTransaction("spent output isn't p2pkh"))?;
+
+        let public_key = key.public_key();
+        let sec = [true, false]
+            .iter()
+            .map(|compressed| public_key.serialize(*compressed))
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .find(|sec| hash160(sec) == hash)
+            .ok_or(Error::InvalidTransaction(
+                "key doesn't match the spent output",
+            ))?;
+
+        let digest = self.legacy_sighash(index, prev_script, SIGHASH_ALL)?;
+        let mut signature = key.create_signature(digest)?.serialize()?;
+        signature.push(SIGHASH_ALL as u8);
+
+        let mut script_sig = Vec::new();
+        push_slice(&mut script_sig, &signature);
+        push_slice(&mut script_sig, &sec);
+        self.inputs[index].script_sig = Script::from(script_sig);
+        Ok(())
+    }
+
+    /// Sign the P2WPKH input at `index` spending `prev_output` with `SIGHASH_ALL`,
+    /// the witness becomes the signature and the compressed public key
+    pub fn sign_p2wpkh_input(
+        &mut self,
+        index: usize,
+        key: &PrivateKey,
+        prev_output: &TxOut,
+    ) -> Result<()> {
+        let hash = prev_output
+            .script_pubkey
+            .p2wpkh_hash()
+            .ok_or(Error::InvalidTransaction("spent output isn't p2wpkh"))?;
+
+        let sec = key.public_key().serialize_compressed()?;
+        if hash160(sec) != hash {
+            return Err(Error::InvalidTransaction(
+                "key doesn't match the spent output",
+            ));
+        }
+
+        // BIP143 script code, the P2PKH script of the same hash
+        let mut script_code = vec![0x76, 0xa9, 0x14];
+        script_code.extend_from_slice(hash);
+        script_code.extend_from_slice(&[0x88, 0xac]);
+
+        let digest = self.segwit_v0_sighash(
+            index,
+            &Script::from(script_code),
+            prev_output.amount,
+            SIGHASH_ALL,
+        )?;
+        let mut signature = key.create_signature(digest)?.serialize()?;
+        signature.push(SIGHASH_ALL as u8);
+
+        self.inputs[index].witness = Witness::p2wpkh(&signature, &sec);
+        Ok(())
+    }
+
+    /// Sign the taproot key path spend of the input at `index`, `prevouts` are the
+    /// outputs spent by every input and `merkle_root` the script tree the output key
+    /// commits to. The signature is 64 bytes with `SIGHASH_DEFAULT`, 65 otherwise
+    pub fn sign_p2tr_key_spend(
+        &mut self,
+        index: usize,
+        key: &PrivateKey,
+        prevouts: &[TxOut],
+        merkle_root: Option<&[u8; 32]>,
+        sighash_type: u32,
+    ) -> Result<()> {
+        let output_key = prevouts
+            .get(index)
+            .and_then(|prevout| prevout.script_pubkey.p2tr_output_key())
+            .ok_or(Error::InvalidTransaction("spent output isn't p2tr"))?;
+
+        let tweaked = tweak_private_key(key, merkle_root)?;
+        if schnorr::x_only_public_key(&tweaked) != output_key {
+            return Err(Error::InvalidTransaction(
+                "key doesn't match the spent output",
+            ));
+        }
+
+        let digest = SighashCache::new(self).taproot_key_spend_sighash(
+            index,
+            prevouts,
+            None,
+            sighash_type,
+        )?;
+        let mut signature = schnorr::sign(&tweaked, &digest, &rand::random())?.to_vec();
+        if sighash_type != SIGHASH_DEFAULT {
+            signature.push(sighash_type as u8);
+        }
+
+        self.inputs[index].witness = Witness::p2tr_key_spend(&signature);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use hex_literal::hex;
+
+    use crate::amount::Amount;
+    use crate::consensus;
+    use crate::core::input::{OutPoint, TxIn};
+    use crate::core::txid::Txid;
+    use crate::secp256k1::signature::Signature;
+
+    use super::*;
+
+    fn p2pkh_script(sec: &[u8]) -> Script {
+        let mut script = vec![0x76, 0xa9, 0x14];
+        script.extend(hash160(sec));
+        script.extend(&[0x88, 0xac]);
+        Script::from(script)
+    }
+
+    fn unsigned_tx() -> Transaction {
+        let inputs = vec![
+            TxIn::new(OutPoint::new(Txid::from_bytes([0x11; 32]), 0)),
+            TxIn::new(OutPoint::new(Txid::from_bytes([0x22; 32]), 1)),
+        ];
+        let output = TxOut::new(Amount::from_sat(90_000), Script::from(vec![0x51]));
+        Transaction::new(1, inputs, vec![output], 0)
+    }
+
+    #[test]
+    fn sign_p2pkh() -> Result<()> {
+        let key = PrivateKey::new(8675309u32);
+        let public_key = key.public_key();
+
+        for compressed in [true, false].iter() {
+            let sec = public_key.serialize(*compressed)?;
+            let prev_script = p2pkh_script(&sec);
+
+            let mut tx = unsigned_tx();
+            tx.sign_p2pkh_input(1, &key, &prev_script)?;
+            assert!(tx.inputs[0].script_sig.bytes.is_empty());
+
+            // <signature> <public key>
+            let script_sig = &tx.inputs[1].script_sig.bytes;
+            let signature = &script_sig[1..1 + script_sig[0] as usize];
+            assert_eq!(signature.last(), Some(&(SIGHASH_ALL as u8)));
+            assert_eq!(&script_sig[2 + signature.len()..], &sec[..]);
+            assert_eq!(script_sig[1 + signature.len()] as usize, sec.len());
+
+            let digest = tx.legacy_sighash(1, &prev_script, SIGHASH_ALL)?;
+            let signature = Signature::deserialize(&signature[..signature.len() - 1])?;
+            assert!(signature.is_valid(digest, public_key)?);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn sign_p2pkh_wrong_script() -> Result<()> {
+        let key = PrivateKey::new(8675309u32);
+        let other = PrivateKey::new(12345u32).public_key().serialize(true)?;
+
+        let mut tx = unsigned_tx();
+        assert!(tx.sign_p2pkh_input(0, &key, &p2pkh_script(&other)).is_err());
+        assert!(tx
+            .sign_p2pkh_input(0, &key, &Script::from(vec![0x51]))
+            .is_err());
+
+        let sec = key.public_key().serialize(true)?;
+        assert!(tx.sign_p2pkh_input(2, &key, &p2pkh_script(&sec)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn sign_p2wpkh_bip143() -> Result<()> {
+        // BIP143 native P2WPKH example, the second input is P2WPKH
+        let raw = hex!("0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f0000000000eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac11000000");
+        let mut tx: Transaction = consensus::deserialize(&raw)?;
+
+        let key = PrivateKey::from_bytes_be(hex!(
+            "619c335025c7f4012e556c2a58b2506e30b8511b53ade95ea316fd8c3286feb9"
+        ));
+        let prev_output = TxOut::new(
+            Amount::from_sat(600_000_000),
+            Script::from(hex!("00141d0f172a0ecb48aee1be1f2687d2963ae33f71a1").to_vec()),
+        );
+
+        tx.sign_p2wpkh_input(1, &key, &prev_output)?;
+        assert_eq!(
+            tx.inputs[1].witness[..],
+            [
+                hex!("304402203609e17b84f6a7d30c80bfa610b5b4542f32a8a0d5447a12fb1366d7f01cc44a0220573a954c4518331561406f90300e8f3358f51928d43c212a8caed02de67eebee01").to_vec(),
+                hex!("025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee6357").to_vec(),
+            ]
+        );
+        assert!(tx.inputs[1].script_sig.bytes.is_empty());
+
+        // wrong key, wrong script and missing input
+        let other = PrivateKey::new(12345u32);
+        assert!(tx.sign_p2wpkh_input(1, &other, &prev_output).is_err());
+        let p2pkh = TxOut::new(
+            prev_output.amount,
+            p2pkh_script(&key.public_key().serialize(true)?),
+        );
+        assert!(tx.sign_p2wpkh_input(1, &key, &p2pkh).is_err());
+        assert!(tx.sign_p2wpkh_input(2, &key, &prev_output).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn sign_p2tr_key_spend() -> Result<()> {
+        let key = PrivateKey::new(8675309u32);
+        let internal_key = schnorr::x_only_public_key(&key);
+        let merkle_root = [0x42; 32];
+
+        for merkle_root in [None, Some(&merkle_root)].iter() {
+            let (output_key, _) = crate::taproot::tweak_public_key(&internal_key, *merkle_root)?;
+            let mut script_pubkey = vec![0x51, 0x20];
+            script_pubkey.extend_from_slice(&output_key);
+
+            let prevouts = vec![
+                TxOut::new(Amount::from_sat(50_000), p2pkh_script(&[0x02; 33])),
+                TxOut::new(Amount::from_sat(60_000), Script::from(script_pubkey)),
+            ];
+
+            let mut tx = unsigned_tx();
+            for sighash_type in [SIGHASH_DEFAULT, SIGHASH_ALL].iter() {
+                tx.sign_p2tr_key_spend(1, &key, &prevouts, *merkle_root, *sighash_type)?;
+
+                let witness = tx.inputs[1].witness.clone();
+                assert_eq!(witness.len(), 1);
+                let signature = &witness[0];
+                match *sighash_type {
+                    SIGHASH_DEFAULT => assert_eq!(signature.len(), 64),
+                    _ => assert_eq!(signature[64..], [*sighash_type as u8]),
+                }
+
+                let digest = SighashCache::new(&tx).taproot_key_spend_sighash(
+                    1,
+                    &prevouts,
+                    None,
+                    *sighash_type,
+                )?;
+                let mut schnorr_signature = [0; 64];
+                schnorr_signature.copy_from_slice(&signature[..64]);
+                assert!(schnorr::verify(&output_key, &digest, &schnorr_signature));
+            }
+
+            // not the key of the output, or not a taproot output
+            let other = PrivateKey::new(12345u32);
+            assert!(tx
+                .sign_p2tr_key_spend(1, &other, &prevouts, *merkle_root, SIGHASH_DEFAULT)
+                .is_err());
+            assert!(tx
+                .sign_p2tr_key_spend(0, &key, &prevouts, *merkle_root, SIGHASH_DEFAULT)
+                .is_err());
+        }
+
+        Ok(())
+    }
+}
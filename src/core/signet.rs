@@ -0,0 +1,309 @@
+//! Signet block solutions (BIP325): blocks are valid once a transaction spending
+//! the network's challenge script, committed to by the block, is satisfied
+
+use hex_literal::hex;
+
+use crate::amount::Amount;
+use crate::consensus::{self, Decodable, Encodable};
+use crate::utils::hash256;
+use crate::{Error, Result};
+
+use super::block::Block;
+use super::input::{OutPoint, TxIn};
+use super::interpreter::{verify_script, TransactionChecker, VerifyFlags};
+use super::merkle::merkle_root;
+use super::output::TxOut;
+use super::script::{push_slice, Instruction, Script};
+use super::tx::Transaction;
+use super::witness::Witness;
+
+/// Start of the push carrying the solution in the witness commitment output
+pub const SIGNET_HEADER: [u8; 4] = [0xec, 0xc7, 0xda, 0xa2];
+/// 1-of-2 multisig of the default signet
+pub const DEFAULT_SIGNET_CHALLENGE: [u8; 71] = hex!("512103ad5e0edad18cb1f0fc0d28a3d4f1f3e445640337489abb10404f2d1e086be430210359ef5021964fe22d6f8e05b2463c9540ce96883fe3b278760f048f5189f2e6c452ae");
+
+/// Message start of the signet of `challenge`, the first bytes of its hash
+pub fn signet_magic(challenge: &Script) -> [u8; 4] {
+    let mut data = Vec::new();
+    consensus::write_compact_size(&mut data, challenge.len() as u64)
+        .expect("writes to a vector don't fail");
+    data.extend_from_slice(challenge.as_bytes());
+
+    let mut magic = [0; 4];
+    magic.copy_from_slice(&hash256(data)[..4]);
+    magic
+}
+
+/// The virtual transactions of a block: `to_spend` pays to the challenge and
+/// commits to the block without its solution, `to_sign` spends it with the
+/// solution
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignetTxs {
+    pub(crate) to_spend: Transaction,
+    pub(crate) to_sign: Transaction,
+}
+
+impl SignetTxs {
+    pub fn new(block: &Block, challenge: &Script) -> Result<Self> {
+        let mut coinbase = block
+            .coinbase()
+            .filter(|tx| tx.is_coinbase())
+            .cloned()
+            .ok_or(Error::InvalidBlock("missing coinbase"))?;
+        let index = coinbase
+            .witness_commitment_index()
+            .ok_or(Error::InvalidBlock("missing witness commitment"))?;
+
+        let (script, solution) = replace_solution(&coinbase.outputs[index].script_pubkey, None)?;
+        coinbase.outputs[index].script_pubkey = script;
+
+        let mut txids = vec![coinbase.txid()?.to_bytes()];
+        for tx in &block.transactions()[1..] {
+            txids.push(tx.txid()?.to_bytes());
+        }
+
+        // the header without its bits and nonce, committing to the modified block
+        let header = block.header();
+        let mut block_data = Vec::with_capacity(72);
+        header.version().consensus_encode(&mut block_data)?;
+        header.prev_blockhash().consensus_encode(&mut block_data)?;
+        block_data.extend_from_slice(&merkle_root(&txids));
+        header.time().consensus_encode(&mut block_data)?;
+
+        let mut script_sig = vec![0x00];
+        push_slice(&mut script_sig, &block_data);
+        let mut input = TxIn::new(OutPoint::null());
+        input.script_sig = Script::from(script_sig);
+        input.sequence = 0;
+        let to_spend = Transaction::new(
+            0,
+            vec![input],
+            vec![TxOut::new(Amount::ZERO, challenge.clone())],
+            0,
+        );
+
+        let mut input = TxIn::new(OutPoint::new(to_spend.txid()?, 0));
+        input.sequence = 0;
+        if let Some(solution) = solution {
+            let mut reader = solution.as_slice();
+            input.script_sig = Script::consensus_decode(&mut reader)?;
+            input.witness = Witness::consensus_decode(&mut reader)?;
+            if !reader.is_empty() {
+                return Err(Error::InvalidBlock("trailing bytes in signet solution"));
+            }
+        }
+        let to_sign = Transaction::new(
+            0,
+            vec![input],
+            vec![TxOut::new(Amount::ZERO, Script::from(vec![0x6a]))],
+            0,
+        );
+
+        Ok(Self { to_spend, to_sign })
+    }
+
+    pub fn to_spend(&self) -> &Transaction {
+        &self.to_spend
+    }
+
+    pub fn to_sign(&self) -> &Transaction {
+        &self.to_sign
+    }
+
+    /// Whether the solution satisfies the challenge under the block rules
+    pub fn verify(&self) -> Result<()> {
+        let flags =
+            VerifyFlags::P2SH | VerifyFlags::WITNESS | VerifyFlags::DERSIG | VerifyFlags::NULLDUMMY;
+        let prevouts = &self.to_spend.outputs[..1];
+        let input = &self.to_sign.inputs[0];
+        let mut checker = TransactionChecker::new(&self.to_sign, prevouts, 0);
+        verify_script(
+            &input.script_sig,
+            &prevouts[0].script_pubkey,
+            &input.witness,
+            flags,
+            &mut checker,
+        )
+        .map_err(|error| Error::InvalidInput { index: 0, error })
+    }
+}
+
+impl Block {
+    /// Whether the block's solution satisfies `challenge`, the genesis block
+    /// has none
+    pub fn check_signet_solution(&self, challenge: &Script) -> Result<()> {
+        if self.header().prev_blockhash() == &Default::default() {
+            return Ok(());
+        }
+
+        SignetTxs::new(self, challenge)?.verify()
+    }
+
+    /// Puts the script sig and witness of `to_sign` as the solution, in the
+    /// witness commitment output
+    pub fn set_signet_solution(&mut self, script_sig: &Script, witness: &Witness) -> Result<()> {
+        let mut solution = Vec::new();
+        script_sig.consensus_encode(&mut solution)?;
+        witness.consensus_encode(&mut solution)?;
+
+        let coinbase = self
+            .txdata
+            .first_mut()
+            .filter(|tx| tx.is_coinbase())
+            .ok_or(Error::InvalidBlock("missing coinbase"))?;
+        let index = coinbase
+            .witness_commitment_index()
+            .ok_or(Error::InvalidBlock("missing witness commitment"))?;
+
+        let output = &mut coinbase.outputs[index];
+        output.script_pubkey = replace_solution(&output.script_pubkey, Some(&solution))?.0;
+        self.header.merkle_root = self.compute_merkle_root()?.0;
+        Ok(())
+    }
+
+    /// Signs the block for `challenge`: `sign` fills the script sig and witness
+    /// of the `to_sign` transaction spending the given output, like
+    /// [`Transaction::sign_p2wpkh_input`] does, which then become the solution
+    pub fn sign_signet(
+        &mut self,
+        challenge: &Script,
+        sign: impl FnOnce(&mut Transaction, &TxOut) -> Result<()>,
+    ) -> Result<()> {
+        // the solution is left out of what's signed, an empty one already has
+        // the place it goes in
+        self.set_signet_solution(&Script::new(), &Witness::new())?;
+        let SignetTxs {
+            to_spend,
+            mut to_sign,
+        } = SignetTxs::new(self, challenge)?;
+
+        sign(&mut to_sign, &to_spend.outputs[0])?;
+        let input = &to_sign.inputs[0];
+        self.set_signet_solution(&input.script_sig, &input.witness)
+    }
+}
+
+/// The witness commitment `script` with the first push starting with the
+/// signet header, and having more, replaced by the header and `solution`,
+/// appended if there's none. The solution found is returned
+fn replace_solution(script: &Script, solution: Option<&[u8]>) -> Result<(Script, Option<Vec<u8>>)> {
+    let mut replaced = Vec::with_capacity(script.len());
+    let mut found = None;
+    for instruction in script.instructions() {
+        match instruction? {
+            Instruction::PushBytes(data)
+                if found.is_none()
+                    && data.len() > SIGNET_HEADER.len()
+                    && data.starts_with(&SIGNET_HEADER) =>
+            {
+                found = Some(data[SIGNET_HEADER.len()..].to_vec());
+                push_slice(
+                    &mut replaced,
+                    &[&SIGNET_HEADER[..], solution.unwrap_or_default()].concat(),
+                );
+            }
+            Instruction::PushBytes(data) => push_slice(&mut replaced, data),
+            Instruction::Op(opcode) => replaced.push(opcode.to_u8()),
+        }
+    }
+
+    // left as it is without a solution to put
+    match (&found, solution) {
+        (None, Some(solution)) => {
+            push_slice(&mut replaced, &[&SIGNET_HEADER[..], solution].concat())
+        }
+        (None, None) => return Ok((script.clone(), None)),
+        _ => {}
+    }
+
+    Ok((Script::from(replaced), found))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use anyhow::Result;
+
+    use crate::core::mining::BlockTemplateBuilder;
+    use crate::core::txid::BlockHash;
+    use crate::network::Network;
+    use crate::secp256k1::crypto::PrivateKey;
+    use crate::utils::hash160;
+
+    use super::*;
+
+    fn block() -> Result<Block> {
+        let template = BlockTemplateBuilder::new(
+            BlockHash::from_bytes([0x11; 32]),
+            1000,
+            1_700_000_000,
+            0x1e03_77ae,
+            Script::p2wpkh(&[0x44; 20]),
+        )
+        .build(&[])?;
+        Ok(template.into_block())
+    }
+
+    fn p2wpkh(key: &PrivateKey) -> Result<Script> {
+        let hash = hash160(key.public_key().serialize_compressed()?);
+        Ok(Script::p2wpkh(&hash.as_slice().try_into()?))
+    }
+
+    #[test]
+    fn default_signet_magic() {
+        let challenge = Script::from(DEFAULT_SIGNET_CHALLENGE.to_vec());
+        assert_eq!(signet_magic(&challenge), [0x0a, 0x03, 0xcf, 0x40]);
+    }
+
+    #[test]
+    fn sign_and_check() -> Result<()> {
+        let key = PrivateKey::new(8675309u32);
+        let challenge = p2wpkh(&key)?;
+
+        let mut block = block()?;
+        assert!(block.check_signet_solution(&challenge).is_err());
+        block.sign_signet(&challenge, |tx, output| {
+            tx.sign_p2wpkh_input(0, &key, output)
+        })?;
+        block.check_merkle_root()?;
+        block.check_signet_solution(&challenge)?;
+
+        let txs = SignetTxs::new(&block, &challenge)?;
+        assert_eq!(txs.to_sign().inputs()[0].witness.len(), 2);
+        assert_eq!(txs.to_spend().outputs()[0].script_pubkey, challenge);
+
+        // the nonce isn't committed to, the time is
+        let mut grinded = block.clone();
+        grinded.header.nonce += 1;
+        grinded.check_signet_solution(&challenge)?;
+        let mut later = block.clone();
+        later.header.time += 1;
+        assert!(later.check_signet_solution(&challenge).is_err());
+
+        // a solution by another key
+        let other = PrivateKey::new(12345u32);
+        assert!(block.check_signet_solution(&p2wpkh(&other)?).is_err());
+
+        // signing again replaces the solution instead of adding another one
+        let outputs = block.transactions()[0].outputs().len();
+        block.sign_signet(&challenge, |tx, output| {
+            tx.sign_p2wpkh_input(0, &key, output)
+        })?;
+        assert_eq!(block.transactions()[0].outputs().len(), outputs);
+        block.check_signet_solution(&challenge)?;
+        Ok(())
+    }
+
+    #[test]
+    fn trivial_challenge() -> Result<()> {
+        // OP_TRUE needs no solution at all
+        let challenge = Script::from(vec![0x51]);
+        let block = block()?;
+        block.check_signet_solution(&challenge)?;
+
+        let genesis = Network::Signet.params().genesis_block();
+        genesis.check_signet_solution(&Script::from(DEFAULT_SIGNET_CHALLENGE.to_vec()))?;
+        Ok(())
+    }
+}
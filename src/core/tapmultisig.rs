@@ -0,0 +1,483 @@
+//! m-of-n tapscript spends (BIP342), `OP_CHECKSIGADD` replaces `OP_CHECKMULTISIG`
+//! in script path spends of taproot outputs
+
+use std::convert::TryFrom;
+
+use crate::secp256k1::crypto::PrivateKey;
+use crate::secp256k1::schnorr;
+use crate::taproot::{tapleaf_hash, TaprootBuilder, TaprootSpendInfo, TAPROOT_LEAF_TAPSCRIPT};
+use crate::{Error, Result};
+
+use super::interpreter::decode_num;
+use super::opcode::Opcode;
+use super::output::TxOut;
+use super::script::{Instruction, Script, ScriptBuilder};
+use super::sighash::{SighashCache, SIGHASH_DEFAULT};
+use super::tx::Transaction;
+use super::witness::Witness;
+
+/// Keys allowed in a single leaf by standardness, like Bitcoin Core
+pub const MAX_PUBKEYS_PER_MULTI_A: usize = 999;
+/// Leaves [`TapLeafStrategy::PerSigners`] builds at most
+const MAX_SIGNER_LEAVES: usize = 1 << 16;
+
+/// How the keys of the multisig are spread over the leaves of the script tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TapLeafStrategy {
+    /// A single leaf with every key
+    Single,
+    /// A `threshold`-of-`threshold` leaf per set of signers, spends only reveal
+    /// the keys that signed and carry no empty signatures
+    PerSigners,
+}
+
+/// The script tree shared by the signers of an m-of-n tapscript multisig
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TapMultisig {
+    pub(crate) threshold: usize,
+    /// X-only keys, sorted
+    pub(crate) keys: Vec<[u8; 32]>,
+    pub(crate) strategy: TapLeafStrategy,
+}
+
+impl TapMultisig {
+    /// `threshold` of the given x-only keys must sign, their order doesn't matter
+    pub fn new(threshold: usize, keys: &[[u8; 32]], strategy: TapLeafStrategy) -> Result<Self> {
+        if keys.len() > MAX_PUBKEYS_PER_MULTI_A {
+            return Err(Error::InvalidMultisig("too many keys"));
+        }
+
+        if threshold == 0 || threshold > keys.len() {
+            return Err(Error::InvalidMultisig("threshold out of range"));
+        }
+
+        let mut keys = keys.to_vec();
+        keys.sort_unstable();
+        if keys.windows(2).any(|pair| pair[0] == pair[1]) {
+            return Err(Error::InvalidMultisig("repeated key"));
+        }
+
+        if strategy == TapLeafStrategy::PerSigners
+            && combinations(keys.len(), threshold).is_none_or(|n| n > MAX_SIGNER_LEAVES)
+        {
+            return Err(Error::InvalidMultisig("too many signer sets"));
+        }
+
+        Ok(Self {
+            threshold,
+            keys,
+            strategy,
+        })
+    }
+
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    pub fn keys(&self) -> &[[u8; 32]] {
+        &self.keys
+    }
+
+    pub fn strategy(&self) -> TapLeafStrategy {
+        self.strategy
+    }
+
+    /// Leaf scripts in tree order, sets of signers in lexicographic order
+    pub fn leaf_scripts(&self) -> Vec<Script> {
+        match self.strategy {
+            TapLeafStrategy::Single => vec![multi_a_script(self.threshold, &self.keys)],
+            TapLeafStrategy::PerSigners => signer_sets(self.keys.len(), self.threshold)
+                .into_iter()
+                .map(|set| {
+                    let keys: Vec<_> = set.iter().map(|i| self.keys[*i]).collect();
+                    multi_a_script(self.threshold, &keys)
+                })
+                .collect(),
+        }
+    }
+
+    /// Commit to the leaves with `internal_key`, as balanced a tree as they make.
+    /// An unspendable internal key leaves the script path as the only way
+    pub fn spend_info(&self, internal_key: &[u8; 32]) -> Result<TaprootSpendInfo> {
+        let scripts = self.leaf_scripts();
+        let depth = balanced_depth(scripts.len());
+        // the leftmost leaves go one level deeper to fill the tree
+        let deeper = 2 * scripts.len() - (1 << depth);
+
+        scripts
+            .iter()
+            .enumerate()
+            .try_fold(TaprootBuilder::new(), |builder, (i, script)| {
+                let leaf_depth = if i < deeper { depth } else { depth - 1 };
+                builder.add_leaf(leaf_depth, &script.bytes)
+            })?
+            .finalize(internal_key)
+    }
+
+    /// `SIGHASH_DEFAULT` digest each signer signs for spending the input at `index`
+    /// through the leaf `script`, `prevouts` are the outputs spent by every input
+    pub fn sighash(
+        &self,
+        tx: &Transaction,
+        index: usize,
+        prevouts: &[TxOut],
+        script: &Script,
+    ) -> Result<[u8; 32]> {
+        let leaf_hash = tapleaf_hash(TAPROOT_LEAF_TAPSCRIPT, &script.bytes);
+        SighashCache::new(tx).taproot_script_spend_sighash(
+            index,
+            prevouts,
+            &leaf_hash,
+            None,
+            None,
+            SIGHASH_DEFAULT,
+        )
+    }
+
+    /// Partial signature of one signer for the leaf `script`, 64 bytes
+    pub fn sign(
+        &self,
+        tx: &Transaction,
+        index: usize,
+        prevouts: &[TxOut],
+        script: &Script,
+        key: &PrivateKey,
+    ) -> Result<Vec<u8>> {
+        let x_only = schnorr::x_only_public_key(key);
+        match script.multi_a_keys() {
+            Some((_, keys)) if keys.contains(&x_only) => {}
+            _ => return Err(Error::InvalidMultisig("key isn't part of the leaf")),
+        }
+
+        let digest = self.sighash(tx, index, prevouts, script)?;
+        Ok(schnorr::sign(key, &digest, &rand::random())?.to_vec())
+    }
+
+    /// Set the witness of the input at `index` from the collected partial
+    /// signatures, spending the first leaf they satisfy. `spend_info` is the tree
+    /// committed to by the spent output, see [`TapMultisig::spend_info`]
+    pub fn finalize(
+        &self,
+        tx: &mut Transaction,
+        index: usize,
+        prevouts: &[TxOut],
+        spend_info: &TaprootSpendInfo,
+        signatures: &[([u8; 32], Vec<u8>)],
+    ) -> Result<()> {
+        for (key, signature) in signatures.iter() {
+            if !self.keys.contains(key) {
+                return Err(Error::InvalidMultisig("key isn't part of the multisig"));
+            }
+
+            if signature.len() != 64 {
+                return Err(Error::InvalidMultisig("unsupported sighash type"));
+            }
+        }
+
+        for script in self.leaf_scripts() {
+            let digest = self.sighash(tx, index, prevouts, &script)?;
+            let valid = |key: &[u8; 32]| {
+                signatures
+                    .iter()
+                    .filter(|(signer, _)| signer == key)
+                    .map(|(_, signature)| signature)
+                    .find(|signature| {
+                        let mut bytes = [0; 64];
+                        bytes.copy_from_slice(signature);
+                        schnorr::verify(key, &digest, &bytes)
+                    })
+            };
+
+            // signatures beyond the threshold are left out, as empty ones
+            let (_, keys) = script.multi_a_keys().unwrap(); // safe, built above
+            let mut found = 0;
+            let mut stack = Vec::with_capacity(keys.len());
+            for key in keys.iter() {
+                match valid(key).filter(|_| found < self.threshold) {
+                    Some(signature) => {
+                        found += 1;
+                        stack.push(signature.clone());
+                    }
+                    None => stack.push(vec![]),
+                }
+            }
+
+            if found < self.threshold {
+                continue;
+            }
+
+            let control_block = spend_info
+                .control_block(&script.bytes, TAPROOT_LEAF_TAPSCRIPT)
+                .ok_or(Error::InvalidMultisig("leaf isn't part of the tree"))?;
+            let input = tx
+                .inputs
+                .get_mut(index)
+                .ok_or(Error::InvalidTransaction("input index out of range"))?;
+
+            // the first key checks the top of the stack
+            stack.reverse();
+            input.witness = Witness::p2tr_script_spend(stack, &script, &control_block);
+            return Ok(());
+        }
+
+        Err(Error::InvalidMultisig("not enough signatures"))
+    }
+}
+
+impl Script {
+    /// `<key> OP_CHECKSIG <key> OP_CHECKSIGADD ... <threshold> OP_NUMEQUAL`, the
+    /// tapscript multisig, keys sorted with `sorted`
+    pub fn multi_a(threshold: usize, keys: &[[u8; 32]], sorted: bool) -> Result<Self> {
+        if keys.len() > MAX_PUBKEYS_PER_MULTI_A {
+            return Err(Error::InvalidMultisig("too many keys"));
+        }
+
+        if threshold == 0 || threshold > keys.len() {
+            return Err(Error::InvalidMultisig("threshold out of range"));
+        }
+
+        let mut keys = keys.to_vec();
+        if sorted {
+            keys.sort_unstable();
+        }
+
+        Ok(multi_a_script(threshold, &keys))
+    }
+
+    /// Threshold and keys of a [`Script::multi_a`] script
+    pub fn multi_a_keys(&self) -> Option<(usize, Vec<[u8; 32]>)> {
+        let instructions = self.instructions().collect::<Result<Vec<_>>>().ok()?;
+        let (numequal, rest) = instructions.split_last()?;
+        let (threshold, rest) = rest.split_last()?;
+
+        if *numequal != Instruction::Op(Opcode::OP_NUMEQUAL) || !rest.len().is_multiple_of(2) {
+            return None;
+        }
+
+        let keys = rest
+            .chunks(2)
+            .enumerate()
+            .map(|(i, pair)| {
+                let check = match i {
+                    0 => Opcode::OP_CHECKSIG,
+                    _ => Opcode::OP_CHECKSIGADD,
+                };
+                match pair {
+                    [Instruction::PushBytes(key), Instruction::Op(opcode)] if *opcode == check => {
+                        <[u8; 32]>::try_from(*key).ok()
+                    }
+                    _ => None,
+                }
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        let threshold = match threshold {
+            Instruction::Op(opcode) => opcode.small_int(),
+            Instruction::PushBytes(bytes) => decode_num(bytes, true, 4).ok(),
+        }?;
+
+        let threshold = usize::try_from(threshold).ok()?;
+        if keys.is_empty() || threshold == 0 || threshold > keys.len() {
+            return None;
+        }
+
+        Some((threshold, keys))
+    }
+}
+
+fn multi_a_script(threshold: usize, keys: &[[u8; 32]]) -> Script {
+    keys.iter()
+        .enumerate()
+        .fold(ScriptBuilder::new(), |builder, (i, key)| {
+            builder.push_slice(key).push_opcode(match i {
+                0 => Opcode::OP_CHECKSIG,
+                _ => Opcode::OP_CHECKSIGADD,
+            })
+        })
+        .push_int(threshold as i64)
+        .push_opcode(Opcode::OP_NUMEQUAL)
+        .into_script()
+}
+
+/// `n choose k`, none on overflow
+fn combinations(n: usize, k: usize) -> Option<usize> {
+    (0..k).try_fold(1usize, |acc, i| {
+        acc.checked_mul(n - i).map(|product| product / (i + 1))
+    })
+}
+
+/// Every set of `k` of the indexes below `n`, in lexicographic order
+fn signer_sets(n: usize, k: usize) -> Vec<Vec<usize>> {
+    let mut sets = Vec::new();
+    let mut set: Vec<usize> = (0..k).collect();
+    loop {
+        sets.push(set.clone());
+
+        // the rightmost index that can still move forward
+        let i = match (0..k).rev().find(|i| set[*i] < n - k + i) {
+            Some(i) => i,
+            None => return sets,
+        };
+
+        set[i] += 1;
+        for j in i + 1..k {
+            set[j] = set[j - 1] + 1;
+        }
+    }
+}
+
+/// Depth of the deepest leaves of a balanced tree with `leaves` leaves
+fn balanced_depth(leaves: usize) -> usize {
+    leaves.next_power_of_two().trailing_zeros() as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::amount::Amount;
+    use crate::core::input::{OutPoint, TxIn};
+    use crate::core::interpreter::VerifyFlags;
+    use crate::core::txid::Txid;
+
+    use super::*;
+
+    fn spending_tx() -> Transaction {
+        let input = TxIn::new(OutPoint::new(Txid::from_bytes([0x11; 32]), 0));
+        let output = TxOut::new(Amount::from_sat(90_000), Script::from(vec![0x51]));
+        Transaction::new(2, vec![input], vec![output], 0)
+    }
+
+    #[test]
+    fn multi_a_scripts() -> Result<()> {
+        let keys = [[0x03; 32], [0x01; 32], [0x02; 32]];
+        let script = Script::multi_a(2, &keys, false)?;
+        assert_eq!(script.len(), 3 * 34 + 2);
+        assert_eq!(script.multi_a_keys(), Some((2, keys.to_vec())));
+
+        let sorted = Script::multi_a(2, &keys, true)?;
+        assert_eq!(
+            sorted.multi_a_keys(),
+            Some((2, vec![[1; 32], [2; 32], [3; 32]]))
+        );
+
+        // thresholds above 16 are pushed as numbers
+        let many: Vec<_> = (0..20u8).map(|i| [i; 32]).collect();
+        let script = Script::multi_a(17, &many, false)?;
+        assert_eq!(script.multi_a_keys(), Some((17, many)));
+
+        assert!(Script::multi_a(0, &keys, true).is_err());
+        assert!(Script::multi_a(4, &keys, true).is_err());
+        assert!(Script::multi_a(1, &[], true).is_err());
+        assert_eq!(Script::from(vec![0x51, 0x9c]).multi_a_keys(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn leaf_strategies() -> Result<()> {
+        assert_eq!(combinations(5, 3), Some(10));
+        assert_eq!(combinations(999, 500), None);
+        assert_eq!(
+            signer_sets(4, 2),
+            vec![
+                vec![0, 1],
+                vec![0, 2],
+                vec![0, 3],
+                vec![1, 2],
+                vec![1, 3],
+                vec![2, 3]
+            ]
+        );
+        assert_eq!(signer_sets(2, 2), vec![vec![0, 1]]);
+        assert_eq!(balanced_depth(1), 0);
+        assert_eq!(balanced_depth(5), 3);
+
+        let keys = [[0x03; 32], [0x01; 32], [0x02; 32]];
+        let single = TapMultisig::new(2, &keys, TapLeafStrategy::Single)?;
+        assert_eq!(single.keys(), &[[1; 32], [2; 32], [3; 32]]);
+        assert_eq!(single.leaf_scripts().len(), 1);
+
+        let per_signers = TapMultisig::new(2, &keys, TapLeafStrategy::PerSigners)?;
+        let scripts = per_signers.leaf_scripts();
+        assert_eq!(scripts.len(), 3);
+        assert_eq!(scripts[1].multi_a_keys(), Some((2, vec![[1; 32], [3; 32]])));
+
+        // the two first leaves one level deeper
+        let spend_info = per_signers.spend_info(&[0x02; 32])?;
+        let depths: Vec<_> = spend_info
+            .leaves()
+            .iter()
+            .map(|leaf| leaf.merkle_path().len())
+            .collect();
+        assert_eq!(depths, [2, 2, 1]);
+
+        assert!(TapMultisig::new(2, &[[1; 32], [1; 32]], TapLeafStrategy::Single).is_err());
+        let many: Vec<_> = (0..40u8).map(|i| [i; 32]).collect();
+        assert!(TapMultisig::new(20, &many, TapLeafStrategy::PerSigners).is_err());
+        assert!(TapMultisig::new(20, &many, TapLeafStrategy::Single).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn script_path_spends() -> Result<()> {
+        let signers: Vec<_> = (51..54u32).map(PrivateKey::new).collect();
+        let keys: Vec<_> = signers.iter().map(schnorr::x_only_public_key).collect();
+        let internal_key = schnorr::x_only_public_key(&PrivateKey::new(31337u32));
+
+        for strategy in [TapLeafStrategy::Single, TapLeafStrategy::PerSigners].iter() {
+            let multisig = TapMultisig::new(2, &keys, *strategy)?;
+            let spend_info = multisig.spend_info(&internal_key)?;
+            let prevouts = vec![TxOut::new(
+                Amount::from_sat(100_000),
+                Script::p2tr(spend_info.output_key()),
+            )];
+
+            // the first and last signers
+            let mut tx = spending_tx();
+            let signed = [&signers[0], &signers[2]];
+            let script = multisig
+                .leaf_scripts()
+                .into_iter()
+                .find(|script| {
+                    let (_, leaf_keys) = script.multi_a_keys().unwrap();
+                    signed
+                        .iter()
+                        .all(|key| leaf_keys.contains(&schnorr::x_only_public_key(key)))
+                })
+                .unwrap();
+
+            let signatures = signed
+                .iter()
+                .map(|key| {
+                    let signature = multisig.sign(&tx, 0, &prevouts, &script, key)?;
+                    Ok((schnorr::x_only_public_key(key), signature))
+                })
+                .collect::<crate::Result<Vec<_>>>()?;
+
+            assert!(multisig
+                .finalize(&mut tx, 0, &prevouts, &spend_info, &signatures[..1])
+                .is_err());
+            multisig.finalize(&mut tx, 0, &prevouts, &spend_info, &signatures)?;
+            assert_eq!(tx.inputs[0].witness.tapscript(), Some(script.clone()));
+            match strategy {
+                TapLeafStrategy::Single => assert_eq!(tx.inputs[0].witness.len(), 5),
+                TapLeafStrategy::PerSigners => assert_eq!(tx.inputs[0].witness.len(), 4),
+            }
+            tx.verify_with_flags(&prevouts, VerifyFlags::STANDARD)?;
+
+            // another output, another digest
+            let mut other = spending_tx();
+            other.outputs[0].amount = Amount::from_sat(80_000);
+            assert!(multisig
+                .finalize(&mut other, 0, &prevouts, &spend_info, &signatures)
+                .is_err());
+
+            let outsider = PrivateKey::new(99u32);
+            assert!(multisig
+                .sign(&tx, 0, &prevouts, &script, &outsider)
+                .is_err());
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,261 @@
+//! Timelocked scripts: absolute (CLTV) and relative (CSV) delays and hashed
+//! timelock contracts, with the elements satisfying each of them
+
+use crate::secp256k1::crypto::PublicKey;
+use crate::Result;
+
+use super::locktime::{LockTime, RelativeLockTime};
+use super::opcode::Opcode;
+use super::script::{Script, ScriptBuilder};
+
+impl Script {
+    /// `<locktime> OP_CHECKLOCKTIMEVERIFY OP_DROP <key> OP_CHECKSIG`, `key` can't
+    /// spend it before `lock_time`. The spending transaction sets it as its locktime
+    /// and the input a non final sequence
+    pub fn cltv(lock_time: LockTime, public_key: &PublicKey) -> Result<Self> {
+        Ok(ScriptBuilder::new()
+            .push_int(i64::from(lock_time.to_consensus_u32()))
+            .push_opcode(Opcode::OP_CHECKLOCKTIMEVERIFY)
+            .push_opcode(Opcode::OP_DROP)
+            .push_slice(&public_key.serialize_compressed()?)
+            .push_opcode(Opcode::OP_CHECKSIG)
+            .into_script())
+    }
+
+    /// `<sequence> OP_CHECKSEQUENCEVERIFY OP_DROP <key> OP_CHECKSIG`, `key` can't
+    /// spend it until `delay` passed since it confirmed. The spending transaction
+    /// is version 2 and the input sequence the delay
+    pub fn csv(delay: RelativeLockTime, public_key: &PublicKey) -> Result<Self> {
+        Ok(ScriptBuilder::new()
+            .push_int(i64::from(delay.to_sequence().to_consensus_u32()))
+            .push_opcode(Opcode::OP_CHECKSEQUENCEVERIFY)
+            .push_opcode(Opcode::OP_DROP)
+            .push_slice(&public_key.serialize_compressed()?)
+            .push_opcode(Opcode::OP_CHECKSIG)
+            .into_script())
+    }
+
+    /// Hashed timelock contract, `receiver` claims it with the preimage of
+    /// `payment_hash` (SHA256) or `sender` takes it back after `timeout`:
+    ///
+    /// ```text
+    /// OP_IF
+    ///     OP_SHA256 <payment hash> OP_EQUALVERIFY <receiver>
+    /// OP_ELSE
+    ///     <timeout> OP_CHECKLOCKTIMEVERIFY OP_DROP <sender>
+    /// OP_ENDIF
+    /// OP_CHECKSIG
+    /// ```
+    pub fn htlc(
+        payment_hash: &[u8; 32],
+        receiver: &PublicKey,
+        sender: &PublicKey,
+        timeout: LockTime,
+    ) -> Result<Self> {
+        Ok(ScriptBuilder::new()
+            .push_opcode(Opcode::OP_IF)
+            .push_opcode(Opcode::OP_SHA256)
+            .push_slice(payment_hash)
+            .push_opcode(Opcode::OP_EQUALVERIFY)
+            .push_slice(&receiver.serialize_compressed()?)
+            .push_opcode(Opcode::OP_ELSE)
+            .push_int(i64::from(timeout.to_consensus_u32()))
+            .push_opcode(Opcode::OP_CHECKLOCKTIMEVERIFY)
+            .push_opcode(Opcode::OP_DROP)
+            .push_slice(&sender.serialize_compressed()?)
+            .push_opcode(Opcode::OP_ENDIF)
+            .push_opcode(Opcode::OP_CHECKSIG)
+            .into_script())
+    }
+}
+
+/// Elements satisfying [`Script::cltv`] and [`Script::csv`], the signature with
+/// its sighash byte
+pub fn timelock_satisfaction(signature: &[u8]) -> Vec<Vec<u8>> {
+    vec![signature.to_vec()]
+}
+
+/// Elements taking the first branch of [`Script::htlc`], by the receiver
+pub fn htlc_claim_satisfaction(signature: &[u8], preimage: &[u8]) -> Vec<Vec<u8>> {
+    vec![signature.to_vec(), preimage.to_vec(), vec![0x01]]
+}
+
+/// Elements taking the second branch of [`Script::htlc`], by the sender
+pub fn htlc_refund_satisfaction(signature: &[u8]) -> Vec<Vec<u8>> {
+    vec![signature.to_vec(), vec![]]
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use sha2::{Digest, Sha256};
+
+    use crate::amount::Amount;
+    use crate::core::input::{OutPoint, TxIn};
+    use crate::core::interpreter::{ScriptError, VerifyFlags};
+    use crate::core::locktime::Sequence;
+    use crate::core::output::TxOut;
+    use crate::core::sighash::SIGHASH_ALL;
+    use crate::core::tx::Transaction;
+    use crate::core::txid::Txid;
+    use crate::secp256k1::crypto::PrivateKey;
+    use crate::Error;
+
+    use crate::core::witness::Witness;
+
+    use super::*;
+
+    fn spending_tx(locktime: LockTime, sequence: Sequence) -> Transaction {
+        let mut input = TxIn::new(OutPoint::new(Txid::from_bytes([0x11; 32]), 0));
+        input.set_sequence(sequence);
+        let output = TxOut::new(Amount::from_sat(90_000), Script::from(vec![0x51]));
+        Transaction::new(2, vec![input], vec![output], locktime.to_consensus_u32())
+    }
+
+    /// Spend a P2WSH output of `script` with the elements `satisfy` makes of a
+    /// signature by `key`
+    fn spend_p2wsh(
+        mut tx: Transaction,
+        script: &Script,
+        key: &PrivateKey,
+        satisfy: impl Fn(&[u8]) -> Vec<Vec<u8>>,
+    ) -> crate::Result<()> {
+        let mut program = [0; 32];
+        program.copy_from_slice(&Sha256::digest(&script.bytes));
+        let prevout = TxOut::new(Amount::from_sat(100_000), Script::p2wsh(&program));
+
+        let digest = tx.segwit_v0_sighash(0, script, prevout.amount, SIGHASH_ALL)?;
+        let mut signature = key.create_signature(digest)?.serialize()?;
+        signature.push(SIGHASH_ALL as u8);
+
+        tx.inputs[0].witness = Witness::p2wsh(&satisfy(&signature), script);
+        tx.verify_with_flags(&[prevout], VerifyFlags::STANDARD)
+    }
+
+    fn unsatisfied(result: crate::Result<()>) -> bool {
+        matches!(
+            result,
+            Err(Error::InvalidInput {
+                error: ScriptError::UnsatisfiedLockTime,
+                ..
+            })
+        )
+    }
+
+    #[test]
+    fn absolute_and_relative_delays() -> Result<()> {
+        let key = PrivateKey::new(4242u32);
+        let lock_time = LockTime::from_height(800_000)?;
+        let script = Script::cltv(lock_time, key.public_key())?;
+        assert_eq!(script.len(), 4 + 2 + 34 + 1);
+
+        let sequence = Sequence::ENABLE_LOCKTIME_NO_RBF;
+        spend_p2wsh(
+            spending_tx(lock_time, sequence),
+            &script,
+            &key,
+            timelock_satisfaction,
+        )?;
+
+        let early = LockTime::from_height(799_999)?;
+        let result = spend_p2wsh(
+            spending_tx(early, sequence),
+            &script,
+            &key,
+            timelock_satisfaction,
+        );
+        assert!(unsatisfied(result));
+
+        // a final sequence disables the locktime
+        let result = spend_p2wsh(
+            spending_tx(lock_time, Sequence::MAX),
+            &script,
+            &key,
+            timelock_satisfaction,
+        );
+        assert!(unsatisfied(result));
+
+        let delay = RelativeLockTime::Blocks(144);
+        let script = Script::csv(delay, key.public_key())?;
+        spend_p2wsh(
+            spending_tx(LockTime::ZERO, delay.to_sequence()),
+            &script,
+            &key,
+            timelock_satisfaction,
+        )?;
+
+        let result = spend_p2wsh(
+            spending_tx(LockTime::ZERO, Sequence::from_height(143)),
+            &script,
+            &key,
+            timelock_satisfaction,
+        );
+        assert!(unsatisfied(result));
+        Ok(())
+    }
+
+    #[test]
+    fn hashed_timelock_contracts() -> Result<()> {
+        let receiver = PrivateKey::new(1111u32);
+        let sender = PrivateKey::new(2222u32);
+        let preimage = [0x42; 32];
+        let mut payment_hash = [0; 32];
+        payment_hash.copy_from_slice(&Sha256::digest(&preimage[..]));
+
+        let timeout = LockTime::from_height(800_000)?;
+        let script = Script::htlc(
+            &payment_hash,
+            receiver.public_key(),
+            sender.public_key(),
+            timeout,
+        )?;
+
+        // the receiver any time with the preimage
+        let claim = spending_tx(LockTime::ZERO, Sequence::MAX);
+        spend_p2wsh(claim.clone(), &script, &receiver, |signature| {
+            htlc_claim_satisfaction(signature, &preimage)
+        })?;
+        assert!(spend_p2wsh(claim.clone(), &script, &receiver, |signature| {
+            htlc_claim_satisfaction(signature, &[0x43; 32])
+        })
+        .is_err());
+        assert!(spend_p2wsh(claim, &script, &sender, |signature| {
+            htlc_claim_satisfaction(signature, &preimage)
+        })
+        .is_err());
+
+        // the sender only after the timeout
+        let refund = spending_tx(timeout, Sequence::ENABLE_LOCKTIME_NO_RBF);
+        spend_p2wsh(refund, &script, &sender, htlc_refund_satisfaction)?;
+        let early = spending_tx(LockTime::ZERO, Sequence::ENABLE_LOCKTIME_NO_RBF);
+        assert!(unsatisfied(spend_p2wsh(
+            early,
+            &script,
+            &sender,
+            htlc_refund_satisfaction
+        )));
+        Ok(())
+    }
+
+    #[test]
+    fn p2sh_script_sigs() -> Result<()> {
+        let key = PrivateKey::new(4242u32);
+        let lock_time = LockTime::from_height(800_000)?;
+        let script = Script::cltv(lock_time, key.public_key())?;
+
+        let mut hash = [0; 20];
+        hash.copy_from_slice(&crate::utils::hash160(&script.bytes));
+        let prevout = TxOut::new(Amount::from_sat(100_000), Script::p2sh(&hash));
+
+        let mut tx = spending_tx(lock_time, Sequence::ENABLE_LOCKTIME_NO_RBF);
+        let digest = tx.legacy_sighash(0, &script, SIGHASH_ALL)?;
+        let mut signature = key.create_signature(digest)?.serialize()?;
+        signature.push(SIGHASH_ALL as u8);
+
+        tx.inputs[0].script_sig =
+            Script::p2sh_script_sig(&timelock_satisfaction(&signature), &script);
+        assert!(tx.inputs[0].script_sig.is_push_only());
+        tx.verify_with_flags(&[prevout], VerifyFlags::STANDARD)?;
+        Ok(())
+    }
+}
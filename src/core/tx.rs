@@ -1,110 +1,269 @@
-use std::convert::TryFrom;
+use std::io::{self, Read, Write};
 
-use byteorder::{LittleEndian, ReadBytesExt};
 use bytes::Buf;
 
-use crate::utils::hash256;
-use crate::varint::VarInt;
-use crate::Result;
+use crate::amount::Amount;
+use crate::consensus::{self, Decodable, Encodable};
+use crate::network::Network;
+use crate::{Error, Result};
 
-use super::input::Input;
-use super::output::Output;
+use super::input::TxIn;
+use super::locktime::LockTime;
+use super::output::TxOut;
+use super::txid::{Txid, Wtxid};
+use super::witness::Witness;
 
-#[derive(Debug, Clone)]
-pub struct Tx {
+/// Segwit marker and flag following the version (BIP144)
+const SEGWIT_MARKER: u8 = 0x00;
+const SEGWIT_FLAG: u8 = 0x01;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transaction {
     pub(crate) version: u32,
-    pub(crate) inputs: Vec<Input>,
-    pub(crate) outputs: Vec<Output>,
-    pub(crate) locktime: u64,
-    pub(crate) testnet: bool,
+    pub(crate) inputs: Vec<TxIn>,
+    pub(crate) outputs: Vec<TxOut>,
+    pub(crate) locktime: u32,
 }
 
-impl Tx {
+impl Transaction {
+    pub fn new(version: u32, inputs: Vec<TxIn>, outputs: Vec<TxOut>, locktime: u32) -> Self {
+        Self {
+            version,
+            inputs,
+            outputs,
+            locktime,
+        }
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn inputs(&self) -> &[TxIn] {
+        &self.inputs
+    }
+
+    pub fn outputs(&self) -> &[TxOut] {
+        &self.outputs
+    }
+
+    pub fn locktime(&self) -> LockTime {
+        LockTime::from_consensus(self.locktime)
+    }
+
+    /// Whether any input carries witness data, which changes the serialization
+    pub fn has_witness(&self) -> bool {
+        self.inputs.iter().any(|input| !input.witness.is_empty())
+    }
+
     pub fn id(&self) -> Result<String> {
-        Ok(hex::encode(self.hash()?))
+        Ok(self.txid()?.to_string())
     }
 
-    pub fn hash(&self) -> Result<Vec<u8>> {
-        let serialized = self.serialize()?;
-        let mut digest = hash256(&serialized);
-        digest.reverse();
-        Ok(digest)
+    /// Hash of the serialization without witness data
+    pub fn txid(&self) -> Result<Txid> {
+        let mut serialized = Vec::new();
+        self.encode_legacy(&mut serialized)?;
+        Ok(Txid::hash(&serialized))
     }
 
-    pub async fn fee(&self, testnet: bool) -> Result<u64> {
-        let mut input_sum = 0;
-        for input in &self.inputs {
-            let prev_tx = input.fetch_tx(testnet).await?;
-            input_sum += input.value(&prev_tx);
-        }
+    /// Hash of the full serialization, the same as the txid without witness data
+    pub fn wtxid(&self) -> Result<Wtxid> {
+        Ok(Wtxid::hash(&self.serialize()?))
+    }
 
-        let output_sum: u64 = self.outputs.iter().map(|output| output.amount).sum();
-        Ok(input_sum - output_sum)
+    /// Serialized size without the witness data
+    pub fn base_size(&self) -> Result<usize> {
+        self.encode_legacy(&mut io::sink())
     }
 
-    pub fn serialize(&self) -> Result<Vec<u8>> {
-        let version_bytes = self.version.to_le_bytes();
+    /// Serialized size with the witness data, if any
+    pub fn total_size(&self) -> Result<usize> {
+        self.consensus_encode(&mut io::sink())
+    }
 
-        let no_inputs = VarInt::try_from(self.inputs.len())?;
-        let no_inputs_bytes = no_inputs.serialize().into_iter();
+    /// BIP141 weight, witness data is discounted to a quarter
+    pub fn weight(&self) -> Result<usize> {
+        Ok(self.base_size()? * 3 + self.total_size()?)
+    }
 
-        let no_outputs = VarInt::try_from(self.outputs.len())?;
-        let no_outputs_bytes = no_outputs.serialize().into_iter();
+    /// Virtual size, the weight in vbytes rounding up
+    pub fn vsize(&self) -> Result<usize> {
+        Ok(self.weight()?.div_ceil(4))
+    }
 
-        let inputs_bytes = self
-            .inputs
-            .iter()
-            .map(|input| input.serialize())
-            .collect::<Result<Vec<_>, _>>()?
-            .into_iter()
-            .flatten();
+    /// Fetches the spent transactions, fails if the outputs spend more than the inputs
+    pub async fn fee(&self, network: Network) -> Result<Amount> {
+        let mut input_sum = Amount::ZERO;
+        for input in &self.inputs {
+            let prev_tx = input.fetch_tx(network).await?;
+            input_sum = input_sum
+                .checked_add(input.value(&prev_tx))
+                .ok_or(Error::InvalidTransaction("input values overflow"))?;
+        }
 
-        let outputs_bytes = self
+        let output_sum = self
             .outputs
             .iter()
-            .map(|output| output.serialize())
-            .collect::<Result<Vec<_>, _>>()?
-            .into_iter()
-            .flatten();
+            .try_fold(Amount::ZERO, |sum, output| sum.checked_add(output.amount))
+            .ok_or(Error::InvalidTransaction("output values overflow"))?;
 
-        let locktime_bytes = self.locktime.to_le_bytes();
+        input_sum
+            .checked_sub(output_sum)
+            .ok_or(Error::InvalidTransaction("outputs exceed inputs"))
+    }
 
-        let result = version_bytes
-            .iter()
-            .copied()
-            .chain(no_inputs_bytes)
-            .chain(inputs_bytes)
-            .chain(no_outputs_bytes)
-            .chain(outputs_bytes)
-            .chain(locktime_bytes.iter().copied())
-            .collect();
+    /// Segwit serialization if any input has witness data, legacy otherwise
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        consensus::serialize(self)
+    }
 
-        Ok(result)
+    pub fn deserialize(buf: impl Buf) -> Result<Self> {
+        Self::consensus_decode(&mut buf.reader())
     }
 
-    pub fn deserialize(buf: impl Buf, testnet: bool) -> Result<Self> {
-        let mut reader = buf.reader();
+    fn encode_legacy<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        Ok(self.version.consensus_encode(writer)?
+            + self.inputs.consensus_encode(writer)?
+            + self.outputs.consensus_encode(writer)?
+            + self.locktime.consensus_encode(writer)?)
+    }
+}
 
-        let version = reader.read_u32::<LittleEndian>()?;
+/// The network isn't part of the encoding
+impl Encodable for Transaction {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        if !self.has_witness() {
+            return self.encode_legacy(writer);
+        }
 
-        let no_inputs = VarInt::deserialize(reader.get_mut())?;
-        let inputs: Vec<_> = (0..no_inputs.as_u64())
-            .map(|_| Input::deserialize(reader.get_mut()))
-            .collect::<Result<_, _>>()?;
+        let mut len = self.version.consensus_encode(writer)?
+            + SEGWIT_MARKER.consensus_encode(writer)?
+            + SEGWIT_FLAG.consensus_encode(writer)?
+            + self.inputs.consensus_encode(writer)?
+            + self.outputs.consensus_encode(writer)?;
+        for input in &self.inputs {
+            len += input.witness.consensus_encode(writer)?;
+        }
 
-        let no_outputs = VarInt::deserialize(reader.get_mut())?;
-        let outputs: Vec<_> = (0..no_outputs.as_u64())
-            .map(|_| Output::deserialize(reader.get_mut()))
-            .collect::<Result<_, _>>()?;
+        Ok(len + self.locktime.consensus_encode(writer)?)
+    }
+}
 
-        let locktime = reader.read_u64::<LittleEndian>()?;
+impl Decodable for Transaction {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        let version = u32::consensus_decode(reader)?;
+
+        // an empty input vector is the segwit marker
+        let mut inputs: Vec<TxIn> = Vec::consensus_decode(reader)?;
+        let segwit = inputs.is_empty();
+        if segwit {
+            if u8::consensus_decode(reader)? != SEGWIT_FLAG {
+                return Err(Error::InvalidTransaction("unknown segwit flag"));
+            }
+            inputs = Vec::consensus_decode(reader)?;
+        }
+
+        let outputs = Vec::consensus_decode(reader)?;
+        if segwit {
+            for input in &mut inputs {
+                input.witness = Witness::consensus_decode(reader)?;
+            }
+
+            // the legacy encoding must be used when there's no witness data
+            if inputs.iter().all(|input| input.witness.is_empty()) {
+                return Err(Error::InvalidTransaction("superfluous witness record"));
+            }
+        }
 
         Ok(Self {
             version,
             inputs,
             outputs,
-            locktime,
-            testnet,
+            locktime: u32::consensus_decode(reader)?,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use hex_literal::hex;
+
+    use super::*;
+
+    #[test]
+    fn legacy_tx_roundtrip() -> Result<()> {
+        let raw = hex!("0100000001813f79011acb80925dfe69b3def355fe914bd1d96a3f5f71bf8303c6a989c7d1000000006b483045022100ed81ff192e75a3fd2304004dcadb746fa5e24c5031ccfcf21320b0277457c98f02207a986d955c6e0cb35d446a89d3f56100f4d7f67801c31967743a9c8e10615bed01210349fc4e631e3624a545de3f89f5d8684c7b8138bd94bdd531d2e213bf016b278afeffffff02a135ef01000000001976a914bc3b654dca7e56b04dca18f2566cdaf02e8d9ada88ac99c39800000000001976a9141c4bc762dd5423e332166702cb75f40df79fea1288ac19430600");
+
+        let tx: Transaction = consensus::deserialize(&raw)?;
+        assert_eq!(tx.version, 1);
+        assert_eq!(tx.inputs.len(), 1);
+        assert_eq!(tx.inputs[0].script_sig.bytes.len(), 0x6b);
+        assert_eq!(tx.inputs[0].sequence, 0xfffffffe);
+        assert_eq!(tx.outputs[0].amount, Amount::from_sat(32454049));
+        assert_eq!(tx.outputs[1].amount, Amount::from_sat(10011545));
+        assert_eq!(tx.locktime, 410393);
+
+        assert_eq!(tx.serialize()?, raw.to_vec());
+        assert_eq!(
+            tx.id()?,
+            "452c629d67e41baec3ac6f04fe744b4b9617f8f859c63b3002f8684e7a4fee03"
+        );
+        assert_eq!(tx.wtxid()?.to_bytes(), tx.txid()?.to_bytes());
+        assert_eq!(tx.weight()?, raw.len() * 4);
+        assert_eq!(tx.vsize()?, raw.len());
+        Ok(())
+    }
+
+    #[test]
+    fn segwit_tx_roundtrip() -> Result<()> {
+        // BIP143 native P2WPKH example, one legacy and one witness input
+        let raw = hex!("01000000000102fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f00000000494830450221008b9d1dc26ba6a9cb62127b02742fa9d754cd3bebf337f7a55d114c8e5cdd30be022040529b194ba3f9281a99f2b1c0a19c0489bc22ede944ccf4ecbab4cc618ef3ed01eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac000247304402203609e17b84f6a7d30c80bfa610b5b4542f32a8a0d5447a12fb1366d7f01cc44a0220573a954c4518331561406f90300e8f3358f51928d43c212a8caed02de67eebee0121025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee635711000000");
+
+        let tx: Transaction = consensus::deserialize(&raw)?;
+        assert!(tx.has_witness());
+        assert_eq!(tx.inputs.len(), 2);
+        assert!(tx.inputs[0].witness.is_empty());
+        assert_eq!(tx.inputs[1].witness.len(), 2);
+        assert_eq!(
+            tx.inputs[1].previous_output.txid().to_string(),
+            "8ac60eb9575db5b2d987e29f301b5b819ea83a5c6579d282d189cc04b8e151ef"
+        );
+        assert_eq!(tx.outputs[1].amount, Amount::from_sat(223450000));
+        assert_eq!(tx.locktime, 17);
+
+        assert_eq!(tx.serialize()?, raw.to_vec());
+        assert_eq!(
+            tx.id()?,
+            "e8151a2af31c368a35053ddd4bdb285a8595c769a3ad83e0fa02314a602d4609"
+        );
+        assert_eq!(
+            tx.wtxid()?.to_string(),
+            "c36c38370907df2324d9ce9d149d191192f338b37665a82e78e76a12c909b762"
+        );
+
+        assert_eq!((tx.base_size()?, tx.total_size()?), (233, 343));
+        assert_eq!(tx.weight()?, 1042);
+        assert_eq!(tx.vsize()?, 261);
+
+        // same transaction without its witnesses
+        let mut stripped = tx.clone();
+        stripped.inputs[1].witness.clear();
+        assert_eq!(stripped.serialize()?.len(), 233);
+        assert_eq!(stripped.wtxid()?.to_bytes(), tx.txid()?.to_bytes());
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_segwit_encodings() {
+        // marker with an unknown flag, and a marker without any witness
+        let unknown_flag = hex!("0100000000020000000000");
+        assert!(consensus::deserialize::<Transaction>(&unknown_flag).is_err());
+
+        let mut empty_witness = hex!("01000000000101").to_vec();
+        empty_witness.extend(&[0x11; 32]);
+        empty_witness.extend(&hex!("0000000000ffffffff000000000000"));
+        assert!(consensus::deserialize::<Transaction>(&empty_witness).is_err());
+    }
+}
@@ -0,0 +1,128 @@
+//! Transaction and block hashes, kept in the byte order they're hashed and serialized in but
+//! displayed reversed like explorers and RPC do
+
+use std::fmt::{self, Debug, Display, Formatter};
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+use crate::consensus::{Decodable, Encodable};
+use crate::utils::hash256;
+use crate::{Error, Result};
+
+macro_rules! txid_type {
+    ($(#[$doc:meta])* $type:ident, $expecting:literal) => {
+        $(#[$doc])*
+        #[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub struct $type(pub(crate) [u8; 32]);
+
+        impl $type {
+            /// From the bytes in internal (not reversed) order
+            pub const fn from_bytes(bytes: [u8; 32]) -> Self {
+                $type(bytes)
+            }
+
+            /// The bytes in internal (not reversed) order
+            pub const fn to_bytes(self) -> [u8; 32] {
+                self.0
+            }
+
+            /// Double SHA256 of `data`
+            pub(crate) fn hash(data: &[u8]) -> Self {
+                let mut bytes = [0; 32];
+                bytes.copy_from_slice(&hash256(data));
+                $type(bytes)
+            }
+        }
+
+        impl AsRef<[u8]> for $type {
+            fn as_ref(&self) -> &[u8] {
+                &self.0
+            }
+        }
+
+        impl Debug for $type {
+            fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+                write!(fmt, "{}({})", stringify!($type), self)
+            }
+        }
+
+        /// Reversed hex
+        impl Display for $type {
+            fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+                let mut bytes = self.0;
+                bytes.reverse();
+                write!(fmt, "{}", hex::encode(bytes))
+            }
+        }
+
+        /// Reversed hex, 64 characters
+        impl FromStr for $type {
+            type Err = Error;
+
+            fn from_str(string: &str) -> Result<Self> {
+                let mut bytes = [0u8; 32];
+                hex::decode_to_slice(string, &mut bytes).map_err(|_| Error::InvalidHex)?;
+                bytes.reverse();
+                Ok($type(bytes))
+            }
+        }
+
+        impl Encodable for $type {
+            fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+                self.0.consensus_encode(writer)
+            }
+        }
+
+        impl Decodable for $type {
+            fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+                Ok($type(<[u8; 32]>::consensus_decode(reader)?))
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        serde_impl!($type, $expecting);
+    };
+}
+
+txid_type!(
+    /// Hash of a transaction without its witness data, what outpoints refer to
+    Txid,
+    "a transaction id in hex"
+);
+
+txid_type!(
+    /// Hash of a transaction with its witness data, the same as the txid without it
+    Wtxid,
+    "a witness transaction id in hex"
+);
+
+txid_type!(
+    /// Hash of a block header, what headers link to their previous block with
+    BlockHash,
+    "a block hash in hex"
+);
+
+#[cfg(test)]
+mod tests {
+    use crate::consensus;
+
+    use super::*;
+
+    #[test]
+    fn reversed_hex() {
+        // the genesis coinbase
+        let string = "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+        let txid: Txid = string.parse().unwrap();
+        assert_eq!(txid.to_bytes()[0], 0x3b);
+        assert_eq!(txid.to_string(), string);
+        assert_eq!(format!("{:?}", txid), format!("Txid({})", string));
+
+        // serialized as hashed
+        let bytes = consensus::serialize(&txid).unwrap();
+        assert_eq!(bytes, txid.to_bytes());
+        assert_eq!(consensus::deserialize::<Txid>(&bytes).unwrap(), txid);
+
+        assert!(matches!("zz".parse::<Wtxid>(), Err(Error::InvalidHex)));
+        assert!(string[..62].parse::<Wtxid>().is_err());
+    }
+}
@@ -0,0 +1,210 @@
+//! Validation of a transaction against the outputs it spends
+
+use crate::amount::Amount;
+use crate::{Error, Result};
+
+use super::interpreter::{verify_script, TransactionChecker, VerifyFlags};
+use super::output::TxOut;
+use super::tx::Transaction;
+
+impl Transaction {
+    /// Check every input spends its output in `prevouts`, given in input order, and
+    /// that the outputs don't pay more than what's spent, under the consensus rules
+    pub fn verify(&self, prevouts: &[TxOut]) -> Result<()> {
+        self.verify_with_flags(prevouts, VerifyFlags::CONSENSUS)
+    }
+
+    /// Same as [`Transaction::verify`] running the scripts under `flags`
+    pub fn verify_with_flags(&self, prevouts: &[TxOut], flags: VerifyFlags) -> Result<()> {
+        if prevouts.len() != self.inputs.len() {
+            return Err(Error::InvalidTransaction("one prevout per input needed"));
+        }
+
+        let spent = checked_sum(prevouts)?;
+        let paid = checked_sum(&self.outputs)?;
+        if paid > spent {
+            return Err(Error::InvalidTransaction(
+                "outputs pay more than the inputs",
+            ));
+        }
+
+        let mut checker = TransactionChecker::new(self, prevouts, 0);
+        for (index, (input, prevout)) in self.inputs.iter().zip(prevouts).enumerate() {
+            checker.set_input(index);
+            verify_script(
+                &input.script_sig,
+                &prevout.script_pubkey,
+                &input.witness,
+                flags,
+                &mut checker,
+            )
+            .map_err(|error| Error::InvalidInput { index, error })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Sum of the amounts, each and the total within the money supply
+fn checked_sum(outputs: &[TxOut]) -> Result<Amount> {
+    outputs
+        .iter()
+        .try_fold(Amount::ZERO, |total, output| {
+            total
+                .checked_add(output.amount)
+                .filter(|total| output.amount <= Amount::MAX_MONEY && *total <= Amount::MAX_MONEY)
+        })
+        .ok_or(Error::InvalidAmount("above the money supply"))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use hex_literal::hex;
+
+    use crate::consensus;
+    use crate::core::input::{OutPoint, TxIn};
+    use crate::core::interpreter::ScriptError;
+    use crate::core::script::Script;
+    use crate::core::sighash::SIGHASH_ALL;
+    use crate::core::txid::Txid;
+    use crate::secp256k1::crypto::PrivateKey;
+    use crate::secp256k1::schnorr;
+    use crate::utils::hash160;
+
+    use super::*;
+
+    fn p2pkh_script(sec: &[u8]) -> Script {
+        let mut script = vec![0x76, 0xa9, 0x14];
+        script.extend(hash160(sec));
+        script.extend(&[0x88, 0xac]);
+        Script::from(script)
+    }
+
+    #[test]
+    fn verify_bip143_p2wpkh() -> Result<()> {
+        // BIP143 native P2WPKH example, a P2PK input followed by a P2WPKH one
+        let raw = hex!("01000000000102fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f00000000494830450221008b9d1dc26ba6a9cb62127b02742fa9d754cd3bebf337f7a55d114c8e5cdd30be022040529b194ba3f9281a99f2b1c0a19c0489bc22ede944ccf4ecbab4cc618ef3ed01eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac000247304402203609e17b84f6a7d30c80bfa610b5b4542f32a8a0d5447a12fb1366d7f01cc44a0220573a954c4518331561406f90300e8f3358f51928d43c212a8caed02de67eebee0121025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee635711000000");
+        let tx: Transaction = consensus::deserialize(&raw)?;
+
+        let prevouts = vec![
+            TxOut::new(
+                Amount::from_sat(625_000_000),
+                Script::from(
+                    hex!("2103c9f4836b9a4f77fc0d81f7bcb01b7f1b35916864b9476c241ce9fc198bd25432ac")
+                        .to_vec(),
+                ),
+            ),
+            TxOut::new(
+                Amount::from_sat(600_000_000),
+                Script::from(hex!("00141d0f172a0ecb48aee1be1f2687d2963ae33f71a1").to_vec()),
+            ),
+        ];
+
+        tx.verify(&prevouts)?;
+        tx.verify_with_flags(&prevouts, VerifyFlags::STANDARD)?;
+
+        // committing to another amount breaks the signature
+        let mut wrong_amount = prevouts.clone();
+        wrong_amount[1].amount = Amount::from_sat(600_000_001);
+        assert!(matches!(
+            tx.verify(&wrong_amount),
+            Err(Error::InvalidInput {
+                index: 1,
+                error: ScriptError::EvalFalse
+            })
+        ));
+        assert!(matches!(
+            tx.verify_with_flags(&wrong_amount, VerifyFlags::STANDARD),
+            Err(Error::InvalidInput {
+                index: 1,
+                error: ScriptError::NullFail
+            })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn verify_signed_inputs() -> Result<()> {
+        let key = PrivateKey::new(8675309u32);
+        let sec = key.public_key().serialize_compressed()?;
+
+        let mut p2wpkh = vec![0x00, 0x14];
+        p2wpkh.extend(hash160(sec));
+        let mut p2sh_p2wpkh = vec![0xa9, 0x14];
+        p2sh_p2wpkh.extend(hash160(&p2wpkh));
+        p2sh_p2wpkh.push(0x87);
+
+        let internal_key = schnorr::x_only_public_key(&key);
+        let (output_key, _) = crate::taproot::tweak_public_key(&internal_key, None)?;
+        let mut p2tr = vec![0x51, 0x20];
+        p2tr.extend_from_slice(&output_key);
+
+        let prevouts = vec![
+            TxOut::new(Amount::from_sat(10_000), p2pkh_script(&sec)),
+            TxOut::new(Amount::from_sat(20_000), Script::from(p2wpkh.clone())),
+            TxOut::new(Amount::from_sat(30_000), Script::from(p2sh_p2wpkh)),
+            TxOut::new(Amount::from_sat(40_000), Script::from(p2tr)),
+        ];
+
+        let inputs = (0..4)
+            .map(|vout| TxIn::new(OutPoint::new(Txid::from_bytes([0x11; 32]), vout)))
+            .collect();
+        let output = TxOut::new(Amount::from_sat(99_000), Script::from(vec![0x51]));
+        let mut tx = Transaction::new(2, inputs, vec![output], 0);
+
+        tx.sign_p2pkh_input(0, &key, &prevouts[0].script_pubkey)?;
+        tx.sign_p2wpkh_input(1, &key, &prevouts[1])?;
+        let nested = TxOut::new(prevouts[2].amount, Script::from(p2wpkh.clone()));
+        tx.sign_p2wpkh_input(2, &key, &nested)?;
+        let mut script_sig = vec![p2wpkh.len() as u8];
+        script_sig.extend(&p2wpkh);
+        tx.inputs[2].script_sig = Script::from(script_sig);
+        tx.sign_p2tr_key_spend(3, &key, &prevouts, None, SIGHASH_ALL)?;
+
+        tx.verify(&prevouts)?;
+
+        // paying more than spent, or fewer prevouts
+        let mut inflating = tx.clone();
+        inflating.outputs[0].amount = Amount::from_sat(100_001);
+        assert!(inflating.verify(&prevouts).is_err());
+        assert!(tx.verify(&prevouts[..3]).is_err());
+
+        // a tampered signature fails only its input
+        let mut tampered = tx.clone();
+        tampered.inputs[3].witness[0][10] ^= 1;
+        assert!(matches!(
+            tampered.verify(&prevouts),
+            Err(Error::InvalidInput {
+                index: 3,
+                error: ScriptError::InvalidSignature
+            })
+        ));
+
+        // someone else's key
+        let mut other = tx.clone();
+        let other_key = PrivateKey::new(12345u32);
+        other.sign_p2wpkh_input(
+            1,
+            &other_key,
+            &TxOut::new(
+                prevouts[1].amount,
+                Script::from(
+                    [
+                        &[0x00, 0x14][..],
+                        &hash160(other_key.public_key().serialize_compressed()?),
+                    ]
+                    .concat(),
+                ),
+            ),
+        )?;
+        assert!(matches!(
+            other.verify(&prevouts),
+            Err(Error::InvalidInput {
+                index: 1,
+                error: ScriptError::EqualVerify
+            })
+        ));
+        Ok(())
+    }
+}
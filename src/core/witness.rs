@@ -0,0 +1,212 @@
+//! Witness stacks of segwit inputs (BIP141)
+
+use std::io::{Read, Write};
+use std::ops::{Deref, DerefMut};
+
+use bytes::Buf;
+
+use crate::consensus::{self, Decodable, Encodable};
+use crate::varint;
+use crate::Result;
+
+use super::script::Script;
+
+/// First byte of the taproot annex
+pub(crate) const ANNEX_TAG: u8 = 0x50;
+
+/// Stack of byte vectors given to a witness program, bottom element first
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Witness {
+    pub(crate) stack: Vec<Vec<u8>>,
+}
+
+impl Witness {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `<signature> <key>`, the signature carrying its sighash byte
+    pub fn p2wpkh(signature: &[u8], public_key: &[u8; 33]) -> Self {
+        Self::from(vec![signature.to_vec(), public_key.to_vec()])
+    }
+
+    /// The `stack` elements followed by the witness script
+    pub fn p2wsh(stack: &[Vec<u8>], witness_script: &Script) -> Self {
+        let mut witness = Self::from(stack.to_vec());
+        witness.push(witness_script.bytes.clone());
+        witness
+    }
+
+    /// A single schnorr signature, 64 bytes or 65 with an explicit sighash byte
+    pub fn p2tr_key_spend(signature: &[u8]) -> Self {
+        Self::from(vec![signature.to_vec()])
+    }
+
+    /// The elements the leaf `script` consumes followed by the script and the control
+    /// block proving it's committed to by the output key
+    pub fn p2tr_script_spend(stack: Vec<Vec<u8>>, script: &Script, control_block: &[u8]) -> Self {
+        let mut witness = Self::from(stack);
+        witness.push(script.bytes.clone());
+        witness.push(control_block.to_vec());
+        witness
+    }
+
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        consensus::serialize(self)
+    }
+
+    pub fn deserialize(buf: impl Buf) -> Result<Self> {
+        Self::consensus_decode(&mut buf.reader())
+    }
+
+    /// Serialized size, element count included
+    pub fn size(&self) -> usize {
+        self.stack
+            .iter()
+            .map(|element| varint::encoded_len(element.len() as u64) + element.len())
+            .sum::<usize>()
+            + varint::encoded_len(self.stack.len() as u64)
+    }
+
+    pub fn push(&mut self, element: Vec<u8>) {
+        self.stack.push(element);
+    }
+
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        self.stack.pop()
+    }
+
+    pub fn clear(&mut self) {
+        self.stack.clear();
+    }
+
+    /// Last element of a taproot spend with at least two, when it starts with 0x50
+    pub fn taproot_annex(&self) -> Option<&[u8]> {
+        match self.stack.as_slice() {
+            [_, .., annex] if annex.first() == Some(&ANNEX_TAG) => Some(annex),
+            _ => None,
+        }
+    }
+
+    /// Control block of a taproot script path spend, the last element besides the annex
+    pub fn taproot_control_block(&self) -> Option<&[u8]> {
+        match self.without_annex() {
+            [_, .., control_block] => Some(control_block),
+            _ => None,
+        }
+    }
+
+    /// Leaf script of a taproot script path spend, right before the control block
+    pub fn tapscript(&self) -> Option<Script> {
+        match self.without_annex() {
+            [.., script, _] => Some(Script::from(script.clone())),
+            _ => None,
+        }
+    }
+
+    /// Elements of a taproot spend, the annex aside
+    pub(crate) fn without_annex(&self) -> &[Vec<u8>] {
+        match self.taproot_annex() {
+            Some(_) => &self.stack[..self.stack.len() - 1],
+            None => &self.stack,
+        }
+    }
+}
+
+impl Deref for Witness {
+    type Target = [Vec<u8>];
+
+    fn deref(&self) -> &Self::Target {
+        &self.stack
+    }
+}
+
+impl DerefMut for Witness {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.stack
+    }
+}
+
+impl From<Vec<Vec<u8>>> for Witness {
+    fn from(stack: Vec<Vec<u8>>) -> Self {
+        Self { stack }
+    }
+}
+
+impl From<Witness> for Vec<Vec<u8>> {
+    fn from(witness: Witness) -> Self {
+        witness.stack
+    }
+}
+
+/// Element count followed by the length prefixed elements
+impl Encodable for Witness {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        self.stack.consensus_encode(writer)
+    }
+}
+
+impl Decodable for Witness {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        let stack = Vec::consensus_decode(reader)?;
+        Ok(Self { stack })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+
+    #[test]
+    fn serialization() -> Result<()> {
+        let witness = Witness::p2wpkh(&[0x30; 72], &[0x02; 33]);
+        let bytes = witness.serialize()?;
+        assert_eq!(bytes[..2], [0x02, 0x48]);
+        assert_eq!(bytes.len(), witness.size());
+        assert_eq!(Witness::deserialize(&bytes[..])?, witness);
+
+        assert_eq!(Witness::new().serialize()?, hex!("00"));
+        assert_eq!(Witness::new().size(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn taproot_elements() {
+        let key_spend = Witness::p2tr_key_spend(&[0x01; 64]);
+        assert_eq!(key_spend.len(), 1);
+        assert_eq!(key_spend.taproot_annex(), None);
+        assert_eq!(key_spend.taproot_control_block(), None);
+        assert_eq!(key_spend.tapscript(), None);
+
+        // a lone element is never an annex
+        let lone = Witness::p2tr_key_spend(&[ANNEX_TAG; 64]);
+        assert_eq!(lone.taproot_annex(), None);
+
+        let mut with_annex = key_spend.clone();
+        with_annex.push(vec![ANNEX_TAG, 0x01]);
+        assert_eq!(with_annex.taproot_annex(), Some(&[ANNEX_TAG, 0x01][..]));
+        assert_eq!(with_annex.without_annex(), &key_spend[..]);
+        assert_eq!(with_annex.taproot_control_block(), None);
+
+        let script = Script::from(vec![0x51]);
+        let control_block = [0xc0; 33];
+        let mut script_spend =
+            Witness::p2tr_script_spend(vec![vec![0x02]], &script, &control_block);
+        assert_eq!(script_spend.len(), 3);
+        assert_eq!(script_spend.tapscript(), Some(script.clone()));
+        assert_eq!(
+            script_spend.taproot_control_block(),
+            Some(&control_block[..])
+        );
+
+        script_spend.push(vec![ANNEX_TAG]);
+        assert_eq!(script_spend.tapscript(), Some(script));
+        assert_eq!(
+            script_spend.taproot_control_block(),
+            Some(&control_block[..])
+        );
+        assert_eq!(script_spend.pop(), Some(vec![ANNEX_TAG]));
+    }
+}
@@ -0,0 +1,201 @@
+use num_bigint::BigUint;
+
+use crate::field::{Field, PrimeField};
+use crate::{Error, Result};
+
+/// An elliptic curve in short Weierstrass form, `y^2 = x^3 + a*x + b`, over any
+/// field `F` (rationals, prime fields, ...). Mostly educational, the `secp256k1`
+/// module has its own specialized implementation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EllipticCurve<F> {
+    a: F,
+    b: F,
+}
+
+/// A point of some curve, operations are done through the curve itself
+#[derive(Debug, Clone, PartialEq)]
+pub enum Point<F> {
+    AtInfinity,
+    Normal(F, F),
+}
+
+impl<F> Point<F> {
+    pub fn x(&self) -> Option<&F> {
+        match self {
+            Point::AtInfinity => None,
+            Point::Normal(x, _) => Some(x),
+        }
+    }
+
+    pub fn y(&self) -> Option<&F> {
+        match self {
+            Point::AtInfinity => None,
+            Point::Normal(_, y) => Some(y),
+        }
+    }
+
+    pub fn is_point_at_inf(&self) -> bool {
+        matches!(self, Point::AtInfinity)
+    }
+}
+
+impl<F: Field> EllipticCurve<F> {
+    pub fn new(a: F, b: F) -> Self {
+        Self { a, b }
+    }
+
+    pub fn a(&self) -> &F {
+        &self.a
+    }
+
+    pub fn b(&self) -> &F {
+        &self.b
+    }
+
+    pub fn contains(&self, x: &F, y: &F) -> bool {
+        let lhs = y.clone() * y.clone();
+        let rhs = x.clone() * x.clone() * x.clone() + self.a.clone() * x.clone() + self.b.clone();
+        lhs == rhs
+    }
+
+    /// Build a point of this curve, checking it satisfies the curve equation
+    pub fn point(&self, x: F, y: F) -> Result<Point<F>> {
+        if self.contains(&x, &y) {
+            Ok(Point::Normal(x, y))
+        } else {
+            Err(Error::PointNotOnTheCurve)
+        }
+    }
+
+    pub fn neg(&self, point: &Point<F>) -> Point<F> {
+        match point {
+            Point::AtInfinity => Point::AtInfinity,
+            Point::Normal(x, y) => Point::Normal(x.clone(), F::zero() - y.clone()),
+        }
+    }
+
+    pub fn add(&self, lhs: &Point<F>, rhs: &Point<F>) -> Point<F> {
+        let (x1, y1, x2, y2) = match (lhs, rhs) {
+            // additive identity
+            (Point::AtInfinity, p) | (p, Point::AtInfinity) => return p.clone(),
+            (Point::Normal(x1, y1), Point::Normal(x2, y2)) => (x1, y1, x2, y2),
+        };
+
+        let slope = if x1 != x2 {
+            (y2.clone() - y1.clone()) / (x2.clone() - x1.clone())
+        } else if y1 != y2 || y1.is_zero() {
+            // rhs is the additive inverse of lhs (or a vertical tangent)
+            return Point::AtInfinity;
+        } else {
+            let x1_squared = x1.clone() * x1.clone();
+            let three = F::one() + F::one() + F::one();
+            (three * x1_squared + self.a.clone()) / (y1.clone() + y1.clone())
+        };
+
+        let x3 = slope.clone() * slope.clone() - x1.clone() - x2.clone();
+        let y3 = slope * (x1.clone() - x3.clone()) - y1.clone();
+
+        Point::Normal(x3, y3)
+    }
+
+    pub fn double(&self, point: &Point<F>) -> Point<F> {
+        self.add(point, point)
+    }
+
+    /// Scalar multiplication with double and add
+    pub fn mul<U>(&self, point: &Point<F>, coef: U) -> Point<F>
+    where
+        U: Into<BigUint>,
+    {
+        let coef = coef.into();
+
+        let mut result = Point::AtInfinity;
+        for bit in (0..coef.bits()).rev() {
+            result = self.double(&result);
+            if coef.bit(bit) {
+                result = self.add(&result, point);
+            }
+        }
+
+        result
+    }
+}
+
+/// Domain parameters of a curve over a prime field, enough to run ECDSA on it
+pub trait CurveParams {
+    type Field: PrimeField;
+
+    fn a() -> Self::Field;
+
+    fn b() -> Self::Field;
+
+    /// The base point, generates a subgroup of prime order
+    fn generator() -> Point<Self::Field>;
+
+    /// The (prime) order of the generator
+    fn order() -> &'static BigUint;
+
+    fn curve() -> EllipticCurve<Self::Field> {
+        EllipticCurve::new(Self::a(), Self::b())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use num_traits::Zero;
+
+    use super::*;
+    use crate::field::Fp;
+    use crate::secp256k1::field::FieldElement;
+
+    prime_modulus!(F223 = "df");
+
+    fn f223(n: u8) -> Fp<F223> {
+        Fp::new(n)
+    }
+
+    fn curve223() -> EllipticCurve<Fp<F223>> {
+        EllipticCurve::new(f223(0), f223(7))
+    }
+
+    #[test]
+    fn points_on_the_curve() {
+        let curve = curve223();
+
+        assert!(curve.point(f223(192), f223(105)).is_ok());
+        assert!(curve.point(f223(17), f223(56)).is_ok());
+        assert!(curve.point(f223(1), f223(193)).is_ok());
+        assert!(curve.point(f223(200), f223(119)).is_err());
+        assert!(curve.point(f223(42), f223(99)).is_err());
+    }
+
+    #[test]
+    fn group_law() {
+        let curve = curve223();
+        let point = |x, y| curve.point(f223(x), f223(y)).unwrap();
+
+        let sum = curve.add(&point(192, 105), &point(17, 56));
+        assert_eq!(sum, point(170, 142));
+        assert_eq!(curve.double(&point(47, 71)), point(36, 111));
+        assert_eq!(curve.mul(&point(47, 71), 21u8), Point::AtInfinity);
+        assert_eq!(curve.mul(&point(15, 86), 7u8), Point::AtInfinity);
+
+        let p = point(47, 71);
+        assert_eq!(curve.add(&p, &curve.neg(&p)), Point::AtInfinity);
+        assert_eq!(curve.add(&p, &Point::AtInfinity), p);
+    }
+
+    #[test]
+    fn matches_secp256k1() {
+        let g = &*crate::secp256k1::G;
+        let curve = EllipticCurve::new(FieldElement::zero(), FieldElement::from_u64(7));
+        let generator = curve.point(*g.x().unwrap(), *g.y().unwrap()).unwrap();
+
+        let k = 0xdead_beef_u64;
+        let expected = g * k;
+        let result = curve.mul(&generator, k);
+
+        assert_eq!(result.x(), expected.x());
+        assert_eq!(result.y(), expected.y());
+    }
+}
@@ -0,0 +1,97 @@
+use crate::{Error, Result};
+
+const INPUT_CHARSET: &str =
+    "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+fn polymod(c: u64, value: u64) -> u64 {
+    let c0 = c >> 35;
+    let mut c = ((c & 0x7_ffff_ffff) << 5) ^ value;
+    if c0 & 1 != 0 {
+        c ^= 0xf5_dee5_1989;
+    }
+    if c0 & 2 != 0 {
+        c ^= 0xa9_fdca_3312;
+    }
+    if c0 & 4 != 0 {
+        c ^= 0x1b_ab10_e32d;
+    }
+    if c0 & 8 != 0 {
+        c ^= 0x37_06b1_677a;
+    }
+    if c0 & 16 != 0 {
+        c ^= 0x64_4d62_6ffd;
+    }
+    c
+}
+
+/// The 8 characters checksum of a descriptor (BIP380), without the `#` separator
+pub fn checksum(descriptor: &str) -> Result<String> {
+    let mut c = 1;
+    let mut class = 0;
+    let mut class_count = 0;
+
+    for ch in descriptor.chars() {
+        let position = INPUT_CHARSET
+            .find(ch)
+            .ok_or(Error::InvalidDescriptor("invalid character"))? as u64;
+
+        c = polymod(c, position & 31);
+        class = class * 3 + (position >> 5);
+        class_count += 1;
+        if class_count == 3 {
+            c = polymod(c, class);
+            class = 0;
+            class_count = 0;
+        }
+    }
+
+    if class_count > 0 {
+        c = polymod(c, class);
+    }
+    for _ in 0..8 {
+        c = polymod(c, 0);
+    }
+    c ^= 1;
+
+    Ok((0..8)
+        .map(|j| CHECKSUM_CHARSET[((c >> (5 * (7 - j))) & 31) as usize] as char)
+        .collect())
+}
+
+/// Split the descriptor from its checksum, verifying the latter if present
+pub fn strip_checksum(descriptor: &str) -> Result<&str> {
+    match descriptor.rsplit_once('#') {
+        Some((descriptor, expected)) => {
+            if expected.len() != 8 {
+                return Err(Error::InvalidDescriptor("checksum must be 8 chars"));
+            }
+            if checksum(descriptor)? != expected {
+                return Err(Error::InvalidDescriptor("checksum mismatch"));
+            }
+            Ok(descriptor)
+        }
+        None => Ok(descriptor),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bip380_checksums() {
+        assert_eq!(checksum("raw(deadbeef)").unwrap(), "89f8spxm");
+        assert_eq!(
+            strip_checksum("raw(deadbeef)#89f8spxm").unwrap(),
+            "raw(deadbeef)"
+        );
+        assert_eq!(strip_checksum("raw(deadbeef)").unwrap(), "raw(deadbeef)");
+
+        assert!(strip_checksum("raw(deadbeef)#").is_err());
+        assert!(strip_checksum("raw(deadbeef)#89f8spxmx").is_err());
+        assert!(strip_checksum("raw(deadbeef)#89f8spxn").is_err());
+        assert!(strip_checksum("raw(Deadbeef)#89f8spxm").is_err());
+        assert!(checksum("raw(deadbeef)\u{e9}").is_err());
+    }
+}
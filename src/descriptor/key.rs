@@ -0,0 +1,540 @@
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use crate::bip32::{
+    fmt_index, parse_index, version, version_of, DerivationPath, ExtendedPrivateKey,
+    ExtendedPublicKey, HARDENED,
+};
+use crate::network::Network;
+use crate::secp256k1::crypto::{PrivateKey, PublicKey};
+use crate::{base58, Error, Result};
+
+/// Fingerprint of the master key and path from it to the key in the expression
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyOrigin {
+    pub fingerprint: [u8; 4],
+    pub path: DerivationPath,
+}
+
+impl FromStr for KeyOrigin {
+    type Err = Error;
+
+    fn from_str(origin: &str) -> Result<Self> {
+        let mut steps = origin.splitn(2, '/');
+
+        let fingerprint = steps.next().unwrap_or_default();
+        if fingerprint.len() != 8 {
+            return Err(Error::InvalidDescriptorKey(
+                "fingerprint must be 8 hex chars",
+            ));
+        }
+
+        let mut result = [0u8; 4];
+        hex::decode_to_slice(fingerprint, &mut result)
+            .map_err(|_| Error::InvalidDescriptorKey("fingerprint must be 8 hex chars"))?;
+
+        let path = match steps.next() {
+            Some(path) => path.parse()?,
+            None => DerivationPath::default(),
+        };
+
+        Ok(Self {
+            fingerprint: result,
+            path,
+        })
+    }
+}
+
+impl Display for KeyOrigin {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        write!(fmt, "{}", hex::encode(self.fingerprint))?;
+        fmt_path(&self.path, fmt)
+    }
+}
+
+/// Trailing `/*` of a ranged key expression
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wildcard {
+    None,
+    Unhardened,
+    Hardened,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyKind {
+    /// Hex encoded public key
+    Public { key: PublicKey, compressed: bool },
+
+    /// 32 bytes hex encoded x-only public key, only valid inside `tr()`
+    XOnly { key: PublicKey },
+
+    /// WIF encoded private key
+    Private {
+        key: PrivateKey,
+        compressed: bool,
+        network: Network,
+    },
+
+    /// `xpub`/`tpub` followed by derivation steps
+    ExtendedPublic {
+        key: ExtendedPublicKey,
+        network: Network,
+        path: DerivationPath,
+        wildcard: Wildcard,
+    },
+
+    /// `xprv`/`tprv` followed by derivation steps
+    ExtendedPrivate {
+        key: ExtendedPrivateKey,
+        network: Network,
+        path: DerivationPath,
+        wildcard: Wildcard,
+    },
+}
+
+/// A key expression as used inside output descriptors (BIP380)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DescriptorKey {
+    pub(crate) origin: Option<KeyOrigin>,
+    pub(crate) kind: KeyKind,
+}
+
+impl DescriptorKey {
+    pub fn new(origin: Option<KeyOrigin>, kind: KeyKind) -> Self {
+        Self { origin, kind }
+    }
+
+    pub fn origin(&self) -> Option<&KeyOrigin> {
+        self.origin.as_ref()
+    }
+
+    pub fn kind(&self) -> &KeyKind {
+        &self.kind
+    }
+
+    /// Whether this expression describes a range of keys (ends with `/*`)
+    pub fn is_wildcard(&self) -> bool {
+        match &self.kind {
+            KeyKind::ExtendedPublic { wildcard, .. }
+            | KeyKind::ExtendedPrivate { wildcard, .. } => *wildcard != Wildcard::None,
+            _ => false,
+        }
+    }
+
+    /// Whether this expression holds private key material
+    pub fn has_secret(&self) -> bool {
+        matches!(
+            self.kind,
+            KeyKind::Private { .. } | KeyKind::ExtendedPrivate { .. }
+        )
+    }
+
+    /// Whether the derived keys are serialized in compressed form
+    pub fn is_compressed(&self) -> bool {
+        match &self.kind {
+            KeyKind::Public { compressed, .. } | KeyKind::Private { compressed, .. } => *compressed,
+            _ => true,
+        }
+    }
+
+    /// Derive the concrete public key at `index`, ignored for non ranged expressions
+    pub fn derive_public_key(&self, index: u32) -> Result<PublicKey> {
+        match &self.kind {
+            KeyKind::Public { key, .. } | KeyKind::XOnly { key } => Ok(key.clone()),
+            KeyKind::Private { key, .. } => Ok(key.public_key().clone()),
+            KeyKind::ExtendedPublic {
+                key,
+                path,
+                wildcard,
+                ..
+            } => {
+                let path = full_path(path, *wildcard, index)?;
+                Ok(key.derive_path(path)?.public_key().clone())
+            }
+            KeyKind::ExtendedPrivate {
+                key,
+                path,
+                wildcard,
+                ..
+            } => {
+                let path = full_path(path, *wildcard, index)?;
+                Ok(key.derive_path(path)?.private_key().public_key().clone())
+            }
+        }
+    }
+
+    /// Derive the concrete private key at `index`, if this expression has one
+    pub fn derive_private_key(&self, index: u32) -> Result<Option<PrivateKey>> {
+        match &self.kind {
+            KeyKind::Private { key, .. } => Ok(Some(key.clone())),
+            KeyKind::ExtendedPrivate {
+                key,
+                path,
+                wildcard,
+                ..
+            } => {
+                let path = full_path(path, *wildcard, index)?;
+                Ok(Some(key.derive_path(path)?.private_key().clone()))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Origin of the key derived at `index`, relative to the master key
+    pub fn derived_origin(&self, index: u32) -> Result<Option<KeyOrigin>> {
+        let (fingerprint, path) = match (&self.kind, &self.origin) {
+            (KeyKind::Public { .. }, _)
+            | (KeyKind::XOnly { .. }, _)
+            | (KeyKind::Private { .. }, _) => return Ok(self.origin.clone()),
+
+            (KeyKind::ExtendedPublic { path, wildcard, .. }, Some(origin))
+            | (KeyKind::ExtendedPrivate { path, wildcard, .. }, Some(origin)) => (
+                origin.fingerprint,
+                origin.path.extend(&full_path(path, *wildcard, index)?),
+            ),
+
+            (
+                KeyKind::ExtendedPublic {
+                    key,
+                    path,
+                    wildcard,
+                    ..
+                },
+                None,
+            ) => (key.fingerprint()?, full_path(path, *wildcard, index)?),
+
+            (
+                KeyKind::ExtendedPrivate {
+                    key,
+                    path,
+                    wildcard,
+                    ..
+                },
+                None,
+            ) => (key.fingerprint()?, full_path(path, *wildcard, index)?),
+        };
+
+        Ok(Some(KeyOrigin { fingerprint, path }))
+    }
+
+    /// Replace this expression with the one holding the concrete public key at `index`
+    pub fn at_index(&self, index: u32) -> Result<Self> {
+        let key = self.derive_public_key(index)?;
+        let kind = match &self.kind {
+            KeyKind::XOnly { .. } => KeyKind::XOnly { key },
+            _ => KeyKind::Public {
+                key,
+                compressed: self.is_compressed(),
+            },
+        };
+
+        Ok(Self {
+            origin: self.derived_origin(index)?,
+            kind,
+        })
+    }
+
+    /// Replace any private key material with its public counterpart
+    pub fn to_public(&self) -> Self {
+        let kind = match &self.kind {
+            KeyKind::Private {
+                key, compressed, ..
+            } => KeyKind::Public {
+                key: key.public_key().clone(),
+                compressed: *compressed,
+            },
+
+            KeyKind::ExtendedPrivate {
+                key,
+                network,
+                path,
+                wildcard,
+            } => KeyKind::ExtendedPublic {
+                key: key.extended_public_key(),
+                network: *network,
+                path: path.clone(),
+                wildcard: *wildcard,
+            },
+
+            kind => kind.clone(),
+        };
+
+        Self {
+            origin: self.origin.clone(),
+            kind,
+        }
+    }
+}
+
+/// Path from the extended key to the derived key at `index`
+fn full_path(path: &DerivationPath, wildcard: Wildcard, index: u32) -> Result<DerivationPath> {
+    match wildcard {
+        Wildcard::None => Ok(path.clone()),
+        _ if index >= HARDENED => Err(Error::InvalidDescriptorKey("index out of range")),
+        Wildcard::Unhardened => Ok(path.child(index)),
+        Wildcard::Hardened => Ok(path.child(index + HARDENED)),
+    }
+}
+
+/// Format the path as `/`-prefixed steps, without the leading `m`
+fn fmt_path(path: &DerivationPath, fmt: &mut Formatter) -> fmt::Result {
+    for index in path.as_ref() {
+        write!(fmt, "/")?;
+        fmt_index(*index, fmt)?;
+    }
+
+    Ok(())
+}
+
+fn parse_steps(steps: &[&str]) -> Result<(DerivationPath, Wildcard)> {
+    let (wildcard, steps) = match steps.split_last() {
+        Some((&"*", rest)) => (Wildcard::Unhardened, rest),
+        Some((&"*'", rest)) | Some((&"*h", rest)) => (Wildcard::Hardened, rest),
+        _ => (Wildcard::None, steps),
+    };
+
+    let path = steps
+        .iter()
+        .map(|step| parse_index(step))
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok((DerivationPath::new(path), wildcard))
+}
+
+fn parse_wif(data: &[u8]) -> Result<KeyKind> {
+    let (key, metadata) = PrivateKey::from_wif_payload(data)?;
+
+    Ok(KeyKind::Private {
+        key,
+        compressed: metadata.compressed,
+        network: metadata.network,
+    })
+}
+
+/// Test networks share the extended key versions, these resolve to testnet
+fn network_of(version: [u8; 4]) -> Network {
+    if version == version::XPUB || version == version::XPRV {
+        Network::Mainnet
+    } else {
+        Network::Testnet
+    }
+}
+
+impl FromStr for DescriptorKey {
+    type Err = Error;
+
+    fn from_str(expr: &str) -> Result<Self> {
+        let (origin, expr) = match expr.strip_prefix('[') {
+            Some(rest) => {
+                let end = rest
+                    .find(']')
+                    .ok_or(Error::InvalidDescriptorKey("unclosed key origin"))?;
+                (Some(rest[..end].parse()?), &rest[end + 1..])
+            }
+            None => (None, expr),
+        };
+
+        let mut parts = expr.split('/');
+        let key = parts.next().unwrap_or_default();
+        let steps: Vec<_> = parts.collect();
+
+        let is_hex = key.chars().all(|c| c.is_ascii_hexdigit());
+        if is_hex && key.len() == 64 {
+            if !steps.is_empty() {
+                return Err(Error::InvalidDescriptorKey(
+                    "cannot derive from a single key",
+                ));
+            }
+
+            let mut x = [0u8; 32];
+            hex::decode_to_slice(key, &mut x)
+                .map_err(|_| Error::InvalidDescriptorKey("invalid hex public key"))?;
+            let kind = KeyKind::XOnly {
+                key: PublicKey::from_x(&x, false)?,
+            };
+
+            return Ok(Self { origin, kind });
+        }
+
+        if is_hex && (key.len() == 66 || key.len() == 130) {
+            if !steps.is_empty() {
+                return Err(Error::InvalidDescriptorKey(
+                    "cannot derive from a single key",
+                ));
+            }
+
+            let bytes = hex::decode(key)
+                .map_err(|_| Error::InvalidDescriptorKey("invalid hex public key"))?;
+            let kind = KeyKind::Public {
+                key: PublicKey::deserialize(&bytes)?,
+                compressed: key.len() == 66,
+            };
+
+            return Ok(Self { origin, kind });
+        }
+
+        let data = base58::decode_checksum(key)?;
+        if data.len() != 78 {
+            if !steps.is_empty() {
+                return Err(Error::InvalidDescriptorKey(
+                    "cannot derive from a single key",
+                ));
+            }
+
+            let kind = parse_wif(&data)?;
+            return Ok(Self { origin, kind });
+        }
+
+        let (path, wildcard) = parse_steps(&steps)?;
+        let version = version_of(&data)?;
+        let kind = match version {
+            version::XPUB | version::TPUB => KeyKind::ExtendedPublic {
+                key: ExtendedPublicKey::deserialize(&data)?,
+                network: network_of(version),
+                path,
+                wildcard,
+            },
+
+            version::XPRV | version::TPRV => KeyKind::ExtendedPrivate {
+                key: ExtendedPrivateKey::deserialize(&data)?,
+                network: network_of(version),
+                path,
+                wildcard,
+            },
+
+            _ => return Err(Error::InvalidDescriptorKey("unknown extended key version")),
+        };
+
+        Ok(Self { origin, kind })
+    }
+}
+
+impl Display for DescriptorKey {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        if let Some(origin) = &self.origin {
+            write!(fmt, "[{}]", origin)?;
+        }
+
+        let (path, wildcard) = match &self.kind {
+            KeyKind::Public { key, compressed } => {
+                let bytes = key.serialize(*compressed).map_err(|_| fmt::Error)?;
+                return write!(fmt, "{}", hex::encode(bytes));
+            }
+
+            KeyKind::XOnly { key } => {
+                let bytes = key.serialize_compressed().map_err(|_| fmt::Error)?;
+                return write!(fmt, "{}", hex::encode(&bytes[1..]));
+            }
+
+            KeyKind::Private {
+                key,
+                compressed,
+                network,
+            } => {
+                let wif = key
+                    .create_wif(*compressed, *network)
+                    .map_err(|_| fmt::Error)?;
+                return write!(fmt, "{}", wif);
+            }
+
+            KeyKind::ExtendedPublic {
+                key,
+                network,
+                path,
+                wildcard,
+            } => {
+                let xpub = key
+                    .create_xpub(network.xpub_version())
+                    .map_err(|_| fmt::Error)?;
+                write!(fmt, "{}", xpub)?;
+                (path, wildcard)
+            }
+
+            KeyKind::ExtendedPrivate {
+                key,
+                network,
+                path,
+                wildcard,
+            } => {
+                let xprv = key
+                    .create_xprv(network.xprv_version())
+                    .map_err(|_| fmt::Error)?;
+                write!(fmt, "{}", xprv)?;
+                (path, wildcard)
+            }
+        };
+
+        fmt_path(path, fmt)?;
+        match wildcard {
+            Wildcard::None => Ok(()),
+            Wildcard::Unhardened => write!(fmt, "/*"),
+            Wildcard::Hardened => write!(fmt, "/*'"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XPUB: &str = "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8";
+    const XPRV: &str = "xprv9s21ZrQH143K3QTDL4LXw2F7HEK3wJUD2nW2nRk4stbPy6cq3jPPqjiChkVvvNKmPGJxWUtg6LnF5kejMRNNU3TGtRBeJgk33yuGBxrMPHi";
+
+    fn roundtrip(expr: &str) -> DescriptorKey {
+        let key: DescriptorKey = expr.parse().unwrap();
+        assert_eq!(key.to_string(), expr);
+        key
+    }
+
+    #[test]
+    fn single_keys() {
+        let key = roundtrip(
+            "[deadbeef/0'/0'/0']0260b2003c386519fc9eadf2b5cf124dd8eea4c4e68d5e154050a9346ea98ce600",
+        );
+        assert!(!key.is_wildcard());
+        assert_eq!(key.origin().unwrap().fingerprint, [0xde, 0xad, 0xbe, 0xef]);
+
+        let key = roundtrip("cMahea7zqjxrtgAbB7LSGbcQUr1uX1ojuat9jZodMN8rFTv2sfUK");
+        assert!(key.has_secret());
+        assert_eq!(
+            key.derive_public_key(0).unwrap(),
+            PrivateKey::new(5003usize).public_key().clone()
+        );
+
+        let key = roundtrip("60b2003c386519fc9eadf2b5cf124dd8eea4c4e68d5e154050a9346ea98ce600");
+        assert!(matches!(key.kind(), KeyKind::XOnly { .. }));
+        assert_eq!(
+            key.at_index(0).unwrap().to_string(),
+            "60b2003c386519fc9eadf2b5cf124dd8eea4c4e68d5e154050a9346ea98ce600"
+        );
+
+        assert!(
+            "0260b2003c386519fc9eadf2b5cf124dd8eea4c4e68d5e154050a9346ea98ce600/0"
+                .parse::<DescriptorKey>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn extended_keys() {
+        let key = roundtrip(&format!("[bd16bee5/0']{}/1/*", XPUB));
+        assert!(key.is_wildcard());
+
+        let expected = ExtendedPublicKey::from_xpub(XPUB)
+            .unwrap()
+            .derive_path([1, 7])
+            .unwrap();
+        assert_eq!(&key.derive_public_key(7).unwrap(), expected.public_key());
+
+        let origin = key.derived_origin(7).unwrap().unwrap();
+        assert_eq!(origin.to_string(), "bd16bee5/0'/1/7");
+
+        let key = roundtrip(&format!("{}/0'/*'", XPRV));
+        let private = key.derive_private_key(3).unwrap().unwrap();
+        assert_eq!(&key.derive_public_key(3).unwrap(), private.public_key());
+        assert!(key.to_public().to_string().starts_with("xpub"));
+
+        let hardened: DescriptorKey = format!("{}/0'/*", XPUB).parse().unwrap();
+        assert!(hardened.derive_public_key(0).is_err());
+    }
+}
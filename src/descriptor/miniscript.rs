@@ -0,0 +1,1421 @@
+use std::fmt::{self, Display, Formatter};
+
+use crate::secp256k1::crypto::PublicKey;
+use crate::utils::hash160;
+use crate::{Error, Result};
+
+use super::key::DescriptorKey;
+use super::{parse_key, parse_tap_key, push_int, push_slice, split_args, split_call, x_only};
+
+const OP_0: u8 = 0x00;
+const OP_1: u8 = 0x51;
+const OP_IF: u8 = 0x63;
+const OP_NOTIF: u8 = 0x64;
+const OP_ELSE: u8 = 0x67;
+const OP_ENDIF: u8 = 0x68;
+const OP_VERIFY: u8 = 0x69;
+const OP_TOALTSTACK: u8 = 0x6b;
+const OP_FROMALTSTACK: u8 = 0x6c;
+const OP_IFDUP: u8 = 0x73;
+const OP_DUP: u8 = 0x76;
+const OP_SWAP: u8 = 0x7c;
+const OP_SIZE: u8 = 0x82;
+const OP_EQUAL: u8 = 0x87;
+const OP_EQUALVERIFY: u8 = 0x88;
+const OP_0NOTEQUAL: u8 = 0x92;
+const OP_ADD: u8 = 0x93;
+const OP_BOOLAND: u8 = 0x9a;
+const OP_BOOLOR: u8 = 0x9b;
+const OP_NUMEQUAL: u8 = 0x9c;
+const OP_NUMEQUALVERIFY: u8 = 0x9d;
+const OP_RIPEMD160: u8 = 0xa6;
+const OP_SHA256: u8 = 0xa8;
+const OP_HASH160: u8 = 0xa9;
+const OP_HASH256: u8 = 0xaa;
+const OP_CHECKSIG: u8 = 0xac;
+const OP_CHECKSIGVERIFY: u8 = 0xad;
+const OP_CHECKMULTISIG: u8 = 0xae;
+const OP_CHECKMULTISIGVERIFY: u8 = 0xaf;
+const OP_CHECKLOCKTIMEVERIFY: u8 = 0xb1;
+const OP_CHECKSEQUENCEVERIFY: u8 = 0xb2;
+const OP_CHECKSIGADD: u8 = 0xba;
+
+/// Standardness limit of `wsh()` witness scripts
+const MAX_WITNESS_SCRIPT_SIZE: usize = 3600;
+
+/// Script flavour the miniscript is compiled to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptContext {
+    /// Witness script of a `wsh()` output
+    Segwitv0,
+    /// Leaf of a `tr()` script tree (BIP342)
+    Tap,
+}
+
+impl ScriptContext {
+    /// DER signature plus the sighash byte, or schnorr signature plus the sighash byte
+    fn signature_size(self) -> usize {
+        match self {
+            Self::Segwitv0 => 1 + 73,
+            Self::Tap => 1 + 65,
+        }
+    }
+
+    fn key_size(self) -> usize {
+        match self {
+            Self::Segwitv0 => 1 + 33,
+            Self::Tap => 1 + 32,
+        }
+    }
+}
+
+/// What a fragment leaves on the stack
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Base {
+    /// Pushes nonzero on satisfaction and an exact zero on dissatisfaction
+    B,
+    /// Continues on satisfaction, aborts otherwise
+    V,
+    /// Pushes a key, a signature check turns it into `B`
+    K,
+    /// Like `B` but takes its input from one below the top of the stack
+    W,
+}
+
+/// Type of a miniscript fragment, the base plus its correctness properties
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Type {
+    pub base: Base,
+    /// `z`, consumes exactly 0 stack elements
+    pub zero_arg: bool,
+    /// `o`, consumes exactly 1 stack element
+    pub one_arg: bool,
+    /// `n`, the top input is never zero when satisfied
+    pub non_zero: bool,
+    /// `d`, can always be dissatisfied
+    pub dissatisfiable: bool,
+    /// `u`, pushes exactly 1 when satisfied
+    pub unit: bool,
+}
+
+impl Type {
+    fn new(base: Base) -> Self {
+        Self {
+            base,
+            zero_arg: false,
+            one_arg: false,
+            non_zero: false,
+            dissatisfiable: false,
+            unit: false,
+        }
+    }
+
+    fn is(&self, base: Base) -> bool {
+        self.base == base
+    }
+}
+
+/// Hash lock of a `sha256()`, `hash256()`, `ripemd160()` or `hash160()` fragment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashLock {
+    Sha256([u8; 32]),
+    Hash256([u8; 32]),
+    Ripemd160([u8; 20]),
+    Hash160([u8; 20]),
+}
+
+impl HashLock {
+    fn parts(&self) -> (&'static str, u8, &[u8]) {
+        match self {
+            Self::Sha256(hash) => ("sha256", OP_SHA256, hash),
+            Self::Hash256(hash) => ("hash256", OP_HASH256, hash),
+            Self::Ripemd160(hash) => ("ripemd160", OP_RIPEMD160, hash),
+            Self::Hash160(hash) => ("hash160", OP_HASH160, hash),
+        }
+    }
+}
+
+impl Display for HashLock {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        let (name, _, hash) = self.parts();
+        write!(fmt, "{}({})", name, hex::encode(hash))
+    }
+}
+
+/// Miniscript fragments, `pk()`, `pkh()`, `and_n()` and the `t:`, `l:`, `u:` wrappers
+/// are sugar for the fragments below
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node {
+    False,
+    True,
+    PkK(DescriptorKey),
+    PkH(DescriptorKey),
+    Older(u32),
+    After(u32),
+    Hash(HashLock),
+    AndOr(Box<Miniscript>, Box<Miniscript>, Box<Miniscript>),
+    AndV(Box<Miniscript>, Box<Miniscript>),
+    AndB(Box<Miniscript>, Box<Miniscript>),
+    OrB(Box<Miniscript>, Box<Miniscript>),
+    OrC(Box<Miniscript>, Box<Miniscript>),
+    OrD(Box<Miniscript>, Box<Miniscript>),
+    OrI(Box<Miniscript>, Box<Miniscript>),
+    Thresh(usize, Vec<Miniscript>),
+    Multi(usize, Vec<DescriptorKey>),
+    MultiA(usize, Vec<DescriptorKey>),
+    /// `a:`
+    Alt(Box<Miniscript>),
+    /// `s:`
+    Swap(Box<Miniscript>),
+    /// `c:`
+    Check(Box<Miniscript>),
+    /// `d:`
+    DupIf(Box<Miniscript>),
+    /// `v:`
+    Verify(Box<Miniscript>),
+    /// `j:`
+    NonZero(Box<Miniscript>),
+    /// `n:`
+    ZeroNotEqual(Box<Miniscript>),
+}
+
+/// Provides what's needed to satisfy a miniscript, nothing by default
+pub trait Satisfier {
+    /// Signature for the given key, including the sighash byte
+    fn lookup_signature(&self, _key: &PublicKey) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn lookup_preimage(&self, _hash: &HashLock) -> Option<[u8; 32]> {
+        None
+    }
+
+    /// Whether the spending input's sequence satisfies `older(n)`
+    fn check_older(&self, _n: u32) -> bool {
+        false
+    }
+
+    /// Whether the spending transaction's locktime satisfies `after(n)`
+    fn check_after(&self, _n: u32) -> bool {
+        false
+    }
+}
+
+/// Witness stack in push order, the last element ends at the top of the stack
+pub type Witness = Vec<Vec<u8>>;
+
+/// A type checked miniscript expression
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Miniscript {
+    pub(crate) node: Node,
+    pub(crate) ty: Type,
+    pub(crate) context: ScriptContext,
+}
+
+impl Miniscript {
+    /// Type check the fragment, failing if its subexpressions don't fit
+    pub fn new(node: Node, context: ScriptContext) -> Result<Self> {
+        let ty = type_check(&node, context)?;
+        Ok(Self { node, ty, context })
+    }
+
+    /// Parse a top level expression, which must be of type `B`
+    pub fn parse(expr: &str, context: ScriptContext) -> Result<Self> {
+        let miniscript = parse_fragment(expr, context)?;
+        if !miniscript.ty.is(Base::B) {
+            return Err(Error::InvalidMiniscript("top level must be of type B"));
+        }
+
+        if context == ScriptContext::Segwitv0
+            && miniscript.script_pubkey(0)?.len() > MAX_WITNESS_SCRIPT_SIZE
+        {
+            return Err(Error::InvalidMiniscript("witness script too large"));
+        }
+
+        Ok(miniscript)
+    }
+
+    pub fn node(&self) -> &Node {
+        &self.node
+    }
+
+    pub fn ty(&self) -> Type {
+        self.ty
+    }
+
+    pub fn context(&self) -> ScriptContext {
+        self.context
+    }
+
+    fn children(&self) -> Vec<&Miniscript> {
+        match &self.node {
+            Node::AndOr(x, y, z) => vec![x, y, z],
+            Node::AndV(x, y)
+            | Node::AndB(x, y)
+            | Node::OrB(x, y)
+            | Node::OrC(x, y)
+            | Node::OrD(x, y)
+            | Node::OrI(x, y) => vec![x, y],
+            Node::Thresh(_, subs) => subs.iter().collect(),
+            Node::Alt(x)
+            | Node::Swap(x)
+            | Node::Check(x)
+            | Node::DupIf(x)
+            | Node::Verify(x)
+            | Node::NonZero(x)
+            | Node::ZeroNotEqual(x) => vec![x],
+            _ => vec![],
+        }
+    }
+
+    pub fn keys(&self) -> Vec<&DescriptorKey> {
+        match &self.node {
+            Node::PkK(key) | Node::PkH(key) => vec![key],
+            Node::Multi(_, keys) | Node::MultiA(_, keys) => keys.iter().collect(),
+            _ => self
+                .children()
+                .into_iter()
+                .flat_map(|child| child.keys())
+                .collect(),
+        }
+    }
+
+    /// Compile to script with the keys derived at `index`
+    pub fn script_pubkey(&self, index: u32) -> Result<Vec<u8>> {
+        let mut script = Vec::new();
+        self.encode(index, &mut script)?;
+        Ok(script)
+    }
+
+    fn encode(&self, index: u32, script: &mut Vec<u8>) -> Result<()> {
+        match &self.node {
+            Node::False => script.push(OP_0),
+            Node::True => script.push(OP_1),
+            Node::PkK(key) => push_slice(script, &self.serialize_key(key, index)?),
+            Node::PkH(key) => {
+                let hash = hash160(self.serialize_key(key, index)?);
+                script.extend_from_slice(&[OP_DUP, OP_HASH160]);
+                push_slice(script, &hash);
+                script.push(OP_EQUALVERIFY);
+            }
+            Node::Older(n) => {
+                push_int(script, *n as usize);
+                script.push(OP_CHECKSEQUENCEVERIFY);
+            }
+            Node::After(n) => {
+                push_int(script, *n as usize);
+                script.push(OP_CHECKLOCKTIMEVERIFY);
+            }
+            Node::Hash(lock) => {
+                // the preimage is always 32 bytes
+                let (_, opcode, hash) = lock.parts();
+                script.push(OP_SIZE);
+                push_int(script, 32);
+                script.extend_from_slice(&[OP_EQUALVERIFY, opcode]);
+                push_slice(script, hash);
+                script.push(OP_EQUAL);
+            }
+            Node::AndOr(x, y, z) => {
+                x.encode(index, script)?;
+                script.push(OP_NOTIF);
+                z.encode(index, script)?;
+                script.push(OP_ELSE);
+                y.encode(index, script)?;
+                script.push(OP_ENDIF);
+            }
+            Node::AndV(x, y) => {
+                x.encode(index, script)?;
+                y.encode(index, script)?;
+            }
+            Node::AndB(x, y) => {
+                x.encode(index, script)?;
+                y.encode(index, script)?;
+                script.push(OP_BOOLAND);
+            }
+            Node::OrB(x, z) => {
+                x.encode(index, script)?;
+                z.encode(index, script)?;
+                script.push(OP_BOOLOR);
+            }
+            Node::OrC(x, z) => {
+                x.encode(index, script)?;
+                script.push(OP_NOTIF);
+                z.encode(index, script)?;
+                script.push(OP_ENDIF);
+            }
+            Node::OrD(x, z) => {
+                x.encode(index, script)?;
+                script.extend_from_slice(&[OP_IFDUP, OP_NOTIF]);
+                z.encode(index, script)?;
+                script.push(OP_ENDIF);
+            }
+            Node::OrI(x, z) => {
+                script.push(OP_IF);
+                x.encode(index, script)?;
+                script.push(OP_ELSE);
+                z.encode(index, script)?;
+                script.push(OP_ENDIF);
+            }
+            Node::Thresh(k, subs) => {
+                for (i, sub) in subs.iter().enumerate() {
+                    sub.encode(index, script)?;
+                    if i > 0 {
+                        script.push(OP_ADD);
+                    }
+                }
+                push_int(script, *k);
+                script.push(OP_EQUAL);
+            }
+            Node::Multi(k, keys) => {
+                push_int(script, *k);
+                for key in keys {
+                    push_slice(script, &self.serialize_key(key, index)?);
+                }
+                push_int(script, keys.len());
+                script.push(OP_CHECKMULTISIG);
+            }
+            Node::MultiA(k, keys) => {
+                for (i, key) in keys.iter().enumerate() {
+                    push_slice(script, &self.serialize_key(key, index)?);
+                    script.push(if i == 0 { OP_CHECKSIG } else { OP_CHECKSIGADD });
+                }
+                push_int(script, *k);
+                script.push(OP_NUMEQUAL);
+            }
+            Node::Alt(x) => {
+                script.push(OP_TOALTSTACK);
+                x.encode(index, script)?;
+                script.push(OP_FROMALTSTACK);
+            }
+            Node::Swap(x) => {
+                script.push(OP_SWAP);
+                x.encode(index, script)?;
+            }
+            Node::Check(x) => {
+                x.encode(index, script)?;
+                script.push(OP_CHECKSIG);
+            }
+            Node::DupIf(x) => {
+                script.extend_from_slice(&[OP_DUP, OP_IF]);
+                x.encode(index, script)?;
+                script.push(OP_ENDIF);
+            }
+            Node::Verify(x) => {
+                x.encode(index, script)?;
+                // `B` fragments always end with an opcode, never with pushed data
+                let verify = match script.last() {
+                    Some(&OP_EQUAL) => Some(OP_EQUALVERIFY),
+                    Some(&OP_CHECKSIG) => Some(OP_CHECKSIGVERIFY),
+                    Some(&OP_CHECKMULTISIG) => Some(OP_CHECKMULTISIGVERIFY),
+                    Some(&OP_NUMEQUAL) => Some(OP_NUMEQUALVERIFY),
+                    _ => None,
+                };
+                match verify {
+                    Some(opcode) => *script.last_mut().unwrap() = opcode,
+                    None => script.push(OP_VERIFY),
+                }
+            }
+            Node::NonZero(x) => {
+                script.extend_from_slice(&[OP_SIZE, OP_0NOTEQUAL, OP_IF]);
+                x.encode(index, script)?;
+                script.push(OP_ENDIF);
+            }
+            Node::ZeroNotEqual(x) => {
+                x.encode(index, script)?;
+                script.push(OP_0NOTEQUAL);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn serialize_key(&self, key: &DescriptorKey, index: u32) -> Result<Vec<u8>> {
+        match self.context {
+            ScriptContext::Segwitv0 => Ok(key.derive_public_key(index)?.serialize(true)?),
+            ScriptContext::Tap => Ok(x_only(key, index)?.to_vec()),
+        }
+    }
+
+    /// Largest witness able to satisfy the script, each element with its length
+    /// prefix and without the script itself. `None` if it can't be satisfied.
+    pub fn max_satisfaction_size(&self) -> Option<usize> {
+        self.max_sizes().0
+    }
+
+    /// Largest satisfaction and dissatisfaction witness sizes
+    fn max_sizes(&self) -> (Option<usize>, Option<usize>) {
+        let sig = self.context.signature_size();
+        let add = |a: Option<usize>, b: Option<usize>| Some(a? + b?);
+        let max = |a: Option<usize>, b: Option<usize>| match (a, b) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            (a, b) => a.or(b),
+        };
+
+        match &self.node {
+            Node::False => (None, Some(0)),
+            Node::True => (Some(0), None),
+            Node::PkK(_) => (Some(sig), Some(1)),
+            Node::PkH(_) => {
+                let key = self.context.key_size();
+                (Some(sig + key), Some(1 + key))
+            }
+            Node::Older(_) | Node::After(_) => (Some(0), None),
+            Node::Hash(_) => (Some(33), Some(33)),
+            Node::AndOr(x, y, z) => {
+                let (x, y, z) = (x.max_sizes(), y.max_sizes(), z.max_sizes());
+                (max(add(y.0, x.0), add(z.0, x.1)), add(z.1, x.1))
+            }
+            Node::AndV(x, y) => (add(x.max_sizes().0, y.max_sizes().0), None),
+            Node::AndB(x, y) => {
+                let (x, y) = (x.max_sizes(), y.max_sizes());
+                (add(x.0, y.0), add(x.1, y.1))
+            }
+            Node::OrB(x, z) => {
+                let (x, z) = (x.max_sizes(), z.max_sizes());
+                (max(add(x.0, z.1), add(x.1, z.0)), add(x.1, z.1))
+            }
+            Node::OrC(x, z) => {
+                let (x, z) = (x.max_sizes(), z.max_sizes());
+                (max(x.0, add(x.1, z.0)), None)
+            }
+            Node::OrD(x, z) => {
+                let (x, z) = (x.max_sizes(), z.max_sizes());
+                (max(x.0, add(x.1, z.0)), add(x.1, z.1))
+            }
+            Node::OrI(x, z) => {
+                // 0x01 to take the first branch, an empty element for the second one
+                let (x, z) = (x.max_sizes(), z.max_sizes());
+                (
+                    max(add(x.0, Some(2)), add(z.0, Some(1))),
+                    max(add(x.1, Some(2)), add(z.1, Some(1))),
+                )
+            }
+            Node::Thresh(k, subs) => {
+                let sizes: Vec<_> = subs.iter().map(|sub| sub.max_sizes()).collect();
+                let dissat = sizes
+                    .iter()
+                    .map(|(_, dissat)| *dissat)
+                    .sum::<Option<usize>>();
+
+                // every subexpression is dissatisfiable (`d`), pick the k costliest ones
+                let mut extra: Vec<_> = sizes
+                    .iter()
+                    .filter_map(|(sat, dissat)| Some((*sat)? as isize - (*dissat)? as isize))
+                    .collect();
+                extra.sort_unstable_by(|a, b| b.cmp(a));
+                let sat = match dissat {
+                    Some(dissat) if extra.len() >= *k => {
+                        Some((dissat as isize + extra[..*k].iter().sum::<isize>()) as usize)
+                    }
+                    _ => None,
+                };
+
+                (sat, dissat)
+            }
+            Node::Multi(k, _) => (Some(1 + k * sig), Some(1 + k)),
+            Node::MultiA(k, keys) => (Some(k * sig + keys.len() - k), Some(keys.len())),
+            Node::Alt(x) | Node::Swap(x) | Node::Check(x) | Node::ZeroNotEqual(x) => x.max_sizes(),
+            Node::DupIf(x) => (add(x.max_sizes().0, Some(2)), Some(1)),
+            Node::Verify(x) => (x.max_sizes().0, None),
+            Node::NonZero(x) => (x.max_sizes().0, Some(1)),
+        }
+    }
+
+    /// Smallest witness satisfying the script with the keys derived at `index`
+    pub fn satisfy<S>(&self, index: u32, satisfier: &S) -> Result<Witness>
+    where
+        S: Satisfier + ?Sized,
+    {
+        self.satisfactions(index, satisfier)?
+            .0
+            .ok_or(Error::InvalidMiniscript("cannot satisfy"))
+    }
+
+    /// Smallest satisfaction and dissatisfaction witnesses, if available
+    fn satisfactions<S>(
+        &self,
+        index: u32,
+        satisfier: &S,
+    ) -> Result<(Option<Witness>, Option<Witness>)>
+    where
+        S: Satisfier + ?Sized,
+    {
+        let signature = |key: &DescriptorKey| -> Result<Option<Vec<u8>>> {
+            Ok(satisfier.lookup_signature(&key.derive_public_key(index)?))
+        };
+
+        Ok(match &self.node {
+            Node::False => (None, Some(vec![])),
+            Node::True => (Some(vec![]), None),
+            Node::PkK(key) => (signature(key)?.map(|sig| vec![sig]), Some(vec![vec![]])),
+            Node::PkH(key) => {
+                let key_bytes = self.serialize_key(key, index)?;
+                (
+                    signature(key)?.map(|sig| vec![sig, key_bytes.clone()]),
+                    Some(vec![vec![], key_bytes]),
+                )
+            }
+            Node::Older(n) => (satisfier.check_older(*n).then(Vec::new), None),
+            Node::After(n) => (satisfier.check_after(*n).then(Vec::new), None),
+            Node::Hash(lock) => (
+                satisfier
+                    .lookup_preimage(lock)
+                    .map(|preimage| vec![preimage.to_vec()]),
+                Some(vec![vec![0u8; 32]]),
+            ),
+            Node::AndOr(x, y, z) => {
+                let (x, y, z) = (
+                    x.satisfactions(index, satisfier)?,
+                    y.satisfactions(index, satisfier)?,
+                    z.satisfactions(index, satisfier)?,
+                );
+                (
+                    smallest(concat(&y.0, &x.0), concat(&z.0, &x.1)),
+                    concat(&z.1, &x.1),
+                )
+            }
+            Node::AndV(x, y) => {
+                let (x, y) = (
+                    x.satisfactions(index, satisfier)?,
+                    y.satisfactions(index, satisfier)?,
+                );
+                (concat(&y.0, &x.0), None)
+            }
+            Node::AndB(x, y) => {
+                let (x, y) = (
+                    x.satisfactions(index, satisfier)?,
+                    y.satisfactions(index, satisfier)?,
+                );
+                (concat(&y.0, &x.0), concat(&y.1, &x.1))
+            }
+            Node::OrB(x, z) => {
+                let (x, z) = (
+                    x.satisfactions(index, satisfier)?,
+                    z.satisfactions(index, satisfier)?,
+                );
+                (
+                    smallest(concat(&z.1, &x.0), concat(&z.0, &x.1)),
+                    concat(&z.1, &x.1),
+                )
+            }
+            Node::OrC(x, z) => {
+                let (x, z) = (
+                    x.satisfactions(index, satisfier)?,
+                    z.satisfactions(index, satisfier)?,
+                );
+                (smallest(x.0, concat(&z.0, &x.1)), None)
+            }
+            Node::OrD(x, z) => {
+                let (x, z) = (
+                    x.satisfactions(index, satisfier)?,
+                    z.satisfactions(index, satisfier)?,
+                );
+                (smallest(x.0, concat(&z.0, &x.1)), concat(&z.1, &x.1))
+            }
+            Node::OrI(x, z) => {
+                let (x, z) = (
+                    x.satisfactions(index, satisfier)?,
+                    z.satisfactions(index, satisfier)?,
+                );
+                let one = Some(vec![vec![1]]);
+                let zero = Some(vec![vec![]]);
+                (
+                    smallest(concat(&one, &x.0), concat(&zero, &z.0)),
+                    smallest(concat(&one, &x.1), concat(&zero, &z.1)),
+                )
+            }
+            Node::Thresh(k, subs) => {
+                let witnesses = subs
+                    .iter()
+                    .map(|sub| sub.satisfactions(index, satisfier))
+                    .collect::<Result<Vec<_>>>()?;
+                (
+                    thresh_satisfaction(*k, &witnesses),
+                    thresh_satisfaction(0, &witnesses),
+                )
+            }
+            Node::Multi(k, keys) => {
+                // signatures in the same order as the keys, plus the dummy element
+                let mut witness = vec![vec![]];
+                for key in keys {
+                    if witness.len() > *k {
+                        break;
+                    }
+                    if let Some(sig) = signature(key)? {
+                        witness.push(sig);
+                    }
+                }
+
+                let sat = (witness.len() == k + 1).then_some(witness);
+                (sat, Some(vec![vec![]; k + 1]))
+            }
+            Node::MultiA(k, keys) => {
+                // the first key checks the top of the stack
+                let mut witness = Vec::with_capacity(keys.len());
+                let mut count = 0;
+                for key in keys {
+                    match signature(key)? {
+                        Some(sig) if count < *k => {
+                            count += 1;
+                            witness.push(sig);
+                        }
+                        _ => witness.push(vec![]),
+                    }
+                }
+                witness.reverse();
+
+                let sat = (count == *k).then_some(witness);
+                (sat, Some(vec![vec![]; keys.len()]))
+            }
+            Node::Alt(x) | Node::Swap(x) | Node::Check(x) | Node::ZeroNotEqual(x) => {
+                x.satisfactions(index, satisfier)?
+            }
+            Node::DupIf(x) => {
+                let (sat, _) = x.satisfactions(index, satisfier)?;
+                (concat(&Some(vec![vec![1]]), &sat), Some(vec![vec![]]))
+            }
+            Node::Verify(x) => (x.satisfactions(index, satisfier)?.0, None),
+            Node::NonZero(x) => (x.satisfactions(index, satisfier)?.0, Some(vec![vec![]])),
+        })
+    }
+
+    /// Abstract spending policy of the script, ignoring how it's encoded
+    pub fn lift(&self) -> Policy {
+        let lift2 = |k: usize, x: &Miniscript, y: &Miniscript| {
+            Policy::Threshold(k, vec![x.lift(), y.lift()])
+        };
+
+        match &self.node {
+            Node::False => Policy::Unsatisfiable,
+            Node::True => Policy::Trivial,
+            Node::PkK(key) | Node::PkH(key) => Policy::Key(key.clone()),
+            Node::Older(n) => Policy::Older(*n),
+            Node::After(n) => Policy::After(*n),
+            Node::Hash(lock) => Policy::Hash(*lock),
+            Node::AndOr(x, y, z) => Policy::Threshold(
+                1,
+                vec![Policy::Threshold(2, vec![x.lift(), y.lift()]), z.lift()],
+            ),
+            Node::AndV(x, y) | Node::AndB(x, y) => lift2(2, x, y),
+            Node::OrB(x, z) | Node::OrC(x, z) | Node::OrD(x, z) | Node::OrI(x, z) => lift2(1, x, z),
+            Node::Thresh(k, subs) => {
+                Policy::Threshold(*k, subs.iter().map(|sub| sub.lift()).collect())
+            }
+            Node::Multi(k, keys) | Node::MultiA(k, keys) => {
+                Policy::Threshold(*k, keys.iter().cloned().map(Policy::Key).collect())
+            }
+            Node::Alt(x)
+            | Node::Swap(x)
+            | Node::Check(x)
+            | Node::DupIf(x)
+            | Node::Verify(x)
+            | Node::NonZero(x)
+            | Node::ZeroNotEqual(x) => x.lift(),
+        }
+    }
+
+    pub(crate) fn map_keys<F>(&self, map: &F) -> Result<Self>
+    where
+        F: Fn(&DescriptorKey) -> Result<DescriptorKey>,
+    {
+        let sub = |x: &Miniscript| -> Result<Box<Miniscript>> { Ok(Box::new(x.map_keys(map)?)) };
+
+        let node = match &self.node {
+            Node::PkK(key) => Node::PkK(map(key)?),
+            Node::PkH(key) => Node::PkH(map(key)?),
+            Node::AndOr(x, y, z) => Node::AndOr(sub(x)?, sub(y)?, sub(z)?),
+            Node::AndV(x, y) => Node::AndV(sub(x)?, sub(y)?),
+            Node::AndB(x, y) => Node::AndB(sub(x)?, sub(y)?),
+            Node::OrB(x, z) => Node::OrB(sub(x)?, sub(z)?),
+            Node::OrC(x, z) => Node::OrC(sub(x)?, sub(z)?),
+            Node::OrD(x, z) => Node::OrD(sub(x)?, sub(z)?),
+            Node::OrI(x, z) => Node::OrI(sub(x)?, sub(z)?),
+            Node::Thresh(k, subs) => Node::Thresh(
+                *k,
+                subs.iter()
+                    .map(|x| x.map_keys(map))
+                    .collect::<Result<_>>()?,
+            ),
+            Node::Multi(k, keys) => Node::Multi(*k, keys.iter().map(map).collect::<Result<_>>()?),
+            Node::MultiA(k, keys) => Node::MultiA(*k, keys.iter().map(map).collect::<Result<_>>()?),
+            Node::Alt(x) => Node::Alt(sub(x)?),
+            Node::Swap(x) => Node::Swap(sub(x)?),
+            Node::Check(x) => Node::Check(sub(x)?),
+            Node::DupIf(x) => Node::DupIf(sub(x)?),
+            Node::Verify(x) => Node::Verify(sub(x)?),
+            Node::NonZero(x) => Node::NonZero(sub(x)?),
+            Node::ZeroNotEqual(x) => Node::ZeroNotEqual(sub(x)?),
+            node => node.clone(),
+        };
+
+        Ok(Self {
+            node,
+            ty: self.ty,
+            context: self.context,
+        })
+    }
+
+    /// Wrapper letters and the fragment they apply to, undoing the sugar
+    fn display_parts(&self) -> (String, String) {
+        let prepend = |letter: char, x: &Miniscript| {
+            let (wrappers, fragment) = x.display_parts();
+            (format!("{}{}", letter, wrappers), fragment)
+        };
+
+        match &self.node {
+            Node::Check(x) => match &x.node {
+                Node::PkK(key) => (String::new(), format!("pk({})", key)),
+                Node::PkH(key) => (String::new(), format!("pkh({})", key)),
+                _ => prepend('c', x),
+            },
+            Node::AndV(x, y) if y.node == Node::True => prepend('t', x),
+            Node::OrI(x, z) if x.node == Node::False => prepend('l', z),
+            Node::OrI(x, z) if z.node == Node::False => prepend('u', x),
+            Node::Alt(x) => prepend('a', x),
+            Node::Swap(x) => prepend('s', x),
+            Node::DupIf(x) => prepend('d', x),
+            Node::Verify(x) => prepend('v', x),
+            Node::NonZero(x) => prepend('j', x),
+            Node::ZeroNotEqual(x) => prepend('n', x),
+            _ => (String::new(), self.fragment_string()),
+        }
+    }
+
+    fn fragment_string(&self) -> String {
+        let join = |subs: &[&Miniscript]| {
+            subs.iter()
+                .map(|sub| sub.to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        let join_keys = |keys: &[DescriptorKey]| {
+            keys.iter()
+                .map(|key| key.to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+
+        match &self.node {
+            Node::False => "0".into(),
+            Node::True => "1".into(),
+            Node::PkK(key) => format!("pk_k({})", key),
+            Node::PkH(key) => format!("pk_h({})", key),
+            Node::Older(n) => format!("older({})", n),
+            Node::After(n) => format!("after({})", n),
+            Node::Hash(lock) => lock.to_string(),
+            Node::AndOr(x, y, z) if z.node == Node::False => format!("and_n({})", join(&[x, y])),
+            Node::AndOr(x, y, z) => format!("andor({})", join(&[x, y, z])),
+            Node::AndV(x, y) => format!("and_v({})", join(&[x, y])),
+            Node::AndB(x, y) => format!("and_b({})", join(&[x, y])),
+            Node::OrB(x, z) => format!("or_b({})", join(&[x, z])),
+            Node::OrC(x, z) => format!("or_c({})", join(&[x, z])),
+            Node::OrD(x, z) => format!("or_d({})", join(&[x, z])),
+            Node::OrI(x, z) => format!("or_i({})", join(&[x, z])),
+            Node::Thresh(k, subs) => {
+                let subs: Vec<_> = subs.iter().collect();
+                format!("thresh({},{})", k, join(&subs))
+            }
+            Node::Multi(k, keys) => format!("multi({},{})", k, join_keys(keys)),
+            Node::MultiA(k, keys) => format!("multi_a({},{})", k, join_keys(keys)),
+            _ => unreachable!(), // wrappers are handled by `display_parts`
+        }
+    }
+}
+
+impl Display for Miniscript {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        match self.display_parts() {
+            (wrappers, fragment) if wrappers.is_empty() => write!(fmt, "{}", fragment),
+            (wrappers, fragment) => write!(fmt, "{}:{}", wrappers, fragment),
+        }
+    }
+}
+
+/// Semantic policy a miniscript enforces (`and` and `or` being thresholds)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Policy {
+    Unsatisfiable,
+    Trivial,
+    Key(DescriptorKey),
+    Older(u32),
+    After(u32),
+    Hash(HashLock),
+    Threshold(usize, Vec<Policy>),
+}
+
+impl Display for Policy {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Unsatisfiable => write!(fmt, "UNSATISFIABLE"),
+            Self::Trivial => write!(fmt, "TRIVIAL"),
+            Self::Key(key) => write!(fmt, "pk({})", key),
+            Self::Older(n) => write!(fmt, "older({})", n),
+            Self::After(n) => write!(fmt, "after({})", n),
+            Self::Hash(lock) => write!(fmt, "{}", lock),
+            Self::Threshold(k, subs) => {
+                match *k {
+                    k if k == subs.len() => write!(fmt, "and(")?,
+                    1 => write!(fmt, "or(")?,
+                    k => write!(fmt, "thresh({},", k)?,
+                }
+                for (i, sub) in subs.iter().enumerate() {
+                    if i > 0 {
+                        write!(fmt, ",")?;
+                    }
+                    write!(fmt, "{}", sub)?;
+                }
+                write!(fmt, ")")
+            }
+        }
+    }
+}
+
+fn concat(first: &Option<Witness>, second: &Option<Witness>) -> Option<Witness> {
+    Some([first.as_ref()?.as_slice(), second.as_ref()?.as_slice()].concat())
+}
+
+fn witness_size(witness: &[Vec<u8>]) -> usize {
+    witness.iter().map(|element| 1 + element.len()).sum()
+}
+
+fn smallest(a: Option<Witness>, b: Option<Witness>) -> Option<Witness> {
+    match (a, b) {
+        (Some(a), Some(b)) if witness_size(&b) < witness_size(&a) => Some(b),
+        (Some(a), _) => Some(a),
+        (None, b) => b,
+    }
+}
+
+/// Satisfy exactly `k` subexpressions and dissatisfy the rest, picking the cheapest
+fn thresh_satisfaction(
+    k: usize,
+    witnesses: &[(Option<Witness>, Option<Witness>)],
+) -> Option<Witness> {
+    let mut costs: Vec<_> = witnesses
+        .iter()
+        .enumerate()
+        .filter_map(|(i, (sat, dissat))| {
+            let cost =
+                witness_size(sat.as_ref()?) as isize - witness_size(dissat.as_ref()?) as isize;
+            Some((cost, i))
+        })
+        .collect();
+    costs.sort_unstable();
+    if costs.len() < k {
+        return None;
+    }
+
+    let satisfied: Vec<_> = costs[..k].iter().map(|(_, i)| *i).collect();
+    let mut witness = Vec::new();
+    for (i, (sat, dissat)) in witnesses.iter().enumerate().rev() {
+        let part = if satisfied.contains(&i) { sat } else { dissat };
+        witness.extend(part.clone()?);
+    }
+
+    Some(witness)
+}
+
+fn type_check(node: &Node, context: ScriptContext) -> Result<Type> {
+    let err = |msg| Err(Error::InvalidMiniscript(msg));
+    let is_bdu = |ty: Type| ty.is(Base::B) && ty.dissatisfiable && ty.unit;
+    let same_base = |x: Type, y: Type| x.base == y.base && x.base != Base::W;
+
+    let ty = match node {
+        Node::False => Type {
+            zero_arg: true,
+            dissatisfiable: true,
+            unit: true,
+            ..Type::new(Base::B)
+        },
+        Node::True => Type {
+            zero_arg: true,
+            unit: true,
+            ..Type::new(Base::B)
+        },
+        Node::PkK(_) => Type {
+            one_arg: true,
+            non_zero: true,
+            dissatisfiable: true,
+            unit: true,
+            ..Type::new(Base::K)
+        },
+        Node::PkH(_) => Type {
+            non_zero: true,
+            dissatisfiable: true,
+            unit: true,
+            ..Type::new(Base::K)
+        },
+        Node::Older(n) | Node::After(n) => {
+            if *n == 0 || *n >= 0x8000_0000 {
+                return err("timelock out of range");
+            }
+            Type {
+                zero_arg: true,
+                ..Type::new(Base::B)
+            }
+        }
+        Node::Hash(_) => Type {
+            one_arg: true,
+            non_zero: true,
+            dissatisfiable: true,
+            unit: true,
+            ..Type::new(Base::B)
+        },
+        Node::AndOr(x, y, z) => {
+            let (x, y, z) = (x.ty, y.ty, z.ty);
+            if !is_bdu(x) || !same_base(y, z) {
+                return err("andor() expects Bdu and two of type B, K or V");
+            }
+            Type {
+                zero_arg: x.zero_arg && y.zero_arg && z.zero_arg,
+                one_arg: (x.zero_arg && y.one_arg && z.one_arg)
+                    || (x.one_arg && y.zero_arg && z.zero_arg),
+                dissatisfiable: z.dissatisfiable,
+                unit: y.unit && z.unit,
+                ..Type::new(y.base)
+            }
+        }
+        Node::AndV(x, y) => {
+            let (x, y) = (x.ty, y.ty);
+            if !x.is(Base::V) || y.is(Base::W) {
+                return err("and_v() expects V and one of type B, K or V");
+            }
+            Type {
+                zero_arg: x.zero_arg && y.zero_arg,
+                one_arg: (x.zero_arg && y.one_arg) || (x.one_arg && y.zero_arg),
+                non_zero: x.non_zero || (x.zero_arg && y.non_zero),
+                unit: y.unit,
+                ..Type::new(y.base)
+            }
+        }
+        Node::AndB(x, y) => {
+            let (x, y) = (x.ty, y.ty);
+            if !x.is(Base::B) || !y.is(Base::W) {
+                return err("and_b() expects B and W");
+            }
+            Type {
+                zero_arg: x.zero_arg && y.zero_arg,
+                one_arg: (x.zero_arg && y.one_arg) || (x.one_arg && y.zero_arg),
+                non_zero: x.non_zero || (x.zero_arg && y.non_zero),
+                dissatisfiable: x.dissatisfiable && y.dissatisfiable,
+                unit: true,
+                ..Type::new(Base::B)
+            }
+        }
+        Node::OrB(x, z) => {
+            let (x, z) = (x.ty, z.ty);
+            if !x.is(Base::B) || !x.dissatisfiable || !z.is(Base::W) || !z.dissatisfiable {
+                return err("or_b() expects Bd and Wd");
+            }
+            Type {
+                zero_arg: x.zero_arg && z.zero_arg,
+                one_arg: (x.zero_arg && z.one_arg) || (x.one_arg && z.zero_arg),
+                dissatisfiable: true,
+                unit: true,
+                ..Type::new(Base::B)
+            }
+        }
+        Node::OrC(x, z) => {
+            let (x, z) = (x.ty, z.ty);
+            if !is_bdu(x) || !z.is(Base::V) {
+                return err("or_c() expects Bdu and V");
+            }
+            Type {
+                zero_arg: x.zero_arg && z.zero_arg,
+                one_arg: x.one_arg && z.zero_arg,
+                ..Type::new(Base::V)
+            }
+        }
+        Node::OrD(x, z) => {
+            let (x, z) = (x.ty, z.ty);
+            if !is_bdu(x) || !z.is(Base::B) {
+                return err("or_d() expects Bdu and B");
+            }
+            Type {
+                zero_arg: x.zero_arg && z.zero_arg,
+                one_arg: x.one_arg && z.zero_arg,
+                dissatisfiable: z.dissatisfiable,
+                unit: z.unit,
+                ..Type::new(Base::B)
+            }
+        }
+        Node::OrI(x, z) => {
+            let (x, z) = (x.ty, z.ty);
+            if !same_base(x, z) {
+                return err("or_i() expects two of type B, K or V");
+            }
+            Type {
+                one_arg: x.zero_arg && z.zero_arg,
+                dissatisfiable: x.dissatisfiable || z.dissatisfiable,
+                unit: x.unit && z.unit,
+                ..Type::new(x.base)
+            }
+        }
+        Node::Thresh(k, subs) => {
+            if *k == 0 || *k > subs.len() {
+                return err("invalid threshold");
+            }
+
+            let first_is_bdu = is_bdu(subs[0].ty);
+            let rest_are_wdu = subs[1..]
+                .iter()
+                .all(|sub| sub.ty.is(Base::W) && sub.ty.dissatisfiable && sub.ty.unit);
+            if !first_is_bdu || !rest_are_wdu {
+                return err("thresh() expects Bdu followed by Wdu");
+            }
+
+            let args: Vec<_> = subs.iter().filter(|sub| !sub.ty.zero_arg).collect();
+            Type {
+                zero_arg: args.is_empty(),
+                one_arg: args.len() == 1 && args[0].ty.one_arg,
+                dissatisfiable: true,
+                unit: true,
+                ..Type::new(Base::B)
+            }
+        }
+        Node::Multi(k, keys) => {
+            if context == ScriptContext::Tap {
+                return err("multi() is not allowed in tapscript, use multi_a()");
+            }
+            if keys.is_empty() || keys.len() > 20 || *k == 0 || *k > keys.len() {
+                return err("invalid multisig threshold or number of keys");
+            }
+            Type {
+                non_zero: true,
+                dissatisfiable: true,
+                unit: true,
+                ..Type::new(Base::B)
+            }
+        }
+        Node::MultiA(k, keys) => {
+            if context == ScriptContext::Segwitv0 {
+                return err("multi_a() is only allowed in tapscript");
+            }
+            if keys.is_empty() || *k == 0 || *k > keys.len() {
+                return err("invalid multisig threshold or number of keys");
+            }
+            Type {
+                dissatisfiable: true,
+                unit: true,
+                ..Type::new(Base::B)
+            }
+        }
+        Node::Alt(x) => {
+            if !x.ty.is(Base::B) {
+                return err("a: expects B");
+            }
+            Type {
+                dissatisfiable: x.ty.dissatisfiable,
+                unit: x.ty.unit,
+                ..Type::new(Base::W)
+            }
+        }
+        Node::Swap(x) => {
+            if !x.ty.is(Base::B) || !x.ty.one_arg {
+                return err("s: expects Bo");
+            }
+            Type {
+                dissatisfiable: x.ty.dissatisfiable,
+                unit: x.ty.unit,
+                ..Type::new(Base::W)
+            }
+        }
+        Node::Check(x) => {
+            if !x.ty.is(Base::K) {
+                return err("c: expects K");
+            }
+            Type {
+                one_arg: x.ty.one_arg,
+                non_zero: x.ty.non_zero,
+                dissatisfiable: x.ty.dissatisfiable,
+                unit: true,
+                ..Type::new(Base::B)
+            }
+        }
+        Node::DupIf(x) => {
+            if !x.ty.is(Base::V) || !x.ty.zero_arg {
+                return err("d: expects Vz");
+            }
+            // MINIMALIF is consensus in tapscript, only policy in segwit v0
+            Type {
+                one_arg: true,
+                non_zero: true,
+                dissatisfiable: true,
+                unit: context == ScriptContext::Tap,
+                ..Type::new(Base::B)
+            }
+        }
+        Node::Verify(x) => {
+            if !x.ty.is(Base::B) {
+                return err("v: expects B");
+            }
+            Type {
+                zero_arg: x.ty.zero_arg,
+                one_arg: x.ty.one_arg,
+                non_zero: x.ty.non_zero,
+                ..Type::new(Base::V)
+            }
+        }
+        Node::NonZero(x) => {
+            if !x.ty.is(Base::B) || !x.ty.non_zero {
+                return err("j: expects Bn");
+            }
+            Type {
+                one_arg: x.ty.one_arg,
+                non_zero: true,
+                dissatisfiable: true,
+                unit: x.ty.unit,
+                ..Type::new(Base::B)
+            }
+        }
+        Node::ZeroNotEqual(x) => {
+            if !x.ty.is(Base::B) {
+                return err("n: expects B");
+            }
+            Type {
+                zero_arg: x.ty.zero_arg,
+                one_arg: x.ty.one_arg,
+                non_zero: x.ty.non_zero,
+                dissatisfiable: x.ty.dissatisfiable,
+                unit: true,
+                ..Type::new(Base::B)
+            }
+        }
+    };
+
+    Ok(ty)
+}
+
+fn parse_fragment(expr: &str, context: ScriptContext) -> Result<Miniscript> {
+    let colon = expr.find(':');
+    let paren = expr.find('(');
+    if let Some(colon) = colon.filter(|colon| paren.is_none_or(|paren| *colon < paren)) {
+        let mut miniscript = parse_fragment(&expr[colon + 1..], context)?;
+        for wrapper in expr[..colon].chars().rev() {
+            miniscript = wrap(wrapper, miniscript, context)?;
+        }
+        return Ok(miniscript);
+    }
+
+    let new = |node| Miniscript::new(node, context);
+    let sub = |expr| -> Result<Box<Miniscript>> { Ok(Box::new(parse_fragment(expr, context)?)) };
+    let key = |expr| match context {
+        ScriptContext::Segwitv0 => parse_key(expr, super::Context::Wsh),
+        ScriptContext::Tap => parse_tap_key(expr),
+    };
+
+    match expr {
+        "0" => return new(Node::False),
+        "1" => return new(Node::True),
+        _ => {}
+    }
+
+    let (name, args) = split_call(expr)?;
+    let args = split_args(args)?;
+    match (name, args.as_slice()) {
+        ("pk_k", [k]) => new(Node::PkK(key(k)?)),
+        ("pk_h", [k]) => new(Node::PkH(key(k)?)),
+        ("pk", [k]) => new(Node::Check(Box::new(new(Node::PkK(key(k)?))?))),
+        ("pkh", [k]) => new(Node::Check(Box::new(new(Node::PkH(key(k)?))?))),
+        ("older", [n]) => new(Node::Older(parse_number(n)?)),
+        ("after", [n]) => new(Node::After(parse_number(n)?)),
+        ("sha256", [h]) => new(Node::Hash(HashLock::Sha256(parse_hash(h)?))),
+        ("hash256", [h]) => new(Node::Hash(HashLock::Hash256(parse_hash(h)?))),
+        ("ripemd160", [h]) => new(Node::Hash(HashLock::Ripemd160(parse_hash(h)?))),
+        ("hash160", [h]) => new(Node::Hash(HashLock::Hash160(parse_hash(h)?))),
+        ("andor", [x, y, z]) => new(Node::AndOr(sub(x)?, sub(y)?, sub(z)?)),
+        ("and_n", [x, y]) => new(Node::AndOr(sub(x)?, sub(y)?, sub("0")?)),
+        ("and_v", [x, y]) => new(Node::AndV(sub(x)?, sub(y)?)),
+        ("and_b", [x, y]) => new(Node::AndB(sub(x)?, sub(y)?)),
+        ("or_b", [x, z]) => new(Node::OrB(sub(x)?, sub(z)?)),
+        ("or_c", [x, z]) => new(Node::OrC(sub(x)?, sub(z)?)),
+        ("or_d", [x, z]) => new(Node::OrD(sub(x)?, sub(z)?)),
+        ("or_i", [x, z]) => new(Node::OrI(sub(x)?, sub(z)?)),
+        ("thresh", [k, subs @ ..]) => new(Node::Thresh(
+            parse_number(k)? as usize,
+            subs.iter()
+                .map(|sub| parse_fragment(sub, context))
+                .collect::<Result<_>>()?,
+        )),
+        ("multi", [k, keys @ ..]) => new(Node::Multi(
+            parse_number(k)? as usize,
+            keys.iter().map(|k| key(k)).collect::<Result<_>>()?,
+        )),
+        ("multi_a", [k, keys @ ..]) => new(Node::MultiA(
+            parse_number(k)? as usize,
+            keys.iter().map(|k| key(k)).collect::<Result<_>>()?,
+        )),
+        _ => Err(Error::InvalidMiniscript("unknown fragment")),
+    }
+}
+
+fn wrap(wrapper: char, x: Miniscript, context: ScriptContext) -> Result<Miniscript> {
+    let x = Box::new(x);
+    let constant =
+        |node| -> Result<Box<Miniscript>> { Ok(Box::new(Miniscript::new(node, context)?)) };
+
+    let node = match wrapper {
+        'a' => Node::Alt(x),
+        's' => Node::Swap(x),
+        'c' => Node::Check(x),
+        'd' => Node::DupIf(x),
+        'v' => Node::Verify(x),
+        'j' => Node::NonZero(x),
+        'n' => Node::ZeroNotEqual(x),
+        't' => Node::AndV(x, constant(Node::True)?),
+        'l' => Node::OrI(constant(Node::False)?, x),
+        'u' => Node::OrI(x, constant(Node::False)?),
+        _ => return Err(Error::InvalidMiniscript("unknown wrapper")),
+    };
+
+    Miniscript::new(node, context)
+}
+
+fn parse_number(expr: &str) -> Result<u32> {
+    expr.parse()
+        .map_err(|_| Error::InvalidMiniscript("invalid number"))
+}
+
+fn parse_hash<const N: usize>(expr: &str) -> Result<[u8; N]> {
+    let mut hash = [0u8; N];
+    hex::decode_to_slice(expr, &mut hash).map_err(|_| Error::InvalidMiniscript("invalid hash"))?;
+    Ok(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secp256k1::crypto::PrivateKey;
+
+    const A: &str = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+    const B: &str = "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+
+    fn parse(expr: &str) -> Miniscript {
+        let expr = expr.replace("A", A).replace("B", B);
+        let miniscript = Miniscript::parse(&expr, ScriptContext::Segwitv0).unwrap();
+        assert_eq!(miniscript.to_string(), expr);
+        miniscript
+    }
+
+    struct Signer {
+        key: PublicKey,
+        older: u32,
+    }
+
+    impl Satisfier for Signer {
+        fn lookup_signature(&self, key: &PublicKey) -> Option<Vec<u8>> {
+            (key == &self.key).then(|| vec![0x30; 72])
+        }
+
+        fn check_older(&self, n: u32) -> bool {
+            n <= self.older
+        }
+    }
+
+    #[test]
+    fn compile_fragments() {
+        let miniscript = parse("or_d(pk(A),and_v(v:pk(B),older(144)))");
+        let script = miniscript.script_pubkey(0).unwrap();
+        let expected = format!("21{}ac736421{}ad029000b268", A, B);
+        assert_eq!(hex::encode(script), expected);
+
+        let hash = "6c60f404f8167a38fc70eaf8aa17ac351023bef86bcb9d1086a19afe95bd5333";
+        let miniscript = parse(&format!("and_v(v:sha256({}),pk(A))", hash));
+        let script = miniscript.script_pubkey(0).unwrap();
+        let expected = format!("82012088a820{}8821{}ac", hash, A);
+        assert_eq!(hex::encode(script), expected);
+
+        // sugar and wrappers roundtrip
+        parse("thresh(2,pk(A),s:pk(B),sln:older(144))");
+        parse("and_n(pk(A),l:after(1000))");
+        parse("andor(pk(A),tv:pkh(B),older(10))");
+        assert_eq!(
+            Miniscript::parse(&format!("c:pk_k({})", A), ScriptContext::Segwitv0)
+                .unwrap()
+                .to_string(),
+            format!("pk({})", A)
+        );
+    }
+
+    #[test]
+    fn type_checking() {
+        let ty = parse("thresh(2,pk(A),s:pk(B),sln:older(144))").ty();
+        assert_eq!(ty.base, Base::B);
+        assert!(ty.dissatisfiable && ty.unit);
+
+        let invalid = [
+            "pk_k(A)",
+            "and_v(pk(A),pk(B))",
+            "or_b(pk(A),pk(B))",
+            "thresh(3,pk(A),s:pk(B))",
+            "older(0)",
+            "multi_a(1,A,B)",
+            "v:pk(A)",
+            "foo(A)",
+        ];
+        for expr in &invalid {
+            let expr = expr.replace("A", A).replace("B", B);
+            assert!(Miniscript::parse(&expr, ScriptContext::Segwitv0).is_err());
+        }
+
+        let multi = format!("multi(1,{},{})", A, B);
+        assert!(Miniscript::parse(&multi, ScriptContext::Tap).is_err());
+        let multi_a = format!("multi_a(1,{},{})", A, B);
+        let miniscript = Miniscript::parse(&multi_a, ScriptContext::Tap).unwrap();
+        let expected = format!("20{}ac20{}ba519c", &A[2..], &B[2..]);
+        assert_eq!(hex::encode(miniscript.script_pubkey(0).unwrap()), expected);
+    }
+
+    #[test]
+    fn satisfy_and_analyze() {
+        let miniscript = parse("or_d(pk(A),and_v(v:pk(B),older(144)))");
+        assert_eq!(miniscript.max_satisfaction_size(), Some(1 + 74));
+
+        let signer = Signer {
+            key: PrivateKey::new(2usize).public_key().clone(),
+            older: 144,
+        };
+        let witness = miniscript.satisfy(0, &signer).unwrap();
+        assert_eq!(witness, vec![vec![0x30; 72], vec![]]);
+
+        let too_early = Signer {
+            older: 100,
+            ..signer
+        };
+        assert!(miniscript.satisfy(0, &too_early).is_err());
+
+        let signer = Signer {
+            key: PrivateKey::new(1usize).public_key().clone(),
+            older: 0,
+        };
+        assert_eq!(
+            miniscript.satisfy(0, &signer).unwrap(),
+            vec![vec![0x30; 72]]
+        );
+
+        let miniscript = parse("thresh(2,pk(A),s:pk(B),sln:older(144))");
+        let signer = Signer {
+            key: PrivateKey::new(2usize).public_key().clone(),
+            older: 144,
+        };
+        // older, signature of B and no signature for A
+        let witness = miniscript.satisfy(0, &signer).unwrap();
+        assert_eq!(witness, vec![vec![], vec![0x30; 72], vec![]]);
+        assert_eq!(miniscript.max_satisfaction_size(), Some(74 + 74 + 1 + 1));
+    }
+
+    #[test]
+    fn lift_policies() {
+        let miniscript = parse("or_d(pk(A),and_v(v:pk(B),older(144)))");
+        assert_eq!(
+            miniscript.lift().to_string(),
+            format!("or(pk({}),and(pk({}),older(144)))", A, B)
+        );
+
+        let miniscript = parse("thresh(2,pk(A),s:pk(B),sln:older(144))");
+        assert_eq!(
+            miniscript.lift().to_string(),
+            format!("thresh(2,pk({}),pk({}),or(UNSATISFIABLE,older(144)))", A, B)
+        );
+    }
+}
@@ -0,0 +1,764 @@
+pub mod checksum;
+pub mod key;
+pub mod miniscript;
+
+use std::fmt::{self, Display, Formatter};
+use std::ops::Range;
+use std::str::FromStr;
+
+use sha2::{Digest, Sha256};
+
+use crate::address::Address;
+use crate::consensus;
+use crate::network::Network;
+use crate::utils::{hash160, tagged_hash};
+use crate::{taproot, Error, Result};
+
+use self::key::{DescriptorKey, KeyKind};
+use self::miniscript::{Miniscript, ScriptContext};
+
+const OP_CHECKSIG: u8 = 0xac;
+const OP_CHECKMULTISIG: u8 = 0xae;
+
+/// Version of the leaves committed by `tr()` script trees (BIP342)
+const TAPSCRIPT_LEAF_VERSION: u8 = 0xc0;
+
+/// `multi()`/`sortedmulti()` expression (BIP383)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Multi {
+    pub(crate) threshold: usize,
+    pub(crate) keys: Vec<DescriptorKey>,
+    pub(crate) sorted: bool,
+}
+
+impl Multi {
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    pub fn keys(&self) -> &[DescriptorKey] {
+        &self.keys
+    }
+
+    /// Whether the keys are sorted in the script (`sortedmulti()`)
+    pub fn is_sorted(&self) -> bool {
+        self.sorted
+    }
+
+    fn script(&self, index: u32) -> Result<Vec<u8>> {
+        let mut keys = self
+            .keys
+            .iter()
+            .map(|key| serialize_key(key, index))
+            .collect::<Result<Vec<_>>>()?;
+        if self.sorted {
+            keys.sort();
+        }
+
+        let mut script = Vec::new();
+        push_int(&mut script, self.threshold);
+        for key in &keys {
+            push_slice(&mut script, key);
+        }
+        push_int(&mut script, keys.len());
+        script.push(OP_CHECKMULTISIG);
+        Ok(script)
+    }
+}
+
+/// Script tree of a `tr()` descriptor, leaves are tapscript miniscripts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TapTree {
+    Leaf(Miniscript),
+    Branch(Box<TapTree>, Box<TapTree>),
+}
+
+impl TapTree {
+    fn merkle_root(&self, index: u32) -> Result<[u8; 32]> {
+        match self {
+            Self::Leaf(miniscript) => {
+                let script = miniscript.script_pubkey(index)?;
+
+                let mut data = vec![TAPSCRIPT_LEAF_VERSION];
+                consensus::write_compact_size(&mut data, script.len() as u64)?;
+                data.extend(script);
+                Ok(tagged_hash("TapLeaf", data))
+            }
+
+            Self::Branch(left, right) => {
+                let left = left.merkle_root(index)?;
+                let right = right.merkle_root(index)?;
+                let (first, second) = if left <= right {
+                    (left, right)
+                } else {
+                    (right, left)
+                };
+                Ok(tagged_hash("TapBranch", [first, second].concat()))
+            }
+        }
+    }
+
+    fn keys(&self) -> Vec<&DescriptorKey> {
+        match self {
+            Self::Leaf(miniscript) => miniscript.keys(),
+            Self::Branch(left, right) => {
+                let mut keys = left.keys();
+                keys.extend(right.keys());
+                keys
+            }
+        }
+    }
+}
+
+impl Display for TapTree {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Leaf(miniscript) => write!(fmt, "{}", miniscript),
+            Self::Branch(left, right) => write!(fmt, "{{{},{}}}", left, right),
+        }
+    }
+}
+
+/// An output descriptor (BIP380 to BIP386), `sh()` and `wsh()` wrap another descriptor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Descriptor {
+    Pk(DescriptorKey),
+    Pkh(DescriptorKey),
+    Wpkh(DescriptorKey),
+    Sh(Box<Descriptor>),
+    Wsh(Box<Descriptor>),
+    Multi(Multi),
+    /// Any other miniscript, only inside `wsh()`
+    Miniscript(Miniscript),
+    Tr {
+        internal_key: DescriptorKey,
+        tree: Option<TapTree>,
+    },
+}
+
+/// Where an expression appears, each one restricts what can be nested
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Context {
+    Top,
+    Sh,
+    Wsh,
+}
+
+impl Descriptor {
+    fn keys(&self) -> Vec<&DescriptorKey> {
+        match self {
+            Self::Pk(key) | Self::Pkh(key) | Self::Wpkh(key) => vec![key],
+            Self::Sh(inner) | Self::Wsh(inner) => inner.keys(),
+            Self::Multi(multi) => multi.keys.iter().collect(),
+            Self::Miniscript(miniscript) => miniscript.keys(),
+            Self::Tr { internal_key, tree } => {
+                let mut keys = vec![internal_key];
+                if let Some(tree) = tree {
+                    keys.extend(tree.keys());
+                }
+                keys
+            }
+        }
+    }
+
+    /// Whether this descriptor describes a range of outputs
+    pub fn is_wildcard(&self) -> bool {
+        self.keys().iter().any(|key| key.is_wildcard())
+    }
+
+    /// Whether any of the keys holds private key material
+    pub fn has_secret(&self) -> bool {
+        self.keys().iter().any(|key| key.has_secret())
+    }
+
+    /// The concrete script pubkey at `index`, ignored for non ranged descriptors
+    pub fn script_pubkey(&self, index: u32) -> Result<Vec<u8>> {
+        match self {
+            Self::Pk(key) => {
+                let mut script = Vec::with_capacity(67);
+                push_slice(&mut script, &serialize_key(key, index)?);
+                script.push(OP_CHECKSIG);
+                Ok(script)
+            }
+
+            // OP_DUP OP_HASH160 <20 bytes> OP_EQUALVERIFY OP_CHECKSIG
+            Self::Pkh(key) => {
+                let hash = hash160(serialize_key(key, index)?);
+                Ok([&[0x76, 0xa9, 0x14], &hash[..], &[0x88, OP_CHECKSIG]].concat())
+            }
+
+            // OP_0 <20 bytes>
+            Self::Wpkh(key) => {
+                let hash = hash160(serialize_key(key, index)?);
+                Ok([&[0x00, 0x14], &hash[..]].concat())
+            }
+
+            // OP_HASH160 <20 bytes> OP_EQUAL
+            Self::Sh(inner) => {
+                let hash = hash160(inner.script_pubkey(index)?);
+                Ok([&[0xa9, 0x14], &hash[..], &[0x87]].concat())
+            }
+
+            // OP_0 <32 bytes>
+            Self::Wsh(inner) => {
+                let hash = Sha256::digest(&inner.script_pubkey(index)?);
+                Ok([&[0x00, 0x20], &hash[..]].concat())
+            }
+
+            Self::Multi(multi) => multi.script(index),
+            Self::Miniscript(miniscript) => miniscript.script_pubkey(index),
+
+            // OP_1 <32 bytes>
+            Self::Tr { internal_key, tree } => {
+                let merkle_root = match tree {
+                    Some(tree) => Some(tree.merkle_root(index)?),
+                    None => None,
+                };
+                let internal_key = x_only(internal_key, index)?;
+                let (output_key, _) =
+                    taproot::tweak_public_key(&internal_key, merkle_root.as_ref())?;
+                Ok([&[0x51, 0x20], &output_key[..]].concat())
+            }
+        }
+    }
+
+    /// Script committed by `sh()`/`wsh()`, needed to spend their outputs
+    pub fn redeem_script(&self, index: u32) -> Result<Option<Vec<u8>>> {
+        match self {
+            Self::Sh(inner) | Self::Wsh(inner) => Ok(Some(inner.script_pubkey(index)?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Address of the output at `index`, bare `pk()` and `multi()` have none
+    pub fn address(&self, index: u32, network: Network) -> Result<Address> {
+        Address::from_script(self.script_pubkey(index)?, network)
+    }
+
+    /// Addresses of the outputs in the given index range
+    pub fn derive_addresses(&self, range: Range<u32>, network: Network) -> Result<Vec<Address>> {
+        range.map(|index| self.address(index, network)).collect()
+    }
+
+    /// Replace every key with the concrete public key at `index`
+    pub fn at_index(&self, index: u32) -> Result<Self> {
+        self.map_keys(&|key| key.at_index(index))
+    }
+
+    /// Replace any private key material with its public counterpart
+    pub fn to_public(&self) -> Self {
+        self.map_keys(&|key| Ok(key.to_public())).unwrap() // safe, never fails
+    }
+
+    fn map_keys<F>(&self, map: &F) -> Result<Self>
+    where
+        F: Fn(&DescriptorKey) -> Result<DescriptorKey>,
+    {
+        fn map_tree<F>(tree: &TapTree, map: &F) -> Result<TapTree>
+        where
+            F: Fn(&DescriptorKey) -> Result<DescriptorKey>,
+        {
+            match tree {
+                TapTree::Leaf(miniscript) => Ok(TapTree::Leaf(miniscript.map_keys(map)?)),
+                TapTree::Branch(left, right) => Ok(TapTree::Branch(
+                    Box::new(map_tree(left, map)?),
+                    Box::new(map_tree(right, map)?),
+                )),
+            }
+        }
+
+        Ok(match self {
+            Self::Pk(key) => Self::Pk(map(key)?),
+            Self::Pkh(key) => Self::Pkh(map(key)?),
+            Self::Wpkh(key) => Self::Wpkh(map(key)?),
+            Self::Sh(inner) => Self::Sh(Box::new(inner.map_keys(map)?)),
+            Self::Wsh(inner) => Self::Wsh(Box::new(inner.map_keys(map)?)),
+            Self::Multi(multi) => Self::Multi(Multi {
+                threshold: multi.threshold,
+                keys: multi.keys.iter().map(map).collect::<Result<_>>()?,
+                sorted: multi.sorted,
+            }),
+            Self::Miniscript(miniscript) => Self::Miniscript(miniscript.map_keys(map)?),
+            Self::Tr { internal_key, tree } => Self::Tr {
+                internal_key: map(internal_key)?,
+                tree: match tree {
+                    Some(tree) => Some(map_tree(tree, map)?),
+                    None => None,
+                },
+            },
+        })
+    }
+
+    fn write_without_checksum<W: fmt::Write>(&self, fmt: &mut W) -> fmt::Result {
+        match self {
+            Self::Pk(key) => write!(fmt, "pk({})", key),
+            Self::Pkh(key) => write!(fmt, "pkh({})", key),
+            Self::Wpkh(key) => write!(fmt, "wpkh({})", key),
+            Self::Sh(inner) => {
+                write!(fmt, "sh(")?;
+                inner.write_without_checksum(fmt)?;
+                write!(fmt, ")")
+            }
+            Self::Wsh(inner) => {
+                write!(fmt, "wsh(")?;
+                inner.write_without_checksum(fmt)?;
+                write!(fmt, ")")
+            }
+            Self::Multi(multi) => {
+                let name = if multi.sorted { "sortedmulti" } else { "multi" };
+                write!(fmt, "{}({}", name, multi.threshold)?;
+                for key in &multi.keys {
+                    write!(fmt, ",{}", key)?;
+                }
+                write!(fmt, ")")
+            }
+            Self::Miniscript(miniscript) => write!(fmt, "{}", miniscript),
+            Self::Tr { internal_key, tree } => match tree {
+                Some(tree) => write!(fmt, "tr({},{})", internal_key, tree),
+                None => write!(fmt, "tr({})", internal_key),
+            },
+        }
+    }
+
+    fn parse(expr: &str, context: Context) -> Result<Self> {
+        let (name, args) = match split_call(expr) {
+            Ok(call) => call,
+            Err(_) if context == Context::Wsh => {
+                return Ok(Self::Miniscript(Miniscript::parse(
+                    expr,
+                    ScriptContext::Segwitv0,
+                )?))
+            }
+            Err(err) => return Err(err),
+        };
+
+        match (name, context) {
+            ("pk", _) => Ok(Self::Pk(parse_key(args, context)?)),
+            ("pkh", _) => Ok(Self::Pkh(parse_key(args, context)?)),
+            ("wpkh", Context::Top) | ("wpkh", Context::Sh) => {
+                Ok(Self::Wpkh(parse_key(args, Context::Wsh)?))
+            }
+            ("sh", Context::Top) => Ok(Self::Sh(Box::new(Self::parse(args, Context::Sh)?))),
+            ("wsh", Context::Top) | ("wsh", Context::Sh) => {
+                Ok(Self::Wsh(Box::new(Self::parse(args, Context::Wsh)?)))
+            }
+            ("multi", _) => Ok(Self::Multi(parse_multi(args, false, context)?)),
+            ("sortedmulti", _) => Ok(Self::Multi(parse_multi(args, true, context)?)),
+            ("tr", Context::Top) => parse_tr(args),
+
+            ("wpkh", _) | ("sh", _) | ("wsh", _) | ("tr", _) => Err(Error::InvalidDescriptor(
+                "expression not allowed in this context",
+            )),
+            (_, Context::Wsh) => Ok(Self::Miniscript(Miniscript::parse(
+                expr,
+                ScriptContext::Segwitv0,
+            )?)),
+            _ => Err(Error::InvalidDescriptor("unknown expression")),
+        }
+    }
+}
+
+impl Display for Descriptor {
+    /// Always followed by the checksum, `{:#}` leaves it out
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        if fmt.alternate() {
+            return self.write_without_checksum(fmt);
+        }
+
+        let mut descriptor = String::new();
+        self.write_without_checksum(&mut descriptor)?;
+        let checksum = checksum::checksum(&descriptor).map_err(|_| fmt::Error)?;
+        write!(fmt, "{}#{}", descriptor, checksum)
+    }
+}
+
+impl FromStr for Descriptor {
+    type Err = Error;
+
+    /// The checksum is optional, but verified when present
+    fn from_str(descriptor: &str) -> Result<Self> {
+        let descriptor = checksum::strip_checksum(descriptor)?;
+        Self::parse(descriptor, Context::Top)
+    }
+}
+
+/// Split `name(args)` into its parts
+fn split_call(expr: &str) -> Result<(&str, &str)> {
+    let open = expr
+        .find('(')
+        .ok_or(Error::InvalidDescriptor("expected an expression"))?;
+    let args = expr[open + 1..]
+        .strip_suffix(')')
+        .ok_or(Error::InvalidDescriptor("unclosed parenthesis"))?;
+
+    Ok((&expr[..open], args))
+}
+
+/// Split at the commas not nested in parenthesis, brackets or braces
+fn split_args(args: &str) -> Result<Vec<&str>> {
+    let mut parts = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+
+    for (i, ch) in args.char_indices() {
+        match ch {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => {
+                depth = depth
+                    .checked_sub(1)
+                    .ok_or(Error::InvalidDescriptor("unbalanced brackets"))?
+            }
+            ',' if depth == 0 => {
+                parts.push(&args[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    if depth != 0 {
+        return Err(Error::InvalidDescriptor("unbalanced brackets"));
+    }
+
+    parts.push(&args[start..]);
+    Ok(parts)
+}
+
+/// Segwit scripts only allow compressed keys, x-only keys are only valid in `tr()`
+fn parse_key(expr: &str, context: Context) -> Result<DescriptorKey> {
+    let key: DescriptorKey = expr.parse()?;
+
+    if let KeyKind::XOnly { .. } = key.kind() {
+        return Err(Error::InvalidDescriptor("x-only keys only allowed in tr()"));
+    }
+    if context == Context::Wsh && !key.is_compressed() {
+        return Err(Error::UncompressedSegwitKey);
+    }
+
+    Ok(key)
+}
+
+fn parse_tap_key(expr: &str) -> Result<DescriptorKey> {
+    let key: DescriptorKey = expr.parse()?;
+    if !key.is_compressed() {
+        return Err(Error::UncompressedSegwitKey);
+    }
+
+    Ok(key)
+}
+
+fn parse_multi(args: &str, sorted: bool, context: Context) -> Result<Multi> {
+    let args = split_args(args)?;
+    let (threshold, keys) = args.split_first().unwrap(); // safe, never empty
+
+    let threshold: usize = threshold
+        .parse()
+        .map_err(|_| Error::InvalidDescriptor("invalid multisig threshold"))?;
+    let keys = keys
+        .iter()
+        .map(|key| parse_key(key, context))
+        .collect::<Result<Vec<_>>>()?;
+
+    // bare multisig is standard up to 3 keys, p2sh is limited by the 520 bytes push
+    let max_keys = match context {
+        Context::Top => 3,
+        Context::Sh => 15,
+        Context::Wsh => 20,
+    };
+
+    if keys.is_empty() || keys.len() > max_keys {
+        return Err(Error::InvalidDescriptor("invalid number of multisig keys"));
+    }
+    if threshold == 0 || threshold > keys.len() {
+        return Err(Error::InvalidDescriptor("invalid multisig threshold"));
+    }
+
+    Ok(Multi {
+        threshold,
+        keys,
+        sorted,
+    })
+}
+
+fn parse_tr(args: &str) -> Result<Descriptor> {
+    let args = split_args(args)?;
+    let tree = match args.len() {
+        1 => None,
+        2 => Some(parse_tree(args[1])?),
+        _ => {
+            return Err(Error::InvalidDescriptor(
+                "tr() expects at most two arguments",
+            ))
+        }
+    };
+
+    Ok(Descriptor::Tr {
+        internal_key: parse_tap_key(args[0])?,
+        tree,
+    })
+}
+
+fn parse_tree(expr: &str) -> Result<TapTree> {
+    if let Some(branches) = expr.strip_prefix('{') {
+        let branches = branches
+            .strip_suffix('}')
+            .ok_or(Error::InvalidDescriptor("unclosed brace"))?;
+
+        return match split_args(branches)?.as_slice() {
+            [left, right] => Ok(TapTree::Branch(
+                Box::new(parse_tree(left)?),
+                Box::new(parse_tree(right)?),
+            )),
+            _ => Err(Error::InvalidDescriptor("branches must have two children")),
+        };
+    }
+
+    Ok(TapTree::Leaf(Miniscript::parse(expr, ScriptContext::Tap)?))
+}
+
+fn serialize_key(key: &DescriptorKey, index: u32) -> Result<Vec<u8>> {
+    key.derive_public_key(index)?.serialize(key.is_compressed())
+}
+
+fn x_only(key: &DescriptorKey, index: u32) -> Result<[u8; 32]> {
+    let compressed = key.derive_public_key(index)?.serialize_compressed()?;
+    let mut x = [0u8; 32];
+    x.copy_from_slice(&compressed[1..]);
+    Ok(x)
+}
+
+/// Minimally encoded script number, OP_0 and OP_1 to OP_16 for small ones
+fn push_int(script: &mut Vec<u8>, n: usize) {
+    match n {
+        0 => script.push(0x00),
+        1..=16 => script.push(0x50 + n as u8),
+        _ => {
+            let mut bytes = n.to_le_bytes().to_vec();
+            while bytes.last() == Some(&0) {
+                bytes.pop();
+            }
+            // the most significant bit is the sign
+            if bytes.last().unwrap() & 0x80 != 0 {
+                bytes.push(0);
+            }
+            push_slice(script, &bytes);
+        }
+    }
+}
+
+/// Keys are always shorter than OP_PUSHDATA1
+fn push_slice(script: &mut Vec<u8>, data: &[u8]) {
+    script.push(data.len() as u8);
+    script.extend_from_slice(data);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bip32::ExtendedPublicKey;
+    use hex_literal::hex;
+
+    const XPUB: &str = "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8";
+
+    fn roundtrip(string: &str) -> Descriptor {
+        let descriptor: Descriptor = string.parse().unwrap();
+        assert_eq!(descriptor.to_string(), string);
+        descriptor
+    }
+
+    #[test]
+    fn single_key_descriptors() {
+        // BIP381 and BIP382 test vectors
+        let descriptor = roundtrip(
+            "pk(0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798)#gn28ywm7",
+        );
+        assert_eq!(
+            descriptor.script_pubkey(0).unwrap(),
+            hex!("210279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798ac")
+        );
+        assert!(descriptor.address(0, Network::Mainnet).is_err());
+
+        let descriptor = roundtrip(
+            "pkh(02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5)#8fhd9pwu",
+        );
+        assert_eq!(
+            descriptor.script_pubkey(0).unwrap(),
+            hex!("76a91406afd46bcdfd22ef94ac122aa11f241244a37ecc88ac")
+        );
+
+        let descriptor = roundtrip(
+            "wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)#8zl0zxma",
+        );
+        assert_eq!(
+            descriptor.script_pubkey(0).unwrap(),
+            hex!("00147dd65592d0ab2fe0d0257d571abf032cd9db93dc")
+        );
+
+        let descriptor = roundtrip(
+            "sh(wpkh(03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556))#qkrrc7je",
+        );
+        assert_eq!(
+            descriptor.script_pubkey(0).unwrap(),
+            hex!("a914cc6ffbc0bf31af759451068f90ba7a0272b6b33287")
+        );
+        assert_eq!(
+            descriptor.redeem_script(0).unwrap().unwrap(),
+            hex!("00147fda9cf020c16cacf529c87d8de89bfc70b8c9cb")
+        );
+    }
+
+    #[test]
+    fn multisig_descriptors() {
+        // BIP383 test vector
+        let descriptor = roundtrip("wsh(multi(2,03a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7,03774ae7f858a9411e5ef4246b70c65aac5649980be5c17891bbec17895da008cb,03d01115d548e7561b15c38f004d734633687cf4419620095bc5b0f47070afe85a))#en3tu306");
+        assert_eq!(
+            descriptor.script_pubkey(0).unwrap(),
+            hex!("0020773d709598b76c4e3b575c08aad40658963f9322affc0f8c28d1d9a68d0c944a")
+        );
+
+        let sorted = roundtrip("sh(sortedmulti(2,03acd484e2f0c7f65309ad178a9f559abde09796974c57e714c35f110dfc27ccbe,022f01e5e15cca351daff3843fb70f3c2f0a1bdd05e5af888a67784ef3e10a2a01))#qwx6n9lh");
+        let unsorted: Descriptor = "sh(multi(2,022f01e5e15cca351daff3843fb70f3c2f0a1bdd05e5af888a67784ef3e10a2a01,03acd484e2f0c7f65309ad178a9f559abde09796974c57e714c35f110dfc27ccbe))".parse().unwrap();
+        assert_eq!(
+            sorted.script_pubkey(0).unwrap(),
+            unsorted.script_pubkey(0).unwrap()
+        );
+
+        // threshold out of range, too many keys for bare multisig
+        let key = "03acd484e2f0c7f65309ad178a9f559abde09796974c57e714c35f110dfc27ccbe";
+        assert!(format!("multi(0,{})", key).parse::<Descriptor>().is_err());
+        assert!(format!("multi(2,{})", key).parse::<Descriptor>().is_err());
+        assert!(format!("multi(1,{0},{0},{0},{0})", key)
+            .parse::<Descriptor>()
+            .is_err());
+
+        let keys = vec![key; 20].join(",");
+        let descriptor: Descriptor = format!("wsh(multi(20,{}))", keys).parse().unwrap();
+        let script = descriptor.redeem_script(0).unwrap().unwrap();
+        assert_eq!(&script[..2], &[0x01, 20]);
+        assert_eq!(&script[script.len() - 3..], &[0x01, 20, OP_CHECKMULTISIG]);
+    }
+
+    #[test]
+    fn taproot_descriptors() {
+        // BIP386 test vector
+        let descriptor = roundtrip(
+            "tr(a34b99f22c790c4e36b2b3c2c35a36db06226e41c692fc82b8b56ac1c540c5bd)#dh4fyxrd",
+        );
+        assert_eq!(
+            descriptor.script_pubkey(0).unwrap(),
+            hex!("512077aab6e066f8a7419c5ab714c12c67d25007ed55a43cadcacb4d7a970a093f11")
+        );
+
+        let descriptor = roundtrip("tr(a34b99f22c790c4e36b2b3c2c35a36db06226e41c692fc82b8b56ac1c540c5bd,{pk(669b8afcec803a0d323e9a17f3ea8e68e8abe5a278020a929adbec52421adbd0),pk(0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798)})#hj37kvzw");
+        assert_eq!(
+            descriptor.script_pubkey(0).unwrap(),
+            hex!("51204f7a11a36e31cbe9410d54e23dd7c74017c99a5acd280c0048f32e54ba899fdb")
+        );
+        assert!(descriptor
+            .address(0, Network::Mainnet)
+            .unwrap()
+            .to_string()
+            .starts_with("bc1p"));
+
+        // x-only keys outside tr(), tr() nested, multi() leaves
+        assert!(
+            "pkh(a34b99f22c790c4e36b2b3c2c35a36db06226e41c692fc82b8b56ac1c540c5bd)"
+                .parse::<Descriptor>()
+                .is_err()
+        );
+        assert!(
+            "sh(tr(a34b99f22c790c4e36b2b3c2c35a36db06226e41c692fc82b8b56ac1c540c5bd))"
+                .parse::<Descriptor>()
+                .is_err()
+        );
+        assert!("tr(a34b99f22c790c4e36b2b3c2c35a36db06226e41c692fc82b8b56ac1c540c5bd,multi(1,669b8afcec803a0d323e9a17f3ea8e68e8abe5a278020a929adbec52421adbd0))"
+            .parse::<Descriptor>()
+            .is_err());
+    }
+
+    #[test]
+    fn ranged_descriptors() {
+        let descriptor: Descriptor = format!("wpkh([bd16bee5/84'/0'/0']{}/0/*)", XPUB)
+            .parse()
+            .unwrap();
+        assert!(descriptor.is_wildcard());
+        assert!(!descriptor.has_secret());
+
+        let addresses = descriptor.derive_addresses(0..3, Network::Mainnet).unwrap();
+        let xpub = ExtendedPublicKey::from_xpub(XPUB).unwrap();
+        for (index, address) in (0..3).zip(&addresses) {
+            let public_key = xpub.derive_path([0, index]).unwrap().public_key().clone();
+            assert_eq!(
+                address,
+                &Address::p2wpkh(&public_key, Network::Mainnet).unwrap()
+            );
+        }
+
+        let concrete = descriptor.at_index(2).unwrap();
+        assert!(!concrete.is_wildcard());
+        assert!(format!("{:#}", concrete).starts_with("wpkh([bd16bee5/84'/0'/0'/0/2]"));
+        assert_eq!(
+            concrete.script_pubkey(0).unwrap(),
+            descriptor.script_pubkey(2).unwrap()
+        );
+    }
+
+    #[test]
+    fn miniscript_descriptors() {
+        let key = "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+        let descriptor: Descriptor = format!("wsh(and_v(v:pk({}),older(144)))", key)
+            .parse()
+            .unwrap();
+        let witness_script = descriptor.redeem_script(0).unwrap().unwrap();
+        assert_eq!(hex::encode(&witness_script), format!("21{}ad029000b2", key));
+        assert_eq!(roundtrip(&descriptor.to_string()), descriptor);
+        assert!(descriptor
+            .address(0, Network::Mainnet)
+            .unwrap()
+            .to_string()
+            .starts_with("bc1q"));
+
+        // miniscript is only allowed inside wsh() and tr() leaves
+        assert!(format!("and_v(v:pk({}),older(144))", key)
+            .parse::<Descriptor>()
+            .is_err());
+        assert!(format!("sh(and_v(v:pk({}),older(144)))", key)
+            .parse::<Descriptor>()
+            .is_err());
+
+        let descriptor: Descriptor = format!(
+            "tr(a34b99f22c790c4e36b2b3c2c35a36db06226e41c692fc82b8b56ac1c540c5bd,{{pk({}),multi_a(1,{},669b8afcec803a0d323e9a17f3ea8e68e8abe5a278020a929adbec52421adbd0)}})",
+            key, key
+        )
+        .parse()
+        .unwrap();
+        assert_eq!(roundtrip(&descriptor.to_string()), descriptor);
+        assert!(descriptor
+            .script_pubkey(0)
+            .unwrap()
+            .starts_with(&[0x51, 0x20]));
+    }
+
+    #[test]
+    fn invalid_descriptors() {
+        let key = "02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9";
+        let uncompressed = "04a34b99f22c790c4e36b2b3c2c35a36db06226e41c692fc82b8b56ac1c540c5bda47213adca5f0578ddb89388f63fdaa61c558c55fc6e745d2b6d1157a54159fa";
+
+        assert!(format!("wpkh({})#8zl0zxmb", key)
+            .parse::<Descriptor>()
+            .is_err());
+        assert!(format!("wpkh({}", key).parse::<Descriptor>().is_err());
+        assert!(format!("foo({})", key).parse::<Descriptor>().is_err());
+        assert!(format!("sh(sh(pkh({})))", key)
+            .parse::<Descriptor>()
+            .is_err());
+        assert!(format!("wsh(wpkh({}))", key).parse::<Descriptor>().is_err());
+        assert!(format!("pkh({})", uncompressed)
+            .parse::<Descriptor>()
+            .is_ok());
+        assert!(matches!(
+            format!("wpkh({})", uncompressed).parse::<Descriptor>(),
+            Err(Error::UncompressedSegwitKey)
+        ));
+    }
+}
@@ -0,0 +1,232 @@
+use alloc::vec::Vec;
+
+use hmac::{Hmac, Mac, NewMac};
+use num_bigint::BigUint;
+use num_traits::Zero;
+use sha2::Sha256;
+
+use crate::curve::{CurveParams, Point};
+use crate::field::PrimeField;
+use crate::secp256k1::signature::Signature;
+use crate::utils::{prepend_padding, Chain};
+use crate::{Error, Result};
+
+// Generic (and slow) ECDSA over any curve described by `CurveParams`, the
+// `secp256k1` module has the optimized Bitcoin implementation.
+
+/// Public key of the given secret
+pub fn public_key<C: CurveParams>(secret: &BigUint) -> Result<Point<C::Field>> {
+    let secret = secret % C::order();
+    if secret.is_zero() {
+        return Err(Error::InvalidPrivateKey);
+    }
+
+    Ok(C::curve().mul(&C::generator(), secret))
+}
+
+/// Sign the given digest with a deterministic nonce (RFC 6979 with HMAC-SHA256),
+/// the resulting `s` isn't normalized.
+pub fn sign<C: CurveParams>(secret: &BigUint, digest: &[u8]) -> Result<Signature> {
+    let n = C::order();
+    let secret = secret % n;
+    if secret.is_zero() {
+        return Err(Error::InvalidPrivateKey);
+    }
+
+    let z = bits2int(digest, n.bits()) % n;
+    let mut extra = 0usize;
+
+    loop {
+        let k = deterministic_k(&secret, digest, n, extra);
+        let r = match C::curve().mul(&C::generator(), k.clone()) {
+            Point::Normal(x, _) => x.to_biguint() % n,
+            Point::AtInfinity => BigUint::zero(),
+        };
+
+        let k_inv = k.modpow(&(n - 2u8), n);
+        let s = (&z + &r * &secret) * k_inv % n;
+        if !r.is_zero() && !s.is_zero() {
+            return Ok(Signature::new(r, s));
+        }
+
+        // practically unreachable, try the next candidate nonce
+        extra += 1;
+    }
+}
+
+/// Verify a signature of the given digest
+pub fn verify<C: CurveParams>(
+    digest: &[u8],
+    signature: &Signature,
+    pub_key: &Point<C::Field>,
+) -> Result<bool> {
+    let n = C::order();
+    let (r, s) = (&signature.r, &signature.s);
+    if r.is_zero() || r >= n || s.is_zero() || s >= n {
+        return Ok(false);
+    }
+
+    let curve = C::curve();
+    match pub_key {
+        Point::Normal(x, y) if curve.contains(x, y) => {}
+        _ => return Err(Error::PointNotOnTheCurve),
+    }
+
+    let z = bits2int(digest, n.bits()) % n;
+    let s_inv = s.modpow(&(n - 2u8), n);
+    let u = z * &s_inv % n;
+    let v = r * s_inv % n;
+
+    let total = curve.add(&curve.mul(&C::generator(), u), &curve.mul(pub_key, v));
+
+    match total {
+        Point::Normal(x, _) => Ok(&(x.to_biguint() % n) == r),
+        Point::AtInfinity => Ok(false),
+    }
+}
+
+/// Leftmost `qlen` bits of the given bytes as an integer
+fn bits2int(bytes: &[u8], qlen: u64) -> BigUint {
+    let number = BigUint::from_bytes_be(bytes);
+    let blen = bytes.len() as u64 * 8;
+
+    if blen > qlen {
+        number >> (blen - qlen)
+    } else {
+        number
+    }
+}
+
+/// Big endian bytes, padded to the byte length of the order
+fn int2octets(number: &BigUint, rlen: usize) -> Vec<u8> {
+    prepend_padding(number.to_bytes_be(), rlen, 0).unwrap() // safe, lower than the order
+}
+
+/// Nonce generation from RFC 6979 (HMAC-SHA256) for a curve of order `n`, the
+/// `skip` first valid candidates are discarded.
+pub(crate) fn deterministic_k(
+    secret: &BigUint,
+    digest: &[u8],
+    n: &BigUint,
+    skip: usize,
+) -> BigUint {
+    type HmacSha256 = Hmac<Sha256>;
+
+    let qlen = n.bits();
+    let rlen = qlen.div_ceil(8) as usize;
+
+    let secret = int2octets(secret, rlen);
+    let digest = int2octets(&(bits2int(digest, qlen) % n), rlen);
+    let k = [0x00u8; 32];
+    let v = [0x01u8; 32];
+
+    let hmac = HmacSha256::new_varkey(&k).unwrap();
+    let k = hmac
+        .chain(&v)
+        .chain(&[0x00])
+        .chain(&secret)
+        .chain(&digest)
+        .finalize()
+        .into_bytes();
+
+    let hmac = HmacSha256::new_varkey(&k).unwrap();
+    let v = hmac.chain(&v).finalize().into_bytes();
+
+    let hmac = HmacSha256::new_varkey(&k).unwrap();
+    let mut k = hmac
+        .chain(&v)
+        .chain(&[0x01])
+        .chain(&secret)
+        .chain(&digest)
+        .finalize()
+        .into_bytes();
+
+    let hmac = HmacSha256::new_varkey(&k).unwrap();
+    let mut v = hmac.chain(&v).finalize().into_bytes();
+
+    let mut skip = skip;
+    loop {
+        let mut t = Vec::with_capacity(rlen);
+        while t.len() < rlen {
+            let hmac = HmacSha256::new_varkey(&k).unwrap();
+            v = hmac.chain(&v).finalize().into_bytes();
+            t.extend_from_slice(&v);
+        }
+
+        let candidate = bits2int(&t, qlen);
+        if !candidate.is_zero() && &candidate < n {
+            if skip == 0 {
+                return candidate;
+            }
+
+            skip -= 1;
+        }
+
+        let hmac = HmacSha256::new_varkey(&k).unwrap();
+        k = hmac.chain(&v).chain(&[0x00]).finalize().into_bytes();
+        let hmac = HmacSha256::new_varkey(&k).unwrap();
+        v = hmac.chain(&v).finalize().into_bytes();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+    use sha2::Digest;
+
+    use super::*;
+    use crate::p256::P256;
+    use crate::secp256k1::crypto::PrivateKey;
+    use crate::secp256k1::Secp256k1;
+
+    #[test]
+    fn p256_rfc6979_vector() {
+        // RFC 6979, A.2.5 with SHA-256 and the message "sample"
+        let secret = biguint!("c9afa9d845ba75166b5c215767b1d6934e50c3db36e89b127b8a622b120f6721");
+        let digest = sha2::Sha256::digest(b"sample");
+
+        let pub_key = public_key::<P256>(&secret).unwrap();
+        assert_eq!(
+            pub_key.x().unwrap().to_biguint(),
+            biguint!("60fed4ba255a9d31c961eb74c6356d68c049b8923b61fa6ce669622e60f29fb6")
+        );
+        assert_eq!(
+            pub_key.y().unwrap().to_biguint(),
+            biguint!("7903fe1008b8bc99a41ae9e95628bc64f2f1b20c2d7e9f5177a3c294d4462299")
+        );
+
+        let k = deterministic_k(&secret, &digest, P256::order(), 0);
+        assert_eq!(
+            k,
+            biguint!("a6e3c57dd01abe90086538398355dd4c3b17aa873382b0f24d6129493d8aad60")
+        );
+
+        let signature = sign::<P256>(&secret, &digest).unwrap();
+        let expected = Signature::new(
+            biguint!("efd48b2aacb6a8fd1140dd9cd45e81d69d2c877b56aaf991c34d0ea84eaf3716"),
+            biguint!("f7cb1c942d657c41d436c7a1b6e29f65f3e900dbb9aff4064dc4ab2f843acda8"),
+        );
+        assert_eq!(signature, expected);
+
+        assert!(verify::<P256>(&digest, &signature, &pub_key).unwrap());
+        let other = sha2::Sha256::digest(b"test");
+        assert!(!verify::<P256>(&other, &signature, &pub_key).unwrap());
+    }
+
+    #[test]
+    fn matches_secp256k1_module() {
+        let secret = BigUint::from(0xdead_beef_u64);
+        let digest = hex!("bc62d4b80d9e36da29c16c5d4d9f11731f36052c72401a76c23c0fb5a9b74423");
+
+        let private_key = PrivateKey::new(secret.clone());
+        let signature = private_key.create_signature(digest).unwrap();
+
+        let pub_key = public_key::<Secp256k1>(&secret).unwrap();
+        assert!(verify::<Secp256k1>(&digest, &signature, &pub_key).unwrap());
+
+        // the secp256k1 module normalizes s
+        let generic = sign::<Secp256k1>(&secret, &digest).unwrap();
+        assert_eq!(generic.r, signature.r);
+        assert!(generic.s == signature.s || generic.s == Secp256k1::order() - &signature.s);
+    }
+}
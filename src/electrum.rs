@@ -0,0 +1,216 @@
+use num_bigint::{BigUint, RandBigInt};
+use num_integer::Integer;
+use num_traits::{ToPrimitive, Zero};
+use subtle::ConstantTimeEq;
+
+use crate::bip32::{version, ExtendedPrivateKey, HARDENED};
+use crate::network::Network;
+use crate::utils::{hmac_sha512, pbkdf2_hmac_sha512};
+use crate::wordlist::ENGLISH;
+use crate::{Error, Result};
+
+/// Amount of entropy used when generating new seeds, this gives 12 words
+const ENTROPY_BITS: u64 = 132;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElectrumSeedType {
+    Standard,
+    Segwit,
+    TwoFactor,
+    TwoFactorSegwit,
+}
+
+impl ElectrumSeedType {
+    /// Hex prefix of the `HMAC-SHA512("Seed version", seed)` digest
+    pub fn prefix(self) -> &'static str {
+        match self {
+            ElectrumSeedType::Standard => "01",
+            ElectrumSeedType::Segwit => "100",
+            ElectrumSeedType::TwoFactor => "101",
+            ElectrumSeedType::TwoFactorSegwit => "102",
+        }
+    }
+
+    /// Version bytes Electrum uses to serialize the root private key
+    pub fn xprv_version(self, network: Network) -> [u8; 4] {
+        match (self, !network.is_mainnet()) {
+            (ElectrumSeedType::Standard, false) | (ElectrumSeedType::TwoFactor, false) => {
+                version::XPRV
+            }
+            (ElectrumSeedType::Standard, true) | (ElectrumSeedType::TwoFactor, true) => {
+                version::TPRV
+            }
+            (_, false) => version::ZPRV,
+            (_, true) => version::VPRV,
+        }
+    }
+
+    fn from_digest(digest: &str) -> Option<Self> {
+        [
+            ElectrumSeedType::Standard,
+            ElectrumSeedType::Segwit,
+            ElectrumSeedType::TwoFactor,
+            ElectrumSeedType::TwoFactorSegwit,
+        ]
+        .iter()
+        .copied()
+        .find(|seed_type| digest.starts_with(seed_type.prefix()))
+    }
+}
+
+/// A new style (version 2.0+) Electrum seed
+#[derive(Debug, Clone)]
+pub struct ElectrumSeed {
+    phrase: String,
+    seed_type: ElectrumSeedType,
+}
+
+impl PartialEq for ElectrumSeed {
+    fn eq(&self, other: &Self) -> bool {
+        let same_phrase = self.phrase.as_bytes().ct_eq(other.phrase.as_bytes());
+        bool::from(same_phrase) && self.seed_type == other.seed_type
+    }
+}
+
+impl Eq for ElectrumSeed {}
+
+impl ElectrumSeed {
+    /// Generate a new random seed of the given type
+    pub fn generate(seed_type: ElectrumSeedType) -> Self {
+        let mut rng = rand::thread_rng();
+        let lower = BigUint::from(1usize) << (ENTROPY_BITS - 11);
+
+        let mut entropy = rng.gen_biguint(ENTROPY_BITS);
+        while entropy < lower {
+            entropy = rng.gen_biguint(ENTROPY_BITS);
+        }
+
+        loop {
+            entropy += 1usize;
+            let phrase = encode_words(&entropy);
+            if seed_type_of(&phrase) == Some(seed_type) {
+                return Self { phrase, seed_type };
+            }
+        }
+    }
+
+    /// Validate the given mnemonic phrase as an Electrum seed
+    pub fn from_phrase<S>(phrase: S) -> Result<Self>
+    where
+        S: AsRef<str>,
+    {
+        let phrase = normalize(phrase.as_ref());
+        match seed_type_of(&phrase) {
+            Some(seed_type) => Ok(Self { phrase, seed_type }),
+            None => Err(Error::InvalidElectrumSeed),
+        }
+    }
+
+    /// Check whether the given phrase is a valid Electrum seed
+    pub fn is_valid<S>(phrase: S) -> bool
+    where
+        S: AsRef<str>,
+    {
+        seed_type_of(&normalize(phrase.as_ref())).is_some()
+    }
+
+    pub fn phrase(&self) -> &str {
+        &self.phrase
+    }
+
+    pub fn seed_type(&self) -> ElectrumSeedType {
+        self.seed_type
+    }
+
+    /// Stretch the mnemonic (and optional passphrase) into the BIP32 seed
+    pub fn to_seed(&self, passphrase: &str) -> [u8; 64] {
+        let salt = format!("electrum{}", normalize(passphrase));
+        pbkdf2_hmac_sha512(&self.phrase, salt, 2048)
+    }
+
+    /// Derive the root key of the wallet, segwit wallets live under `m/0'`
+    pub fn root_key(&self, passphrase: &str) -> Result<ExtendedPrivateKey> {
+        let master = ExtendedPrivateKey::new_master(self.to_seed(passphrase))?;
+
+        match self.seed_type {
+            ElectrumSeedType::Segwit => master.derive_child(HARDENED),
+            _ => Ok(master),
+        }
+    }
+}
+
+/// Lowercase and collapse whitespaces, only ASCII (english) seeds are handled
+fn normalize(phrase: &str) -> String {
+    phrase
+        .split_whitespace()
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn seed_type_of(phrase: &str) -> Option<ElectrumSeedType> {
+    let digest = hex::encode(hmac_sha512(b"Seed version", phrase));
+    ElectrumSeedType::from_digest(&digest)
+}
+
+/// Encode the number using the wordlist as base 2048 digits, least significant first
+fn encode_words(number: &BigUint) -> String {
+    let base = BigUint::from(ENGLISH.len());
+    let mut number = number.clone();
+    let mut words = Vec::new();
+
+    while !number.is_zero() {
+        let (q, r) = number.div_mod_floor(&base);
+        number = q;
+        words.push(ENGLISH[r.to_usize().unwrap()]);
+    }
+
+    words.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEGWIT_SEED: &str =
+        "wild father tree among universe such mobile favorite target dynamic credit identify";
+
+    #[test]
+    fn validate_seed() {
+        let seed = ElectrumSeed::from_phrase(SEGWIT_SEED).unwrap();
+        assert_eq!(seed.seed_type(), ElectrumSeedType::Segwit);
+
+        let upper = SEGWIT_SEED.to_uppercase().replace(' ', "  ");
+        assert_eq!(ElectrumSeed::from_phrase(upper).unwrap(), seed);
+
+        assert!(!ElectrumSeed::is_valid(
+            "cell dumb heartbeat north boom tease ship baby bright kingdom rare squeeze"
+        ));
+    }
+
+    #[test]
+    fn seed_and_root_key() {
+        let seed = ElectrumSeed::from_phrase(SEGWIT_SEED).unwrap();
+        assert_eq!(
+            hex::encode(&seed.to_seed("")[..]),
+            "aac2a6302e48577ab4b46f23dbae0774e2e62c796f797d0a1b5faeb528301e30\
+             64342dafb79069e7c4c6b8c38ae11d7a973bec0d4f70626f8cc5184a8d0b0756"
+        );
+
+        let root = seed.root_key("").unwrap();
+        let version = seed.seed_type().xprv_version(Network::Mainnet);
+        assert_eq!(
+            root.create_xprv(version).unwrap(),
+            "zprvAZwZufq98oZX4FwwyHWQ37owMYPrNNPKkNGvt1d3MfSwtfaGAKgWhdnYqC6tNsFnNKfum29S49fSoL7VQ2sSFMKQehn4kdfJdRsDcAJQ9n5"
+        );
+    }
+
+    #[test]
+    fn generate_seed() {
+        for &seed_type in &[ElectrumSeedType::Standard, ElectrumSeedType::Segwit] {
+            let seed = ElectrumSeed::generate(seed_type);
+            assert_eq!(seed.phrase().split(' ').count(), 12);
+            assert_eq!(ElectrumSeed::from_phrase(seed.phrase()).unwrap(), seed);
+        }
+    }
+}
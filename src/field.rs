@@ -0,0 +1,355 @@
+use core::fmt::{self, Debug};
+use core::marker::PhantomData;
+use core::ops::{Add, Div, Mul, Sub};
+
+use num_bigint::BigUint;
+use num_integer::Integer;
+use num_traits::{One, Zero};
+
+/// Anything with exact field arithmetic (rationals, prime fields, ...), implemented
+/// automatically for every type with the required operations
+pub trait Field:
+    Sized
+    + Clone
+    + Debug
+    + PartialEq
+    + Zero
+    + One
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+{
+}
+
+impl<T> Field for T where
+    T: Sized
+        + Clone
+        + Debug
+        + PartialEq
+        + Zero
+        + One
+        + Add<Output = Self>
+        + Sub<Output = Self>
+        + Mul<Output = Self>
+        + Div<Output = Self>
+{
+}
+
+/// Arithmetic shared by every prime field, the modulus must be an odd prime
+pub trait PrimeField: Field {
+    /// The prime modulus of this field
+    fn modulus() -> &'static BigUint;
+
+    /// Build a new element, reducing the given number
+    fn from_biguint(number: BigUint) -> Self;
+
+    /// Get this element as an arbitrary precision integer (always reduced)
+    fn to_biguint(&self) -> BigUint;
+
+    /// Get the _additive inverse_ of this element.
+    fn add_inv(&self) -> Self;
+
+    /// Get the _multiplicative inverse_ of this element.
+    fn mul_inv(&self) -> Self {
+        // Fermat's little theorem
+        self.pow_biguint(&(Self::modulus() - 2u8))
+    }
+
+    fn square(&self) -> Self {
+        self.clone() * self.clone()
+    }
+
+    fn pow_biguint(&self, exponent: &BigUint) -> Self {
+        let mut result = Self::one();
+        for bit in (0..exponent.bits()).rev() {
+            result = result.square();
+            if exponent.bit(bit) {
+                result = result * self.clone();
+            }
+        }
+
+        result
+    }
+
+    /// Get both square roots of this element as `(even, odd)`, or `None` if this
+    /// element isn't a quadratic residue. Zero comes back as `(0, 0)`, it has no
+    /// odd root.
+    fn sqrt(&self) -> Option<(Self, Self)> {
+        if self.is_zero() {
+            return Some((Self::zero(), Self::zero()));
+        }
+
+        // Euler's criterion
+        let p = Self::modulus();
+        if !self.pow_biguint(&(p >> 1)).is_one() {
+            return None;
+        }
+
+        let root = if p % 4u8 == BigUint::from(3u8) {
+            self.pow_biguint(&((p + 1u8) >> 2))
+        } else {
+            tonelli_shanks(self)
+        };
+
+        let other = root.add_inv();
+        if root.to_biguint().is_even() {
+            Some((root, other))
+        } else {
+            Some((other, root))
+        }
+    }
+}
+
+/// Square root of a quadratic residue for any odd prime
+fn tonelli_shanks<F: PrimeField>(n: &F) -> F {
+    let p = F::modulus();
+
+    // p - 1 = q * 2^s, with q odd
+    let s = (p - 1u8).trailing_zeros().unwrap(); // safe, p - 1 > 0
+    let q = (p - 1u8) >> s;
+
+    // any quadratic non residue
+    let half = p >> 1;
+    let z = (2u8..)
+        .map(|z| F::from_biguint(BigUint::from(z)))
+        .find(|z| !z.pow_biguint(&half).is_one())
+        .unwrap(); // safe, half of the elements are non residues
+
+    let mut m = s;
+    let mut c = z.pow_biguint(&q);
+    let mut t = n.pow_biguint(&q);
+    let mut r = n.pow_biguint(&((q + 1u8) >> 1));
+
+    while !t.is_one() {
+        // least i such that t^(2^i) = 1
+        let mut i = 0;
+        let mut t2 = t.clone();
+        while !t2.is_one() {
+            t2 = t2.square();
+            i += 1;
+        }
+
+        let mut b = c;
+        for _ in 0..(m - i - 1) {
+            b = b.square();
+        }
+
+        m = i;
+        c = b.square();
+        t = t * c.clone();
+        r = r * b;
+    }
+
+    r
+}
+
+/// A prime modulus known at the type level, see `prime_modulus!`
+pub trait Modulus: 'static {
+    fn modulus() -> &'static BigUint;
+}
+
+/// An element of the field of integers modulo `M`, backed by an arbitrary
+/// precision integer. Slower than a specialized implementation (like the
+/// `secp256k1` field) but works with any prime.
+pub struct Fp<M> {
+    value: BigUint,
+    modulus: PhantomData<M>,
+}
+
+impl<M: Modulus> Fp<M> {
+    /// Build a new element, reducing the given number
+    pub fn new<U>(number: U) -> Self
+    where
+        U: Into<BigUint>,
+    {
+        Self {
+            value: number.into() % M::modulus(),
+            modulus: PhantomData,
+        }
+    }
+
+    /// Interpret the given bytes as a big endian number, reducing it
+    pub fn from_bytes_be<B>(bytes: B) -> Self
+    where
+        B: AsRef<[u8]>,
+    {
+        Self::new(BigUint::from_bytes_be(bytes.as_ref()))
+    }
+
+    pub fn value(&self) -> &BigUint {
+        &self.value
+    }
+}
+
+impl<M: Modulus> PrimeField for Fp<M> {
+    fn modulus() -> &'static BigUint {
+        M::modulus()
+    }
+
+    fn from_biguint(number: BigUint) -> Self {
+        Self::new(number)
+    }
+
+    fn to_biguint(&self) -> BigUint {
+        self.value.clone()
+    }
+
+    fn add_inv(&self) -> Self {
+        Self::new(M::modulus() - &self.value)
+    }
+
+    fn pow_biguint(&self, exponent: &BigUint) -> Self {
+        Self::new(self.value.modpow(exponent, M::modulus()))
+    }
+}
+
+impl<M> Clone for Fp<M> {
+    fn clone(&self) -> Self {
+        Self {
+            value: self.value.clone(),
+            modulus: PhantomData,
+        }
+    }
+}
+
+impl<M> PartialEq for Fp<M> {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl<M> Eq for Fp<M> {}
+
+impl<M> Debug for Fp<M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Fp({:#x})", self.value)
+    }
+}
+
+impl<M: Modulus> Zero for Fp<M> {
+    fn zero() -> Self {
+        Self::new(0u8)
+    }
+
+    fn is_zero(&self) -> bool {
+        self.value.is_zero()
+    }
+}
+
+impl<M: Modulus> One for Fp<M> {
+    fn one() -> Self {
+        Self::new(1u8)
+    }
+}
+
+impl<M: Modulus> Add<&Fp<M>> for &Fp<M> {
+    type Output = Fp<M>;
+
+    fn add(self, rhs: &Fp<M>) -> Self::Output {
+        Fp::new(&self.value + &rhs.value)
+    }
+}
+
+impl<M: Modulus> Sub<&Fp<M>> for &Fp<M> {
+    type Output = Fp<M>;
+
+    fn sub(self, rhs: &Fp<M>) -> Self::Output {
+        Fp::new(&self.value + M::modulus() - &rhs.value)
+    }
+}
+
+impl<M: Modulus> Mul<&Fp<M>> for &Fp<M> {
+    type Output = Fp<M>;
+
+    fn mul(self, rhs: &Fp<M>) -> Self::Output {
+        Fp::new(&self.value * &rhs.value)
+    }
+}
+
+impl<M: Modulus> Div<&Fp<M>> for &Fp<M> {
+    type Output = Fp<M>;
+
+    fn div(self, rhs: &Fp<M>) -> Self::Output {
+        self.mul(&rhs.mul_inv())
+    }
+}
+
+forward_binop_impl!(impl<M: Modulus> for non-copyable Fp<M> where Add does add);
+forward_binop_impl!(impl<M: Modulus> for non-copyable Fp<M> where Sub does sub);
+forward_binop_impl!(impl<M: Modulus> for non-copyable Fp<M> where Mul does mul);
+forward_binop_impl!(impl<M: Modulus> for non-copyable Fp<M> where Div does div);
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use core::convert::TryInto;
+
+    use num_bigint::RandBigInt;
+
+    use super::*;
+    use crate::secp256k1::field::{FieldElement, Secp256k1Prime};
+
+    prime_modulus!(F223 = "df");
+    prime_modulus!(F13 = "0d");
+    prime_modulus!(F17 = "11");
+
+    #[test]
+    fn small_field_arithmetic() {
+        let a = Fp::<F223>::new(192u8);
+        let b = Fp::<F223>::new(105u8);
+
+        assert_eq!(&a + &b, Fp::new(74u8));
+        assert_eq!(&b - &a, Fp::new(136u8));
+        assert_eq!(&a * &b, Fp::new(90u8));
+        assert_eq!(&a / &b * &b, a);
+        assert_eq!(&a + &a.add_inv(), Fp::zero());
+        assert_eq!(a.pow_biguint(&BigUint::from(222u8)), Fp::one());
+    }
+
+    #[test]
+    fn square_roots() {
+        fn check<F: PrimeField>() {
+            let p: u32 = F::modulus().try_into().unwrap();
+            for n in 0..p {
+                let n = F::from_biguint(BigUint::from(n));
+                let residue = (0..p).any(|x| F::from_biguint(BigUint::from(x)).square() == n);
+
+                match n.sqrt() {
+                    Some((even, odd)) => {
+                        assert!(residue);
+                        assert_eq!(even.square(), n);
+                        assert_eq!(odd.square(), n);
+                        assert!(even.to_biguint().is_even());
+                    }
+                    None => assert!(!residue),
+                }
+            }
+        }
+
+        // p = 3 (mod 4) and p = 1 (mod 4) (Tonelli-Shanks)
+        check::<Fp<F223>>();
+        check::<Fp<F13>>();
+        check::<Fp<F17>>();
+    }
+
+    #[test]
+    fn matches_specialized_field() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..8 {
+            let (a, b) = (
+                rng.gen_biguint_below(Secp256k1Prime::modulus()),
+                rng.gen_biguint_below(Secp256k1Prime::modulus()),
+            );
+
+            let generic =
+                Fp::<Secp256k1Prime>::new(a.clone()) * Fp::new(b.clone()) / Fp::new(a.clone());
+            let specialized =
+                FieldElement::new(a.clone()) * FieldElement::new(b) / FieldElement::new(a);
+            assert_eq!(generic.to_biguint(), PrimeField::to_biguint(&specialized));
+            assert_eq!(
+                PrimeField::sqrt(&generic).map(|(even, _)| even.to_biguint()),
+                specialized.sqrt().map(|(even, _)| even.to_biguint())
+            );
+        }
+    }
+}
@@ -1,59 +1,260 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
 #[macro_use]
 mod macros;
+pub mod address;
+pub mod amount;
 pub mod base58;
+pub mod bech32;
+pub mod bip21;
+#[cfg(feature = "std")]
+pub mod bip32;
+pub mod chacha20poly1305;
+#[cfg(feature = "std")]
+pub mod consensus;
+#[cfg(feature = "std")]
 pub mod core;
-mod format;
+pub mod curve;
+#[cfg(feature = "std")]
+pub mod descriptor;
+pub mod ecdsa;
+#[cfg(feature = "std")]
+pub mod electrum;
+pub mod field;
+#[cfg(feature = "std")]
+pub mod net;
+pub mod network;
+pub mod p256;
+#[cfg(feature = "std")]
+pub mod params;
+#[cfg(feature = "std")]
+pub mod psbt;
 pub mod secp256k1;
+pub mod taproot;
 pub mod utils;
+#[cfg(feature = "std")]
 pub mod varint;
+#[cfg(feature = "std")]
+mod wordlist;
 
+use alloc::string::{String, ToString};
+#[cfg(feature = "std")]
 use std::io;
 
+#[cfg(feature = "std")]
 use thiserror::Error;
 
-#[derive(Debug, Error)]
+/// Without `std` only the `Debug` representation is available
+#[derive(Debug)]
+#[cfg_attr(feature = "std", derive(Error))]
 pub enum Error {
-    #[error("custom error: {0}")]
+    #[cfg_attr(feature = "std", error("custom error: {0}"))]
     Custom(String),
 
-    #[error("io error: {source}")]
+    #[cfg(feature = "std")]
+    #[cfg_attr(feature = "std", error("io error: {source}"))]
     IoError {
         #[from]
         source: io::Error,
     },
 
-    #[error("hyper error: {source}")]
+    #[cfg(feature = "std")]
+    #[cfg_attr(feature = "std", error("hyper error: {source}"))]
     HyperError {
         #[from]
         source: hyper::Error,
     },
 
-    #[error("int to big for varint")]
+    #[cfg_attr(feature = "std", error("int to big for varint"))]
     IntToBigForVarInt,
 
-    #[error("invalid bytes for varint")]
+    #[cfg_attr(feature = "std", error("invalid bytes for varint"))]
     InvalidBytesForVarInt,
 
-    #[error("point is not on the curve")]
+    #[cfg_attr(feature = "std", error("non canonical varint encoding"))]
+    NonCanonicalVarInt,
+
+    #[cfg_attr(
+        feature = "std",
+        error("vector of {0} elements exceeds the maximum size")
+    )]
+    OversizedVector(u64),
+
+    #[cfg_attr(feature = "std", error("{0} trailing bytes after the encoded data"))]
+    TrailingBytes(usize),
+
+    #[cfg_attr(feature = "std", error("point is not on the curve"))]
     PointNotOnTheCurve,
 
-    #[error("overflow error while padding")]
+    #[cfg_attr(feature = "std", error("invalid public key ({0})"))]
+    InvalidPublicKey(&'static str),
+
+    #[cfg_attr(feature = "std", error("overflow error while padding"))]
     OverflowPadding,
 
-    #[error("cannot serialize point at infinity")]
+    #[cfg_attr(feature = "std", error("cannot serialize point at infinity"))]
     SerializePointAtInfinity,
 
-    #[error("invalid digest, expecting 32 bytes, got {0}")]
+    #[cfg_attr(feature = "std", error("invalid digest, expecting 32 bytes, got {0}"))]
     InvalidDigestLength(usize),
 
-    #[error("invalid sec bytes, expecting either 33 or 65 bytes, got {0} ")]
+    #[cfg_attr(
+        feature = "std",
+        error("invalid sec bytes, expecting either 33 or 65 bytes, got {0} ")
+    )]
     InvalidSecBytesLength(usize),
 
-    #[error("invalid signature ({0})")]
+    #[cfg_attr(feature = "std", error("invalid signature ({0})"))]
     InvalidSignature(&'static str),
 
-    #[error("fetched invalid transaction")]
+    #[cfg_attr(feature = "std", error("fetched invalid transaction"))]
     FetchedInvalidTransaction,
+
+    #[cfg_attr(feature = "std", error("invalid transaction ({0})"))]
+    InvalidTransaction(&'static str),
+
+    #[cfg(feature = "std")]
+    #[cfg_attr(feature = "std", error("input {index} doesn't verify ({error})"))]
+    InvalidInput {
+        index: usize,
+        error: crate::core::interpreter::ScriptError,
+    },
+
+    #[cfg(feature = "std")]
+    #[cfg_attr(feature = "std", error("non standard transaction ({0})"))]
+    NonStandard(crate::core::policy::PolicyError),
+
+    #[cfg_attr(feature = "std", error("invalid block ({0})"))]
+    InvalidBlock(&'static str),
+
+    #[cfg(feature = "std")]
+    #[cfg_attr(feature = "std", error("invalid block ({0})"))]
+    BlockValidation(crate::core::block::BlockValidationError),
+
+    #[cfg_attr(feature = "std", error("invalid bloom filter ({0})"))]
+    InvalidBloomFilter(&'static str),
+
+    #[cfg_attr(feature = "std", error("invalid p2p message ({0})"))]
+    InvalidMessage(&'static str),
+
+    #[cfg_attr(feature = "std", error("invalid compact block ({0})"))]
+    InvalidCompactBlock(&'static str),
+
+    #[cfg_attr(feature = "std", error("handshake failed ({0})"))]
+    HandshakeFailed(&'static str),
+
+    #[cfg_attr(feature = "std", error("invalid address book ({0})"))]
+    InvalidAddressBook(&'static str),
+
+    #[cfg_attr(feature = "std", error("headers sync failed ({0})"))]
+    SyncFailed(&'static str),
+
+    #[cfg_attr(feature = "std", error("broadcast failed ({0})"))]
+    BroadcastFailed(&'static str),
+
+    #[cfg_attr(feature = "std", error("peer timed out ({0})"))]
+    PeerTimedOut(&'static str),
+
+    #[cfg_attr(feature = "std", error("peer misbehaved ({0})"))]
+    PeerMisbehaved(&'static str),
+
+    #[cfg_attr(feature = "std", error("v2 transport failed ({0})"))]
+    TransportFailed(&'static str),
+
+    #[cfg_attr(feature = "std", error("authentication failed, wrong key or tampered data"))]
+    AuthenticationFailed,
+
+    #[cfg_attr(feature = "std", error("coin selection failed ({0})"))]
+    CoinSelection(&'static str),
+
+    #[cfg_attr(feature = "std", error("invalid locktime ({0})"))]
+    InvalidLockTime(&'static str),
+
+    #[cfg_attr(feature = "std", error("invalid multisig ({0})"))]
+    InvalidMultisig(&'static str),
+
+    #[cfg_attr(feature = "std", error("invalid script ({0})"))]
+    InvalidScript(&'static str),
+
+    #[cfg_attr(
+        feature = "std",
+        error("invalid seed length, expecting between 16 and 64 bytes, got {0}")
+    )]
+    InvalidSeedLength(usize),
+
+    #[cfg_attr(feature = "std", error("invalid extended key ({0})"))]
+    InvalidExtendedKey(&'static str),
+
+    #[cfg_attr(feature = "std", error("cannot derive child key at index {0}"))]
+    InvalidChildKey(u32),
+
+    #[cfg_attr(feature = "std", error("invalid electrum seed, unknown seed version"))]
+    InvalidElectrumSeed,
+
+    #[cfg_attr(feature = "std", error("invalid base58 character {0:?}"))]
+    InvalidBase58Character(char),
+
+    #[cfg_attr(feature = "std", error("invalid base58 checksum"))]
+    InvalidBase58Checksum,
+
+    #[cfg_attr(feature = "std", error("invalid hex string"))]
+    InvalidHex,
+
+    #[cfg_attr(feature = "std", error("invalid bech32 string ({0})"))]
+    InvalidBech32(&'static str),
+
+    #[cfg_attr(feature = "std", error("invalid address ({0})"))]
+    InvalidAddress(&'static str),
+
+    #[cfg_attr(feature = "std", error("invalid amount ({0})"))]
+    InvalidAmount(&'static str),
+
+    #[cfg_attr(feature = "std", error("invalid bip21 uri ({0})"))]
+    InvalidBip21(&'static str),
+
+    #[cfg_attr(feature = "std", error("unknown network: {0}"))]
+    UnknownNetwork(String),
+
+    #[cfg_attr(feature = "std", error("segwit outputs require compressed keys"))]
+    UncompressedSegwitKey,
+
+    #[cfg_attr(feature = "std", error("invalid taproot tweak"))]
+    InvalidTaprootTweak,
+
+    #[cfg_attr(feature = "std", error("invalid taproot tree ({0})"))]
+    InvalidTaprootTree(&'static str),
+
+    #[cfg_attr(feature = "std", error("invalid derivation path ({0})"))]
+    InvalidDerivationPath(&'static str),
+
+    #[cfg_attr(
+        feature = "std",
+        error("cannot derive hardened child {0} from a public key")
+    )]
+    HardenedPublicDerivation(u32),
+
+    #[cfg_attr(feature = "std", error("invalid descriptor key ({0})"))]
+    InvalidDescriptorKey(&'static str),
+
+    #[cfg_attr(feature = "std", error("invalid descriptor ({0})"))]
+    InvalidDescriptor(&'static str),
+
+    #[cfg_attr(feature = "std", error("invalid miniscript ({0})"))]
+    InvalidMiniscript(&'static str),
+
+    #[cfg_attr(feature = "std", error("invalid psbt ({0})"))]
+    InvalidPsbt(&'static str),
+
+    #[cfg_attr(feature = "std", error("invalid wif ({0})"))]
+    InvalidWif(&'static str),
+
+    #[cfg_attr(
+        feature = "std",
+        error("invalid private key, must be in the range [1, n)")
+    )]
+    InvalidPrivateKey,
 }
 
 impl Error {
@@ -62,4 +263,4 @@ impl Error {
     }
 }
 
-pub type Result<T, E = Error> = std::result::Result<T, E>;
+pub type Result<T, E = Error> = ::core::result::Result<T, E>;
@@ -24,6 +24,32 @@ macro_rules! forward_binop_impl {
             }
         }
     };
+
+    (impl<$gen:ident: $bound:path> for non-copyable $type:ty where $binop:ident does $met:ident) => {
+        impl<'a, $gen: $bound> $binop<&'a $type> for $type {
+            type Output = $type;
+
+            fn $met(self, rhs: &'a $type) -> Self::Output {
+                $binop::$met(&self, rhs)
+            }
+        }
+
+        impl<'a, $gen: $bound> $binop<$type> for &'a $type {
+            type Output = $type;
+
+            fn $met(self, rhs: $type) -> Self::Output {
+                $binop::$met(self, &rhs)
+            }
+        }
+
+        impl<$gen: $bound> $binop for $type {
+            type Output = $type;
+
+            fn $met(self, rhs: $type) -> Self::Output {
+                $binop::$met(&self, &rhs)
+            }
+        }
+    };
 }
 
 #[macro_export]
@@ -33,10 +59,29 @@ macro_rules! biguint {
     };
 }
 
+/// Declare a type level prime modulus, usable with `Fp`
+macro_rules! prime_modulus {
+    ($(#[$meta:meta])* $vis:vis $name:ident = $hex:tt) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        $vis struct $name;
+
+        impl $crate::field::Modulus for $name {
+            fn modulus() -> &'static num_bigint::BigUint {
+                lazy_static::lazy_static! {
+                    static ref MODULUS: num_bigint::BigUint = biguint!($hex);
+                }
+
+                &MODULUS
+            }
+        }
+    };
+}
+
 macro_rules! field_elem {
     ($hex:tt) => {{
         use $crate::secp256k1::field::FieldElement;
-        FieldElement::new(biguint!($hex))
+        FieldElement::from_bytes_be(hex_literal::hex!($hex))
     }};
 }
 
@@ -46,3 +91,91 @@ macro_rules! ec_point {
         Point::new(field_elem!($hex_x), field_elem!($hex_y)).unwrap()
     }};
 }
+
+/// Serde through `Display`/`FromStr`, binary formats use the raw bytes instead when
+/// the conversions are given
+#[cfg(feature = "serde")]
+macro_rules! serde_impl {
+    ($type:ty, $expecting:literal) => {
+        impl serde::Serialize for $type {
+            fn serialize<S>(&self, serializer: S) -> ::core::result::Result<S::Ok, S::Error>
+            where
+                S: serde::Serializer,
+            {
+                serializer.collect_str(self)
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for $type {
+            fn deserialize<D>(deserializer: D) -> ::core::result::Result<Self, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+            {
+                serde_impl!(@visitor $type, $expecting);
+                deserializer.deserialize_str(Visitor)
+            }
+        }
+    };
+
+    ($type:ty, $expecting:literal, $to_bytes:expr, $from_bytes:expr) => {
+        impl serde::Serialize for $type {
+            fn serialize<S>(&self, serializer: S) -> ::core::result::Result<S::Ok, S::Error>
+            where
+                S: serde::Serializer,
+            {
+                if serializer.is_human_readable() {
+                    return serializer.collect_str(self);
+                }
+
+                let bytes = ($to_bytes)(self).map_err(|err| {
+                    <S::Error as serde::ser::Error>::custom(format_args!("{:?}", err))
+                })?;
+                serializer.serialize_bytes(AsRef::<[u8]>::as_ref(&bytes))
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for $type {
+            fn deserialize<D>(deserializer: D) -> ::core::result::Result<Self, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+            {
+                serde_impl!(@visitor $type, $expecting, $from_bytes);
+                if deserializer.is_human_readable() {
+                    deserializer.deserialize_str(Visitor)
+                } else {
+                    deserializer.deserialize_bytes(Visitor)
+                }
+            }
+        }
+    };
+
+    (@visitor $type:ty, $expecting:literal $(, $from_bytes:expr)?) => {
+        struct Visitor;
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = $type;
+
+            fn expecting(&self, fmt: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                fmt.write_str($expecting)
+            }
+
+            fn visit_str<E>(self, value: &str) -> ::core::result::Result<$type, E>
+            where
+                E: serde::de::Error,
+            {
+                value
+                    .parse::<$type>()
+                    .map_err(|err| E::custom(format_args!("{:?}", err)))
+            }
+
+            $(
+                fn visit_bytes<E>(self, value: &[u8]) -> ::core::result::Result<$type, E>
+                where
+                    E: serde::de::Error,
+                {
+                    ($from_bytes)(value).map_err(|err| E::custom(format_args!("{:?}", err)))
+                }
+            )?
+        }
+    };
+}
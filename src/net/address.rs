@@ -0,0 +1,519 @@
+//! Peer addresses as carried by `version`, `addr` and `addrv2` messages, and
+//! the services they're announced with
+
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::ops::{BitOr, BitOrAssign};
+use std::str::FromStr;
+
+use crate::consensus::{self, Decodable, Encodable};
+use crate::utils::sha3_256;
+use crate::{Error, Result};
+
+/// Longest address of an `addrv2` message, of any network (BIP155)
+pub const MAX_ADDRV2_SIZE: usize = 512;
+/// Version byte of Tor v3 addresses
+const TORV3_VERSION: u8 = 3;
+/// RFC 4648 base32 alphabet, lowercase as onion and I2P addresses are written
+const BASE32_CHARSET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+/// What a peer serves, as bits
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct ServiceFlags(u64);
+
+impl ServiceFlags {
+    pub const NONE: ServiceFlags = ServiceFlags(0);
+    /// The full chain
+    pub const NETWORK: ServiceFlags = ServiceFlags(1 << 0);
+    /// Filtered connections (BIP111)
+    pub const BLOOM: ServiceFlags = ServiceFlags(1 << 2);
+    /// Blocks and transactions with their witness data (BIP144)
+    pub const WITNESS: ServiceFlags = ServiceFlags(1 << 3);
+    /// Compact block filters (BIP157)
+    pub const COMPACT_FILTERS: ServiceFlags = ServiceFlags(1 << 6);
+    /// The last 288 blocks only (BIP159)
+    pub const NETWORK_LIMITED: ServiceFlags = ServiceFlags(1 << 10);
+    /// Encrypted transport (BIP324)
+    pub const P2P_V2: ServiceFlags = ServiceFlags(1 << 11);
+
+    pub const fn from_bits(bits: u64) -> Self {
+        ServiceFlags(bits)
+    }
+
+    pub const fn bits(self) -> u64 {
+        self.0
+    }
+
+    pub const fn contains(self, other: ServiceFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for ServiceFlags {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        ServiceFlags(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for ServiceFlags {
+    fn bitor_assign(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+}
+
+impl Encodable for ServiceFlags {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        self.0.consensus_encode(writer)
+    }
+}
+
+impl Decodable for ServiceFlags {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        Ok(ServiceFlags(u64::consensus_decode(reader)?))
+    }
+}
+
+/// Services, IPv6 (IPv4 mapped into it) and port of a peer, without the time
+/// `addr` messages prefix it with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NetAddress {
+    pub services: ServiceFlags,
+    pub ip: Ipv6Addr,
+    pub port: u16,
+}
+
+impl NetAddress {
+    pub fn new(address: SocketAddr, services: ServiceFlags) -> Self {
+        let ip = match address.ip() {
+            IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+            IpAddr::V6(ip) => ip,
+        };
+
+        Self {
+            services,
+            ip,
+            port: address.port(),
+        }
+    }
+
+    /// Mapped IPv4 addresses are turned back into IPv4 ones
+    pub fn socket_addr(&self) -> SocketAddr {
+        let ip = match self.ip.to_ipv4_mapped() {
+            Some(ip) => IpAddr::V4(ip),
+            None => IpAddr::V6(self.ip),
+        };
+
+        SocketAddr::new(ip, self.port)
+    }
+}
+
+/// What peers send when they don't know (or tell) an address
+impl Default for NetAddress {
+    fn default() -> Self {
+        Self {
+            services: ServiceFlags::NONE,
+            ip: Ipv6Addr::UNSPECIFIED,
+            port: 0,
+        }
+    }
+}
+
+/// The port is the only big endian number of the protocol
+impl Encodable for NetAddress {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        Ok(self.services.consensus_encode(writer)?
+            + self.ip.octets().consensus_encode(writer)?
+            + self.port.to_be_bytes().consensus_encode(writer)?)
+    }
+}
+
+impl Decodable for NetAddress {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        Ok(Self {
+            services: ServiceFlags::consensus_decode(reader)?,
+            ip: Ipv6Addr::from(<[u8; 16]>::consensus_decode(reader)?),
+            port: u16::from_be_bytes(<[u8; 2]>::consensus_decode(reader)?),
+        })
+    }
+}
+
+/// An address of any of the networks of BIP155
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AddrV2 {
+    Ipv4(Ipv4Addr),
+    Ipv6(Ipv6Addr),
+    /// Deprecated, no longer reachable in Tor
+    TorV2([u8; 10]),
+    /// The ed25519 public key of the service
+    TorV3([u8; 32]),
+    /// The SHA-256 of the destination
+    I2p([u8; 32]),
+    Cjdns(Ipv6Addr),
+    /// A network not known by this crate, its address is kept as is
+    Unknown {
+        network: u8,
+        bytes: Vec<u8>,
+    },
+}
+
+impl AddrV2 {
+    /// BIP155 network id
+    pub fn network(&self) -> u8 {
+        match self {
+            AddrV2::Ipv4(_) => 1,
+            AddrV2::Ipv6(_) => 2,
+            AddrV2::TorV2(_) => 3,
+            AddrV2::TorV3(_) => 4,
+            AddrV2::I2p(_) => 5,
+            AddrV2::Cjdns(_) => 6,
+            AddrV2::Unknown { network, .. } => *network,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            AddrV2::Ipv4(ip) => ip.octets().to_vec(),
+            AddrV2::Ipv6(ip) | AddrV2::Cjdns(ip) => ip.octets().to_vec(),
+            AddrV2::TorV2(bytes) => bytes.to_vec(),
+            AddrV2::TorV3(bytes) | AddrV2::I2p(bytes) => bytes.to_vec(),
+            AddrV2::Unknown { bytes, .. } => bytes.clone(),
+        }
+    }
+
+    /// Known networks must have addresses of their length
+    pub fn from_bytes(network: u8, bytes: &[u8]) -> Result<Self> {
+        let wrong_length = || Error::InvalidMessage("address of the wrong length");
+        let address = match network {
+            1 => AddrV2::Ipv4(
+                <[u8; 4]>::try_from(bytes)
+                    .map_err(|_| wrong_length())?
+                    .into(),
+            ),
+            2 => AddrV2::Ipv6(
+                <[u8; 16]>::try_from(bytes)
+                    .map_err(|_| wrong_length())?
+                    .into(),
+            ),
+            3 => AddrV2::TorV2(bytes.try_into().map_err(|_| wrong_length())?),
+            4 => AddrV2::TorV3(bytes.try_into().map_err(|_| wrong_length())?),
+            5 => AddrV2::I2p(bytes.try_into().map_err(|_| wrong_length())?),
+            6 => AddrV2::Cjdns(
+                <[u8; 16]>::try_from(bytes)
+                    .map_err(|_| wrong_length())?
+                    .into(),
+            ),
+            network => AddrV2::Unknown {
+                network,
+                bytes: bytes.to_vec(),
+            },
+        };
+
+        Ok(address)
+    }
+
+    /// The IP, if reachable without going through an overlay network
+    pub fn ip(&self) -> Option<IpAddr> {
+        match self {
+            AddrV2::Ipv4(ip) => Some(IpAddr::V4(*ip)),
+            AddrV2::Ipv6(ip) => Some(IpAddr::V6(*ip)),
+            _ => None,
+        }
+    }
+}
+
+impl From<IpAddr> for AddrV2 {
+    fn from(ip: IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(ip) => AddrV2::Ipv4(ip),
+            IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+                Some(ip) => AddrV2::Ipv4(ip),
+                None => AddrV2::Ipv6(ip),
+            },
+        }
+    }
+}
+
+/// Of Tor v3 hostnames, `<base32 of key, checksum and version>.onion` as in
+/// Tor's rend-spec-v3
+fn torv3_checksum(key: &[u8; 32]) -> [u8; 2] {
+    let mut data = b".onion checksum".to_vec();
+    data.extend_from_slice(key);
+    data.push(TORV3_VERSION);
+    let digest = sha3_256(data);
+    [digest[0], digest[1]]
+}
+
+/// IPs as usual, Tor and I2P addresses as their hostnames
+impl fmt::Display for AddrV2 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddrV2::Ipv4(ip) => write!(f, "{}", ip),
+            AddrV2::Ipv6(ip) | AddrV2::Cjdns(ip) => write!(f, "{}", ip),
+            AddrV2::TorV2(bytes) => write!(f, "{}.onion", base32_encode(bytes)),
+            AddrV2::TorV3(key) => {
+                let mut bytes = key.to_vec();
+                bytes.extend_from_slice(&torv3_checksum(key));
+                bytes.push(TORV3_VERSION);
+                write!(f, "{}.onion", base32_encode(&bytes))
+            }
+            AddrV2::I2p(hash) => write!(f, "{}.b32.i2p", base32_encode(hash)),
+            AddrV2::Unknown { network, bytes } => {
+                write!(f, "unknown-{}:{}", network, hex::encode(bytes))
+            }
+        }
+    }
+}
+
+/// IPs, Tor v3 and I2P hostnames. CJDNS addresses look like IPv6 ones and are
+/// parsed as such
+impl FromStr for AddrV2 {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Ok(ip) = s.parse::<IpAddr>() {
+            return Ok(ip.into());
+        }
+
+        let invalid = || Error::InvalidAddress("not an IP, onion or I2P address");
+        if let Some(host) = s.strip_suffix(".onion") {
+            let bytes = base32_decode(host).ok_or_else(invalid)?;
+            if bytes.len() != 35 || bytes[34] != TORV3_VERSION {
+                return Err(Error::InvalidAddress("not a Tor v3 address"));
+            }
+
+            let key = <[u8; 32]>::try_from(&bytes[..32]).map_err(|_| invalid())?;
+            if bytes[32..34] != torv3_checksum(&key) {
+                return Err(Error::InvalidAddress("wrong Tor v3 checksum"));
+            }
+            return Ok(AddrV2::TorV3(key));
+        }
+
+        if let Some(host) = s.strip_suffix(".b32.i2p") {
+            // 52 characters, without the padding
+            let bytes = base32_decode(host).ok_or_else(invalid)?;
+            return match (host.len(), <[u8; 32]>::try_from(&bytes[..])) {
+                (52, Ok(hash)) => Ok(AddrV2::I2p(hash)),
+                _ => Err(Error::InvalidAddress("not an I2P address")),
+            };
+        }
+
+        Err(invalid())
+    }
+}
+
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity((bytes.len() * 8).div_ceil(5));
+    let (mut buffer, mut bits) = (0u32, 0);
+    for byte in bytes {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_CHARSET[(buffer >> bits) as usize & 0x1f] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_CHARSET[(buffer << (5 - bits)) as usize & 0x1f] as char);
+    }
+    encoded
+}
+
+/// Unpadded, in either case. None if there are invalid characters
+fn base32_decode(s: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(s.len() * 5 / 8);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in s.bytes() {
+        let value = BASE32_CHARSET
+            .iter()
+            .position(|x| *x == c.to_ascii_lowercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+/// An entry of an `addrv2` message (BIP155), services go as a compact size
+/// there
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AddrV2Message {
+    /// Last time it was seen at
+    pub time: u32,
+    pub services: ServiceFlags,
+    pub addr: AddrV2,
+    pub port: u16,
+}
+
+impl AddrV2Message {
+    pub fn new(time: u32, services: ServiceFlags, addr: AddrV2, port: u16) -> Self {
+        Self {
+            time,
+            services,
+            addr,
+            port,
+        }
+    }
+
+    /// None if it's not an IP address
+    pub fn socket_addr(&self) -> Option<SocketAddr> {
+        self.addr.ip().map(|ip| SocketAddr::new(ip, self.port))
+    }
+
+    /// The same address as an `addr` message would carry it, none if it's not
+    /// an IP address
+    pub fn to_net_address(&self) -> Option<NetAddress> {
+        self.socket_addr()
+            .map(|address| NetAddress::new(address, self.services))
+    }
+}
+
+impl Encodable for AddrV2Message {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        let mut len = self.time.consensus_encode(writer)?;
+        len += consensus::write_compact_size(writer, self.services.bits())?;
+        len += self.addr.network().consensus_encode(writer)?;
+        len += self.addr.to_bytes().consensus_encode(writer)?;
+        len += self.port.to_be_bytes().consensus_encode(writer)?;
+        Ok(len)
+    }
+}
+
+impl Decodable for AddrV2Message {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        let time = u32::consensus_decode(reader)?;
+        let services = ServiceFlags::from_bits(consensus::read_compact_size(reader)?);
+        let network = u8::consensus_decode(reader)?;
+        let len = consensus::read_compact_size(reader)? as usize;
+        if len > MAX_ADDRV2_SIZE {
+            return Err(Error::InvalidMessage("address too long"));
+        }
+
+        let mut bytes = vec![0; len];
+        reader.read_exact(&mut bytes)?;
+        Ok(Self {
+            time,
+            services,
+            addr: AddrV2::from_bytes(network, &bytes)?,
+            port: u16::from_be_bytes(<[u8; 2]>::consensus_decode(reader)?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use hex_literal::hex;
+
+    use crate::consensus;
+
+    use super::*;
+
+    #[test]
+    fn ipv4_mapped() -> Result<()> {
+        let socket: SocketAddr = "10.0.0.1:8333".parse()?;
+        let address = NetAddress::new(socket, ServiceFlags::NETWORK);
+        assert_eq!(
+            consensus::serialize(&address)?,
+            hex!("010000000000000000000000000000000000ffff0a000001208d")
+        );
+        assert_eq!(address.socket_addr(), socket);
+
+        let decoded: NetAddress = consensus::deserialize(&consensus::serialize(&address)?)?;
+        assert_eq!(decoded, address);
+
+        let socket: SocketAddr = "[2001:db8::1]:18444".parse()?;
+        assert_eq!(
+            NetAddress::new(socket, ServiceFlags::NONE).socket_addr(),
+            socket
+        );
+
+        let services = ServiceFlags::NETWORK | ServiceFlags::WITNESS;
+        assert_eq!(services.bits(), 9);
+        assert!(services.contains(ServiceFlags::WITNESS));
+        assert!(!services.contains(ServiceFlags::NETWORK | ServiceFlags::BLOOM));
+        Ok(())
+    }
+
+    #[test]
+    fn addrv2() -> Result<()> {
+        assert_eq!(
+            crate::utils::sha3_256(b"abc"),
+            hex!("3a985da74fe225b2045c172d6bd390bd855f086e3e9d525b46bfe24511431532")
+        );
+
+        // from Bitcoin Core's netbase tests
+        let onion = "pg6mmjiyjmcrsslvykfwnntlaru7p5svn6y2ymmju6nubxndf4pscryd.onion";
+        let torv3: AddrV2 = onion.parse()?;
+        assert!(matches!(torv3, AddrV2::TorV3(_)));
+        assert_eq!(torv3.to_string(), onion);
+        assert_eq!(torv3.ip(), None);
+        // one character off
+        let typo = "pg6mmjiyjmcrsslvykfwnntlaru7p5svn6y2ymmju6nubxndf4pscryb.onion";
+        assert!(typo.parse::<AddrV2>().is_err());
+
+        let i2p = "ukeu3k5oycgaauneqgtnvselmt4yemvoilkln7jpvamvfx7dnkdq.b32.i2p";
+        let address: AddrV2 = i2p.parse()?;
+        assert!(matches!(address, AddrV2::I2p(_)));
+        assert_eq!(address.to_string(), i2p);
+        assert_eq!(
+            i2p.to_uppercase()
+                .replace(".B32.I2P", ".b32.i2p")
+                .parse::<AddrV2>()?,
+            address
+        );
+        assert!(
+            "ukeu3k5oycgaauneqgtnvselmt4yemvoilkln7jpvamvfx7dnkd.b32.i2p"
+                .parse::<AddrV2>()
+                .is_err()
+        );
+
+        assert_eq!(
+            "::ffff:10.0.0.1".parse::<AddrV2>()?,
+            AddrV2::Ipv4(Ipv4Addr::new(10, 0, 0, 1))
+        );
+
+        let message = AddrV2Message::new(0x5f00_0000, ServiceFlags::NETWORK, torv3.clone(), 8333);
+        let bytes = consensus::serialize(&message)?;
+        assert_eq!(bytes[..7], hex!("0000005f010420"));
+        assert_eq!(bytes[39..], hex!("208d"));
+        assert_eq!(consensus::deserialize::<AddrV2Message>(&bytes)?, message);
+        assert_eq!(message.to_net_address(), None);
+
+        let ipv4 = AddrV2Message::new(
+            0,
+            ServiceFlags::WITNESS,
+            AddrV2::Ipv4(Ipv4Addr::new(1, 2, 3, 4)),
+            8333,
+        );
+        let bytes = consensus::serialize(&ipv4)?;
+        assert_eq!(bytes, hex!("0000000008010401020304208d"));
+        assert_eq!(
+            ipv4.to_net_address(),
+            Some(NetAddress::new(
+                "1.2.3.4:8333".parse()?,
+                ServiceFlags::WITNESS
+            ))
+        );
+
+        // known networks of the wrong length, unknown ones of any
+        assert!(
+            consensus::deserialize::<AddrV2Message>(&hex!("00000000000103010203208d")).is_err()
+        );
+        let unknown: AddrV2Message = consensus::deserialize(&hex!("00000000002a03010203208d"))?;
+        assert_eq!(
+            unknown.addr,
+            AddrV2::Unknown {
+                network: 42,
+                bytes: vec![1, 2, 3]
+            }
+        );
+        Ok(())
+    }
+}
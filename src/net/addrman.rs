@@ -0,0 +1,419 @@
+//! A lighter take on Bitcoin Core's `AddrMan`: the addresses heard of, how
+//! connecting to them went, and a file to keep them in between runs
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+
+use rand::seq::SliceRandom;
+
+use crate::consensus::{self, Decodable, Encodable};
+use crate::{Error, Result};
+
+use super::address::{AddrV2Message, NetAddress, ServiceFlags};
+
+/// Most addresses kept, those seen the longest ago make room for new ones
+pub const MAX_ADDRESSES: usize = 20_000;
+
+/// Seconds after which an address not heard of again is useless (30 days)
+const HORIZON: u32 = 30 * 24 * 60 * 60;
+/// Failed attempts after which an address that never worked is useless
+const MAX_RETRIES: u32 = 3;
+/// Failed attempts after which an address that didn't work in a week is useless
+const MAX_FAILURES: u32 = 10;
+const MIN_FAIL: u32 = 7 * 24 * 60 * 60;
+/// Seconds ahead of now past which a time is bogus
+const MAX_FUTURE: u32 = 10 * 60;
+/// Seconds after an attempt during which the address is given a chance
+const RECENT_ATTEMPT: u32 = 60;
+/// Of the file format
+const FORMAT_VERSION: u8 = 1;
+
+/// An address and its history, times are seconds since the Unix epoch and
+/// zero for never
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddressEntry {
+    pub(crate) address: NetAddress,
+    pub(crate) last_seen: u32,
+    /// Failed attempts since the last success
+    pub(crate) attempts: u32,
+    pub(crate) last_attempt: u32,
+    pub(crate) last_success: u32,
+}
+
+impl AddressEntry {
+    pub fn address(&self) -> &NetAddress {
+        &self.address
+    }
+
+    /// Last time it was heard of
+    pub fn last_seen(&self) -> u32 {
+        self.last_seen
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    pub fn last_success(&self) -> u32 {
+        self.last_success
+    }
+
+    /// Whether it isn't worth keeping: too old, from the future, or failing
+    /// too often, as Bitcoin Core's `IsTerrible`
+    pub fn is_terrible(&self, now: u32) -> bool {
+        if self.last_attempt != 0 && now.saturating_sub(self.last_attempt) < RECENT_ATTEMPT {
+            return false;
+        }
+
+        self.last_seen > now.saturating_add(MAX_FUTURE)
+            || now.saturating_sub(self.last_seen) > HORIZON
+            || (self.last_success == 0 && self.attempts >= MAX_RETRIES)
+            || (now.saturating_sub(self.last_success) > MIN_FAIL && self.attempts >= MAX_FAILURES)
+    }
+}
+
+impl Encodable for AddressEntry {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        Ok(self.address.consensus_encode(writer)?
+            + self.last_seen.consensus_encode(writer)?
+            + self.attempts.consensus_encode(writer)?
+            + self.last_attempt.consensus_encode(writer)?
+            + self.last_success.consensus_encode(writer)?)
+    }
+}
+
+impl Decodable for AddressEntry {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        Ok(Self {
+            address: NetAddress::consensus_decode(reader)?,
+            last_seen: u32::consensus_decode(reader)?,
+            attempts: u32::consensus_decode(reader)?,
+            last_attempt: u32::consensus_decode(reader)?,
+            last_success: u32::consensus_decode(reader)?,
+        })
+    }
+}
+
+/// Addresses to connect to, from seeds and the gossip of peers
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AddressBook {
+    pub(crate) entries: HashMap<SocketAddr, AddressEntry>,
+}
+
+impl AddressBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, address: &SocketAddr) -> Option<&AddressEntry> {
+        self.entries.get(address)
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &AddressEntry> {
+        self.entries.values()
+    }
+
+    /// Heard of `address` at `time`, its services are added to those known.
+    /// Whether it's new
+    pub fn add(&mut self, address: NetAddress, time: u32, now: u32) -> bool {
+        // peers lie about times in the future
+        let time = if time > now.saturating_add(MAX_FUTURE) {
+            now
+        } else {
+            time
+        };
+
+        let socket = address.socket_addr();
+        if let Some(entry) = self.entries.get_mut(&socket) {
+            entry.last_seen = entry.last_seen.max(time);
+            entry.address.services |= address.services;
+            return false;
+        }
+
+        if self.entries.len() >= MAX_ADDRESSES {
+            let oldest = self
+                .entries
+                .values()
+                .min_by_key(|entry| entry.last_seen)
+                .map(|entry| entry.address.socket_addr());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        self.entries.insert(
+            socket,
+            AddressEntry {
+                address,
+                last_seen: time,
+                attempts: 0,
+                last_attempt: 0,
+                last_success: 0,
+            },
+        );
+        true
+    }
+
+    /// The addresses of an `addr` message, or seeds at `now`. How many were new
+    pub fn add_many(
+        &mut self,
+        addresses: impl IntoIterator<Item = (u32, NetAddress)>,
+        now: u32,
+    ) -> usize {
+        addresses
+            .into_iter()
+            .filter(|(time, address)| self.add(*address, *time, now))
+            .count()
+    }
+
+    /// The addresses of an `addrv2` message. Only IP ones can be kept, those
+    /// of Tor, I2P and CJDNS are skipped. How many were new
+    pub fn add_many_v2<'a>(
+        &mut self,
+        addresses: impl IntoIterator<Item = &'a AddrV2Message>,
+        now: u32,
+    ) -> usize {
+        addresses
+            .into_iter()
+            .filter_map(|message| Some((message.time, message.to_net_address()?)))
+            .filter(|(time, address)| self.add(*address, *time, now))
+            .count()
+    }
+
+    /// Connecting to `address` is being tried
+    pub fn attempt(&mut self, address: &SocketAddr, now: u32) {
+        if let Some(entry) = self.entries.get_mut(address) {
+            entry.attempts += 1;
+            entry.last_attempt = now;
+        }
+    }
+
+    /// The handshake with `address` succeeded
+    pub fn good(&mut self, address: &SocketAddr, now: u32) {
+        if let Some(entry) = self.entries.get_mut(address) {
+            entry.attempts = 0;
+            entry.last_attempt = now;
+            entry.last_success = now;
+            entry.last_seen = now;
+        }
+    }
+
+    pub fn remove(&mut self, address: &SocketAddr) -> Option<AddressEntry> {
+        self.entries.remove(address)
+    }
+
+    /// Drops the terrible addresses, returning how many
+    pub fn prune(&mut self, now: u32) -> usize {
+        let len = self.entries.len();
+        self.entries.retain(|_, entry| !entry.is_terrible(now));
+        len - self.entries.len()
+    }
+
+    /// Up to `count` random addresses known to serve `services`, terrible
+    /// ones left out
+    pub fn select(&self, services: ServiceFlags, count: usize, now: u32) -> Vec<NetAddress> {
+        let mut candidates: Vec<_> = self
+            .entries
+            .values()
+            .filter(|entry| entry.address.services.contains(services) && !entry.is_terrible(now))
+            .map(|entry| entry.address)
+            .collect();
+
+        candidates.shuffle(&mut rand::thread_rng());
+        candidates.truncate(count);
+        candidates
+    }
+
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        consensus::serialize(self)
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Self> {
+        consensus::deserialize(bytes)
+    }
+
+    /// Writes to a temporary file first, so a crash never leaves half of it
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, self.serialize()?)?;
+        fs::rename(&temporary, path)?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Self::deserialize(&fs::read(path)?)
+    }
+}
+
+/// The format version and every entry
+impl Encodable for AddressBook {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        let entries: Vec<_> = self.entries.values().copied().collect();
+        Ok(FORMAT_VERSION.consensus_encode(writer)? + entries.consensus_encode(writer)?)
+    }
+}
+
+impl Decodable for AddressBook {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        if u8::consensus_decode(reader)? != FORMAT_VERSION {
+            return Err(Error::InvalidAddressBook("unknown version"));
+        }
+
+        let entries: Vec<AddressEntry> = Vec::consensus_decode(reader)?;
+        if entries.len() > MAX_ADDRESSES {
+            return Err(Error::InvalidAddressBook("too many addresses"));
+        }
+
+        Ok(Self {
+            entries: entries
+                .into_iter()
+                .map(|entry| (entry.address.socket_addr(), entry))
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    use anyhow::Result;
+
+    use super::*;
+
+    const NOW: u32 = 1_700_000_000;
+
+    fn address(n: u32, services: ServiceFlags) -> NetAddress {
+        let socket = SocketAddrV4::new(Ipv4Addr::from(0x0a00_0000 + n), 8333);
+        NetAddress::new(socket.into(), services)
+    }
+
+    #[test]
+    fn add_and_select() {
+        let mut book = AddressBook::new();
+        let full = ServiceFlags::NETWORK | ServiceFlags::WITNESS;
+        assert_eq!(
+            book.add_many((0..10).map(|n| (NOW - n, address(n, full))), NOW),
+            10
+        );
+        assert!(book.add(address(10, ServiceFlags::NETWORK), NOW, NOW));
+
+        // known addresses are refreshed, their services merged
+        assert!(!book.add(address(10, ServiceFlags::BLOOM), NOW + 1, NOW));
+        let entry = book
+            .get(&address(10, ServiceFlags::NONE).socket_addr())
+            .unwrap();
+        assert_eq!(entry.last_seen(), NOW + 1);
+        assert!(entry.address().services.contains(ServiceFlags::BLOOM));
+        assert_eq!(book.len(), 11);
+
+        assert_eq!(book.select(ServiceFlags::WITNESS, 20, NOW).len(), 10);
+        assert_eq!(book.select(ServiceFlags::NETWORK, 5, NOW).len(), 5);
+        assert_eq!(book.select(ServiceFlags::BLOOM, 5, NOW).len(), 1);
+
+        // times in the future are taken as now
+        assert!(book.add(address(11, full), NOW + 3600, NOW));
+        let socket = address(11, full).socket_addr();
+        assert_eq!(book.get(&socket).unwrap().last_seen(), NOW);
+    }
+
+    #[test]
+    fn addrv2_gossip() {
+        use crate::net::address::AddrV2;
+
+        let mut book = AddressBook::new();
+        let addresses = [
+            AddrV2Message::new(
+                NOW,
+                ServiceFlags::NETWORK,
+                AddrV2::Ipv4(Ipv4Addr::new(10, 0, 0, 1)),
+                8333,
+            ),
+            AddrV2Message::new(NOW, ServiceFlags::NETWORK, AddrV2::TorV3([1; 32]), 8333),
+            AddrV2Message::new(NOW, ServiceFlags::NETWORK, AddrV2::I2p([2; 32]), 0),
+        ];
+        assert_eq!(book.add_many_v2(&addresses, NOW), 1);
+        assert_eq!(
+            book.entries().next().unwrap().address(),
+            &address(1, ServiceFlags::NETWORK)
+        );
+    }
+
+    #[test]
+    fn attempts_and_pruning() {
+        let mut book = AddressBook::new();
+        let (working, failing, stale) = (
+            address(1, ServiceFlags::NETWORK),
+            address(2, ServiceFlags::NETWORK),
+            address(3, ServiceFlags::NETWORK),
+        );
+        book.add(working, NOW, NOW);
+        book.add(failing, NOW, NOW);
+        book.add(stale, NOW - HORIZON - 1, NOW);
+
+        for _ in 0..MAX_RETRIES {
+            book.attempt(&failing.socket_addr(), NOW);
+            book.attempt(&working.socket_addr(), NOW);
+        }
+        book.good(&working.socket_addr(), NOW);
+        assert_eq!(book.get(&working.socket_addr()).unwrap().attempts(), 0);
+
+        // just tried, then given up on
+        assert!(!book.get(&failing.socket_addr()).unwrap().is_terrible(NOW));
+        let later = NOW + RECENT_ATTEMPT;
+        assert!(book.get(&failing.socket_addr()).unwrap().is_terrible(later));
+        assert_eq!(book.select(ServiceFlags::NETWORK, 10, later), vec![working]);
+
+        assert_eq!(book.prune(later), 2);
+        assert_eq!(book.len(), 1);
+        assert!(book.remove(&working.socket_addr()).is_some());
+        assert!(book.is_empty());
+    }
+
+    #[test]
+    fn eviction() {
+        let mut book = AddressBook::new();
+        for n in 0..MAX_ADDRESSES as u32 {
+            book.add(address(n, ServiceFlags::NETWORK), NOW - n, NOW);
+        }
+
+        // the one seen the longest ago goes
+        book.add(address(u32::MAX >> 8, ServiceFlags::NETWORK), NOW, NOW);
+        assert_eq!(book.len(), MAX_ADDRESSES);
+        let oldest = address(MAX_ADDRESSES as u32 - 1, ServiceFlags::NONE);
+        assert!(book.get(&oldest.socket_addr()).is_none());
+    }
+
+    #[test]
+    fn persistence() -> Result<()> {
+        let mut book = AddressBook::new();
+        book.add_many(
+            (0..5).map(|n| (NOW, address(n, ServiceFlags::NETWORK))),
+            NOW,
+        );
+        book.good(&address(0, ServiceFlags::NONE).socket_addr(), NOW);
+
+        let path = std::env::temp_dir().join(format!("addrman-{}.dat", std::process::id()));
+        book.save(&path)?;
+        let loaded = AddressBook::load(&path)?;
+        fs::remove_file(&path)?;
+        assert_eq!(loaded, book);
+
+        let mut bytes = book.serialize()?;
+        bytes[0] = 2;
+        assert!(AddressBook::deserialize(&bytes).is_err());
+        Ok(())
+    }
+}
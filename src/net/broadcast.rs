@@ -0,0 +1,328 @@
+//! Getting a transaction of ours to the network: it's announced with `inv`,
+//! sent when the peer asks for it with `getdata`, and announced again until a
+//! peer announces it back or it shows up in a block
+
+use std::time::{Duration, Instant};
+
+use crate::amount::Amount;
+use crate::core::fee::FeeRate;
+use crate::core::tx::Transaction;
+use crate::core::txid::{BlockHash, Txid, Wtxid};
+use crate::{Error, Result};
+
+use super::handshake::PeerFeatures;
+use super::message::{Inventory, NetworkMessage};
+
+/// Time between announcements of a transaction nobody took up yet
+pub const REBROADCAST_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// How a broadcast ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BroadcastStatus {
+    /// A peer announced it, it's in the mempool of the peer
+    Seen,
+    /// It's in this block
+    Confirmed(BlockHash),
+    /// A peer turned it down with `reject`, see [`RejectMessage`](super::message::RejectMessage)
+    Rejected { code: u8, reason: String },
+}
+
+/// A transaction being broadcast, without doing any IO itself: announcements
+/// are handed out and messages of the peers taken in until the status is known
+#[derive(Debug, Clone)]
+pub struct TxBroadcast {
+    pub(crate) tx: Transaction,
+    pub(crate) txid: Txid,
+    pub(crate) wtxid: Wtxid,
+    /// Unknown unless the fee was given
+    pub(crate) fee_rate: Option<FeeRate>,
+    pub(crate) rebroadcast_interval: Duration,
+    pub(crate) last_announced: Option<Instant>,
+    /// Whether a peer asked for it with `getdata`
+    pub(crate) requested: bool,
+    pub(crate) status: Option<BroadcastStatus>,
+}
+
+impl TxBroadcast {
+    pub fn new(tx: Transaction) -> Result<Self> {
+        Ok(Self {
+            txid: tx.txid()?,
+            wtxid: tx.wtxid()?,
+            tx,
+            fee_rate: None,
+            rebroadcast_interval: REBROADCAST_INTERVAL,
+            last_announced: None,
+            requested: false,
+            status: None,
+        })
+    }
+
+    /// Peers whose `feefilter` is above the fee rate it pays aren't announced
+    /// the transaction, they'd ignore it
+    pub fn with_fee(mut self, fee: Amount) -> Result<Self> {
+        self.fee_rate = Some(self.tx.fee_rate(fee)?);
+        Ok(self)
+    }
+
+    /// Time between announcements, [`REBROADCAST_INTERVAL`] unless set
+    pub fn with_rebroadcast_interval(mut self, rebroadcast_interval: Duration) -> Self {
+        self.rebroadcast_interval = rebroadcast_interval;
+        self
+    }
+
+    pub fn tx(&self) -> &Transaction {
+        &self.tx
+    }
+
+    pub fn txid(&self) -> &Txid {
+        &self.txid
+    }
+
+    /// Whether a peer asked for the transaction after its announcement
+    pub fn was_requested(&self) -> bool {
+        self.requested
+    }
+
+    /// None until the broadcast is over
+    pub fn status(&self) -> Option<&BroadcastStatus> {
+        self.status.as_ref()
+    }
+
+    /// When the next announcement is due, none once the broadcast is over
+    pub fn next_announcement_at(&self) -> Option<Instant> {
+        match (&self.status, self.last_announced) {
+            (Some(_), _) => None,
+            (None, Some(last)) => Some(last + self.rebroadcast_interval),
+            // right away
+            (None, None) => Some(Instant::now()),
+        }
+    }
+
+    /// The `inv` for a peer with `features` if one is due at `now`, by wtxid
+    /// if the peer relays them so. Fails if the fee rate is under the
+    /// `feefilter` of the peer
+    pub fn next_announcement(
+        &mut self,
+        now: Instant,
+        features: &PeerFeatures,
+    ) -> Result<Option<NetworkMessage>> {
+        if self.status.is_some() {
+            return Ok(None);
+        }
+        if self
+            .fee_rate
+            .is_some_and(|fee_rate| fee_rate < features.fee_filter)
+        {
+            return Err(Error::BroadcastFailed(
+                "fee rate under the feefilter of the peer",
+            ));
+        }
+
+        let due = self
+            .last_announced
+            .is_none_or(|last| now.saturating_duration_since(last) >= self.rebroadcast_interval);
+        if !due {
+            return Ok(None);
+        }
+
+        self.last_announced = Some(now);
+        let item = match features.wtxid_relay {
+            true => Inventory::WTx(self.wtxid),
+            false => Inventory::Tx(self.txid),
+        };
+        Ok(Some(NetworkMessage::Inv(vec![item])))
+    }
+
+    /// Takes a message of a peer, returning the answer to it if any: the
+    /// transaction when it's asked for, and the blocks announced to look for
+    /// it in
+    pub fn receive(&mut self, message: &NetworkMessage) -> Option<NetworkMessage> {
+        let is_ours = |item: &Inventory| {
+            *item == Inventory::Tx(self.txid)
+                || *item == Inventory::WitnessTx(self.txid)
+                || *item == Inventory::WTx(self.wtxid)
+        };
+
+        match message {
+            NetworkMessage::GetData(items) if items.iter().any(is_ours) => {
+                self.requested = true;
+                return Some(NetworkMessage::Tx(self.tx.clone()));
+            }
+            NetworkMessage::Inv(items) if items.iter().any(is_ours) => {
+                self.status.get_or_insert(BroadcastStatus::Seen);
+            }
+            NetworkMessage::Inv(items) if self.status.is_none() => {
+                let blocks: Vec<_> = items
+                    .iter()
+                    .filter(|item| matches!(item, Inventory::Block(_)))
+                    .copied()
+                    .collect();
+                if !blocks.is_empty() {
+                    return Some(NetworkMessage::GetData(blocks));
+                }
+            }
+            NetworkMessage::Headers(headers) if self.status.is_none() && !headers.is_empty() => {
+                let blocks = headers
+                    .iter()
+                    .map(|header| Inventory::Block(header.block_hash()))
+                    .collect();
+                return Some(NetworkMessage::GetData(blocks));
+            }
+            NetworkMessage::Block(block) => {
+                let confirmed = block
+                    .transactions()
+                    .iter()
+                    .any(|tx| tx.txid().is_ok_and(|txid| txid == self.txid));
+                if confirmed {
+                    self.status = Some(BroadcastStatus::Confirmed(block.block_hash()));
+                }
+            }
+            NetworkMessage::Reject(reject)
+                if reject.message == "tx" && reject.data == Some(self.txid.to_bytes()) =>
+            {
+                self.status = Some(BroadcastStatus::Rejected {
+                    code: reject.code,
+                    reason: reject.reason.clone(),
+                });
+            }
+            _ => {}
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::core::fee::FeeRate;
+    use crate::core::input::{OutPoint, TxIn};
+    use crate::core::mining::mine_block;
+    use crate::core::output::TxOut;
+    use crate::core::script::Script;
+    use crate::net::message::RejectMessage;
+    use crate::net::PROTOCOL_VERSION;
+    use crate::network::Network;
+
+    use super::*;
+
+    fn transaction() -> Transaction {
+        let input = TxIn::new(OutPoint::new(Txid::from_bytes([1; 32]), 0));
+        let output = TxOut::new(Amount::from_sat(50_000), Script::p2wpkh(&[2; 20]));
+        Transaction::new(2, vec![input], vec![output], 0)
+    }
+
+    fn features(wtxid_relay: bool, fee_filter: FeeRate) -> PeerFeatures {
+        PeerFeatures {
+            version: PROTOCOL_VERSION,
+            services: Default::default(),
+            user_agent: String::new(),
+            start_height: 0,
+            relay: true,
+            send_headers: true,
+            wtxid_relay,
+            addrv2: true,
+            compact_blocks: true,
+            high_bandwidth: false,
+            fee_filter,
+        }
+    }
+
+    #[test]
+    fn announce_and_send() -> Result<()> {
+        let tx = transaction();
+        let (txid, wtxid) = (tx.txid()?, tx.wtxid()?);
+        let interval = Duration::from_secs(60);
+        let mut broadcast = TxBroadcast::new(tx.clone())?.with_rebroadcast_interval(interval);
+        let legacy = features(false, FeeRate::ZERO);
+
+        // announced right away, then once per interval
+        let now = Instant::now();
+        assert_eq!(
+            broadcast.next_announcement(now, &legacy)?,
+            Some(NetworkMessage::Inv(vec![Inventory::Tx(txid)]))
+        );
+        assert_eq!(broadcast.next_announcement(now, &legacy)?, None);
+        assert_eq!(broadcast.next_announcement_at(), Some(now + interval));
+        assert_eq!(
+            broadcast.next_announcement(now + interval, &features(true, FeeRate::ZERO))?,
+            Some(NetworkMessage::Inv(vec![Inventory::WTx(wtxid)]))
+        );
+
+        // asked for by either id
+        let getdata = NetworkMessage::GetData(vec![Inventory::WTx(wtxid)]);
+        assert_eq!(
+            broadcast.receive(&getdata),
+            Some(NetworkMessage::Tx(tx.clone()))
+        );
+        assert!(broadcast.was_requested());
+        let other = NetworkMessage::GetData(vec![Inventory::Tx(Txid::from_bytes([9; 32]))]);
+        assert_eq!(broadcast.receive(&other), None);
+        assert_eq!(broadcast.status(), None);
+
+        // until a peer announces it
+        broadcast.receive(&NetworkMessage::Inv(vec![Inventory::Tx(txid)]));
+        assert_eq!(broadcast.status(), Some(&BroadcastStatus::Seen));
+        assert_eq!(
+            broadcast.next_announcement(now + 2 * interval, &legacy)?,
+            None
+        );
+        assert_eq!(broadcast.next_announcement_at(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn confirmation_rejection_and_fee_filter() -> Result<()> {
+        let tx = transaction();
+        let genesis = Network::Regtest.params().genesis_block();
+        let block = mine_block(
+            genesis.header(),
+            1,
+            std::slice::from_ref(&tx),
+            Script::new(),
+        )?;
+        let mut broadcast = TxBroadcast::new(tx.clone())?;
+        let getdata = Some(NetworkMessage::GetData(vec![Inventory::Block(
+            block.block_hash(),
+        )]));
+        let inv = NetworkMessage::Inv(vec![Inventory::Block(block.block_hash())]);
+        assert_eq!(broadcast.receive(&inv), getdata);
+        let headers = NetworkMessage::Headers(vec![*block.header()]);
+        assert_eq!(broadcast.receive(&headers), getdata);
+        broadcast.receive(&NetworkMessage::Block(genesis.clone()));
+        assert_eq!(broadcast.status(), None);
+        broadcast.receive(&NetworkMessage::Block(block.clone()));
+        assert_eq!(
+            broadcast.status(),
+            Some(&BroadcastStatus::Confirmed(block.block_hash()))
+        );
+
+        let mut broadcast = TxBroadcast::new(tx.clone())?;
+        let reject = |data| RejectMessage {
+            message: "tx".to_string(),
+            code: RejectMessage::INSUFFICIENT_FEE,
+            reason: "min relay fee not met".to_string(),
+            data: Some(data),
+        };
+        broadcast.receive(&NetworkMessage::Reject(reject([0; 32])));
+        assert_eq!(broadcast.status(), None);
+        broadcast.receive(&NetworkMessage::Reject(reject(tx.txid()?.to_bytes())));
+        assert_eq!(
+            broadcast.status(),
+            Some(&BroadcastStatus::Rejected {
+                code: RejectMessage::INSUFFICIENT_FEE,
+                reason: "min relay fee not met".to_string(),
+            })
+        );
+
+        // paying 1000 sats over some 100 vbytes, about 10 sat/vB
+        let mut broadcast = TxBroadcast::new(tx)?.with_fee(Amount::from_sat(1_000))?;
+        let now = Instant::now();
+        let picky = features(false, FeeRate::from_sat_per_vb(20));
+        assert!(broadcast.next_announcement(now, &picky).is_err());
+        let cheap = features(false, FeeRate::from_sat_per_vb(5));
+        assert!(broadcast.next_announcement(now, &cheap)?.is_some());
+        Ok(())
+    }
+}
@@ -0,0 +1,558 @@
+//! Compact block relay (BIP152): blocks announced as their header and short
+//! ids of their transactions, which the receiver mostly has in its mempool
+//! already. Only what's missing is asked for with `getblocktxn`
+//!
+//! Only version 2 is spoken, short ids of wtxids
+
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+
+use sha2::{Digest, Sha256};
+
+use crate::consensus::{self, Decodable, Encodable};
+use crate::core::block::{Block, BlockHeader, MAX_BLOCK_WEIGHT};
+use crate::core::filter::siphash24;
+use crate::core::tx::Transaction;
+use crate::core::txid::{BlockHash, Wtxid};
+use crate::{Error, Result};
+
+/// Version of `sendcmpct` with short ids of wtxids, the only one spoken
+pub const COMPACT_BLOCK_VERSION: u64 = 2;
+/// Most transactions a block can have, each weighing at least 40
+pub const MAX_TX_COUNT: usize = MAX_BLOCK_WEIGHT / 40;
+/// Bytes of each short id
+pub const SHORT_ID_SIZE: usize = 6;
+
+/// Payload of the `sendcmpct` message: compact blocks of `version` are
+/// understood, and new blocks are to be announced with `cmpctblock` right
+/// away if `announce` is set (high bandwidth mode)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendCmpctMessage {
+    pub announce: bool,
+    pub version: u64,
+}
+
+impl SendCmpctMessage {
+    /// Of [`COMPACT_BLOCK_VERSION`]
+    pub fn new(announce: bool) -> Self {
+        Self {
+            announce,
+            version: COMPACT_BLOCK_VERSION,
+        }
+    }
+}
+
+impl Encodable for SendCmpctMessage {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        Ok(self.announce.consensus_encode(writer)? + self.version.consensus_encode(writer)?)
+    }
+}
+
+impl Decodable for SendCmpctMessage {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        Ok(Self {
+            announce: bool::consensus_decode(reader)?,
+            version: u64::consensus_decode(reader)?,
+        })
+    }
+}
+
+/// Indexes go each as the difference with the one before minus one
+fn encode_index<W: Write + ?Sized>(
+    writer: &mut W,
+    index: usize,
+    last: Option<usize>,
+) -> Result<usize> {
+    let differential = match last {
+        Some(last) if index <= last => {
+            return Err(Error::InvalidCompactBlock("indexes out of order"))
+        }
+        Some(last) => index - last - 1,
+        None => index,
+    };
+    consensus::write_compact_size(writer, differential as u64)
+}
+
+fn decode_index<R: Read + ?Sized>(reader: &mut R, last: Option<usize>) -> Result<usize> {
+    let differential = consensus::read_compact_size(reader)?;
+    let index = last
+        .map_or(0, |last| last as u64 + 1)
+        .checked_add(differential)
+        .ok_or(Error::InvalidCompactBlock("index out of range"))?;
+    // as Bitcoin Core, that keeps them in 16 bits
+    match index > u64::from(u16::MAX) {
+        true => Err(Error::InvalidCompactBlock("index out of range")),
+        false => Ok(index as usize),
+    }
+}
+
+/// A transaction sent along with the short ids, at `index` in the block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrefilledTransaction {
+    pub index: usize,
+    pub tx: Transaction,
+}
+
+/// Payload of the `cmpctblock` message, a block as its header and the short
+/// ids of its transactions but for those prefilled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactBlock {
+    pub header: BlockHeader,
+    /// Salt of the short ids, random
+    pub nonce: u64,
+    /// Of the transactions not prefilled, in order
+    pub short_ids: Vec<u64>,
+    /// By increasing index
+    pub prefilled: Vec<PrefilledTransaction>,
+}
+
+impl CompactBlock {
+    /// Of `block` with the coinbase prefilled, as Bitcoin Core sends them
+    pub fn new(block: &Block, nonce: u64) -> Result<Self> {
+        let (coinbase, txs) = block
+            .transactions()
+            .split_first()
+            .ok_or(Error::InvalidBlock("missing coinbase"))?;
+
+        let mut compact = Self {
+            header: *block.header(),
+            nonce,
+            short_ids: Vec::with_capacity(txs.len()),
+            prefilled: vec![PrefilledTransaction {
+                index: 0,
+                tx: coinbase.clone(),
+            }],
+        };
+        let key = compact.short_id_key();
+        for tx in txs {
+            compact.short_ids.push(short_id(key, &tx.wtxid()?));
+        }
+
+        Ok(compact)
+    }
+
+    pub fn block_hash(&self) -> BlockHash {
+        self.header.block_hash()
+    }
+
+    pub fn tx_count(&self) -> usize {
+        self.short_ids.len() + self.prefilled.len()
+    }
+
+    /// The SipHash key, the first half of the SHA256 of the header and nonce
+    fn short_id_key(&self) -> (u64, u64) {
+        let digest = Sha256::new()
+            .chain(self.header.serialize())
+            .chain(self.nonce.to_le_bytes())
+            .finalize();
+
+        let mut k0 = [0; 8];
+        let mut k1 = [0; 8];
+        k0.copy_from_slice(&digest[..8]);
+        k1.copy_from_slice(&digest[8..16]);
+        (u64::from_le_bytes(k0), u64::from_le_bytes(k1))
+    }
+
+    /// The short id of the transaction of `wtxid` in this block
+    pub fn short_id(&self, wtxid: &Wtxid) -> u64 {
+        short_id(self.short_id_key(), wtxid)
+    }
+}
+
+/// The lower 6 bytes of the SipHash of `wtxid`
+fn short_id(key: (u64, u64), wtxid: &Wtxid) -> u64 {
+    siphash24(key.0, key.1, wtxid.as_ref()) & 0xffff_ffff_ffff
+}
+
+impl Encodable for CompactBlock {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        let mut len =
+            self.header.consensus_encode(writer)? + self.nonce.consensus_encode(writer)?;
+
+        len += consensus::write_compact_size(writer, self.short_ids.len() as u64)?;
+        for short_id in &self.short_ids {
+            writer.write_all(&short_id.to_le_bytes()[..SHORT_ID_SIZE])?;
+            len += SHORT_ID_SIZE;
+        }
+
+        len += consensus::write_compact_size(writer, self.prefilled.len() as u64)?;
+        let mut last = None;
+        for prefilled in &self.prefilled {
+            len += encode_index(writer, prefilled.index, last)?;
+            len += prefilled.tx.consensus_encode(writer)?;
+            last = Some(prefilled.index);
+        }
+
+        Ok(len)
+    }
+}
+
+impl Decodable for CompactBlock {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        let header = BlockHeader::consensus_decode(reader)?;
+        let nonce = u64::consensus_decode(reader)?;
+
+        let len = consensus::read_compact_size(reader)? as usize;
+        if len > MAX_TX_COUNT {
+            return Err(Error::InvalidCompactBlock("too many short ids"));
+        }
+        let mut short_ids = Vec::with_capacity(len);
+        for _ in 0..len {
+            let mut bytes = [0; 8];
+            reader.read_exact(&mut bytes[..SHORT_ID_SIZE])?;
+            short_ids.push(u64::from_le_bytes(bytes));
+        }
+
+        let len = consensus::read_compact_size(reader)? as usize;
+        if len > MAX_TX_COUNT {
+            return Err(Error::InvalidCompactBlock(
+                "too many prefilled transactions",
+            ));
+        }
+        let mut prefilled: Vec<PrefilledTransaction> = Vec::with_capacity(len);
+        for _ in 0..len {
+            let index = decode_index(reader, prefilled.last().map(|last| last.index))?;
+            let tx = Transaction::consensus_decode(reader)?;
+            prefilled.push(PrefilledTransaction { index, tx });
+        }
+
+        Ok(Self {
+            header,
+            nonce,
+            short_ids,
+            prefilled,
+        })
+    }
+}
+
+/// Payload of the `getblocktxn` message, the transactions of a block missing
+/// to rebuild it from its `cmpctblock`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GetBlockTxnMessage {
+    pub block_hash: BlockHash,
+    /// By increasing index
+    pub indexes: Vec<usize>,
+}
+
+impl GetBlockTxnMessage {
+    /// The transactions asked for, failing if `block` doesn't have them
+    pub fn answer(&self, block: &Block) -> Result<BlockTxnMessage> {
+        if block.block_hash() != self.block_hash {
+            return Err(Error::InvalidCompactBlock("getblocktxn of another block"));
+        }
+
+        let transactions = self
+            .indexes
+            .iter()
+            .map(|index| block.transactions().get(*index).cloned())
+            .collect::<Option<_>>()
+            .ok_or(Error::InvalidCompactBlock("index out of range"))?;
+
+        Ok(BlockTxnMessage {
+            block_hash: self.block_hash,
+            transactions,
+        })
+    }
+}
+
+impl Encodable for GetBlockTxnMessage {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        let mut len = self.block_hash.consensus_encode(writer)?;
+        len += consensus::write_compact_size(writer, self.indexes.len() as u64)?;
+
+        let mut last = None;
+        for index in &self.indexes {
+            len += encode_index(writer, *index, last)?;
+            last = Some(*index);
+        }
+        Ok(len)
+    }
+}
+
+impl Decodable for GetBlockTxnMessage {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        let block_hash = BlockHash::consensus_decode(reader)?;
+        let len = consensus::read_compact_size(reader)? as usize;
+        if len > MAX_TX_COUNT {
+            return Err(Error::InvalidCompactBlock("too many indexes"));
+        }
+
+        let mut indexes: Vec<usize> = Vec::with_capacity(len);
+        for _ in 0..len {
+            indexes.push(decode_index(reader, indexes.last().copied())?);
+        }
+
+        Ok(Self {
+            block_hash,
+            indexes,
+        })
+    }
+}
+
+/// Payload of the `blocktxn` message, the transactions asked for with
+/// `getblocktxn` in the same order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockTxnMessage {
+    pub block_hash: BlockHash,
+    pub transactions: Vec<Transaction>,
+}
+
+impl Encodable for BlockTxnMessage {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        Ok(self.block_hash.consensus_encode(writer)?
+            + self.transactions.consensus_encode(writer)?)
+    }
+}
+
+impl Decodable for BlockTxnMessage {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        Ok(Self {
+            block_hash: BlockHash::consensus_decode(reader)?,
+            transactions: Vec::consensus_decode(reader)?,
+        })
+    }
+}
+
+/// A block being rebuilt from its `cmpctblock`, Bitcoin Core's
+/// `PartiallyDownloadedBlock`. Failing at any step means asking for the full
+/// block instead
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartialBlock {
+    pub(crate) header: BlockHeader,
+    pub(crate) txs: Vec<Option<Transaction>>,
+}
+
+impl PartialBlock {
+    /// From `compact`, with the transactions of `mempool` its short ids match.
+    /// Those two or more transactions match are left missing
+    pub fn new<'a>(
+        compact: &CompactBlock,
+        mempool: impl IntoIterator<Item = &'a Transaction>,
+    ) -> Result<Self> {
+        let count = compact.tx_count();
+        if count == 0 || count > MAX_TX_COUNT {
+            return Err(Error::InvalidCompactBlock("wrong number of transactions"));
+        }
+
+        let mut txs = vec![None; count];
+        let mut last = None;
+        for prefilled in &compact.prefilled {
+            if prefilled.index >= count || last.is_some_and(|last| prefilled.index <= last) {
+                return Err(Error::InvalidCompactBlock("prefilled index out of range"));
+            }
+            txs[prefilled.index] = Some(prefilled.tx.clone());
+            last = Some(prefilled.index);
+        }
+
+        // the short ids fill the rest in order
+        let slots = (0..count).filter(|index| txs[*index].is_none());
+        let mut by_short_id = HashMap::with_capacity(compact.short_ids.len());
+        for (short_id, slot) in compact.short_ids.iter().zip(slots) {
+            if by_short_id.insert(*short_id, slot).is_some() {
+                return Err(Error::InvalidCompactBlock("short id collision"));
+            }
+        }
+
+        let key = compact.short_id_key();
+        let mut collisions = HashSet::new();
+        for tx in mempool {
+            let slot = match by_short_id.get(&short_id(key, &tx.wtxid()?)) {
+                Some(slot) if !collisions.contains(slot) => *slot,
+                _ => continue,
+            };
+
+            match &txs[slot] {
+                None => txs[slot] = Some(tx.clone()),
+                Some(other) if other != tx => {
+                    txs[slot] = None;
+                    collisions.insert(slot);
+                }
+                Some(_) => {}
+            }
+        }
+
+        Ok(Self {
+            header: compact.header,
+            txs,
+        })
+    }
+
+    pub fn block_hash(&self) -> BlockHash {
+        self.header.block_hash()
+    }
+
+    /// Indexes of the transactions still missing
+    pub fn missing(&self) -> Vec<usize> {
+        (0..self.txs.len())
+            .filter(|index| self.txs[*index].is_none())
+            .collect()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.txs.iter().all(Option::is_some)
+    }
+
+    /// The `getblocktxn` asking for the missing transactions
+    pub fn request(&self) -> GetBlockTxnMessage {
+        GetBlockTxnMessage {
+            block_hash: self.block_hash(),
+            indexes: self.missing(),
+        }
+    }
+
+    /// Takes the `blocktxn` answering [`PartialBlock::request`]
+    pub fn fill(&mut self, message: &BlockTxnMessage) -> Result<()> {
+        if message.block_hash != self.block_hash() {
+            return Err(Error::InvalidCompactBlock("blocktxn of another block"));
+        }
+
+        let missing = self.missing();
+        if message.transactions.len() != missing.len() {
+            return Err(Error::InvalidCompactBlock("wrong number of transactions"));
+        }
+
+        for (index, tx) in missing.into_iter().zip(&message.transactions) {
+            self.txs[index] = Some(tx.clone());
+        }
+        Ok(())
+    }
+
+    /// The block once complete, failing if the header doesn't commit to the
+    /// transactions, as when a short id matched the wrong one
+    pub fn into_block(self) -> Result<Block> {
+        let txs = self
+            .txs
+            .into_iter()
+            .collect::<Option<Vec<_>>>()
+            .ok_or(Error::InvalidCompactBlock("missing transactions"))?;
+
+        let block = Block::new(self.header, txs);
+        block
+            .check_merkle_root()
+            .map_err(|_| Error::InvalidCompactBlock("merkle root mismatch"))?;
+        Ok(block)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::core::input::{OutPoint, TxIn};
+    use crate::core::mining::mine_block;
+    use crate::core::script::Script;
+    use crate::core::txid::Txid;
+    use crate::network::Network;
+
+    use super::*;
+
+    fn block(count: u8) -> Result<Block> {
+        let txs = (1..=count)
+            .map(|i| {
+                let outpoint = OutPoint::new(Txid::from_bytes([i; 32]), 0);
+                Transaction::new(2, vec![TxIn::new(outpoint)], vec![], 0)
+            })
+            .collect::<Vec<_>>();
+        let genesis = Network::Regtest.params().genesis_block();
+        Ok(mine_block(genesis.header(), 1, &txs, Script::new())?)
+    }
+
+    #[test]
+    fn encoding() -> Result<()> {
+        let block = block(3)?;
+        let mut compact = CompactBlock::new(&block, 42)?;
+        assert_eq!(compact.tx_count(), 4);
+        assert_eq!(compact.block_hash(), block.block_hash());
+        assert!(compact.short_ids.iter().all(|id| *id < 1 << 48));
+        assert_eq!(
+            compact.short_ids[1],
+            compact.short_id(&block.transactions()[2].wtxid()?)
+        );
+
+        compact.prefilled.push(PrefilledTransaction {
+            index: 3,
+            tx: block.transactions()[3].clone(),
+        });
+        compact.short_ids.pop();
+        let raw = consensus::serialize(&compact)?;
+        assert_eq!(consensus::deserialize::<CompactBlock>(&raw)?, compact);
+
+        let request = GetBlockTxnMessage {
+            block_hash: block.block_hash(),
+            indexes: vec![1, 2, 5],
+        };
+        let raw = consensus::serialize(&request)?;
+        // differentially encoded
+        assert_eq!(raw[32..], [3, 1, 0, 2]);
+        assert_eq!(consensus::deserialize::<GetBlockTxnMessage>(&raw)?, request);
+        assert!(request.answer(&block).is_err());
+
+        let unordered = GetBlockTxnMessage {
+            block_hash: block.block_hash(),
+            indexes: vec![2, 1],
+        };
+        assert!(consensus::serialize(&unordered).is_err());
+        let mut too_far = raw[..32].to_vec();
+        too_far.extend([1, 0xfd, 0xff, 0xff]);
+        assert!(consensus::deserialize::<GetBlockTxnMessage>(&too_far).is_ok());
+        too_far.extend([0]);
+        too_far[32] = 2;
+        assert!(consensus::deserialize::<GetBlockTxnMessage>(&too_far).is_err());
+
+        // a differential that wraps around past the index before
+        let mut wrapping = raw[..32].to_vec();
+        wrapping.extend([2, 0]);
+        wrapping.push(0xff);
+        wrapping.extend([0xff; 8]);
+        assert!(consensus::deserialize::<GetBlockTxnMessage>(&wrapping).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn reconstruction() -> Result<()> {
+        let block = block(5)?;
+        let compact = CompactBlock::new(&block, 7)?;
+
+        // all of them known
+        let partial = PartialBlock::new(&compact, &block.transactions()[1..])?;
+        assert!(partial.is_complete());
+        assert_eq!(partial.into_block()?, block);
+
+        // two missing, others in the mempool not in the block
+        let other = self::block(8)?;
+        let mempool = block.transactions()[2..4]
+            .iter()
+            .chain(&other.transactions()[6..]);
+        let mut partial = PartialBlock::new(&compact, mempool)?;
+        assert_eq!(partial.missing(), [1, 4, 5]);
+        assert!(partial.clone().into_block().is_err());
+
+        let request = partial.request();
+        let answer = request.answer(&block)?;
+        assert!(partial
+            .fill(&BlockTxnMessage {
+                block_hash: answer.block_hash,
+                transactions: answer.transactions[1..].to_vec(),
+            })
+            .is_err());
+        partial.fill(&answer)?;
+        assert_eq!(partial.into_block()?, block);
+
+        // wrong transactions are caught by the merkle root
+        let mut partial = PartialBlock::new(&compact, &block.transactions()[1..])?;
+        partial.txs[2] = Some(other.transactions()[3].clone());
+        assert!(matches!(
+            partial.into_block(),
+            Err(Error::InvalidCompactBlock("merkle root mismatch"))
+        ));
+
+        let mut colliding = compact.clone();
+        colliding.short_ids[1] = colliding.short_ids[0];
+        assert!(PartialBlock::new(&colliding, None).is_err());
+        let mut empty = compact;
+        empty.short_ids.clear();
+        empty.prefilled.clear();
+        assert!(PartialBlock::new(&empty, None).is_err());
+        Ok(())
+    }
+}
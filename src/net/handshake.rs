@@ -0,0 +1,282 @@
+//! The `version`/`verack` exchange opening every connection, and the features
+//! both sides agree on along the way
+
+use crate::core::fee::FeeRate;
+use crate::{Error, Result};
+
+use super::address::ServiceFlags;
+use super::compact::{SendCmpctMessage, COMPACT_BLOCK_VERSION};
+use super::message::{NetworkMessage, VersionMessage};
+use super::{
+    MIN_PEER_PROTOCOL_VERSION, SENDHEADERS_VERSION, SHORT_IDS_BLOCKS_VERSION, WTXID_RELAY_VERSION,
+};
+
+/// What the connection runs with once the handshake is done
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerFeatures {
+    /// The lower of both protocol versions
+    pub version: u32,
+    pub services: ServiceFlags,
+    pub user_agent: String,
+    pub start_height: i32,
+    /// Whether the peer wants transactions announced before loading a filter
+    pub relay: bool,
+    /// New blocks are announced to the peer with `headers` (BIP130)
+    pub send_headers: bool,
+    /// Transactions are announced by wtxid (BIP339)
+    pub wtxid_relay: bool,
+    /// Addresses are gossiped with `addrv2` (BIP155)
+    pub addrv2: bool,
+    /// The peer understands compact blocks of [`COMPACT_BLOCK_VERSION`]
+    /// (BIP152)
+    pub compact_blocks: bool,
+    /// New blocks are announced to the peer with `cmpctblock` right away
+    pub high_bandwidth: bool,
+    /// Lowest fee rate of the transactions to announce to the peer (BIP133),
+    /// zero until it sends `feefilter`
+    pub fee_filter: FeeRate,
+}
+
+/// One side of the handshake: our `version` goes first, the one of the peer is
+/// answered with the features we support and `verack`, and the connection is
+/// ready once both `verack`s crossed
+#[derive(Debug, Clone)]
+pub struct Handshake {
+    pub(crate) local: VersionMessage,
+    pub(crate) required_services: ServiceFlags,
+    pub(crate) remote: Option<VersionMessage>,
+    pub(crate) verack: bool,
+    pub(crate) wtxid_relay: bool,
+    pub(crate) addrv2: bool,
+    pub(crate) send_headers: bool,
+    pub(crate) compact: Option<SendCmpctMessage>,
+}
+
+impl Handshake {
+    pub fn new(local: VersionMessage) -> Self {
+        Self {
+            local,
+            required_services: ServiceFlags::NONE,
+            remote: None,
+            verack: false,
+            wtxid_relay: false,
+            addrv2: false,
+            send_headers: false,
+            compact: None,
+        }
+    }
+
+    /// Peers not serving all of these are turned down
+    pub fn with_required_services(mut self, services: ServiceFlags) -> Self {
+        self.required_services = services;
+        self
+    }
+
+    pub fn local(&self) -> &VersionMessage {
+        &self.local
+    }
+
+    pub fn remote(&self) -> Option<&VersionMessage> {
+        self.remote.as_ref()
+    }
+
+    /// What's sent first, without waiting for the peer
+    pub fn start(&self) -> NetworkMessage {
+        NetworkMessage::Version(self.local.clone())
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.remote.is_some() && self.verack
+    }
+
+    /// Once the handshake is done
+    pub fn features(&self) -> Option<PeerFeatures> {
+        let remote = self.remote.as_ref().filter(|_| self.verack)?;
+        let version = self.local.version.min(remote.version);
+
+        Some(PeerFeatures {
+            version,
+            services: remote.services,
+            user_agent: remote.user_agent.clone(),
+            start_height: remote.start_height,
+            relay: remote.relay,
+            send_headers: self.send_headers,
+            wtxid_relay: self.wtxid_relay && version >= WTXID_RELAY_VERSION,
+            addrv2: self.addrv2,
+            compact_blocks: self.compact.is_some(),
+            high_bandwidth: self.compact.is_some_and(|compact| compact.announce),
+            fee_filter: FeeRate::ZERO,
+        })
+    }
+
+    /// Takes a message of the peer, returning the ones to answer with. Other
+    /// than those of the handshake, messages are ignored
+    pub fn receive(&mut self, message: &NetworkMessage) -> Result<Vec<NetworkMessage>> {
+        match message {
+            NetworkMessage::Version(version) => self.receive_version(version),
+            NetworkMessage::Verack => {
+                let remote = self
+                    .remote
+                    .as_ref()
+                    .ok_or(Error::HandshakeFailed("verack before version"))?;
+                if self.verack {
+                    return Err(Error::HandshakeFailed("duplicate verack"));
+                }
+
+                self.verack = true;
+                let version = self.local.version.min(remote.version);
+                let mut answers = Vec::new();
+                if version >= SENDHEADERS_VERSION {
+                    answers.push(NetworkMessage::SendHeaders);
+                }
+                // understood, but announced with headers or inv
+                if version >= SHORT_IDS_BLOCKS_VERSION {
+                    answers.push(NetworkMessage::SendCmpct(SendCmpctMessage::new(false)));
+                }
+                Ok(answers)
+            }
+            NetworkMessage::WtxidRelay | NetworkMessage::SendAddrV2 => {
+                if self.remote.is_none() || self.verack {
+                    return Err(Error::HandshakeFailed(
+                        "feature negotiated outside the handshake",
+                    ));
+                }
+
+                if *message == NetworkMessage::WtxidRelay {
+                    self.wtxid_relay = true;
+                } else {
+                    self.addrv2 = true;
+                }
+                Ok(Vec::new())
+            }
+            NetworkMessage::SendHeaders => {
+                self.send_headers = true;
+                Ok(Vec::new())
+            }
+            NetworkMessage::SendCmpct(compact) => {
+                // other versions are ignored, as Bitcoin Core does
+                if compact.version == COMPACT_BLOCK_VERSION {
+                    self.compact = Some(*compact);
+                }
+                Ok(Vec::new())
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    fn receive_version(&mut self, remote: &VersionMessage) -> Result<Vec<NetworkMessage>> {
+        if self.remote.is_some() {
+            return Err(Error::HandshakeFailed("duplicate version"));
+        }
+        if remote.version < MIN_PEER_PROTOCOL_VERSION {
+            return Err(Error::HandshakeFailed("obsolete protocol version"));
+        }
+        if remote.nonce == self.local.nonce {
+            return Err(Error::HandshakeFailed("connected to ourselves"));
+        }
+        if !remote.services.contains(self.required_services) {
+            return Err(Error::HandshakeFailed("missing required services"));
+        }
+
+        // features go before verack
+        let mut answers = Vec::new();
+        if self.local.version.min(remote.version) >= WTXID_RELAY_VERSION {
+            answers.push(NetworkMessage::WtxidRelay);
+        }
+        answers.push(NetworkMessage::SendAddrV2);
+        answers.push(NetworkMessage::Verack);
+
+        self.remote = Some(remote.clone());
+        Ok(answers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use anyhow::Result;
+
+    use crate::net::PROTOCOL_VERSION;
+
+    use super::*;
+
+    fn version(start_height: i32) -> VersionMessage {
+        VersionMessage::new("127.0.0.1:18444".parse().unwrap(), start_height)
+    }
+
+    /// Delivers the messages of each side to the other until none are left
+    fn exchange(first: &mut Handshake, second: &mut Handshake) -> crate::Result<()> {
+        let mut to_second = VecDeque::from(vec![first.start()]);
+        let mut to_first = VecDeque::from(vec![second.start()]);
+        while !to_first.is_empty() || !to_second.is_empty() {
+            if let Some(message) = to_second.pop_front() {
+                to_first.extend(second.receive(&message)?);
+            }
+            if let Some(message) = to_first.pop_front() {
+                to_second.extend(first.receive(&message)?);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn negotiation() -> Result<()> {
+        let client = Handshake::new(version(0).with_relay(false));
+        let mut node = Handshake::new(
+            version(800_000).with_services(ServiceFlags::NETWORK | ServiceFlags::WITNESS),
+        )
+        .with_required_services(ServiceFlags::NONE);
+        assert!(!client.is_done());
+        assert_eq!(client.features(), None);
+
+        let mut client = client.with_required_services(ServiceFlags::WITNESS);
+        exchange(&mut client, &mut node)?;
+        assert!(client.is_done() && node.is_done());
+
+        let features = client.features().unwrap();
+        assert_eq!(features.version, PROTOCOL_VERSION);
+        assert_eq!(features.start_height, 800_000);
+        assert!(features.services.contains(ServiceFlags::WITNESS));
+        assert!(features.relay);
+        assert!(features.send_headers && features.wtxid_relay && features.addrv2);
+        assert!(features.compact_blocks && !features.high_bandwidth);
+        assert!(!node.features().unwrap().relay);
+
+        // older peers get neither sendheaders nor wtxidrelay
+        let mut client = Handshake::new(version(0));
+        let mut old = Handshake::new(version(0).with_version(70011));
+        exchange(&mut client, &mut old)?;
+        let features = client.features().unwrap();
+        assert_eq!(features.version, 70011);
+        assert!(!features.send_headers && !features.wtxid_relay);
+        assert!(features.addrv2 && !features.compact_blocks);
+        Ok(())
+    }
+
+    #[test]
+    fn violations() -> Result<()> {
+        let mut client = Handshake::new(version(0));
+        assert!(client.receive(&NetworkMessage::Verack).is_err());
+        assert!(client.receive(&NetworkMessage::WtxidRelay).is_err());
+        // our own version back
+        assert!(client.receive(&client.start()).is_err());
+
+        let obsolete = version(0).with_version(MIN_PEER_PROTOCOL_VERSION - 1);
+        assert!(client.receive(&NetworkMessage::Version(obsolete)).is_err());
+
+        let mut picky = Handshake::new(version(0)).with_required_services(ServiceFlags::NETWORK);
+        assert!(picky.receive(&Handshake::new(version(0)).start()).is_err());
+
+        // anything else goes unanswered, features only before verack
+        let peer = Handshake::new(version(0));
+        assert_eq!(client.receive(&NetworkMessage::Ping(1))?, Vec::new());
+        client.receive(&peer.start())?;
+        assert!(client.receive(&peer.start()).is_err());
+        client.receive(&NetworkMessage::Verack)?;
+        assert!(client.receive(&NetworkMessage::Verack).is_err());
+        assert!(client.receive(&NetworkMessage::SendAddrV2).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,193 @@
+//! Keeping connections alive and telling dead ones apart: peers are pinged
+//! every so often, those that don't answer in time are given up on, and how
+//! long the answers take is the latency of the peer
+
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+
+use crate::{Error, Result};
+
+use super::message::NetworkMessage;
+
+/// Time between pings, Bitcoin Core's `PING_INTERVAL`
+pub const PING_INTERVAL: Duration = Duration::from_secs(2 * 60);
+/// Time a peer has to answer a ping in, or to send anything at all, Bitcoin
+/// Core's `TIMEOUT_INTERVAL`
+pub const PING_TIMEOUT: Duration = Duration::from_secs(20 * 60);
+
+/// Pings of a peer and its answers, without doing any IO itself: pings are
+/// handed out when due and the messages of the peer taken in
+#[derive(Debug, Clone)]
+pub struct Liveness {
+    pub(crate) ping_interval: Duration,
+    pub(crate) ping_timeout: Duration,
+    /// Nonce of the ping not answered yet and when it was sent
+    pub(crate) pending: Option<(u64, Instant)>,
+    pub(crate) last_ping: Option<Instant>,
+    pub(crate) last_received: Instant,
+    pub(crate) latency: Option<Duration>,
+    pub(crate) min_latency: Option<Duration>,
+}
+
+impl Liveness {
+    /// For a peer connected at `now`
+    pub fn new(now: Instant) -> Self {
+        Self {
+            ping_interval: PING_INTERVAL,
+            ping_timeout: PING_TIMEOUT,
+            pending: None,
+            last_ping: None,
+            last_received: now,
+            latency: None,
+            min_latency: None,
+        }
+    }
+
+    /// Time between pings, [`PING_INTERVAL`] unless set
+    pub fn with_ping_interval(mut self, ping_interval: Duration) -> Self {
+        self.ping_interval = ping_interval;
+        self
+    }
+
+    /// Time to answer in, [`PING_TIMEOUT`] unless set
+    pub fn with_ping_timeout(mut self, ping_timeout: Duration) -> Self {
+        self.ping_timeout = ping_timeout;
+        self
+    }
+
+    /// Time the last ping took to be answered, none until one is
+    pub fn latency(&self) -> Option<Duration> {
+        self.latency
+    }
+
+    /// Quickest any ping was answered in
+    pub fn min_latency(&self) -> Option<Duration> {
+        self.min_latency
+    }
+
+    /// Whether a ping is waiting for its pong
+    pub fn is_waiting(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// When the next ping is due, none while one is waiting for its pong
+    pub fn next_ping_at(&self) -> Option<Instant> {
+        match (self.pending, self.last_ping) {
+            (Some(_), _) => None,
+            (None, Some(last)) => Some(last + self.ping_interval),
+            // right away
+            (None, None) => Some(self.last_received),
+        }
+    }
+
+    /// The ping to send if one is due at `now`, with a random nonce
+    pub fn next_ping(&mut self, now: Instant) -> Option<NetworkMessage> {
+        if self.next_ping_at()? > now {
+            return None;
+        }
+
+        let nonce = rand::thread_rng().gen();
+        self.pending = Some((nonce, now));
+        self.last_ping = Some(now);
+        Some(NetworkMessage::Ping(nonce))
+    }
+
+    /// Takes a message of the peer received at `now`, returning the pong to
+    /// answer it with if it's a ping. Pongs of other nonces are ignored, as
+    /// Bitcoin Core does
+    pub fn receive(&mut self, message: &NetworkMessage, now: Instant) -> Option<NetworkMessage> {
+        self.last_received = now;
+
+        match message {
+            NetworkMessage::Ping(nonce) => return Some(NetworkMessage::Pong(*nonce)),
+            NetworkMessage::Pong(nonce) => match self.pending {
+                Some((pending, sent)) if pending == *nonce => {
+                    let latency = now.saturating_duration_since(sent);
+                    self.pending = None;
+                    self.latency = Some(latency);
+                    self.min_latency =
+                        Some(self.min_latency.map_or(latency, |min| min.min(latency)));
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+
+        None
+    }
+
+    /// Fails if the last ping wasn't answered or nothing was received in the
+    /// timeout, the peer should be disconnected then
+    pub fn check(&self, now: Instant) -> Result<()> {
+        if let Some((_, sent)) = self.pending {
+            if now.saturating_duration_since(sent) >= self.ping_timeout {
+                return Err(Error::PeerTimedOut("ping not answered"));
+            }
+        }
+        if now.saturating_duration_since(self.last_received) >= self.ping_timeout {
+            return Err(Error::PeerTimedOut("nothing received"));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+
+    #[test]
+    fn ping_pong() -> Result<()> {
+        let start = Instant::now();
+        let second = Duration::from_secs(1);
+        let mut liveness = Liveness::new(start)
+            .with_ping_interval(10 * second)
+            .with_ping_timeout(30 * second);
+
+        let nonce = match liveness.next_ping(start) {
+            Some(NetworkMessage::Ping(nonce)) => nonce,
+            message => panic!("expected a ping, got {:?}", message),
+        };
+        assert_eq!(liveness.next_ping(start + 20 * second), None);
+        assert_eq!(
+            liveness.receive(&NetworkMessage::Ping(7), start + second),
+            Some(NetworkMessage::Pong(7))
+        );
+
+        // only the pong of the nonce sent counts
+        liveness.receive(&NetworkMessage::Pong(nonce.wrapping_add(1)), start + second);
+        assert!(liveness.is_waiting());
+        liveness.receive(&NetworkMessage::Pong(nonce), start + 3 * second);
+        assert!(!liveness.is_waiting());
+        assert_eq!(liveness.latency(), Some(3 * second));
+
+        assert_eq!(liveness.next_ping_at(), Some(start + 10 * second));
+        assert_eq!(liveness.next_ping(start + 9 * second), None);
+        let nonce = match liveness.next_ping(start + 10 * second) {
+            Some(NetworkMessage::Ping(nonce)) => nonce,
+            message => panic!("expected a ping, got {:?}", message),
+        };
+        liveness.receive(&NetworkMessage::Pong(nonce), start + 15 * second);
+        assert_eq!(liveness.latency(), Some(5 * second));
+        assert_eq!(liveness.min_latency(), Some(3 * second));
+        Ok(())
+    }
+
+    #[test]
+    fn timeouts() {
+        let start = Instant::now();
+        let second = Duration::from_secs(1);
+        let mut liveness = Liveness::new(start).with_ping_timeout(30 * second);
+        assert!(liveness.check(start + 29 * second).is_ok());
+        assert!(liveness.check(start + 30 * second).is_err());
+
+        // unanswered, however chatty the peer is otherwise
+        liveness.next_ping(start);
+        liveness.receive(&NetworkMessage::Verack, start + 29 * second);
+        assert!(liveness.check(start + 29 * second).is_ok());
+        assert!(liveness.check(start + 30 * second).is_err());
+    }
+}
@@ -0,0 +1,1059 @@
+//! The envelope every P2P message travels in (magic, command, payload length
+//! and checksum) and the payloads known by command
+
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rand::Rng;
+
+use crate::consensus::{self, Decodable, Encodable};
+use crate::core::block::{Block, BlockHeader};
+use crate::core::bloom::BloomFilter;
+use crate::core::filter::BlockFilter;
+use crate::core::merkle::MerkleBlock;
+use crate::core::tx::Transaction;
+use crate::core::txid::{BlockHash, Txid, Wtxid};
+use crate::utils::hash256;
+use crate::{Error, Result};
+
+use super::address::{AddrV2Message, NetAddress, ServiceFlags};
+use super::compact::{BlockTxnMessage, CompactBlock, GetBlockTxnMessage, SendCmpctMessage};
+use super::PROTOCOL_VERSION;
+
+/// Size of the envelope before the payload
+pub const HEADER_SIZE: usize = 24;
+/// Largest payload peers accept, Bitcoin Core's `MAX_PROTOCOL_MESSAGE_LENGTH`
+pub const MAX_PAYLOAD_SIZE: usize = 4_000_000;
+/// Most headers in a `headers` message
+pub const MAX_HEADERS: usize = 2_000;
+/// Most items in an `inv`, `getdata` or `notfound` message
+pub const MAX_INV_SIZE: usize = 50_000;
+/// Most addresses in an `addr` or `addrv2` message
+pub const MAX_ADDR: usize = 1_000;
+/// Most hashes in a block locator
+pub const MAX_LOCATOR_SIZE: usize = 101;
+/// Most filters asked for by a `getcfilters` message (BIP157)
+pub const MAX_GETCFILTERS_SIZE: u32 = 1_000;
+/// Type of basic block filters (BIP158)
+pub const BASIC_FILTER_TYPE: u8 = 0;
+/// Longest user agent peers accept
+pub const MAX_USER_AGENT_SIZE: usize = 256;
+/// Longest reason of a `reject` message
+pub const MAX_REJECT_REASON_SIZE: usize = 111;
+
+/// What this crate tells peers it is (BIP14)
+pub const USER_AGENT: &str = concat!("/oxicoin:", env!("CARGO_PKG_VERSION"), "/");
+
+/// Commands are ASCII padded with zeros up to this size
+pub const COMMAND_SIZE: usize = 12;
+
+/// First bytes of the double SHA256 of `payload`
+pub fn checksum(payload: &[u8]) -> [u8; 4] {
+    let mut checksum = [0; 4];
+    checksum.copy_from_slice(&hash256(payload)[..4]);
+    checksum
+}
+
+/// `command` without its padding, printable ASCII only
+pub(crate) fn parse_command(command: &[u8; COMMAND_SIZE]) -> Result<String> {
+    let len = command
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(COMMAND_SIZE);
+
+    let (command, padding) = command.split_at(len);
+    if !command.iter().all(|byte| (0x20..0x7f).contains(byte))
+        || padding.iter().any(|byte| *byte != 0)
+    {
+        return Err(Error::InvalidMessage("malformed command"));
+    }
+
+    // safe, printable ASCII
+    Ok(String::from_utf8(command.to_vec()).unwrap())
+}
+
+/// What precedes every payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageHeader {
+    /// Network the message is meant for, see [`Params::magic`](crate::params::Params)
+    pub magic: [u8; 4],
+    pub command: [u8; COMMAND_SIZE],
+    pub length: u32,
+    pub checksum: [u8; 4],
+}
+
+impl MessageHeader {
+    /// The command without its padding, printable ASCII only
+    pub fn command(&self) -> Result<String> {
+        parse_command(&self.command)
+    }
+}
+
+impl Encodable for MessageHeader {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        Ok(self.magic.consensus_encode(writer)?
+            + self.command.consensus_encode(writer)?
+            + self.length.consensus_encode(writer)?
+            + self.checksum.consensus_encode(writer)?)
+    }
+}
+
+impl Decodable for MessageHeader {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        Ok(Self {
+            magic: <[u8; 4]>::consensus_decode(reader)?,
+            command: <[u8; COMMAND_SIZE]>::consensus_decode(reader)?,
+            length: u32::consensus_decode(reader)?,
+            checksum: <[u8; 4]>::consensus_decode(reader)?,
+        })
+    }
+}
+
+/// Payload of the `version` message, the first one each side sends
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionMessage {
+    /// Protocol version of the sender
+    pub version: u32,
+    pub services: ServiceFlags,
+    /// Seconds since the Unix epoch
+    pub timestamp: i64,
+    /// The receiving peer as seen by the sender
+    pub receiver: NetAddress,
+    pub sender: NetAddress,
+    /// Random, tells connections to the sender itself apart
+    pub nonce: u64,
+    pub user_agent: String,
+    /// Height of the best chain of the sender
+    pub start_height: i32,
+    /// Whether to announce transactions before a filter is loaded (BIP37),
+    /// true when left out
+    pub relay: bool,
+}
+
+impl VersionMessage {
+    /// Ours for a connection to `receiver`: the latest protocol version, no
+    /// services, timestamped now with a random nonce
+    pub fn new(receiver: SocketAddr, start_height: i32) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs() as i64)
+            .unwrap_or_default();
+
+        Self {
+            version: PROTOCOL_VERSION,
+            services: ServiceFlags::NONE,
+            timestamp,
+            receiver: NetAddress::new(receiver, ServiceFlags::NONE),
+            sender: NetAddress::default(),
+            nonce: rand::thread_rng().gen(),
+            user_agent: USER_AGENT.to_string(),
+            start_height,
+            relay: true,
+        }
+    }
+
+    pub fn with_version(mut self, version: u32) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// What's served to the peer, also set on the sender address
+    pub fn with_services(mut self, services: ServiceFlags) -> Self {
+        self.services = services;
+        self.sender.services = services;
+        self
+    }
+
+    pub fn with_user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = user_agent.to_string();
+        self
+    }
+
+    /// Light clients ask for no transactions until they load a filter
+    pub fn with_relay(mut self, relay: bool) -> Self {
+        self.relay = relay;
+        self
+    }
+}
+
+impl Encodable for VersionMessage {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        Ok(self.version.consensus_encode(writer)?
+            + self.services.consensus_encode(writer)?
+            + self.timestamp.consensus_encode(writer)?
+            + self.receiver.consensus_encode(writer)?
+            + self.sender.consensus_encode(writer)?
+            + self.nonce.consensus_encode(writer)?
+            + self.user_agent.as_bytes().consensus_encode(writer)?
+            + self.start_height.consensus_encode(writer)?
+            + self.relay.consensus_encode(writer)?)
+    }
+}
+
+impl Decodable for VersionMessage {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        let version = u32::consensus_decode(reader)?;
+        let services = ServiceFlags::consensus_decode(reader)?;
+        let timestamp = i64::consensus_decode(reader)?;
+        let receiver = NetAddress::consensus_decode(reader)?;
+        let sender = NetAddress::consensus_decode(reader)?;
+        let nonce = u64::consensus_decode(reader)?;
+
+        let user_agent = Vec::<u8>::consensus_decode(reader)?;
+        if user_agent.len() > MAX_USER_AGENT_SIZE {
+            return Err(Error::InvalidMessage("user agent too long"));
+        }
+        let user_agent = String::from_utf8(user_agent)
+            .map_err(|_| Error::InvalidMessage("user agent isn't utf8"))?;
+
+        let start_height = i32::consensus_decode(reader)?;
+        // left out by older peers
+        let mut byte = [0];
+        let relay = reader.read(&mut byte)? == 0 || byte[0] != 0;
+
+        Ok(Self {
+            version,
+            services,
+            timestamp,
+            receiver,
+            sender,
+            nonce,
+            user_agent,
+            start_height,
+            relay,
+        })
+    }
+}
+
+/// Payload of the `getheaders` and `getblocks` messages: what comes after the
+/// first hash of `locator` in the chain of the peer, up to `stop_hash`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GetHeadersMessage {
+    pub version: u32,
+    /// Hashes from the tip backwards, denser the closer to it
+    pub locator: Vec<BlockHash>,
+    /// Zero to get as many as fit in the response
+    pub stop_hash: BlockHash,
+}
+
+impl Encodable for GetHeadersMessage {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        Ok(self.version.consensus_encode(writer)?
+            + self.locator.consensus_encode(writer)?
+            + self.stop_hash.consensus_encode(writer)?)
+    }
+}
+
+impl Decodable for GetHeadersMessage {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        let version = u32::consensus_decode(reader)?;
+        let locator: Vec<_> = Vec::consensus_decode(reader)?;
+        if locator.len() > MAX_LOCATOR_SIZE {
+            return Err(Error::InvalidMessage("locator too large"));
+        }
+
+        Ok(Self {
+            version,
+            locator,
+            stop_hash: BlockHash::consensus_decode(reader)?,
+        })
+    }
+}
+
+/// Payload of the `getcfilters` message: the filters of the blocks of the
+/// chain of the peer from `start_height` up to `stop_hash` (BIP157)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GetCFiltersMessage {
+    pub filter_type: u8,
+    pub start_height: u32,
+    pub stop_hash: BlockHash,
+}
+
+impl Encodable for GetCFiltersMessage {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        Ok(self.filter_type.consensus_encode(writer)?
+            + self.start_height.consensus_encode(writer)?
+            + self.stop_hash.consensus_encode(writer)?)
+    }
+}
+
+impl Decodable for GetCFiltersMessage {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        Ok(Self {
+            filter_type: u8::consensus_decode(reader)?,
+            start_height: u32::consensus_decode(reader)?,
+            stop_hash: BlockHash::consensus_decode(reader)?,
+        })
+    }
+}
+
+/// Payload of the `cfilter` message, the filter of a block (BIP157)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CFilterMessage {
+    pub filter_type: u8,
+    pub block_hash: BlockHash,
+    pub filter: BlockFilter,
+}
+
+impl Encodable for CFilterMessage {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        Ok(self.filter_type.consensus_encode(writer)?
+            + self.block_hash.consensus_encode(writer)?
+            + self.filter.consensus_encode(writer)?)
+    }
+}
+
+impl Decodable for CFilterMessage {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        Ok(Self {
+            filter_type: u8::consensus_decode(reader)?,
+            block_hash: BlockHash::consensus_decode(reader)?,
+            filter: BlockFilter::consensus_decode(reader)?,
+        })
+    }
+}
+
+/// Payload of the `reject` message, why a message of ours was turned down
+/// (BIP61). Dropped by Bitcoin Core 0.20, other implementations still send it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RejectMessage {
+    /// Command of the message rejected
+    pub message: String,
+    pub code: u8,
+    pub reason: String,
+    /// Hash of the transaction or block rejected
+    pub data: Option<[u8; 32]>,
+}
+
+impl RejectMessage {
+    pub const MALFORMED: u8 = 0x01;
+    pub const INVALID: u8 = 0x10;
+    pub const OBSOLETE: u8 = 0x11;
+    pub const DUPLICATE: u8 = 0x12;
+    pub const NONSTANDARD: u8 = 0x40;
+    pub const DUST: u8 = 0x41;
+    pub const INSUFFICIENT_FEE: u8 = 0x42;
+    pub const CHECKPOINT: u8 = 0x43;
+}
+
+impl Encodable for RejectMessage {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        let mut len = self.message.as_bytes().consensus_encode(writer)?
+            + self.code.consensus_encode(writer)?
+            + self.reason.as_bytes().consensus_encode(writer)?;
+        if let Some(data) = &self.data {
+            len += data.consensus_encode(writer)?;
+        }
+        Ok(len)
+    }
+}
+
+impl Decodable for RejectMessage {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        let message = String::from_utf8(Vec::consensus_decode(reader)?)
+            .map_err(|_| Error::InvalidMessage("rejected command isn't utf8"))?;
+        let code = u8::consensus_decode(reader)?;
+
+        let reason = Vec::<u8>::consensus_decode(reader)?;
+        if reason.len() > MAX_REJECT_REASON_SIZE {
+            return Err(Error::InvalidMessage("reject reason too long"));
+        }
+        let reason = String::from_utf8(reason)
+            .map_err(|_| Error::InvalidMessage("reject reason isn't utf8"))?;
+
+        // only there for transactions and blocks
+        let mut data = [0; 32];
+        let data = match reader.read(&mut data[..1])? {
+            0 => None,
+            _ => {
+                reader.read_exact(&mut data[1..])?;
+                Some(data)
+            }
+        };
+
+        Ok(Self {
+            message,
+            code,
+            reason,
+            data,
+        })
+    }
+}
+
+/// Flag of the types asking for witness data along (BIP144)
+const WITNESS_FLAG: u32 = 1 << 30;
+
+/// An object announced by `inv` or requested by `getdata`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Inventory {
+    /// Ignored by peers, its hash is always zero
+    Error,
+    Tx(Txid),
+    /// A transaction by its wtxid, how peers that sent `wtxidrelay` announce
+    /// and request them (BIP339)
+    WTx(Wtxid),
+    Block(BlockHash),
+    /// The `merkleblock` of the block for the loaded filter (BIP37)
+    FilteredBlock(BlockHash),
+    /// The `cmpctblock` of the block, only asked for (BIP152)
+    CompactBlock(BlockHash),
+    /// The transaction with its witness, only asked for (BIP144)
+    WitnessTx(Txid),
+    /// The block with the witnesses of its transactions, only asked for
+    /// (BIP144)
+    WitnessBlock(BlockHash),
+    FilteredWitnessBlock(BlockHash),
+    /// A type not known by this crate
+    Unknown {
+        kind: u32,
+        hash: [u8; 32],
+    },
+}
+
+impl Inventory {
+    pub fn kind(&self) -> u32 {
+        match self {
+            Inventory::Error => 0,
+            Inventory::Tx(_) => 1,
+            Inventory::Block(_) => 2,
+            Inventory::FilteredBlock(_) => 3,
+            Inventory::CompactBlock(_) => 4,
+            Inventory::WTx(_) => 5,
+            Inventory::WitnessTx(_) => WITNESS_FLAG | 1,
+            Inventory::WitnessBlock(_) => WITNESS_FLAG | 2,
+            Inventory::FilteredWitnessBlock(_) => WITNESS_FLAG | 3,
+            Inventory::Unknown { kind, .. } => *kind,
+        }
+    }
+
+    pub fn hash(&self) -> [u8; 32] {
+        match self {
+            Inventory::Error => [0; 32],
+            Inventory::Tx(txid) | Inventory::WitnessTx(txid) => txid.to_bytes(),
+            Inventory::WTx(wtxid) => wtxid.to_bytes(),
+            Inventory::Block(hash)
+            | Inventory::FilteredBlock(hash)
+            | Inventory::CompactBlock(hash)
+            | Inventory::WitnessBlock(hash)
+            | Inventory::FilteredWitnessBlock(hash) => hash.to_bytes(),
+            Inventory::Unknown { hash, .. } => *hash,
+        }
+    }
+
+    /// Whether it's a transaction, by any id
+    pub fn is_tx(&self) -> bool {
+        matches!(
+            self,
+            Inventory::Tx(_) | Inventory::WTx(_) | Inventory::WitnessTx(_)
+        )
+    }
+
+    /// The block, whole or not
+    pub fn block_hash(&self) -> Option<BlockHash> {
+        match self {
+            Inventory::Block(hash)
+            | Inventory::FilteredBlock(hash)
+            | Inventory::CompactBlock(hash)
+            | Inventory::WitnessBlock(hash)
+            | Inventory::FilteredWitnessBlock(hash) => Some(*hash),
+            _ => None,
+        }
+    }
+
+    /// The same object with its witness data, what's asked for to peers
+    /// serving it. Others are left as they are
+    pub fn with_witness(self) -> Self {
+        match self {
+            Inventory::Tx(txid) => Inventory::WitnessTx(txid),
+            Inventory::Block(hash) => Inventory::WitnessBlock(hash),
+            Inventory::FilteredBlock(hash) => Inventory::FilteredWitnessBlock(hash),
+            inventory => inventory,
+        }
+    }
+}
+
+impl Encodable for Inventory {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        Ok(self.kind().consensus_encode(writer)? + self.hash().consensus_encode(writer)?)
+    }
+}
+
+impl Decodable for Inventory {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        let kind = u32::consensus_decode(reader)?;
+        let hash = <[u8; 32]>::consensus_decode(reader)?;
+
+        Ok(match kind {
+            0 => Inventory::Error,
+            1 => Inventory::Tx(Txid::from_bytes(hash)),
+            2 => Inventory::Block(BlockHash::from_bytes(hash)),
+            3 => Inventory::FilteredBlock(BlockHash::from_bytes(hash)),
+            4 => Inventory::CompactBlock(BlockHash::from_bytes(hash)),
+            5 => Inventory::WTx(Wtxid::from_bytes(hash)),
+            0x4000_0001 => Inventory::WitnessTx(Txid::from_bytes(hash)),
+            0x4000_0002 => Inventory::WitnessBlock(BlockHash::from_bytes(hash)),
+            0x4000_0003 => Inventory::FilteredWitnessBlock(BlockHash::from_bytes(hash)),
+            _ => Inventory::Unknown { kind, hash },
+        })
+    }
+}
+
+/// Payloads by command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetworkMessage {
+    Version(VersionMessage),
+    Verack,
+    Ping(u64),
+    Pong(u64),
+    GetAddr,
+    /// Addresses with the time they were last seen at
+    Addr(Vec<(u32, NetAddress)>),
+    /// Addresses of any network (BIP155), sent instead of `addr` to peers that
+    /// asked with `sendaddrv2`
+    AddrV2(Vec<AddrV2Message>),
+    /// Announce new blocks with `headers` rather than `inv` (BIP130)
+    SendHeaders,
+    /// Announce transactions by wtxid (BIP339), only sent before `verack`
+    WtxidRelay,
+    /// Gossip addresses with `addrv2` (BIP155), only sent before `verack`
+    SendAddrV2,
+    GetHeaders(GetHeadersMessage),
+    GetBlocks(GetHeadersMessage),
+    Headers(Vec<BlockHeader>),
+    Inv(Vec<Inventory>),
+    GetData(Vec<Inventory>),
+    NotFound(Vec<Inventory>),
+    Tx(Transaction),
+    Block(Block),
+    MemPool,
+    /// Lowest fee rate, in satoshis per kilo vbyte, of the transactions to
+    /// announce (BIP133)
+    FeeFilter(i64),
+    FilterLoad(BloomFilter),
+    FilterAdd(Vec<u8>),
+    FilterClear,
+    MerkleBlock(MerkleBlock),
+    GetCFilters(GetCFiltersMessage),
+    CFilter(CFilterMessage),
+    /// Compact blocks are understood, and wanted as announcements if set
+    /// (BIP152)
+    SendCmpct(SendCmpctMessage),
+    CmpctBlock(CompactBlock),
+    GetBlockTxn(GetBlockTxnMessage),
+    BlockTxn(BlockTxnMessage),
+    Reject(RejectMessage),
+    /// Any other command, its payload is kept as is
+    Unknown {
+        command: String,
+        payload: Vec<u8>,
+    },
+}
+
+impl NetworkMessage {
+    pub fn command(&self) -> &str {
+        match self {
+            NetworkMessage::Version(_) => "version",
+            NetworkMessage::Verack => "verack",
+            NetworkMessage::Ping(_) => "ping",
+            NetworkMessage::Pong(_) => "pong",
+            NetworkMessage::GetAddr => "getaddr",
+            NetworkMessage::Addr(_) => "addr",
+            NetworkMessage::AddrV2(_) => "addrv2",
+            NetworkMessage::SendHeaders => "sendheaders",
+            NetworkMessage::WtxidRelay => "wtxidrelay",
+            NetworkMessage::SendAddrV2 => "sendaddrv2",
+            NetworkMessage::GetHeaders(_) => "getheaders",
+            NetworkMessage::GetBlocks(_) => "getblocks",
+            NetworkMessage::Headers(_) => "headers",
+            NetworkMessage::Inv(_) => "inv",
+            NetworkMessage::GetData(_) => "getdata",
+            NetworkMessage::NotFound(_) => "notfound",
+            NetworkMessage::Tx(_) => "tx",
+            NetworkMessage::Block(_) => "block",
+            NetworkMessage::MemPool => "mempool",
+            NetworkMessage::FeeFilter(_) => "feefilter",
+            NetworkMessage::FilterLoad(_) => "filterload",
+            NetworkMessage::FilterAdd(_) => "filteradd",
+            NetworkMessage::FilterClear => "filterclear",
+            NetworkMessage::MerkleBlock(_) => "merkleblock",
+            NetworkMessage::GetCFilters(_) => "getcfilters",
+            NetworkMessage::CFilter(_) => "cfilter",
+            NetworkMessage::SendCmpct(_) => "sendcmpct",
+            NetworkMessage::CmpctBlock(_) => "cmpctblock",
+            NetworkMessage::GetBlockTxn(_) => "getblocktxn",
+            NetworkMessage::BlockTxn(_) => "blocktxn",
+            NetworkMessage::Reject(_) => "reject",
+            NetworkMessage::Unknown { command, .. } => command,
+        }
+    }
+
+    /// The payload alone, without the envelope
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        match self {
+            NetworkMessage::Version(version) => consensus::serialize(version),
+            NetworkMessage::Ping(nonce) | NetworkMessage::Pong(nonce) => {
+                consensus::serialize(nonce)
+            }
+            NetworkMessage::Addr(addresses) => {
+                let mut payload = Vec::new();
+                consensus::write_compact_size(&mut payload, addresses.len() as u64)?;
+                for (time, address) in addresses {
+                    time.consensus_encode(&mut payload)?;
+                    address.consensus_encode(&mut payload)?;
+                }
+                Ok(payload)
+            }
+            NetworkMessage::AddrV2(addresses) => consensus::serialize(addresses),
+            NetworkMessage::GetHeaders(message) | NetworkMessage::GetBlocks(message) => {
+                consensus::serialize(message)
+            }
+            NetworkMessage::Headers(headers) => {
+                // each one followed by an empty transaction count
+                let mut payload = Vec::new();
+                consensus::write_compact_size(&mut payload, headers.len() as u64)?;
+                for header in headers {
+                    header.consensus_encode(&mut payload)?;
+                    0u8.consensus_encode(&mut payload)?;
+                }
+                Ok(payload)
+            }
+            NetworkMessage::Inv(items)
+            | NetworkMessage::GetData(items)
+            | NetworkMessage::NotFound(items) => consensus::serialize(items),
+            NetworkMessage::Tx(tx) => consensus::serialize(tx),
+            NetworkMessage::Block(block) => consensus::serialize(block),
+            NetworkMessage::FeeFilter(fee_rate) => consensus::serialize(fee_rate),
+            NetworkMessage::FilterLoad(filter) => consensus::serialize(filter),
+            NetworkMessage::FilterAdd(data) => consensus::serialize(data),
+            NetworkMessage::MerkleBlock(block) => consensus::serialize(block),
+            NetworkMessage::GetCFilters(message) => consensus::serialize(message),
+            NetworkMessage::CFilter(message) => consensus::serialize(message),
+            NetworkMessage::SendCmpct(message) => consensus::serialize(message),
+            NetworkMessage::CmpctBlock(block) => consensus::serialize(block),
+            NetworkMessage::GetBlockTxn(message) => consensus::serialize(message),
+            NetworkMessage::BlockTxn(message) => consensus::serialize(message),
+            NetworkMessage::Reject(message) => consensus::serialize(message),
+            NetworkMessage::Unknown { payload, .. } => Ok(payload.clone()),
+            NetworkMessage::Verack
+            | NetworkMessage::GetAddr
+            | NetworkMessage::SendHeaders
+            | NetworkMessage::WtxidRelay
+            | NetworkMessage::SendAddrV2
+            | NetworkMessage::MemPool
+            | NetworkMessage::FilterClear => Ok(Vec::new()),
+        }
+    }
+
+    /// The payload of `command`, which must span all of it but for `version`
+    /// that may be extended by later protocol versions
+    pub fn deserialize(command: &str, payload: &[u8]) -> Result<Self> {
+        let message = match command {
+            "version" => {
+                NetworkMessage::Version(VersionMessage::consensus_decode(&mut &payload[..])?)
+            }
+            "verack" => NetworkMessage::Verack,
+            "ping" => NetworkMessage::Ping(consensus::deserialize(payload)?),
+            "pong" => NetworkMessage::Pong(consensus::deserialize(payload)?),
+            "getaddr" => NetworkMessage::GetAddr,
+            "addr" => NetworkMessage::Addr(deserialize_addr(payload)?),
+            "addrv2" => NetworkMessage::AddrV2(deserialize_addrv2(payload)?),
+            "sendheaders" => NetworkMessage::SendHeaders,
+            "wtxidrelay" => NetworkMessage::WtxidRelay,
+            "sendaddrv2" => NetworkMessage::SendAddrV2,
+            "getheaders" => NetworkMessage::GetHeaders(consensus::deserialize(payload)?),
+            "getblocks" => NetworkMessage::GetBlocks(consensus::deserialize(payload)?),
+            "headers" => NetworkMessage::Headers(deserialize_headers(payload)?),
+            "inv" => NetworkMessage::Inv(deserialize_inv(payload)?),
+            "getdata" => NetworkMessage::GetData(deserialize_inv(payload)?),
+            "notfound" => NetworkMessage::NotFound(deserialize_inv(payload)?),
+            "tx" => NetworkMessage::Tx(consensus::deserialize(payload)?),
+            "block" => NetworkMessage::Block(consensus::deserialize(payload)?),
+            "mempool" => NetworkMessage::MemPool,
+            "feefilter" => NetworkMessage::FeeFilter(consensus::deserialize(payload)?),
+            "filterload" => NetworkMessage::FilterLoad(consensus::deserialize(payload)?),
+            "filteradd" => NetworkMessage::FilterAdd(consensus::deserialize(payload)?),
+            "filterclear" => NetworkMessage::FilterClear,
+            "merkleblock" => NetworkMessage::MerkleBlock(consensus::deserialize(payload)?),
+            "getcfilters" => NetworkMessage::GetCFilters(consensus::deserialize(payload)?),
+            "cfilter" => NetworkMessage::CFilter(consensus::deserialize(payload)?),
+            "sendcmpct" => NetworkMessage::SendCmpct(consensus::deserialize(payload)?),
+            "cmpctblock" => NetworkMessage::CmpctBlock(consensus::deserialize(payload)?),
+            "getblocktxn" => NetworkMessage::GetBlockTxn(consensus::deserialize(payload)?),
+            "blocktxn" => NetworkMessage::BlockTxn(consensus::deserialize(payload)?),
+            "reject" => NetworkMessage::Reject(consensus::deserialize(payload)?),
+            _ => NetworkMessage::Unknown {
+                command: command.to_string(),
+                payload: payload.to_vec(),
+            },
+        };
+
+        Ok(message)
+    }
+}
+
+fn deserialize_addr(payload: &[u8]) -> Result<Vec<(u32, NetAddress)>> {
+    let mut reader = payload;
+    let len = consensus::read_compact_size(&mut reader)? as usize;
+    if len > MAX_ADDR {
+        return Err(Error::InvalidMessage("too many addresses"));
+    }
+
+    let mut addresses = Vec::with_capacity(len);
+    for _ in 0..len {
+        let time = u32::consensus_decode(&mut reader)?;
+        addresses.push((time, NetAddress::consensus_decode(&mut reader)?));
+    }
+
+    if !reader.is_empty() {
+        return Err(Error::TrailingBytes(reader.len()));
+    }
+    Ok(addresses)
+}
+
+fn deserialize_addrv2(payload: &[u8]) -> Result<Vec<AddrV2Message>> {
+    let mut reader = payload;
+    let len = consensus::read_compact_size(&mut reader)? as usize;
+    if len > MAX_ADDR {
+        return Err(Error::InvalidMessage("too many addresses"));
+    }
+
+    let mut addresses = Vec::with_capacity(len);
+    for _ in 0..len {
+        addresses.push(AddrV2Message::consensus_decode(&mut reader)?);
+    }
+
+    if !reader.is_empty() {
+        return Err(Error::TrailingBytes(reader.len()));
+    }
+    Ok(addresses)
+}
+
+fn deserialize_headers(payload: &[u8]) -> Result<Vec<BlockHeader>> {
+    let mut reader = payload;
+    let len = consensus::read_compact_size(&mut reader)? as usize;
+    if len > MAX_HEADERS {
+        return Err(Error::InvalidMessage("too many headers"));
+    }
+
+    // the transaction counts are ignored, as Bitcoin Core does
+    let mut headers = Vec::with_capacity(len);
+    for _ in 0..len {
+        headers.push(BlockHeader::consensus_decode(&mut reader)?);
+        consensus::read_compact_size(&mut reader)?;
+    }
+
+    if !reader.is_empty() {
+        return Err(Error::TrailingBytes(reader.len()));
+    }
+    Ok(headers)
+}
+
+fn deserialize_inv(payload: &[u8]) -> Result<Vec<Inventory>> {
+    let items: Vec<_> = consensus::deserialize(payload)?;
+    if items.len() > MAX_INV_SIZE {
+        return Err(Error::InvalidMessage("too many inventory items"));
+    }
+
+    Ok(items)
+}
+
+/// A payload in its envelope
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub(crate) magic: [u8; 4],
+    pub(crate) payload: NetworkMessage,
+}
+
+impl Message {
+    pub fn new(magic: [u8; 4], payload: NetworkMessage) -> Self {
+        Self { magic, payload }
+    }
+
+    pub fn magic(&self) -> [u8; 4] {
+        self.magic
+    }
+
+    pub fn command(&self) -> &str {
+        self.payload.command()
+    }
+
+    pub fn payload(&self) -> &NetworkMessage {
+        &self.payload
+    }
+
+    pub fn into_payload(self) -> NetworkMessage {
+        self.payload
+    }
+
+    /// The message at the start of `buf` and the bytes it took, none until
+    /// all of it arrived
+    pub fn parse(buf: &[u8]) -> Result<Option<(Self, usize)>> {
+        if buf.len() < HEADER_SIZE {
+            return Ok(None);
+        }
+
+        let header: MessageHeader = consensus::deserialize(&buf[..HEADER_SIZE])?;
+        let len = HEADER_SIZE + header.length as usize;
+        if header.length as usize > MAX_PAYLOAD_SIZE {
+            return Err(Error::InvalidMessage("payload too large"));
+        }
+        if buf.len() < len {
+            return Ok(None);
+        }
+
+        let message = Self::from_parts(&header, &buf[HEADER_SIZE..len])?;
+        Ok(Some((message, len)))
+    }
+
+    /// The message of `header` once its payload was read
+    pub fn from_parts(header: &MessageHeader, payload: &[u8]) -> Result<Self> {
+        if payload.len() != header.length as usize {
+            return Err(Error::InvalidMessage("payload length mismatch"));
+        }
+        if checksum(payload) != header.checksum {
+            return Err(Error::InvalidMessage("checksum mismatch"));
+        }
+
+        let command = header.command()?;
+        Ok(Self {
+            magic: header.magic,
+            payload: NetworkMessage::deserialize(&command, payload)?,
+        })
+    }
+
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        consensus::serialize(self)
+    }
+}
+
+impl Encodable for Message {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        let command = self.command().as_bytes();
+        if command.len() > COMMAND_SIZE {
+            return Err(Error::InvalidMessage("command too long"));
+        }
+
+        let payload = self.payload.serialize()?;
+        let mut header = MessageHeader {
+            magic: self.magic,
+            command: [0; COMMAND_SIZE],
+            length: payload.len() as u32,
+            checksum: checksum(&payload),
+        };
+        header.command[..command.len()].copy_from_slice(command);
+
+        header.consensus_encode(writer)?;
+        writer.write_all(&payload)?;
+        Ok(HEADER_SIZE + payload.len())
+    }
+}
+
+impl Decodable for Message {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        let header = MessageHeader::consensus_decode(reader)?;
+        if header.length as usize > MAX_PAYLOAD_SIZE {
+            return Err(Error::InvalidMessage("payload too large"));
+        }
+
+        let mut payload = vec![0; header.length as usize];
+        reader.read_exact(&mut payload)?;
+        Self::from_parts(&header, &payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use anyhow::Result;
+    use hex_literal::hex;
+
+    use crate::net::address::AddrV2;
+    use crate::network::Network;
+
+    use super::*;
+
+    const MAINNET: [u8; 4] = [0xf9, 0xbe, 0xb4, 0xd9];
+
+    #[test]
+    fn envelope() -> Result<()> {
+        let verack = Message::new(MAINNET, NetworkMessage::Verack);
+        let raw = hex!("f9beb4d976657261636b000000000000000000005df6e0e2");
+        assert_eq!(verack.serialize()?, raw);
+        assert_eq!(consensus::deserialize::<Message>(&raw)?, verack);
+
+        // bad checksum, padding and command
+        let mut corrupted = raw;
+        corrupted[20] ^= 1;
+        assert!(consensus::deserialize::<Message>(&corrupted).is_err());
+        let mut corrupted = raw;
+        corrupted[15] = b'x';
+        assert!(consensus::deserialize::<Message>(&corrupted).is_err());
+        let mut corrupted = raw;
+        corrupted[4] = 0x07;
+        assert!(consensus::deserialize::<Message>(&corrupted).is_err());
+
+        // announced payload too large
+        let mut oversized = raw;
+        oversized[16..20].copy_from_slice(&(MAX_PAYLOAD_SIZE as u32 + 1).to_le_bytes());
+        assert!(Message::parse(&oversized).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn framing() -> Result<()> {
+        let ping = Message::new(MAINNET, NetworkMessage::Ping(0x0123_4567_89ab_cdef));
+        let pong = Message::new(MAINNET, NetworkMessage::Pong(7));
+        let mut stream = ping.serialize()?;
+        stream.extend(pong.serialize()?);
+        assert_eq!(stream.len(), 2 * (HEADER_SIZE + 8));
+
+        for len in 0..HEADER_SIZE + 8 {
+            assert_eq!(Message::parse(&stream[..len])?, None);
+        }
+        let (message, len) = Message::parse(&stream)?.unwrap();
+        assert_eq!((message, len), (ping, HEADER_SIZE + 8));
+        let (message, _) = Message::parse(&stream[len..])?.unwrap();
+        assert_eq!(message.into_payload(), NetworkMessage::Pong(7));
+        Ok(())
+    }
+
+    #[test]
+    fn version_message() -> Result<()> {
+        // example of the developer reference
+        let raw = hex!("721101000100000000000000bc8f5e5400000000010000000000000000000000000000000000ffffc61b6409208d010000000000000000000000000000000000ffffcb0071c0208d128035cbc97953f80f2f5361746f7368693a302e392e332fcf05050001");
+        let version = match NetworkMessage::deserialize("version", &raw)? {
+            NetworkMessage::Version(version) => version,
+            _ => unreachable!(),
+        };
+
+        assert_eq!(version.version, 70002);
+        assert_eq!(version.services, ServiceFlags::NETWORK);
+        assert_eq!(version.timestamp, 1_415_483_324);
+        assert_eq!(
+            version.receiver.socket_addr(),
+            "198.27.100.9:8333".parse::<SocketAddr>()?
+        );
+        assert_eq!(
+            version.sender.socket_addr(),
+            "203.0.113.192:8333".parse::<SocketAddr>()?
+        );
+        assert_eq!(version.user_agent, "/Satoshi:0.9.3/");
+        assert_eq!(version.start_height, 329_167);
+        assert!(version.relay);
+        assert_eq!(NetworkMessage::Version(version.clone()).serialize()?, raw);
+
+        // older peers leave the relay flag out
+        let old = NetworkMessage::deserialize("version", &raw[..raw.len() - 1])?;
+        assert_eq!(old, NetworkMessage::Version(version));
+        Ok(())
+    }
+
+    #[test]
+    fn payloads_roundtrip() -> Result<()> {
+        let genesis = Network::Regtest.params().genesis_block();
+        let address = NetAddress::new("127.0.0.1:18444".parse()?, ServiceFlags::NETWORK);
+        let messages = vec![
+            NetworkMessage::GetAddr,
+            NetworkMessage::Addr(vec![(1_700_000_000, address)]),
+            NetworkMessage::AddrV2(vec![
+                AddrV2Message::new(
+                    1_700_000_000,
+                    ServiceFlags::NETWORK,
+                    AddrV2::TorV3([5; 32]),
+                    8333,
+                ),
+                AddrV2Message::new(1_700_000_000, ServiceFlags::NONE, AddrV2::I2p([6; 32]), 0),
+            ]),
+            NetworkMessage::SendHeaders,
+            NetworkMessage::WtxidRelay,
+            NetworkMessage::GetHeaders(GetHeadersMessage {
+                version: PROTOCOL_VERSION,
+                locator: vec![genesis.block_hash()],
+                stop_hash: BlockHash::default(),
+            }),
+            NetworkMessage::Headers(vec![*genesis.header(); 2]),
+            NetworkMessage::Inv(vec![
+                Inventory::Tx(genesis.transactions()[0].txid()?),
+                Inventory::Unknown {
+                    kind: 0x4000_0004,
+                    hash: [7; 32],
+                },
+            ]),
+            NetworkMessage::GetData(vec![
+                Inventory::Block(genesis.block_hash()),
+                Inventory::WTx(genesis.transactions()[0].wtxid()?),
+                Inventory::WitnessTx(genesis.transactions()[0].txid()?),
+                Inventory::WitnessBlock(genesis.block_hash()),
+                Inventory::FilteredWitnessBlock(genesis.block_hash()),
+                Inventory::CompactBlock(genesis.block_hash()),
+            ]),
+            NetworkMessage::Reject(RejectMessage {
+                message: "tx".to_string(),
+                code: RejectMessage::INSUFFICIENT_FEE,
+                reason: "min relay fee not met".to_string(),
+                data: Some([3; 32]),
+            }),
+            NetworkMessage::Reject(RejectMessage {
+                message: "version".to_string(),
+                code: RejectMessage::OBSOLETE,
+                reason: String::new(),
+                data: None,
+            }),
+            NetworkMessage::Tx(genesis.transactions()[0].clone()),
+            NetworkMessage::Block(genesis.clone()),
+            NetworkMessage::FeeFilter(1_000),
+            NetworkMessage::GetCFilters(GetCFiltersMessage {
+                filter_type: BASIC_FILTER_TYPE,
+                start_height: 1,
+                stop_hash: genesis.block_hash(),
+            }),
+            NetworkMessage::CFilter(CFilterMessage {
+                filter_type: BASIC_FILTER_TYPE,
+                block_hash: genesis.block_hash(),
+                filter: BlockFilter::new_basic(&genesis, |_| None)?,
+            }),
+            NetworkMessage::SendCmpct(SendCmpctMessage::new(true)),
+            NetworkMessage::CmpctBlock(CompactBlock::new(&genesis, 7)?),
+            NetworkMessage::GetBlockTxn(GetBlockTxnMessage {
+                block_hash: genesis.block_hash(),
+                indexes: vec![0, 2],
+            }),
+            NetworkMessage::BlockTxn(BlockTxnMessage {
+                block_hash: genesis.block_hash(),
+                transactions: genesis.transactions().to_vec(),
+            }),
+            NetworkMessage::Unknown {
+                command: "sendtxrcncl".to_string(),
+                payload: vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            },
+        ];
+
+        for payload in messages {
+            let message = Message::new(MAINNET, payload);
+            let decoded: Message = consensus::deserialize(&message.serialize()?)?;
+            assert_eq!(decoded, message);
+        }
+
+        // the witness flag is the second highest bit
+        let item = Inventory::Block(genesis.block_hash()).with_witness();
+        assert_eq!(consensus::serialize(&item)?[..4], [2, 0, 0, 0x40]);
+        assert_eq!(item.block_hash(), Some(genesis.block_hash()));
+        assert!(Inventory::WTx(genesis.transactions()[0].wtxid()?).is_tx());
+
+        // headers come with an empty transaction count
+        let headers = NetworkMessage::Headers(vec![*genesis.header()]);
+        assert_eq!(headers.serialize()?.len(), 1 + BlockHeader::SIZE + 1);
+
+        let too_many = NetworkMessage::Headers(vec![*genesis.header(); MAX_HEADERS + 1]);
+        assert!(NetworkMessage::deserialize("headers", &too_many.serialize()?).is_err());
+
+        let long_command = NetworkMessage::Unknown {
+            command: "waytoolongcommand".to_string(),
+            payload: Vec::new(),
+        };
+        assert!(Message::new(MAINNET, long_command).serialize().is_err());
+        Ok(())
+    }
+}
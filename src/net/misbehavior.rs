@@ -0,0 +1,169 @@
+//! Scoring of peers breaking the protocol, as Bitcoin Core did before it
+//! discouraged them outright: each violation adds to the score of the peer,
+//! which is disconnected once it reaches the threshold
+
+use crate::{Error, Result};
+
+use super::message::NetworkMessage;
+
+/// Score at which a peer is disconnected and not connected to again, Bitcoin
+/// Core's former `-banscore` default
+pub const DISCOURAGE_THRESHOLD: u32 = 100;
+
+/// Ways of breaking the protocol, scored as Bitcoin Core 0.20 did
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Misbehavior {
+    /// `version` again after the handshake
+    DuplicateVersion,
+    /// `verack` again after the handshake
+    DuplicateVerack,
+    /// `wtxidrelay` or `sendaddrv2` after the handshake, they only count
+    /// before `verack`
+    LateNegotiation,
+    /// Headers of one message not building on each other
+    NonContinuousHeaders,
+    /// Headers not connecting to the chain known, too many times
+    UnconnectingHeaders,
+    /// A header with not enough work or breaking consensus
+    InvalidHeader,
+    /// A block breaking consensus or not matching its header
+    InvalidBlock,
+    /// A transaction breaking consensus
+    InvalidTransaction,
+    /// A block, transaction or filter not asked for
+    Unrequested,
+}
+
+impl Misbehavior {
+    pub fn score(self) -> u32 {
+        match self {
+            Misbehavior::DuplicateVersion | Misbehavior::DuplicateVerack => 1,
+            Misbehavior::Unrequested => 10,
+            Misbehavior::NonContinuousHeaders | Misbehavior::UnconnectingHeaders => 20,
+            Misbehavior::LateNegotiation
+            | Misbehavior::InvalidHeader
+            | Misbehavior::InvalidBlock
+            | Misbehavior::InvalidTransaction => 100,
+        }
+    }
+
+    pub fn reason(self) -> &'static str {
+        match self {
+            Misbehavior::DuplicateVersion => "duplicate version",
+            Misbehavior::DuplicateVerack => "duplicate verack",
+            Misbehavior::LateNegotiation => "feature negotiation after verack",
+            Misbehavior::NonContinuousHeaders => "non-continuous headers",
+            Misbehavior::UnconnectingHeaders => "unconnecting headers",
+            Misbehavior::InvalidHeader => "invalid header",
+            Misbehavior::InvalidBlock => "invalid block",
+            Misbehavior::InvalidTransaction => "invalid transaction",
+            Misbehavior::Unrequested => "unrequested data",
+        }
+    }
+
+    /// What's wrong with a message received after the handshake, as far as
+    /// can be told from the message alone
+    pub fn of_message(message: &NetworkMessage) -> Option<Self> {
+        match message {
+            NetworkMessage::Version(_) => Some(Misbehavior::DuplicateVersion),
+            NetworkMessage::Verack => Some(Misbehavior::DuplicateVerack),
+            NetworkMessage::WtxidRelay | NetworkMessage::SendAddrV2 => {
+                Some(Misbehavior::LateNegotiation)
+            }
+            NetworkMessage::Headers(headers) => headers
+                .windows(2)
+                .any(|pair| *pair[1].prev_blockhash() != pair[0].block_hash())
+                .then_some(Misbehavior::NonContinuousHeaders),
+            _ => None,
+        }
+    }
+}
+
+/// The score of a peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MisbehaviorScore {
+    pub(crate) score: u32,
+    pub(crate) threshold: u32,
+}
+
+impl Default for MisbehaviorScore {
+    fn default() -> Self {
+        Self {
+            score: 0,
+            threshold: DISCOURAGE_THRESHOLD,
+        }
+    }
+}
+
+impl MisbehaviorScore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Score to disconnect at, [`DISCOURAGE_THRESHOLD`] unless set
+    pub fn with_threshold(mut self, threshold: u32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    pub fn score(&self) -> u32 {
+        self.score
+    }
+
+    /// Whether the peer reached the threshold
+    pub fn is_discouraged(&self) -> bool {
+        self.score >= self.threshold
+    }
+
+    /// Adds `misbehavior` to the score, failing once the threshold is reached
+    pub fn add(&mut self, misbehavior: Misbehavior) -> Result<()> {
+        self.score = self.score.saturating_add(misbehavior.score());
+        match self.is_discouraged() {
+            true => Err(Error::PeerMisbehaved(misbehavior.reason())),
+            false => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::network::Network;
+
+    use super::*;
+
+    #[test]
+    fn scoring() -> Result<()> {
+        let header = *Network::Regtest.params().genesis_block().header();
+        assert_eq!(
+            Misbehavior::of_message(&NetworkMessage::Headers(vec![header; 2])),
+            Some(Misbehavior::NonContinuousHeaders)
+        );
+        assert_eq!(
+            Misbehavior::of_message(&NetworkMessage::Headers(vec![header])),
+            None
+        );
+        assert_eq!(
+            Misbehavior::of_message(&NetworkMessage::SendAddrV2),
+            Some(Misbehavior::LateNegotiation)
+        );
+        assert_eq!(Misbehavior::of_message(&NetworkMessage::Ping(1)), None);
+
+        let mut score = MisbehaviorScore::new();
+        for _ in 0..4 {
+            score.add(Misbehavior::NonContinuousHeaders)?;
+        }
+        assert_eq!(score.score(), 80);
+        assert!(!score.is_discouraged());
+        assert!(matches!(
+            score.add(Misbehavior::UnconnectingHeaders),
+            Err(Error::PeerMisbehaved("unconnecting headers"))
+        ));
+        assert!(score.is_discouraged());
+
+        let mut score = MisbehaviorScore::new().with_threshold(1);
+        assert!(score.add(Misbehavior::DuplicateVersion).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,47 @@
+//! Bitcoin P2P protocol: the messages peers exchange, how they're framed and
+//! how connections are set up
+
+pub mod address;
+pub mod addrman;
+pub mod broadcast;
+pub mod compact;
+pub mod handshake;
+pub mod liveness;
+pub mod message;
+pub mod misbehavior;
+#[cfg(feature = "tokio")]
+pub mod peer;
+pub mod request;
+pub mod seeds;
+pub mod spv;
+pub mod sync;
+pub mod transport;
+
+pub use address::{AddrV2, AddrV2Message, NetAddress, ServiceFlags};
+pub use addrman::AddressBook;
+pub use broadcast::{BroadcastStatus, TxBroadcast};
+pub use compact::{CompactBlock, PartialBlock};
+pub use handshake::Handshake;
+pub use liveness::Liveness;
+pub use message::{Message, NetworkMessage, VersionMessage};
+pub use misbehavior::{Misbehavior, MisbehaviorScore};
+#[cfg(feature = "tokio")]
+pub use peer::Peer;
+pub use request::RequestTracker;
+pub use seeds::DnsSeeder;
+pub use spv::{SpvClient, WalletEvent};
+pub use sync::HeaderSync;
+pub use transport::V2Handshake;
+
+/// Latest protocol version spoken, the one `wtxidrelay` came with
+pub const PROTOCOL_VERSION: u32 = 70016;
+/// Oldest protocol version peers are accepted with
+pub const MIN_PEER_PROTOCOL_VERSION: u32 = 31800;
+/// Protocol version `sendheaders` came with (BIP130)
+pub const SENDHEADERS_VERSION: u32 = 70012;
+/// Protocol version `feefilter` came with (BIP133)
+pub const FEEFILTER_VERSION: u32 = 70013;
+/// Protocol version compact blocks came with (BIP152)
+pub const SHORT_IDS_BLOCKS_VERSION: u32 = 70014;
+/// Protocol version `wtxidrelay` came with (BIP339)
+pub const WTXID_RELAY_VERSION: u32 = 70016;
@@ -0,0 +1,872 @@
+//! Connections to peers over TCP: the handshake, then typed messages both ways,
+//! every wait for the peer bounded by a timeout. Messages go encrypted over the
+//! v2 transport (BIP324) with the peers that speak it
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::time;
+
+use crate::core::block::Block;
+use crate::core::fee::FeeRate;
+use crate::core::tx::Transaction;
+use crate::{Error, Result};
+
+use super::broadcast::{BroadcastStatus, TxBroadcast};
+use super::compact::{CompactBlock, PartialBlock, COMPACT_BLOCK_VERSION};
+use super::handshake::{Handshake, PeerFeatures};
+use super::liveness::Liveness;
+use super::message::{Inventory, Message, NetworkMessage};
+use super::misbehavior::{Misbehavior, MisbehaviorScore};
+use super::transport::{self, ReceiveCipher, SendCipher, V2Handshake};
+
+/// Time to connect and complete the handshake in
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(60);
+/// Time without messages after which the peer is given up on, as Bitcoin Core
+/// does
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(20 * 60);
+
+/// `future` unless `duration` runs out first, a `TimedOut` io error then
+async fn timeout<T>(duration: Duration, future: impl Future<Output = Result<T>>) -> Result<T> {
+    match time::timeout(duration, future).await {
+        Ok(result) => result,
+        Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "peer timed out").into()),
+    }
+}
+
+/// The receiving half of a connection, a stream of messages
+#[derive(Debug)]
+pub struct MessageReader {
+    pub(crate) reader: OwnedReadHalf,
+    pub(crate) magic: [u8; 4],
+    /// Over the v2 transport, none over v1
+    pub(crate) cipher: Option<ReceiveCipher>,
+    /// Bytes of messages not complete yet
+    pub(crate) buffer: Vec<u8>,
+    /// Received during the handshake, handed out first
+    pub(crate) pending: VecDeque<NetworkMessage>,
+}
+
+impl MessageReader {
+    pub fn new(reader: OwnedReadHalf, magic: [u8; 4]) -> Self {
+        Self {
+            reader,
+            magic,
+            cipher: None,
+            buffer: Vec::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Whether messages arrive over the v2 transport
+    pub fn is_v2(&self) -> bool {
+        self.cipher.is_some()
+    }
+
+    /// The next message, waiting for it as long as it takes. The connection
+    /// closing is an `UnexpectedEof` io error. Cancelling it loses nothing
+    pub async fn receive(&mut self) -> Result<NetworkMessage> {
+        if let Some(message) = self.pending.pop_front() {
+            return Ok(message);
+        }
+
+        loop {
+            if let Some(message) = self.parse()? {
+                return Ok(message);
+            }
+
+            self.read().await?;
+        }
+    }
+
+    /// The message at the start of the buffer if all of it arrived, decoy
+    /// packets are dropped
+    fn parse(&mut self) -> Result<Option<NetworkMessage>> {
+        if let Some(cipher) = &mut self.cipher {
+            while let Some((packet, len)) = cipher.parse(&self.buffer, b"")? {
+                self.buffer.drain(..len);
+                if !packet.ignore {
+                    return transport::deserialize_message(&packet.contents).map(Some);
+                }
+            }
+            return Ok(None);
+        }
+
+        // other networks are told apart before their messages arrive
+        let len = self.buffer.len().min(self.magic.len());
+        if self.buffer[..len] != self.magic[..len] {
+            return Err(Error::InvalidMessage("wrong network magic"));
+        }
+
+        match Message::parse(&self.buffer)? {
+            Some((message, len)) => {
+                self.buffer.drain(..len);
+                Ok(Some(message.into_payload()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Waits for more bytes, the connection closing is an `UnexpectedEof` io
+    /// error
+    async fn read(&mut self) -> Result<()> {
+        match self.reader.read_buf(&mut self.buffer).await? {
+            0 => Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The sending half of a connection, a sink of messages
+#[derive(Debug)]
+pub struct MessageWriter {
+    pub(crate) writer: OwnedWriteHalf,
+    pub(crate) magic: [u8; 4],
+    /// Over the v2 transport, none over v1
+    pub(crate) cipher: Option<SendCipher>,
+}
+
+impl MessageWriter {
+    pub fn new(writer: OwnedWriteHalf, magic: [u8; 4]) -> Self {
+        Self {
+            writer,
+            magic,
+            cipher: None,
+        }
+    }
+
+    /// Whether messages are sent over the v2 transport
+    pub fn is_v2(&self) -> bool {
+        self.cipher.is_some()
+    }
+
+    pub async fn send(&mut self, message: NetworkMessage) -> Result<()> {
+        let bytes = match &mut self.cipher {
+            Some(cipher) => cipher.encrypt_message(&message)?,
+            None => Message::new(self.magic, message).serialize()?,
+        };
+        self.writer.write_all(&bytes).await?;
+        Ok(())
+    }
+
+    /// Stops sending, the peer sees the connection closing once it read
+    /// everything sent before
+    pub async fn shutdown(&mut self) -> Result<()> {
+        self.writer.shutdown().await?;
+        Ok(())
+    }
+}
+
+/// A connection past the handshake
+#[derive(Debug)]
+pub struct Peer {
+    pub(crate) address: SocketAddr,
+    pub(crate) features: PeerFeatures,
+    pub(crate) reader: MessageReader,
+    pub(crate) writer: MessageWriter,
+    pub(crate) idle_timeout: Duration,
+    pub(crate) liveness: Liveness,
+    pub(crate) score: MisbehaviorScore,
+    /// Of the v2 transport, none over v1
+    pub(crate) session_id: Option<[u8; 32]>,
+}
+
+impl Peer {
+    /// Connects to `address` on the network of `magic`, in
+    /// [`HANDSHAKE_TIMEOUT`]
+    pub async fn connect(
+        address: SocketAddr,
+        magic: [u8; 4],
+        handshake: Handshake,
+    ) -> Result<Self> {
+        Self::connect_timeout(address, magic, handshake, HANDSHAKE_TIMEOUT).await
+    }
+
+    pub async fn connect_timeout(
+        address: SocketAddr,
+        magic: [u8; 4],
+        handshake: Handshake,
+        duration: Duration,
+    ) -> Result<Self> {
+        timeout(duration, async {
+            let (reader, writer) = Self::open(address, magic).await?;
+            Self::handshake(address, reader, writer, handshake, None).await
+        })
+        .await
+    }
+
+    /// Connects over the v2 transport, falling back to v1 if the peer hangs
+    /// up before sending anything as peers not speaking v2 do, in
+    /// [`HANDSHAKE_TIMEOUT`]
+    pub async fn connect_v2(
+        address: SocketAddr,
+        magic: [u8; 4],
+        handshake: Handshake,
+    ) -> Result<Self> {
+        Self::connect_v2_timeout(address, magic, handshake, HANDSHAKE_TIMEOUT).await
+    }
+
+    pub async fn connect_v2_timeout(
+        address: SocketAddr,
+        magic: [u8; 4],
+        handshake: Handshake,
+        duration: Duration,
+    ) -> Result<Self> {
+        timeout(duration, async {
+            let (mut reader, mut writer) = Self::open(address, magic).await?;
+            if let Some(session_id) = Self::key_exchange(&mut reader, &mut writer, true).await? {
+                return Self::handshake(address, reader, writer, handshake, Some(session_id)).await;
+            }
+
+            let (reader, writer) = Self::open(address, magic).await?;
+            Self::handshake(address, reader, writer, handshake, None).await
+        })
+        .await
+    }
+
+    /// Over an accepted connection, over the v2 transport unless the peer
+    /// starts with a v1 `version`
+    pub async fn from_stream(
+        stream: TcpStream,
+        magic: [u8; 4],
+        handshake: Handshake,
+        duration: Duration,
+    ) -> Result<Self> {
+        timeout(duration, async {
+            let address = stream.peer_addr()?;
+            let (mut reader, mut writer) = Self::split(stream, magic)?;
+
+            let prefix = transport::v1_prefix(magic);
+            while reader.buffer.len() < prefix.len() && prefix.starts_with(&reader.buffer) {
+                reader.read().await?;
+            }
+            if reader.buffer.starts_with(&prefix) {
+                return Self::handshake(address, reader, writer, handshake, None).await;
+            }
+
+            match Self::key_exchange(&mut reader, &mut writer, false).await? {
+                Some(session_id) => {
+                    Self::handshake(address, reader, writer, handshake, Some(session_id)).await
+                }
+                None => Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+            }
+        })
+        .await
+    }
+
+    /// Longest wait for a message in [`Peer::receive`], [`IDLE_TIMEOUT`]
+    /// unless set
+    pub fn with_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Pings and their timeouts, see [`Peer::keepalive`]
+    pub fn with_liveness(mut self, liveness: Liveness) -> Self {
+        self.liveness = liveness;
+        self
+    }
+
+    /// Score to disconnect at, see [`Peer::misbehaving`]
+    pub fn with_score(mut self, score: MisbehaviorScore) -> Self {
+        self.score = score;
+        self
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn features(&self) -> &PeerFeatures {
+        &self.features
+    }
+
+    pub fn liveness(&self) -> &Liveness {
+        &self.liveness
+    }
+
+    /// Time the last ping took to be answered, none until one is
+    pub fn latency(&self) -> Option<Duration> {
+        self.liveness.latency()
+    }
+
+    pub fn score(&self) -> &MisbehaviorScore {
+        &self.score
+    }
+
+    /// Whether messages go over the v2 transport
+    pub fn is_v2(&self) -> bool {
+        self.session_id.is_some()
+    }
+
+    /// Same on both sides of a v2 connection unless someone is in the middle,
+    /// none over v1
+    pub fn session_id(&self) -> Option<[u8; 32]> {
+        self.session_id
+    }
+
+    pub async fn send(&mut self, message: NetworkMessage) -> Result<()> {
+        self.writer.send(message).await
+    }
+
+    /// The next message other than `sendheaders`, `feefilter` and `sendcmpct`
+    /// of the version spoken, which are kept track of in the features of the
+    /// peer. Pongs are kept track of for
+    /// the latency but handed out as well, pings are left to answer. Messages
+    /// breaking the protocol are dropped and scored, failing once the peer
+    /// should be disconnected
+    pub async fn receive(&mut self) -> Result<NetworkMessage> {
+        loop {
+            let message = timeout(self.idle_timeout, self.reader.receive()).await?;
+            self.liveness.receive(&message, Instant::now());
+            if let Some(misbehavior) = Misbehavior::of_message(&message) {
+                self.misbehaving(misbehavior)?;
+                continue;
+            }
+
+            match message {
+                NetworkMessage::SendHeaders => self.features.send_headers = true,
+                NetworkMessage::SendCmpct(compact) if compact.version == COMPACT_BLOCK_VERSION => {
+                    self.features.compact_blocks = true;
+                    self.features.high_bandwidth = compact.announce;
+                }
+                NetworkMessage::FeeFilter(fee_rate) => {
+                    self.features.fee_filter = FeeRate::from_sat_per_kvb(fee_rate.max(0) as u64)
+                }
+                message => return Ok(message),
+            }
+        }
+    }
+
+    /// Adds to the score of the peer, failing once it should be disconnected
+    pub fn misbehaving(&mut self, misbehavior: Misbehavior) -> Result<()> {
+        self.score.add(misbehavior)
+    }
+
+    /// Pings the peer if it's due, failing if the last ping wasn't answered
+    /// in time. Meant to be called every so often while receiving
+    pub async fn keepalive(&mut self) -> Result<()> {
+        let now = Instant::now();
+        self.liveness.check(now)?;
+        if let Some(ping) = self.liveness.next_ping(now) {
+            self.send(ping).await?;
+        }
+        Ok(())
+    }
+
+    /// Broadcasts `tx` with the defaults of [`TxBroadcast`], see
+    /// [`Peer::broadcast`]
+    pub async fn broadcast_transaction(&mut self, tx: &Transaction) -> Result<BroadcastStatus> {
+        self.broadcast(TxBroadcast::new(tx.clone())?).await
+    }
+
+    /// Announces the transaction of `broadcast` every rebroadcast interval
+    /// until the peer announces it back, it's in a block or it's rejected.
+    /// Pings are answered along the way, other messages are dropped
+    pub async fn broadcast(&mut self, mut broadcast: TxBroadcast) -> Result<BroadcastStatus> {
+        loop {
+            if let Some(inv) = broadcast.next_announcement(Instant::now(), &self.features)? {
+                self.send(inv).await?;
+            }
+
+            let deadline = match broadcast.next_announcement_at() {
+                Some(deadline) => time::Instant::from_std(deadline),
+                None => time::Instant::now(),
+            };
+            let message = match time::timeout_at(deadline, self.receive()).await {
+                Ok(message) => message?,
+                // time to announce it again
+                Err(_) => continue,
+            };
+
+            if let NetworkMessage::Ping(nonce) = message {
+                self.send(NetworkMessage::Pong(nonce)).await?;
+                continue;
+            }
+            if let Some(answer) = broadcast.receive(&message) {
+                self.send(answer).await?;
+            }
+            if let Some(status) = broadcast.status() {
+                return Ok(status.clone());
+            }
+        }
+    }
+
+    /// The block of `compact`, rebuilt from the transactions of `mempool` and
+    /// those missing asked for with `getblocktxn`. The full block is asked for
+    /// if that fails, as after short id collisions. Pings are answered along
+    /// the way, other messages are dropped
+    pub async fn reconstruct_block<'a>(
+        &mut self,
+        compact: &CompactBlock,
+        mempool: impl IntoIterator<Item = &'a Transaction>,
+    ) -> Result<Block> {
+        let hash = compact.block_hash();
+        if let Ok(mut partial) = PartialBlock::new(compact, mempool) {
+            if !partial.is_complete() {
+                self.send(NetworkMessage::GetBlockTxn(partial.request()))
+                    .await?;
+                loop {
+                    match self.receive().await? {
+                        NetworkMessage::BlockTxn(message) if message.block_hash == hash => {
+                            if partial.fill(&message).is_err() {
+                                self.misbehaving(Misbehavior::InvalidBlock)?;
+                            }
+                            break;
+                        }
+                        NetworkMessage::Ping(nonce) => {
+                            self.send(NetworkMessage::Pong(nonce)).await?
+                        }
+                        _ => {}
+                    }
+                }
+            }
+
+            if let Ok(block) = partial.into_block() {
+                return Ok(block);
+            }
+        }
+
+        self.send(NetworkMessage::GetData(vec![Inventory::WitnessBlock(hash)]))
+            .await?;
+        loop {
+            match self.receive().await? {
+                NetworkMessage::Block(block) if block.block_hash() == hash => return Ok(block),
+                NetworkMessage::Ping(nonce) => self.send(NetworkMessage::Pong(nonce)).await?,
+                _ => {}
+            }
+        }
+    }
+
+    /// Halves to receive and send from different tasks, without the timeouts
+    pub fn into_split(self) -> (MessageReader, MessageWriter) {
+        (self.reader, self.writer)
+    }
+
+    /// Closes the connection once what was sent is flushed
+    pub async fn shutdown(mut self) -> Result<()> {
+        self.writer.shutdown().await
+    }
+
+    async fn open(address: SocketAddr, magic: [u8; 4]) -> Result<(MessageReader, MessageWriter)> {
+        Self::split(TcpStream::connect(address).await?, magic)
+    }
+
+    fn split(stream: TcpStream, magic: [u8; 4]) -> Result<(MessageReader, MessageWriter)> {
+        stream.set_nodelay(true)?;
+        let (reader, writer) = stream.into_split();
+        Ok((
+            MessageReader::new(reader, magic),
+            MessageWriter::new(writer, magic),
+        ))
+    }
+
+    /// Sets up the ciphers of both halves, returning the session id. None if
+    /// the peer hung up before sending anything
+    async fn key_exchange(
+        reader: &mut MessageReader,
+        writer: &mut MessageWriter,
+        initiating: bool,
+    ) -> Result<Option<[u8; 32]>> {
+        let mut handshake = V2Handshake::new(reader.magic, initiating);
+        let mut received = !reader.buffer.is_empty();
+        let mut send = handshake.start();
+        loop {
+            // the peer may hang up while this is still being sent
+            match writer.writer.write_all(&send).await {
+                Err(_) if !received => return Ok(None),
+                result => result?,
+            }
+
+            let (used, answer) = handshake.receive(&reader.buffer)?;
+            reader.buffer.drain(..used);
+            send = answer;
+            if handshake.is_done() {
+                break;
+            }
+
+            match reader.read().await {
+                Ok(()) => received = true,
+                Err(Error::IoError { .. }) if !received => return Ok(None),
+                Err(err) => return Err(err),
+            }
+        }
+        writer.writer.write_all(&send).await?;
+
+        // safe, done
+        let ciphers = handshake.into_ciphers().unwrap();
+        reader.cipher = Some(ciphers.receive);
+        writer.cipher = Some(ciphers.send);
+        Ok(Some(ciphers.session_id))
+    }
+
+    async fn handshake(
+        address: SocketAddr,
+        mut reader: MessageReader,
+        mut writer: MessageWriter,
+        mut handshake: Handshake,
+        session_id: Option<[u8; 32]>,
+    ) -> Result<Self> {
+        writer.send(handshake.start()).await?;
+        while !handshake.is_done() {
+            let message = reader.receive().await?;
+            for answer in handshake.receive(&message)? {
+                writer.send(answer).await?;
+            }
+
+            match message {
+                NetworkMessage::Version(_)
+                | NetworkMessage::Verack
+                | NetworkMessage::WtxidRelay
+                | NetworkMessage::SendAddrV2
+                | NetworkMessage::SendHeaders
+                | NetworkMessage::SendCmpct(_) => {}
+                message => reader.pending.push_back(message),
+            }
+        }
+
+        Ok(Self {
+            address,
+            // safe, the handshake is done
+            features: handshake.features().unwrap(),
+            reader,
+            writer,
+            idle_timeout: IDLE_TIMEOUT,
+            liveness: Liveness::new(Instant::now()),
+            score: MisbehaviorScore::new(),
+            session_id,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use tokio::net::TcpListener;
+    use tokio::runtime::{Builder, Runtime};
+
+    use crate::net::address::ServiceFlags;
+    use crate::net::message::VersionMessage;
+    use crate::network::Network;
+
+    use super::*;
+
+    fn runtime() -> Runtime {
+        Builder::new_current_thread().enable_all().build().unwrap()
+    }
+
+    #[test]
+    fn handshake_and_messages() -> Result<()> {
+        let magic = Network::Regtest.params().magic;
+        runtime().block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await?;
+            let address = listener.local_addr()?;
+
+            // answers a ping, then waits for the connection to close
+            let node = tokio::spawn(async move {
+                let (stream, _) = listener.accept().await?;
+                let version =
+                    VersionMessage::new(address, 100).with_services(ServiceFlags::NETWORK);
+                let mut peer =
+                    Peer::from_stream(stream, magic, Handshake::new(version), HANDSHAKE_TIMEOUT)
+                        .await?;
+                if let NetworkMessage::Ping(nonce) = peer.receive().await? {
+                    peer.send(NetworkMessage::Pong(nonce)).await?;
+                }
+                peer.receive().await
+            });
+
+            let handshake = Handshake::new(VersionMessage::new(address, 0))
+                .with_required_services(ServiceFlags::NETWORK);
+            let mut peer = Peer::connect(address, magic, handshake).await?;
+            assert_eq!(peer.address(), address);
+            assert_eq!(peer.features().start_height, 100);
+            assert!(peer.features().wtxid_relay);
+
+            peer.send(NetworkMessage::Ping(42)).await?;
+            assert_eq!(peer.receive().await?, NetworkMessage::Pong(42));
+            assert!(peer.features().send_headers);
+            assert_eq!(peer.latency(), None);
+
+            peer.shutdown().await?;
+            assert!(node.await?.is_err());
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn keepalive_and_misbehavior() -> Result<()> {
+        let magic = Network::Regtest.params().magic;
+        runtime().block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await?;
+            let address = listener.local_addr()?;
+
+            // answers pings, then breaks the protocol
+            let node = tokio::spawn(async move {
+                let (stream, _) = listener.accept().await?;
+                let handshake = Handshake::new(VersionMessage::new(address, 0));
+                let mut peer =
+                    Peer::from_stream(stream, magic, handshake, HANDSHAKE_TIMEOUT).await?;
+                if let NetworkMessage::Ping(nonce) = peer.receive().await? {
+                    peer.send(NetworkMessage::Pong(nonce)).await?;
+                }
+                peer.send(NetworkMessage::Verack).await?;
+                peer.send(NetworkMessage::SendAddrV2).await?;
+                peer.receive().await
+            });
+
+            let handshake = Handshake::new(VersionMessage::new(address, 0));
+            let mut peer = Peer::connect(address, magic, handshake).await?;
+            peer.keepalive().await?;
+            assert!(peer.liveness().is_waiting());
+            assert!(matches!(peer.receive().await?, NetworkMessage::Pong(_)));
+            assert!(!peer.liveness().is_waiting());
+            assert!(peer.latency().is_some());
+            // not due again yet
+            peer.keepalive().await?;
+            assert!(!peer.liveness().is_waiting());
+
+            assert!(matches!(
+                peer.receive().await,
+                Err(Error::PeerMisbehaved("feature negotiation after verack"))
+            ));
+            assert_eq!(peer.score().score(), 101);
+
+            peer.shutdown().await?;
+            assert!(node.await?.is_err());
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn v2_transport_and_downgrade() -> Result<()> {
+        let magic = Network::Regtest.params().magic;
+        runtime().block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await?;
+            let address = listener.local_addr()?;
+
+            // speaks v2, then hangs up on v2 as v1 peers do
+            let node = tokio::spawn(async move {
+                let (stream, _) = listener.accept().await?;
+                let handshake = Handshake::new(VersionMessage::new(address, 7));
+                let mut peer =
+                    Peer::from_stream(stream, magic, handshake, HANDSHAKE_TIMEOUT).await?;
+                assert!(peer.is_v2());
+                if let NetworkMessage::Ping(nonce) = peer.receive().await? {
+                    peer.send(NetworkMessage::Pong(nonce)).await?;
+                }
+                let session_id = peer.session_id();
+                assert!(peer.receive().await.is_err());
+
+                let (mut stream, _) = listener.accept().await?;
+                stream.read_buf(&mut Vec::new()).await?;
+                drop(stream);
+                let (stream, _) = listener.accept().await?;
+                let handshake = Handshake::new(VersionMessage::new(address, 8));
+                let mut peer =
+                    Peer::from_stream(stream, magic, handshake, HANDSHAKE_TIMEOUT).await?;
+                assert!(!peer.is_v2());
+                if let NetworkMessage::Ping(nonce) = peer.receive().await? {
+                    peer.send(NetworkMessage::Pong(nonce)).await?;
+                }
+                let _ = peer.receive().await;
+                Ok::<_, Error>(session_id)
+            });
+
+            let handshake = Handshake::new(VersionMessage::new(address, 0));
+            let mut peer = Peer::connect_v2(address, magic, handshake.clone()).await?;
+            assert!(peer.is_v2());
+            assert_eq!(peer.features().start_height, 7);
+            peer.send(NetworkMessage::Ping(42)).await?;
+            assert_eq!(peer.receive().await?, NetworkMessage::Pong(42));
+            let session_id = peer.session_id();
+            peer.shutdown().await?;
+
+            let mut peer = Peer::connect_v2(address, magic, handshake).await?;
+            assert!(!peer.is_v2());
+            assert_eq!(peer.session_id(), None);
+            assert_eq!(peer.features().start_height, 8);
+            // the node sends sendcmpct once done, wait for it before hanging up
+            peer.send(NetworkMessage::Ping(43)).await?;
+            assert_eq!(peer.receive().await?, NetworkMessage::Pong(43));
+            peer.shutdown().await?;
+
+            assert_eq!(node.await??, session_id);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn compact_blocks() -> Result<()> {
+        use crate::core::input::{OutPoint, TxIn};
+        use crate::core::mining::mine_block;
+        use crate::core::script::Script;
+        use crate::core::txid::Txid;
+
+        let magic = Network::Regtest.params().magic;
+        let txs = (1..=4)
+            .map(|i| {
+                let outpoint = OutPoint::new(Txid::from_bytes([i; 32]), 0);
+                Transaction::new(2, vec![TxIn::new(outpoint)], vec![], 0)
+            })
+            .collect::<Vec<_>>();
+        let genesis = Network::Regtest.params().genesis_block();
+        let block = mine_block(genesis.header(), 1, &txs, Script::new())?;
+        let compact = CompactBlock::new(&block, 1)?;
+
+        runtime().block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await?;
+            let address = listener.local_addr()?;
+
+            // serves the block, whole or by its transactions
+            let node = tokio::spawn(async move {
+                let (stream, _) = listener.accept().await?;
+                let handshake = Handshake::new(VersionMessage::new(address, 0));
+                let mut peer =
+                    Peer::from_stream(stream, magic, handshake, HANDSHAKE_TIMEOUT).await?;
+                let mut requests = Vec::new();
+                loop {
+                    let answer = match peer.receive().await? {
+                        NetworkMessage::GetBlockTxn(request) => {
+                            requests.push(request.indexes.clone());
+                            NetworkMessage::BlockTxn(request.answer(&block)?)
+                        }
+                        NetworkMessage::GetData(_) => NetworkMessage::Block(block.clone()),
+                        _ => break,
+                    };
+                    peer.send(answer).await?;
+                }
+                Ok::<_, Error>(requests)
+            });
+
+            let handshake = Handshake::new(VersionMessage::new(address, 0));
+            let mut peer = Peer::connect(address, magic, handshake).await?;
+            let rebuilt = peer.reconstruct_block(&compact, &txs[1..3]).await?;
+            assert_eq!(rebuilt.block_hash(), compact.block_hash());
+            assert_eq!(rebuilt.transactions()[1..], txs[..]);
+            // sent after verack, so known once something else arrived
+            assert!(peer.features().compact_blocks);
+            assert!(!peer.features().high_bandwidth);
+
+            // a wrong transaction matching a short id, the full block then
+            let mut colliding = compact.clone();
+            colliding.short_ids[0] = colliding.short_id(&txs[3].wtxid()?);
+            colliding.short_ids[3] = compact.short_ids[0];
+            let full = peer.reconstruct_block(&colliding, &txs).await?;
+            assert_eq!(full, rebuilt);
+
+            peer.send(NetworkMessage::Ping(0)).await?;
+            assert_eq!(node.await??, [vec![1, 4]]);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn timeouts_and_wrong_network() -> Result<()> {
+        runtime().block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await?;
+            let address = listener.local_addr()?;
+
+            // a silent peer, then one of another network
+            let node = tokio::spawn(async move {
+                let (silent, _) = listener.accept().await?;
+                let (stream, _) = listener.accept().await?;
+                let handshake = Handshake::new(VersionMessage::new(address, 0));
+                let mainnet = Network::Mainnet.params().magic;
+                let result = Peer::from_stream(stream, mainnet, handshake, HANDSHAKE_TIMEOUT).await;
+                drop(silent);
+                result
+            });
+
+            let regtest = Network::Regtest.params().magic;
+            let handshake = Handshake::new(VersionMessage::new(address, 0));
+            match Peer::connect_timeout(
+                address,
+                regtest,
+                handshake.clone(),
+                Duration::from_millis(100),
+            )
+            .await
+            {
+                Err(Error::IoError { source }) => {
+                    assert_eq!(source.kind(), io::ErrorKind::TimedOut)
+                }
+                result => panic!("unexpected {:?}", result),
+            }
+
+            assert!(matches!(
+                Peer::connect(address, regtest, handshake).await,
+                Err(Error::InvalidMessage(_))
+            ));
+            assert!(node.await?.is_err());
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn broadcast() -> Result<()> {
+        use crate::core::input::{OutPoint, TxIn};
+        use crate::core::mining::mine_block;
+        use crate::core::script::Script;
+        use crate::core::txid::Txid;
+        use crate::net::message::Inventory;
+
+        let magic = Network::Regtest.params().magic;
+        let tx = Transaction::new(
+            2,
+            vec![TxIn::new(OutPoint::new(Txid::from_bytes([1; 32]), 0))],
+            vec![],
+            0,
+        );
+        let genesis = Network::Regtest.params().genesis_block();
+        let block = mine_block(
+            genesis.header(),
+            1,
+            std::slice::from_ref(&tx),
+            Script::new(),
+        )?;
+        let hash = block.block_hash();
+
+        runtime().block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await?;
+            let address = listener.local_addr()?;
+
+            // asks for the transaction, then announces a block with it
+            let node = tokio::spawn(async move {
+                let (stream, _) = listener.accept().await?;
+                let handshake = Handshake::new(VersionMessage::new(address, 0));
+                let mut peer =
+                    Peer::from_stream(stream, magic, handshake, HANDSHAKE_TIMEOUT).await?;
+                peer.send(NetworkMessage::FeeFilter(1_000)).await?;
+                loop {
+                    let answer = match peer.receive().await? {
+                        NetworkMessage::Inv(items) => NetworkMessage::GetData(items),
+                        NetworkMessage::Tx(_) => NetworkMessage::Inv(vec![Inventory::Block(hash)]),
+                        NetworkMessage::GetData(_) => NetworkMessage::Block(block.clone()),
+                        _ => break,
+                    };
+                    peer.send(answer).await?;
+                }
+                peer.receive().await
+            });
+
+            let handshake = Handshake::new(VersionMessage::new(address, 0));
+            let mut peer = Peer::connect(address, magic, handshake).await?;
+            assert_eq!(
+                peer.broadcast_transaction(&tx).await?,
+                BroadcastStatus::Confirmed(hash)
+            );
+            assert_eq!(peer.features().fee_filter, FeeRate::MIN_RELAY);
+
+            peer.shutdown().await?;
+            assert!(node.await?.is_err());
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,375 @@
+//! Bookkeeping of the objects asked for with `getdata`: which peer each one
+//! was asked to and when, so no peer gets more than its share at once and
+//! those not answering in time are asked again to another peer
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::core::tx::Transaction;
+use crate::core::txid::BlockHash;
+use crate::Result;
+
+use super::message::{Inventory, NetworkMessage};
+
+/// Most blocks asked to a peer at once, Bitcoin Core's
+/// `MAX_BLOCKS_IN_TRANSIT_PER_PEER`
+pub const MAX_BLOCKS_IN_FLIGHT: usize = 16;
+/// Most transactions asked to a peer at once, Bitcoin Core's
+/// `MAX_PEER_TX_REQUEST_IN_FLIGHT`
+pub const MAX_TXS_IN_FLIGHT: usize = 100;
+/// Time a peer has to answer a `getdata` in
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// What answers match a request, transactions by any of their ids
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Key {
+    Tx([u8; 32]),
+    Block([u8; 32]),
+}
+
+impl Key {
+    fn of(item: &Inventory) -> Option<Self> {
+        match item.block_hash() {
+            Some(hash) => Some(Key::Block(hash.to_bytes())),
+            None if item.is_tx() => Some(Key::Tx(item.hash())),
+            None => None,
+        }
+    }
+}
+
+/// An object asked to a peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Request {
+    item: Inventory,
+    peer: SocketAddr,
+    sent: Instant,
+}
+
+/// Objects wanted and asked for, without doing any IO itself: `getdata`s are
+/// handed out per peer within the limits and answers taken in
+#[derive(Debug, Clone)]
+pub struct RequestTracker {
+    /// Not asked to any peer yet, in the order they were wanted
+    queue: VecDeque<Inventory>,
+    in_flight: HashMap<Key, Request>,
+    /// Peers that didn't have each object or didn't answer in time
+    failed: HashMap<Key, HashSet<SocketAddr>>,
+    max_blocks: usize,
+    max_txs: usize,
+    timeout: Duration,
+}
+
+impl Default for RequestTracker {
+    fn default() -> Self {
+        Self {
+            queue: VecDeque::new(),
+            in_flight: HashMap::new(),
+            failed: HashMap::new(),
+            max_blocks: MAX_BLOCKS_IN_FLIGHT,
+            max_txs: MAX_TXS_IN_FLIGHT,
+            timeout: REQUEST_TIMEOUT,
+        }
+    }
+}
+
+impl RequestTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Most blocks and transactions asked to a peer at once,
+    /// [`MAX_BLOCKS_IN_FLIGHT`] and [`MAX_TXS_IN_FLIGHT`] unless set
+    pub fn with_limits(mut self, max_blocks: usize, max_txs: usize) -> Self {
+        self.max_blocks = max_blocks;
+        self.max_txs = max_txs;
+        self
+    }
+
+    /// Time peers have to answer in, [`REQUEST_TIMEOUT`] unless set
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Queues `item` unless it's already wanted, whether it's tracked: only
+    /// transactions and blocks are
+    pub fn want(&mut self, item: Inventory) -> bool {
+        let key = match Key::of(&item) {
+            Some(key) => key,
+            None => return false,
+        };
+
+        if self.in_flight.contains_key(&key)
+            || self.queue.iter().any(|queued| Key::of(queued) == Some(key))
+        {
+            return true;
+        }
+
+        self.queue.push_back(item);
+        true
+    }
+
+    /// Whether `item` is queued or asked for
+    pub fn is_wanted(&self, item: &Inventory) -> bool {
+        Key::of(item).is_some_and(|key| {
+            self.in_flight.contains_key(&key)
+                || self.queue.iter().any(|queued| Key::of(queued) == Some(key))
+        })
+    }
+
+    /// Whether nothing is queued or asked for
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty() && self.in_flight.is_empty()
+    }
+
+    /// Objects asked to `peer` and not answered yet
+    pub fn in_flight(&self, peer: &SocketAddr) -> usize {
+        self.in_flight
+            .values()
+            .filter(|request| request.peer == *peer)
+            .count()
+    }
+
+    /// The `getdata` to send `peer` with the queued objects it has room for
+    /// and didn't fail to serve before, none if there are none
+    pub fn next_request(&mut self, peer: SocketAddr, now: Instant) -> Option<NetworkMessage> {
+        let (mut blocks, mut txs) =
+            self.in_flight.keys().fold((0, 0), |(blocks, txs), key| {
+                match (key, self.in_flight[key].peer == peer) {
+                    (Key::Block(_), true) => (blocks + 1, txs),
+                    (Key::Tx(_), true) => (blocks, txs + 1),
+                    _ => (blocks, txs),
+                }
+            });
+
+        let mut items = Vec::new();
+        let mut left = VecDeque::with_capacity(self.queue.len());
+        for item in self.queue.drain(..) {
+            // safe, only those with a key are queued
+            let key = Key::of(&item).unwrap();
+            let count = match key {
+                Key::Block(_) => &mut blocks,
+                Key::Tx(_) => &mut txs,
+            };
+            let max = match key {
+                Key::Block(_) => self.max_blocks,
+                Key::Tx(_) => self.max_txs,
+            };
+            let failed = self
+                .failed
+                .get(&key)
+                .is_some_and(|peers| peers.contains(&peer));
+
+            if *count >= max || failed {
+                left.push_back(item);
+                continue;
+            }
+
+            *count += 1;
+            self.in_flight.insert(
+                key,
+                Request {
+                    item,
+                    peer,
+                    sent: now,
+                },
+            );
+            items.push(item);
+        }
+        self.queue = left;
+
+        match items.is_empty() {
+            true => None,
+            false => Some(NetworkMessage::GetData(items)),
+        }
+    }
+
+    /// `tx` arrived from `peer`, whether it was asked to it
+    pub fn receive_tx(&mut self, peer: &SocketAddr, tx: &Transaction) -> Result<bool> {
+        let txid = Key::Tx(tx.txid()?.to_bytes());
+        let wtxid = Key::Tx(tx.wtxid()?.to_bytes());
+        Ok(self.complete(peer, txid) || self.complete(peer, wtxid))
+    }
+
+    /// The block `hash`, whole, filtered or compact, arrived from `peer`,
+    /// whether it was asked to it
+    pub fn receive_block(&mut self, peer: &SocketAddr, hash: &BlockHash) -> bool {
+        self.complete(peer, Key::Block(hash.to_bytes()))
+    }
+
+    fn complete(&mut self, peer: &SocketAddr, key: Key) -> bool {
+        match self.in_flight.get(&key) {
+            Some(request) if request.peer == *peer => {
+                self.in_flight.remove(&key);
+                self.failed.remove(&key);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// `peer` answered with `notfound`, what it didn't have goes back to the
+    /// front of the queue for other peers
+    pub fn receive_not_found(&mut self, peer: &SocketAddr, items: &[Inventory]) {
+        for item in items {
+            if let Some(key) = Key::of(item) {
+                self.fail(peer, key);
+            }
+        }
+    }
+
+    fn fail(&mut self, peer: &SocketAddr, key: Key) {
+        match self.in_flight.get(&key) {
+            Some(request) if request.peer == *peer => {
+                let request = self.in_flight.remove(&key).unwrap(); // safe, just found
+                self.failed.entry(key).or_default().insert(*peer);
+                self.queue.push_front(request.item);
+            }
+            _ => {}
+        }
+    }
+
+    /// Requests older than the timeout at `now` go back to the queue for other
+    /// peers, returning the peers that let them expire
+    pub fn expire(&mut self, now: Instant) -> Vec<SocketAddr> {
+        let expired: Vec<_> = self
+            .in_flight
+            .iter()
+            .filter(|(_, request)| now.saturating_duration_since(request.sent) >= self.timeout)
+            .map(|(key, request)| (*key, request.peer))
+            .collect();
+
+        let mut peers = Vec::new();
+        for (key, peer) in expired {
+            self.fail(&peer, key);
+            if !peers.contains(&peer) {
+                peers.push(peer);
+            }
+        }
+
+        peers
+    }
+
+    /// The peer disconnected, what was asked to it goes back to the queue
+    pub fn remove_peer(&mut self, peer: &SocketAddr) {
+        let keys: Vec<_> = self
+            .in_flight
+            .iter()
+            .filter(|(_, request)| request.peer == *peer)
+            .map(|(key, _)| *key)
+            .collect();
+
+        for key in keys {
+            self.fail(peer, key);
+        }
+    }
+
+    /// Stops wanting `item`, whether it was queued or asked for
+    pub fn cancel(&mut self, item: &Inventory) -> bool {
+        let key = match Key::of(item) {
+            Some(key) => key,
+            None => return false,
+        };
+
+        let len = self.queue.len();
+        self.queue.retain(|queued| Key::of(queued) != Some(key));
+        self.failed.remove(&key);
+        self.in_flight.remove(&key).is_some() || self.queue.len() != len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::core::txid::Txid;
+    use crate::network::Network;
+
+    use super::*;
+
+    fn block(n: u8) -> Inventory {
+        Inventory::WitnessBlock(BlockHash::from_bytes([n; 32]))
+    }
+
+    fn getdata(items: &[Inventory]) -> Option<NetworkMessage> {
+        Some(NetworkMessage::GetData(items.to_vec()))
+    }
+
+    #[test]
+    fn limits_per_peer() -> Result<()> {
+        let (first, second): (SocketAddr, SocketAddr) =
+            ("127.0.0.1:8333".parse()?, "127.0.0.2:8333".parse()?);
+        let mut tracker = RequestTracker::new().with_limits(2, 1);
+        let tx = Network::Regtest.params().genesis_block().transactions()[0].clone();
+        for n in 0..3 {
+            assert!(tracker.want(block(n)));
+        }
+        // once, by any type of the same object
+        assert!(tracker.want(Inventory::Block(BlockHash::from_bytes([0; 32]))));
+        assert!(tracker.want(Inventory::WTx(tx.wtxid()?)));
+        assert!(tracker.want(Inventory::Tx(Txid::from_bytes([9; 32]))));
+        assert!(!tracker.want(Inventory::Error));
+
+        let now = Instant::now();
+        assert_eq!(
+            tracker.next_request(first, now),
+            getdata(&[block(0), block(1), Inventory::WTx(tx.wtxid()?)])
+        );
+        assert_eq!(tracker.in_flight(&first), 3);
+        assert_eq!(tracker.next_request(first, now), None);
+        assert_eq!(
+            tracker.next_request(second, now),
+            getdata(&[block(2), Inventory::Tx(Txid::from_bytes([9; 32]))])
+        );
+
+        // answers free room, only from the peer asked
+        assert!(!tracker.receive_block(&second, &BlockHash::from_bytes([0; 32])));
+        assert!(tracker.receive_block(&first, &BlockHash::from_bytes([0; 32])));
+        assert!(tracker.receive_tx(&first, &tx)?);
+        assert!(!tracker.receive_tx(&first, &tx)?);
+        assert_eq!(tracker.in_flight(&first), 1);
+
+        assert!(tracker.want(block(3)));
+        assert_eq!(tracker.next_request(first, now), getdata(&[block(3)]));
+        assert!(tracker.cancel(&block(3)));
+        assert!(!tracker.is_wanted(&block(3)));
+        Ok(())
+    }
+
+    #[test]
+    fn failures_go_to_other_peers() -> Result<()> {
+        let (first, second): (SocketAddr, SocketAddr) =
+            ("127.0.0.1:8333".parse()?, "127.0.0.2:8333".parse()?);
+        let mut tracker = RequestTracker::new().with_timeout(Duration::from_secs(10));
+        tracker.want(block(0));
+        tracker.want(block(1));
+
+        let now = Instant::now();
+        tracker.next_request(first, now);
+        tracker.receive_not_found(&first, &[block(0)]);
+        assert_eq!(tracker.in_flight(&first), 1);
+        assert!(tracker.is_wanted(&block(0)));
+        assert_eq!(tracker.next_request(first, now), None);
+        assert_eq!(tracker.next_request(second, now), getdata(&[block(0)]));
+
+        // not answering in time
+        assert!(tracker.expire(now + Duration::from_secs(9)).is_empty());
+        let mut expired = tracker.expire(now + Duration::from_secs(10));
+        expired.sort();
+        assert_eq!(expired, vec![first, second]);
+        assert_eq!(tracker.next_request(first, now), None);
+        assert_eq!(tracker.next_request(second, now), getdata(&[block(1)]));
+
+        // disconnecting
+        tracker.remove_peer(&second);
+        assert_eq!(tracker.in_flight(&second), 0);
+        assert!(!tracker.is_empty());
+        let third = "127.0.0.3:8333".parse()?;
+        assert_eq!(
+            tracker.next_request(third, now),
+            getdata(&[block(1), block(0)])
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+//! Bootstrapping from DNS seeds: hosts whose addresses are those of reachable
+//! peers, most of them serving only peers with given services when asked with
+//! an `x<services>.` subdomain
+
+use std::collections::HashSet;
+use std::net::{SocketAddr, ToSocketAddrs};
+
+use crate::params::Params;
+
+use super::address::{NetAddress, ServiceFlags};
+
+/// Services asked for by default, full nodes with witness data
+pub const DEFAULT_SEED_SERVICES: ServiceFlags =
+    ServiceFlags::from_bits(ServiceFlags::NETWORK.bits() | ServiceFlags::WITNESS.bits());
+
+/// Host to query `seed` for peers with `services`
+pub fn seed_host(seed: &str, services: ServiceFlags) -> String {
+    if services == ServiceFlags::NONE {
+        seed.to_string()
+    } else {
+        format!("x{:x}.{}", services.bits(), seed)
+    }
+}
+
+/// Seeds to resolve and the services of the peers wanted from them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsSeeder {
+    pub(crate) seeds: Vec<String>,
+    pub(crate) port: u16,
+    pub(crate) services: ServiceFlags,
+    pub(crate) filter_by_host: bool,
+}
+
+impl DnsSeeder {
+    /// The seeds of the network, asked for [`DEFAULT_SEED_SERVICES`]
+    pub fn new(params: &Params) -> Self {
+        Self {
+            seeds: params
+                .dns_seeds
+                .iter()
+                .map(|seed| seed.to_string())
+                .collect(),
+            port: params.default_port,
+            services: DEFAULT_SEED_SERVICES,
+            filter_by_host: true,
+        }
+    }
+
+    /// Other seeds than those of the network
+    pub fn with_seeds(mut self, seeds: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.seeds = seeds.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn with_services(mut self, services: ServiceFlags) -> Self {
+        self.services = services;
+        self
+    }
+
+    /// Whether to ask for the services with the subdomain, seeds not
+    /// supporting it don't resolve with it
+    pub fn with_filter_by_host(mut self, filter_by_host: bool) -> Self {
+        self.filter_by_host = filter_by_host;
+        self
+    }
+
+    pub fn seeds(&self) -> &[String] {
+        &self.seeds
+    }
+
+    /// The hosts queried, in order
+    pub fn hosts(&self) -> Vec<String> {
+        let services = if self.filter_by_host {
+            self.services
+        } else {
+            ServiceFlags::NONE
+        };
+
+        self.seeds
+            .iter()
+            .map(|seed| seed_host(seed, services))
+            .collect()
+    }
+
+    /// Addresses of every seed that resolves, without duplicates. They're
+    /// assumed to serve what was asked for, which is checked on connecting.
+    /// Resolving blocks, async callers should do it on a blocking thread
+    pub fn resolve(&self) -> Vec<NetAddress> {
+        let mut seen = HashSet::new();
+        let mut addresses = Vec::new();
+        for host in self.hosts() {
+            let resolved = match (host.as_str(), self.port).to_socket_addrs() {
+                Ok(resolved) => resolved,
+                // the other seeds may still answer
+                Err(_) => continue,
+            };
+
+            for address in resolved {
+                if seen.insert(address) {
+                    addresses.push(NetAddress::new(address, self.services));
+                }
+            }
+        }
+
+        addresses
+    }
+
+    /// Like [`DnsSeeder::resolve`] without the services
+    pub fn resolve_socket_addrs(&self) -> Vec<SocketAddr> {
+        self.resolve().iter().map(NetAddress::socket_addr).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::network::Network;
+
+    use super::*;
+
+    #[test]
+    fn hosts() {
+        assert_eq!(
+            seed_host("seed.bitcoin.sipa.be", DEFAULT_SEED_SERVICES),
+            "x9.seed.bitcoin.sipa.be"
+        );
+        assert_eq!(
+            seed_host("seed.example", ServiceFlags::NONE),
+            "seed.example"
+        );
+
+        let params = Network::Mainnet.params();
+        let seeder = DnsSeeder::new(&params)
+            .with_services(ServiceFlags::NETWORK | ServiceFlags::COMPACT_FILTERS);
+        assert_eq!(seeder.seeds().len(), params.dns_seeds.len());
+        assert_eq!(seeder.hosts()[0], "x41.seed.bitcoin.sipa.be");
+
+        let unfiltered = seeder.with_filter_by_host(false);
+        assert_eq!(unfiltered.hosts()[0], "seed.bitcoin.sipa.be");
+        assert!(DnsSeeder::new(&Network::Regtest.params())
+            .hosts()
+            .is_empty());
+    }
+
+    #[test]
+    fn resolve() {
+        let params = Network::Regtest.params();
+        let seeder = DnsSeeder::new(&params)
+            .with_seeds(vec!["localhost", "localhost", "invalid..host"])
+            .with_filter_by_host(false);
+
+        let addresses = seeder.resolve();
+        assert!(!addresses.is_empty());
+        for address in &addresses {
+            assert!(address.socket_addr().ip().is_loopback());
+            assert_eq!(address.port, params.default_port);
+            assert_eq!(address.services, DEFAULT_SEED_SERVICES);
+        }
+
+        // both seeds resolve to the same addresses
+        let unique: HashSet<_> = seeder.resolve_socket_addrs().into_iter().collect();
+        assert_eq!(unique.len(), addresses.len());
+    }
+}
@@ -0,0 +1,639 @@
+//! Light client over compact block filters (BIP157): headers are synced first,
+//! then the filter of every block is matched against the watched scripts and
+//! only the blocks matching are downloaded, their transactions touching those
+//! scripts reported as events
+
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+
+use crate::core::block::{Block, BlockHeader};
+use crate::core::chain::{ChainEvent, HeaderChain};
+use crate::core::input::OutPoint;
+use crate::core::script::Script;
+use crate::core::tx::Transaction;
+use crate::core::txid::BlockHash;
+use crate::{Error, Result};
+
+use super::message::{
+    CFilterMessage, GetCFiltersMessage, Inventory, NetworkMessage, BASIC_FILTER_TYPE,
+    MAX_GETCFILTERS_SIZE,
+};
+use super::request::MAX_BLOCKS_IN_FLIGHT;
+use super::sync::HeaderSync;
+
+/// What happened to the transactions of the wallet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WalletEvent {
+    /// A transaction paying to a watched script or spending from one made it
+    /// into a block of the active chain
+    Confirmed {
+        tx: Transaction,
+        block_hash: BlockHash,
+        height: u32,
+    },
+    /// Blocks already scanned left the active chain, `disconnected` from the
+    /// old tip down. What confirmed in them isn't anymore, the blocks of the
+    /// new chain are scanned from `fork_height` up
+    Reorg {
+        disconnected: Vec<BlockHash>,
+        fork_height: u32,
+    },
+}
+
+/// Keeps a [`HeaderChain`] synced and scans it for the transactions of a set
+/// of scripts, without doing any IO itself. Filters are trusted as peers
+/// send them, their headers aren't checked
+#[derive(Debug, Clone)]
+pub struct SpvClient {
+    pub(crate) sync: HeaderSync,
+    pub(crate) scripts: Vec<Script>,
+    /// Outputs paying to watched scripts, transactions spending them are
+    /// relevant too
+    pub(crate) outpoints: HashSet<OutPoint>,
+    /// Height of the last block whose filter was matched
+    pub(crate) filter_height: u32,
+    /// Height up to which filters were asked for, none waiting
+    pub(crate) filters_stop: Option<u32>,
+    /// Blocks matching their filter, by height, and whether they were asked for
+    pub(crate) wanted: BTreeMap<u32, (BlockHash, bool)>,
+    pub(crate) events: VecDeque<WalletEvent>,
+}
+
+impl SpvClient {
+    pub fn new(chain: HeaderChain) -> Self {
+        Self::from_sync(HeaderSync::new(chain))
+    }
+
+    /// Over a sync set up with its own stall timeout or peers
+    pub fn from_sync(sync: HeaderSync) -> Self {
+        let filter_height = sync.chain().height();
+        Self {
+            sync,
+            scripts: Vec::new(),
+            outpoints: HashSet::new(),
+            filter_height,
+            filters_stop: None,
+            wanted: BTreeMap::new(),
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Blocks are scanned from `height` on, the birthday of the wallet, rather
+    /// than from the tip the chain had when the client was made
+    pub fn with_scan_from(mut self, height: u32) -> Self {
+        self.filter_height = height.saturating_sub(1);
+        self
+    }
+
+    pub fn watch_script(&mut self, script: Script) {
+        if !self.scripts.contains(&script) {
+            self.scripts.push(script);
+        }
+    }
+
+    pub fn scripts(&self) -> &[Script] {
+        &self.scripts
+    }
+
+    pub fn sync(&self) -> &HeaderSync {
+        &self.sync
+    }
+
+    /// To add and remove peers
+    pub fn sync_mut(&mut self) -> &mut HeaderSync {
+        &mut self.sync
+    }
+
+    pub fn chain(&self) -> &HeaderChain {
+        self.sync.chain()
+    }
+
+    /// Height of the last block scanned
+    pub fn filter_height(&self) -> u32 {
+        self.filter_height
+    }
+
+    /// Whether headers are synced and every block up to the tip was scanned
+    pub fn is_synced(&self) -> bool {
+        self.sync.is_synced()
+            && self.filter_height >= self.chain().height()
+            && self.wanted.is_empty()
+    }
+
+    pub fn next_event(&mut self) -> Option<WalletEvent> {
+        self.events.pop_front()
+    }
+
+    /// Every event not taken yet
+    pub fn events(&mut self) -> impl Iterator<Item = WalletEvent> + '_ {
+        self.events.drain(..)
+    }
+
+    /// Takes the `headers` of `peer` like [`HeaderSync::receive_headers`],
+    /// rewinding the scan past the fork on reorgs
+    pub fn receive_headers(&mut self, peer: &SocketAddr, headers: &[BlockHeader]) -> Result<()> {
+        for event in self.sync.receive_headers(peer, headers)? {
+            if let ChainEvent::Reorg {
+                disconnected,
+                connected,
+            } = event
+            {
+                // safe, a reorg connects at least one header
+                let fork_height = self
+                    .chain()
+                    .get(&connected[0].block_hash())
+                    .unwrap()
+                    .height()
+                    - 1;
+                self.rewind(fork_height, &disconnected);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn rewind(&mut self, fork_height: u32, disconnected: &[BlockHeader]) {
+        self.wanted.retain(|height, _| *height <= fork_height);
+        // filters on the way past the fork are of the old chain
+        self.filters_stop = match self.filters_stop {
+            Some(stop) if fork_height > self.filter_height => Some(stop.min(fork_height)),
+            _ => None,
+        };
+        if fork_height >= self.filter_height {
+            return;
+        }
+
+        self.filter_height = fork_height;
+        self.events.push_back(WalletEvent::Reorg {
+            disconnected: disconnected.iter().map(BlockHeader::block_hash).collect(),
+            fork_height,
+        });
+    }
+
+    /// The `getcfilters` for the next blocks to scan, none while filters asked
+    /// for are still on their way or there are no scripts to match
+    pub fn next_filters_request(&mut self) -> Option<NetworkMessage> {
+        if self.filters_stop.is_some() || self.scripts.is_empty() {
+            return None;
+        }
+
+        let start_height = self.filter_height + 1;
+        let stop = self
+            .chain()
+            .height()
+            .min(self.filter_height + MAX_GETCFILTERS_SIZE);
+        if stop < start_height {
+            return None;
+        }
+
+        let stop_hash = *self.chain().at_height(stop)?.hash();
+        self.filters_stop = Some(stop);
+        Some(NetworkMessage::GetCFilters(GetCFiltersMessage {
+            filter_type: BASIC_FILTER_TYPE,
+            start_height,
+            stop_hash,
+        }))
+    }
+
+    /// Whether filters asked for are still on their way
+    pub fn is_waiting_for_filters(&self) -> bool {
+        self.filters_stop.is_some()
+    }
+
+    /// Matches the filter of the next block to scan, filters come in order.
+    /// Those of blocks a reorg took out of the active chain are ignored
+    pub fn receive_filter(&mut self, message: &CFilterMessage) -> Result<()> {
+        if !self.chain().is_active(&message.block_hash) {
+            return Ok(());
+        }
+
+        let stop = self
+            .filters_stop
+            .ok_or(Error::InvalidMessage("unrequested filter"))?;
+        let height = self.filter_height + 1;
+        let block_hash = self.chain().at_height(height).map(|entry| *entry.hash());
+        if message.filter_type != BASIC_FILTER_TYPE || block_hash != Some(message.block_hash) {
+            return Err(Error::InvalidMessage("unexpected filter"));
+        }
+
+        let scripts: Vec<_> = self.scripts.iter().map(Script::as_bytes).collect();
+        if message.filter.match_any(&message.block_hash, &scripts)? {
+            self.wanted.insert(height, (message.block_hash, false));
+        }
+
+        self.filter_height = height;
+        if height == stop {
+            self.filters_stop = None;
+        }
+        Ok(())
+    }
+
+    /// The `getdata` for the blocks that matched and weren't asked for yet,
+    /// with their witnesses and no more than [`MAX_BLOCKS_IN_FLIGHT`] at once
+    pub fn next_blocks_request(&mut self) -> Option<NetworkMessage> {
+        let in_flight = self
+            .wanted
+            .values()
+            .filter(|(_, requested)| *requested)
+            .count();
+        let items: Vec<_> = self
+            .wanted
+            .values_mut()
+            .filter(|(_, requested)| !*requested)
+            .take(MAX_BLOCKS_IN_FLIGHT.saturating_sub(in_flight))
+            .map(|(hash, requested)| {
+                *requested = true;
+                Inventory::WitnessBlock(*hash)
+            })
+            .collect();
+
+        match items.is_empty() {
+            true => None,
+            false => Some(NetworkMessage::GetData(items)),
+        }
+    }
+
+    /// Whether blocks that matched are still to be received
+    pub fn is_waiting_for_blocks(&self) -> bool {
+        !self.wanted.is_empty()
+    }
+
+    /// Scans a block that matched its filter for the transactions of the
+    /// wallet, blocks are scanned in the order they're received. Others are
+    /// ignored
+    pub fn receive_block(&mut self, block: &Block) -> Result<()> {
+        let block_hash = block.block_hash();
+        let height = match self
+            .wanted
+            .iter()
+            .find(|(_, (hash, _))| *hash == block_hash)
+        {
+            Some((height, _)) => *height,
+            None => return Ok(()),
+        };
+
+        block.check_merkle_root()?;
+        self.wanted.remove(&height);
+
+        for tx in block.transactions() {
+            let txid = tx.txid()?;
+            let mut relevant = tx
+                .inputs()
+                .iter()
+                .any(|input| self.outpoints.contains(input.previous_output()));
+
+            for (vout, output) in tx.outputs().iter().enumerate() {
+                if self.scripts.contains(output.script_pubkey()) {
+                    self.outpoints.insert(OutPoint::new(txid, vout as u32));
+                    relevant = true;
+                }
+            }
+
+            if relevant {
+                self.events.push_back(WalletEvent::Confirmed {
+                    tx: tx.clone(),
+                    block_hash,
+                    height,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl SpvClient {
+    /// Syncs headers with `peers`, then scans the blocks past the last one
+    /// scanned with the first of them serving compact filters. Peers failing
+    /// are shut down and taken out of `peers`, the events are left to take
+    pub async fn run(&mut self, peers: &mut Vec<super::Peer>) -> Result<()> {
+        use super::address::ServiceFlags;
+
+        self.sync.run(peers).await?;
+        loop {
+            let index = peers
+                .iter()
+                .position(|peer| {
+                    peer.features()
+                        .services
+                        .contains(ServiceFlags::COMPACT_FILTERS)
+                })
+                .ok_or(Error::SyncFailed("no peer serves compact filters"))?;
+
+            let address = peers[index].address();
+            match self.scan(&mut peers[index]).await {
+                Ok(()) => return Ok(()),
+                Err(_) => {
+                    self.sync.remove_peer(&address);
+                    self.filters_stop = None;
+                    self.wanted
+                        .values_mut()
+                        .for_each(|(_, requested)| *requested = false);
+                    // the connection is given up on either way
+                    let _ = peers.remove(index).shutdown().await;
+                }
+            }
+        }
+    }
+
+    /// Filters and blocks from `peer` until the tip is scanned, each message
+    /// waited for no longer than the stall timeout
+    async fn scan(&mut self, peer: &mut super::Peer) -> Result<()> {
+        use tokio::time;
+
+        let address = peer.address();
+        let stall_timeout = self.sync.stall_timeout;
+        loop {
+            let request = self
+                .next_filters_request()
+                .or_else(|| self.next_blocks_request());
+            match request {
+                Some(request) => peer.send(request).await?,
+                None if !self.is_waiting_for_filters() && !self.is_waiting_for_blocks() => {
+                    return Ok(())
+                }
+                None => {}
+            }
+
+            let message = match time::timeout(stall_timeout, peer.receive()).await {
+                Ok(message) => message?,
+                Err(_) => return Err(Error::SyncFailed("peer stalled")),
+            };
+
+            match message {
+                NetworkMessage::CFilter(filter) => self.receive_filter(&filter)?,
+                NetworkMessage::Block(block) => self.receive_block(&block)?,
+                NetworkMessage::Headers(headers) => self.receive_headers(&address, &headers)?,
+                NetworkMessage::NotFound(_) => {
+                    return Err(Error::SyncFailed("block not found"));
+                }
+                NetworkMessage::Ping(nonce) => peer.send(NetworkMessage::Pong(nonce)).await?,
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use anyhow::Result;
+
+    use crate::amount::Amount;
+    use crate::core::filter::BlockFilter;
+    use crate::core::input::TxIn;
+    use crate::core::mining::mine_block;
+    use crate::core::output::TxOut;
+    use crate::core::pow::PowParams;
+    use crate::network::Network;
+
+    use super::*;
+
+    fn ours() -> Script {
+        Script::p2wpkh(&[1; 20])
+    }
+
+    fn theirs() -> Script {
+        Script::p2wpkh(&[2; 20])
+    }
+
+    /// Regtest blocks after the genesis, the coinbase of the second one pays
+    /// to us and the fourth one spends it
+    fn blocks() -> Result<Vec<Block>> {
+        let mut blocks = vec![Network::Regtest.params().genesis_block()];
+        for height in 1..=5 {
+            let txs = match height {
+                4 => vec![Transaction::new(
+                    2,
+                    vec![TxIn::new(OutPoint::new(
+                        blocks[2].transactions()[0].txid()?,
+                        0,
+                    ))],
+                    vec![TxOut::new(Amount::from_sat(1_000), theirs())],
+                    0,
+                )],
+                _ => vec![],
+            };
+            let script = if height == 2 { ours() } else { theirs() };
+            let prev = *blocks[blocks.len() - 1].header();
+            blocks.push(mine_block(&prev, height, &txs, script)?);
+        }
+        Ok(blocks)
+    }
+
+    /// The `cfilter` of `block`, spending outputs of `blocks`
+    fn filter(block: &Block, blocks: &[Block]) -> Result<CFilterMessage> {
+        let filter = BlockFilter::new_basic(block, |outpoint| {
+            blocks
+                .iter()
+                .flat_map(Block::transactions)
+                .find(|tx| tx.txid().ok().as_ref() == Some(outpoint.txid()))
+                .map(|tx| {
+                    tx.outputs()[outpoint.vout() as usize]
+                        .script_pubkey()
+                        .clone()
+                })
+        })?;
+
+        Ok(CFilterMessage {
+            filter_type: BASIC_FILTER_TYPE,
+            block_hash: block.block_hash(),
+            filter,
+        })
+    }
+
+    fn client(genesis: &Block) -> SpvClient {
+        let chain = HeaderChain::new(*genesis.header(), PowParams::new(Network::Regtest));
+        let mut client = SpvClient::new(chain).with_scan_from(1);
+        client.watch_script(ours());
+        client
+    }
+
+    #[test]
+    fn scan_and_reorg() -> Result<()> {
+        let blocks = blocks()?;
+        let headers: Vec<_> = blocks[1..].iter().map(|block| *block.header()).collect();
+        let peer = "127.0.0.1:18444".parse()?;
+        let mut client = client(&blocks[0]);
+        client.sync_mut().add_peer(peer, 5);
+        // nothing to scan without headers
+        assert!(client.next_filters_request().is_none());
+        client.sync_mut().next_request(Instant::now());
+        client.receive_headers(&peer, &headers)?;
+        assert!(!client.is_synced());
+
+        assert_eq!(
+            client.next_filters_request(),
+            Some(NetworkMessage::GetCFilters(GetCFiltersMessage {
+                filter_type: BASIC_FILTER_TYPE,
+                start_height: 1,
+                stop_hash: blocks[5].block_hash(),
+            }))
+        );
+        assert!(client.next_filters_request().is_none());
+        assert!(client
+            .receive_filter(&filter(&blocks[2], &blocks)?)
+            .is_err());
+        for block in &blocks[1..] {
+            client.receive_filter(&filter(block, &blocks)?)?;
+        }
+        assert!(!client.is_waiting_for_filters());
+        assert_eq!(client.filter_height(), 5);
+
+        // the payment and its spend, in height order
+        assert_eq!(
+            client.next_blocks_request(),
+            Some(NetworkMessage::GetData(vec![
+                Inventory::WitnessBlock(blocks[2].block_hash()),
+                Inventory::WitnessBlock(blocks[4].block_hash()),
+            ]))
+        );
+        assert!(client.next_blocks_request().is_none());
+        client.receive_block(&blocks[2])?;
+        client.receive_block(&blocks[3])?;
+        client.receive_block(&blocks[4])?;
+        assert!(client.is_synced());
+
+        let events: Vec<_> = client.events().collect();
+        assert_eq!(
+            events,
+            vec![
+                WalletEvent::Confirmed {
+                    tx: blocks[2].transactions()[0].clone(),
+                    block_hash: blocks[2].block_hash(),
+                    height: 2,
+                },
+                WalletEvent::Confirmed {
+                    tx: blocks[4].transactions()[1].clone(),
+                    block_hash: blocks[4].block_hash(),
+                    height: 4,
+                },
+            ]
+        );
+
+        // a longer chain from block 3 takes the spend out
+        let mut fork = blocks[..4].to_vec();
+        for height in 4..=6 {
+            let prev = *fork[fork.len() - 1].header();
+            fork.push(mine_block(&prev, height, &[], theirs())?);
+        }
+        let headers: Vec<_> = fork[4..].iter().map(|block| *block.header()).collect();
+        client.receive_headers(&peer, &headers)?;
+        assert_eq!(
+            client.next_event(),
+            Some(WalletEvent::Reorg {
+                disconnected: vec![blocks[5].block_hash(), blocks[4].block_hash()],
+                fork_height: 3,
+            })
+        );
+        assert_eq!(client.filter_height(), 3);
+
+        // filters of the old chain are ignored, the new one is scanned from the fork
+        match client.next_filters_request() {
+            Some(NetworkMessage::GetCFilters(message)) => assert_eq!(message.start_height, 4),
+            request => panic!("unexpected {:?}", request),
+        }
+        client.receive_filter(&filter(&blocks[5], &blocks)?)?;
+        for block in &fork[4..] {
+            client.receive_filter(&filter(block, &fork)?)?;
+        }
+        assert_eq!(client.next_blocks_request(), None);
+        assert_eq!(client.next_event(), None);
+        Ok(())
+    }
+
+    /// Accepts a peer serving compact filters and answers it from `blocks`
+    /// until it disconnects
+    #[cfg(feature = "tokio")]
+    async fn serve(listener: tokio::net::TcpListener, blocks: Vec<Block>) -> crate::Result<()> {
+        use crate::net::address::ServiceFlags;
+        use crate::net::handshake::Handshake;
+        use crate::net::message::{VersionMessage, MAX_HEADERS};
+        use crate::net::peer::{Peer, HANDSHAKE_TIMEOUT};
+
+        let mut chain = HeaderChain::new(*blocks[0].header(), PowParams::new(Network::Regtest));
+        for block in &blocks[1..] {
+            chain.accept(*block.header())?;
+        }
+
+        let (stream, address) = listener.accept().await?;
+        let version = VersionMessage::new(address, chain.height() as i32)
+            .with_services(ServiceFlags::NETWORK | ServiceFlags::COMPACT_FILTERS);
+        let magic = Network::Regtest.params().magic;
+        let mut peer =
+            Peer::from_stream(stream, magic, Handshake::new(version), HANDSHAKE_TIMEOUT).await?;
+        loop {
+            match peer.receive().await? {
+                NetworkMessage::GetHeaders(message) => {
+                    let headers =
+                        chain.headers_after(&message.locator, &message.stop_hash, MAX_HEADERS);
+                    peer.send(NetworkMessage::Headers(headers)).await?;
+                }
+                NetworkMessage::GetCFilters(message) => {
+                    let stop = chain
+                        .get(&message.stop_hash)
+                        .map_or(0, |entry| entry.height());
+                    for block in &blocks[message.start_height as usize..=stop as usize] {
+                        let filter = filter(block, &blocks).unwrap();
+                        peer.send(NetworkMessage::CFilter(filter)).await?;
+                    }
+                }
+                NetworkMessage::GetData(items) => {
+                    for item in items {
+                        let block = blocks
+                            .iter()
+                            .find(|block| item.block_hash() == Some(block.block_hash()));
+                        if let Some(block) = block {
+                            peer.send(NetworkMessage::Block(block.clone())).await?;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn run_with_peer() -> Result<()> {
+        use tokio::net::TcpListener;
+        use tokio::runtime::Builder;
+
+        use crate::net::handshake::Handshake;
+        use crate::net::message::VersionMessage;
+        use crate::net::peer::Peer;
+
+        let blocks = blocks()?;
+        let mut client = client(&blocks[0]);
+        Builder::new_current_thread()
+            .enable_all()
+            .build()?
+            .block_on(async move {
+                let listener = TcpListener::bind("127.0.0.1:0").await?;
+                let address = listener.local_addr()?;
+                let node = tokio::spawn(serve(listener, blocks.clone()));
+
+                let magic = Network::Regtest.params().magic;
+                let handshake = Handshake::new(VersionMessage::new(address, 0));
+                let mut peers = vec![Peer::connect(address, magic, handshake).await?];
+                client.run(&mut peers).await?;
+                assert!(client.is_synced());
+                assert_eq!(client.chain().height(), 5);
+
+                let heights: Vec<_> = client
+                    .events()
+                    .map(|event| match event {
+                        WalletEvent::Confirmed { height, .. } => height,
+                        event => panic!("unexpected {:?}", event),
+                    })
+                    .collect();
+                assert_eq!(heights, vec![2, 4]);
+
+                peers.pop().unwrap().shutdown().await?;
+                assert!(node.await?.is_err());
+                Ok(())
+            })
+    }
+}
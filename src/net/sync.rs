@@ -0,0 +1,524 @@
+//! Headers-first sync: `getheaders` with a locator of our active chain to one
+//! peer at a time, the `headers` answered with accepted into the
+//! [`HeaderChain`], until no peer has more. Peers too slow to answer are
+//! given up on for the next one
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::core::block::BlockHeader;
+use crate::core::chain::{ChainEvent, HeaderChain};
+use crate::core::txid::BlockHash;
+use crate::{Error, Result};
+
+use super::message::{GetHeadersMessage, NetworkMessage, MAX_HEADERS};
+use super::PROTOCOL_VERSION;
+
+/// Time a peer has to answer `getheaders` in before it's taken as stalling
+pub const STALL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What's known of a peer taking part in the sync
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncPeer {
+    /// Highest height the peer is known to have, the one of its `version` at
+    /// first
+    pub(crate) best_height: u32,
+    /// Whether its last answer had every header it has past our locator
+    pub(crate) synced: bool,
+}
+
+impl SyncPeer {
+    pub fn best_height(&self) -> u32 {
+        self.best_height
+    }
+
+    pub fn is_synced(&self) -> bool {
+        self.synced
+    }
+}
+
+/// The `getheaders` waiting for an answer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeadersRequest {
+    pub peer: SocketAddr,
+    pub sent: Instant,
+}
+
+/// Drives a [`HeaderChain`] to the tip of the best of its peers without doing
+/// any IO itself, requests are handed out and answers taken in
+#[derive(Debug, Clone)]
+pub struct HeaderSync {
+    pub(crate) chain: HeaderChain,
+    pub(crate) peers: HashMap<SocketAddr, SyncPeer>,
+    pub(crate) in_flight: Option<HeadersRequest>,
+    pub(crate) stall_timeout: Duration,
+}
+
+impl HeaderSync {
+    pub fn new(chain: HeaderChain) -> Self {
+        Self {
+            chain,
+            peers: HashMap::new(),
+            in_flight: None,
+            stall_timeout: STALL_TIMEOUT,
+        }
+    }
+
+    /// Time peers have to answer in, [`STALL_TIMEOUT`] unless set
+    pub fn with_stall_timeout(mut self, stall_timeout: Duration) -> Self {
+        self.stall_timeout = stall_timeout;
+        self
+    }
+
+    pub fn chain(&self) -> &HeaderChain {
+        &self.chain
+    }
+
+    pub fn into_chain(self) -> HeaderChain {
+        self.chain
+    }
+
+    pub fn peer(&self, peer: &SocketAddr) -> Option<&SyncPeer> {
+        self.peers.get(peer)
+    }
+
+    pub fn in_flight(&self) -> Option<&HeadersRequest> {
+        self.in_flight.as_ref()
+    }
+
+    /// A peer past the handshake, with the start height of its `version`
+    pub fn add_peer(&mut self, peer: SocketAddr, start_height: i32) {
+        self.peers.insert(
+            peer,
+            SyncPeer {
+                best_height: start_height.max(0) as u32,
+                synced: false,
+            },
+        );
+    }
+
+    /// The peer disconnected, what was asked to it is asked to another one
+    pub fn remove_peer(&mut self, peer: &SocketAddr) -> Option<SyncPeer> {
+        if self.in_flight.is_some_and(|request| request.peer == *peer) {
+            self.in_flight = None;
+        }
+
+        self.peers.remove(peer)
+    }
+
+    /// Whether no peer has headers we're missing, as far as we know
+    pub fn is_synced(&self) -> bool {
+        self.peers.values().all(|peer| peer.synced)
+    }
+
+    /// The `getheaders` to send and to who, the peer with the highest height
+    /// not synced with. None while a request is waiting for its answer
+    pub fn next_request(&mut self, now: Instant) -> Option<(SocketAddr, NetworkMessage)> {
+        if self.in_flight.is_some() {
+            return None;
+        }
+
+        let (peer, _) = self
+            .peers
+            .iter()
+            .filter(|(_, peer)| !peer.synced)
+            .max_by_key(|(address, peer)| (peer.best_height, std::cmp::Reverse(**address)))?;
+
+        let peer = *peer;
+        self.in_flight = Some(HeadersRequest { peer, sent: now });
+        let message = NetworkMessage::GetHeaders(GetHeadersMessage {
+            version: PROTOCOL_VERSION,
+            locator: self.chain.locator(),
+            stop_hash: BlockHash::default(),
+        });
+
+        Some((peer, message))
+    }
+
+    /// The peer taking longer than the stall timeout to answer, which is
+    /// removed so the request goes to another one. It should be disconnected
+    pub fn check_stall(&mut self, now: Instant) -> Option<SocketAddr> {
+        let request = self.in_flight?;
+        if now.saturating_duration_since(request.sent) < self.stall_timeout {
+            return None;
+        }
+
+        self.remove_peer(&request.peer);
+        Some(request.peer)
+    }
+
+    /// Takes the `headers` of `peer`, answering our request or announcing new
+    /// blocks (BIP130), returning what each header did to the chain. A peer
+    /// sending headers that don't connect or aren't valid is removed and
+    /// should be disconnected
+    pub fn receive_headers(
+        &mut self,
+        peer: &SocketAddr,
+        headers: &[BlockHeader],
+    ) -> Result<Vec<ChainEvent>> {
+        if !self.peers.contains_key(peer) {
+            return Err(Error::InvalidMessage("headers from an unknown peer"));
+        }
+
+        let requested = self.in_flight.is_some_and(|request| request.peer == *peer);
+        if requested {
+            self.in_flight = None;
+        }
+
+        let first = match headers.first() {
+            Some(first) => first,
+            None => {
+                // empty answers mean there's nothing past our locator
+                self.peers
+                    .entry(*peer)
+                    .and_modify(|state| state.synced = true);
+                return Ok(Vec::new());
+            }
+        };
+
+        if !self.chain.contains(first.prev_blockhash()) {
+            if requested {
+                self.remove_peer(peer);
+                return Err(Error::InvalidMessage("headers don't connect"));
+            }
+
+            // an announcement of blocks we're missing the parents of
+            self.peers
+                .entry(*peer)
+                .and_modify(|state| state.synced = false);
+            return Ok(Vec::new());
+        }
+
+        let connected = headers
+            .windows(2)
+            .all(|pair| *pair[1].prev_blockhash() == pair[0].block_hash());
+        if !connected {
+            self.remove_peer(peer);
+            return Err(Error::InvalidMessage("headers aren't a chain"));
+        }
+
+        let mut events = Vec::with_capacity(headers.len());
+        for header in headers {
+            match self.chain.accept(*header) {
+                Ok(event) => events.push(event),
+                Err(error) => {
+                    self.remove_peer(peer);
+                    return Err(error);
+                }
+            }
+        }
+
+        // safe, the headers were accepted
+        let height = self
+            .chain
+            .get(&headers[headers.len() - 1].block_hash())
+            .unwrap()
+            .height();
+        if let Some(state) = self.peers.get_mut(peer) {
+            state.best_height = state.best_height.max(height);
+            // full answers are followed by more
+            if requested {
+                state.synced = headers.len() < MAX_HEADERS;
+            }
+        }
+
+        Ok(events)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl HeaderSync {
+    /// Syncs with `peers` until none has more headers, answering their pings
+    /// along the way and dropping anything else they send. Those stalling or
+    /// misbehaving are shut down and taken out of `peers`
+    pub async fn run(&mut self, peers: &mut Vec<super::Peer>) -> Result<()> {
+        use tokio::time;
+
+        for peer in peers.iter() {
+            if !self.peers.contains_key(&peer.address()) {
+                self.add_peer(peer.address(), peer.features().start_height);
+            }
+        }
+
+        loop {
+            let (address, request) = match self.next_request(Instant::now()) {
+                Some(request) => request,
+                None if self.in_flight.is_none() => return Ok(()),
+                None => return Err(Error::SyncFailed("request already in flight")),
+            };
+
+            let index = match peers.iter().position(|peer| peer.address() == address) {
+                Some(index) => index,
+                None => {
+                    self.remove_peer(&address);
+                    continue;
+                }
+            };
+
+            let deadline = time::Instant::now() + self.stall_timeout;
+            let result = async {
+                let peer = &mut peers[index];
+                peer.send(request).await?;
+                loop {
+                    match peer.receive().await? {
+                        NetworkMessage::Headers(headers) => {
+                            return self.receive_headers(&address, &headers).map(|_| ())
+                        }
+                        NetworkMessage::Ping(nonce) => {
+                            peer.send(NetworkMessage::Pong(nonce)).await?
+                        }
+                        _ => {}
+                    }
+                }
+            };
+
+            // stalling past the deadline
+            let failed = match time::timeout_at(deadline, result).await {
+                Ok(result) => result.is_err(),
+                Err(_) => true,
+            };
+
+            if failed {
+                self.remove_peer(&address);
+                // the connection is given up on either way
+                let _ = peers.remove(index).shutdown().await;
+                if peers.is_empty() && !self.is_synced() {
+                    return Err(Error::SyncFailed("no peers left"));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::core::pow::PowParams;
+    use crate::network::Network;
+
+    use super::*;
+
+    fn genesis() -> BlockHeader {
+        *Network::Regtest.params().genesis_block().header()
+    }
+
+    fn chain() -> HeaderChain {
+        HeaderChain::new(genesis(), PowParams::new(Network::Regtest))
+    }
+
+    /// Regtest headers on top of `prev`, `salt` tells forks apart
+    fn mine_chain(prev: &BlockHeader, len: usize, salt: u8) -> Vec<BlockHeader> {
+        let mut headers: Vec<BlockHeader> = Vec::new();
+        for _ in 0..len {
+            let prev = headers.last().unwrap_or(prev);
+            let mut header = BlockHeader::new(
+                4,
+                prev.block_hash(),
+                [salt; 32],
+                prev.time() + 600,
+                0x207f_ffff,
+                0,
+            );
+            while header.validate_pow().is_err() {
+                header.nonce += 1;
+            }
+            headers.push(header);
+        }
+        headers
+    }
+
+    /// How a node with `chain` answers `request`
+    fn answer(chain: &HeaderChain, request: &NetworkMessage) -> Vec<BlockHeader> {
+        match request {
+            NetworkMessage::GetHeaders(message) => {
+                chain.headers_after(&message.locator, &message.stop_hash, MAX_HEADERS)
+            }
+            _ => panic!("unexpected {:?}", request),
+        }
+    }
+
+    #[test]
+    fn sync_in_batches() -> Result<()> {
+        let mut node = chain();
+        let headers = mine_chain(&genesis(), MAX_HEADERS + 10, 0);
+        for header in &headers {
+            node.accept(*header)?;
+        }
+
+        let (peer, behind): (SocketAddr, SocketAddr) =
+            ("127.0.0.1:18444".parse()?, "127.0.0.2:18444".parse()?);
+        let mut sync = HeaderSync::new(chain());
+        sync.add_peer(peer, node.height() as i32);
+        sync.add_peer(behind, 0);
+        let now = Instant::now();
+
+        // the highest peer first, one request at a time
+        let (to, request) = sync.next_request(now).unwrap();
+        assert_eq!(to, peer);
+        assert!(sync.next_request(now).is_none());
+        let events = sync.receive_headers(&peer, &answer(&node, &request))?;
+        assert_eq!(events.len(), MAX_HEADERS);
+        assert!(!sync.peer(&peer).unwrap().is_synced());
+
+        let (to, request) = sync.next_request(now).unwrap();
+        assert_eq!(to, peer);
+        assert_eq!(
+            sync.receive_headers(&peer, &answer(&node, &request))?.len(),
+            10
+        );
+        assert_eq!(sync.chain().tip().header(), node.tip().header());
+        assert!(sync.peer(&peer).unwrap().is_synced());
+
+        // the other peer has nothing else
+        let (to, request) = sync.next_request(now).unwrap();
+        assert_eq!(to, behind);
+        assert_eq!(
+            sync.receive_headers(&behind, &answer(&chain(), &request))?,
+            vec![]
+        );
+        assert!(sync.is_synced());
+        assert!(sync.next_request(now).is_none());
+
+        // new blocks announced with headers
+        let new = mine_chain(&headers[headers.len() - 1], 1, 0);
+        assert_eq!(
+            sync.receive_headers(&behind, &new)?,
+            vec![ChainEvent::Extended]
+        );
+        assert_eq!(
+            sync.peer(&behind).unwrap().best_height(),
+            MAX_HEADERS as u32 + 11
+        );
+
+        // those not connecting get the peer asked for what's in between
+        let gap = mine_chain(&new[0], 2, 0);
+        assert_eq!(sync.receive_headers(&behind, &gap[1..])?, vec![]);
+        assert!(!sync.is_synced());
+        assert_eq!(sync.next_request(now).map(|(to, _)| to), Some(behind));
+        Ok(())
+    }
+
+    #[test]
+    fn stalls_and_misbehavior() -> Result<()> {
+        let (slow, honest, liar): (SocketAddr, SocketAddr, SocketAddr) = (
+            "127.0.0.1:18444".parse()?,
+            "127.0.0.2:18444".parse()?,
+            "127.0.0.3:18444".parse()?,
+        );
+        let mut sync = HeaderSync::new(chain()).with_stall_timeout(Duration::from_secs(5));
+        sync.add_peer(slow, 300);
+        sync.add_peer(honest, 200);
+        sync.add_peer(liar, 100);
+
+        // the slow peer is switched away from once it stalls
+        let start = Instant::now();
+        assert_eq!(sync.next_request(start).map(|(to, _)| to), Some(slow));
+        assert_eq!(sync.check_stall(start + Duration::from_secs(4)), None);
+        assert_eq!(sync.check_stall(start + Duration::from_secs(5)), Some(slow));
+        assert!(sync.peer(&slow).is_none());
+        assert!(sync.receive_headers(&slow, &[]).is_err());
+
+        let headers = mine_chain(&genesis(), 5, 0);
+        assert_eq!(sync.next_request(start).map(|(to, _)| to), Some(honest));
+        sync.receive_headers(&honest, &headers)?;
+        assert_eq!(sync.chain().height(), 5);
+
+        // answers not connecting or not valid get the peer dropped
+        assert_eq!(sync.next_request(start).map(|(to, _)| to), Some(liar));
+        assert!(sync.receive_headers(&liar, &headers[2..]).is_ok());
+        let orphans = mine_chain(&headers[4], 2, 1);
+        assert!(sync.receive_headers(&liar, &orphans[1..]).is_ok());
+        assert_eq!(sync.next_request(start).map(|(to, _)| to), Some(liar));
+        assert!(matches!(
+            sync.receive_headers(&liar, &orphans[1..]),
+            Err(Error::InvalidMessage("headers don't connect"))
+        ));
+        assert!(sync.peer(&liar).is_none());
+
+        sync.add_peer(liar, 100);
+        let mut invalid = mine_chain(&headers[4], 1, 1)[0];
+        invalid.bits = 0x207f_fffe;
+        assert_eq!(sync.next_request(start).map(|(to, _)| to), Some(liar));
+        assert!(sync.receive_headers(&liar, &[invalid]).is_err());
+        assert!(sync.peer(&liar).is_none());
+        assert_eq!(sync.chain().height(), 5);
+        Ok(())
+    }
+
+    /// Accepts a peer and answers its `getheaders` from `node` if any, until it
+    /// disconnects
+    #[cfg(feature = "tokio")]
+    async fn serve(
+        listener: tokio::net::TcpListener,
+        magic: [u8; 4],
+        start_height: i32,
+        node: Option<HeaderChain>,
+    ) -> crate::Result<()> {
+        use crate::net::handshake::Handshake;
+        use crate::net::message::VersionMessage;
+        use crate::net::peer::{Peer, HANDSHAKE_TIMEOUT};
+
+        let (stream, address) = listener.accept().await?;
+        let handshake = Handshake::new(VersionMessage::new(address, start_height));
+        let mut peer = Peer::from_stream(stream, magic, handshake, HANDSHAKE_TIMEOUT).await?;
+        loop {
+            if let (NetworkMessage::GetHeaders(message), Some(node)) =
+                (peer.receive().await?, &node)
+            {
+                let headers = node.headers_after(&message.locator, &message.stop_hash, MAX_HEADERS);
+                peer.send(NetworkMessage::Headers(headers)).await?;
+            }
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn sync_with_peers() -> Result<()> {
+        use tokio::net::TcpListener;
+        use tokio::runtime::Builder;
+
+        use crate::net::handshake::Handshake;
+        use crate::net::message::VersionMessage;
+        use crate::net::peer::Peer;
+
+        let mut node = chain();
+        for header in mine_chain(&genesis(), 50, 0) {
+            node.accept(header)?;
+        }
+        let magic = Network::Regtest.params().magic;
+
+        Builder::new_current_thread()
+            .enable_all()
+            .build()?
+            .block_on(async move {
+                // a peer claiming more blocks that never answers, then one serving them
+                let (silent, serving) = (
+                    TcpListener::bind("127.0.0.1:0").await?,
+                    TcpListener::bind("127.0.0.1:0").await?,
+                );
+                let addresses = [silent.local_addr()?, serving.local_addr()?];
+                let silent = tokio::spawn(serve(silent, magic, 1_000, None));
+                let serving = tokio::spawn(serve(serving, magic, 50, Some(node)));
+
+                let mut peers = Vec::new();
+                for address in addresses {
+                    let handshake = Handshake::new(VersionMessage::new(address, 0));
+                    peers.push(Peer::connect(address, magic, handshake).await?);
+                }
+
+                let mut sync =
+                    HeaderSync::new(chain()).with_stall_timeout(Duration::from_millis(200));
+                sync.run(&mut peers).await?;
+                assert_eq!(sync.chain().height(), 50);
+                assert!(sync.is_synced());
+                assert_eq!(peers.len(), 1);
+                assert!(silent.await?.is_err());
+
+                peers.pop().unwrap().shutdown().await?;
+                assert!(serving.await?.is_err());
+                Ok(())
+            })
+    }
+}
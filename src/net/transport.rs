@@ -0,0 +1,643 @@
+//! The v2 transport (BIP324): both sides agree on keys with an ElligatorSwift
+//! ECDH, then every message goes in an encrypted and authenticated packet
+//! whose length is encrypted as well, so that the traffic looks random.
+//! Frequent commands are sent as a single byte instead of their name
+
+use std::convert::TryInto;
+
+use hmac::{Hmac, Mac, NewMac};
+use rand::Rng;
+use sha2::Sha256;
+
+use crate::chacha20poly1305::{self, chacha20_block, KEY_SIZE, TAG_SIZE};
+use crate::secp256k1::crypto::PrivateKey;
+use crate::secp256k1::ellswift;
+use crate::utils::Chain;
+use crate::{Error, Result};
+
+use super::message::{self, NetworkMessage, COMMAND_SIZE, MAX_PAYLOAD_SIZE};
+
+/// Packets encrypted with the same keys before they're replaced
+pub const REKEY_INTERVAL: u64 = 224;
+/// Most garbage sent after the public key
+pub const MAX_GARBAGE_LEN: usize = 4095;
+pub const GARBAGE_TERMINATOR_LEN: usize = 16;
+pub const ELLSWIFT_LEN: usize = 64;
+/// Bytes of the encrypted length before every packet
+pub const LENGTH_LEN: usize = 3;
+/// Bytes of the header before the contents of every packet
+pub const HEADER_LEN: usize = 1;
+/// Header bit of the packets to drop on arrival, decoys to hide the traffic
+pub const IGNORE_BIT: u8 = 0x80;
+/// Longest contents of a packet, a message with its full command
+pub const MAX_CONTENTS_LEN: usize = 1 + COMMAND_SIZE + MAX_PAYLOAD_SIZE;
+
+/// Commands sent as a single byte, the one of each being its position plus
+/// one. Zero is followed by the full command
+const SHORT_IDS: [&str; 28] = [
+    "addr",
+    "block",
+    "blocktxn",
+    "cmpctblock",
+    "feefilter",
+    "filteradd",
+    "filterclear",
+    "filterload",
+    "getblocks",
+    "getblocktxn",
+    "getdata",
+    "getheaders",
+    "headers",
+    "inv",
+    "mempool",
+    "merkleblock",
+    "notfound",
+    "ping",
+    "pong",
+    "sendcmpct",
+    "tx",
+    "getcfilters",
+    "cfilter",
+    "getcfheaders",
+    "cfheaders",
+    "getcfcheckpt",
+    "cfcheckpt",
+    "addrv2",
+];
+
+/// What a v1 peer sends first, the header of its `version`. A responder seeing
+/// it falls back to v1
+pub fn v1_prefix(magic: [u8; 4]) -> [u8; 16] {
+    let mut prefix = [0; 16];
+    prefix[..4].copy_from_slice(&magic);
+    prefix[4..11].copy_from_slice(b"version");
+    prefix
+}
+
+/// The contents of a packet carrying `message`
+pub fn serialize_message(message: &NetworkMessage) -> Result<Vec<u8>> {
+    let command = message.command();
+    let mut contents = match SHORT_IDS.iter().position(|short| *short == command) {
+        Some(index) => vec![index as u8 + 1],
+        None => {
+            if command.len() > COMMAND_SIZE {
+                return Err(Error::InvalidMessage("command too long"));
+            }
+            let mut contents = vec![0; 1 + COMMAND_SIZE];
+            contents[1..1 + command.len()].copy_from_slice(command.as_bytes());
+            contents
+        }
+    };
+
+    contents.extend(message.serialize()?);
+    Ok(contents)
+}
+
+/// The message carried in `contents`. Short ids not assigned yet give unknown
+/// messages without a command, to be ignored as Bitcoin Core does
+pub fn deserialize_message(contents: &[u8]) -> Result<NetworkMessage> {
+    match contents.split_first() {
+        None => Err(Error::InvalidMessage("empty packet")),
+        Some((0, rest)) if rest.len() < COMMAND_SIZE => {
+            Err(Error::InvalidMessage("truncated command"))
+        }
+        Some((0, rest)) => {
+            let (command, payload) = rest.split_at(COMMAND_SIZE);
+            // safe, split at its size
+            let command = message::parse_command(command.try_into().unwrap())?;
+            NetworkMessage::deserialize(&command, payload)
+        }
+        Some((id, payload)) => match SHORT_IDS.get(*id as usize - 1) {
+            Some(command) => NetworkMessage::deserialize(command, payload),
+            None => Ok(NetworkMessage::Unknown {
+                command: String::new(),
+                payload: payload.to_vec(),
+            }),
+        },
+    }
+}
+
+/// HKDF-SHA256 (RFC 5869) of a single output block
+fn hkdf_sha256(salt: &[u8], ikm: &[u8], info: &[u8]) -> [u8; 32] {
+    // safe, HMAC takes keys of any length
+    let prk = Hmac::<Sha256>::new_varkey(salt)
+        .unwrap()
+        .chain(ikm)
+        .finalize()
+        .into_bytes();
+    Hmac::<Sha256>::new_varkey(&prk)
+        .unwrap()
+        .chain(info)
+        .chain(&[1])
+        .finalize()
+        .into_bytes()
+        .into()
+}
+
+/// ChaCha20 whose keystream goes on across chunks, replacing its key every
+/// [`REKEY_INTERVAL`] chunks. Encrypts the lengths of the packets
+#[derive(Debug, Clone)]
+pub(crate) struct FsChaCha20 {
+    key: [u8; KEY_SIZE],
+    chunks: u64,
+    block: u32,
+    keystream: Vec<u8>,
+}
+
+impl FsChaCha20 {
+    pub(crate) fn new(key: [u8; KEY_SIZE]) -> Self {
+        Self {
+            key,
+            chunks: 0,
+            block: 0,
+            keystream: Vec::new(),
+        }
+    }
+
+    fn keystream(&mut self, len: usize) -> Vec<u8> {
+        let mut nonce = [0; 12];
+        nonce[4..].copy_from_slice(&(self.chunks / REKEY_INTERVAL).to_le_bytes());
+        while self.keystream.len() < len {
+            self.keystream
+                .extend_from_slice(&chacha20_block(&self.key, &nonce, self.block));
+            self.block += 1;
+        }
+        self.keystream.drain(..len).collect()
+    }
+
+    /// Encrypts or decrypts the next chunk in place
+    pub(crate) fn crypt(&mut self, chunk: &mut [u8]) {
+        let keystream = self.keystream(chunk.len());
+        chunk
+            .iter_mut()
+            .zip(keystream)
+            .for_each(|(byte, key)| *byte ^= key);
+
+        if (self.chunks + 1).is_multiple_of(REKEY_INTERVAL) {
+            // safe, asked for the size of a key
+            self.key = self.keystream(KEY_SIZE).try_into().unwrap();
+            self.block = 0;
+            self.keystream.clear();
+        }
+        self.chunks += 1;
+    }
+}
+
+/// ChaCha20Poly1305 with the number of the packet as its nonce, replacing its
+/// key every [`REKEY_INTERVAL`] packets. Encrypts the packets themselves
+#[derive(Debug, Clone)]
+pub(crate) struct FsChaCha20Poly1305 {
+    key: [u8; KEY_SIZE],
+    packets: u64,
+}
+
+impl FsChaCha20Poly1305 {
+    pub(crate) fn new(key: [u8; KEY_SIZE]) -> Self {
+        Self { key, packets: 0 }
+    }
+
+    fn nonce(&self) -> [u8; 12] {
+        let mut nonce = [0; 12];
+        nonce[..4].copy_from_slice(&((self.packets % REKEY_INTERVAL) as u32).to_le_bytes());
+        nonce[4..].copy_from_slice(&(self.packets / REKEY_INTERVAL).to_le_bytes());
+        nonce
+    }
+
+    fn next(&mut self) {
+        if (self.packets + 1).is_multiple_of(REKEY_INTERVAL) {
+            let mut nonce = self.nonce();
+            nonce[..4].copy_from_slice(&[0xff; 4]);
+            let key = chacha20poly1305::encrypt(&self.key, &nonce, b"", &[0; KEY_SIZE]);
+            self.key.copy_from_slice(&key[..KEY_SIZE]);
+        }
+        self.packets += 1;
+    }
+
+    pub(crate) fn encrypt(&mut self, aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let ciphertext = chacha20poly1305::encrypt(&self.key, &self.nonce(), aad, plaintext);
+        self.next();
+        ciphertext
+    }
+
+    /// Fails without moving on to the next packet, the connection is useless
+    /// after that anyway
+    pub(crate) fn decrypt(&mut self, aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>> {
+        let plaintext = chacha20poly1305::decrypt(&self.key, &self.nonce(), aad, ciphertext)?;
+        self.next();
+        Ok(plaintext)
+    }
+}
+
+/// The sending half of the ciphers of a connection
+#[derive(Debug, Clone)]
+pub struct SendCipher {
+    pub(crate) length: FsChaCha20,
+    pub(crate) packet: FsChaCha20Poly1305,
+}
+
+impl SendCipher {
+    /// The packet of `contents`, `aad` being authenticated along with it
+    pub fn encrypt(&mut self, contents: &[u8], aad: &[u8], ignore: bool) -> Vec<u8> {
+        let mut length = (contents.len() as u32).to_le_bytes();
+        self.length.crypt(&mut length[..LENGTH_LEN]);
+
+        let mut plaintext = Vec::with_capacity(HEADER_LEN + contents.len());
+        plaintext.push(if ignore { IGNORE_BIT } else { 0 });
+        plaintext.extend_from_slice(contents);
+
+        let mut packet = length[..LENGTH_LEN].to_vec();
+        packet.extend(self.packet.encrypt(aad, &plaintext));
+        packet
+    }
+
+    /// The packet carrying `message`
+    pub fn encrypt_message(&mut self, message: &NetworkMessage) -> Result<Vec<u8>> {
+        Ok(self.encrypt(&serialize_message(message)?, b"", false))
+    }
+}
+
+/// A packet once decrypted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    /// Decoys are to be dropped
+    pub ignore: bool,
+    pub contents: Vec<u8>,
+}
+
+/// The receiving half of the ciphers of a connection
+#[derive(Debug, Clone)]
+pub struct ReceiveCipher {
+    pub(crate) length: FsChaCha20,
+    pub(crate) packet: FsChaCha20Poly1305,
+    /// Length of the packet being received, decrypted as soon as it arrives
+    pub(crate) pending: Option<usize>,
+}
+
+impl ReceiveCipher {
+    /// The packet at the start of `buf` and the bytes it took, none until all
+    /// of it arrived. Fails if it was tampered with or `aad` doesn't match
+    pub fn parse(&mut self, buf: &[u8], aad: &[u8]) -> Result<Option<(Packet, usize)>> {
+        if buf.len() < LENGTH_LEN {
+            return Ok(None);
+        }
+
+        let contents_len = match self.pending {
+            Some(len) => len,
+            None => {
+                let mut length = [0; 4];
+                length[..LENGTH_LEN].copy_from_slice(&buf[..LENGTH_LEN]);
+                self.length.crypt(&mut length[..LENGTH_LEN]);
+                let len = u32::from_le_bytes(length) as usize;
+                if len > MAX_CONTENTS_LEN {
+                    return Err(Error::TransportFailed("packet too large"));
+                }
+                self.pending = Some(len);
+                len
+            }
+        };
+
+        let len = LENGTH_LEN + HEADER_LEN + contents_len + TAG_SIZE;
+        if buf.len() < len {
+            return Ok(None);
+        }
+
+        self.pending = None;
+        let mut plaintext = self.packet.decrypt(aad, &buf[LENGTH_LEN..len])?;
+        let packet = Packet {
+            ignore: plaintext[0] & IGNORE_BIT != 0,
+            contents: plaintext.split_off(HEADER_LEN),
+        };
+        Ok(Some((packet, len)))
+    }
+}
+
+/// The keys of both directions of a connection and what's derived along with
+/// them
+#[derive(Debug, Clone)]
+pub struct Ciphers {
+    pub send: SendCipher,
+    pub receive: ReceiveCipher,
+    /// Same on both sides unless someone is in the middle, for users to
+    /// compare out of band
+    pub session_id: [u8; 32],
+    pub send_terminator: [u8; GARBAGE_TERMINATOR_LEN],
+    pub receive_terminator: [u8; GARBAGE_TERMINATOR_LEN],
+}
+
+impl Ciphers {
+    /// From the shared secret of [`ellswift::xdh`], on the network of `magic`
+    pub fn new(secret: &[u8; 32], magic: [u8; 4], initiating: bool) -> Self {
+        let salt = [&b"bitcoin_v2_shared_secret"[..], &magic[..]].concat();
+        let derive = |info: &str| hkdf_sha256(&salt, secret, info.as_bytes());
+
+        let initiator = (derive("initiator_L"), derive("initiator_P"));
+        let responder = (derive("responder_L"), derive("responder_P"));
+        let terminators = derive("garbage_terminators");
+        let (ours, theirs) = match initiating {
+            true => (initiator, responder),
+            false => (responder, initiator),
+        };
+        let (first, second) = terminators.split_at(GARBAGE_TERMINATOR_LEN);
+        let (send_terminator, receive_terminator) = match initiating {
+            true => (first, second),
+            false => (second, first),
+        };
+
+        Self {
+            send: SendCipher {
+                length: FsChaCha20::new(ours.0),
+                packet: FsChaCha20Poly1305::new(ours.1),
+            },
+            receive: ReceiveCipher {
+                length: FsChaCha20::new(theirs.0),
+                packet: FsChaCha20Poly1305::new(theirs.1),
+                pending: None,
+            },
+            session_id: derive("session_id"),
+            // safe, split in halves of their size
+            send_terminator: send_terminator.try_into().unwrap(),
+            receive_terminator: receive_terminator.try_into().unwrap(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum State {
+    /// Waiting for the public key of the peer
+    Key,
+    /// Looking for the end of the garbage of the peer
+    Garbage(Box<Ciphers>),
+    /// Waiting for the version packet, the first one authenticating the
+    /// garbage received
+    Version(Box<Ciphers>, Vec<u8>),
+    Done(Box<Ciphers>),
+}
+
+/// One side of the key exchange, without doing any IO itself: what's received
+/// is taken in and what to send handed out until both sides have their
+/// ciphers. Responders are expected to have ruled out v1 peers with
+/// [`v1_prefix`] before starting
+#[derive(Debug, Clone)]
+pub struct V2Handshake {
+    pub(crate) magic: [u8; 4],
+    pub(crate) initiating: bool,
+    pub(crate) key: PrivateKey,
+    pub(crate) ellswift: [u8; ELLSWIFT_LEN],
+    pub(crate) garbage: Vec<u8>,
+    state: State,
+}
+
+impl V2Handshake {
+    /// With a random key and garbage
+    pub fn new(magic: [u8; 4], initiating: bool) -> Self {
+        let mut rng = rand::thread_rng();
+        let garbage_len = rng.gen_range(0, MAX_GARBAGE_LEN + 1);
+        let garbage = (0..garbage_len).map(|_| rng.gen()).collect();
+        Self::with_key(
+            magic,
+            initiating,
+            PrivateKey::from_bytes_be(rng.gen::<[u8; 32]>()),
+        )
+        .with_garbage(garbage)
+    }
+
+    pub fn with_key(magic: [u8; 4], initiating: bool, key: PrivateKey) -> Self {
+        let ellswift = ellswift::create(&key, &mut rand::thread_rng());
+        Self {
+            magic,
+            initiating,
+            key,
+            ellswift,
+            garbage: Vec::new(),
+            state: State::Key,
+        }
+    }
+
+    /// Sent after the public key, up to [`MAX_GARBAGE_LEN`] bytes
+    pub fn with_garbage(mut self, mut garbage: Vec<u8>) -> Self {
+        garbage.truncate(MAX_GARBAGE_LEN);
+        self.garbage = garbage;
+        self
+    }
+
+    /// What's sent first, without waiting for the peer: the public key and
+    /// the garbage
+    pub fn start(&self) -> Vec<u8> {
+        [&self.ellswift[..], &self.garbage[..]].concat()
+    }
+
+    pub fn is_done(&self) -> bool {
+        matches!(self.state, State::Done(_))
+    }
+
+    /// Takes the bytes received so far, returning how many of them were used
+    /// and what to send. Those left over are packets for the ciphers once done
+    pub fn receive(&mut self, buf: &[u8]) -> Result<(usize, Vec<u8>)> {
+        let mut used = 0;
+        let mut send = Vec::new();
+
+        loop {
+            let buf = &buf[used..];
+            self.state = match std::mem::replace(&mut self.state, State::Key) {
+                State::Key if buf.len() < ELLSWIFT_LEN => {
+                    self.state = State::Key;
+                    break;
+                }
+                State::Key => {
+                    // safe, long enough
+                    let theirs = buf[..ELLSWIFT_LEN].try_into().unwrap();
+                    let secret = ellswift::xdh(&self.key, &self.ellswift, &theirs, self.initiating);
+                    let mut ciphers = Ciphers::new(&secret, self.magic, self.initiating);
+
+                    send.extend_from_slice(&ciphers.send_terminator);
+                    send.extend(ciphers.send.encrypt(b"", &self.garbage, false));
+                    used += ELLSWIFT_LEN;
+                    State::Garbage(Box::new(ciphers))
+                }
+                State::Garbage(ciphers) => {
+                    let terminator = &ciphers.receive_terminator;
+                    match buf
+                        .windows(GARBAGE_TERMINATOR_LEN)
+                        .position(|window| window == terminator)
+                    {
+                        Some(len) if len <= MAX_GARBAGE_LEN => {
+                            used += len + GARBAGE_TERMINATOR_LEN;
+                            State::Version(ciphers, buf[..len].to_vec())
+                        }
+                        _ if buf.len() >= MAX_GARBAGE_LEN + GARBAGE_TERMINATOR_LEN => {
+                            return Err(Error::TransportFailed("garbage terminator not found"))
+                        }
+                        _ => {
+                            self.state = State::Garbage(ciphers);
+                            break;
+                        }
+                    }
+                }
+                State::Version(mut ciphers, garbage) => {
+                    match ciphers.receive.parse(buf, &garbage)? {
+                        // only the first packet authenticates the garbage
+                        Some((packet, len)) if packet.ignore => {
+                            used += len;
+                            State::Version(ciphers, Vec::new())
+                        }
+                        // its contents are reserved for later extensions
+                        Some((_, len)) => {
+                            used += len;
+                            State::Done(ciphers)
+                        }
+                        None => {
+                            self.state = State::Version(ciphers, garbage);
+                            break;
+                        }
+                    }
+                }
+                State::Done(ciphers) => {
+                    self.state = State::Done(ciphers);
+                    break;
+                }
+            };
+        }
+
+        Ok((used, send))
+    }
+
+    /// The ciphers for the rest of the connection, none until done
+    pub fn into_ciphers(self) -> Option<Ciphers> {
+        match self.state {
+            State::Done(ciphers) => Some(*ciphers),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::network::Network;
+
+    use super::*;
+
+    /// Feeds `side` a bit more of what arrived, handing over what it sends
+    fn step(
+        side: &mut V2Handshake,
+        received: &mut Vec<u8>,
+        arrived: &mut usize,
+        sent: &mut Vec<u8>,
+    ) -> Result<()> {
+        *arrived = received.len().min(*arrived + 1000);
+        let (used, send) = side.receive(&received[..*arrived])?;
+        received.drain(..used);
+        *arrived -= used;
+        sent.extend(send);
+        Ok(())
+    }
+
+    /// Runs both sides against each other, the bytes of each arriving a bit
+    /// at a time
+    fn handshake(initiator: &mut V2Handshake, responder: &mut V2Handshake) -> Result<()> {
+        let (mut to_responder, mut to_initiator) = (initiator.start(), responder.start());
+        let (mut arrived_initiator, mut arrived_responder) = (0, 0);
+        for _ in 0..100 {
+            step(
+                initiator,
+                &mut to_initiator,
+                &mut arrived_initiator,
+                &mut to_responder,
+            )?;
+            step(
+                responder,
+                &mut to_responder,
+                &mut arrived_responder,
+                &mut to_initiator,
+            )?;
+
+            if initiator.is_done() && responder.is_done() {
+                assert!(to_initiator.is_empty() && to_responder.is_empty());
+                return Ok(());
+            }
+        }
+
+        anyhow::bail!("handshake stuck")
+    }
+
+    #[test]
+    fn short_ids() -> Result<()> {
+        let ping = NetworkMessage::Ping(7);
+        let contents = serialize_message(&ping)?;
+        assert_eq!(contents[0], 18);
+        assert_eq!(deserialize_message(&contents)?, ping);
+
+        let version = serialize_message(&NetworkMessage::Verack)?;
+        assert_eq!(version, b"\0verack\0\0\0\0\0\0");
+        assert_eq!(deserialize_message(&version)?, NetworkMessage::Verack);
+
+        assert!(matches!(
+            deserialize_message(&[200, 1, 2]),
+            Ok(NetworkMessage::Unknown { command, payload }) if command.is_empty() && payload == [1, 2]
+        ));
+        assert!(deserialize_message(&[0, b'p']).is_err());
+        assert!(deserialize_message(&[]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn key_exchange_and_packets() -> Result<()> {
+        let magic = Network::Regtest.params().magic;
+        let mut initiator = V2Handshake::new(magic, true);
+        let mut responder = V2Handshake::new(magic, false).with_garbage(vec![7; MAX_GARBAGE_LEN]);
+        handshake(&mut initiator, &mut responder)?;
+
+        let mut ours = initiator.into_ciphers().unwrap();
+        let mut theirs = responder.into_ciphers().unwrap();
+        assert_eq!(ours.session_id, theirs.session_id);
+
+        // past a couple of rekeys, with a decoy and a packet in pieces
+        for i in 0..500u64 {
+            if i == 300 {
+                let decoy = ours.send.encrypt(&[1; 10], b"", true);
+                let (packet, len) = theirs.receive.parse(&decoy, b"")?.unwrap();
+                assert!(packet.ignore);
+                assert_eq!(len, decoy.len());
+            }
+
+            let bytes = ours.send.encrypt_message(&NetworkMessage::Ping(i))?;
+            if i == 400 {
+                assert!(theirs.receive.parse(&bytes[..5], b"")?.is_none());
+            }
+
+            let (packet, len) = theirs.receive.parse(&bytes, b"")?.unwrap();
+            assert!(!packet.ignore);
+            assert_eq!(len, LENGTH_LEN + HEADER_LEN + 9 + TAG_SIZE);
+            assert_eq!(
+                deserialize_message(&packet.contents)?,
+                NetworkMessage::Ping(i)
+            );
+        }
+
+        let mut tampered = theirs.send.encrypt_message(&NetworkMessage::Verack)?;
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(ours.receive.parse(&tampered, b"").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn wrong_keys_and_garbage() -> Result<()> {
+        let magic = Network::Regtest.params().magic;
+
+        // no terminator in the garbage allowed
+        let mut responder = V2Handshake::new(magic, false);
+        let mut bytes = V2Handshake::new(magic, true).start();
+        bytes.resize(ELLSWIFT_LEN + MAX_GARBAGE_LEN + GARBAGE_TERMINATOR_LEN, 0);
+        assert!(matches!(
+            responder.receive(&bytes),
+            Err(Error::TransportFailed(_))
+        ));
+
+        // other networks derive other keys
+        let mut initiator = V2Handshake::new(magic, true);
+        let mut responder = V2Handshake::new(Network::Mainnet.params().magic, false);
+        assert!(handshake(&mut initiator, &mut responder).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,177 @@
+use alloc::string::ToString;
+use core::fmt::{self, Display, Formatter};
+use core::str::FromStr;
+
+use crate::{Error, Result};
+
+/// Bitcoin networks, every test network shares the base58 prefixes and extended
+/// key versions of testnet, only the bech32 human readable part sets regtest apart
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Network {
+    Mainnet,
+    Testnet,
+    Testnet4,
+    Signet,
+    Regtest,
+}
+
+impl Network {
+    pub const ALL: [Network; 5] = [
+        Network::Mainnet,
+        Network::Testnet,
+        Network::Testnet4,
+        Network::Signet,
+        Network::Regtest,
+    ];
+
+    pub fn is_mainnet(self) -> bool {
+        self == Network::Mainnet
+    }
+
+    /// Version byte of P2PKH addresses
+    pub fn p2pkh_prefix(self) -> u8 {
+        if self.is_mainnet() {
+            0x00
+        } else {
+            0x6f
+        }
+    }
+
+    /// Version byte of P2SH addresses
+    pub fn p2sh_prefix(self) -> u8 {
+        if self.is_mainnet() {
+            0x05
+        } else {
+            0xc4
+        }
+    }
+
+    /// Version byte of WIF encoded private keys
+    pub fn wif_prefix(self) -> u8 {
+        if self.is_mainnet() {
+            0x80
+        } else {
+            0xef
+        }
+    }
+
+    /// Human readable part of segwit addresses
+    pub fn bech32_hrp(self) -> &'static str {
+        match self {
+            Network::Mainnet => "bc",
+            Network::Testnet | Network::Testnet4 | Network::Signet => "tb",
+            Network::Regtest => "bcrt",
+        }
+    }
+
+    /// Version bytes of `xprv`/`tprv` extended private keys
+    pub fn xprv_version(self) -> [u8; 4] {
+        if self.is_mainnet() {
+            [0x04, 0x88, 0xad, 0xe4]
+        } else {
+            [0x04, 0x35, 0x83, 0x94]
+        }
+    }
+
+    /// Version bytes of `xpub`/`tpub` extended public keys
+    pub fn xpub_version(self) -> [u8; 4] {
+        if self.is_mainnet() {
+            [0x04, 0x88, 0xb2, 0x1e]
+        } else {
+            [0x04, 0x35, 0x87, 0xcf]
+        }
+    }
+
+    /// Network of a P2PKH or P2SH version byte, test networks resolve to testnet
+    pub fn from_base58_prefix(prefix: u8) -> Option<Self> {
+        match prefix {
+            0x00 | 0x05 => Some(Network::Mainnet),
+            0x6f | 0xc4 => Some(Network::Testnet),
+            _ => None,
+        }
+    }
+
+    /// Network of a WIF version byte, test networks resolve to testnet
+    pub fn from_wif_prefix(prefix: u8) -> Option<Self> {
+        match prefix {
+            0x80 => Some(Network::Mainnet),
+            0xef => Some(Network::Testnet),
+            _ => None,
+        }
+    }
+
+    /// Network of a segwit human readable part, `tb` resolves to testnet
+    pub fn from_bech32_hrp(hrp: &str) -> Option<Self> {
+        match hrp {
+            "bc" => Some(Network::Mainnet),
+            "tb" => Some(Network::Testnet),
+            "bcrt" => Some(Network::Regtest),
+            _ => None,
+        }
+    }
+}
+
+/// Lowercase names as used by Bitcoin Core, e.g. `mainnet` or `testnet4`
+impl Display for Network {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Network::Mainnet => "mainnet",
+            Network::Testnet => "testnet",
+            Network::Testnet4 => "testnet4",
+            Network::Signet => "signet",
+            Network::Regtest => "regtest",
+        };
+
+        fmt.write_str(name)
+    }
+}
+
+impl FromStr for Network {
+    type Err = Error;
+
+    fn from_str(string: &str) -> Result<Self> {
+        Network::ALL
+            .iter()
+            .copied()
+            .find(|network| network.to_string() == string)
+            .ok_or_else(|| Error::UnknownNetwork(string.to_string()))
+    }
+}
+
+#[cfg(feature = "serde")]
+serde_impl!(Network, "a network name");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefixes_roundtrip() {
+        for network in Network::ALL.iter().copied() {
+            let expected = match network {
+                Network::Mainnet => Network::Mainnet,
+                Network::Regtest => Network::Regtest,
+                _ => Network::Testnet,
+            };
+
+            let base58 = Network::from_base58_prefix(network.p2pkh_prefix());
+            assert_eq!(base58, Network::from_base58_prefix(network.p2sh_prefix()));
+            assert_eq!(base58.map(Network::is_mainnet), Some(network.is_mainnet()));
+            assert_eq!(Network::from_wif_prefix(network.wif_prefix()), base58);
+            assert_eq!(
+                Network::from_bech32_hrp(network.bech32_hrp()),
+                Some(expected)
+            );
+        }
+    }
+
+    #[test]
+    fn names_roundtrip() {
+        for network in &Network::ALL {
+            assert_eq!(network.to_string().parse::<Network>().unwrap(), *network);
+        }
+
+        assert_eq!("testnet4".parse::<Network>().unwrap(), Network::Testnet4);
+        assert!("bitcoin".parse::<Network>().is_err());
+    }
+}
@@ -0,0 +1,51 @@
+use lazy_static::lazy_static;
+use num_bigint::BigUint;
+
+use crate::curve::{CurveParams, Point};
+use crate::field::{Fp, Modulus};
+
+prime_modulus!(
+    /// The NIST P-256 prime, 2^256 - 2^224 + 2^192 + 2^96 - 1
+    pub P256Prime = "ffffffff00000001000000000000000000000000ffffffffffffffffffffffff"
+);
+
+pub type FieldElement = Fp<P256Prime>;
+
+lazy_static! {
+    static ref B: FieldElement = FieldElement::from_bytes_be(hex_literal::hex!(
+        "5ac635d8aa3a93e7b3ebbd55769886bc651d06b0cc53b0f63bce3c3e27d2604b"
+    ));
+    static ref GX: FieldElement = FieldElement::from_bytes_be(hex_literal::hex!(
+        "6b17d1f2e12c4247f8bce6e563a440f277037d812deb33a0f4a13945d898c296"
+    ));
+    static ref GY: FieldElement = FieldElement::from_bytes_be(hex_literal::hex!(
+        "4fe342e2fe1a7f9b8ee7eb4a7c0f9e162bce33576b315ececbb6406837bf51f5"
+    ));
+    static ref N: BigUint =
+        biguint!("ffffffff00000000ffffffffffffffffbce6faada7179e84f3b9cac2fc632551");
+}
+
+/// The NIST P-256 (`secp256r1`) domain parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct P256;
+
+impl CurveParams for P256 {
+    type Field = FieldElement;
+
+    fn a() -> FieldElement {
+        // a = -3
+        FieldElement::new(P256Prime::modulus() - 3u8)
+    }
+
+    fn b() -> FieldElement {
+        B.clone()
+    }
+
+    fn generator() -> Point<FieldElement> {
+        Point::Normal(GX.clone(), GY.clone())
+    }
+
+    fn order() -> &'static BigUint {
+        &N
+    }
+}
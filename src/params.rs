@@ -0,0 +1,362 @@
+//! Constants every network is defined by: its genesis block, P2P magic and
+//! port, address prefixes and consensus parameters
+
+use hex_literal::hex;
+
+use crate::amount::Amount;
+use crate::core::block::{Block, BlockHeader};
+use crate::core::coinbase;
+use crate::core::input::{OutPoint, TxIn};
+use crate::core::output::TxOut;
+use crate::core::pow::PowParams;
+use crate::core::script::{push_slice, Script};
+use crate::core::signet;
+use crate::core::tx::Transaction;
+use crate::core::txid::BlockHash;
+use crate::network::Network;
+
+/// Headline of the genesis coinbase of every network but testnet4
+const GENESIS_MESSAGE: &[u8] =
+    b"The Times 03/Jan/2009 Chancellor on brink of second bailout for banks";
+/// Satoshi's key, paid by the genesis coinbase of every network but testnet4
+const GENESIS_PUBKEY: [u8; 65] = hex!("04678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5f");
+const TESTNET4_GENESIS_MESSAGE: &[u8] =
+    b"03/May/2024 000000000000000000001ebd58c244970b3aa9d783bb001011fbe8ea8e98e00e";
+
+/// The checkpoints Bitcoin Core had hardcoded
+const MAINNET_CHECKPOINTS: [(u32, &str); 13] = [
+    (
+        11111,
+        "0000000069e244f73d78e8fd29ba2fd2ed618bd6fa2ee92559f542fdb26e7c1d",
+    ),
+    (
+        33333,
+        "000000002dd5588a74784eaa7ab0507a18ad16a236e7b1ce69f00d7ddfb5d0a6",
+    ),
+    (
+        74000,
+        "0000000000573993a3c9e41ce34471c079dcf5f52a0e824a81e7f953b8661a20",
+    ),
+    (
+        105000,
+        "00000000000291ce28027faea320c8d2b054b2e0fe44a773f3eefb151d6bdc97",
+    ),
+    (
+        134444,
+        "00000000000005b12ffd4cd315cd34ffd4a594f430ac814c91184a0d42d2b0fe",
+    ),
+    (
+        168000,
+        "000000000000099e61ea72015e79632f216fe6cb33d7899acb35b75c8303b763",
+    ),
+    (
+        193000,
+        "000000000000059f452a5f7340de6682a977387c17010ff6e6c3bd83ca8b1317",
+    ),
+    (
+        210000,
+        "000000000000048b95347e83192f69cf0366076336c639f9b7228e9ba171342e",
+    ),
+    (
+        216116,
+        "00000000000001b4f4b433e81ee46494af945cf96014816a4e2370f11b23df4e",
+    ),
+    (
+        225430,
+        "00000000000001c108384350f74090433e7fcf79a606b8e797f065b130575932",
+    ),
+    (
+        250000,
+        "000000000000003887df1f29024b06fc2200b55f8af8f35453d7be294df2d214",
+    ),
+    (
+        279000,
+        "0000000000000001ae8c72a0b0c301f67e3afca10e819efa9041e458e9bd7e40",
+    ),
+    (
+        295000,
+        "00000000000000004d9b4ef50f0f9d686fd69db2e03af35a100370c64632a983",
+    ),
+];
+const TESTNET_CHECKPOINTS: [(u32, &str); 1] = [(
+    546,
+    "000000002a936ca763904c3c35fce2f3556c559c0214345d31b1bcebf76acb70",
+)];
+
+const MAINNET_DNS_SEEDS: [&str; 9] = [
+    "seed.bitcoin.sipa.be",
+    "dnsseed.bluematt.me",
+    "seed.bitcoin.jonasschnelli.ch",
+    "seed.btc.petertodd.net",
+    "seed.bitcoin.sprovoost.nl",
+    "dnsseed.emzy.de",
+    "seed.bitcoin.wiz.biz",
+    "seed.mainnet.achownodes.xyz",
+    "dnsseed.bitcoin.dashjr-list-of-p2p-nodes.us",
+];
+const TESTNET_DNS_SEEDS: [&str; 4] = [
+    "testnet-seed.bitcoin.jonasschnelli.ch",
+    "seed.tbtc.petertodd.net",
+    "seed.testnet.bitcoin.sprovoost.nl",
+    "seed.testnet.achownodes.xyz",
+];
+const TESTNET4_DNS_SEEDS: [&str; 2] = [
+    "seed.testnet4.bitcoin.sprovoost.nl",
+    "seed.testnet4.wiz.biz",
+];
+const SIGNET_DNS_SEEDS: [&str; 2] = [
+    "seed.signet.bitcoin.sprovoost.nl",
+    "seed.signet.achownodes.xyz",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Params {
+    pub network: Network,
+    /// Start of every P2P message
+    pub magic: [u8; 4],
+    pub default_port: u16,
+    /// Hosts resolving to addresses of peers, as Bitcoin Core ships them
+    pub dns_seeds: &'static [&'static str],
+    pub bech32_hrp: &'static str,
+    pub p2pkh_prefix: u8,
+    pub p2sh_prefix: u8,
+    pub wif_prefix: u8,
+    pub xprv_version: [u8; 4],
+    pub xpub_version: [u8; 4],
+    /// Blocks between each halving of the subsidy
+    pub subsidy_halving_interval: u32,
+    pub pow: PowParams,
+    /// Heights and hashes of blocks known to be in the chain
+    pub checkpoints: &'static [(u32, &'static str)],
+}
+
+impl Params {
+    pub fn new(network: Network) -> Self {
+        let (magic, default_port, subsidy_halving_interval) = match network {
+            Network::Mainnet => ([0xf9, 0xbe, 0xb4, 0xd9], 8333, 210_000),
+            Network::Testnet => ([0x0b, 0x11, 0x09, 0x07], 18333, 210_000),
+            Network::Testnet4 => ([0x1c, 0x16, 0x3f, 0x28], 48333, 210_000),
+            // the default signet, custom ones take the magic from their challenge
+            Network::Signet => ([0x0a, 0x03, 0xcf, 0x40], 38333, 210_000),
+            Network::Regtest => ([0xfa, 0xbf, 0xb5, 0xda], 18444, 150),
+        };
+
+        let dns_seeds: &[_] = match network {
+            Network::Mainnet => &MAINNET_DNS_SEEDS,
+            Network::Testnet => &TESTNET_DNS_SEEDS,
+            Network::Testnet4 => &TESTNET4_DNS_SEEDS,
+            Network::Signet => &SIGNET_DNS_SEEDS,
+            Network::Regtest => &[],
+        };
+
+        let checkpoints: &[_] = match network {
+            Network::Mainnet => &MAINNET_CHECKPOINTS,
+            Network::Testnet => &TESTNET_CHECKPOINTS,
+            _ => &[],
+        };
+
+        Self {
+            network,
+            magic,
+            default_port,
+            dns_seeds,
+            bech32_hrp: network.bech32_hrp(),
+            p2pkh_prefix: network.p2pkh_prefix(),
+            p2sh_prefix: network.p2sh_prefix(),
+            wif_prefix: network.wif_prefix(),
+            xprv_version: network.xprv_version(),
+            xpub_version: network.xpub_version(),
+            subsidy_halving_interval,
+            pow: PowParams::new(network),
+            checkpoints,
+        }
+    }
+
+    /// A custom signet, its magic comes from the challenge blocks are signed for
+    pub fn custom_signet(challenge: &Script) -> Self {
+        Self {
+            magic: signet::signet_magic(challenge),
+            ..Self::new(Network::Signet)
+        }
+    }
+
+    /// The first block, hardcoded in every node
+    pub fn genesis_block(&self) -> Block {
+        let (time, nonce) = match self.network {
+            Network::Mainnet => (1_231_006_505, 2_083_236_893),
+            Network::Testnet => (1_296_688_602, 414_098_458),
+            Network::Testnet4 => (1_714_777_860, 393_743_547),
+            Network::Signet => (1_598_918_400, 52_613_770),
+            Network::Regtest => (1_296_688_602, 2),
+        };
+
+        let coinbase = match self.network {
+            Network::Testnet4 => genesis_coinbase(TESTNET4_GENESIS_MESSAGE, &[0; 33]),
+            _ => genesis_coinbase(GENESIS_MESSAGE, &GENESIS_PUBKEY),
+        };
+
+        // a single transaction is its own merkle root
+        let merkle_root = coinbase
+            .txid()
+            .map(|txid| txid.to_bytes())
+            .unwrap_or_default();
+        let header = BlockHeader::new(
+            1,
+            BlockHash::default(),
+            merkle_root,
+            time,
+            self.pow.pow_limit,
+            nonce,
+        );
+        Block::new(header, vec![coinbase])
+    }
+
+    pub fn genesis_hash(&self) -> BlockHash {
+        self.genesis_block().block_hash()
+    }
+
+    pub fn checkpoints(&self) -> Vec<(u32, BlockHash)> {
+        self.checkpoints
+            .iter()
+            .map(|(height, hash)| (*height, hash.parse().unwrap())) // safe, hardcoded
+            .collect()
+    }
+
+    /// New coins created by the block at `height`
+    pub fn subsidy_at_height(&self, height: u32) -> Amount {
+        coinbase::subsidy_at_height(height, self.subsidy_halving_interval)
+    }
+
+    /// Coins created by the blocks up to and including the one at `height`
+    pub fn supply_at_height(&self, height: u32) -> Amount {
+        coinbase::supply_at_height(height, self.subsidy_halving_interval)
+    }
+
+    /// Coins created once the subsidy runs out
+    pub fn max_supply(&self) -> Amount {
+        self.supply_at_height(u32::MAX)
+    }
+}
+
+impl Network {
+    /// Every constant of the network
+    pub fn params(self) -> Params {
+        Params::new(self)
+    }
+}
+
+/// Pays 50 bitcoins to a P2PK output of `pubkey`, its script sig pushes the
+/// mainnet bits, 4 and `message`
+fn genesis_coinbase(message: &[u8], pubkey: &[u8]) -> Transaction {
+    let mut script_sig = hex!("04ffff001d0104").to_vec();
+    push_slice(&mut script_sig, message);
+    let mut input = TxIn::new(OutPoint::null());
+    input.script_sig = Script::from(script_sig);
+
+    let mut script_pubkey = Vec::new();
+    push_slice(&mut script_pubkey, pubkey);
+    script_pubkey.push(0xac);
+    let output = TxOut::new(
+        Amount::from_sat(50 * Amount::ONE_BTC.to_sat()),
+        Script::from(script_pubkey),
+    );
+
+    Transaction::new(1, vec![input], vec![output], 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+
+    #[test]
+    fn genesis_blocks() -> Result<()> {
+        for (network, hash) in [
+            (
+                Network::Mainnet,
+                "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f",
+            ),
+            (
+                Network::Testnet,
+                "000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943",
+            ),
+            (
+                Network::Testnet4,
+                "00000000da84f2bafbbc53dee25a72ae507ff4914b867c565be350b0da8bf043",
+            ),
+            (
+                Network::Signet,
+                "00000008819873e925422c1ff0f99f7cc9bbb232af63a077a480a3633bee1ef6",
+            ),
+            (
+                Network::Regtest,
+                "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206",
+            ),
+        ]
+        .iter()
+        {
+            let params = network.params();
+            let genesis = params.genesis_block();
+            assert_eq!(params.genesis_hash().to_string(), *hash, "{}", network);
+            genesis.header().validate_pow()?;
+            genesis.check_merkle_root()?;
+            genesis.check_limits(None)?;
+        }
+
+        let mainnet = Network::Mainnet.params().genesis_block();
+        assert_eq!(mainnet.serialize()?.len(), 285);
+        Ok(())
+    }
+
+    #[test]
+    fn network_constants() {
+        let mainnet = Network::Mainnet.params();
+        assert_eq!(mainnet.magic, [0xf9, 0xbe, 0xb4, 0xd9]);
+        assert_eq!(mainnet.default_port, 8333);
+        assert_eq!(mainnet.pow.adjustment_interval(), 2016);
+        assert_eq!(mainnet.p2pkh_prefix, 0x00);
+
+        let regtest = Network::Regtest.params();
+        assert_eq!(regtest.subsidy_halving_interval, 150);
+        assert_eq!(regtest.bech32_hrp, "bcrt");
+        assert!(regtest.pow.no_retargeting);
+
+        assert_eq!(
+            mainnet.subsidy_at_height(840_000),
+            Amount::from_sat(312_500_000)
+        );
+        assert_eq!(
+            mainnet.max_supply(),
+            Amount::from_sat(2_099_999_997_690_000)
+        );
+        assert_eq!(
+            regtest.subsidy_at_height(300),
+            Amount::from_sat(1_250_000_000)
+        );
+        assert_eq!(regtest.max_supply(), Amount::from_sat(1_499_999_998_350));
+
+        let checkpoints = mainnet.checkpoints();
+        assert_eq!(checkpoints.len(), 13);
+        assert!(checkpoints.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert!(regtest.checkpoints().is_empty());
+
+        let default_signet = Script::from(signet::DEFAULT_SIGNET_CHALLENGE.to_vec());
+        assert_eq!(
+            Params::custom_signet(&default_signet),
+            Network::Signet.params()
+        );
+        assert_ne!(
+            Params::custom_signet(&Script::from(vec![0x51])).magic,
+            Network::Signet.params().magic
+        );
+
+        // magics and ports tell the networks apart
+        for (i, first) in Network::ALL.iter().enumerate() {
+            for second in &Network::ALL[i + 1..] {
+                assert_ne!(first.params().magic, second.params().magic);
+                assert_ne!(first.params().default_port, second.params().default_port);
+            }
+        }
+    }
+}
@@ -0,0 +1,83 @@
+//! Standard (RFC 4648) base64 with padding, the text form of a PSBT
+
+use crate::{Error, Result};
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn encode(bytes: &[u8]) -> String {
+    let mut result = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let mut group = [0u8; 3];
+        group[..chunk.len()].copy_from_slice(chunk);
+        let group = u32::from_be_bytes([0, group[0], group[1], group[2]]);
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                let index = (group >> (18 - 6 * i)) & 0x3f;
+                result.push(ALPHABET[index as usize] as char);
+            } else {
+                result.push('=');
+            }
+        }
+    }
+
+    result
+}
+
+pub fn decode(string: &str) -> Result<Vec<u8>> {
+    let bytes = string.as_bytes();
+    if !bytes.len().is_multiple_of(4) {
+        return Err(Error::InvalidPsbt("invalid base64 length"));
+    }
+
+    let mut result = Vec::with_capacity(bytes.len() / 4 * 3);
+    for (i, chunk) in bytes.chunks(4).enumerate() {
+        let is_last = (i + 1) * 4 == bytes.len();
+        let padding = chunk.iter().rev().take_while(|&&c| c == b'=').count();
+        if padding > 2 || (padding > 0 && !is_last) {
+            return Err(Error::InvalidPsbt("invalid base64 padding"));
+        }
+
+        let mut group = 0u32;
+        for &c in &chunk[..4 - padding] {
+            let value = ALPHABET
+                .iter()
+                .position(|&a| a == c)
+                .ok_or(Error::InvalidPsbt("invalid base64 character"))?;
+            group = (group << 6) | value as u32;
+        }
+
+        group <<= 6 * padding as u32;
+        let group = group.to_be_bytes();
+        result.extend_from_slice(&group[1..4 - padding]);
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc4648_vectors() {
+        let vectors = [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ];
+
+        for (raw, encoded) in vectors.iter() {
+            assert_eq!(encode(raw.as_bytes()), *encoded);
+            assert_eq!(decode(encoded).unwrap(), raw.as_bytes());
+        }
+
+        assert!(decode("Zm9").is_err());
+        assert!(decode("Zg==Zm9v").is_err());
+        assert!(decode("Zm9*").is_err());
+    }
+}
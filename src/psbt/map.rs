@@ -0,0 +1,433 @@
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+use crate::bip32::DerivationPath;
+use crate::consensus::{self, read_compact_size, Decodable, Encodable};
+use crate::core::output::TxOut;
+use crate::core::script::Script;
+use crate::core::tx::Transaction;
+use crate::descriptor::key::KeyOrigin;
+use crate::{Error, Result};
+
+/// Input key types (BIP174)
+pub(crate) mod input_type {
+    pub const NON_WITNESS_UTXO: u64 = 0x00;
+    pub const WITNESS_UTXO: u64 = 0x01;
+    pub const PARTIAL_SIG: u64 = 0x02;
+    pub const SIGHASH_TYPE: u64 = 0x03;
+    pub const REDEEM_SCRIPT: u64 = 0x04;
+    pub const WITNESS_SCRIPT: u64 = 0x05;
+    pub const BIP32_DERIVATION: u64 = 0x06;
+    pub const FINAL_SCRIPTSIG: u64 = 0x07;
+    pub const FINAL_SCRIPTWITNESS: u64 = 0x08;
+    pub const PREVIOUS_TXID: u64 = 0x0e;
+    pub const OUTPUT_INDEX: u64 = 0x0f;
+    pub const SEQUENCE: u64 = 0x10;
+    pub const REQUIRED_TIME_LOCKTIME: u64 = 0x11;
+    pub const REQUIRED_HEIGHT_LOCKTIME: u64 = 0x12;
+}
+
+/// Output key types (BIP174)
+pub(crate) mod output_type {
+    pub const REDEEM_SCRIPT: u64 = 0x00;
+    pub const WITNESS_SCRIPT: u64 = 0x01;
+    pub const BIP32_DERIVATION: u64 = 0x02;
+    pub const AMOUNT: u64 = 0x03;
+    pub const SCRIPT: u64 = 0x04;
+}
+
+pub use crate::core::locktime::LOCKTIME_THRESHOLD;
+
+/// A key-value pair, the key type is split from the rest of the key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Pair {
+    pub key_type: u64,
+    pub key_data: Vec<u8>,
+    pub value: Vec<u8>,
+}
+
+impl Pair {
+    pub fn new(key_type: u64, key_data: Vec<u8>, value: Vec<u8>) -> Self {
+        Self {
+            key_type,
+            key_data,
+            value,
+        }
+    }
+
+    /// Full serialized key, what uniqueness within a map is checked against
+    pub fn key(&self) -> Vec<u8> {
+        let mut key = crate::varint::encode(self.key_type);
+        key.extend_from_slice(&self.key_data);
+        key
+    }
+
+    /// Read the next pair of a map, `None` once the separator is found
+    pub fn read<R: Read + ?Sized>(reader: &mut R) -> Result<Option<Self>> {
+        let key: Vec<u8> = Vec::consensus_decode(reader)?;
+        if key.is_empty() {
+            return Ok(None);
+        }
+
+        let mut key_reader = key.as_slice();
+        let key_type = read_compact_size(&mut key_reader)?;
+        let key_data = key_reader.to_vec();
+        let value = Vec::consensus_decode(reader)?;
+
+        Ok(Some(Self::new(key_type, key_data, value)))
+    }
+
+    pub fn write<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        Ok(self.key().consensus_encode(writer)? + self.value.consensus_encode(writer)?)
+    }
+
+    /// Key types whose key is only the type itself
+    fn expect_empty_key(&self) -> Result<()> {
+        if self.key_data.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::InvalidPsbt("unexpected key data"))
+        }
+    }
+}
+
+/// Read all the pairs of a map, rejecting duplicated keys
+pub(crate) fn read_map<R: Read + ?Sized>(reader: &mut R) -> Result<Vec<Pair>> {
+    let mut pairs: Vec<Pair> = Vec::new();
+    while let Some(pair) = Pair::read(reader)? {
+        if pairs.iter().any(|other| other.key() == pair.key()) {
+            return Err(Error::InvalidPsbt("duplicated key"));
+        }
+
+        pairs.push(pair);
+    }
+
+    Ok(pairs)
+}
+
+/// Write the pairs followed by the separator
+pub(crate) fn write_map<W: Write + ?Sized>(writer: &mut W, pairs: &[Pair]) -> Result<usize> {
+    let mut len = 0;
+    for pair in pairs {
+        len += pair.write(writer)?;
+    }
+
+    Ok(len + 0u8.consensus_encode(writer)?)
+}
+
+/// Fingerprint followed by the little endian indexes
+pub(crate) fn serialize_origin(origin: &KeyOrigin) -> Vec<u8> {
+    let mut result = origin.fingerprint.to_vec();
+    for index in origin.path.as_ref() {
+        result.extend_from_slice(&index.to_le_bytes());
+    }
+
+    result
+}
+
+pub(crate) fn deserialize_origin(bytes: &[u8]) -> Result<KeyOrigin> {
+    if bytes.len() < 4 || !bytes.len().is_multiple_of(4) {
+        return Err(Error::InvalidPsbt("invalid key origin"));
+    }
+
+    let mut fingerprint = [0u8; 4];
+    fingerprint.copy_from_slice(&bytes[..4]);
+    let path: Vec<_> = bytes[4..]
+        .chunks(4)
+        .map(|index| u32::from_le_bytes([index[0], index[1], index[2], index[3]]))
+        .collect();
+
+    Ok(KeyOrigin {
+        fingerprint,
+        path: DerivationPath::from(path),
+    })
+}
+
+/// Public keys are map keys, they must be valid SEC encodings
+fn expect_public_key(pair: &Pair) -> Result<Vec<u8>> {
+    match pair.key_data.len() {
+        33 | 65 => Ok(pair.key_data.clone()),
+        _ => Err(Error::InvalidPsbt("invalid public key in key")),
+    }
+}
+
+/// Per input data of a PSBT
+#[derive(Debug, Clone, Default)]
+pub struct Input {
+    /// The whole transaction being spent, needed for legacy inputs
+    pub non_witness_utxo: Option<Transaction>,
+    /// Only the output being spent, enough for segwit inputs
+    pub witness_utxo: Option<TxOut>,
+    /// SEC encoded public key to DER signature followed by the sighash byte
+    pub partial_sigs: BTreeMap<Vec<u8>, Vec<u8>>,
+    pub sighash_type: Option<u32>,
+    pub redeem_script: Option<Script>,
+    pub witness_script: Option<Script>,
+    /// SEC encoded public key to its origin
+    pub bip32_derivation: BTreeMap<Vec<u8>, KeyOrigin>,
+    pub final_script_sig: Option<Script>,
+    pub final_script_witness: Option<Vec<Vec<u8>>>,
+    /// Minimum locktime as a timestamp this input needs, version 2 only
+    pub required_time_locktime: Option<u32>,
+    /// Minimum locktime as a block height this input needs, version 2 only
+    pub required_height_locktime: Option<u32>,
+    /// Full key to value of the entries this crate doesn't understand
+    pub unknown: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl Input {
+    /// Whether the finalizer already ran on this input
+    pub fn is_finalized(&self) -> bool {
+        self.final_script_sig.is_some() || self.final_script_witness.is_some()
+    }
+
+    /// Whether any field only allowed in version 2 is set
+    pub(crate) fn has_v2_fields(&self) -> bool {
+        self.required_time_locktime.is_some() || self.required_height_locktime.is_some()
+    }
+
+    pub(crate) fn from_pairs(pairs: Vec<Pair>) -> Result<Self> {
+        let mut input = Self::default();
+        for pair in pairs {
+            match pair.key_type {
+                input_type::NON_WITNESS_UTXO => {
+                    pair.expect_empty_key()?;
+                    input.non_witness_utxo = Some(consensus::deserialize(&pair.value)?);
+                }
+
+                input_type::WITNESS_UTXO => {
+                    pair.expect_empty_key()?;
+                    input.witness_utxo = Some(consensus::deserialize(&pair.value)?);
+                }
+
+                input_type::PARTIAL_SIG => {
+                    let key = expect_public_key(&pair)?;
+                    input.partial_sigs.insert(key, pair.value);
+                }
+
+                input_type::SIGHASH_TYPE => {
+                    pair.expect_empty_key()?;
+                    input.sighash_type = Some(consensus::deserialize(&pair.value)?);
+                }
+
+                input_type::REDEEM_SCRIPT => {
+                    pair.expect_empty_key()?;
+                    input.redeem_script = Some(Script { bytes: pair.value });
+                }
+
+                input_type::WITNESS_SCRIPT => {
+                    pair.expect_empty_key()?;
+                    input.witness_script = Some(Script { bytes: pair.value });
+                }
+
+                input_type::BIP32_DERIVATION => {
+                    let key = expect_public_key(&pair)?;
+                    let origin = deserialize_origin(&pair.value)?;
+                    input.bip32_derivation.insert(key, origin);
+                }
+
+                input_type::FINAL_SCRIPTSIG => {
+                    pair.expect_empty_key()?;
+                    input.final_script_sig = Some(Script { bytes: pair.value });
+                }
+
+                input_type::FINAL_SCRIPTWITNESS => {
+                    pair.expect_empty_key()?;
+                    input.final_script_witness = Some(consensus::deserialize(&pair.value)?);
+                }
+
+                input_type::REQUIRED_TIME_LOCKTIME => {
+                    pair.expect_empty_key()?;
+                    let locktime = consensus::deserialize(&pair.value)?;
+                    if locktime < LOCKTIME_THRESHOLD {
+                        return Err(Error::InvalidPsbt("required time locktime is a height"));
+                    }
+
+                    input.required_time_locktime = Some(locktime);
+                }
+
+                input_type::REQUIRED_HEIGHT_LOCKTIME => {
+                    pair.expect_empty_key()?;
+                    let locktime = consensus::deserialize(&pair.value)?;
+                    if locktime >= LOCKTIME_THRESHOLD {
+                        return Err(Error::InvalidPsbt("required height locktime is a time"));
+                    }
+
+                    input.required_height_locktime = Some(locktime);
+                }
+
+                _ => {
+                    input.unknown.insert(pair.key(), pair.value);
+                }
+            }
+        }
+
+        Ok(input)
+    }
+
+    pub(crate) fn to_pairs(&self) -> Result<Vec<Pair>> {
+        let mut pairs = Vec::new();
+
+        if let Some(tx) = &self.non_witness_utxo {
+            let value = consensus::serialize(tx)?;
+            pairs.push(Pair::new(input_type::NON_WITNESS_UTXO, vec![], value));
+        }
+
+        if let Some(output) = &self.witness_utxo {
+            let value = consensus::serialize(output)?;
+            pairs.push(Pair::new(input_type::WITNESS_UTXO, vec![], value));
+        }
+
+        for (key, signature) in &self.partial_sigs {
+            let pair = Pair::new(input_type::PARTIAL_SIG, key.clone(), signature.clone());
+            pairs.push(pair);
+        }
+
+        if let Some(sighash_type) = self.sighash_type {
+            let value = sighash_type.to_le_bytes().to_vec();
+            pairs.push(Pair::new(input_type::SIGHASH_TYPE, vec![], value));
+        }
+
+        if let Some(script) = &self.redeem_script {
+            let value = script.bytes.clone();
+            pairs.push(Pair::new(input_type::REDEEM_SCRIPT, vec![], value));
+        }
+
+        if let Some(script) = &self.witness_script {
+            let value = script.bytes.clone();
+            pairs.push(Pair::new(input_type::WITNESS_SCRIPT, vec![], value));
+        }
+
+        for (key, origin) in &self.bip32_derivation {
+            let value = serialize_origin(origin);
+            pairs.push(Pair::new(input_type::BIP32_DERIVATION, key.clone(), value));
+        }
+
+        if let Some(script) = &self.final_script_sig {
+            let value = script.bytes.clone();
+            pairs.push(Pair::new(input_type::FINAL_SCRIPTSIG, vec![], value));
+        }
+
+        if let Some(witness) = &self.final_script_witness {
+            let value = consensus::serialize(witness)?;
+            pairs.push(Pair::new(input_type::FINAL_SCRIPTWITNESS, vec![], value));
+        }
+
+        if let Some(locktime) = self.required_time_locktime {
+            let value = locktime.to_le_bytes().to_vec();
+            pairs.push(Pair::new(input_type::REQUIRED_TIME_LOCKTIME, vec![], value));
+        }
+
+        if let Some(locktime) = self.required_height_locktime {
+            let value = locktime.to_le_bytes().to_vec();
+            pairs.push(Pair::new(
+                input_type::REQUIRED_HEIGHT_LOCKTIME,
+                vec![],
+                value,
+            ));
+        }
+
+        pairs.extend(unknown_pairs(&self.unknown)?);
+        Ok(pairs)
+    }
+}
+
+impl Encodable for Input {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        write_map(writer, &self.to_pairs()?)
+    }
+}
+
+impl Decodable for Input {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        Self::from_pairs(read_map(reader)?)
+    }
+}
+
+/// Per output data of a PSBT
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Output {
+    pub redeem_script: Option<Script>,
+    pub witness_script: Option<Script>,
+    /// SEC encoded public key to its origin
+    pub bip32_derivation: BTreeMap<Vec<u8>, KeyOrigin>,
+    /// Full key to value of the entries this crate doesn't understand
+    pub unknown: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl Output {
+    pub(crate) fn from_pairs(pairs: Vec<Pair>) -> Result<Self> {
+        let mut output = Self::default();
+        for pair in pairs {
+            match pair.key_type {
+                output_type::REDEEM_SCRIPT => {
+                    pair.expect_empty_key()?;
+                    output.redeem_script = Some(Script { bytes: pair.value });
+                }
+
+                output_type::WITNESS_SCRIPT => {
+                    pair.expect_empty_key()?;
+                    output.witness_script = Some(Script { bytes: pair.value });
+                }
+
+                output_type::BIP32_DERIVATION => {
+                    let key = expect_public_key(&pair)?;
+                    let origin = deserialize_origin(&pair.value)?;
+                    output.bip32_derivation.insert(key, origin);
+                }
+
+                _ => {
+                    output.unknown.insert(pair.key(), pair.value);
+                }
+            }
+        }
+
+        Ok(output)
+    }
+
+    pub(crate) fn to_pairs(&self) -> Result<Vec<Pair>> {
+        let mut pairs = Vec::new();
+
+        if let Some(script) = &self.redeem_script {
+            let value = script.bytes.clone();
+            pairs.push(Pair::new(output_type::REDEEM_SCRIPT, vec![], value));
+        }
+
+        if let Some(script) = &self.witness_script {
+            let value = script.bytes.clone();
+            pairs.push(Pair::new(output_type::WITNESS_SCRIPT, vec![], value));
+        }
+
+        for (key, origin) in &self.bip32_derivation {
+            let value = serialize_origin(origin);
+            pairs.push(Pair::new(output_type::BIP32_DERIVATION, key.clone(), value));
+        }
+
+        pairs.extend(unknown_pairs(&self.unknown)?);
+        Ok(pairs)
+    }
+}
+
+impl Encodable for Output {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        write_map(writer, &self.to_pairs()?)
+    }
+}
+
+impl Decodable for Output {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        Self::from_pairs(read_map(reader)?)
+    }
+}
+
+/// Unknown entries are stored with their full key, split it back into a pair
+pub(crate) fn unknown_pairs(unknown: &BTreeMap<Vec<u8>, Vec<u8>>) -> Result<Vec<Pair>> {
+    unknown
+        .iter()
+        .map(|(key, value)| {
+            let mut key_reader = key.as_slice();
+            let key_type = read_compact_size(&mut key_reader)?;
+            Ok(Pair::new(key_type, key_reader.to_vec(), value.clone()))
+        })
+        .collect()
+}
@@ -0,0 +1,1008 @@
+//! Partially signed bitcoin transactions, version 0 (BIP174) and 2 (BIP370)
+
+mod base64;
+mod map;
+
+pub use map::{Input, Output, LOCKTIME_THRESHOLD};
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+use crate::amount::Amount;
+use crate::bip32::ExtendedPublicKey;
+use crate::consensus::{self, Decodable, Encodable};
+use crate::core::fee::{FeeRate, InputType};
+use crate::core::input::{OutPoint, TxIn};
+use crate::core::output::TxOut;
+use crate::core::script::{push_slice, Script};
+use crate::core::tx::Transaction;
+use crate::core::txid::Txid;
+use crate::core::witness::Witness;
+use crate::descriptor::key::KeyOrigin;
+use crate::secp256k1::crypto::{PrivateKey, PublicKey};
+use crate::utils::hash160;
+use crate::{Error, Result};
+
+use map::{input_type, output_type, read_map, unknown_pairs, write_map, Pair};
+
+/// Magic bytes every PSBT starts with, `psbt` followed by `0xff`
+pub const MAGIC: [u8; 5] = *b"psbt\xff";
+
+/// The only sighash type the signer produces
+pub use crate::core::sighash::SIGHASH_ALL;
+
+const GLOBAL_UNSIGNED_TX: u64 = 0x00;
+const GLOBAL_XPUB: u64 = 0x01;
+const GLOBAL_TX_VERSION: u64 = 0x02;
+const GLOBAL_FALLBACK_LOCKTIME: u64 = 0x03;
+const GLOBAL_INPUT_COUNT: u64 = 0x04;
+const GLOBAL_OUTPUT_COUNT: u64 = 0x05;
+const GLOBAL_TX_MODIFIABLE: u64 = 0x06;
+const GLOBAL_VERSION: u64 = 0xfb;
+
+/// Default sequence of inputs without the field in version 2
+const DEFAULT_SEQUENCE: u32 = 0xffffffff;
+
+/// Either version is kept as the unsigned transaction plus the version 2 only
+/// fields, the encoding is picked by `version`
+#[derive(Debug, Clone)]
+pub struct Psbt {
+    pub(crate) unsigned_tx: Transaction,
+    pub(crate) version: u32,
+    /// Locktime when no input requires one, version 2 only
+    pub(crate) fallback_locktime: Option<u32>,
+    /// Bit flags of what can still be added (inputs, outputs, SIGHASH_SINGLE
+    /// signatures), version 2 only
+    pub(crate) tx_modifiable: Option<u8>,
+    /// Serialized (78 bytes) extended public key to its origin
+    pub(crate) xpubs: BTreeMap<Vec<u8>, KeyOrigin>,
+    pub(crate) unknown: BTreeMap<Vec<u8>, Vec<u8>>,
+    pub(crate) inputs: Vec<Input>,
+    pub(crate) outputs: Vec<Output>,
+}
+
+impl Psbt {
+    /// Creator role, the transaction must not carry any signature yet
+    pub fn new(unsigned_tx: Transaction) -> Result<Self> {
+        if unsigned_tx
+            .inputs
+            .iter()
+            .any(|input| !input.script_sig.bytes.is_empty())
+        {
+            return Err(Error::InvalidPsbt("unsigned tx has script sigs"));
+        }
+
+        let inputs = vec![Input::default(); unsigned_tx.inputs.len()];
+        let outputs = vec![Output::default(); unsigned_tx.outputs.len()];
+
+        Ok(Self {
+            unsigned_tx,
+            version: 0,
+            fallback_locktime: None,
+            tx_modifiable: None,
+            xpubs: BTreeMap::new(),
+            unknown: BTreeMap::new(),
+            inputs,
+            outputs,
+        })
+    }
+
+    pub fn unsigned_tx(&self) -> &Transaction {
+        &self.unsigned_tx
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn tx_modifiable(&self) -> Option<u8> {
+        self.tx_modifiable
+    }
+
+    pub fn set_tx_modifiable(&mut self, flags: Option<u8>) {
+        self.tx_modifiable = flags;
+    }
+
+    /// Locktime of the final transaction. In version 2 it's negotiated from the
+    /// input requirements (BIP370), heights are preferred when both are possible.
+    pub fn locktime(&self) -> Result<u32> {
+        if self.version == 0 {
+            return Ok(self.unsigned_tx.locktime);
+        }
+
+        let constrained: Vec<_> = self
+            .inputs
+            .iter()
+            .filter(|input| input.has_v2_fields())
+            .collect();
+
+        if constrained.is_empty() {
+            return Ok(self.fallback_locktime.unwrap_or(0));
+        }
+
+        let heights: Option<Vec<_>> = constrained
+            .iter()
+            .map(|input| input.required_height_locktime)
+            .collect();
+
+        let times: Option<Vec<_>> = constrained
+            .iter()
+            .map(|input| input.required_time_locktime)
+            .collect();
+
+        heights
+            .or(times)
+            .and_then(|locktimes| locktimes.into_iter().max())
+            .ok_or(Error::InvalidPsbt("incompatible input locktimes"))
+    }
+
+    /// Same PSBT in the version 2 encoding, always lossless
+    pub fn to_v2(&self) -> Result<Self> {
+        if self.version == 2 {
+            return Ok(self.clone());
+        }
+
+        if self.unsigned_tx.version < 2 {
+            return Err(Error::InvalidPsbt("version 2 requires tx version 2"));
+        }
+
+        let mut psbt = self.clone();
+        psbt.version = 2;
+        psbt.fallback_locktime = Some(self.unsigned_tx.locktime).filter(|&locktime| locktime != 0);
+        Ok(psbt)
+    }
+
+    /// Same PSBT in the version 0 encoding, fails if the per input locktime
+    /// requirements or the modifiable flags would be lost
+    pub fn to_v0(&self) -> Result<Self> {
+        if self.version == 0 {
+            return Ok(self.clone());
+        }
+
+        if self.tx_modifiable.is_some() || self.inputs.iter().any(Input::has_v2_fields) {
+            return Err(Error::InvalidPsbt("not representable as version 0"));
+        }
+
+        let mut psbt = self.clone();
+        psbt.unsigned_tx.locktime = self.locktime()?;
+        psbt.version = 0;
+        psbt.fallback_locktime = None;
+        Ok(psbt)
+    }
+
+    pub fn inputs(&self) -> &[Input] {
+        &self.inputs
+    }
+
+    pub fn inputs_mut(&mut self) -> &mut [Input] {
+        &mut self.inputs
+    }
+
+    pub fn outputs(&self) -> &[Output] {
+        &self.outputs
+    }
+
+    pub fn outputs_mut(&mut self) -> &mut [Output] {
+        &mut self.outputs
+    }
+
+    /// Record an extended public key used by the inputs or outputs
+    pub fn add_xpub(
+        &mut self,
+        xpub: &ExtendedPublicKey,
+        version: [u8; 4],
+        origin: KeyOrigin,
+    ) -> Result<()> {
+        self.xpubs.insert(xpub.serialize(version)?, origin);
+        Ok(())
+    }
+
+    /// Updater role, attach the transaction spent by the input at `index`
+    pub fn add_non_witness_utxo(&mut self, index: usize, prev_tx: Transaction) -> Result<()> {
+        let input = self.unsigned_input(index)?;
+        if &prev_tx.txid()? != input.previous_output.txid() {
+            return Err(Error::InvalidPsbt("utxo doesn't match the spent txid"));
+        }
+
+        if prev_tx.outputs.len() <= input.previous_output.vout() as usize {
+            return Err(Error::InvalidPsbt("utxo doesn't have the spent output"));
+        }
+
+        self.inputs[index].non_witness_utxo = Some(prev_tx);
+        Ok(())
+    }
+
+    /// Updater role, attach only the output spent by the (segwit) input at `index`
+    pub fn add_witness_utxo(&mut self, index: usize, utxo: TxOut) -> Result<()> {
+        self.unsigned_input(index)?;
+        self.inputs[index].witness_utxo = Some(utxo);
+        Ok(())
+    }
+
+    /// Updater role, record where a key signing the input at `index` comes from
+    pub fn add_input_derivation(
+        &mut self,
+        index: usize,
+        key: &PublicKey,
+        origin: KeyOrigin,
+    ) -> Result<()> {
+        self.unsigned_input(index)?;
+        let key = key.serialize_compressed()?.to_vec();
+        self.inputs[index].bip32_derivation.insert(key, origin);
+        Ok(())
+    }
+
+    /// Updater role, record where a key of the output at `index` comes from
+    pub fn add_output_derivation(
+        &mut self,
+        index: usize,
+        key: &PublicKey,
+        origin: KeyOrigin,
+    ) -> Result<()> {
+        let output = self
+            .outputs
+            .get_mut(index)
+            .ok_or(Error::InvalidPsbt("output index out of range"))?;
+
+        let key = key.serialize_compressed()?.to_vec();
+        output.bip32_derivation.insert(key, origin);
+        Ok(())
+    }
+
+    /// Signer role, add a `SIGHASH_ALL` signature to every P2PKH input spending
+    /// to the given key. Returns the number of signed inputs.
+    pub fn sign(&mut self, key: &PrivateKey) -> Result<usize> {
+        let public_key = key.public_key();
+        let candidates = [
+            public_key.serialize_compressed()?.to_vec(),
+            public_key.serialize_uncompressed()?.to_vec(),
+        ];
+
+        let mut signed = 0;
+        for index in 0..self.inputs.len() {
+            let input = &self.inputs[index];
+            if input.is_finalized() || input.non_witness_utxo.is_none() {
+                continue;
+            }
+
+            if let Some(sighash_type) = input.sighash_type {
+                if sighash_type != SIGHASH_ALL {
+                    return Err(Error::InvalidPsbt("unsupported sighash type"));
+                }
+            }
+
+            let script_pubkey = self.spent_output(index)?.script_pubkey.clone();
+            let hash = match script_pubkey.p2pkh_hash() {
+                Some(hash) => hash,
+                None => continue,
+            };
+
+            let sec = match candidates.iter().find(|sec| hash160(sec) == hash) {
+                Some(sec) => sec.clone(),
+                None => continue,
+            };
+
+            let digest = self.legacy_sighash_all(index, &script_pubkey)?;
+            let mut signature = key.create_signature(digest)?.serialize()?;
+            signature.push(SIGHASH_ALL as u8);
+
+            self.inputs[index].partial_sigs.insert(sec, signature);
+            signed += 1;
+        }
+
+        Ok(signed)
+    }
+
+    /// Finalizer role for P2PKH and P2WPKH inputs, builds the final script sig or
+    /// witness from the partial signatures and drops the data no longer needed
+    pub fn finalize(&mut self) -> Result<()> {
+        for index in 0..self.inputs.len() {
+            if self.inputs[index].is_finalized() {
+                continue;
+            }
+
+            let script_pubkey = self.spent_output(index)?.script_pubkey.clone();
+            let input = &mut self.inputs[index];
+
+            if let Some(hash) = script_pubkey.p2pkh_hash() {
+                let (key, signature) = signature_for(input, hash)?;
+                let mut script_sig = Vec::new();
+                push_slice(&mut script_sig, &signature);
+                push_slice(&mut script_sig, &key);
+                input.final_script_sig = Some(Script::from(script_sig));
+            } else if let Some(hash) = script_pubkey.p2wpkh_hash() {
+                let (key, signature) = signature_for(input, hash)?;
+                if key.len() != 33 {
+                    return Err(Error::UncompressedSegwitKey);
+                }
+
+                input.final_script_witness = Some(vec![signature, key]);
+            } else {
+                return Err(Error::InvalidPsbt("unsupported input script"));
+            }
+
+            input.partial_sigs.clear();
+            input.sighash_type = None;
+            input.redeem_script = None;
+            input.witness_script = None;
+            input.bip32_derivation.clear();
+        }
+
+        Ok(())
+    }
+
+    /// Extractor role, the network serialization of the fully signed transaction
+    pub fn extract_tx(&self) -> Result<Transaction> {
+        let mut tx = self.unsigned_tx.clone();
+        tx.locktime = self.locktime()?;
+        for (tx_input, input) in tx.inputs.iter_mut().zip(&self.inputs) {
+            if !input.is_finalized() {
+                return Err(Error::InvalidPsbt("input isn't finalized"));
+            }
+
+            tx_input.script_sig = input.final_script_sig.clone().unwrap_or_default();
+            tx_input.witness =
+                Witness::from(input.final_script_witness.clone().unwrap_or_default());
+        }
+
+        Ok(tx)
+    }
+
+    /// What the inputs spend minus what the outputs pay, every utxo must be known
+    pub fn fee(&self) -> Result<Amount> {
+        let mut spent = Amount::ZERO;
+        for index in 0..self.inputs.len() {
+            spent = spent
+                .checked_add(self.spent_output(index)?.amount)
+                .ok_or(Error::InvalidAmount("amount overflow"))?;
+        }
+
+        let mut paid = Amount::ZERO;
+        for output in &self.unsigned_tx.outputs {
+            paid = paid
+                .checked_add(output.amount)
+                .ok_or(Error::InvalidAmount("amount overflow"))?;
+        }
+
+        spent
+            .checked_sub(paid)
+            .ok_or(Error::InvalidPsbt("outputs pay more than the inputs"))
+    }
+
+    /// A PSBT of the replacement built by [`Transaction::bump_fee`], keeping the
+    /// utxos, scripts and derivations but none of the signatures
+    pub fn bump_fee(
+        &self,
+        input_types: &[InputType],
+        fee_rate: FeeRate,
+        change_index: usize,
+    ) -> Result<Self> {
+        let fee = self.fee()?;
+        let mut psbt = self.clone();
+        psbt.unsigned_tx = self
+            .unsigned_tx
+            .bump_fee(fee, input_types, fee_rate, change_index)?;
+
+        for input in psbt.inputs.iter_mut() {
+            input.partial_sigs.clear();
+            input.final_script_sig = None;
+            input.final_script_witness = None;
+        }
+
+        Ok(psbt)
+    }
+
+    /// Serialize to the binary format
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        consensus::serialize(self)
+    }
+
+    /// Parse the binary format
+    pub fn deserialize<B>(bytes: B) -> Result<Self>
+    where
+        B: AsRef<[u8]>,
+    {
+        consensus::deserialize(bytes.as_ref())
+    }
+
+    /// The input at `index` of the unsigned transaction
+    fn unsigned_input(&self, index: usize) -> Result<&TxIn> {
+        self.unsigned_tx
+            .inputs
+            .get(index)
+            .ok_or(Error::InvalidPsbt("input index out of range"))
+    }
+
+    /// The output spent by the input at `index`, from either utxo field
+    fn spent_output(&self, index: usize) -> Result<&TxOut> {
+        let prev_idx = self.unsigned_input(index)?.previous_output.vout() as usize;
+        let input = &self.inputs[index];
+
+        match (&input.non_witness_utxo, &input.witness_utxo) {
+            (Some(prev_tx), _) => Ok(&prev_tx.outputs[prev_idx]),
+            (None, Some(utxo)) => Ok(utxo),
+            (None, None) => Err(Error::InvalidPsbt("missing utxo")),
+        }
+    }
+
+    /// Pre-segwit signature hash of the input at `index` for `SIGHASH_ALL`
+    fn legacy_sighash_all(&self, index: usize, script_code: &Script) -> Result<[u8; 32]> {
+        let mut tx = self.unsigned_tx.clone();
+        tx.locktime = self.locktime()?;
+        tx.legacy_sighash(index, script_code, SIGHASH_ALL)
+    }
+}
+
+impl Encodable for Psbt {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        let tx = &self.unsigned_tx;
+        let mut pairs = Vec::new();
+
+        if self.version == 0 {
+            if self.fallback_locktime.is_some()
+                || self.tx_modifiable.is_some()
+                || self.inputs.iter().any(Input::has_v2_fields)
+            {
+                return Err(Error::InvalidPsbt("version 2 field in version 0 psbt"));
+            }
+
+            let value = consensus::serialize(tx)?;
+            pairs.push(Pair::new(GLOBAL_UNSIGNED_TX, vec![], value));
+        }
+
+        for (xpub, origin) in &self.xpubs {
+            let value = map::serialize_origin(origin);
+            pairs.push(Pair::new(GLOBAL_XPUB, xpub.clone(), value));
+        }
+
+        if self.version == 2 {
+            let value = tx.version.to_le_bytes().to_vec();
+            pairs.push(Pair::new(GLOBAL_TX_VERSION, vec![], value));
+
+            if let Some(locktime) = self.fallback_locktime {
+                let value = locktime.to_le_bytes().to_vec();
+                pairs.push(Pair::new(GLOBAL_FALLBACK_LOCKTIME, vec![], value));
+            }
+
+            let value = crate::varint::encode(tx.inputs.len() as u64);
+            pairs.push(Pair::new(GLOBAL_INPUT_COUNT, vec![], value));
+
+            let value = crate::varint::encode(tx.outputs.len() as u64);
+            pairs.push(Pair::new(GLOBAL_OUTPUT_COUNT, vec![], value));
+
+            if let Some(flags) = self.tx_modifiable {
+                pairs.push(Pair::new(GLOBAL_TX_MODIFIABLE, vec![], vec![flags]));
+            }
+        }
+
+        if self.version != 0 {
+            let value = self.version.to_le_bytes().to_vec();
+            pairs.push(Pair::new(GLOBAL_VERSION, vec![], value));
+        }
+
+        pairs.extend(unknown_pairs(&self.unknown)?);
+
+        let mut len = MAGIC.consensus_encode(writer)? + write_map(writer, &pairs)?;
+        for (tx_input, input) in tx.inputs.iter().zip(&self.inputs) {
+            let mut pairs = Vec::new();
+            if self.version == 2 {
+                let txid = tx_input.previous_output.txid().to_bytes().to_vec();
+                pairs.push(Pair::new(input_type::PREVIOUS_TXID, vec![], txid));
+
+                let value = tx_input.previous_output.vout().to_le_bytes().to_vec();
+                pairs.push(Pair::new(input_type::OUTPUT_INDEX, vec![], value));
+
+                if tx_input.sequence != DEFAULT_SEQUENCE {
+                    let value = tx_input.sequence.to_le_bytes().to_vec();
+                    pairs.push(Pair::new(input_type::SEQUENCE, vec![], value));
+                }
+            }
+
+            pairs.extend(input.to_pairs()?);
+            len += write_map(writer, &pairs)?;
+        }
+
+        for (tx_output, output) in tx.outputs.iter().zip(&self.outputs) {
+            let mut pairs = Vec::new();
+            if self.version == 2 {
+                let value = tx_output.amount.to_sat().to_le_bytes().to_vec();
+                pairs.push(Pair::new(output_type::AMOUNT, vec![], value));
+
+                let value = tx_output.script_pubkey.bytes.clone();
+                pairs.push(Pair::new(output_type::SCRIPT, vec![], value));
+            }
+
+            pairs.extend(output.to_pairs()?);
+            len += write_map(writer, &pairs)?;
+        }
+
+        Ok(len)
+    }
+}
+
+/// Key types of the inputs and outputs only valid in version 2
+fn is_v2_input_type(key_type: u64) -> bool {
+    (input_type::PREVIOUS_TXID..=input_type::REQUIRED_HEIGHT_LOCKTIME).contains(&key_type)
+}
+
+fn is_v2_output_type(key_type: u64) -> bool {
+    key_type == output_type::AMOUNT || key_type == output_type::SCRIPT
+}
+
+/// Fields of a version 2 global map that make up the unsigned transaction
+#[derive(Default)]
+struct TxFields {
+    version: Option<u32>,
+    input_count: Option<u64>,
+    output_count: Option<u64>,
+}
+
+impl Decodable for Psbt {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        if <[u8; 5]>::consensus_decode(reader)? != MAGIC {
+            return Err(Error::InvalidPsbt("invalid magic bytes"));
+        }
+
+        let mut unsigned_tx = None;
+        let mut version = 0;
+        let mut fields = TxFields::default();
+        let mut fallback_locktime = None;
+        let mut tx_modifiable = None;
+        let mut has_v2_globals = false;
+        let mut xpubs = BTreeMap::new();
+        let mut unknown = BTreeMap::new();
+
+        for pair in read_map(reader)? {
+            // only the xpub among the known global types has key data
+            let is_known = pair.key_type <= GLOBAL_TX_MODIFIABLE || pair.key_type == GLOBAL_VERSION;
+            if is_known && pair.key_type != GLOBAL_XPUB && !pair.key_data.is_empty() {
+                return Err(Error::InvalidPsbt("unexpected key data"));
+            }
+
+            has_v2_globals |= (GLOBAL_TX_VERSION..=GLOBAL_TX_MODIFIABLE).contains(&pair.key_type);
+            match pair.key_type {
+                GLOBAL_UNSIGNED_TX => {
+                    unsigned_tx = Some(consensus::deserialize::<Transaction>(&pair.value)?);
+                }
+
+                GLOBAL_XPUB if pair.key_data.len() == 78 => {
+                    ExtendedPublicKey::deserialize(&pair.key_data)?;
+                    xpubs.insert(pair.key_data, map::deserialize_origin(&pair.value)?);
+                }
+
+                GLOBAL_XPUB => return Err(Error::InvalidPsbt("unexpected key data")),
+                GLOBAL_TX_VERSION => fields.version = Some(consensus::deserialize(&pair.value)?),
+                GLOBAL_FALLBACK_LOCKTIME => {
+                    fallback_locktime = Some(consensus::deserialize(&pair.value)?);
+                }
+
+                GLOBAL_INPUT_COUNT => {
+                    fields.input_count = Some(consensus::read_compact_size(&mut &pair.value[..])?);
+                }
+
+                GLOBAL_OUTPUT_COUNT => {
+                    fields.output_count = Some(consensus::read_compact_size(&mut &pair.value[..])?);
+                }
+
+                GLOBAL_TX_MODIFIABLE => tx_modifiable = Some(consensus::deserialize(&pair.value)?),
+                GLOBAL_VERSION => version = consensus::deserialize(&pair.value)?,
+                _ => {
+                    unknown.insert(pair.key(), pair.value);
+                }
+            }
+        }
+
+        let mut psbt = match version {
+            0 if has_v2_globals => {
+                return Err(Error::InvalidPsbt("version 2 field in version 0 psbt"));
+            }
+
+            0 => {
+                let unsigned_tx = unsigned_tx.ok_or(Error::InvalidPsbt("missing unsigned tx"))?;
+                let mut psbt = Self::new(unsigned_tx)?;
+                for input in psbt.inputs.iter_mut() {
+                    let pairs = read_map(reader)?;
+                    if pairs.iter().any(|pair| is_v2_input_type(pair.key_type)) {
+                        return Err(Error::InvalidPsbt("version 2 field in version 0 psbt"));
+                    }
+
+                    *input = Input::from_pairs(pairs)?;
+                }
+
+                for output in psbt.outputs.iter_mut() {
+                    let pairs = read_map(reader)?;
+                    if pairs.iter().any(|pair| is_v2_output_type(pair.key_type)) {
+                        return Err(Error::InvalidPsbt("version 2 field in version 0 psbt"));
+                    }
+
+                    *output = Output::from_pairs(pairs)?;
+                }
+
+                psbt
+            }
+
+            2 if unsigned_tx.is_some() => {
+                return Err(Error::InvalidPsbt("unsigned tx in version 2 psbt"));
+            }
+
+            2 => {
+                let mut psbt = decode_v2_maps(reader, fields)?;
+                psbt.fallback_locktime = fallback_locktime;
+                psbt.tx_modifiable = tx_modifiable;
+                psbt.unsigned_tx.locktime = psbt.locktime()?;
+                psbt
+            }
+
+            _ => return Err(Error::InvalidPsbt("unsupported version")),
+        };
+
+        psbt.xpubs = xpubs;
+        psbt.unknown = unknown;
+        Ok(psbt)
+    }
+}
+
+/// Build the unsigned transaction back from the version 2 input and output maps
+fn decode_v2_maps<R: Read + ?Sized>(reader: &mut R, fields: TxFields) -> Result<Psbt> {
+    let missing = Error::InvalidPsbt("missing version 2 global field");
+    let (tx_version, input_count, output_count) =
+        match (fields.version, fields.input_count, fields.output_count) {
+            (Some(version), Some(inputs), Some(outputs)) => (version, inputs, outputs),
+            _ => return Err(missing),
+        };
+
+    if tx_version < 2 {
+        return Err(Error::InvalidPsbt("version 2 requires tx version 2"));
+    }
+
+    if input_count > consensus::MAX_VEC_SIZE || output_count > consensus::MAX_VEC_SIZE {
+        return Err(Error::OversizedVector(input_count.max(output_count)));
+    }
+
+    let mut tx_inputs = Vec::new();
+    let mut inputs = Vec::new();
+    for _ in 0..input_count {
+        let (mut txid, mut prev_idx, mut sequence) = (None, None, DEFAULT_SEQUENCE);
+        let mut rest = Vec::new();
+        for pair in read_map(reader)? {
+            match pair.key_type {
+                input_type::PREVIOUS_TXID | input_type::OUTPUT_INDEX | input_type::SEQUENCE
+                    if !pair.key_data.is_empty() =>
+                {
+                    return Err(Error::InvalidPsbt("unexpected key data"));
+                }
+
+                input_type::PREVIOUS_TXID => {
+                    txid = Some(consensus::deserialize::<Txid>(&pair.value)?);
+                }
+
+                input_type::OUTPUT_INDEX => prev_idx = Some(consensus::deserialize(&pair.value)?),
+                input_type::SEQUENCE => sequence = consensus::deserialize(&pair.value)?,
+                _ => rest.push(pair),
+            }
+        }
+
+        let (txid, prev_idx) = match (txid, prev_idx) {
+            (Some(txid), Some(prev_idx)) => (txid, prev_idx),
+            _ => return Err(Error::InvalidPsbt("missing previous output")),
+        };
+
+        let mut tx_input = TxIn::new(OutPoint::new(txid, prev_idx));
+        tx_input.sequence = sequence;
+        tx_inputs.push(tx_input);
+        inputs.push(Input::from_pairs(rest)?);
+    }
+
+    let mut tx_outputs = Vec::new();
+    let mut outputs = Vec::new();
+    for _ in 0..output_count {
+        let (mut amount, mut script) = (None, None);
+        let mut rest = Vec::new();
+        for pair in read_map(reader)? {
+            match pair.key_type {
+                output_type::AMOUNT | output_type::SCRIPT if !pair.key_data.is_empty() => {
+                    return Err(Error::InvalidPsbt("unexpected key data"));
+                }
+
+                output_type::AMOUNT => {
+                    amount = Some(consensus::deserialize::<Amount>(&pair.value)?)
+                }
+                output_type::SCRIPT => script = Some(Script::from(pair.value)),
+                _ => rest.push(pair),
+            }
+        }
+
+        let (amount, script) = match (amount, script) {
+            (Some(amount), Some(script)) => (amount, script),
+            _ => return Err(Error::InvalidPsbt("missing output amount or script")),
+        };
+
+        tx_outputs.push(TxOut::new(amount, script));
+        outputs.push(Output::from_pairs(rest)?);
+    }
+
+    let unsigned_tx = Transaction::new(tx_version, tx_inputs, tx_outputs, 0);
+    let mut psbt = Psbt::new(unsigned_tx)?;
+    psbt.version = 2;
+    psbt.inputs = inputs;
+    psbt.outputs = outputs;
+    Ok(psbt)
+}
+
+/// Base64 encoding of the binary format
+impl Display for Psbt {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        let bytes = self.serialize().map_err(|_| fmt::Error)?;
+        write!(fmt, "{}", base64::encode(&bytes))
+    }
+}
+
+impl FromStr for Psbt {
+    type Err = Error;
+
+    fn from_str(string: &str) -> Result<Self> {
+        Self::deserialize(base64::decode(string)?)
+    }
+}
+
+/// The partial signature made by the key hashing to `hash`
+fn signature_for(input: &Input, hash: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+    input
+        .partial_sigs
+        .iter()
+        .find(|(key, _)| hash160(key) == hash)
+        .map(|(key, signature)| (key.clone(), signature.clone()))
+        .ok_or(Error::InvalidPsbt("missing signature"))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use hex_literal::hex;
+
+    use crate::secp256k1::signature::Signature;
+
+    use super::*;
+
+    fn p2pkh_script(key: &PublicKey) -> Result<Script> {
+        let mut script = vec![0x76, 0xa9, 0x14];
+        script.extend(hash160(key.serialize_compressed()?));
+        script.extend(&[0x88, 0xac]);
+        Ok(Script::from(script))
+    }
+
+    /// A transaction paying to `key` and one spending its first output
+    fn spend(key: &PublicKey) -> Result<(Transaction, Transaction)> {
+        let funding_input = TxIn::new(OutPoint::new(Txid::from_bytes([0x11; 32]), 0));
+        let funding_output = TxOut::new(Amount::from_sat(50_000), p2pkh_script(key)?);
+        let funding = Transaction::new(1, vec![funding_input], vec![funding_output], 0);
+
+        let input = TxIn::new(OutPoint::new(funding.txid()?, 0));
+        let output = TxOut::new(
+            Amount::from_sat(40_000),
+            Script::from(vec![0x00, 0x14, 0x22, 0x22]),
+        );
+        let spending = Transaction::new(2, vec![input], vec![output], 0);
+        Ok((funding, spending))
+    }
+
+    #[test]
+    fn sign_finalize_and_extract() -> Result<()> {
+        let key = PrivateKey::new(12345u32);
+        let public_key = key.public_key();
+        let (funding, spending) = spend(public_key)?;
+
+        let mut psbt = Psbt::new(spending)?;
+        psbt.add_non_witness_utxo(0, funding)?;
+        let origin: KeyOrigin = "d34db33f/44'/0'/0'/0/0".parse()?;
+        psbt.add_input_derivation(0, public_key, origin.clone())?;
+
+        assert_eq!(psbt.sign(&PrivateKey::new(1u32))?, 0);
+        assert_eq!(psbt.sign(&key)?, 1);
+
+        let sec = public_key.serialize_compressed()?.to_vec();
+        let signature = psbt.inputs()[0].partial_sigs[&sec].clone();
+        assert_eq!(signature.last(), Some(&(SIGHASH_ALL as u8)));
+
+        let script_code = psbt.spent_output(0)?.script_pubkey.clone();
+        let digest = psbt.legacy_sighash_all(0, &script_code)?;
+        let der = Signature::deserialize(&signature[..signature.len() - 1])?;
+        assert!(der.is_valid(digest, public_key)?);
+
+        // roundtrip through the text format before finalizing
+        let mut psbt: Psbt = psbt.to_string().parse()?;
+        assert_eq!(psbt.inputs()[0].bip32_derivation[&sec], origin);
+
+        psbt.finalize()?;
+        let input = &psbt.inputs()[0];
+        assert!(input.partial_sigs.is_empty() && input.bip32_derivation.is_empty());
+
+        let mut script_sig = vec![signature.len() as u8];
+        script_sig.extend(&signature);
+        script_sig.push(33);
+        script_sig.extend(&sec);
+
+        let tx = psbt.extract_tx()?;
+        assert_eq!(tx.inputs[0].script_sig.bytes, script_sig);
+        Ok(())
+    }
+
+    #[test]
+    fn bump_fee() -> Result<()> {
+        let key = PrivateKey::new(12345u32);
+        let (funding, mut spending) = spend(key.public_key())?;
+
+        let psbt = Psbt::new(spending.clone())?;
+        assert!(psbt.fee().is_err());
+
+        spending.signal_rbf();
+        let mut psbt = Psbt::new(spending)?;
+        psbt.add_non_witness_utxo(0, funding)?;
+        assert_eq!(psbt.fee()?, Amount::from_sat(10_000));
+
+        let mut overpaying = psbt.clone();
+        let output = TxOut::new(Amount::from_sat(u64::MAX), Script::new());
+        overpaying.unsigned_tx.outputs = vec![output.clone(), output];
+        assert!(matches!(overpaying.fee(), Err(Error::InvalidAmount(_))));
+
+        assert_eq!(psbt.sign(&key)?, 1);
+
+        let rate = FeeRate::from_sat_per_vb(100);
+        let bumped = psbt.bump_fee(&[InputType::P2pkh], rate, 0)?;
+        assert!(bumped.inputs()[0].partial_sigs.is_empty());
+        assert!(bumped.inputs()[0].non_witness_utxo.is_some());
+
+        let vsize = bumped.unsigned_tx().estimated_vsize(&[InputType::P2pkh])?;
+        assert_eq!(bumped.fee()?, rate.fee_for_vsize(vsize));
+        Ok(())
+    }
+
+    #[test]
+    fn serialization_roundtrip() -> Result<()> {
+        let key = PrivateKey::new(12345u32);
+        let (funding, spending) = spend(key.public_key())?;
+        let spent = funding.outputs[0].clone();
+
+        let mut psbt = Psbt::new(spending)?;
+        psbt.add_witness_utxo(0, spent)?;
+        psbt.inputs_mut()[0]
+            .unknown
+            .insert(vec![0xf0, 0x01], vec![0xaa, 0xbb]);
+        psbt.outputs_mut()[0].redeem_script = Some(Script::from(vec![0x51]));
+
+        let bytes = psbt.serialize()?;
+        assert_eq!(bytes[..5], MAGIC);
+        assert_eq!(Psbt::deserialize(&bytes)?.serialize()?, bytes);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_invalid_psbts() -> Result<()> {
+        let key = PrivateKey::new(12345u32);
+        let (funding, spending) = spend(key.public_key())?;
+        let bytes = Psbt::new(spending.clone())?.serialize()?;
+
+        // raw transaction without the magic
+        assert!(Psbt::deserialize(spending.serialize()?).is_err());
+        // missing the output map
+        assert!(Psbt::deserialize(&bytes[..bytes.len() - 1]).is_err());
+        // duplicated unsigned tx
+        let tx = spending.serialize()?;
+        let mut duplicated = MAGIC.to_vec();
+        for _ in 0..2 {
+            Pair::new(GLOBAL_UNSIGNED_TX, vec![], tx.clone()).write(&mut duplicated)?;
+        }
+        duplicated.extend(&hex!("000000"));
+        assert!(Psbt::deserialize(&duplicated).is_err());
+
+        // the utxo must be the spent transaction
+        let mut psbt = Psbt::new(spending)?;
+        assert!(psbt
+            .add_non_witness_utxo(0, psbt.unsigned_tx().clone())
+            .is_err());
+        assert!(psbt.add_non_witness_utxo(1, funding).is_err());
+        assert!(psbt.extract_tx().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn version_2_roundtrip() -> Result<()> {
+        let key = PrivateKey::new(12345u32);
+        let (funding, mut spending) = spend(key.public_key())?;
+        spending.inputs[0].sequence = 0xfffffffd;
+        spending.locktime = 800_000;
+
+        let mut v0 = Psbt::new(spending.clone())?;
+        v0.add_non_witness_utxo(0, funding)?;
+        v0.outputs_mut()[0].redeem_script = Some(Script::from(vec![0x51]));
+        let v0_bytes = v0.serialize()?;
+
+        let v2 = v0.to_v2()?;
+        let v2_bytes = v2.serialize()?;
+        assert_ne!(v2_bytes, v0_bytes);
+
+        let parsed = Psbt::deserialize(&v2_bytes)?;
+        assert_eq!(parsed.version(), 2);
+        assert_eq!(parsed.locktime()?, 800_000);
+        assert_eq!(parsed.unsigned_tx().serialize()?, spending.serialize()?);
+        assert_eq!(parsed.serialize()?, v2_bytes);
+        assert_eq!(parsed.to_v0()?.serialize()?, v0_bytes);
+
+        // version 1 transactions can't be expressed in version 2
+        spending.version = 1;
+        assert!(Psbt::new(spending)?.to_v2().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn version_2_locktime_negotiation() -> Result<()> {
+        let key = PrivateKey::new(12345u32);
+        let (_, mut spending) = spend(key.public_key())?;
+        let second = spending.inputs[0].clone();
+        spending.inputs.push(second);
+
+        let mut psbt = Psbt::new(spending)?.to_v2()?;
+        assert_eq!(psbt.locktime()?, 0);
+        psbt.fallback_locktime = Some(1234);
+        assert_eq!(psbt.locktime()?, 1234);
+
+        // the fallback is ignored once an input has a requirement
+        psbt.inputs_mut()[0].required_height_locktime = Some(10_000);
+        assert_eq!(psbt.locktime()?, 10_000);
+
+        // heights are preferred when every input allows both
+        psbt.inputs_mut()[0].required_time_locktime = Some(1_600_000_000);
+        psbt.inputs_mut()[1].required_time_locktime = Some(1_700_000_000);
+        assert_eq!(psbt.locktime()?, 1_700_000_000);
+        psbt.inputs_mut()[1].required_height_locktime = Some(20_000);
+        assert_eq!(psbt.locktime()?, 20_000);
+
+        // one input requires a time and the other a height
+        psbt.inputs_mut()[0].required_height_locktime = None;
+        psbt.inputs_mut()[1].required_time_locktime = None;
+        assert!(psbt.locktime().is_err());
+        assert!(psbt.extract_tx().is_err());
+
+        // the requirements can't be expressed in version 0
+        psbt.inputs_mut()[1].required_height_locktime = None;
+        let bytes = psbt.serialize()?;
+        assert_eq!(Psbt::deserialize(&bytes)?.locktime()?, 1_600_000_000);
+        assert!(psbt.to_v0().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_mixed_versions() -> Result<()> {
+        let key = PrivateKey::new(12345u32);
+        let (_, spending) = spend(key.public_key())?;
+        let tx = spending.serialize()?;
+
+        // unsigned tx along with version 2
+        let mut bytes = MAGIC.to_vec();
+        Pair::new(GLOBAL_UNSIGNED_TX, vec![], tx.clone()).write(&mut bytes)?;
+        Pair::new(GLOBAL_VERSION, vec![], 2u32.to_le_bytes().to_vec()).write(&mut bytes)?;
+        bytes.extend(&hex!("000000"));
+        assert!(Psbt::deserialize(&bytes).is_err());
+
+        // version 2 global in a version 0 psbt
+        let mut bytes = MAGIC.to_vec();
+        Pair::new(GLOBAL_UNSIGNED_TX, vec![], tx).write(&mut bytes)?;
+        Pair::new(GLOBAL_FALLBACK_LOCKTIME, vec![], vec![0; 4]).write(&mut bytes)?;
+        bytes.extend(&hex!("000000"));
+        assert!(Psbt::deserialize(&bytes).is_err());
+
+        // version 2 input field in a version 0 psbt
+        let mut psbt = Psbt::new(spending)?;
+        psbt.inputs_mut()[0].required_height_locktime = Some(100);
+        assert!(psbt.serialize().is_err());
+
+        // version 2 without the transaction fields
+        let mut bytes = MAGIC.to_vec();
+        Pair::new(GLOBAL_VERSION, vec![], 2u32.to_le_bytes().to_vec()).write(&mut bytes)?;
+        bytes.push(0x00);
+        assert!(Psbt::deserialize(&bytes).is_err());
+        Ok(())
+    }
+}
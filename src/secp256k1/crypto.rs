@@ -1,24 +1,44 @@
-use hmac::{Hmac, Mac, NewMac};
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::convert::{TryFrom, TryInto};
+use core::fmt::{self, Display, Formatter, LowerHex};
+use core::str::FromStr;
+
 use num_bigint::BigUint;
-use num_traits::One;
-use sha2::Sha256;
+use num_traits::Zero;
+use once_cell::race::OnceBox;
+use subtle::{Choice, ConstantTimeEq};
 
-use crate::utils::{hash160, prepend_padding, Chain};
-use crate::{base58, Error, Result};
+use crate::address::Address;
+use crate::curve::CurveParams;
+use crate::network::Network;
+use crate::{base58, ecdsa, Error, Result};
 
 use super::curve::Point;
 use super::field::FieldElement;
+use super::scalar::Scalar;
 use super::signature::Signature;
-use super::{G, N};
+use super::{mul_g, Secp256k1};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PublicKey {
     pub(crate) ec_point: Point,
 }
 
-impl From<Point> for PublicKey {
-    fn from(ec_point: Point) -> Self {
-        Self { ec_point }
+impl TryFrom<Point> for PublicKey {
+    type Error = Error;
+
+    fn try_from(ec_point: Point) -> Result<Self> {
+        if ec_point.is_point_at_inf() {
+            return Err(Error::InvalidPublicKey("point at infinity"));
+        }
+
+        if !ec_point.is_on_curve() {
+            return Err(Error::PointNotOnTheCurve);
+        }
+
+        Ok(Self { ec_point })
     }
 }
 
@@ -52,11 +72,24 @@ impl PublicKey {
         Self::new(x, y)
     }
 
+    /// Public key with the given x coordinate (big endian) and y parity
+    pub fn from_x(x: &[u8; 32], odd: bool) -> Result<Self> {
+        let x = FieldElement::from_canonical_bytes(*x).ok_or(Error::PointNotOnTheCurve)?;
+        let ec_point = Point::lift_x(x, odd)?;
+        Ok(Self { ec_point })
+    }
+
+    /// Whether this key is a finite point on the curve, always true for keys
+    /// built through the checked constructors
+    pub fn is_on_curve(&self) -> bool {
+        self.ec_point.is_on_curve()
+    }
+
     pub fn valid_signature<B>(&self, digest: B, signature: &Signature) -> Result<bool>
     where
         B: AsRef<[u8]>,
     {
-        signature.is_valid(digest, &self)
+        signature.is_valid(digest, self)
     }
 
     /// Serialize this public key using the SEC format
@@ -64,6 +97,16 @@ impl PublicKey {
         self.ec_point.serialize(compressed)
     }
 
+    /// Serialize this public key using the compressed SEC format
+    pub fn serialize_compressed(&self) -> Result<[u8; 33]> {
+        self.ec_point.serialize_compressed()
+    }
+
+    /// Serialize this public key using the uncompressed SEC format
+    pub fn serialize_uncompressed(&self) -> Result<[u8; 65]> {
+        self.ec_point.serialize_uncompressed()
+    }
+
     /// Deserialize the given bytes using the SEC format
     pub fn deserialize<B>(bytes: B) -> Result<Self>
     where
@@ -73,20 +116,34 @@ impl PublicKey {
         Ok(Self { ec_point })
     }
 
-    /// Create the address
-    pub fn create_address(&self, compressed: bool, testnet: bool) -> Result<String> {
-        let serialized = self.serialize(compressed)?;
-        let digest = hash160(serialized);
-        let prefix = if testnet { 0x6f } else { 0x00 };
-        let data: Vec<_> = std::iter::once(prefix).chain(digest).collect();
-        Ok(base58::encode_checksum(data))
+    /// Deserialize the given bytes using the compressed SEC format
+    pub fn from_sec_compressed(bytes: &[u8; 33]) -> Result<Self> {
+        let ec_point = Point::from_sec_compressed(bytes)?;
+        Ok(Self { ec_point })
+    }
+
+    /// Deserialize the given bytes using the uncompressed SEC format
+    pub fn from_sec_uncompressed(bytes: &[u8; 65]) -> Result<Self> {
+        let ec_point = Point::from_sec_uncompressed(bytes)?;
+        Ok(Self { ec_point })
+    }
+
+    /// Create the (P2PKH) address
+    pub fn create_address(&self, compressed: bool, network: Network) -> Result<Address> {
+        Address::p2pkh(self, compressed, network)
+    }
+
+    /// Create the native segwit (P2WPKH) address, always from the compressed key
+    pub fn p2wpkh_address(&self, network: Network) -> Result<Address> {
+        Address::p2wpkh(self, network)
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone)]
 pub struct PrivateKey {
-    pub(crate) secret: BigUint,
-    pub(crate) pub_key: PublicKey,
+    pub(crate) secret: Scalar,
+    /// derived on first use, signing and WIF export don't need it
+    pub(crate) pub_key: OnceBox<PublicKey>,
 }
 
 impl PrivateKey {
@@ -94,9 +151,8 @@ impl PrivateKey {
     where
         U: Into<BigUint>,
     {
-        let secret = secret.into();
-        let ec_point = &*G * secret.clone();
-        let pub_key = PublicKey { ec_point };
+        let secret = Scalar::new(secret);
+        let pub_key = OnceBox::new();
 
         Self { secret, pub_key }
     }
@@ -118,7 +174,14 @@ impl PrivateKey {
     }
 
     pub fn public_key(&self) -> &PublicKey {
-        &self.pub_key
+        self.pub_key.get_or_init(|| {
+            let ec_point = mul_g(&self.secret);
+            Box::new(PublicKey { ec_point })
+        })
+    }
+
+    pub fn secret(&self) -> &Scalar {
+        &self.secret
     }
 
     pub fn create_signature<B>(&self, digest: B) -> Result<Signature>
@@ -126,87 +189,73 @@ impl PrivateKey {
         B: AsRef<[u8]>,
     {
         let digest = digest.as_ref();
-        if digest.len() != 32 {
-            return Err(Error::InvalidDigestLength(digest.len()));
-        }
+        let digest: [u8; 32] = digest
+            .try_into()
+            .map_err(|_| Error::InvalidDigestLength(digest.len()))?;
 
         let k = self.deterministic_k(digest)?;
-        let r = (&*G * k.clone()).x().unwrap().0.clone();
+        let r = Scalar::from_bytes_be(mul_g(&k).x().unwrap().to_bytes_be());
 
-        let k_inv = k.modpow(&(&*N - 2usize), &*N);
-        let z = BigUint::from_bytes_be(digest);
-        let mut s = (z + &r * &self.secret) * k_inv % &*N;
-        if s > &*N / 2usize {
-            s = &*N - s;
+        let z = Scalar::from_bytes_be(digest);
+        let mut s = (z + &r * &self.secret) * k.invert();
+        if s.is_high() {
+            s = s.negate();
         }
 
         Ok(Signature::new(r, s))
     }
 
-    fn deterministic_k<B>(&self, digest: B) -> Result<BigUint>
+    fn deterministic_k<B>(&self, digest: B) -> Result<Scalar>
     where
         B: AsRef<[u8]>,
     {
-        type HmacSha256 = Hmac<Sha256>;
+        debug_assert!(digest.as_ref().len() == 32);
 
-        let digest = digest.as_ref();
-        debug_assert!(digest.len() == 32);
+        let k = ecdsa::deterministic_k(&self.secret.0, digest.as_ref(), Secp256k1::order(), 0);
+        Ok(Scalar::new(k))
+    }
 
-        let mut z = BigUint::from_bytes_be(digest);
-        let k = [0x00u8; 32];
-        let v = [0x01u8; 32];
+    /// Import a WIF encoded private key, along with the flags it was encoded with
+    pub fn from_wif<S>(wif: S) -> Result<(Self, WifMetadata)>
+    where
+        S: AsRef<str>,
+    {
+        let data = base58::decode_checksum(wif)?;
+        Self::from_wif_payload(&data)
+    }
 
-        if z > *N {
-            z -= &*N;
+    /// Parse the already base58check decoded WIF bytes
+    pub(crate) fn from_wif_payload(data: &[u8]) -> Result<(Self, WifMetadata)> {
+        let network = data
+            .first()
+            .and_then(|prefix| Network::from_wif_prefix(*prefix))
+            .ok_or(Error::InvalidWif("unknown prefix"))?;
+
+        let compressed = match data.len() {
+            33 => false,
+            34 if data[33] == 0x01 => true,
+            34 => return Err(Error::InvalidWif("invalid compression flag")),
+            _ => return Err(Error::InvalidWif("invalid length")),
+        };
+
+        let secret = data[1..33].try_into().unwrap(); // safe, 32 bytes
+        let secret = Scalar::from_canonical_bytes(secret).ok_or(Error::InvalidPrivateKey)?;
+        if secret.is_zero() {
+            return Err(Error::InvalidPrivateKey);
         }
 
-        let secret_bytes = prepend_padding(self.secret.to_bytes_be(), 32, 0)?;
-
-        let hmac = HmacSha256::new_varkey(&k).unwrap();
-        let k = hmac
-            .chain(&v)
-            .chain(&[0x00])
-            .chain(&secret_bytes)
-            .chain(digest)
-            .finalize()
-            .into_bytes();
-
-        let hmac = HmacSha256::new_varkey(&k).unwrap();
-        let v = hmac.chain(&v).finalize().into_bytes();
-
-        let hmac = HmacSha256::new_varkey(&k).unwrap();
-        let mut k = hmac
-            .chain(&v)
-            .chain(&[0x01])
-            .chain(&secret_bytes)
-            .chain(digest)
-            .finalize()
-            .into_bytes();
-
-        let hmac = HmacSha256::new_varkey(&k).unwrap();
-        let mut v = hmac.chain(&v).finalize().into_bytes();
-
-        let one = BigUint::one();
-        loop {
-            let hmac = HmacSha256::new_varkey(&k).unwrap();
-            v = hmac.chain(&v).finalize().into_bytes();
-
-            let candidate = BigUint::from_bytes_be(&v);
-            if candidate >= one && candidate < *N {
-                return Ok(candidate);
-            }
+        let metadata = WifMetadata {
+            compressed,
+            network,
+        };
 
-            let hmac = HmacSha256::new_varkey(&k).unwrap();
-            k = hmac.chain(&v).chain(&[0x00]).finalize().into_bytes();
-            let hmac = HmacSha256::new_varkey(&k).unwrap();
-            v = hmac.chain(&v).finalize().into_bytes();
-        }
+        Ok((Self::new(secret), metadata))
     }
 
-    pub fn create_wif(&self, compressed: bool, testnet: bool) -> Result<String> {
-        let secret_bytes = prepend_padding(self.secret.to_bytes_be(), 32, 0)?;
-        let prefix = if testnet { 0xef } else { 0x80 };
-        let mut data: Vec<_> = std::iter::once(prefix).chain(secret_bytes).collect();
+    pub fn create_wif(&self, compressed: bool, network: Network) -> Result<String> {
+        let secret_bytes = self.secret.to_bytes_be().to_vec();
+        let prefix = network.wif_prefix();
+        let mut data: Vec<_> = core::iter::once(prefix).chain(secret_bytes).collect();
         if compressed {
             data.push(0x01)
         }
@@ -214,3 +263,203 @@ impl PrivateKey {
         Ok(base58::encode_checksum(data))
     }
 }
+
+/// Compressed SEC format in hex
+impl LowerHex for PublicKey {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        let serialized = self.serialize_compressed().map_err(|_| fmt::Error)?;
+        write!(fmt, "{}", hex::encode(serialized))
+    }
+}
+
+impl Display for PublicKey {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{:x}", self)
+    }
+}
+
+/// Either compressed or uncompressed SEC format in hex
+impl FromStr for PublicKey {
+    type Err = Error;
+
+    fn from_str(string: &str) -> Result<Self> {
+        let bytes = hex::decode(string).map_err(|_| Error::InvalidHex)?;
+        Self::deserialize(bytes)
+    }
+}
+
+#[cfg(feature = "serde")]
+serde_impl!(
+    PublicKey,
+    "a SEC encoded public key",
+    PublicKey::serialize_compressed,
+    PublicKey::deserialize::<&[u8]>
+);
+
+/// Big endian secret in hex
+impl LowerHex for PrivateKey {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{}", hex::encode(self.secret.to_bytes_be()))
+    }
+}
+
+impl Display for PrivateKey {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{:x}", self)
+    }
+}
+
+/// 32 bytes big endian secret in hex, must be in the range [1, n)
+impl FromStr for PrivateKey {
+    type Err = Error;
+
+    fn from_str(string: &str) -> Result<Self> {
+        let mut bytes = [0u8; 32];
+        hex::decode_to_slice(string, &mut bytes).map_err(|_| Error::InvalidHex)?;
+
+        match Scalar::from_canonical_bytes(bytes) {
+            Some(secret) if !secret.is_zero() => Ok(Self::new(secret)),
+            _ => Err(Error::InvalidPrivateKey),
+        }
+    }
+}
+
+/// Flags stored alongside a WIF encoded private key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WifMetadata {
+    pub compressed: bool,
+    /// test networks share the WIF prefix, these are imported as testnet
+    pub network: Network,
+}
+
+impl ConstantTimeEq for PrivateKey {
+    fn ct_eq(&self, other: &Self) -> Choice {
+        // the public key is fully determined by the secret
+        self.secret.ct_eq(&other.secret)
+    }
+}
+
+impl PartialEq for PrivateKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.ct_eq(other).into()
+    }
+}
+
+impl Eq for PrivateKey {}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn public_key_is_derived_lazily() {
+        let private_key = PrivateKey::new(5001usize);
+        private_key.create_wif(true, Network::Mainnet).unwrap();
+        private_key.create_signature([0x01; 32]).unwrap();
+        assert!(private_key.pub_key.get().is_none());
+
+        let public_key = private_key.public_key().clone();
+        assert_eq!(private_key.pub_key.get(), Some(&public_key));
+        assert_eq!(public_key.ec_point, mul_g(&Scalar::new(5001usize)));
+
+        // clones keep the cached key
+        assert_eq!(private_key.clone().pub_key.get(), Some(&public_key));
+    }
+
+    #[test]
+    fn hex_formatting() {
+        let private_key: PrivateKey =
+            "0c28fca386c7a227600b2fe50b7cae11ec86d3bf1fbe471be89827e19d72aa1d"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            private_key.to_string(),
+            "0c28fca386c7a227600b2fe50b7cae11ec86d3bf1fbe471be89827e19d72aa1d"
+        );
+        assert_eq!(
+            private_key.to_string().parse::<PrivateKey>().unwrap(),
+            private_key
+        );
+
+        let public_key = PrivateKey::new(1usize).public_key().clone();
+        let expected = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+        assert_eq!(public_key.to_string(), expected);
+        assert_eq!(format!("{:x}", public_key), expected);
+        assert_eq!(expected.parse::<PublicKey>().unwrap(), public_key);
+
+        let uncompressed = hex::encode(public_key.serialize_uncompressed().unwrap());
+        assert_eq!(uncompressed.parse::<PublicKey>().unwrap(), public_key);
+
+        assert!(matches!("zz".parse::<PublicKey>(), Err(Error::InvalidHex)));
+        assert!(matches!(
+            "0c28".parse::<PrivateKey>(),
+            Err(Error::InvalidHex)
+        ));
+        assert!(matches!(
+            "0000000000000000000000000000000000000000000000000000000000000000"
+                .parse::<PrivateKey>(),
+            Err(Error::InvalidPrivateKey)
+        ));
+        assert!(matches!(
+            "fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141"
+                .parse::<PrivateKey>(),
+            Err(Error::InvalidPrivateKey)
+        ));
+    }
+
+    #[test]
+    fn wif_import() {
+        let wif = "5HueCGU8rMjxEXxiPuD5BDku4MkFqeZyd4dZ1jvhTVqvbTLvyTJ";
+        let (private_key, metadata) = PrivateKey::from_wif(wif).unwrap();
+        assert_eq!(
+            private_key.secret().to_bytes_be(),
+            hex_literal::hex!("0c28fca386c7a227600b2fe50b7cae11ec86d3bf1fbe471be89827e19d72aa1d")
+        );
+        assert!(!metadata.compressed);
+        assert_eq!(metadata.network, Network::Mainnet);
+
+        for &compressed in &[false, true] {
+            for &network in &[Network::Mainnet, Network::Testnet] {
+                let wif = private_key.create_wif(compressed, network).unwrap();
+                let (imported, metadata) = PrivateKey::from_wif(wif).unwrap();
+                assert_eq!(imported, private_key);
+                assert_eq!(
+                    metadata,
+                    WifMetadata {
+                        compressed,
+                        network
+                    }
+                );
+            }
+        }
+
+        // test networks share the prefix
+        let wif = private_key.create_wif(true, Network::Signet).unwrap();
+        let (_, metadata) = PrivateKey::from_wif(wif).unwrap();
+        assert_eq!(metadata.network, Network::Testnet);
+
+        let mut data = [0u8; 34];
+        data[0] = 0x80;
+        data[33] = 0x01;
+        let zero = base58::encode_checksum(data);
+        assert!(matches!(
+            PrivateKey::from_wif(zero),
+            Err(Error::InvalidPrivateKey)
+        ));
+
+        data[0] = 0x00;
+        let prefix = base58::encode_checksum(data);
+        assert!(matches!(
+            PrivateKey::from_wif(prefix),
+            Err(Error::InvalidWif(_))
+        ));
+
+        data[0] = 0x80;
+        data[33] = 0x02;
+        let flag = base58::encode_checksum(data);
+        assert!(matches!(
+            PrivateKey::from_wif(flag),
+            Err(Error::InvalidWif(_))
+        ));
+    }
+}
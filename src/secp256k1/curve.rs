@@ -1,15 +1,15 @@
-use std::ops::{Add, Mul};
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use core::ops::{Add, Mul};
 
 use lazy_static::lazy_static;
 use num_bigint::BigUint;
-use num_integer::Integer;
-use num_traits::{One, Pow, Zero};
+use num_traits::{Pow, Zero};
 
-use crate::utils::prepend_padding;
 use crate::{Error, Result};
 
 use super::field::FieldElement;
-use super::field::PRIME;
+use super::jacobian::JacobianPoint;
 
 lazy_static! {
     pub(crate) static ref B: FieldElement = FieldElement::new(7usize);
@@ -29,7 +29,7 @@ impl EllipticCurve {
     }
 
     pub fn contains(&self, x: &FieldElement, y: &FieldElement) -> bool {
-        y.pow(2usize) == x.pow(3usize) + &self.a * x + &self.b
+        y.pow(2usize) == x.pow(3usize) + self.a * *x + self.b
     }
 }
 
@@ -54,14 +54,14 @@ impl Point {
 
     pub fn x(&self) -> Option<&FieldElement> {
         match self {
-            Point::AtInfinity { .. } => None,
+            Point::AtInfinity => None,
             Point::Normal(x, _) => Some(x),
         }
     }
 
     pub fn y(&self) -> Option<&FieldElement> {
         match self {
-            Point::AtInfinity { .. } => None,
+            Point::AtInfinity => None,
             Point::Normal(_, y) => Some(y),
         }
     }
@@ -72,26 +72,36 @@ impl Point {
 
     /// Serialize the given point with the SEC format
     pub fn serialize(&self, compressed: bool) -> Result<Vec<u8>> {
+        if compressed {
+            self.serialize_compressed().map(Vec::from)
+        } else {
+            self.serialize_uncompressed().map(Vec::from)
+        }
+    }
+
+    /// Serialize the given point with the compressed SEC format
+    pub fn serialize_compressed(&self) -> Result<[u8; 33]> {
         match self {
             Self::Normal(x, y) => {
-                if compressed {
-                    let x_bigendian = prepend_padding(x.0.to_bytes_be(), 32, 0u8)?;
-                    let y_evenness = if y.0.is_even() { 0x02 } else { 0x03 };
-
-                    let serialized: Vec<_> =
-                        std::iter::once(y_evenness).chain(x_bigendian).collect();
-
-                    Ok(serialized)
-                } else {
-                    let x_bigendian = prepend_padding(x.0.to_bytes_be(), 32, 0u8)?;
-                    let y_bigendian = prepend_padding(y.0.to_bytes_be(), 32, 0u8)?;
-                    let serialized: Vec<_> = std::iter::once(0x04u8)
-                        .chain(x_bigendian)
-                        .chain(y_bigendian)
-                        .collect();
-
-                    Ok(serialized)
-                }
+                let mut serialized = [0u8; 33];
+                serialized[0] = if y.is_even() { 0x02 } else { 0x03 };
+                serialized[1..].copy_from_slice(&x.to_bytes_be());
+                Ok(serialized)
+            }
+
+            _ => Err(Error::SerializePointAtInfinity),
+        }
+    }
+
+    /// Serialize the given point with the uncompressed SEC format
+    pub fn serialize_uncompressed(&self) -> Result<[u8; 65]> {
+        match self {
+            Self::Normal(x, y) => {
+                let mut serialized = [0u8; 65];
+                serialized[0] = 0x04;
+                serialized[1..33].copy_from_slice(&x.to_bytes_be());
+                serialized[33..].copy_from_slice(&y.to_bytes_be());
+                Ok(serialized)
             }
 
             _ => Err(Error::SerializePointAtInfinity),
@@ -105,36 +115,64 @@ impl Point {
     {
         let bytes = bytes.as_ref();
 
-        let length = bytes.len();
-        if length != 33 && length != 65 {
-            return Err(Error::InvalidSecBytesLength(length));
+        if let Ok(bytes) = bytes.try_into() {
+            Self::from_sec_compressed(bytes)
+        } else if let Ok(bytes) = bytes.try_into() {
+            Self::from_sec_uncompressed(bytes)
+        } else {
+            Err(Error::InvalidSecBytesLength(bytes.len()))
         }
+    }
 
-        // uncompressed sec format
-        if bytes[0] == 0x04 {
-            let x = FieldElement::new(BigUint::from_bytes_be(&bytes[1..33]));
-            let y = FieldElement::new(BigUint::from_bytes_be(&bytes[33..65]));
-            return Self::new(x, y);
-        }
+    /// Deserialize the given bytes with the compressed SEC format
+    pub fn from_sec_compressed(bytes: &[u8; 33]) -> Result<Self> {
+        let y_is_even = match bytes[0] {
+            0x02 => true,
+            0x03 => false,
+            _ => return Err(Error::PointNotOnTheCurve),
+        };
 
-        // compressed sec format
-        let y_is_even = bytes[0] == 0x02;
-        let x = FieldElement::new(BigUint::from_bytes_be(&bytes[1..]));
+        let x = coordinate(&bytes[1..])?;
+        Self::lift_x(x, !y_is_even)
+    }
 
+    /// Point with the given x coordinate and y parity
+    pub fn lift_x(x: FieldElement, odd: bool) -> Result<Self> {
         // elliptic curve equation: y^2 = x^3 + x*a + b
         // rhs of the elliptic curve equation (note a = 0)
-        let alpha = x.pow(3u8) + &*B;
+        let alpha = x.pow(3u8) + *B;
 
-        // solve lhs
-        let beta = alpha.sqrt();
+        // solve lhs, there's no point with this x if alpha isn't a square
+        let (even_y, odd_y) = alpha.sqrt().ok_or(Error::PointNotOnTheCurve)?;
+        let y = if odd { odd_y } else { even_y };
 
-        let y = match (beta.0.is_even(), y_is_even) {
-            (true, true) | (false, false) => beta,
-            (true, false) | (false, true) => FieldElement::new(&*PRIME - beta.0),
-        };
+        Ok(Self::Normal(x, y)) // no need to check, sqrt verifies the root
+    }
 
-        Ok(Self::Normal(x, y)) // no need to check
+    /// Whether this is a finite point satisfying the curve equation
+    pub fn is_on_curve(&self) -> bool {
+        match self {
+            Self::Normal(x, y) => ECURVE.contains(x, y),
+            Self::AtInfinity => false,
+        }
     }
+
+    /// Deserialize the given bytes with the uncompressed SEC format
+    pub fn from_sec_uncompressed(bytes: &[u8; 65]) -> Result<Self> {
+        if bytes[0] != 0x04 {
+            return Err(Error::PointNotOnTheCurve);
+        }
+
+        let x = coordinate(&bytes[1..33])?;
+        let y = coordinate(&bytes[33..])?;
+        Self::new(x, y)
+    }
+}
+
+/// Coordinates must be lower than P
+fn coordinate(bytes: &[u8]) -> Result<FieldElement> {
+    let bytes = bytes.try_into().unwrap(); // safe, 32 bytes
+    FieldElement::from_canonical_bytes(bytes).ok_or(Error::PointNotOnTheCurve)
 }
 
 impl Zero for Point {
@@ -147,13 +185,13 @@ impl Zero for Point {
     }
 }
 
-impl<'a, 'b> Add<&'a Point> for &'b Point {
+impl Add<&Point> for &Point {
     type Output = Point;
 
-    fn add(self, rhs: &'a Point) -> Self::Output {
+    fn add(self, rhs: &Point) -> Self::Output {
         match (self, rhs) {
             // Additive identity
-            (Point::AtInfinity, p) | (p, Point::AtInfinity) => p.to_owned(),
+            (Point::AtInfinity, p) | (p, Point::AtInfinity) => p.clone(),
 
             // Normal addition between points
             (Point::Normal(x1, y1), Point::Normal(x2, y2)) => match (x1 == x2, y1 == y2) {
@@ -166,9 +204,9 @@ impl<'a, 'b> Add<&'a Point> for &'b Point {
                         return Point::at_infinity();
                     }
 
-                    let slope = (x1.pow(2usize) * 3usize + &ECURVE.a) / (y1 * 2usize);
+                    let slope = (x1.pow(2usize) * 3usize + ECURVE.a) / (y1 * 2usize);
                     let x3 = slope.pow(2usize) - (x1 * 2);
-                    let y3 = slope * (x1 - &x3) - y1;
+                    let y3 = slope * (x1 - x3) - y1;
 
                     Point::Normal(x3, y3)
                 }
@@ -177,7 +215,7 @@ impl<'a, 'b> Add<&'a Point> for &'b Point {
                 _ => {
                     let slope = (y2 - y1) / (x2 - x1);
                     let x3 = slope.pow(2usize) - x1 - x2;
-                    let y3 = slope * (x1 - &x3) - y1;
+                    let y3 = slope * (x1 - x3) - y1;
 
                     Point::Normal(x3, y3)
                 }
@@ -186,29 +224,25 @@ impl<'a, 'b> Add<&'a Point> for &'b Point {
     }
 }
 
-impl<'a, U> Mul<U> for &'a Point
+impl<U> Mul<U> for &Point
 where
     U: Into<BigUint>,
 {
     type Output = Point;
 
     fn mul(self, coef: U) -> Self::Output {
-        let mut coef = coef.into() % &*PRIME;
-
-        let one = BigUint::one();
-        let mut result = Point::zero();
-        let mut current = self.clone();
-
-        while !coef.is_zero() {
-            if &coef & &one == one {
-                result = &result + &current;
+        let coef = coef.into();
+
+        // double and add, any coefficient works as is in jacobian coordinates, inverting only once at the end
+        let mut result = JacobianPoint::infinity();
+        for bit in (0..coef.bits()).rev() {
+            result = result.double();
+            if coef.bit(bit) {
+                result = result.add_affine(self);
             }
-
-            coef >>= 1;
-            current = &current + &current;
         }
 
-        result
+        result.to_affine()
     }
 }
 
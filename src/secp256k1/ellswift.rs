@@ -0,0 +1,275 @@
+//! ElligatorSwift (BIP324): public keys as 64 bytes `u || t` indistinguishable
+//! from random ones, every such string decoding to the x coordinate of some
+//! point, and the x-only ECDH over them
+
+use lazy_static::lazy_static;
+use num_traits::{Pow, Zero};
+use rand::Rng;
+
+use crate::utils::tagged_hash;
+
+use super::crypto::PrivateKey;
+use super::curve::{Point, B};
+use super::field::{FieldElement, PRIME};
+
+lazy_static! {
+    /// A square root of -3, which one doesn't matter as long as it's always
+    /// the same
+    static ref MINUS_3_SQRT: FieldElement = sqrt(&FieldElement::from_u64(3).add_inv()).unwrap();
+}
+
+/// The square root `x^((p + 1) / 4)` if there's one, as libsecp256k1 picks it
+/// (the encodings of [`encode_with`] depend on that choice)
+fn sqrt(x: &FieldElement) -> Option<FieldElement> {
+    let root = x.pow((&*PRIME + 1usize) / 4usize);
+    match root.square() == *x {
+        true => Some(root),
+        false => None,
+    }
+}
+
+/// Whether `x` is the x coordinate of a point, `x^3 + 7` being a square
+fn is_valid_x(x: &FieldElement) -> bool {
+    sqrt(&(x.pow(3u8) + *B)).is_some()
+}
+
+/// The x coordinate `u || t` encodes, out of range values are reduced
+pub fn decode(encoding: &[u8; 64]) -> FieldElement {
+    let mut half = [0; 32];
+    half.copy_from_slice(&encoding[..32]);
+    let u = FieldElement::from_bytes_be(half);
+    half.copy_from_slice(&encoding[32..]);
+    let t = FieldElement::from_bytes_be(half);
+    xswiftec(u, t)
+}
+
+/// The x coordinate of `(u, t)`, remapping those where the mapping isn't
+/// defined
+fn xswiftec(u: FieldElement, t: FieldElement) -> FieldElement {
+    let one = FieldElement::from_u64(1);
+    let u = if u.is_zero() { one } else { u };
+    let t = if t.is_zero() { one } else { t };
+    let g = u.pow(3u8) + *B;
+    let t = if (g + t.square()).is_zero() { t * 2 } else { t };
+
+    let x = (g - t.square()) / (t * 2);
+    let y = (x + t) / (*MINUS_3_SQRT * u);
+    let two = FieldElement::from_u64(2);
+    let candidates = [
+        u + y.square() * 4,
+        (x.add_inv() / y - u) / two,
+        (x / y - u) / two,
+    ];
+
+    // one of them is always valid, the last one if the others aren't
+    let last = candidates[2];
+    candidates.iter().copied().find(is_valid_x).unwrap_or(last)
+}
+
+/// A `t` such that `(u, t)` decodes to `x`, one of the up to eight there are
+/// by `case`, none if there's no such `t` for the case
+fn xswiftec_inv(x: &FieldElement, u: &FieldElement, case: u8) -> Option<FieldElement> {
+    if u.is_zero() {
+        return None;
+    }
+
+    let g = u.pow(3u8) + *B;
+    let (s, v) = if case & 2 == 0 {
+        if is_valid_x(&(x.add_inv() - *u)) {
+            return None;
+        }
+
+        let s = (g / (u.square() + *u * *x + x.square())).add_inv();
+        (s, *x)
+    } else {
+        let s = *x - *u;
+        if s.is_zero() {
+            return None;
+        }
+
+        let r = sqrt(&((s * (g * 4 + s * u.square() * 3)).add_inv()))?;
+        if case & 1 == 1 && r.is_zero() {
+            return None;
+        }
+        (s, (r / s - *u) / FieldElement::from_u64(2))
+    };
+
+    let w = sqrt(&s)?;
+    let one = FieldElement::from_u64(1);
+    let half = FieldElement::from_u64(2).mul_inv();
+    let t = match case & 5 {
+        0 => (*u * (one - *MINUS_3_SQRT) * half + v) * w.add_inv(),
+        1 => (*u * (one + *MINUS_3_SQRT) * half + v) * w,
+        4 => (*u * (one - *MINUS_3_SQRT) * half + v) * w,
+        _ => (*u * (one + *MINUS_3_SQRT) * half + v) * w.add_inv(),
+    };
+    Some(t)
+}
+
+/// The encoding of `x` with the given `u` and `case`, none if there's none
+/// for them. Random ones are picked by [`encode`]
+pub fn encode_with(x: &FieldElement, u: &FieldElement, case: u8) -> Option<[u8; 64]> {
+    let t = xswiftec_inv(x, u, case)?;
+    let mut encoding = [0; 64];
+    encoding[..32].copy_from_slice(&u.to_bytes_be());
+    encoding[32..].copy_from_slice(&t.to_bytes_be());
+    Some(encoding)
+}
+
+/// A uniformly random encoding of `x`, which must be a valid x coordinate
+pub fn encode<R: Rng + ?Sized>(x: &FieldElement, rng: &mut R) -> [u8; 64] {
+    loop {
+        let u = FieldElement::from_bytes_be(rng.gen());
+        if let Some(encoding) = encode_with(x, &u, rng.gen_range(0, 8)) {
+            return encoding;
+        }
+    }
+}
+
+/// A random encoding of the public key of `key`
+pub fn create<R: Rng + ?Sized>(key: &PrivateKey, rng: &mut R) -> [u8; 64] {
+    match &key.public_key().ec_point {
+        Point::Normal(x, _) => encode(x, rng),
+        // unreachable for a valid key, any encoding will fail the ECDH anyway
+        Point::AtInfinity => [0; 64],
+    }
+}
+
+/// The BIP324 shared secret of `key`, whose encoding is `ours`, and the peer
+/// that sent `theirs`. Both sides get the same one as long as `initiating` is
+/// only set by the side that opened the connection
+pub fn xdh(key: &PrivateKey, ours: &[u8; 64], theirs: &[u8; 64], initiating: bool) -> [u8; 32] {
+    let x = decode(theirs);
+    // safe, decoding always gives a valid x coordinate
+    let point = Point::lift_x(x, false).unwrap();
+    let shared = match &point * key.secret() {
+        Point::Normal(x, _) => x.to_bytes_be(),
+        Point::AtInfinity => [0; 32],
+    };
+
+    let (initiator, responder) = match initiating {
+        true => (ours, theirs),
+        false => (theirs, ours),
+    };
+    tagged_hash(
+        "bip324_ellswift_xonly_ecdh",
+        [&initiator[..], &responder[..], &shared[..]].concat(),
+    )
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use core::convert::TryInto;
+
+    use hex_literal::hex;
+
+    use super::*;
+
+    fn fe(bytes: [u8; 32]) -> FieldElement {
+        FieldElement::from_bytes_be(bytes)
+    }
+
+    #[test]
+    fn decoding() {
+        // from the BIP324 test vectors
+        let cases = [
+            ([0; 64], hex!("edd1fd3e327ce90cc7a3542614289aee9682003e9cf7dcc9cf2ca9743be5aa0c")),
+            (
+                hex!("0000000000000000000000000000000000000000000000000000000000000000fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2f"),
+                hex!("edd1fd3e327ce90cc7a3542614289aee9682003e9cf7dcc9cf2ca9743be5aa0c"),
+            ),
+            (
+                hex!("000000000000000000000000000000000000000000000000000000000000000001d3475bf7655b0fb2d852921035b2ef607f49069b97454e6795251062741771"),
+                hex!("b5da00b73cd6560520e7c364086e7cd23a34bf60d0e707be9fc34d4cd5fdfa2c"),
+            ),
+            (
+                hex!("0000000000000000000000000000000000000000000000000000000000000000bde70df51939b94c9c24979fa7dd04ebd9b3572da7802290438af2a681895441"),
+                hex!("aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa9fffffd6b"),
+            ),
+        ];
+
+        for (encoding, x) in cases.iter() {
+            assert_eq!(decode(encoding), fe(*x));
+        }
+    }
+
+    #[test]
+    fn encoding() {
+        // from the BIP324 test vectors, the encodings of each case if any
+        let u = fe(hex!(
+            "05ff6bdad900fc3261bc7fe34e2fb0f569f06e091ae437d3a52e9da0cbfb9590"
+        ));
+        let x = fe(hex!(
+            "80cdf63774ec7022c89a5a8558e373a279170285e0ab27412dbce510bdfe23fc"
+        ));
+        let encodings = [
+            None,
+            None,
+            Some(hex!(
+                "45654798ece071ba79286d04f7f3eb1c3f1d17dd883610f2ad2efd82a287466b"
+            )),
+            Some(hex!(
+                "0aeaa886f6b76c7158452418cbf5033adc5747e9e9b5d3b2303db96936528557"
+            )),
+            None,
+            None,
+            Some(hex!(
+                "ba9ab867131f8e4586d792fb080c14e3c0e2e82277c9ef0d52d1027c5d78b5c4"
+            )),
+            Some(hex!(
+                "f51557790948938ea7badbe7340afcc523a8b816164a2c4dcfc24695c9ad76d8"
+            )),
+        ];
+
+        for (case, t) in encodings.iter().enumerate() {
+            let encoding = encode_with(&x, &u, case as u8);
+            assert_eq!(
+                encoding.map(|encoding| fe(encoding[32..].try_into().unwrap())),
+                t.map(fe)
+            );
+            if let Some(encoding) = encoding {
+                assert_eq!(decode(&encoding), x);
+            }
+        }
+
+        let mut rng = rand::thread_rng();
+        let key = PrivateKey::from_bytes_be([7; 32]);
+        let encoding = create(&key, &mut rng);
+        assert_eq!(Some(&decode(&encoding)), key.public_key().ec_point.x());
+    }
+
+    #[test]
+    fn bip324_xdh() {
+        let key = PrivateKey::from_bytes_be(hex!(
+            "61062ea5071d800bbfd59e2e8b53d47d194b095ae5a4df04936b49772ef0d4d7"
+        ));
+        let ours = hex!("ec0adff257bbfe500c188c80b4fdd640f6b45a482bbc15fc7cef5931deff0aa186f6eb9bba7b85dc4dcc28b28722de1e3d9108b985e2967045668f66098e475b");
+        let theirs = hex!("a4a94dfce69b4a2a0a099313d10f9f7e7d649d60501c9e1d274c300e0d89aafaffffffffffffffffffffffffffffffffffffffffffffffffffffffff8faf88d5");
+        assert_eq!(
+            xdh(&key, &ours, &theirs, true),
+            hex!("c6992a117f5edbea70c3f511d32d26b9798be4b81a62eaee1a5acaa8459a3592")
+        );
+
+        let key = PrivateKey::from_bytes_be(hex!(
+            "1f9c581b35231838f0f17cf0c979835baccb7f3abbbb96ffcc318ab71e6e126f"
+        ));
+        let ours = hex!("a1855e10e94e00baa23041d916e259f7044e491da6171269694763f018c7e63693d29575dcb464ac816baa1be353ba12e3876cba7628bd0bd8e755e721eb0140");
+        let theirs = hex!("fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2f0000000000000000000000000000000000000000000000000000000000000000");
+        assert_eq!(
+            xdh(&key, &ours, &theirs, false),
+            hex!("a0138f564f74d0ad70bc337dacc9d0bf1d2349364caf1188a1e6e8ddb3b7b184")
+        );
+
+        // both sides agree
+        let mut rng = rand::thread_rng();
+        let (initiator, responder) = (
+            PrivateKey::from_bytes_be([1; 32]),
+            PrivateKey::from_bytes_be([2; 32]),
+        );
+        let (ours, theirs) = (create(&initiator, &mut rng), create(&responder, &mut rng));
+        assert_eq!(
+            xdh(&initiator, &ours, &theirs, true),
+            xdh(&responder, &theirs, &ours, false)
+        );
+    }
+}
@@ -1,9 +1,14 @@
-use std::ops::{Add, Div, Mul, Sub};
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use core::ops::{Add, Div, Mul, Sub};
 
 use lazy_static::lazy_static;
 use num_bigint::{BigInt, BigUint, Sign};
 use num_integer::Integer;
 use num_traits::{One, Pow, Zero};
+use subtle::{Choice, ConstantTimeEq};
+
+use crate::field::PrimeField;
 
 lazy_static! {
     /// `secp256k1` prime = 2^256 - 2^32 - 977
@@ -11,8 +16,97 @@ lazy_static! {
         biguint!("fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2f");
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct FieldElement(pub(crate) BigUint);
+prime_modulus!(
+    /// The `secp256k1` prime for the generic `Fp` field, mostly useful to cross
+    /// check against the specialized `FieldElement`
+    pub Secp256k1Prime = "fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2f"
+);
+
+/// Little endian limbs of the `secp256k1` prime
+const P: [u64; 4] = [
+    0xffff_fffe_ffff_fc2f,
+    0xffff_ffff_ffff_ffff,
+    0xffff_ffff_ffff_ffff,
+    0xffff_ffff_ffff_ffff,
+];
+
+/// 2^256 mod P = 2^32 + 977
+const R: u64 = 0x1_0000_03d1;
+
+#[inline(always)]
+fn adc(a: u64, b: u64, carry: u64) -> (u64, u64) {
+    let t = a as u128 + b as u128 + carry as u128;
+    (t as u64, (t >> 64) as u64)
+}
+
+#[inline(always)]
+fn sbb(a: u64, b: u64, borrow: u64) -> (u64, u64) {
+    let t = (a as u128).wrapping_sub(b as u128 + borrow as u128);
+    (t as u64, (t >> 127) as u64)
+}
+
+#[inline(always)]
+fn mac(acc: u64, a: u64, b: u64, carry: u64) -> (u64, u64) {
+    let t = acc as u128 + (a as u128) * (b as u128) + carry as u128;
+    (t as u64, (t >> 64) as u64)
+}
+
+/// Subtract P if the number `carry * 2^256 + limbs` is greater or equal than P,
+/// the number must be lower than 2P.
+#[inline(always)]
+fn reduce_once(limbs: [u64; 4], carry: u64) -> [u64; 4] {
+    let (d0, b) = sbb(limbs[0], P[0], 0);
+    let (d1, b) = sbb(limbs[1], P[1], b);
+    let (d2, b) = sbb(limbs[2], P[2], b);
+    let (d3, b) = sbb(limbs[3], P[3], b);
+
+    // the subtraction didn't underflow, or the carry covers it
+    if b == 0 || carry != 0 {
+        [d0, d1, d2, d3]
+    } else {
+        limbs
+    }
+}
+
+/// Reduce a 512 bits product, folding the upper half using 2^256 = R (mod P)
+#[inline(always)]
+fn reduce_wide(wide: [u64; 8]) -> [u64; 4] {
+    // lo + hi * R, fits in 5 limbs (R is 33 bits long)
+    let (r0, c) = mac(wide[0], wide[4], R, 0);
+    let (r1, c) = mac(wide[1], wide[5], R, c);
+    let (r2, c) = mac(wide[2], wide[6], R, c);
+    let (r3, r4) = mac(wide[3], wide[7], R, c);
+
+    // fold the fifth limb again, r4 * R is at most 67 bits long
+    let (r0, c) = mac(r0, r4, R, 0);
+    let (r1, c) = adc(r1, c, 0);
+    let (r2, c) = adc(r2, c, 0);
+    let (r3, c) = adc(r3, c, 0);
+
+    // a final carry means the low limbs are tiny, adding R can't overflow again
+    let (r0, k) = mac(r0, c, R, 0);
+    let (r1, k) = adc(r1, k, 0);
+    let (r2, k) = adc(r2, k, 0);
+    let (r3, _) = adc(r3, k, 0);
+
+    reduce_once([r0, r1, r2, r3], 0)
+}
+
+/// Little endian limbs of a big endian 256 bits number
+#[inline(always)]
+fn limbs_from_bytes(bytes: &[u8; 32]) -> [u64; 4] {
+    let mut limbs = [0u64; 4];
+    for (limb, chunk) in limbs.iter_mut().rev().zip(bytes.chunks_exact(8)) {
+        *limb = u64::from_be_bytes(chunk.try_into().unwrap()); // safe, 8 bytes
+    }
+
+    limbs
+}
+
+/// An element of the field of integers modulo the `secp256k1` prime, stored as
+/// four little endian 64 bits limbs (always fully reduced)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldElement(pub(crate) [u64; 4]);
 
 impl FieldElement {
     /// Build a new element in the S256 field
@@ -20,13 +114,77 @@ impl FieldElement {
     where
         U: Into<BigUint>,
     {
-        Self(number.into() % &*PRIME)
+        let number = number.into() % &*PRIME;
+
+        let mut limbs = [0u64; 4];
+        for (limb, digit) in limbs.iter_mut().zip(number.to_u64_digits()) {
+            *limb = digit;
+        }
+
+        Self(limbs)
+    }
+
+    /// Build a new element from a small number
+    pub const fn from_u64(number: u64) -> Self {
+        Self([number, 0, 0, 0])
+    }
+
+    /// Interpret the given bytes as a big endian number, reducing it modulo P
+    pub fn from_bytes_be(bytes: [u8; 32]) -> Self {
+        // any 256 bits number is lower than 2P
+        Self(reduce_once(limbs_from_bytes(&bytes), 0))
+    }
+
+    /// Interpret the given bytes as a big endian number, only if it's already
+    /// lower than P
+    pub fn from_canonical_bytes(bytes: [u8; 32]) -> Option<Self> {
+        let limbs = limbs_from_bytes(&bytes);
+
+        // P <= limbs iff subtracting it doesn't underflow
+        let (_, b) = sbb(limbs[0], P[0], 0);
+        let (_, b) = sbb(limbs[1], P[1], b);
+        let (_, b) = sbb(limbs[2], P[2], b);
+        let (_, b) = sbb(limbs[3], P[3], b);
+
+        if b == 1 {
+            Some(Self(limbs))
+        } else {
+            None
+        }
+    }
+
+    /// Big endian representation, always 32 bytes long
+    pub fn to_bytes_be(&self) -> [u8; 32] {
+        let mut result = [0u8; 32];
+        for (chunk, limb) in result.chunks_exact_mut(8).zip(self.0.iter().rev()) {
+            chunk.copy_from_slice(&limb.to_be_bytes());
+        }
+
+        result
+    }
+
+    /// Get this element as an arbitrary precision integer
+    pub fn to_biguint(&self) -> BigUint {
+        BigUint::from_bytes_be(&self.to_bytes_be())
+    }
+
+    #[inline]
+    pub fn is_even(&self) -> bool {
+        self.0[0] & 1 == 0
     }
 
     /// Get the _additive inverse_ of this element.
     #[inline]
     pub fn add_inv(&self) -> Self {
-        Self(&*PRIME - &self.0)
+        if self.is_zero() {
+            return *self;
+        }
+
+        let (d0, b) = sbb(P[0], self.0[0], 0);
+        let (d1, b) = sbb(P[1], self.0[1], b);
+        let (d2, b) = sbb(P[2], self.0[2], b);
+        let (d3, _) = sbb(P[3], self.0[3], b);
+        Self([d0, d1, d2, d3])
     }
 
     /// Get the _multiplicative inverse_ of this element.
@@ -36,30 +194,159 @@ impl FieldElement {
         self.pow(&*PRIME - 2usize)
     }
 
-    /// Get the square root of this element
-    #[inline]
-    pub fn sqrt(&self) -> Self {
-        self.pow((&*PRIME + 1usize) / 4usize)
+    /// Invert all the given elements at once (Montgomery's trick), costs a single
+    /// inversion plus three multiplications per element. Zeroes are left as is.
+    pub fn batch_invert(elements: &mut [FieldElement]) {
+        let mut prefixes = Vec::with_capacity(elements.len());
+        let mut acc = Self::one();
+        for element in elements.iter() {
+            prefixes.push(acc);
+            if !element.is_zero() {
+                acc = acc * element;
+            }
+        }
+
+        // inverse of the product of every element, peeled one by one
+        let mut inv = acc.mul_inv();
+        for (element, prefix) in elements.iter_mut().zip(prefixes).rev() {
+            if element.is_zero() {
+                continue;
+            }
+
+            let next = inv * *element;
+            *element = inv * prefix;
+            inv = next;
+        }
+    }
+
+    /// Get both square roots of this element as `(even, odd)`, or `None` if this
+    /// element isn't a quadratic residue. Since P = 3 (mod 4) a candidate root is
+    /// just `self^((P + 1) / 4)`. Zero is its only root, so it comes back twice
+    /// as `(0, 0)` and there's no odd one.
+    pub fn sqrt(&self) -> Option<(Self, Self)> {
+        let root = self.pow((&*PRIME + 1usize) / 4usize);
+        if root.square() != *self {
+            return None;
+        }
+
+        if root.is_even() {
+            Some((root, root.add_inv()))
+        } else {
+            Some((root.add_inv(), root))
+        }
+    }
+
+    /// Square this element, every cross product is computed only once
+    pub fn square(&self) -> Self {
+        let a = &self.0;
+        let mut wide = [0u64; 8];
+
+        // a[i] * a[j] for i < j
+        for i in 0..3 {
+            let mut carry = 0;
+            for j in (i + 1)..4 {
+                let (limb, c) = mac(wide[i + j], a[i], a[j], carry);
+                wide[i + j] = limb;
+                carry = c;
+            }
+            wide[i + 4] = carry;
+        }
+
+        // double them
+        for k in (1..8).rev() {
+            wide[k] = (wide[k] << 1) | (wide[k - 1] >> 63);
+        }
+        wide[0] <<= 1;
+
+        // add the squares in the diagonal
+        let mut carry = 0;
+        for i in 0..4 {
+            let t = a[i] as u128 * a[i] as u128;
+            let (lo, c) = adc(wide[2 * i], t as u64, carry);
+            let (hi, c) = adc(wide[2 * i + 1], (t >> 64) as u64, c);
+            wide[2 * i] = lo;
+            wide[2 * i + 1] = hi;
+            carry = c;
+        }
+
+        FieldElement(reduce_wide(wide))
+    }
+
+    /// Exponentiation by the given little endian 64 bits digits
+    fn pow_digits(&self, exponent: &[u64]) -> Self {
+        let mut result = Self::one();
+        for digit in exponent.iter().rev() {
+            for bit in (0..64).rev() {
+                result = result.square();
+                if (digit >> bit) & 1 == 1 {
+                    result = result * self;
+                }
+            }
+        }
+
+        result
+    }
+}
+
+/// The specialized `secp256k1` field, can be used wherever a generic prime field
+/// is expected
+impl PrimeField for FieldElement {
+    fn modulus() -> &'static BigUint {
+        &PRIME
+    }
+
+    fn from_biguint(number: BigUint) -> Self {
+        Self::new(number)
+    }
+
+    fn to_biguint(&self) -> BigUint {
+        FieldElement::to_biguint(self)
+    }
+
+    fn add_inv(&self) -> Self {
+        FieldElement::add_inv(self)
+    }
+
+    fn mul_inv(&self) -> Self {
+        FieldElement::mul_inv(self)
+    }
+
+    fn square(&self) -> Self {
+        FieldElement::square(self)
+    }
+
+    fn pow_biguint(&self, exponent: &BigUint) -> Self {
+        self.pow_digits(&exponent.to_u64_digits())
+    }
+
+    fn sqrt(&self) -> Option<(Self, Self)> {
+        FieldElement::sqrt(self)
+    }
+}
+
+impl ConstantTimeEq for FieldElement {
+    fn ct_eq(&self, other: &Self) -> Choice {
+        self.0.ct_eq(&other.0)
     }
 }
 
 impl Zero for FieldElement {
     fn zero() -> Self {
-        FieldElement(BigUint::zero())
+        FieldElement([0; 4])
     }
 
     fn is_zero(&self) -> bool {
-        self.0.is_zero()
+        self.0 == [0; 4]
     }
 }
 
 impl One for FieldElement {
     fn one() -> Self {
-        FieldElement(BigUint::one())
+        FieldElement([1, 0, 0, 0])
     }
 }
 
-impl<'a, E> Pow<E> for &'a FieldElement
+impl<E> Pow<E> for &FieldElement
 where
     E: Into<BigInt>,
 {
@@ -75,50 +362,78 @@ where
             }
         };
 
-        let number = self.0.modpow(&exponent, &*PRIME);
-        FieldElement(number)
+        self.pow_digits(&exponent.to_u64_digits())
     }
 }
 
-impl<'a, 'b> Add<&'a FieldElement> for &'b FieldElement {
+impl Add<&FieldElement> for &FieldElement {
     type Output = FieldElement;
 
-    fn add(self, rhs: &'a FieldElement) -> Self::Output {
-        let number = (&self.0 + &rhs.0) % &*PRIME;
-        FieldElement::new(number)
+    fn add(self, rhs: &FieldElement) -> Self::Output {
+        let (s0, c) = adc(self.0[0], rhs.0[0], 0);
+        let (s1, c) = adc(self.0[1], rhs.0[1], c);
+        let (s2, c) = adc(self.0[2], rhs.0[2], c);
+        let (s3, c) = adc(self.0[3], rhs.0[3], c);
+
+        FieldElement(reduce_once([s0, s1, s2, s3], c))
     }
 }
 
-impl<'a, 'b> Sub<&'a FieldElement> for &'b FieldElement {
+impl Sub<&FieldElement> for &FieldElement {
     type Output = FieldElement;
 
-    fn sub(self, rhs: &'a FieldElement) -> Self::Output {
-        self.add(&rhs.add_inv())
+    fn sub(self, rhs: &FieldElement) -> Self::Output {
+        let (d0, b) = sbb(self.0[0], rhs.0[0], 0);
+        let (d1, b) = sbb(self.0[1], rhs.0[1], b);
+        let (d2, b) = sbb(self.0[2], rhs.0[2], b);
+        let (d3, b) = sbb(self.0[3], rhs.0[3], b);
+
+        if b == 0 {
+            return FieldElement([d0, d1, d2, d3]);
+        }
+
+        // underflow, wrap around by adding P
+        let (d0, c) = adc(d0, P[0], 0);
+        let (d1, c) = adc(d1, P[1], c);
+        let (d2, c) = adc(d2, P[2], c);
+        let (d3, _) = adc(d3, P[3], c);
+        FieldElement([d0, d1, d2, d3])
     }
 }
 
-impl<'a, 'b> Mul<&'a FieldElement> for &'b FieldElement {
+impl Mul<&FieldElement> for &FieldElement {
     type Output = FieldElement;
 
-    fn mul(self, rhs: &'a FieldElement) -> Self::Output {
-        let number = (&self.0 * &rhs.0) % &*PRIME;
-        FieldElement::new(number)
+    fn mul(self, rhs: &FieldElement) -> Self::Output {
+        let mut wide = [0u64; 8];
+
+        for i in 0..4 {
+            let mut carry = 0;
+            for j in 0..4 {
+                let (limb, c) = mac(wide[i + j], self.0[i], rhs.0[j], carry);
+                wide[i + j] = limb;
+                carry = c;
+            }
+            wide[i + 4] = carry;
+        }
+
+        FieldElement(reduce_wide(wide))
     }
 }
 
-impl<'a, 'b> Div<&'a FieldElement> for &'b FieldElement {
+impl Div<&FieldElement> for &FieldElement {
     type Output = FieldElement;
 
-    fn div(self, rhs: &'a FieldElement) -> Self::Output {
+    fn div(self, rhs: &FieldElement) -> Self::Output {
         self.mul(&rhs.mul_inv())
     }
 }
 
-impl<'a> Mul<usize> for &'a FieldElement {
+impl Mul<usize> for &FieldElement {
     type Output = FieldElement;
 
     fn mul(self, rhs: usize) -> Self::Output {
-        self.mul(FieldElement::new(rhs))
+        self.mul(FieldElement::from_u64(rhs as u64))
     }
 }
 
@@ -134,3 +449,139 @@ forward_binop_impl!(for non-copyable FieldElement where Add does add);
 forward_binop_impl!(for non-copyable FieldElement where Sub does sub);
 forward_binop_impl!(for non-copyable FieldElement where Mul does mul);
 forward_binop_impl!(for non-copyable FieldElement where Div does div);
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use num_bigint::RandBigInt;
+
+    use super::*;
+
+    fn edge_cases() -> Vec<BigUint> {
+        vec![
+            BigUint::zero(),
+            BigUint::one(),
+            &*PRIME - 1usize,
+            &*PRIME - 2usize,
+            BigUint::from(u64::MAX),
+            (BigUint::one() << 255) + 3usize,
+        ]
+    }
+
+    #[test]
+    fn arithmetic_matches_biguint() {
+        let mut rng = rand::thread_rng();
+        let mut numbers = edge_cases();
+        numbers.extend((0..16).map(|_| rng.gen_biguint_below(&PRIME)));
+
+        for a in &numbers {
+            for b in &numbers {
+                let (fa, fb) = (FieldElement::new(a.clone()), FieldElement::new(b.clone()));
+
+                assert_eq!((fa + fb).to_biguint(), (a + b) % &*PRIME);
+                assert_eq!((fa * fb).to_biguint(), (a * b) % &*PRIME);
+                assert_eq!(fa.square(), fa * fa);
+                assert_eq!((fa - fb).to_biguint(), (a + &*PRIME - b) % &*PRIME);
+            }
+        }
+    }
+
+    #[test]
+    fn byte_encoding() {
+        let mut rng = rand::thread_rng();
+        let mut numbers = edge_cases();
+        numbers.extend((0..16).map(|_| rng.gen_biguint_below(&PRIME)));
+
+        for number in &numbers {
+            let element = FieldElement::new(number.clone());
+            let bytes = element.to_bytes_be();
+            assert_eq!(BigUint::from_bytes_be(&bytes), *number);
+            assert_eq!(FieldElement::from_bytes_be(bytes), element);
+            assert_eq!(FieldElement::from_canonical_bytes(bytes), Some(element));
+        }
+
+        // P and above are only accepted reduced
+        for extra in [0u64, 1, 977].iter() {
+            let number = &*PRIME + *extra;
+            let bytes = crate::utils::to_bytes32_be(&number).unwrap();
+            assert_eq!(
+                FieldElement::from_bytes_be(bytes),
+                FieldElement::from_u64(*extra)
+            );
+            assert_eq!(FieldElement::from_canonical_bytes(bytes), None);
+        }
+    }
+
+    #[test]
+    fn inverse_and_sqrt() {
+        let a = FieldElement::new(biguint!(
+            "5a0bd8ffa8a2b0e9b6dc4ab4e7ab0a6c5e04bbd4a0e5c2cafb3d0aea8f3a28b1"
+        ));
+
+        assert_eq!(a * a.mul_inv(), FieldElement::one());
+        assert_eq!(a + a.add_inv(), FieldElement::zero());
+        assert!(bool::from(a.ct_eq(&(a * FieldElement::one()))));
+        assert!(!bool::from(a.ct_eq(&a.add_inv())));
+        let (even, odd) = a.square().sqrt().unwrap();
+        assert!(even.is_even() && !odd.is_even());
+        assert!(even == a || odd == a);
+        assert_eq!(even.square(), a.square());
+        assert_eq!(odd.square(), a.square());
+        assert_eq!(FieldElement::from_bytes_be(a.to_bytes_be()), a);
+        let max = BigUint::from_bytes_be(&[0xff; 32]) % &*PRIME;
+        assert_eq!(FieldElement::from_bytes_be([0xff; 32]).to_biguint(), max);
+
+        let mut five = [0u8; 32];
+        five[31] = 5;
+        assert_eq!(FieldElement::from_bytes_be(five), FieldElement::from_u64(5));
+        assert_eq!(
+            FieldElement::from_canonical_bytes(five),
+            Some(FieldElement::from_u64(5))
+        );
+
+        let p = crate::utils::to_bytes32_be(&PRIME).unwrap();
+        assert!(FieldElement::from_bytes_be(p).is_zero());
+        assert!(FieldElement::from_canonical_bytes(p).is_none());
+        assert!(FieldElement::from_canonical_bytes([0xff; 32]).is_none());
+    }
+
+    #[test]
+    fn sqrt_edge_cases() {
+        // zero is its own and only root, both even
+        let (even, odd) = FieldElement::zero().sqrt().unwrap();
+        assert!(even.is_zero() && odd.is_zero());
+        assert!(odd.is_even());
+
+        // -1 isn't a quadratic residue since P = 3 (mod 4), so neither is 3
+        // given that -3 is
+        assert_eq!(FieldElement::one().add_inv().sqrt(), None);
+        assert_eq!(FieldElement::from_u64(3).sqrt(), None);
+        assert!(FieldElement::from_u64(3).add_inv().sqrt().is_some());
+
+        let (even, odd) = FieldElement::from_u64(4).sqrt().unwrap();
+        assert_eq!(even, FieldElement::from_u64(2));
+        assert_eq!(odd, FieldElement::from_u64(2).add_inv());
+    }
+
+    #[test]
+    fn batch_inverse() {
+        let mut rng = rand::thread_rng();
+        let mut elements: Vec<_> = (0..8)
+            .map(|_| FieldElement::new(rng.gen_biguint_below(&PRIME)))
+            .collect();
+        elements.insert(3, FieldElement::zero());
+        elements.push(FieldElement::one());
+
+        let mut inverses = elements.clone();
+        FieldElement::batch_invert(&mut inverses);
+
+        for (element, inverse) in elements.iter().zip(&inverses) {
+            if element.is_zero() {
+                assert!(inverse.is_zero());
+            } else {
+                assert_eq!(*inverse, element.mul_inv());
+            }
+        }
+
+        FieldElement::batch_invert(&mut []);
+    }
+}
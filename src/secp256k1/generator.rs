@@ -0,0 +1,83 @@
+use alloc::vec::Vec;
+use core::iter::once;
+
+use lazy_static::lazy_static;
+
+use super::curve::Point;
+use super::jacobian::JacobianPoint;
+use super::scalar::Scalar;
+use super::G;
+
+/// Bits of the scalar handled by each table lookup
+const WINDOW_BITS: usize = 4;
+const WINDOW_SIZE: usize = 1 << WINDOW_BITS;
+const WINDOWS: usize = 256 / WINDOW_BITS;
+
+lazy_static! {
+    /// Multiples of the generator, entry `i * WINDOW_SIZE + j` holds `j * 16^i * G`
+    static ref TABLE: Vec<Point> = build_table();
+}
+
+/// Every entry is computed in Jacobian coordinates and normalized at the end with
+/// a single (batched) inversion
+fn build_table() -> Vec<Point> {
+    let mut table = Vec::with_capacity(WINDOWS * WINDOW_SIZE);
+    let mut base = JacobianPoint::from(&*G);
+
+    for _ in 0..WINDOWS {
+        let mut acc = JacobianPoint::infinity();
+        for _ in 0..WINDOW_SIZE {
+            table.push(acc);
+            acc = acc.add(&base);
+        }
+
+        // after the last addition acc = 16 * base
+        base = acc;
+    }
+
+    JacobianPoint::batch_to_affine(&table)
+}
+
+/// Compute `k * G` with one table lookup (and a mixed addition) per window,
+/// no doublings are needed.
+pub(crate) fn mul_g(k: &Scalar) -> Point {
+    let mut result = JacobianPoint::infinity();
+    let bytes = k.to_bytes_be();
+
+    let windows = bytes
+        .iter()
+        .rev()
+        .flat_map(|byte| once(byte & 0x0f).chain(once(byte >> 4)))
+        .enumerate();
+
+    for (i, digit) in windows {
+        result = result.add_affine(&TABLE[i * WINDOW_SIZE + digit as usize]);
+    }
+
+    result.to_affine()
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use num_bigint::RandBigInt;
+    use num_traits::Zero;
+
+    use super::*;
+    use crate::secp256k1::N;
+
+    #[test]
+    fn matches_double_and_add() {
+        let mut rng = rand::thread_rng();
+        let mut scalars = vec![
+            Scalar::zero(),
+            Scalar::new(1usize),
+            Scalar::new(0xf0usize),
+            Scalar::new(&*N - 1usize),
+        ];
+        scalars.extend((0..8).map(|_| Scalar::new(rng.gen_biguint_below(&N))));
+
+        for k in &scalars {
+            assert_eq!(mul_g(k), &*G * k);
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use alloc::vec::Vec;
+
+use lazy_static::lazy_static;
+use num_bigint::{BigInt, BigUint, Sign};
+use num_traits::Signed;
+
+use super::curve::Point;
+use super::field::FieldElement;
+use super::jacobian::JacobianPoint;
+use super::scalar::Scalar;
+use super::wnaf::{odd_multiples, strauss, wnaf, WINDOW};
+use super::{G, N};
+
+lazy_static! {
+    /// Non trivial cube root of unity modulo P
+    pub(crate) static ref BETA: FieldElement = field_elem!(
+        "7ae96a2b657c07106e64479eac3434e99cf0497512f58995c1396c28719501ee"
+    );
+
+    /// Non trivial cube root of unity modulo N, `lambda * (x, y) = (beta * x, y)`
+    pub(crate) static ref LAMBDA: Scalar = Scalar::new(biguint!(
+        "5363ad4cc05c30e0a5261c028812645a122e22ea20816678df02967c1b23bd72"
+    ));
+
+    // short basis of the lattice {(a, b) : a + b * lambda = 0 (mod N)}, note b2 = a1
+    static ref A1: BigInt = BigInt::from(biguint!("3086d221a7d46bcde86c90e49284eb15"));
+    static ref B1: BigInt = -BigInt::from(biguint!("e4437ed6010e88286f547fa90abfe4c3"));
+    static ref A2: BigInt = BigInt::from(biguint!("0114ca50f7a8e2f3f657c1108d9d44cfd8"));
+
+    /// Odd multiples of `G` and `lambda * G`, used by every verification
+    static ref G_MULTIPLES: (Vec<JacobianPoint>, Vec<JacobianPoint>) = (
+        odd_multiples(&JacobianPoint::from(&*G), WINDOW),
+        odd_multiples(&JacobianPoint::from(&endomorphism(&G)), WINDOW),
+    );
+}
+
+/// The endomorphism `(x, y) -> (beta * x, y)`, equivalent to a multiplication by lambda
+pub(crate) fn endomorphism(point: &Point) -> Point {
+    match point {
+        Point::AtInfinity => Point::AtInfinity,
+        Point::Normal(x, y) => Point::Normal(*x * *BETA, *y),
+    }
+}
+
+/// Split `k` into `(k1, k2)` such that `k = k1 + k2 * lambda (mod N)`, both halves
+/// are at most 128 bits long (in absolute value).
+pub(crate) fn decompose(k: &Scalar) -> (BigInt, BigInt) {
+    let n = BigInt::from_biguint(Sign::Plus, N.clone());
+    let half_n = &n >> 1;
+    let k = BigInt::from_biguint(Sign::Plus, k.0.clone());
+
+    // rounded divisions, b2 = a1
+    let c1 = (&*A1 * &k + &half_n) / &n;
+    let c2 = (-&*B1 * &k + &half_n) / &n;
+
+    let k1 = &k - &c1 * &*A1 - &c2 * &*A2;
+    let k2 = -&c1 * &*B1 - &c2 * &*A1;
+
+    (k1, k2)
+}
+
+/// Signed wNAF digits of `k`
+fn signed_wnaf(k: &BigInt) -> Vec<i32> {
+    let magnitude: BigUint = k.abs().to_biguint().unwrap(); // safe, non negative
+    let digits = wnaf(&magnitude, WINDOW);
+
+    if k.is_negative() {
+        digits.into_iter().map(|d| -d).collect()
+    } else {
+        digits
+    }
+}
+
+/// Variable time `a * G + b * P` (Shamir's trick), both products share the same
+/// doublings instead of being computed independently and then added. Each scalar
+/// is split with the endomorphism, so this becomes four interleaved 128 bits
+/// multiplications.
+/// Only to be used with public scalars.
+pub(crate) fn double_mul_vartime(a: &Scalar, point: &Point, b: &Scalar) -> Point {
+    let (a1, a2) = decompose(a);
+    let (b1, b2) = decompose(b);
+
+    let (g1, g2) = &*G_MULTIPLES;
+    let multiples1 = odd_multiples(&JacobianPoint::from(point), WINDOW);
+    let multiples2 = odd_multiples(&JacobianPoint::from(&endomorphism(point)), WINDOW);
+
+    strauss(&[
+        (g1, &signed_wnaf(&a1)),
+        (g2, &signed_wnaf(&a2)),
+        (&multiples1, &signed_wnaf(&b1)),
+        (&multiples2, &signed_wnaf(&b2)),
+    ])
+    .to_affine()
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use num_bigint::RandBigInt;
+    use num_traits::Zero;
+
+    use super::*;
+
+    #[test]
+    fn endomorphism_is_lambda() {
+        assert_eq!(endomorphism(&G), &*G * &*LAMBDA);
+    }
+
+    #[test]
+    fn decomposition() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..32 {
+            let k = Scalar::new(rng.gen_biguint_below(&N));
+            let (k1, k2) = decompose(&k);
+
+            assert!(k1.bits() <= 128 && k2.bits() <= 128);
+
+            let n = BigInt::from_biguint(Sign::Plus, N.clone());
+            let lambda = BigInt::from_biguint(Sign::Plus, LAMBDA.0.clone());
+            let sum = ((k1 + k2 * lambda) % &n + &n) % &n;
+            assert_eq!(sum.to_biguint().unwrap(), k.0);
+        }
+    }
+
+    #[test]
+    fn matches_double_and_add() {
+        let mut rng = rand::thread_rng();
+        let point = &*G * 0xdeadbeefusize;
+
+        for _ in 0..8 {
+            let k = Scalar::new(rng.gen_biguint_below(&N));
+            assert_eq!(double_mul_vartime(&Scalar::zero(), &point, &k), &point * &k);
+        }
+
+        let zero = Scalar::zero();
+        assert!(double_mul_vartime(&zero, &point, &zero).is_point_at_inf());
+        assert_eq!(
+            double_mul_vartime(&zero, &point, &Scalar::new(&*N - 1usize)),
+            &point * (&*N - 1usize)
+        );
+    }
+
+    #[test]
+    fn double_mul_matches_separate_products() {
+        let mut rng = rand::thread_rng();
+        let point = &*G * 0xcafebabeusize;
+
+        for _ in 0..8 {
+            let a = Scalar::new(rng.gen_biguint_below(&N));
+            let b = Scalar::new(rng.gen_biguint_below(&N));
+            let expected = &*G * &a + &point * &b;
+            assert_eq!(double_mul_vartime(&a, &point, &b), expected);
+        }
+
+        let a = Scalar::new(5usize);
+        assert_eq!(double_mul_vartime(&a, &G, &a.negate()), Point::AtInfinity);
+    }
+}
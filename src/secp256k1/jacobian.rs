@@ -0,0 +1,210 @@
+use alloc::vec::Vec;
+
+use num_traits::{One, Zero};
+
+use super::curve::Point;
+use super::field::FieldElement;
+
+/// A point in Jacobian projective coordinates, `(X, Y, Z)` represents the affine
+/// point `(X / Z^2, Y / Z^3)`, any point with `Z = 0` is the point at infinity.
+///
+/// Group operations don't need any field inversion, only converting back to
+/// affine coordinates does.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct JacobianPoint {
+    pub(crate) x: FieldElement,
+    pub(crate) y: FieldElement,
+    pub(crate) z: FieldElement,
+}
+
+impl JacobianPoint {
+    pub(crate) fn infinity() -> Self {
+        Self {
+            x: FieldElement::one(),
+            y: FieldElement::one(),
+            z: FieldElement::zero(),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn is_infinity(&self) -> bool {
+        self.z.is_zero()
+    }
+
+    /// Convert back to affine coordinates, needs a single inversion
+    pub(crate) fn to_affine(self) -> Point {
+        if self.is_infinity() {
+            return Point::AtInfinity;
+        }
+
+        self.to_affine_with(self.z.mul_inv())
+    }
+
+    /// Convert many points back to affine coordinates, sharing a single inversion
+    pub(crate) fn batch_to_affine(points: &[JacobianPoint]) -> Vec<Point> {
+        let mut z_invs: Vec<_> = points.iter().map(|point| point.z).collect();
+        FieldElement::batch_invert(&mut z_invs);
+
+        points
+            .iter()
+            .zip(z_invs)
+            .map(|(point, z_inv)| {
+                if point.is_infinity() {
+                    Point::AtInfinity
+                } else {
+                    point.to_affine_with(z_inv)
+                }
+            })
+            .collect()
+    }
+
+    fn to_affine_with(self, z_inv: FieldElement) -> Point {
+        let z_inv2 = z_inv.square();
+        let x = self.x * z_inv2;
+        let y = self.y * z_inv2 * z_inv;
+
+        Point::Normal(x, y)
+    }
+
+    pub(crate) fn neg(&self) -> Self {
+        Self {
+            x: self.x,
+            y: self.y.add_inv(),
+            z: self.z,
+        }
+    }
+
+    /// Point doubling for curves with `a = 0` (dbl-2009-l)
+    pub(crate) fn double(&self) -> Self {
+        if self.is_infinity() || self.y.is_zero() {
+            return Self::infinity();
+        }
+
+        let a = self.x.square();
+        let b = self.y.square();
+        let c = b.square();
+        let d = ((self.x + b).square() - a - c) * 2;
+        let e = a * 3;
+        let f = e.square();
+
+        let x = f - d * 2;
+        let y = e * (d - x) - c * 8;
+        let z = self.y * self.z * 2;
+
+        Self { x, y, z }
+    }
+
+    /// General point addition (add-2007-bl without the doubled factors)
+    pub(crate) fn add(&self, rhs: &JacobianPoint) -> Self {
+        if self.is_infinity() {
+            return *rhs;
+        }
+
+        if rhs.is_infinity() {
+            return *self;
+        }
+
+        let z1z1 = self.z.square();
+        let z2z2 = rhs.z.square();
+        let u1 = self.x * z2z2;
+        let u2 = rhs.x * z1z1;
+        let s1 = self.y * rhs.z * z2z2;
+        let s2 = rhs.y * self.z * z1z1;
+
+        self.add_inner(u1, u2, s1, s2, self.z * rhs.z)
+    }
+
+    /// Addition with an affine point (Z = 1), saves a few multiplications
+    pub(crate) fn add_affine(&self, rhs: &Point) -> Self {
+        let (x2, y2) = match rhs {
+            Point::AtInfinity => return *self,
+            Point::Normal(x, y) => (x, y),
+        };
+
+        if self.is_infinity() {
+            return Self::from(rhs);
+        }
+
+        let z1z1 = self.z.square();
+        let u2 = *x2 * z1z1;
+        let s2 = *y2 * self.z * z1z1;
+
+        self.add_inner(self.x, u2, self.y, s2, self.z)
+    }
+
+    fn add_inner(
+        &self,
+        u1: FieldElement,
+        u2: FieldElement,
+        s1: FieldElement,
+        s2: FieldElement,
+        z1z2: FieldElement,
+    ) -> Self {
+        let h = u2 - u1;
+        let r = s2 - s1;
+
+        if h.is_zero() {
+            return if r.is_zero() {
+                self.double()
+            } else {
+                Self::infinity()
+            };
+        }
+
+        let hh = h.square();
+        let hhh = h * hh;
+        let v = u1 * hh;
+
+        let x = r.square() - hhh - v * 2;
+        let y = r * (v - x) - s1 * hhh;
+        let z = z1z2 * h;
+
+        Self { x, y, z }
+    }
+}
+
+impl<'a> From<&'a Point> for JacobianPoint {
+    fn from(point: &'a Point) -> Self {
+        match point {
+            Point::AtInfinity => Self::infinity(),
+            Point::Normal(x, y) => Self {
+                x: *x,
+                y: *y,
+                z: FieldElement::one(),
+            },
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::secp256k1::G;
+
+    #[test]
+    fn matches_affine_arithmetic() {
+        let g = JacobianPoint::from(&*G);
+        let g2 = &*G + &*G;
+        let g3 = &g2 + &*G;
+
+        assert_eq!(g.double().to_affine(), g2);
+        assert_eq!(g.double().add_affine(&G).to_affine(), g3);
+        assert_eq!(g.add_affine(&G).to_affine(), g2);
+        assert_eq!(g.add(&g.double()).to_affine(), g3);
+        assert_eq!(g.double().add(&g).to_affine(), g3);
+        assert_eq!(g.add(&g).to_affine(), g2);
+        assert!(g.add(&g.neg()).is_infinity());
+        let minus_g = Point::Normal(*G.x().unwrap(), G.y().unwrap().add_inv());
+        assert!(g.add_affine(&minus_g).is_infinity());
+        assert_eq!(JacobianPoint::infinity().add_affine(&G).to_affine(), *G);
+    }
+
+    #[test]
+    fn batch_normalization() {
+        let g = JacobianPoint::from(&*G);
+        let points = vec![g, g.double(), JacobianPoint::infinity(), g.double().add(&g)];
+
+        let expected: Vec<_> = points.iter().map(|point| point.to_affine()).collect();
+        assert_eq!(JacobianPoint::batch_to_affine(&points), expected);
+    }
+}
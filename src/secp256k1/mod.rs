@@ -2,11 +2,23 @@ use lazy_static::lazy_static;
 
 pub mod crypto;
 pub mod curve;
+pub mod ellswift;
 pub mod field;
+mod generator;
+mod glv;
+mod jacobian;
+pub mod scalar;
+pub mod schnorr;
 pub mod signature;
+mod wnaf;
 
 use curve::Point;
+use field::FieldElement;
+pub(crate) use generator::mul_g;
 use num_bigint::BigUint;
+use num_traits::Zero;
+
+use crate::curve::{self as generic, CurveParams};
 
 lazy_static! {
     pub(crate) static ref G: Point = ec_point!(
@@ -17,6 +29,31 @@ lazy_static! {
         biguint!("fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141");
 }
 
+/// The `secp256k1` domain parameters, for the generic curve machinery
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Secp256k1;
+
+impl CurveParams for Secp256k1 {
+    type Field = FieldElement;
+
+    fn a() -> FieldElement {
+        FieldElement::zero()
+    }
+
+    fn b() -> FieldElement {
+        *curve::B
+    }
+
+    fn generator() -> generic::Point<FieldElement> {
+        let (x, y) = (G.x().unwrap(), G.y().unwrap()); // safe, not the point at infinity
+        generic::Point::Normal(*x, *y)
+    }
+
+    fn order() -> &'static BigUint {
+        &N
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,220 @@
+use core::ops::{Add, Mul, Neg, Sub};
+
+use num_bigint::BigUint;
+use num_traits::{One, Zero};
+use subtle::{Choice, ConstantTimeEq};
+
+use crate::utils::to_bytes32_be;
+
+use super::N;
+
+/// An integer modulo the order `N` of the `secp256k1` group, equality is checked
+/// in constant time since scalars are usually secrets
+#[derive(Debug, Clone)]
+pub struct Scalar(pub(crate) BigUint);
+
+impl Scalar {
+    /// Build a new scalar, reducing the given number modulo `N`
+    pub fn new<U>(number: U) -> Self
+    where
+        U: Into<BigUint>,
+    {
+        Self(number.into() % &*N)
+    }
+
+    /// Interpret the given bytes as a big endian number, reducing it modulo `N`
+    pub fn from_bytes_be(bytes: [u8; 32]) -> Self {
+        Self::new(BigUint::from_bytes_be(&bytes))
+    }
+
+    /// Interpret the given bytes as a big endian number, only if it's already
+    /// lower than `N`
+    pub fn from_canonical_bytes(bytes: [u8; 32]) -> Option<Self> {
+        let number = BigUint::from_bytes_be(&bytes);
+        if number < *N {
+            Some(Self(number))
+        } else {
+            None
+        }
+    }
+
+    /// Big endian representation, always 32 bytes long
+    pub fn to_bytes_be(&self) -> [u8; 32] {
+        to_bytes32_be(&self.0).unwrap() // safe, < N
+    }
+
+    /// Get the _additive inverse_ of this scalar.
+    #[inline]
+    pub fn negate(&self) -> Self {
+        if self.0.is_zero() {
+            self.clone()
+        } else {
+            Self(&*N - &self.0)
+        }
+    }
+
+    /// Get the _multiplicative inverse_ of this scalar, zero has no inverse and maps to zero.
+    #[inline]
+    pub fn invert(&self) -> Self {
+        // Fermat's little theorem, N is prime
+        Self(self.0.modpow(&(&*N - 2usize), &N))
+    }
+
+    /// Whether this scalar is greater than `N / 2`
+    pub fn is_high(&self) -> bool {
+        self.0 > &*N >> 1
+    }
+}
+
+impl From<Scalar> for BigUint {
+    fn from(scalar: Scalar) -> Self {
+        scalar.0
+    }
+}
+
+impl<'a> From<&'a Scalar> for BigUint {
+    fn from(scalar: &'a Scalar) -> Self {
+        scalar.0.clone()
+    }
+}
+
+impl ConstantTimeEq for Scalar {
+    fn ct_eq(&self, other: &Self) -> Choice {
+        self.to_bytes_be().ct_eq(&other.to_bytes_be())
+    }
+}
+
+impl PartialEq for Scalar {
+    fn eq(&self, other: &Self) -> bool {
+        self.ct_eq(other).into()
+    }
+}
+
+impl Eq for Scalar {}
+
+impl Zero for Scalar {
+    fn zero() -> Self {
+        Scalar(BigUint::zero())
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+}
+
+impl One for Scalar {
+    fn one() -> Self {
+        Scalar(BigUint::one())
+    }
+}
+
+impl Add<&Scalar> for &Scalar {
+    type Output = Scalar;
+
+    fn add(self, rhs: &Scalar) -> Self::Output {
+        Scalar::new(&self.0 + &rhs.0)
+    }
+}
+
+impl Sub<&Scalar> for &Scalar {
+    type Output = Scalar;
+
+    fn sub(self, rhs: &Scalar) -> Self::Output {
+        self.add(&rhs.negate())
+    }
+}
+
+impl Mul<&Scalar> for &Scalar {
+    type Output = Scalar;
+
+    fn mul(self, rhs: &Scalar) -> Self::Output {
+        Scalar::new(&self.0 * &rhs.0)
+    }
+}
+
+impl Neg for &Scalar {
+    type Output = Scalar;
+
+    fn neg(self) -> Self::Output {
+        self.negate()
+    }
+}
+
+impl Neg for Scalar {
+    type Output = Scalar;
+
+    fn neg(self) -> Self::Output {
+        self.negate()
+    }
+}
+
+forward_binop_impl!(for non-copyable Scalar where Add does add);
+forward_binop_impl!(for non-copyable Scalar where Sub does sub);
+forward_binop_impl!(for non-copyable Scalar where Mul does mul);
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use num_bigint::RandBigInt;
+
+    use super::*;
+
+    #[test]
+    fn arithmetic() {
+        let a = Scalar::new(N.clone() + 5usize);
+        assert_eq!(a, Scalar::new(5usize));
+
+        let b = Scalar::new(7usize);
+        assert_eq!(&a - &b, Scalar::new(&*N - 2usize));
+        assert_eq!(&a + a.negate(), Scalar::zero());
+        assert_eq!(&b * b.invert(), Scalar::one());
+        assert!(Scalar::zero().invert().is_zero());
+
+        assert!(!Scalar::new(&*N >> 1).is_high());
+        assert!(Scalar::new((&*N >> 1) + 1usize).is_high());
+    }
+
+    #[test]
+    fn byte_encoding() {
+        let mut rng = rand::thread_rng();
+        let mut numbers = vec![BigUint::zero(), BigUint::one(), &*N - 1usize];
+        numbers.extend((0..16).map(|_| rng.gen_biguint_below(&N)));
+
+        for number in numbers {
+            let scalar = Scalar::new(number.clone());
+            let bytes = scalar.to_bytes_be();
+            assert_eq!(BigUint::from_bytes_be(&bytes), number);
+            assert_eq!(Scalar::from_bytes_be(bytes), scalar);
+            assert_eq!(Scalar::from_canonical_bytes(bytes), Some(scalar));
+        }
+
+        // N and above are only accepted reduced
+        for extra in [0u8, 1, 0xff].iter() {
+            let bytes = to_bytes32_be(&(&*N + *extra)).unwrap();
+            assert_eq!(Scalar::from_bytes_be(bytes), Scalar::new(*extra));
+            assert_eq!(Scalar::from_canonical_bytes(bytes), None);
+        }
+    }
+
+    #[test]
+    fn bytes() {
+        let bytes = Scalar::new(0x0102usize).to_bytes_be();
+        assert_eq!(bytes[30..], [0x01, 0x02]);
+        assert_eq!(Scalar::from_bytes_be(bytes), Scalar::new(0x0102usize));
+        let n = to_bytes32_be(&N).unwrap();
+        assert!(Scalar::from_bytes_be(n).is_zero());
+        assert!(Scalar::from_canonical_bytes(n).is_none());
+        assert!(Scalar::from_canonical_bytes([0xff; 32]).is_none());
+        assert_eq!(
+            Scalar::from_canonical_bytes(bytes),
+            Some(Scalar::new(0x0102usize))
+        );
+    }
+
+    #[test]
+    fn constant_time_equality() {
+        let a = Scalar::new(0xdeadbeefusize);
+        assert!(bool::from(a.ct_eq(&Scalar::new(0xdeadbeefusize))));
+        assert!(!bool::from(a.ct_eq(&Scalar::new(0xdeadbeeeusize))));
+        assert!(!bool::from(a.ct_eq(&Scalar::zero())));
+    }
+}
@@ -0,0 +1,151 @@
+//! BIP340 Schnorr signatures, public keys are only their x coordinate
+
+use crate::utils::tagged_hash;
+use crate::{Error, Result};
+
+use super::crypto::{PrivateKey, PublicKey};
+use super::curve::Point;
+use super::field::FieldElement;
+use super::glv::double_mul_vartime;
+use super::mul_g;
+use super::scalar::Scalar;
+
+/// X-only public key of `key`, the one with an even y coordinate
+pub fn x_only_public_key(key: &PrivateKey) -> [u8; 32] {
+    match &key.public_key().ec_point {
+        Point::Normal(x, _) => x.to_bytes_be(),
+        Point::AtInfinity => [0; 32],
+    }
+}
+
+/// Sign the 32 bytes `message`, `aux_rand` should be fresh randomness but any
+/// value (zeros included) still produces a valid signature
+pub fn sign(key: &PrivateKey, message: &[u8; 32], aux_rand: &[u8; 32]) -> Result<[u8; 64]> {
+    let (public_x, public_odd) = match &key.public_key().ec_point {
+        Point::Normal(x, y) => (x.to_bytes_be(), !y.is_even()),
+        Point::AtInfinity => return Err(Error::InvalidPrivateKey),
+    };
+
+    let secret = if public_odd {
+        key.secret().negate()
+    } else {
+        key.secret().clone()
+    };
+
+    let mut masked = secret.to_bytes_be();
+    for (byte, mask) in masked.iter_mut().zip(&tagged_hash("BIP0340/aux", aux_rand)) {
+        *byte ^= mask;
+    }
+
+    let nonce = tagged_hash("BIP0340/nonce", [&masked[..], &public_x, message].concat());
+    let nonce = Scalar::from_bytes_be(nonce);
+    let (nonce_x, nonce) = match mul_g(&nonce) {
+        Point::Normal(x, y) if y.is_even() => (x.to_bytes_be(), nonce),
+        Point::Normal(x, _) => (x.to_bytes_be(), nonce.negate()),
+        Point::AtInfinity => return Err(Error::InvalidSignature("zero nonce")),
+    };
+
+    let challenge = challenge(&nonce_x, &public_x, message);
+    let s = nonce + challenge * secret;
+
+    let mut signature = [0; 64];
+    signature[..32].copy_from_slice(&nonce_x);
+    signature[32..].copy_from_slice(&s.to_bytes_be());
+    Ok(signature)
+}
+
+/// Whether `signature` signs `message` for the x-only `public_key`, invalid keys
+/// and out of range values just make it invalid
+pub fn verify(public_key: &[u8; 32], message: &[u8; 32], signature: &[u8; 64]) -> bool {
+    let public = match PublicKey::from_x(public_key, false) {
+        Ok(public) => public,
+        Err(_) => return false,
+    };
+
+    let mut r = [0; 32];
+    r.copy_from_slice(&signature[..32]);
+    let mut s = [0; 32];
+    s.copy_from_slice(&signature[32..]);
+
+    let (r, s) = match (
+        FieldElement::from_canonical_bytes(r),
+        Scalar::from_canonical_bytes(s),
+    ) {
+        (Some(r), Some(s)) => (r, s),
+        _ => return false,
+    };
+
+    let challenge = challenge(&r.to_bytes_be(), public_key, message);
+    match double_mul_vartime(&s, &public.ec_point, &challenge.negate()) {
+        Point::Normal(x, y) => y.is_even() && x == r,
+        Point::AtInfinity => false,
+    }
+}
+
+fn challenge(nonce_x: &[u8; 32], public_x: &[u8; 32], message: &[u8; 32]) -> Scalar {
+    let data = [&nonce_x[..], public_x, message].concat();
+    Scalar::from_bytes_be(tagged_hash("BIP0340/challenge", data))
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+
+    #[test]
+    fn bip340_vectors() {
+        let cases = [
+            (
+                hex!("0000000000000000000000000000000000000000000000000000000000000003"),
+                hex!("f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9"),
+                [0; 32],
+                [0; 32],
+                hex!("e907831f80848d1069a5371b402410364bdf1c5f8307b0084c55f1ce2dca821525f66a4a85ea8b71e482a74f382d2ce5ebeee8fdb2172f477df4900d310536c0"),
+            ),
+            (
+                hex!("b7e151628aed2a6abf7158809cf4f3c762e7160f38b4da56a784d9045190cfef"),
+                hex!("dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659"),
+                hex!("0000000000000000000000000000000000000000000000000000000000000001"),
+                hex!("243f6a8885a308d313198a2e03707344a4093822299f31d0082efa98ec4e6c89"),
+                hex!("6896bd60eeae296db48a229ff71dfe071bde413e6d43f917dc8dcf8c78de33418906d11ac976abccb20b091292bff4ea897efcb639ea871cfa95f6de339e4b0a"),
+            ),
+        ];
+
+        for (secret, public_key, aux_rand, message, expected) in cases.iter() {
+            let key = PrivateKey::from_bytes_be(secret);
+            assert_eq!(&x_only_public_key(&key), public_key);
+
+            let signature = sign(&key, message, aux_rand).unwrap();
+            assert_eq!(&signature[..], &expected[..]);
+            assert!(verify(public_key, message, &signature));
+
+            let mut tampered = signature;
+            tampered[63] ^= 1;
+            assert!(!verify(public_key, message, &tampered));
+            assert!(!verify(public_key, &[0xff; 32], &signature));
+        }
+    }
+
+    #[test]
+    fn invalid_signatures() {
+        let key = PrivateKey::new(3u32);
+        let public_key = x_only_public_key(&key);
+        let signature = sign(&key, &[0; 32], &[0; 32]).unwrap();
+
+        // public key not on the curve
+        let mut invalid = public_key;
+        invalid[31] = 0x05;
+        invalid[..31].copy_from_slice(&[0; 31]);
+        assert!(!verify(&invalid, &[0; 32], &signature));
+
+        // r not lower than the field prime, s not lower than the group order
+        let mut high_r = signature;
+        high_r[..32].copy_from_slice(&[0xff; 32]);
+        assert!(!verify(&public_key, &[0; 32], &high_r));
+
+        let mut high_s = signature;
+        high_s[32..].copy_from_slice(&[0xff; 32]);
+        assert!(!verify(&public_key, &[0; 32], &high_s));
+    }
+}
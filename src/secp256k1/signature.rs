@@ -1,13 +1,24 @@
-use std::io::Read;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use core::fmt::{self, Display, Formatter, LowerHex};
+use core::str::FromStr;
 
 use bytes::Buf;
 use num_bigint::BigUint;
+use num_traits::Zero;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use subtle::{Choice, ConstantTimeEq};
 
-use crate::utils::strip_start;
+use crate::curve::CurveParams;
+use crate::utils::{strip_start, to_bytes32_be};
 use crate::{Error, Result};
 
 use super::crypto::PublicKey;
-use super::{G, N};
+use super::glv::double_mul_vartime;
+use super::scalar::Scalar;
+use super::Secp256k1;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Signature {
@@ -30,26 +41,54 @@ impl Signature {
         B: AsRef<[u8]>,
     {
         let digest = digest.as_ref();
-        if digest.len() != 32 {
-            return Err(Error::InvalidDigestLength(digest.len()));
+        let digest: [u8; 32] = digest
+            .try_into()
+            .map_err(|_| Error::InvalidDigestLength(digest.len()))?;
+
+        let n = Secp256k1::order();
+        if self.r.is_zero() || &self.r >= n || self.s.is_zero() || &self.s >= n {
+            return Ok(false);
+        }
+
+        let z = Scalar::from_bytes_be(digest);
+        let r = Scalar::new(self.r.clone());
+        let s_inv = Scalar::new(self.s.clone()).invert();
+
+        let u = z * &s_inv;
+        let v = &r * s_inv;
+
+        let total = double_mul_vartime(&u, &pub_key.ec_point, &v);
+        match total.x() {
+            Some(x) => Ok(Scalar::from_bytes_be(x.to_bytes_be()) == r),
+            None => Ok(false),
         }
+    }
 
-        let z = BigUint::from_bytes_be(digest);
-        let s_inv = self.s.modpow(&(&*N - 2usize), &*N);
+    /// Verify many `(digest, signature, public key)` triples at once, checked on
+    /// all cores with the `parallel` feature. Only true if every signature is valid.
+    pub fn verify_batch<B>(batch: &[(B, &Signature, &PublicKey)]) -> Result<bool>
+    where
+        B: AsRef<[u8]> + Sync,
+    {
+        #[cfg(feature = "parallel")]
+        let results = batch.par_iter();
 
-        let u = (&z * &s_inv) % &*N;
-        let v = (&self.r * &s_inv) % &*N;
+        #[cfg(not(feature = "parallel"))]
+        let results = batch.iter();
+
+        let results: Result<Vec<_>> = results
+            .map(|(digest, signature, pub_key)| signature.is_valid(digest, pub_key))
+            .collect();
 
-        let total = &*G * u + &pub_key.ec_point * v;
-        Ok(total.x().unwrap().0 == self.r)
+        Ok(results?.into_iter().all(|valid| valid))
     }
 
     /// Serialize signature with DER format
     pub fn serialize(&self) -> Result<Vec<u8>> {
         let r_bigendian = self.r.to_bytes_be();
         let r_bigendian = strip_start(&r_bigendian, 0x00);
-        let r_bigendian = if r_bigendian[0] & 0x80 == 0x80 {
-            std::iter::once(0x00u8)
+        let r_bigendian = if r_bigendian.first().is_none_or(|byte| byte & 0x80 == 0x80) {
+            core::iter::once(0x00u8)
                 .chain(r_bigendian.iter().copied())
                 .collect::<Vec<_>>()
         } else {
@@ -61,8 +100,8 @@ impl Signature {
 
         let s_bigendian = self.s.to_bytes_be();
         let s_bigendian = strip_start(&s_bigendian, 0x00);
-        let s_bigendian = if s_bigendian[0] & 0x80 == 0x80 {
-            std::iter::once(0x00u8)
+        let s_bigendian = if s_bigendian.first().is_none_or(|byte| byte & 0x80 == 0x80) {
+            core::iter::once(0x00u8)
                 .chain(s_bigendian.iter().copied())
                 .collect::<Vec<_>>()
         } else {
@@ -83,18 +122,17 @@ impl Signature {
         Ok(serialized)
     }
 
-    pub fn deserialize(bytes: impl Buf) -> Result<Self> {
+    pub fn deserialize(mut bytes: impl Buf) -> Result<Self> {
         let size = bytes.remaining();
-        let mut reader = bytes.reader();
 
         let mut buf = [0u8; 4];
-        reader.read_exact(&mut buf)?;
+        read_exact(&mut bytes, &mut buf)?;
 
         if buf[0] != 0x30 {
             return Err(Error::InvalidSignature("bad compound"));
         }
 
-        let claimed_size = (buf[1] + 2) as usize;
+        let claimed_size = usize::from(buf[1]) + 2;
         if claimed_size != size {
             return Err(Error::InvalidSignature("bad signature size"));
         }
@@ -105,11 +143,11 @@ impl Signature {
 
         let r_size = buf[3] as usize;
         let mut r_bytes = vec![0u8; r_size];
-        reader.read_exact(&mut r_bytes)?;
+        read_exact(&mut bytes, &mut r_bytes)?;
         let r = BigUint::from_bytes_be(&r_bytes);
 
         let mut buf = [0u8; 2];
-        reader.read_exact(&mut buf)?;
+        read_exact(&mut bytes, &mut buf)?;
 
         if buf[0] != 0x02 {
             return Err(Error::InvalidSignature("bad marker"));
@@ -117,7 +155,7 @@ impl Signature {
 
         let s_size = buf[1] as usize;
         let mut s_bytes = vec![0u8; s_size];
-        reader.read_exact(&mut s_bytes)?;
+        read_exact(&mut bytes, &mut s_bytes)?;
         let s = BigUint::from_bytes_be(&s_bytes);
 
         if size != 6 + r_size + s_size {
@@ -128,9 +166,60 @@ impl Signature {
     }
 }
 
-#[cfg(test)]
+/// Fill `dst` from the buffer, failing if there aren't enough bytes left
+fn read_exact(bytes: &mut impl Buf, dst: &mut [u8]) -> Result<()> {
+    if bytes.remaining() < dst.len() {
+        return Err(Error::InvalidSignature("signature too short"));
+    }
+
+    bytes.copy_to_slice(dst);
+    Ok(())
+}
+
+/// DER format in hex
+impl LowerHex for Signature {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        let serialized = self.serialize().map_err(|_| fmt::Error)?;
+        write!(fmt, "{}", hex::encode(serialized))
+    }
+}
+
+impl Display for Signature {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{:x}", self)
+    }
+}
+
+/// DER format in hex
+impl FromStr for Signature {
+    type Err = Error;
+
+    fn from_str(string: &str) -> Result<Self> {
+        let bytes = hex::decode(string).map_err(|_| Error::InvalidHex)?;
+        Self::deserialize(bytes.as_slice())
+    }
+}
+
+#[cfg(feature = "serde")]
+serde_impl!(
+    Signature,
+    "a DER encoded signature",
+    Signature::serialize,
+    Signature::deserialize
+);
+
+impl ConstantTimeEq for Signature {
+    fn ct_eq(&self, other: &Self) -> Choice {
+        // fixed size for any valid signature (r, s < N)
+        let bytes = |n: &BigUint| to_bytes32_be(n).map_or_else(|| n.to_bytes_be(), Vec::from);
+        bytes(&self.r).ct_eq(&bytes(&other.r)) & bytes(&self.s).ct_eq(&bytes(&other.s))
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use hex_literal::hex;
+    use subtle::ConstantTimeEq;
 
     use super::Signature;
 
@@ -150,5 +239,60 @@ mod tests {
 
         let deserialized = Signature::deserialize(serialized.as_slice()).unwrap();
         assert_eq!(deserialized, signature);
+        assert!(bool::from(deserialized.ct_eq(&signature)));
+        assert!(!bool::from(deserialized.ct_eq(&Signature::new(0u8, 1u8))));
+
+        assert!(Signature::deserialize(&serialized[..20]).is_err());
+
+        let hex = signature.to_string();
+        assert_eq!(hex, hex::encode(&serialized));
+        assert_eq!(format!("{:x}", signature), hex);
+        assert_eq!(hex.parse::<Signature>().unwrap(), signature);
+        assert!("30zz".parse::<Signature>().is_err());
+    }
+
+    #[test]
+    fn malformed_der() {
+        let valid = hex!("3006020101020102");
+        assert_eq!(
+            Signature::deserialize(&valid[..]).unwrap(),
+            Signature::new(1u8, 2u8)
+        );
+
+        // truncated anywhere
+        for len in 0..valid.len() {
+            assert!(Signature::deserialize(&valid[..len]).is_err());
+        }
+
+        // lengths that don't match the content
+        let bad_lengths = [
+            hex!("3007020101020102"),
+            hex!("3006020201020102"),
+            hex!("3006020101020202"),
+            hex!("3006020001020102"),
+        ];
+        for bytes in bad_lengths.iter() {
+            assert!(Signature::deserialize(&bytes[..]).is_err());
+        }
+
+        // bad markers
+        assert!(Signature::deserialize(&hex!("3106020101020102")[..]).is_err());
+        assert!(Signature::deserialize(&hex!("3006030101020102")[..]).is_err());
+        assert!(Signature::deserialize(&hex!("3006020101030102")[..]).is_err());
+
+        // zero is a single zero byte
+        let zero = hex!("3006020100020101");
+        let signature: Signature = "3006020100020101".parse().unwrap();
+        assert_eq!(signature, Signature::new(0u8, 1u8));
+        assert_eq!(signature.serialize().unwrap(), zero);
+        assert_eq!(signature.to_string(), "3006020100020101");
+        assert_eq!(
+            Signature::new(1u8, 0u8).serialize().unwrap(),
+            hex!("3006020101020100")
+        );
+
+        // the claimed size doesn't fit in a byte
+        assert!(Signature::deserialize(&hex!("30ff020101020102")[..]).is_err());
+        assert!(Signature::deserialize(&hex!("30fe020101020102")[..]).is_err());
     }
 }
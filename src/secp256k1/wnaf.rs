@@ -0,0 +1,122 @@
+use alloc::vec::Vec;
+
+use num_bigint::BigUint;
+use num_integer::Integer;
+use num_traits::{ToPrimitive, Zero};
+
+use super::jacobian::JacobianPoint;
+
+/// Window used for variable base multiplications, needs `2^(w - 2)` precomputed points
+pub(crate) const WINDOW: u32 = 5;
+
+/// Width-`w` non-adjacent form of `k`, least significant digit first. Every non
+/// zero digit is odd, lies in `(-2^(w-1), 2^(w-1))` and is followed by at least
+/// `w - 1` zeroes.
+pub(crate) fn wnaf(k: &BigUint, w: u32) -> Vec<i32> {
+    let modulus = 1i64 << w;
+    let half = modulus >> 1;
+
+    let mut k = k.clone();
+    let mut digits = Vec::with_capacity(k.bits() as usize + 1);
+
+    while !k.is_zero() {
+        if k.is_odd() {
+            let low = (&k % (modulus as u64)).to_i64().unwrap(); // safe, < 2^w
+            let digit = if low >= half { low - modulus } else { low };
+
+            if digit < 0 {
+                k += (-digit) as u64;
+            } else {
+                k -= digit as u64;
+            }
+
+            digits.push(digit as i32);
+        } else {
+            digits.push(0);
+        }
+
+        k >>= 1;
+    }
+
+    digits
+}
+
+/// Odd multiples `P, 3P, 5P, ...` of the given point, `2^(w - 2)` of them
+pub(crate) fn odd_multiples(point: &JacobianPoint, w: u32) -> Vec<JacobianPoint> {
+    let double = point.double();
+    let mut multiples = Vec::with_capacity(1 << (w - 2));
+    multiples.push(*point);
+
+    for i in 1..(1 << (w - 2)) {
+        let next = multiples[i - 1].add(&double);
+        multiples.push(next);
+    }
+
+    multiples
+}
+
+/// Add `digit * P` to `acc` using the table of odd multiples of `P`
+#[inline]
+pub(crate) fn add_digit(
+    acc: &JacobianPoint,
+    digit: i32,
+    multiples: &[JacobianPoint],
+) -> JacobianPoint {
+    match digit {
+        0 => *acc,
+        d if d > 0 => acc.add(&multiples[(d / 2) as usize]),
+        d => acc.add(&multiples[(-d / 2) as usize].neg()),
+    }
+}
+
+/// Interleaved multi-scalar multiplication (Strauss), computes `sum(k_i * P_i)`
+/// sharing the doublings between all the terms. Each term is the table of odd
+/// multiples of `P_i` along with the wNAF digits of `k_i`.
+pub(crate) fn strauss(terms: &[(&[JacobianPoint], &[i32])]) -> JacobianPoint {
+    let length = terms
+        .iter()
+        .map(|(_, digits)| digits.len())
+        .max()
+        .unwrap_or_default();
+
+    let mut result = JacobianPoint::infinity();
+    for i in (0..length).rev() {
+        result = result.double();
+
+        for (multiples, digits) in terms {
+            if let Some(digit) = digits.get(i) {
+                result = add_digit(&result, *digit, multiples);
+            }
+        }
+    }
+
+    result
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use num_bigint::{BigInt, RandBigInt};
+
+    use super::*;
+    use crate::secp256k1::N;
+
+    #[test]
+    fn wnaf_digits() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..16 {
+            let k = rng.gen_biguint_below(&N);
+            let digits = wnaf(&k, WINDOW);
+
+            let value = digits
+                .iter()
+                .rev()
+                .fold(BigInt::zero(), |acc, d| acc * 2 + *d);
+            assert_eq!(value.to_biguint().unwrap(), k);
+
+            for (i, d) in digits.iter().enumerate().filter(|(_, d)| **d != 0) {
+                assert!(d % 2 != 0 && d.abs() < 16);
+                assert!(digits[i + 1..].iter().take(4).all(|d| *d == 0));
+            }
+        }
+    }
+}
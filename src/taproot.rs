@@ -0,0 +1,426 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+use num_traits::Zero;
+
+use crate::secp256k1::crypto::{PrivateKey, PublicKey};
+use crate::secp256k1::curve::Point;
+use crate::secp256k1::mul_g;
+use crate::secp256k1::scalar::Scalar;
+use crate::utils::tagged_hash;
+use crate::{Error, Result};
+
+/// Leaf version of tapscript (BIP342)
+pub const TAPROOT_LEAF_TAPSCRIPT: u8 = 0xc0;
+/// Deepest a leaf can be in a script tree
+const TAPROOT_CONTROL_MAX_NODE_COUNT: usize = 128;
+
+/// BIP341 tweak of an x-only internal key, committing to the script tree with the
+/// given merkle root (if any). Returns the x-only output key and whether its y
+/// coordinate is odd.
+pub fn tweak_public_key(
+    internal_key: &[u8; 32],
+    merkle_root: Option<&[u8; 32]>,
+) -> Result<([u8; 32], bool)> {
+    let internal = PublicKey::from_x(internal_key, false)?;
+
+    let mut data = [0u8; 64];
+    data[..32].copy_from_slice(internal_key);
+    let data = match merkle_root {
+        Some(merkle_root) => {
+            data[32..].copy_from_slice(merkle_root);
+            &data[..]
+        }
+        None => &data[..32],
+    };
+
+    let tweak = tagged_hash("TapTweak", data);
+    let tweak = Scalar::from_canonical_bytes(tweak).ok_or(Error::InvalidTaprootTweak)?;
+
+    match mul_g(&tweak) + &internal.ec_point {
+        Point::Normal(x, y) => {
+            let output_key = x.to_bytes_be()[..].try_into().unwrap(); // safe, 32 bytes
+            Ok((output_key, !y.is_even()))
+        }
+
+        Point::AtInfinity => Err(Error::InvalidTaprootTweak),
+    }
+}
+
+/// BIP341 tweak of a private key, its public key becomes the output key given by
+/// [`tweak_public_key`] for the same merkle root
+pub fn tweak_private_key(key: &PrivateKey, merkle_root: Option<&[u8; 32]>) -> Result<PrivateKey> {
+    let (internal_key, secret) = match &key.public_key().ec_point {
+        Point::Normal(x, y) if y.is_even() => (x.to_bytes_be(), key.secret().clone()),
+        Point::Normal(x, _) => (x.to_bytes_be(), key.secret().negate()),
+        Point::AtInfinity => return Err(Error::InvalidPrivateKey),
+    };
+
+    let mut data = internal_key.to_vec();
+    data.extend(merkle_root.iter().flat_map(|root| root.iter()));
+
+    let tweak = tagged_hash("TapTweak", data);
+    let tweak = Scalar::from_canonical_bytes(tweak).ok_or(Error::InvalidTaprootTweak)?;
+
+    let tweaked = secret + tweak;
+    if tweaked.is_zero() {
+        return Err(Error::InvalidTaprootTweak);
+    }
+
+    Ok(PrivateKey::from_bytes_be(tweaked.to_bytes_be()))
+}
+
+/// BIP341 leaf hash of `script` under `leaf_version`
+pub fn tapleaf_hash(leaf_version: u8, script: &[u8]) -> [u8; 32] {
+    let mut data = vec![leaf_version];
+    // compact size of the script, the varint module needs std
+    let len = script.len() as u64;
+    match len {
+        0..=0xfc => data.push(len as u8),
+        0xfd..=0xffff => {
+            data.push(0xfd);
+            data.extend_from_slice(&(len as u16).to_le_bytes());
+        }
+        0x10000..=0xffffffff => {
+            data.push(0xfe);
+            data.extend_from_slice(&(len as u32).to_le_bytes());
+        }
+        _ => {
+            data.push(0xff);
+            data.extend_from_slice(&len.to_le_bytes());
+        }
+    }
+
+    data.extend_from_slice(script);
+    tagged_hash("TapLeaf", data)
+}
+
+/// BIP341 branch hash, the children are sorted so their order doesn't matter
+pub fn tapbranch_hash(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let (left, right) = match left <= right {
+        true => (left, right),
+        false => (right, left),
+    };
+
+    let mut data = [0u8; 64];
+    data[..32].copy_from_slice(left);
+    data[32..].copy_from_slice(right);
+    tagged_hash("TapBranch", data)
+}
+
+/// A leaf of a script tree and the hashes needed to reach the root from it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TapLeaf {
+    pub(crate) script: Vec<u8>,
+    pub(crate) leaf_version: u8,
+    /// Siblings from the leaf up to the root
+    pub(crate) merkle_path: Vec<[u8; 32]>,
+}
+
+impl TapLeaf {
+    pub fn script(&self) -> &[u8] {
+        &self.script
+    }
+
+    pub fn leaf_version(&self) -> u8 {
+        self.leaf_version
+    }
+
+    pub fn merkle_path(&self) -> &[[u8; 32]] {
+        &self.merkle_path
+    }
+
+    pub fn leaf_hash(&self) -> [u8; 32] {
+        tapleaf_hash(self.leaf_version, &self.script)
+    }
+}
+
+/// A complete subtree, the hash of its root and its leaves
+#[derive(Debug, Clone)]
+struct TapNode {
+    hash: [u8; 32],
+    leaves: Vec<TapLeaf>,
+}
+
+impl TapNode {
+    /// Parent of both nodes, every leaf below one gets the other as a sibling
+    fn combine(mut self, mut other: Self) -> Self {
+        self.leaves
+            .iter_mut()
+            .for_each(|leaf| leaf.merkle_path.push(other.hash));
+        other
+            .leaves
+            .iter_mut()
+            .for_each(|leaf| leaf.merkle_path.push(self.hash));
+
+        self.hash = tapbranch_hash(&self.hash, &other.hash);
+        self.leaves.append(&mut other.leaves);
+        self
+    }
+}
+
+/// Assembles a script tree from leaves given depth first with their depths, the
+/// same way as Bitcoin Core's `TaprootBuilder`
+#[derive(Debug, Clone, Default)]
+pub struct TaprootBuilder {
+    /// Subtrees at each depth still waiting for their sibling
+    branches: Vec<Option<TapNode>>,
+}
+
+impl TaprootBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a tapscript leaf at `depth`, leaves must be added depth first from the
+    /// left of the tree
+    pub fn add_leaf(self, depth: usize, script: &[u8]) -> Result<Self> {
+        self.add_leaf_with_version(depth, script, TAPROOT_LEAF_TAPSCRIPT)
+    }
+
+    /// Same as [`TaprootBuilder::add_leaf`] under another leaf version
+    pub fn add_leaf_with_version(
+        mut self,
+        depth: usize,
+        script: &[u8],
+        leaf_version: u8,
+    ) -> Result<Self> {
+        if leaf_version & 1 != 0 {
+            return Err(Error::InvalidTaprootTree("odd leaf version"));
+        }
+
+        if depth > TAPROOT_CONTROL_MAX_NODE_COUNT {
+            return Err(Error::InvalidTaprootTree("leaf too deep"));
+        }
+
+        let leaf = TapLeaf {
+            script: script.to_vec(),
+            leaf_version,
+            merkle_path: Vec::new(),
+        };
+        let mut node = TapNode {
+            hash: leaf.leaf_hash(),
+            leaves: vec![leaf],
+        };
+
+        // merge with the pending siblings for as long as subtrees complete
+        let mut depth = depth;
+        while let Some(Some(_)) = self.branches.get(depth) {
+            if depth == 0 {
+                return Err(Error::InvalidTaprootTree("tree already complete"));
+            }
+
+            let sibling = self.branches.pop().flatten().unwrap(); // safe, checked above
+            node = sibling.combine(node);
+            depth -= 1;
+        }
+
+        if self.branches.len() <= depth {
+            self.branches.resize(depth + 1, None);
+        }
+
+        self.branches[depth] = Some(node);
+        Ok(self)
+    }
+
+    /// Whether there are no leaves or they form a full tree
+    pub fn is_complete(&self) -> bool {
+        matches!(self.branches.as_slice(), [] | [Some(_)])
+    }
+
+    /// Commit to the tree with `internal_key`, without leaves the output can only be
+    /// spent with the key path
+    pub fn finalize(mut self, internal_key: &[u8; 32]) -> Result<TaprootSpendInfo> {
+        if !self.is_complete() {
+            return Err(Error::InvalidTaprootTree("incomplete tree"));
+        }
+
+        let root = self.branches.pop().flatten();
+        let merkle_root = root.as_ref().map(|root| root.hash);
+        let (output_key, output_key_parity) = tweak_public_key(internal_key, merkle_root.as_ref())?;
+
+        Ok(TaprootSpendInfo {
+            internal_key: *internal_key,
+            merkle_root,
+            output_key,
+            output_key_parity,
+            leaves: root.map(|root| root.leaves).unwrap_or_default(),
+        })
+    }
+}
+
+/// What's needed to spend a taproot output, by the key path or any of its leaves
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaprootSpendInfo {
+    pub(crate) internal_key: [u8; 32],
+    pub(crate) merkle_root: Option<[u8; 32]>,
+    pub(crate) output_key: [u8; 32],
+    pub(crate) output_key_parity: bool,
+    pub(crate) leaves: Vec<TapLeaf>,
+}
+
+impl TaprootSpendInfo {
+    pub fn internal_key(&self) -> &[u8; 32] {
+        &self.internal_key
+    }
+
+    pub fn merkle_root(&self) -> Option<&[u8; 32]> {
+        self.merkle_root.as_ref()
+    }
+
+    /// X-only key of the output, the program of its P2TR script
+    pub fn output_key(&self) -> &[u8; 32] {
+        &self.output_key
+    }
+
+    /// Whether the y coordinate of the output key is odd
+    pub fn output_key_parity(&self) -> bool {
+        self.output_key_parity
+    }
+
+    /// Leaves in the order they were added
+    pub fn leaves(&self) -> &[TapLeaf] {
+        &self.leaves
+    }
+
+    /// Control block of the first leaf with `script` under `leaf_version`: the leaf
+    /// version with the parity bit, the internal key and the merkle path
+    pub fn control_block(&self, script: &[u8], leaf_version: u8) -> Option<Vec<u8>> {
+        let leaf = self
+            .leaves
+            .iter()
+            .find(|leaf| leaf.script == script && leaf.leaf_version == leaf_version)?;
+
+        let mut control_block = vec![leaf_version | self.output_key_parity as u8];
+        control_block.extend_from_slice(&self.internal_key);
+        leaf.merkle_path
+            .iter()
+            .for_each(|node| control_block.extend_from_slice(node));
+        Some(control_block)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex_literal::hex;
+
+    #[test]
+    fn bip341_tweaks() {
+        // BIP341 wallet test vectors
+        let internal_key = hex!("d6889cb081036e0faefa3a35157ad71086b123b2b144b649798b494c300a961d");
+        let (output_key, _) = tweak_public_key(&internal_key, None).unwrap();
+        assert_eq!(
+            output_key,
+            hex!("53a1f6e454df1aa2776a2814a721372d6258050de330b3c6d10ee8f4e0dda343")
+        );
+
+        let internal_key = hex!("187791b6f712a8ea41c8ecdd0ee77fab3e85263b37e1ec18a3651926b3a6cf27");
+        let merkle_root = hex!("5b75adecf53548f3ec6ad7d78383bf84cc57b55a3127c72b9a2481752dd88b21");
+        let (output_key, _) = tweak_public_key(&internal_key, Some(&merkle_root)).unwrap();
+        assert_eq!(
+            output_key,
+            hex!("147c9c57132f6e7ecddba9800bb0c4449251c92a1e60371ee77557b6620f3ea3")
+        );
+
+        // the tweaked private key matches the tweaked public key
+        let key = PrivateKey::new(12345u32);
+        let internal_key = crate::secp256k1::schnorr::x_only_public_key(&key);
+        for merkle_root in [None, Some(&merkle_root)].iter() {
+            let (output_key, _) = tweak_public_key(&internal_key, *merkle_root).unwrap();
+            let tweaked = tweak_private_key(&key, *merkle_root).unwrap();
+            assert_eq!(
+                crate::secp256k1::schnorr::x_only_public_key(&tweaked),
+                output_key
+            );
+        }
+
+        // x = 5 isn't on the curve
+        let mut invalid = [0u8; 32];
+        invalid[31] = 0x05;
+        assert!(tweak_public_key(&invalid, None).is_err());
+    }
+
+    #[test]
+    fn script_trees() {
+        // BIP341 wallet test vector of a single leaf tree
+        let internal_key = hex!("187791b6f712a8ea41c8ecdd0ee77fab3e85263b37e1ec18a3651926b3a6cf27");
+        let script = hex!("20d85a959b0290bf19bb89ed43c916be835475d013da4b362117393e25a48229b8ac");
+        let info = TaprootBuilder::new()
+            .add_leaf(0, &script)
+            .unwrap()
+            .finalize(&internal_key)
+            .unwrap();
+        assert_eq!(
+            info.merkle_root(),
+            Some(&hex!(
+                "5b75adecf53548f3ec6ad7d78383bf84cc57b55a3127c72b9a2481752dd88b21"
+            ))
+        );
+        assert_eq!(
+            info.output_key(),
+            &hex!("147c9c57132f6e7ecddba9800bb0c4449251c92a1e60371ee77557b6620f3ea3")
+        );
+        assert_eq!(
+            info.control_block(&script, TAPROOT_LEAF_TAPSCRIPT).unwrap(),
+            hex!("c1187791b6f712a8ea41c8ecdd0ee77fab3e85263b37e1ec18a3651926b3a6cf27")
+        );
+
+        // A at depth 1, B and C at depth 2
+        let scripts: [&[u8]; 3] = [&[0x51], &[0x52], &[0x53]];
+        let info = TaprootBuilder::new()
+            .add_leaf(1, scripts[0])
+            .and_then(|builder| builder.add_leaf(2, scripts[1]))
+            .and_then(|builder| builder.add_leaf(2, scripts[2]))
+            .and_then(|builder| builder.finalize(&internal_key))
+            .unwrap();
+
+        let hashes: Vec<_> = scripts
+            .iter()
+            .map(|script| tapleaf_hash(TAPROOT_LEAF_TAPSCRIPT, script))
+            .collect();
+        let root = tapbranch_hash(&hashes[0], &tapbranch_hash(&hashes[1], &hashes[2]));
+        assert_eq!(info.merkle_root(), Some(&root));
+        assert_eq!(info.leaves().len(), 3);
+        assert_eq!(info.leaves()[0].merkle_path().len(), 1);
+        assert_eq!(info.leaves()[2].merkle_path()[0], hashes[1]);
+
+        // every control block leads back to the root
+        for (leaf, script) in info.leaves().iter().zip(scripts.iter()) {
+            assert_eq!(leaf.script(), *script);
+            let control_block = info.control_block(script, TAPROOT_LEAF_TAPSCRIPT).unwrap();
+            assert_eq!(control_block.len(), 33 + 32 * leaf.merkle_path().len());
+            assert_eq!(control_block[0] & 1 == 1, info.output_key_parity());
+
+            let computed = control_block[33..]
+                .chunks(32)
+                .fold(leaf.leaf_hash(), |node, sibling| {
+                    tapbranch_hash(&node, sibling.try_into().unwrap())
+                });
+            assert_eq!(computed, root);
+        }
+        assert!(info
+            .control_block(&[0x54], TAPROOT_LEAF_TAPSCRIPT)
+            .is_none());
+
+        // without leaves only the key path
+        let key_only = TaprootBuilder::new().finalize(&internal_key).unwrap();
+        assert_eq!(key_only.merkle_root(), None);
+        assert_eq!(
+            key_only.output_key(),
+            &tweak_public_key(&internal_key, None).unwrap().0
+        );
+
+        let incomplete = TaprootBuilder::new().add_leaf(1, &[0x51]).unwrap();
+        assert!(!incomplete.is_complete());
+        assert!(incomplete.finalize(&internal_key).is_err());
+        let complete = TaprootBuilder::new().add_leaf(0, &[0x51]).unwrap();
+        assert!(complete.is_complete());
+        assert!(complete.add_leaf(0, &[0x52]).is_err());
+        assert!(TaprootBuilder::new()
+            .add_leaf_with_version(0, &[0x51], 0xc1)
+            .is_err());
+        assert!(TaprootBuilder::new().add_leaf(129, &[0x51]).is_err());
+    }
+}
@@ -1,8 +1,13 @@
-use std::cmp::Ordering;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
 
+#[cfg(feature = "std")]
+use hmac::NewMac;
 use hmac::{Hmac, Mac};
+use num_bigint::BigUint;
 use ripemd160::Ripemd160;
-use sha2::{Digest, Sha256};
+use sha2::{Digest, Sha256, Sha512};
 
 use crate::{Error, Result};
 
@@ -24,6 +29,18 @@ where
     }
 }
 
+/// Big endian representation padded to 32 bytes, `None` if the number doesn't fit
+pub(crate) fn to_bytes32_be(number: &BigUint) -> Option<[u8; 32]> {
+    let bytes = number.to_bytes_be();
+    if bytes.len() > 32 {
+        return None;
+    }
+
+    let mut result = [0u8; 32];
+    result[32 - bytes.len()..].copy_from_slice(&bytes);
+    Some(result)
+}
+
 pub(crate) fn strip_start<T>(arr: &[T], elem: T) -> &[T]
 where
     T: Eq,
@@ -51,7 +68,7 @@ where
     let hasher = Ripemd160::new();
     let digest = hasher.chain(digest).finalize();
 
-    digest.as_slice().to_vec()
+    digest.to_vec()
 }
 
 pub fn hash256<B>(data: B) -> Vec<u8>
@@ -66,7 +83,187 @@ where
     hasher.update(digest);
     let digest = hasher.finalize();
 
-    digest.as_slice().to_vec()
+    digest.to_vec()
+}
+
+/// SHA-1, broken but still reachable through `OP_SHA1`
+#[cfg(feature = "std")]
+pub(crate) fn sha1<B>(data: B) -> [u8; 20]
+where
+    B: AsRef<[u8]>,
+{
+    let data = data.as_ref();
+    let mut state: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+
+    // padded with a one bit, zeros and the bit length to a multiple of 64 bytes
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0x00);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut words = [0u32; 80];
+        for (word, bytes) in words.iter_mut().zip(block.chunks(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for i in 16..80 {
+            words[i] = (words[i - 3] ^ words[i - 8] ^ words[i - 14] ^ words[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, word) in words.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        for (state, value) in state.iter_mut().zip([a, b, c, d, e].iter()) {
+            *state = state.wrapping_add(*value);
+        }
+    }
+
+    let mut digest = [0u8; 20];
+    for (bytes, word) in digest.chunks_mut(4).zip(state.iter()) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+/// SHA3-256 (FIPS 202), only needed for the checksums of Tor v3 addresses
+#[cfg(feature = "std")]
+pub(crate) fn sha3_256<B>(data: B) -> [u8; 32]
+where
+    B: AsRef<[u8]>,
+{
+    const RATE: usize = 136;
+    const ROUND_CONSTANTS: [u64; 24] = [
+        0x0000000000000001,
+        0x0000000000008082,
+        0x800000000000808a,
+        0x8000000080008000,
+        0x000000000000808b,
+        0x0000000080000001,
+        0x8000000080008081,
+        0x8000000000008009,
+        0x000000000000008a,
+        0x0000000000000088,
+        0x0000000080008009,
+        0x000000008000000a,
+        0x000000008000808b,
+        0x800000000000008b,
+        0x8000000000008089,
+        0x8000000000008003,
+        0x8000000000008002,
+        0x8000000000000080,
+        0x000000000000800a,
+        0x800000008000000a,
+        0x8000000080008081,
+        0x8000000000008080,
+        0x0000000080000001,
+        0x8000000080008008,
+    ];
+    // rotations and destinations of the lanes in the rho and pi steps
+    const ROTATIONS: [u32; 24] = [
+        1, 3, 6, 10, 15, 21, 28, 36, 45, 55, 2, 14, 27, 41, 56, 8, 25, 43, 62, 18, 39, 61, 20, 44,
+    ];
+    const LANES: [usize; 24] = [
+        10, 7, 11, 17, 18, 3, 5, 16, 8, 21, 24, 4, 15, 23, 19, 13, 12, 2, 20, 14, 22, 9, 6, 1,
+    ];
+
+    // padded with the domain bits 01, a one bit, zeros and a one bit to a
+    // multiple of the rate
+    let mut message = data.as_ref().to_vec();
+    message.push(0x06);
+    while message.len() % RATE != 0 {
+        message.push(0x00);
+    }
+    *message.last_mut().unwrap() |= 0x80; // safe, never empty
+
+    let mut state = [0u64; 25];
+    for block in message.chunks(RATE) {
+        for (lane, bytes) in state.iter_mut().zip(block.chunks(8)) {
+            let mut word = [0u8; 8];
+            word.copy_from_slice(bytes);
+            *lane ^= u64::from_le_bytes(word);
+        }
+
+        for round_constant in ROUND_CONSTANTS.iter() {
+            // theta
+            let mut columns = [0u64; 5];
+            for (x, column) in columns.iter_mut().enumerate() {
+                *column = state[x] ^ state[x + 5] ^ state[x + 10] ^ state[x + 15] ^ state[x + 20];
+            }
+            for x in 0..5 {
+                let d = columns[(x + 4) % 5] ^ columns[(x + 1) % 5].rotate_left(1);
+                for y in 0..5 {
+                    state[x + 5 * y] ^= d;
+                }
+            }
+
+            // rho and pi
+            let mut last = state[1];
+            for (rotation, lane) in ROTATIONS.iter().zip(LANES.iter()) {
+                let next = state[*lane];
+                state[*lane] = last.rotate_left(*rotation);
+                last = next;
+            }
+
+            // chi
+            for y in 0..5 {
+                let row = [
+                    state[5 * y],
+                    state[5 * y + 1],
+                    state[5 * y + 2],
+                    state[5 * y + 3],
+                    state[5 * y + 4],
+                ];
+                for x in 0..5 {
+                    state[x + 5 * y] = row[x] ^ (!row[(x + 1) % 5] & row[(x + 2) % 5]);
+                }
+            }
+
+            // iota
+            state[0] ^= round_constant;
+        }
+    }
+
+    let mut digest = [0u8; 32];
+    for (bytes, lane) in digest.chunks_mut(8).zip(state.iter()) {
+        bytes.copy_from_slice(&lane.to_le_bytes());
+    }
+    digest
+}
+
+/// BIP340 tagged hash, `sha256(sha256(tag) || sha256(tag) || data)`
+pub fn tagged_hash<B>(tag: &str, data: B) -> [u8; 32]
+where
+    B: AsRef<[u8]>,
+{
+    let tag = Sha256::digest(tag.as_bytes());
+    let digest = Sha256::new()
+        .chain(tag)
+        .chain(tag)
+        .chain(data.as_ref())
+        .finalize();
+
+    digest.into()
 }
 
 pub(crate) trait Chain {
@@ -80,6 +277,76 @@ impl Chain for Hmac<Sha256> {
     }
 }
 
+impl Chain for Hmac<Sha512> {
+    fn chain(mut self, data: &[u8]) -> Self {
+        self.update(data);
+        self
+    }
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn hmac_sha512<K, B>(key: K, data: B) -> [u8; 64]
+where
+    K: AsRef<[u8]>,
+    B: AsRef<[u8]>,
+{
+    let hmac = Hmac::<Sha512>::new_varkey(key.as_ref()).unwrap();
+    let digest = hmac.chain(data.as_ref()).finalize().into_bytes();
+
+    let mut result = [0u8; 64];
+    result.copy_from_slice(&digest);
+    result
+}
+
+/// PBKDF2 with HMAC-SHA512 as the PRF, producing a single 64 bytes block.
+#[cfg(feature = "std")]
+pub(crate) fn pbkdf2_hmac_sha512<P, S>(password: P, salt: S, rounds: u32) -> [u8; 64]
+where
+    P: AsRef<[u8]>,
+    S: AsRef<[u8]>,
+{
+    let prf = Hmac::<Sha512>::new_varkey(password.as_ref()).unwrap();
+
+    let block = prf
+        .clone()
+        .chain(salt.as_ref())
+        .chain(&1u32.to_be_bytes())
+        .finalize()
+        .into_bytes();
+
+    let mut u = [0u8; 64];
+    u.copy_from_slice(&block);
+    let mut result = u;
+
+    for _ in 1..rounds {
+        let block = prf.clone().chain(&u).finalize().into_bytes();
+        u.copy_from_slice(&block);
+        result.iter_mut().zip(&u).for_each(|(r, u)| *r ^= u);
+    }
+
+    result
+}
+
+#[cfg(feature = "std")]
 pub(crate) fn default<T: Default>() -> T {
     Default::default()
 }
+
+#[cfg(test)]
+mod tests {
+    use num_traits::One;
+
+    use super::*;
+
+    #[test]
+    fn fixed_size_bytes() {
+        let bytes = to_bytes32_be(&BigUint::from(0x0102u16)).unwrap();
+        assert_eq!(bytes[..30], [0; 30]);
+        assert_eq!(bytes[30..], [0x01, 0x02]);
+        assert_eq!(to_bytes32_be(&BigUint::default()), Some([0; 32]));
+
+        let max = (BigUint::one() << 256) - 1u8;
+        assert_eq!(to_bytes32_be(&max), Some([0xff; 32]));
+        assert_eq!(to_bytes32_be(&(max + 1u8)), None);
+    }
+}
@@ -108,17 +108,17 @@ impl VarInt {
         let mut reader = bytes.reader();
 
         match reader.read_u8()? {
-            first if first == 0xfd => {
+            0xfd => {
                 let value = reader.read_u16::<LittleEndian>()?;
                 Ok(Self::U16(value))
             }
 
-            first if first == 0xfe => {
+            0xfe => {
                 let value = reader.read_u32::<LittleEndian>()?;
                 Ok(Self::U32(value))
             }
 
-            first if first == 0xff => {
+            0xff => {
                 let value = reader.read_u64::<LittleEndian>()?;
                 Ok(Self::U64(value))
             }
@@ -137,6 +137,63 @@ impl VarInt {
     }
 }
 
+/// Bitcoin CompactSize encoding of the given value, always the shortest one
+pub fn encode(value: u64) -> Vec<u8> {
+    let mut result = Vec::with_capacity(encoded_len(value));
+    match value {
+        0..=0xfc => result.push(value as u8),
+        0xfd..=0xffff => {
+            result.push(0xfd);
+            result.extend_from_slice(&(value as u16).to_le_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            result.push(0xfe);
+            result.extend_from_slice(&(value as u32).to_le_bytes());
+        }
+        _ => {
+            result.push(0xff);
+            result.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    result
+}
+
+/// Length in bytes of the CompactSize encoding of the given value
+pub fn encoded_len(value: u64) -> usize {
+    match value {
+        0..=0xfc => 1,
+        0xfd..=0xffff => 3,
+        0x1_0000..=0xffff_ffff => 5,
+        _ => 9,
+    }
+}
+
+/// Decode a CompactSize, encodings that aren't the shortest possible are rejected
+pub fn decode(mut bytes: impl Buf) -> Result<u64> {
+    if !bytes.has_remaining() {
+        return Err(Error::InvalidBytesForVarInt);
+    }
+
+    let (width, min) = match bytes.get_u8() {
+        0xfd => (2, 0xfd),
+        0xfe => (4, 0x1_0000),
+        0xff => (8, 0x1_0000_0000),
+        value => return Ok(u64::from(value)),
+    };
+
+    if bytes.remaining() < width {
+        return Err(Error::InvalidBytesForVarInt);
+    }
+
+    let value = bytes.get_uint_le(width);
+    if value < min {
+        return Err(Error::NonCanonicalVarInt);
+    }
+
+    Ok(value)
+}
+
 #[cfg(test)]
 mod tests {
     use anyhow::Result;
@@ -233,4 +290,33 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn compact_size() -> Result<()> {
+        let cases: [(u64, &[u8]); 8] = [
+            (0, &[0x00]),
+            (0xfc, &[0xfc]),
+            (0xfd, &[0xfd, 0xfd, 0x00]),
+            (0xffff, &[0xfd, 0xff, 0xff]),
+            (0x1_0000, &[0xfe, 0x00, 0x00, 0x01, 0x00]),
+            (0xffff_ffff, &[0xfe, 0xff, 0xff, 0xff, 0xff]),
+            (0x1_0000_0000, &[0xff, 0, 0, 0, 0, 0x01, 0, 0, 0]),
+            (u64::MAX, &[0xff; 9]),
+        ];
+
+        for (value, expected) in cases.iter() {
+            assert_eq!(encode(*value), *expected);
+            assert_eq!(encoded_len(*value), expected.len());
+            assert_eq!(decode(*expected)?, *value);
+        }
+
+        // non canonical and truncated encodings
+        assert!(decode(&[0xfd, 0xfc, 0x00][..]).is_err());
+        assert!(decode(&[0xfe, 0xff, 0xff, 0x00, 0x00][..]).is_err());
+        assert!(decode(&[0xff, 0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0][..]).is_err());
+        assert!(decode(&[0xfd, 0xff][..]).is_err());
+        assert!(decode(&[][..]).is_err());
+
+        Ok(())
+    }
 }
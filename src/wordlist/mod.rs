@@ -0,0 +1,8 @@
+use lazy_static::lazy_static;
+
+const ENGLISH_RAW: &str = include_str!("english.txt");
+
+lazy_static! {
+    /// The BIP39 english wordlist, also used by Electrum for new style seeds
+    pub(crate) static ref ENGLISH: Vec<&'static str> = ENGLISH_RAW.lines().collect();
+}
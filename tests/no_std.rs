@@ -0,0 +1,40 @@
+//! The field, curve and signature code built without `std`, run with
+//! `cargo test-no-std`
+#![cfg(all(feature = "alloc", not(feature = "std")))]
+
+use hex_literal::hex;
+use oxicoin::secp256k1::crypto::{PrivateKey, PublicKey};
+use oxicoin::secp256k1::signature::Signature;
+use oxicoin::Result;
+
+#[test]
+fn sign_and_verify() -> Result<()> {
+    let privkey = PrivateKey::new(12345u32);
+    let digest = hex!("bc62d4b80d9e36da29c16c5d4d9f11731f36052c72401a76c23c0fb5a9b74423");
+
+    let signature = privkey.create_signature(digest)?;
+    assert!(privkey.public_key().valid_signature(digest, &signature)?);
+
+    let der = signature.serialize()?;
+    assert_eq!(Signature::deserialize(der.as_slice())?, signature);
+    assert!(Signature::deserialize(&der[..der.len() - 1]).is_err());
+    Ok(())
+}
+
+#[test]
+fn fixed_array_sec_format() -> Result<()> {
+    let public_key = PrivateKey::new(5001u32).public_key().clone();
+
+    let compressed = public_key.serialize_compressed()?;
+    assert_eq!(compressed[..], public_key.serialize(true)?[..]);
+    assert_eq!(PublicKey::from_sec_compressed(&compressed)?, public_key);
+
+    let uncompressed = public_key.serialize_uncompressed()?;
+    assert_eq!(uncompressed[..], public_key.serialize(false)?[..]);
+    assert_eq!(PublicKey::from_sec_uncompressed(&uncompressed)?, public_key);
+
+    let mut invalid = compressed;
+    invalid[0] = 0x04;
+    assert!(PublicKey::from_sec_compressed(&invalid).is_err());
+    Ok(())
+}
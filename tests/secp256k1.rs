@@ -1,7 +1,12 @@
+#![cfg(feature = "std")]
+
+use std::convert::TryFrom;
+
 use anyhow::Result;
 use hex_literal::hex;
 use num_bigint::BigUint;
 use oxicoin::biguint;
+use oxicoin::network::Network;
 use oxicoin::secp256k1::crypto::{PrivateKey, PublicKey};
 use oxicoin::secp256k1::curve::Point;
 use oxicoin::secp256k1::signature::Signature;
@@ -18,7 +23,7 @@ fn signature_must_be_valid() -> Result<()> {
     let y = biguint!("82b51eab8c27c66e26c858a079bcdf4f1ada34cec420cafc7eac1a42216fb6c4");
     let pub_key = PublicKey::new(x, y)?;
 
-    assert!(signature.is_valid(&digest, &pub_key)?);
+    assert!(signature.is_valid(digest, &pub_key)?);
     Ok(())
 }
 
@@ -27,17 +32,43 @@ fn create_and_validate_signature() -> Result<()> {
     let privkey = PrivateKey::new(BigUint::from(12345usize));
     let digest = hex!("bc62d4b80d9e36da29c16c5d4d9f11731f36052c72401a76c23c0fb5a9b74423");
 
-    let signature = privkey.create_signature(&digest)?;
+    let signature = privkey.create_signature(digest)?;
 
     insta::assert_debug_snapshot!(signature); // signature shouldn't change
     assert!(privkey
         .public_key()
-        .valid_signature(&digest, &signature)
+        .valid_signature(digest, &signature)
         .unwrap());
 
     Ok(())
 }
 
+#[test]
+fn batch_verification() -> Result<()> {
+    let digests = [[0x01u8; 32], [0x02u8; 32], [0x03u8; 32]];
+    let keys: Vec<_> = (1..=3usize)
+        .map(|secret| PrivateKey::new(secret * 1000))
+        .collect();
+    let signatures = keys
+        .iter()
+        .zip(&digests)
+        .map(|(key, digest)| key.create_signature(digest))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut batch: Vec<_> = digests
+        .iter()
+        .zip(&signatures)
+        .zip(&keys)
+        .map(|((digest, signature), key)| (digest, signature, key.public_key()))
+        .collect();
+    assert!(Signature::verify_batch(&batch)?);
+
+    // a single bad signature spoils the batch
+    batch[1].1 = &signatures[0];
+    assert!(!Signature::verify_batch(&batch)?);
+    Ok(())
+}
+
 #[test]
 fn uncompressed_sec_format() {
     fn test_case(secret: usize, expected: &[u8]) {
@@ -46,8 +77,15 @@ fn uncompressed_sec_format() {
         let serialized = public_key.serialize(false).unwrap();
 
         assert_eq!(serialized, expected);
-        let deserialized: PublicKey = Point::deserialize(&serialized).unwrap().into();
+        let deserialized = PublicKey::try_from(Point::deserialize(&serialized).unwrap()).unwrap();
         assert_eq!(&deserialized, public_key);
+
+        let serialized = public_key.serialize_uncompressed().unwrap();
+        assert_eq!(&serialized[..], expected);
+        assert_eq!(
+            &PublicKey::from_sec_uncompressed(&serialized).unwrap(),
+            public_key
+        );
     }
 
     test_case(
@@ -83,8 +121,15 @@ fn compressed_sec_serialization() {
         let serialized = public_key.serialize(true).unwrap();
 
         assert_eq!(serialized, expected);
-        let deserialized: PublicKey = Point::deserialize(&serialized).unwrap().into();
+        let deserialized = PublicKey::try_from(Point::deserialize(&serialized).unwrap()).unwrap();
         assert_eq!(&deserialized, public_key);
+
+        let serialized = public_key.serialize_compressed().unwrap();
+        assert_eq!(&serialized[..], expected);
+        assert_eq!(
+            &PublicKey::from_sec_compressed(&serialized).unwrap(),
+            public_key
+        );
     }
 
     test_case(
@@ -101,6 +146,46 @@ fn compressed_sec_serialization() {
         3917405025026849,
         &hex!("0296be5b1292f6c856b3c5654e886fc13511462059089cdf9c479623bfcbe77690"),
     );
+
+    // x = 5 isn't the x coordinate of any point
+    let mut invalid = [0u8; 33];
+    invalid[0] = 0x02;
+    invalid[32] = 0x05;
+    assert!(Point::deserialize(invalid).is_err());
+
+    // unknown prefixes and mismatched lengths
+    let mut valid = PrivateKey::new(5001usize)
+        .public_key()
+        .serialize_compressed()
+        .unwrap();
+    valid[0] = 0x04;
+    assert!(PublicKey::from_sec_compressed(&valid).is_err());
+    assert!(Point::deserialize(valid).is_err());
+    assert!(Point::deserialize(&valid[..32]).is_err());
+}
+
+#[test]
+fn public_key_validation() {
+    assert!(PublicKey::try_from(Point::at_infinity()).is_err());
+
+    let public_key = PrivateKey::new(5001usize).public_key().clone();
+    assert!(public_key.is_on_curve());
+
+    let point = Point::deserialize(public_key.serialize(true).unwrap()).unwrap();
+    assert_eq!(PublicKey::try_from(point).unwrap(), public_key);
+
+    let compressed = public_key.serialize_compressed().unwrap();
+    let mut x = [0u8; 32];
+    x.copy_from_slice(&compressed[1..]);
+    let odd = compressed[0] == 0x03;
+    assert_eq!(PublicKey::from_x(&x, odd).unwrap(), public_key);
+    assert_ne!(PublicKey::from_x(&x, !odd).unwrap(), public_key);
+
+    // x = 5 isn't the x coordinate of any point, and x must be lower than P
+    let mut x = [0u8; 32];
+    x[31] = 0x05;
+    assert!(PublicKey::from_x(&x, false).is_err());
+    assert!(PublicKey::from_x(&[0xff; 32], false).is_err());
 }
 
 #[test]
@@ -108,9 +193,14 @@ fn address_creation() {
     fn test_case(secret: usize, compressed: bool, testnet: bool, expected: &str) {
         let private_key = PrivateKey::new(secret);
         let public_key = private_key.public_key();
-        let address = public_key.create_address(compressed, testnet).unwrap();
+        let network = if testnet {
+            Network::Testnet
+        } else {
+            Network::Mainnet
+        };
+        let address = public_key.create_address(compressed, network).unwrap();
 
-        assert_eq!(expected, address);
+        assert_eq!(expected, address.to_string());
     }
 
     test_case(5002, false, true, "mmTPbXQFxboEtNRkwfh6K51jvdtHLxGeMA");
@@ -132,7 +222,12 @@ fn address_creation() {
 fn create_wif() {
     fn test_case(secret: usize, compressed: bool, testnet: bool, expected: &str) {
         let private_key = PrivateKey::new(secret);
-        let wif = private_key.create_wif(compressed, testnet).unwrap();
+        let network = if testnet {
+            Network::Testnet
+        } else {
+            Network::Mainnet
+        };
+        let wif = private_key.create_wif(compressed, network).unwrap();
 
         assert_eq!(expected, wif);
     }
@@ -156,3 +251,19 @@ fn create_wif() {
         "KwDiBf89QgGbjEhKnhXJuH7LrciVrZi3qYjgiuQJv1h8Ytr2S53a",
     );
 }
+
+#[test]
+fn large_coefficients() {
+    // the generator, from its compressed SEC format
+    let g = Point::deserialize(
+        &hex!("0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798")[..],
+    )
+    .unwrap();
+
+    // above the field prime, only reduced by the group order
+    let n = biguint!("fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141");
+    let p_plus_one = biguint!("fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc30");
+    assert_eq!(&g * p_plus_one.clone(), &g * (&p_plus_one - &n));
+    assert_eq!(&g * (&n + 7u8), &g * 7u8);
+    assert!((&g * n).is_point_at_inf());
+}
@@ -0,0 +1,51 @@
+#![cfg(feature = "serde")]
+
+use std::fmt::Debug;
+
+use anyhow::Result;
+use oxicoin::address::Address;
+use oxicoin::bip32::ExtendedPublicKey;
+use oxicoin::network::Network;
+use oxicoin::secp256k1::crypto::PublicKey;
+use oxicoin::secp256k1::signature::Signature;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+fn assert_json_roundtrip<T>(value: &str) -> Result<()>
+where
+    T: Serialize + DeserializeOwned + PartialEq + Debug,
+{
+    let json = format!("\"{}\"", value);
+    let parsed: T = serde_json::from_str(&json)?;
+    assert_eq!(serde_json::to_string(&parsed)?, json);
+    assert_eq!(
+        serde_json::from_str::<T>(&serde_json::to_string(&parsed)?)?,
+        parsed
+    );
+    Ok(())
+}
+
+#[test]
+fn human_readable_strings() -> Result<()> {
+    assert_json_roundtrip::<PublicKey>(
+        "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+    )?;
+    assert_json_roundtrip::<Signature>(
+        "3045022100ed81ff192e75a3fd2304004dcadb746fa5e24c5031ccfcf21320b0277457c98f\
+         02207a986d955c6e0cb35d446a89d3f56100f4d7f67801c31967743a9c8e10615bed",
+    )?;
+    assert_json_roundtrip::<Address>("1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH")?;
+    assert_json_roundtrip::<Address>("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4")?;
+    assert_json_roundtrip::<Network>("testnet4")?;
+    assert_json_roundtrip::<ExtendedPublicKey>(
+        "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8",
+    )?;
+    Ok(())
+}
+
+#[test]
+fn invalid_strings_are_rejected() {
+    assert!(serde_json::from_str::<PublicKey>("\"04zz\"").is_err());
+    assert!(serde_json::from_str::<Network>("\"bitcoin\"").is_err());
+    assert!(serde_json::from_str::<Address>("\"1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMh\"").is_err());
+}